@@ -28,3 +28,94 @@ VMValue unsigned_pow2(VMValue* args, uint32_t argc) {
     printf("From lib: %ld", res);
     return (VMValue){.typeind=1, .data=res};
 }
+
+typedef struct HookFrame {
+    uint32_t opcode;
+    uint64_t ip;
+    VMValue* regs;
+    uint32_t reg_count;
+    const uint8_t* code;
+    uint64_t code_len;
+} HookFrame;
+
+// post hook on nop (0x2): counts executed nops in r20
+uint32_t count_nops(HookFrame* frame) {
+    frame->regs[20].typeind = 1;
+    frame->regs[20].data += 1;
+    return 0;
+}
+
+// pre hook on udiv (0x14): division by zero gives 0 instead of an exception
+uint32_t safe_udiv(HookFrame* frame) {
+    if (frame->code_len < 4) {
+        return 0;
+    }
+    uint8_t dst = frame->code[1];
+    uint8_t divisor = frame->code[3];
+    if (frame->regs[divisor].data != 0) {
+        return 0;
+    }
+    frame->regs[dst] = (VMValue){.typeind=1, .data=0};
+    frame->ip += 4;
+    return 1; // skip the instruction
+}
+
+typedef struct HeapApi {
+    void* ctx;
+    uint64_t (*len)(void* ctx, uint64_t ptr);
+    uint32_t (*read)(void* ctx, uint64_t ptr, uint8_t* dst, uint64_t count);
+    uint32_t (*write)(void* ctx, uint64_t ptr, const uint8_t* src, uint64_t count);
+    uint8_t* (*shared)(void* ctx, uint64_t ptr, uint64_t* size);
+} HeapApi;
+
+// heap_api function: reverses the heap buffer at args[0] (up to 256 bytes),
+// returns its length, or UINT64_MAX if reading past its end didn't fail
+VMValue reverse_buf(VMValue* args, uint32_t argc, const HeapApi* heap) {
+    uint64_t ptr = args[0].data;
+    uint64_t len = heap->len(heap->ctx, ptr);
+    uint8_t buf[257];
+    if (len > 256 || heap->read(heap->ctx, ptr, buf, len) != 0) {
+        return (VMValue){.typeind=1, .data=0};
+    }
+    if (heap->read(heap->ctx, ptr, buf, len + 1) == 0) {
+        return (VMValue){.typeind=1, .data=UINT64_MAX};
+    }
+    for (uint64_t i = 0; i < len / 2; i++) {
+        uint8_t t = buf[i];
+        buf[i] = buf[len - 1 - i];
+        buf[len - 1 - i] = t;
+    }
+    heap->write(heap->ctx, ptr, buf, len);
+    return (VMValue){.typeind=1, .data=len};
+}
+
+// shared region of the guest (ncall @shm_create), used across calls
+static uint8_t* shared_buf = NULL;
+static uint64_t shared_len = 0;
+
+// heap_api function: keeps the host pointer of the shared region at
+// args[0], returns its size (0 if args[0] isn't in a shared region)
+VMValue attach_shared(VMValue* args, uint32_t argc, const HeapApi* heap) {
+    shared_buf = heap->shared(heap->ctx, args[0].data, &shared_len);
+    if (shared_buf == NULL) {
+        shared_len = 0;
+    }
+    return (VMValue){.typeind=1, .data=shared_len};
+}
+
+// sets every byte of the attached region to args[0], returns the count
+VMValue shared_fill(VMValue* args, uint32_t argc) {
+    for (uint64_t i = 0; i < shared_len; i++) {
+        shared_buf[i] = (uint8_t)args[0].data;
+    }
+    return (VMValue){.typeind=1, .data=shared_len};
+}
+
+// sum of the bytes of the attached region
+VMValue shared_sum(VMValue* args, uint32_t argc) {
+    uint64_t sum = 0;
+    for (uint64_t i = 0; i < shared_len; i++) {
+        sum += shared_buf[i];
+    }
+    return (VMValue){.typeind=1, .data=sum};
+}
@@ -0,0 +1,94 @@
+use std::io::Write;
+
+use crate::{
+    heap::AllocSite,
+    misclib::{bytes_from_straddr, bytes_into_string_utf16},
+    registers::Register,
+    vm::{RegTypes, VM},
+};
+
+// Guest aborts: `abort Rstr Rlen` stops the program on purpose. The message
+// (a data segment string from dslea, or Rlen bytes of a heap string) goes
+// to the guest stderr with the ip of the abort and, when the image has
+// symbols or a line table, a backtrace through the call frames. With
+// `--abort-coredump` the machine state is dumped into voxvm_err.dump, the
+// process exits with `--abort-exit-code` (ABORT_EXIT_CODE by default)
+// after the usual end of run reports.
+
+/// Exit code of an aborted run, a SIGABRT death as shells report it
+pub const ABORT_EXIT_CODE: i32 = 134;
+
+/// Address of the `call`/`callr` returning to `retaddr`, None for frames
+/// the host entered (entry functions, coroutine bodies)
+fn call_site(vm: &VM, retaddr: u64) -> Option<u64> {
+    let ret: usize = usize::try_from(retaddr).ok()?;
+    // call is 9 bytes, callr 2
+    [(9, 0x90), (2, 0x93)]
+        .into_iter()
+        .find(|(size, op)| ret.checked_sub(*size).and_then(|at| vm.memory.get(at)) == Some(op))
+        .map(|(size, _)| (ret - size) as u64)
+}
+
+/// Frames of the call stack, innermost first: the abort itself, then the
+/// call instruction of every frame
+pub fn backtrace(vm: &VM) -> Vec<String> {
+    let frames = &vm.call_stack.stack;
+    let mut res: Vec<String> = vec![vm.site_desc(&AllocSite {
+        ip: vm.ip as u64,
+        func: vm.call_stack.current_func(),
+    })];
+    for (ind, frame) in frames.iter().enumerate().rev() {
+        let Some(ip) = call_site(vm, frame.retaddr()) else {
+            continue;
+        };
+        // the call is in the caller's function, the frame below
+        let func: Option<usize> = ind.checked_sub(1).and_then(|i| frames[i].func());
+        res.push(vm.site_desc(&AllocSite { ip, func }));
+    }
+    res
+}
+
+fn abort_message(vm: &VM, r_str_ind: usize, r_len_ind: usize) -> String {
+    let bytes: Option<Vec<u8>> = match (vm.registers[r_str_ind], vm.reg_types[r_str_ind]) {
+        (Register::StrAddr(addr), _) => bytes_from_straddr(vm, addr),
+        (reg, RegTypes::ds_addr) => bytes_from_straddr(vm, reg.as_u64()),
+        (reg, RegTypes::address) => {
+            let count: u64 = vm.registers[r_len_ind].as_u64();
+            vm.heap.slice(reg.as_u64(), count).ok().map(|b| b.to_vec())
+        }
+        _ => None,
+    };
+    match bytes.and_then(|b| bytes_into_string_utf16(&b)) {
+        Some(msg) => msg,
+        None => format!("<no string at {}>", vm.registers[r_str_ind]),
+    }
+}
+
+pub fn op_abort(vm: &mut VM) {
+    // 0xFE 0x04, size: 4
+    // abort Rstr Rlen - Rlen is the byte count of heap strings, data
+    // segment strings carry their own
+    let r_str_ind: usize = vm.memory[vm.ip + 2] as usize;
+    let r_len_ind: usize = vm.memory[vm.ip + 3] as usize;
+
+    let mut report: String = format!("ABORT: {}\nAt IP = {:#x}\n", abort_message(vm, r_str_ind, r_len_ind), vm.ip);
+    if !vm.func_names.is_empty() || !vm.line_table.is_empty() {
+        report.push_str("Backtrace:\n");
+        for (ind, frame) in backtrace(vm).iter().enumerate() {
+            report.push_str(&format!("  #{} {}\n", ind, frame));
+        }
+    }
+    let _ = vm.output.stdout.flush();
+    let _ = vm.output.stderr.write_all(report.as_bytes());
+    let _ = vm.output.stderr.flush();
+
+    if vm.abort_coredump {
+        match vm.err_coredump() {
+            Ok(()) => eprintln!("Coredump created."),
+            Err(e) => eprintln!("Error creating coredump: {}", e),
+        }
+    }
+    // ip stays at the abort for dumps and --dump-state
+    vm.aborted = true;
+    vm.halt();
+}
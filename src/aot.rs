@@ -0,0 +1,130 @@
+// `voxvm aot prog.vve -o prog.rs`: ahead of time translation into Rust.
+// Every basic block cfgexport finds becomes a function that runs its
+// instructions through `VM::exec_instr` with constant opcodes, and the
+// generated `Dispatch` picks the block by ip. Handlers still decode operands
+// from memory, so heap, ncalls, exceptions, GC and the other subsystems work
+// as in the interpreter; the opcode fetch and the handler table lookup per
+// instruction go away. Ip values no block starts at (`jmpr` targets) run on
+// the interpreter until execution reaches a block again.
+// The output embeds the whole image. A standalone binary is built with
+//     VOXVM_AOT=/abs/path/prog.rs cargo build --release --features aot
+// and takes the usual flags: without --vve/--vvr it runs the embedded
+// program (on the interpreter with --allow-self-modify or --stdlib).
+
+use std::collections::HashMap;
+use std::fs;
+
+use crate::cfgexport::{find_blocks, Blocks, CfgImage, Decoded};
+#[cfg(feature = "aot")]
+use crate::{vm::VM, vvelink::VveImage};
+
+#[cfg(feature = "aot")]
+mod program {
+    include!(env!("VOXVM_AOT"));
+}
+
+/// `--vve` name of the embedded program
+#[cfg(feature = "aot")]
+pub const EMBEDDED: &str = "<aot>";
+
+#[cfg(feature = "aot")]
+pub fn embedded_image(min_version: u16) -> VveImage {
+    // the image was written by this assembler when the binary was built
+    VveImage::from_bytes(&program::IMAGE, EMBEDDED, min_version).unwrap()
+}
+
+/// Runs the loaded program, with the translated blocks if it's the embedded one
+#[cfg(feature = "aot")]
+pub fn run_embedded(vm: &mut VM, embedded: bool) {
+    match embedded && !vm.segments.allow_self_modify {
+        true => vm.run_with(&mut program::Blocks),
+        false => vm.run(),
+    }
+}
+
+/// Rust source of `img`, `image` is the vve file it came from
+pub fn translate(img: &CfgImage, image: &[u8], source: &str) -> String {
+    let Blocks { instrs, blocks, .. } = find_blocks(img);
+    let lines: HashMap<u64, u32> = img.lines.iter().copied().collect();
+
+    let mut res: String = format!(
+        "// Generated by `voxvm aot {}`: {} basic blocks, {} bytes of code.\n",
+        source,
+        blocks.len(),
+        img.code.len()
+    );
+    res += "// Build with VOXVM_AOT=<path of this file> cargo build --release --features aot\n\n";
+    res += "use crate::vm::{Dispatch, Interpreter, BLOCK_END, VM};\n\n";
+    res += &format!("pub static IMAGE: [u8; {}] = [\n", image.len());
+    for chunk in image.chunks(16) {
+        let row: Vec<String> = chunk.iter().map(|b| format!("{:#04x},", b)).collect();
+        res += &format!("    {}\n", row.join(" "));
+    }
+    res += "];\n\n";
+
+    res += "pub struct Blocks;\n\n";
+    res += "impl Dispatch for Blocks {\n";
+    res += "    fn dispatch(&mut self, vm: &mut VM) -> u64 {\n";
+    res += "        match vm.ip {\n";
+    for leader in blocks.keys() {
+        res += &format!("            {:#x} => b_{:x}(vm),\n", leader, leader);
+    }
+    res += "            _ => Interpreter.dispatch(vm),\n";
+    res += "        }\n    }\n}\n";
+
+    for (leader, body) in &blocks {
+        res += "\n";
+        if let Some(line) = lines.get(leader) {
+            res += &format!("// line {}\n", line);
+        }
+        res += &format!("fn b_{:x}(vm: &mut VM) -> u64 {{\n", leader);
+        for (i, addr) in body.iter().enumerate() {
+            let d: &Decoded = &instrs[addr];
+            let op: u8 = img.code[*addr as usize];
+            match i + 1 == body.len() {
+                true => {
+                    res += &format!("    vm.exec_instr({:#04x}, BLOCK_END); // {:#06x} {}\n", op, addr, d.text);
+                    res += &format!("    {}\n", body.len());
+                }
+                false => {
+                    let next: u64 = addr + d.size as u64;
+                    res += &format!(
+                        "    if !vm.exec_instr({:#04x}, {:#x}) {{ return {}; }} // {:#06x} {}\n",
+                        op,
+                        next,
+                        i + 1,
+                        addr,
+                        d.text
+                    );
+                }
+            }
+        }
+        res += "}\n";
+    }
+    res
+}
+
+pub fn aot_cli(args: &[String]) -> i32 {
+    const MIN_VVE_VERSION: u16 = 3;
+    let (input, output): (&str, &str) = match args {
+        [input, o, output] if o == "-o" => (input, output),
+        _ => {
+            eprintln!("Usage: voxvm aot file.vve -o file.rs");
+            return 1;
+        }
+    };
+    let translated: Result<String, String> = CfgImage::from_vve(input, MIN_VVE_VERSION)
+        .and_then(|img| fs::read(input).map(|bytes| translate(&img, &bytes, input)).map_err(|e| e.to_string()));
+    let src: String = match translated {
+        Ok(src) => src,
+        Err(e) => {
+            eprintln!("ERROR: Can't read {}: {}", input, e);
+            return 1;
+        }
+    };
+    if let Err(e) = fs::write(output, src) {
+        eprintln!("ERROR: Can't write {}: {}", output, e);
+        return 1;
+    }
+    0
+}
@@ -0,0 +1,237 @@
+use std::{collections::HashMap, ops::Range};
+
+use crate::{
+    exceptions::Exception,
+    heap::HEAP_ALIGN,
+    memcap::mem_reserve,
+    nativeerr::{native_fault, NativeErrKind, NativeError, NativeSubsys},
+    registers::Register,
+    vm::{RegTypes, VM},
+};
+
+// Heap arenas for request-scoped data: `ncall @arena_new` makes an arena,
+// `allocarena` bump-allocates zeroed, HEAP_ALIGN aligned bytes in it and
+// `@arena_free` drops all of them at once, without a free list walk or a
+// GC cycle; `@arena_reset` empties an arena for the next request and keeps
+// its buffer. Single blocks can't be freed. Arena pointers are
+// ARENA_BASE + ARENA_ALIGN * slot + offset, `load`, `store`, `load32`,
+// `store32` and `memcpy` take them like heap pointers. An arena handle is
+// its first address. Slots aren't reused, a pointer into a freed arena
+// faults instead of reading another one. The GC neither scans nor sweeps
+// arenas: they are collected as a whole, and a heap pointer kept only in
+// an arena doesn't keep its block alive. Arenas are shared by coroutines.
+
+/// Arena pointers start here, below mapped file pointers
+pub const ARENA_BASE: u64 = 1 << 60;
+/// Distance between arena starts, also the size limit of one arena
+pub const ARENA_ALIGN: u64 = 1 << 32;
+
+#[derive(Debug)]
+struct Arena {
+    bytes: Vec<u8>,
+    limit: usize,
+    allocs: u64, // allocations since creation or the last reset
+}
+
+#[derive(Debug, Default)]
+pub struct ArenaTable {
+    arenas: HashMap<u64, Arena>, // slot -> arena
+    next: u64,                   // slot of the next arena
+    used: u64,                   // bytes in all arenas
+}
+
+impl ArenaTable {
+    pub fn new() -> ArenaTable {
+        ArenaTable::default()
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        self.used
+    }
+
+    /// New arena of at most `limit` bytes, returns its handle
+    fn create(&mut self, limit: usize) -> Option<u64> {
+        let slot: u64 = self.next;
+        if slot >= ARENA_BASE / ARENA_ALIGN {
+            return None; // arena address space is used up
+        }
+        self.next += 1;
+        self.arenas.insert(
+            slot,
+            Arena {
+                bytes: Vec::new(),
+                limit,
+                allocs: 0,
+            },
+        );
+        Some(ARENA_BASE + slot * ARENA_ALIGN)
+    }
+
+    /// Slot of the arena with handle `handle`, if it's alive
+    fn slot(&self, handle: u64) -> Option<u64> {
+        let off: u64 = handle.checked_sub(ARENA_BASE)?;
+        let slot: u64 = off / ARENA_ALIGN;
+        (off.is_multiple_of(ARENA_ALIGN) && self.arenas.contains_key(&slot)).then_some(slot)
+    }
+
+    fn destroy(&mut self, handle: u64) -> Result<(), ()> {
+        let arena: Arena = self.arenas.remove(&self.slot(handle).ok_or(())?).ok_or(())?;
+        self.used -= arena.bytes.len() as u64;
+        Ok(())
+    }
+
+    fn reset(&mut self, handle: u64) -> Result<(), ()> {
+        let arena: &mut Arena = self.arenas.get_mut(&self.slot(handle).ok_or(())?).ok_or(())?;
+        self.used -= arena.bytes.len() as u64;
+        arena.bytes.clear();
+        arena.allocs = 0;
+        Ok(())
+    }
+
+    /// Bytes that allocating `size` in the arena adds, alignment included.
+    /// None if the arena doesn't exist or has no room
+    fn growth(&self, handle: u64, size: u64) -> Option<u64> {
+        let arena: &Arena = self.arenas.get(&self.slot(handle)?)?;
+        let start: usize = arena.bytes.len().next_multiple_of(HEAP_ALIGN);
+        let end: usize = start.checked_add(usize::try_from(size).ok()?)?;
+        (end <= arena.limit).then_some((end - arena.bytes.len()) as u64)
+    }
+
+    /// Zeroed bytes at the end of the arena, `growth` has to allow them
+    fn alloc(&mut self, handle: u64, size: u64) -> u64 {
+        let slot: u64 = (handle - ARENA_BASE) / ARENA_ALIGN;
+        let arena: &mut Arena = self.arenas.get_mut(&slot).expect("arena checked by growth");
+        let start: usize = arena.bytes.len().next_multiple_of(HEAP_ALIGN);
+        let before: usize = arena.bytes.len();
+        arena.bytes.resize(start + size as usize, 0);
+        arena.allocs += 1;
+        self.used += (arena.bytes.len() - before) as u64;
+        handle + start as u64
+    }
+
+    /// The arena and byte range of `count` bytes at `ptr`, all in one arena
+    fn range(&mut self, ptr: u64, count: u64) -> Option<(&mut Arena, Range<usize>)> {
+        let off: u64 = ptr.checked_sub(ARENA_BASE)?;
+        let arena: &mut Arena = self.arenas.get_mut(&(off / ARENA_ALIGN))?;
+        let start: usize = (off % ARENA_ALIGN) as usize;
+        let end: usize = start.checked_add(count as usize)?;
+        (end <= arena.bytes.len()).then_some((arena, start..end))
+    }
+
+    pub fn read(&mut self, ptr: u64, count: u64) -> Result<Vec<u8>, ()> {
+        let (arena, r) = self.range(ptr, count).ok_or(())?;
+        Ok(arena.bytes[r].to_vec())
+    }
+
+    pub fn write(&mut self, ptr: u64, data: &[u8]) -> Result<(), ()> {
+        let (arena, r) = self.range(ptr, data.len() as u64).ok_or(())?;
+        arena.bytes[r].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+pub fn is_arena_ptr(ptr: u64) -> bool {
+    (ARENA_BASE..(ARENA_BASE << 1)).contains(&ptr)
+}
+
+fn set_uint(vm: &mut VM, ind: usize, val: u64) {
+    vm.registers[ind] = Register::uint(val);
+    vm.reg_types[ind] = RegTypes::uint64;
+}
+
+fn arena_fault(vm: &mut VM, kind: NativeErrKind, msg: &str) {
+    native_fault(vm, NativeError::new(NativeSubsys::Arena, kind), Exception::NativeFault, msg);
+}
+
+pub fn op_allocarena(vm: &mut VM) {
+    // 0xB1, size: 4
+    // allocarena Rdest Rarena Rsize - Rdest = pointer to Rsize zeroed bytes
+    // in the arena Rarena, 0 if it doesn't exist or is full
+    let r_dest_ind: usize = vm.memory[vm.ip + 1] as usize;
+    let r_arena_ind: usize = vm.memory[vm.ip + 2] as usize;
+    let r_size_ind: usize = vm.memory[vm.ip + 3] as usize;
+    let handle: u64 = vm.registers[r_arena_ind].as_u64();
+    let size: u64 = vm.registers[r_size_ind].as_u64();
+    vm.ip += 4;
+
+    set_uint(vm, r_dest_ind, 0);
+    let growth: u64 = match vm.arenas.growth(handle, size) {
+        Some(v) => v,
+        None => {
+            vm.exceptions_active.push(Exception::HeapAllocationFault);
+            return;
+        }
+    };
+    if !mem_reserve(vm, growth) {
+        return;
+    }
+    let ptr: u64 = vm.arenas.alloc(handle, size);
+    set_uint(vm, r_dest_ind, ptr);
+}
+
+/// ncall 0xF0
+/// r1 is the arena size limit in bytes, 0 takes the largest (ARENA_ALIGN).
+/// r0 = handle of the new, empty arena, 0 on failure
+pub fn ncall_arena_new(vm: &mut VM) {
+    let limit: u64 = match vm.registers[1].as_u64() {
+        0 => ARENA_ALIGN,
+        v => v,
+    };
+    set_uint(vm, 0, 0);
+    if limit > ARENA_ALIGN {
+        let msg: String = format!("arena_new: {} bytes is above the arena limit {}", limit, ARENA_ALIGN);
+        return arena_fault(vm, NativeErrKind::InvalidInput, &msg);
+    }
+    match vm.arenas.create(limit as usize) {
+        Some(handle) => set_uint(vm, 0, handle),
+        None => arena_fault(vm, NativeErrKind::LimitReached, "arena_new: no arena addresses left"),
+    }
+}
+
+/// ncall 0xF1
+/// r1 is an arena handle. Frees the arena with everything allocated in it,
+/// its pointers fault from now on. r0 = 1, 0 on failure
+pub fn ncall_arena_free(vm: &mut VM) {
+    let handle: u64 = vm.registers[1].as_u64();
+    match vm.arenas.destroy(handle) {
+        Ok(()) => set_uint(vm, 0, 1),
+        Err(()) => {
+            set_uint(vm, 0, 0);
+            arena_fault(vm, NativeErrKind::BadHandle, &format!("arena_free: {:#x} isn't an arena", handle));
+        }
+    }
+}
+
+/// ncall 0xF2
+/// r1 is an arena handle. Frees everything allocated in the arena, the next
+/// `allocarena` starts at its handle again. r0 = 1, 0 on failure
+pub fn ncall_arena_reset(vm: &mut VM) {
+    let handle: u64 = vm.registers[1].as_u64();
+    match vm.arenas.reset(handle) {
+        Ok(()) => set_uint(vm, 0, 1),
+        Err(()) => {
+            set_uint(vm, 0, 0);
+            arena_fault(vm, NativeErrKind::BadHandle, &format!("arena_reset: {:#x} isn't an arena", handle));
+        }
+    }
+}
+
+/// ncall 0xF3
+/// r1 is an arena handle. r0 = bytes in use (alignment included),
+/// r1 = allocations since creation or the last reset, r2 = size limit.
+/// All 0 for a handle that isn't an arena, with a BadHandle fault
+pub fn ncall_arena_stats(vm: &mut VM) {
+    let handle: u64 = vm.registers[1].as_u64();
+    let stats: Option<(u64, u64, u64)> = vm
+        .arenas
+        .slot(handle)
+        .and_then(|slot| vm.arenas.arenas.get(&slot))
+        .map(|a| (a.bytes.len() as u64, a.allocs, a.limit as u64));
+    let (used, allocs, limit) = stats.unwrap_or((0, 0, 0));
+    set_uint(vm, 0, used);
+    set_uint(vm, 1, allocs);
+    set_uint(vm, 2, limit);
+    if stats.is_none() {
+        arena_fault(vm, NativeErrKind::BadHandle, &format!("arena_stats: {:#x} isn't an arena", handle));
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+use crate::assembly::{voxasm_instr_table, LexTypes};
+
+// `alias counter = r5`: named registers, resolved before macros and both
+// assembler passes. Aliases declared before the first `func` are global,
+// the ones inside a function last until the next `func`. Only register
+// operands are rewritten (plus `clobbers` lists, `invoke` arguments and the
+// register of `[rN+offset]`), so an alias never captures a data variable or
+// label of the same name.
+
+pub(crate) struct AliasDiag {
+    pub line: usize, // 0-based
+    pub error: bool,
+    pub msg: String,
+}
+
+struct Alias {
+    reg: usize,
+    line: usize,
+}
+
+fn parse_reg(arg: &str) -> Option<usize> {
+    match arg.strip_prefix('r').map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) if n < 32 => Some(n),
+        _ => None,
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// `alias name = rN` -> (name, reg text), also accepts `alias name rN`
+fn parse_directive(lexems: &[&str]) -> Option<(String, String)> {
+    match lexems {
+        [_, name, "=", reg] | [_, name, reg] => Some((name.to_string(), reg.to_string())),
+        _ => None,
+    }
+}
+
+struct Scopes {
+    global: HashMap<String, Alias>,
+    local: HashMap<String, Alias>,
+    in_func: bool,
+}
+
+impl Scopes {
+    fn get(&self, name: &str) -> Option<usize> {
+        self.local.get(name).or_else(|| self.global.get(name)).map(|a| a.reg)
+    }
+
+    fn define(&mut self, name: String, reg: usize, line: usize, diags: &mut Vec<AliasDiag>) {
+        let scope: &HashMap<String, Alias> = if self.in_func { &self.local } else { &self.global };
+        if let Some(prev) = scope.get(&name) {
+            if prev.reg != reg {
+                diags.push(AliasDiag {
+                    line,
+                    error: true,
+                    msg: format!("alias '{}' already names r{} (line {})", name, prev.reg, prev.line + 1),
+                });
+            }
+            return;
+        }
+        if let (true, Some(outer)) = (self.in_func, self.global.get(&name)) {
+            diags.push(AliasDiag {
+                line,
+                error: false,
+                msg: format!("alias '{}' shadows the global one of line {} (r{})", name, outer.line + 1, outer.reg),
+            });
+        }
+        let visible = self.local.iter().chain(self.global.iter().filter(|(n, _)| !self.local.contains_key(*n)));
+        for (other, alias) in visible {
+            if alias.reg == reg && *other != name {
+                diags.push(AliasDiag {
+                    line,
+                    error: false,
+                    msg: format!("'{}' and '{}' both name r{}", other, name, reg),
+                });
+            }
+        }
+        let scope: &mut HashMap<String, Alias> = if self.in_func { &mut self.local } else { &mut self.global };
+        scope.insert(name, Alias { reg, line });
+    }
+}
+
+/// `[alias+offset]` with the alias replaced by its register
+fn rewrite_mem(token: &str, scopes: &Scopes) -> String {
+    let Some(inner) = token.strip_prefix('[') else {
+        return token.to_string();
+    };
+    let end: usize = inner.find(['+', '-', ']']).unwrap_or(inner.len());
+    match scopes.get(&inner[..end]) {
+        Some(reg) => format!("[r{}{}", reg, &inner[end..]),
+        None => token.to_string(),
+    }
+}
+
+/// Replaces alias names in register positions of `line`
+fn rewrite(line: &str, scopes: &Scopes, table: &HashMap<String, Vec<LexTypes>>) -> String {
+    let (code, comment) = match line.find(['#', ';']) {
+        Some(pos) => line.split_at(pos),
+        None => (line, ""),
+    };
+    let lexems: Vec<&str> = code.split_whitespace().collect();
+    let mnem: &str = match lexems.first() {
+        Some(m) => m,
+        None => return line.to_string(),
+    };
+    // which operand tokens may be registers
+    let reg_pos = |ind: usize| -> bool {
+        match mnem {
+            "clobbers" | "invoke" | "invoker" => true,
+            _ => matches!(table.get(mnem).and_then(|ops| ops.get(ind + 1)), Some(LexTypes::Reg(_))),
+        }
+    };
+    let mem_pos = |ind: usize| -> bool { matches!(table.get(mnem).and_then(|ops| ops.get(ind + 1)), Some(LexTypes::Mem)) };
+    if !table.contains_key(mnem) && !matches!(mnem, "clobbers" | "invoke" | "invoker") {
+        return line.to_string();
+    }
+
+    // keeps the original spacing, commas and `->` of the line
+    let mut res: String = String::new();
+    let mut rest: &str = code;
+    let mut ind: usize = 0;
+    while let Some(start) = rest.find(|c: char| !c.is_whitespace() && c != ',') {
+        res.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end: usize = rest.find(|c: char| c.is_whitespace() || c == ',').unwrap_or(rest.len());
+        let token: &str = &rest[..end];
+        match scopes.get(token) {
+            Some(reg) if ind > 0 && reg_pos(ind) => res.push_str(&format!("r{}", reg)),
+            _ if ind > 0 && mem_pos(ind) => res.push_str(&rewrite_mem(token, scopes)),
+            _ => res.push_str(token),
+        }
+        rest = &rest[end..];
+        ind += 1;
+    }
+    res.push_str(rest);
+    res.push_str(comment);
+    res
+}
+
+/// Resolves aliases of the whole source in place, alias lines become empty
+pub(crate) fn resolve_aliases(lines: &mut [String]) -> Vec<AliasDiag> {
+    let table: HashMap<String, Vec<LexTypes>> = voxasm_instr_table();
+    let mut diags: Vec<AliasDiag> = Vec::new();
+    let mut scopes = Scopes {
+        global: HashMap::new(),
+        local: HashMap::new(),
+        in_func: false,
+    };
+    for (ind, line) in lines.iter_mut().enumerate() {
+        let lexems: Vec<&str> = line
+            .split_whitespace()
+            .take_while(|l| !l.contains('#') && (*l != ";"))
+            .collect();
+        match lexems.first() {
+            Some(&"func") => {
+                scopes.in_func = true;
+                scopes.local.clear();
+            }
+            Some(&"alias") => {
+                let error = |msg: String| AliasDiag { line: ind, error: true, msg };
+                match parse_directive(&lexems) {
+                    Some((name, reg_text)) => match parse_reg(&reg_text) {
+                        _ if !valid_name(&name) => diags.push(error(format!("invalid alias name '{}'", name))),
+                        _ if parse_reg(&name).is_some() || table.contains_key(&name) => {
+                            diags.push(error(format!("alias '{}' would hide a register or instruction", name)))
+                        }
+                        Some(reg) => scopes.define(name, reg, ind, &mut diags),
+                        None => diags.push(error(format!("alias '{}' should name a register, got '{}'", name, reg_text))),
+                    },
+                    None => diags.push(error("alias should be `alias name = rN`".to_string())),
+                }
+                *line = String::new();
+                continue;
+            }
+            _ => {}
+        }
+        if !scopes.global.is_empty() || !scopes.local.is_empty() {
+            *line = rewrite(line, &scopes, &table);
+        }
+    }
+    diags
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use crate::assembly::{parse_int_literal, parse_num_literal, parse_reg_mask};
+
+// Built-in assembler macros, expanded before both assembler passes (and
+// before lint), every expanded instruction keeps the line of its macro.
+//
+//     invoke @func, a1, a2, .. [-> rD]
+//     invoker rF, a1, a2, .. [-> rD]
+//
+// follow the calling convention: arguments go to r1, r2.., the result comes
+// back in r0 and is moved into rD. Registers of the callee's `clobbers`
+// directive are pushed before the call and popped after it (except r0 and
+// rD), so they survive it. `invoker` calls function table index rF, the
+// callee isn't known then and nothing is saved. Arguments are registers or
+// number literals (`5` uint, `-5` int, `5.0` float).
+
+const RESULT_REG: usize = 0;
+
+enum Arg {
+    Reg(usize),
+    Lit(&'static str, String), // load mnemonic, literal
+}
+
+fn parse_reg(arg: &str) -> Option<usize> {
+    match arg.strip_prefix('r').map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) if n < 32 => Some(n),
+        _ => None,
+    }
+}
+
+fn parse_arg(arg: &str) -> Result<Arg, String> {
+    if let Some(reg) = parse_reg(arg) {
+        return Ok(Arg::Reg(reg));
+    }
+    let load: Option<&'static str> = if arg.contains('.') {
+        arg.parse::<f64>().ok().map(|_| "fload")
+    } else if arg.starts_with('-') {
+        parse_int_literal(arg, 64).ok().map(|_| "iload")
+    } else {
+        parse_num_literal(arg).map(|_| "uload")
+    };
+    match load {
+        Some(mnem) => Ok(Arg::Lit(mnem, arg.to_string())),
+        None => Err(format!("argument '{}' is neither a register nor a number", arg)),
+    }
+}
+
+/// Clobbered registers of every function of the source, by name
+pub(crate) fn scan_clobbers(lines: &[String]) -> HashMap<String, u32> {
+    let mut res: HashMap<String, u32> = HashMap::new();
+    let mut cur_func: Option<String> = None;
+    for line in lines {
+        let lexems: Vec<&str> = line.split_whitespace().collect();
+        match lexems.first() {
+            Some(&"func") => cur_func = lexems.get(1).map(|n| n.to_string()),
+            Some(&"clobbers") => {
+                if let (Some(func), Ok(mask)) = (&cur_func, parse_reg_mask(&lexems[1..])) {
+                    *res.entry(func.clone()).or_insert(0) |= mask;
+                }
+            }
+            _ => {}
+        }
+    }
+    res
+}
+
+/// Instructions of a macro line, None if `line` isn't a macro
+pub(crate) fn expand_line(line: &str, clobbers: &HashMap<String, u32>) -> Option<Result<Vec<String>, String>> {
+    let code: &str = line.split(['#', ';']).next().unwrap_or("");
+    let (mnem, rest) = code.trim().split_once(char::is_whitespace).unwrap_or((code.trim(), ""));
+    if (mnem != "invoke") && (mnem != "invoker") {
+        return None;
+    }
+    Some(expand_invoke(mnem, rest, clobbers))
+}
+
+fn expand_invoke(mnem: &str, rest: &str, clobbers: &HashMap<String, u32>) -> Result<Vec<String>, String> {
+    let (call_part, dst) = match rest.split_once("->") {
+        Some((call, dst)) => match parse_reg(dst.trim()) {
+            Some(r) => (call, Some(r)),
+            None => return Err(format!("{}: result should go to a register, got '{}'", mnem, dst.trim())),
+        },
+        None => (rest, None),
+    };
+    let operands: Vec<&str> = call_part
+        .split([',', ' ', '\t'])
+        .filter(|s| !s.is_empty())
+        .collect();
+    let target: &str = match operands.first() {
+        Some(t) => t,
+        None => return Err(format!("{} needs a function", mnem)),
+    };
+    let args: Vec<Arg> = operands[1..].iter().map(|a| parse_arg(a)).collect::<Result<_, _>>()?;
+    if args.len() >= 32 {
+        return Err(format!("{}: too many arguments ({})", mnem, args.len()));
+    }
+
+    let (call, saved_mask): (String, u32) = match mnem {
+        "invoke" => {
+            let name: &str = match target.strip_prefix('@') {
+                Some(n) => n,
+                None => return Err(format!("invoke calls a function by name, as @func, got '{}'", target)),
+            };
+            (format!("call @{}", name), clobbers.get(name).copied().unwrap_or(0))
+        }
+        _ => {
+            let reg: usize = match parse_reg(target) {
+                Some(r) => r,
+                None => return Err(format!("invoker calls a function index register, got '{}'", target)),
+            };
+            if (1..=args.len()).contains(&reg) {
+                return Err(format!("invoker: r{} is overwritten by arguments, keep the index elsewhere", reg));
+            }
+            (format!("callr r{}", reg), 0)
+        }
+    };
+    let saved: Vec<usize> = (0..32)
+        .filter(|r| (saved_mask & (1 << r)) != 0)
+        .filter(|r| (*r != RESULT_REG) && (Some(*r) != dst))
+        .collect();
+
+    let mut res: Vec<String> = Vec::new();
+    for r in &saved {
+        res.push(format!("push r{}", r));
+    }
+    // through the stack, so `invoke @f, r2, r1` doesn't overwrite r1 before reading it
+    let moved: Vec<(usize, usize)> = args
+        .iter()
+        .enumerate()
+        .filter_map(|(i, a)| match a {
+            Arg::Reg(r) if *r != i + 1 => Some((*r, i + 1)),
+            _ => None,
+        })
+        .collect();
+    for (src, _) in &moved {
+        res.push(format!("push r{}", src));
+    }
+    for (_, tgt) in moved.iter().rev() {
+        res.push(format!("pop r{}", tgt));
+    }
+    for (i, arg) in args.iter().enumerate() {
+        if let Arg::Lit(load, lit) = arg {
+            res.push(format!("{} r{} {}", load, i + 1, lit));
+        }
+    }
+    res.push(call);
+    if let Some(r) = dst.filter(|r| *r != RESULT_REG) {
+        res.push(format!("movr r{} r{}", r, RESULT_REG));
+    }
+    for r in saved.iter().rev() {
+        res.push(format!("pop r{}", r));
+    }
+    Ok(res.into_iter().map(|ins| format!("    {}", ins)).collect())
+}
+
+/// Every line with macros expanded, paired with its source line index
+pub(crate) fn expand_macros(lines: Vec<String>) -> Result<Vec<(usize, String)>, (usize, String)> {
+    let clobbers: HashMap<String, u32> = scan_clobbers(&lines);
+    let mut res: Vec<(usize, String)> = Vec::with_capacity(lines.len());
+    for (ind, line) in lines.into_iter().enumerate() {
+        match expand_line(&line, &clobbers) {
+            None => res.push((ind, line)),
+            Some(Ok(instrs)) => res.extend(instrs.into_iter().map(|i| (ind, i))),
+            Some(Err(e)) => return Err((ind, e)),
+        }
+    }
+    Ok(res)
+}
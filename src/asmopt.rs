@@ -0,0 +1,436 @@
+// `--opt`: source level optimizations done by the assembler before both stages.
+// Lines are rewritten or blanked, never added or removed, so line numbers in
+// diagnostics and the vve line table stay the same. Jumps and tables have to
+// use labels, as every removed byte moves the code after it.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::assembly::{
+    encode_data_var, inline_data, parse_int_literal, parse_num_literal, parse_uint_literal, short_load_form, table_entries, voxasm_instr_table, LexTypes,
+};
+use crate::vaslint::{reg_access, unreachable_lines, Acc};
+use crate::vm::RegistersCount;
+
+#[derive(Debug, Default)]
+pub struct OptStats {
+    pub folded: usize,
+    pub dead_loads: usize,
+    pub unreachable: usize,
+    pub code_before: u64,
+    pub code_after: u64,
+    pub dropped_vars: usize,
+    pub data_saved: u64,
+}
+
+impl OptStats {
+    pub fn report(&self) -> String {
+        format!(
+            "opt: folded {} constant op(s), removed {} dead load(s) and {} unreachable instruction(s), \
+             code {} -> {} bytes; dropped {} unused data variable(s), {} bytes",
+            self.folded,
+            self.dead_loads,
+            self.unreachable,
+            self.code_before,
+            self.code_after,
+            self.dropped_vars,
+            self.data_saved
+        )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Const {
+    Uint(u64),
+    Int(i64),
+}
+
+#[derive(PartialEq)]
+enum LineKind {
+    Other,     // blanks, comments, sections, clobbers..
+    Boundary,  // label, func, .start, table: control can arrive from elsewhere
+    Instr,
+    DataVar,
+    DataAlign, // !align= of the next variable
+}
+
+/// Instructions that touch only the registers they name and can't leave the
+/// block, loads known before them stay known after them
+const PURE: [&str; 50] = [
+    "nop", "uload", "uload32", "iload", "iload32", "fload", "lea", "uadd", "umul", "usub", "ucmp",
+    "uinc", "udec", "iadd", "imul", "isub", "icmp", "iabs", "ineg", "iinc", "idec", "fadd",
+    "fmul", "fsub", "fcmp", "fabs", "fneg", "finc", "fdec", "utoi", "itou", "utof", "itof",
+    "movr", "or", "and", "not", "xor", "test", "lnot", "cmovz", "cmovl", "cmovg", "xchg", "sete",
+    "setne", "setl", "setg", "setge", "setle",
+];
+
+/// Removable when the register is overwritten before anything reads it
+const LOADS: [&str; 6] = ["uload", "uload32", "iload", "iload32", "fload", "lea"];
+
+fn code_lexems(line: &str) -> Vec<&str> {
+    line.split_whitespace()
+        .take_while(|l| !l.contains('#') && (*l != ";"))
+        .collect()
+}
+
+fn parse_reg(arg: &str) -> Option<usize> {
+    match arg.strip_prefix('r').map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) if n < RegistersCount => Some(n),
+        _ => None,
+    }
+}
+
+/// `movr` only writes its destination, the other Skip operands are read too
+pub(crate) fn operand_access(mnem: &str, k: usize) -> Acc {
+    match reg_access(mnem).get(k).copied().unwrap_or(Acc::R) {
+        Acc::Skip if mnem == "movr" => Acc::W,
+        Acc::Skip => Acc::RW,
+        a => a,
+    }
+}
+
+fn classify(lines: &[String]) -> Vec<LineKind> {
+    let mut in_code: bool = true;
+    lines
+        .iter()
+        .map(|line| {
+            let lexems: Vec<&str> = code_lexems(line);
+            match lexems.first().copied() {
+                None => LineKind::Other,
+                Some("section") => {
+                    in_code = lexems.get(1) == Some(&"text");
+                    LineKind::Boundary
+                }
+                Some(l) if !in_code && l.starts_with("!align=") => LineKind::DataAlign,
+                Some(_) if !in_code => LineKind::DataVar,
+                Some("label") | Some("func") | Some(".start") | Some("table") => LineKind::Boundary,
+                Some("clobbers") | Some("ncalldef") => LineKind::Other,
+                Some(_) => LineKind::Instr,
+            }
+        })
+        .collect()
+}
+
+/// Replaces the code of a line keeping its indentation and trailing comment
+fn rewrite(line: &str, code: String) -> String {
+    let indent: &str = &line[..(line.len() - line.trim_start().len())];
+    let comment: Option<&str> = line
+        .split_whitespace()
+        .find(|l| l.contains('#') || (*l == ";"))
+        .and_then(|c| line.find(c).map(|pos| &line[pos..]));
+    match comment {
+        Some(c) => format!("{}{}  {}", indent, code, c),
+        None => format!("{}{}", indent, code),
+    }
+}
+
+fn instr_size(line: &str, table: &HashMap<String, Vec<LexTypes>>) -> u64 {
+    let lexems: Vec<&str> = short_load_form(code_lexems(line));
+    let inline: u64 = match inline_data(line, &lexems) {
+        Some(Ok(data)) => data.len() as u64,
+        _ => 0,
+    };
+    match lexems.first().and_then(|m| table.get(*m)).and_then(|ops| ops.get(1)) {
+        Some(LexTypes::Size(s)) => *s + inline,
+        _ => 0,
+    }
+}
+
+fn code_size(lines: &[String], kinds: &[LineKind], table: &HashMap<String, Vec<LexTypes>>) -> u64 {
+    let mut size: u64 = 0;
+    for (line, kind) in lines.iter().zip(kinds) {
+        let lexems: Vec<&str> = code_lexems(line);
+        match kind {
+            LineKind::Boundary if lexems[0] == "table" => {
+                size += 8 + 8 + (table_entries(&lexems).len() as u64) * 8;
+            }
+            LineKind::Instr => size += instr_size(line, table),
+            _ => {}
+        }
+    }
+    size
+}
+
+/// Data segment size without padding, identical const strings counted once
+fn data_size(lines: &[String], kinds: &[LineKind]) -> u64 {
+    let mut size: u64 = 0;
+    let mut interned: HashSet<Vec<u8>> = HashSet::new();
+    let mut rodata: bool = false;
+    for (line_num, (line, kind)) in lines.iter().zip(kinds).enumerate() {
+        let lexems: Vec<&str> = line.split_whitespace().collect();
+        if lexems.first() == Some(&"section") {
+            rodata = lexems.get(1) == Some(&"rodata");
+        }
+        if *kind != LineKind::DataVar {
+            continue;
+        }
+        let ro: bool = rodata || lexems.get(1) == Some(&"const");
+        let bytes: Vec<u8> = encode_data_var(line, &lexems, line_num, ro);
+        if ro && (bytes[0] & 0x0F == 0x4) && !interned.insert(bytes.clone()) {
+            continue;
+        }
+        size += bytes.len() as u64;
+    }
+    size
+}
+
+/// Numeric jump targets and table entries can't survive code moving around.
+/// Returns whether data variables are only ever named (so unused ones can go)
+fn check_addresses(
+    lines: &[String],
+    kinds: &[LineKind],
+    table: &HashMap<String, Vec<LexTypes>>,
+) -> Result<bool, String> {
+    let mut data_named: bool = true;
+    for (ind, (line, kind)) in lines.iter().zip(kinds).enumerate() {
+        let lexems: Vec<&str> = code_lexems(line);
+        match kind {
+            _ if lexems.first() == Some(&".org") => {
+                return Err(format!("{}: .org pins addresses", ind + 1));
+            }
+            LineKind::Boundary if lexems[0] == "table" => {
+                let mut targets: Vec<&str> = table_entries(&lexems);
+                targets.extend(lexems.get(2));
+                if let Some(t) = targets.iter().find(|t| !t.starts_with('@')) {
+                    return Err(format!("{}: table entry '{}' is not a label", ind + 1, t));
+                }
+            }
+            LineKind::Instr => {
+                let ops: &Vec<LexTypes> = match table.get(lexems[0]) {
+                    Some(v) => v,
+                    None => return Err(format!("{}: unknown instruction '{}'", ind + 1, lexems[0])),
+                };
+                let ds_op: bool = matches!(ops[0], LexTypes::Op(0x70..=0x7F));
+                let mut first_addr: bool = true;
+                for (arg, op) in lexems[1..].iter().zip(&ops[2..]) {
+                    if !matches!(op, LexTypes::Addr(_)) {
+                        continue;
+                    }
+                    if ds_op {
+                        // the first address of a data segment op is the variable
+                        data_named &= !first_addr || parse_num_literal(arg).is_none();
+                        first_addr = false;
+                    } else if !arg.starts_with('@') {
+                        return Err(format!("{}: jump target '{}' is not a label", ind + 1, arg));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(data_named)
+}
+
+/// Block-local constant propagation from immediate loads. A flag-free integer
+/// op on two known registers becomes a load of the result when the load of its
+/// destination hasn't been read yet, so that load dies and the code shrinks.
+fn fold_constants(lines: &mut [String], kinds: &[LineKind], table: &HashMap<String, Vec<LexTypes>>) -> usize {
+    let mut folded: usize = 0;
+    let mut known: [Option<Const>; RegistersCount] = [None; RegistersCount];
+    let mut unread_load: [Option<usize>; RegistersCount] = [None; RegistersCount]; // reg -> line
+    for ind in 0..lines.len() {
+        match kinds[ind] {
+            LineKind::Boundary => {
+                known = [None; RegistersCount];
+                unread_load = [None; RegistersCount];
+            }
+            LineKind::Instr => {}
+            _ => continue,
+        }
+        let line: String = lines[ind].clone();
+        let lexems: Vec<&str> = code_lexems(&line);
+        if lexems.is_empty() || kinds[ind] != LineKind::Instr {
+            continue;
+        }
+        let mnem: &str = lexems[0];
+        if !PURE.contains(&mnem) {
+            known = [None; RegistersCount];
+            unread_load = [None; RegistersCount];
+            continue;
+        }
+        let regs: Vec<Option<usize>> = lexems[1..].iter().map(|a| parse_reg(a)).collect();
+        let (d, s): (Option<usize>, Option<usize>) =
+            (regs.first().copied().flatten(), regs.get(1).copied().flatten());
+
+        let (value, is_load): (Option<Const>, bool) = match (mnem, d, s) {
+            ("uload" | "uload32", Some(_), _) => {
+                let bits: u32 = if mnem == "uload" { 64 } else { 32 };
+                (lexems.get(2).and_then(|a| parse_uint_literal(a, bits).ok()).map(Const::Uint), true)
+            }
+            ("iload" | "iload32", Some(_), _) => {
+                let bits: u32 = if mnem == "iload" { 64 } else { 32 };
+                (lexems.get(2).and_then(|a| parse_int_literal(a, bits).ok()).map(Const::Int), true)
+            }
+            ("movr", Some(_), Some(s)) => (known[s], false),
+            ("uadd" | "umul" | "iadd" | "imul" | "isub", Some(d), Some(s)) => {
+                let res: Option<Const> = match (known[d], known[s]) {
+                    (Some(Const::Uint(a)), Some(Const::Uint(b))) => match mnem {
+                        "uadd" => a.checked_add(b),
+                        "umul" => a.checked_mul(b),
+                        _ => None,
+                    }
+                    .map(Const::Uint),
+                    (Some(Const::Int(a)), Some(Const::Int(b))) => match mnem {
+                        "iadd" => a.checked_add(b),
+                        "imul" => a.checked_mul(b),
+                        "isub" => a.checked_sub(b),
+                        _ => None,
+                    }
+                    .map(Const::Int),
+                    _ => None,
+                };
+                let code: Option<String> = res.map(|c| match c {
+                    Const::Uint(v) => format!("uload r{} {}", d, v),
+                    Const::Int(v) => format!("iload r{} {}", d, v),
+                });
+                match (code, unread_load[d]) {
+                    (Some(code), Some(old))
+                        if instr_size(&code, table) <= instr_size(&lines[old], table) + instr_size(&line, table) =>
+                    {
+                        lines[ind] = rewrite(&line, code);
+                        folded += 1;
+                        known[d] = res;
+                        unread_load[d] = Some(ind);
+                        continue;
+                    }
+                    _ => (None, false),
+                }
+            }
+            ("xchg", Some(d), Some(s)) => {
+                known.swap(d, s);
+                unread_load.swap(d, s);
+                continue;
+            }
+            _ => (None, false),
+        };
+
+        for (k, r) in regs.iter().enumerate() {
+            if let Some(r) = r {
+                if operand_access(mnem, k) != Acc::W {
+                    unread_load[*r] = None;
+                }
+                if operand_access(mnem, k) != Acc::R {
+                    known[*r] = None;
+                    unread_load[*r] = None;
+                }
+            }
+        }
+        if let (Some(d), Some(_)) = (d, value) {
+            known[d] = value;
+            if is_load {
+                unread_load[d] = Some(ind);
+            }
+        }
+    }
+    folded
+}
+
+/// Drops loads whose register is overwritten before anything reads it.
+/// Anything that may leave the block or read registers on its own ends it.
+fn remove_dead_loads(lines: &mut [String], kinds: &[LineKind]) -> usize {
+    let mut removed: usize = 0;
+    let mut pending: [Option<usize>; RegistersCount] = [None; RegistersCount];
+    for ind in 0..lines.len() {
+        match kinds[ind] {
+            LineKind::Boundary => pending = [None; RegistersCount],
+            LineKind::Instr => {}
+            _ => continue,
+        }
+        let line: String = lines[ind].clone();
+        let lexems: Vec<&str> = code_lexems(&line);
+        if lexems.is_empty() || kinds[ind] != LineKind::Instr {
+            continue;
+        }
+        let mnem: &str = lexems[0];
+        if !PURE.contains(&mnem) {
+            pending = [None; RegistersCount];
+            continue;
+        }
+
+        let regs: Vec<(usize, Acc)> = lexems[1..]
+            .iter()
+            .enumerate()
+            .filter_map(|(k, a)| parse_reg(a).map(|r| (r, operand_access(mnem, k))))
+            .collect();
+        for (r, acc) in &regs {
+            if *acc != Acc::W {
+                pending[*r] = None;
+            }
+        }
+        for (r, acc) in &regs {
+            if *acc != Acc::W {
+                continue;
+            }
+            if let Some(dead) = pending[*r].take() {
+                lines[dead] = String::new();
+                removed += 1;
+            }
+            if LOADS.contains(&mnem) {
+                pending[*r] = Some(ind);
+            }
+        }
+    }
+    removed
+}
+
+/// Blanks data variables no code line names, with the !align= meant for them
+fn drop_unused_data(lines: &mut [String], kinds: &[LineKind]) -> usize {
+    let mut names: HashSet<String> = HashSet::new();
+    for (line, kind) in lines.iter().zip(kinds) {
+        let lexems: Vec<&str> = code_lexems(line);
+        let skip: usize = match kind {
+            LineKind::Instr | LineKind::Boundary => 0,
+            LineKind::DataVar => 2, // values may name other variables
+            _ => continue,
+        };
+        names.extend(lexems.iter().skip(skip).map(|l| l.trim_start_matches('@').to_string()));
+    }
+
+    let mut dropped: usize = 0;
+    let mut align_line: Option<usize> = None;
+    for ind in 0..lines.len() {
+        match kinds[ind] {
+            LineKind::DataAlign => align_line = Some(ind),
+            LineKind::DataVar => {
+                let name: String = code_lexems(&lines[ind])[0].to_string();
+                if !names.contains(&name) {
+                    lines[ind] = String::new();
+                    if let Some(a) = align_line {
+                        lines[a] = String::new();
+                    }
+                    dropped += 1;
+                }
+                align_line = None;
+            }
+            _ => {}
+        }
+    }
+    dropped
+}
+
+/// Runs all of the passes over the source lines. On Err nothing is changed.
+pub fn optimize(lines: &mut [String]) -> Result<OptStats, String> {
+    let table: HashMap<String, Vec<LexTypes>> = voxasm_instr_table();
+    let kinds: Vec<LineKind> = classify(lines);
+    let data_named: bool = check_addresses(lines, &kinds, &table)?;
+
+    let mut stats: OptStats = OptStats {
+        code_before: code_size(lines, &kinds, &table),
+        ..Default::default()
+    };
+    let data_before: u64 = data_size(lines, &kinds);
+
+    for line in unreachable_lines(&lines.join("\n")) {
+        lines[line - 1] = String::new();
+        stats.unreachable += 1;
+    }
+    stats.folded = fold_constants(lines, &kinds, &table);
+    stats.dead_loads = remove_dead_loads(lines, &kinds);
+    if data_named {
+        stats.dropped_vars = drop_unused_data(lines, &kinds);
+    }
+
+    let kinds: Vec<LineKind> = classify(lines);
+    stats.code_after = code_size(lines, &kinds, &table);
+    stats.data_saved = data_before - data_size(lines, &kinds);
+    Ok(stats)
+}
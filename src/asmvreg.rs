@@ -0,0 +1,355 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::asmopt::operand_access;
+use crate::assembly::{parse_num_literal, table_entries, voxasm_instr_table, LexTypes};
+use crate::vaslint::Acc;
+use crate::vm::RegistersCount;
+
+// Virtual registers: code may name `v0`, `v1`.. wherever a register goes
+// (and as the register of `[vN+offset]`). After macros, every function (and
+// the code before the first one) gets its virtual registers mapped onto the
+// physical ones it doesn't name itself, by a linear scan over live ranges:
+// a range runs from the first to the last mention of the register and
+// covers whole loops (backward jumps) it is live in. r0..r6 stay out when
+// the function has an ncall, they take its results. When the free
+// registers run out, the ranges ending last are spilled into local slots of
+// the call frame (`lset`/`lget`, numbered after the slots the function uses
+// itself), a few registers are then kept aside to load spilled operands of
+// an instruction. Ranges live across a `call`/`callr` or a coroutine switch
+// always go to slots, the callee may write any register. Expanded and spill
+// instructions keep the line of the original one. Macro arguments and
+// `alias` stay physical registers.
+
+const NCALL_RET_REGS: usize = 7; // ncalls return up to r0..r6 (heap_stats)
+
+/// Instructions that may overwrite every register before the next one runs
+const CALLS: &[&str] = &["call", "callr", "coresume", "coyield"];
+
+const JUMPS: &[&str] = &["jmp", "jz", "jl", "jg", "jge", "jle", "jnz", "jexc"];
+
+fn parse_vreg(token: &str) -> Option<usize> {
+    token.strip_prefix('v').and_then(|n| n.parse::<usize>().ok())
+}
+
+fn parse_reg(token: &str) -> Option<usize> {
+    match token.strip_prefix('r').map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) if n < RegistersCount => Some(n),
+        _ => None,
+    }
+}
+
+/// Register name of `[reg+offset]`, with the rest of the operand
+fn split_mem(token: &str) -> Option<(&str, &str)> {
+    let inner: &str = token.strip_prefix('[')?;
+    let end: usize = inner.find(['+', '-', ']']).unwrap_or(inner.len());
+    Some((&inner[..end], &inner[end..]))
+}
+
+/// A register operand: token index in the line and whether it is `[reg+offset]`
+struct RegOperand {
+    token: usize,
+    mem: bool,
+}
+
+struct Instr {
+    pos: usize, // index into the region's lines
+    mnem: String,
+    tokens: Vec<String>,
+    regs: Vec<RegOperand>,
+}
+
+impl Instr {
+    fn reg_name(&self, op: &RegOperand) -> &str {
+        let token: &str = &self.tokens[op.token];
+        match op.mem {
+            true => split_mem(token).map_or(token, |(reg, _)| reg),
+            false => token,
+        }
+    }
+
+    /// Virtual registers of the instruction with how it accesses them, in
+    /// order of appearance
+    fn vregs(&self) -> Vec<(usize, Acc)> {
+        let mut res: Vec<(usize, Acc)> = Vec::new();
+        for (k, op) in self.regs.iter().enumerate() {
+            let Some(v) = parse_vreg(self.reg_name(op)) else {
+                continue;
+            };
+            let acc: Acc = match op.mem {
+                true => Acc::R,
+                false => operand_access(&self.mnem, k),
+            };
+            match res.iter_mut().find(|(r, _)| *r == v) {
+                Some((_, a)) if *a != acc => *a = Acc::RW,
+                Some(_) => {}
+                None => res.push((v, acc)),
+            }
+        }
+        res
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Range {
+    vreg: usize,
+    start: usize, // instruction indices
+    end: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Home {
+    Reg(usize),
+    Slot(u64),
+}
+
+/// Maps the virtual registers of `lines` (line index, text) onto physical
+/// registers and local slots, spill instructions are added after macros
+pub(crate) fn resolve_vregs(lines: Vec<(usize, String)>) -> Result<Vec<(usize, String)>, (usize, String)> {
+    let table: HashMap<String, Vec<LexTypes>> = voxasm_instr_table();
+    if !lines.iter().any(|(_, l)| code_tokens(l).iter().any(|t| t.starts_with('v') || t.starts_with("[v"))) {
+        return Ok(lines);
+    }
+    let mut res: Vec<(usize, String)> = Vec::with_capacity(lines.len());
+    let mut region: Vec<(usize, String)> = Vec::new();
+    for (line_num, line) in lines {
+        if code_tokens(&line).first() == Some(&"func") {
+            res.extend(resolve_region(std::mem::take(&mut region), &table)?);
+        }
+        region.push((line_num, line));
+    }
+    res.extend(resolve_region(region, &table)?);
+    Ok(res)
+}
+
+fn code_tokens(line: &str) -> Vec<&str> {
+    line.split_whitespace()
+        .take_while(|l| !l.contains('#') && (*l != ";"))
+        .collect()
+}
+
+/// Allocation of one function, `lines` start at its `func` line
+fn resolve_region(lines: Vec<(usize, String)>, table: &HashMap<String, Vec<LexTypes>>) -> Result<Vec<(usize, String)>, (usize, String)> {
+    let mut instrs: Vec<Instr> = Vec::new();
+    let mut labels: HashMap<String, usize> = HashMap::new(); // name -> instr index
+    let mut tables: Vec<(usize, Vec<String>)> = Vec::new(); // instr index -> targets
+    let mut in_code: bool = true;
+    for (pos, (_, line)) in lines.iter().enumerate() {
+        let tokens: Vec<&str> = code_tokens(line);
+        match tokens.first().copied() {
+            None => continue,
+            Some("section") => in_code = tokens.get(1) == Some(&"text"),
+            _ if !in_code => {}
+            Some("label") => {
+                if let Some(name) = tokens.get(1) {
+                    labels.insert(name.to_string(), instrs.len());
+                }
+            }
+            Some("table") => {
+                let mut targets: Vec<String> = tokens.get(2).map(|d| d.to_string()).into_iter().collect();
+                targets.extend(table_entries(&tokens).iter().map(|t| t.to_string()));
+                tables.push((instrs.len(), targets));
+            }
+            Some(mnem) => {
+                let Some(ops) = table.get(mnem) else {
+                    continue;
+                };
+                let regs: Vec<RegOperand> = (1..tokens.len())
+                    .filter_map(|token| match ops.get(token + 1) {
+                        Some(LexTypes::Reg(_)) => Some(RegOperand { token, mem: false }),
+                        Some(LexTypes::Mem) => Some(RegOperand { token, mem: true }),
+                        _ => None,
+                    })
+                    .collect();
+                instrs.push(Instr {
+                    pos,
+                    mnem: mnem.to_string(),
+                    tokens: tokens.iter().map(|t| t.to_string()).collect(),
+                    regs,
+                });
+            }
+        }
+    }
+
+    let mut ranges: Vec<Range> = Vec::new();
+    let mut used_regs: u64 = 0;
+    let mut has_ncall: bool = false;
+    let mut first_slot: u64 = 0;
+    for (ind, ins) in instrs.iter().enumerate() {
+        has_ncall |= ins.mnem == "ncall";
+        if let Some(slot) = ins.tokens.get(2).filter(|_| ins.mnem == "lget" || ins.mnem == "lset").and_then(|s| parse_num_literal(s)) {
+            first_slot = first_slot.max(slot.saturating_add(1));
+        }
+        for op in &ins.regs {
+            let name: &str = ins.reg_name(op);
+            if let Some(r) = parse_reg(name) {
+                used_regs |= 1 << r;
+            } else if let Some(v) = parse_vreg(name) {
+                match ranges.iter_mut().find(|r| r.vreg == v) {
+                    Some(range) => range.end = ind,
+                    None => ranges.push(Range { vreg: v, start: ind, end: ind }),
+                }
+            }
+        }
+    }
+    if ranges.is_empty() {
+        return Ok(lines);
+    }
+
+    // backward jumps: a range live anywhere in a loop is live in all of it
+    let target_of = |name: &str| labels.get(name.trim_start_matches('@')).copied();
+    let mut loops: Vec<(usize, usize)> = Vec::new();
+    for (ind, ins) in instrs.iter().enumerate() {
+        let jump_target = ins.tokens.last().filter(|_| JUMPS.contains(&ins.mnem.as_str())).and_then(|l| target_of(l));
+        if let Some(t) = jump_target.filter(|t| *t <= ind) {
+            loops.push((t, ind));
+        }
+        if ins.mnem == "switch" {
+            let name: &str = ins.tokens.last().map_or("", |t| t.trim_start_matches('@'));
+            for (_, targets) in tables.iter().filter(|(at, _)| labels.get(name) == Some(at)) {
+                loops.extend(targets.iter().filter_map(|t| target_of(t)).filter(|t| *t <= ind).map(|t| (t, ind)));
+            }
+        }
+    }
+    let mut changed: bool = true;
+    while changed {
+        changed = false;
+        for range in ranges.iter_mut() {
+            for &(head, tail) in &loops {
+                if (range.start <= tail) && (range.end >= head) && ((range.start > head) || (range.end < tail)) {
+                    range.start = range.start.min(head);
+                    range.end = range.end.max(tail);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    let mut free: Vec<usize> = (0..RegistersCount)
+        .rev()
+        .filter(|r| used_regs & (1 << r) == 0)
+        .filter(|r| !has_ncall || *r >= NCALL_RET_REGS)
+        .collect();
+    let across_call = |range: &Range| {
+        instrs[(range.start + 1)..range.end].iter().any(|ins| CALLS.contains(&ins.mnem.as_str()))
+    };
+    ranges.sort_by_key(|r| (r.start, r.vreg));
+
+    // scratch registers for spilled operands, as many as an instruction needs
+    let mut scratch: Vec<usize> = Vec::new();
+    let homes: HashMap<usize, Home> = loop {
+        let homes: HashMap<usize, Home> = linear_scan(&ranges, &free, first_slot, &across_call);
+        let need: usize = instrs
+            .iter()
+            .map(|ins| ins.vregs().iter().filter(|(v, _)| matches!(homes[v], Home::Slot(_))).count())
+            .max()
+            .unwrap_or(0);
+        if need <= scratch.len() {
+            break homes;
+        }
+        match free.pop() {
+            Some(r) => scratch.push(r),
+            None => {
+                let line: usize = lines[instrs[0].pos].0;
+                return Err((line, "not enough free registers to load spilled virtual registers".to_string()));
+            }
+        }
+    };
+
+    let mut res: Vec<(usize, String)> = lines.clone();
+    let mut extra: Vec<(usize, Vec<String>, Vec<String>)> = Vec::new(); // line pos, before, after
+    for ins in &instrs {
+        let (line_num, line) = &lines[ins.pos];
+        let indent: &str = &line[..(line.len() - line.trim_start().len())];
+        let mut regs: HashMap<usize, usize> = HashMap::new();
+        let (mut before, mut after): (Vec<String>, Vec<String>) = (Vec::new(), Vec::new());
+        let mut spilled: usize = 0;
+        for (v, acc) in ins.vregs() {
+            match homes[&v] {
+                Home::Reg(r) => {
+                    regs.insert(v, r);
+                }
+                Home::Slot(slot) => {
+                    let r: usize = scratch[spilled];
+                    spilled += 1;
+                    regs.insert(v, r);
+                    if acc != Acc::W {
+                        before.push(format!("{}lget r{} {}", indent, r, slot));
+                    }
+                    if acc != Acc::R {
+                        after.push(format!("{}lset r{} {}", indent, r, slot));
+                    }
+                }
+            }
+        }
+        let mut tokens: Vec<String> = ins.tokens.clone();
+        for op in &ins.regs {
+            let token: &str = &ins.tokens[op.token];
+            let (name, rest) = match op.mem {
+                true => split_mem(token).unwrap_or((token, "")),
+                false => (token, ""),
+            };
+            if let Some(r) = parse_vreg(name).and_then(|v| regs.get(&v)) {
+                tokens[op.token] = match op.mem {
+                    true => format!("[r{}{}", r, rest),
+                    false => format!("r{}", r),
+                };
+            }
+        }
+        let comment: &str = line.find(['#', ';']).map_or("", |at| &line[at..]);
+        let code: String = format!("{}{}", indent, tokens.join(" "));
+        res[ins.pos] = (*line_num, if comment.is_empty() { code } else { format!("{}  {}", code, comment) });
+        if !before.is_empty() || !after.is_empty() {
+            extra.push((ins.pos, before, after));
+        }
+    }
+    for (pos, before, after) in extra.into_iter().rev() {
+        let line_num: usize = res[pos].0;
+        res.splice((pos + 1)..(pos + 1), after.into_iter().map(|l| (line_num, l)));
+        res.splice(pos..pos, before.into_iter().map(|l| (line_num, l)));
+    }
+    Ok(res)
+}
+
+/// Register or slot of every virtual register: ranges take a free register
+/// by start, when none is left the one of the live ranges ending last goes
+/// to a slot
+fn linear_scan(ranges: &[Range], free: &[usize], first_slot: u64, across_call: &dyn Fn(&Range) -> bool) -> HashMap<usize, Home> {
+    let mut homes: HashMap<usize, Home> = HashMap::new();
+    let mut free: Vec<usize> = free.to_vec();
+    let mut active: Vec<Range> = Vec::new();
+    let mut next_slot: u64 = first_slot;
+    let mut to_slot = |homes: &mut HashMap<usize, Home>, vreg: usize| {
+        homes.insert(vreg, Home::Slot(next_slot));
+        next_slot += 1;
+    };
+    for range in ranges {
+        let expired: BTreeSet<usize> = active.iter().filter(|a| a.end < range.start).map(|a| a.vreg).collect();
+        for a in active.iter().filter(|a| expired.contains(&a.vreg)) {
+            if let Home::Reg(r) = homes[&a.vreg] {
+                free.push(r);
+            }
+        }
+        active.retain(|a| !expired.contains(&a.vreg));
+        if across_call(range) {
+            to_slot(&mut homes, range.vreg);
+            continue;
+        }
+        if let Some(r) = free.pop() {
+            homes.insert(range.vreg, Home::Reg(r));
+            active.push(*range);
+            continue;
+        }
+        // spill whichever of the live ranges ends last
+        match active.iter().enumerate().max_by_key(|(_, a)| a.end) {
+            Some((ind, longest)) if longest.end > range.end => {
+                let longest: Range = active.remove(ind);
+                let reg: Home = homes[&longest.vreg];
+                to_slot(&mut homes, longest.vreg);
+                homes.insert(range.vreg, reg);
+                active.push(*range);
+            }
+            _ => to_slot(&mut homes, range.vreg),
+        }
+    }
+    homes
+}
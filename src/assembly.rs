@@ -4,22 +4,31 @@ use regex::Regex;
 use std::{
     any::type_name,
     clone,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs::{File, OpenOptions},
-    io::{BufRead, BufReader, Read, Seek, Write},
+    io::{BufRead, BufReader, Write},
     str::FromStr,
 };
 
-use crate::{fileformats::VoxExeHeader, func_ops};
+use crate::asmalias::resolve_aliases;
+use crate::asmmacro::expand_macros;
+use crate::asmvreg::resolve_vregs;
+use crate::asmopt::optimize;
+use crate::dstype::DsType;
+use crate::vaslint::stack_usage;
+use crate::vm::EXT_PREFIX;
+use crate::{fileformats::{data_symbols_section, ncalls_section, VoxExeHeader, VveSection, SECT_FUNC_META, SECT_INTERN, SECT_LINES, SECT_RELOCS, SECT_RODATA, SECT_STACK_DEPTH, SECT_SYMBOLS, SECT_WORDS, ByteOrder, RELOC_CODE, RELOC_DATA_ABS, RELOC_DATA_REL, RELOC_FUNC}};
 //use crate::fileformats::VoxExeHeader;
 
 #[derive(Debug, Clone, Copy)]
-enum LexTypes {
+pub(crate) enum LexTypes {
     Op(u8),
-    Size(u64), // size of instr in bytes
+    ExtOp(u8), // second byte after EXT_PREFIX
+    Size(u64), // size of instr in bytes, the prefix included
     NcallNum(u16),
     Reg(u8),
     Addr(u64),
+    Mem, // `[rN+offset]`: register, then the i64 offset
     Value(u64),
     FuncInd(u64),
     Exception(u64),
@@ -29,9 +38,24 @@ enum LexTypes {
 enum CurrentSection {
     Code,
     Data,
+    ROData, // every variable is const
     None,
 }
 
+/// Data segment variable, laid out after the first stage:
+/// read-only ones first, then the mutable ones.
+struct DataVar {
+    line: usize,
+    size: u64, // type, length and payload
+    align: u64,
+    ro: bool,
+    pad: u64, // padding before the variable
+    org: Option<u64>, // `.org` address of the variable's type byte
+    addr: u64, // of the type byte, relative to the data segment start
+}
+
+const NOP: u8 = 0x02; // `.org` padding in code
+
 pub struct VoxAssembly {
     cur_addr: u64,
     entry: u64,
@@ -40,15 +64,35 @@ pub struct VoxAssembly {
     data_labels: HashMap<String, u64>,
     instr_table: HashMap<String, Vec<LexTypes>>,
     bin_buffer: Vec<u8>,
-    input_file: File,
-    output_file: File,
-    read_buffer: BufReader<File>,
+    source: Vec<String>,       // input lines
+    output_file: Option<File>, // None when assembling in memory
+    image: Vec<u8>,            // the output file bytes, after `assemble`
     is_vve: bool,
     cursect: CurrentSection,
     data_size: u64,
     func_table: HashMap<String, u64>,
     func_indices: HashMap<String, u64>,
     exception_table: HashMap<String, u64>,
+    ncall_names: HashMap<String, u16>, // ncall @name -> code
+    ncalls_used: Vec<u16>,             // codes of emitted ncalls, for SECT_NCALLS
+    interned: HashMap<String, u64>, // const str text -> rel addr
+    intern_dups: HashSet<usize>,    // lines of deduplicated const strs
+    data_vars: Vec<DataVar>,
+    ro_size: u64, // size of the read-only part at the data segment start
+    ro_buffer: Vec<u8>,
+    rw_buffer: Vec<u8>,
+    cur_func: Option<String>,
+    optimize: bool,
+    opt_report: Option<String>,
+    alias_warned: bool, // both passes read the source, warn once
+    func_clobbers: HashMap<String, u32>, // func name -> clobbered regs mask
+    line_table: Vec<(u64, u32)>,         // instr addr -> source line (1-based)
+    code_layout: Vec<(usize, u64, u64)>, // first stage code lines: line, addr, size
+    relocs: Vec<(u64, u8)>,              // code offset -> RELOC_* kind
+    code_words: Vec<(u64, u8)>,          // multi-byte code operands: offset, width
+    byte_order: ByteOrder,               // of the output vve
+    stdlib_funcs: HashMap<String, u64>,  // `--stdlib` function name -> reserved index
+    stack_depths: HashMap<String, u64>,  // func name -> deepest data stack of its body, in slots
 }
 
 impl VoxAssembly {
@@ -57,10 +101,6 @@ impl VoxAssembly {
             true => true,
             false => false,
         };
-        let default_entry: u64 = 0;
-        let labels: HashMap<String, u64> = HashMap::new();
-        let data_labels: HashMap<String, u64> = HashMap::new();
-        let buf: Vec<u8> = Vec::new();
 
         let in_file: File;
         {
@@ -81,6 +121,21 @@ impl VoxAssembly {
             Ok(file) => in_file = file,
             Err(err) => panic!("ERROR: While opening input voxasm file: {}", err),
         }
+        let source: Vec<String> = BufReader::new(in_file).lines().map(|l| l.unwrap()).collect();
+        VoxAssembly::with_source(source, Some(out_file), is_vve)
+    }
+
+    /// Assembler of `src` into a vve image kept in memory, read it with
+    /// `image` after `assemble`
+    pub fn from_source(src: &str) -> VoxAssembly {
+        VoxAssembly::with_source(src.lines().map(String::from).collect(), None, true)
+    }
+
+    fn with_source(source: Vec<String>, output_file: Option<File>, is_vve: bool) -> VoxAssembly {
+        let default_entry: u64 = 0;
+        let labels: HashMap<String, u64> = HashMap::new();
+        let data_labels: HashMap<String, u64> = HashMap::new();
+        let buf: Vec<u8> = Vec::new();
 
         let func_table: HashMap<String, u64> = HashMap::new();
         let func_indices: HashMap<String, u64> = HashMap::new();
@@ -93,26 +148,50 @@ impl VoxAssembly {
             data_labels: data_labels,
             instr_table: voxasm_instr_table(),
             bin_buffer: buf,
-            output_file: out_file,
-            read_buffer: BufReader::new(in_file.try_clone().unwrap()),
-            input_file: in_file,
+            source: source,
+            output_file: output_file,
+            image: Vec::new(),
             is_vve: is_vve,
             cursect: CurrentSection::None,
             data_size: 0,
             func_table: func_table,
             func_indices: func_indices,
             exception_table: get_exc_table(),
+            ncall_names: get_ncall_table(),
+            ncalls_used: Vec::new(),
+            interned: HashMap::new(),
+            intern_dups: HashSet::new(),
+            data_vars: Vec::new(),
+            ro_size: 0,
+            ro_buffer: Vec::new(),
+            rw_buffer: Vec::new(),
+            cur_func: None,
+            optimize: false,
+            opt_report: None,
+            alias_warned: false,
+            func_clobbers: HashMap::new(),
+            line_table: Vec::new(),
+            code_layout: Vec::new(),
+            relocs: Vec::new(),
+            code_words: Vec::new(),
+            byte_order: ByteOrder::Big,
+            stdlib_funcs: HashMap::new(),
+            stack_depths: HashMap::new(),
         }
     }
 
     pub fn assemble(&mut self) {
+        let (depths, diags) = stack_usage(&self.source.join("\n"));
+        for d in diags {
+            eprintln!("{}: WARNING: {}", d.line, d.msg);
+        }
+        self.stack_depths = depths;
         self.first_stage();
         self.cur_addr = 0;
-        self.read_buffer.seek(std::io::SeekFrom::Start(0));
-
-        let lines: Vec<_> = self.read_buffer.by_ref().lines().collect();
-        for (line_num, line) in lines.into_iter().enumerate() {
-            let line = line.unwrap();
+        self.cursect = CurrentSection::None; // sources without `section text` start in code
+        let mut layout_pos: usize = 0;
+        let lines: Vec<(usize, String)> = self.source_lines();
+        for (line_num, line) in lines {
             let lexems: Vec<&str> = line.trim().split_whitespace().collect();
             if lexems.is_empty() {
                 continue;
@@ -123,201 +202,135 @@ impl VoxAssembly {
             } else if lexems[0] == "section" && lexems[1] == "data" {
                 self.cursect = CurrentSection::Data;
                 continue;
+            } else if lexems[0] == "section" && lexems[1] == "rodata" {
+                self.cursect = CurrentSection::ROData;
+                continue;
             }
             //println!("DBG Lexems: {}", lexems.join(", "));
             if (lexems[0] == "label")
                 || (lexems[0] == ".start")
                 || (lexems[0].contains("#") || (lexems[0] == ";") || (lexems[0] == "func"))
+                || (lexems[0] == "clobbers")
+                || (lexems[0] == "ncalldef")
             {
                 continue;
             }
 
-            if self.cursect == CurrentSection::Data {
-                let mut type_lexem_n: usize = 1;
-                let mut is_const: bool = false;
-                const const_mask: u8 = 0x10;
-
-                if let Some(&"const") = lexems.get(1) {
-                    type_lexem_n = 2;
-                    is_const = true;
+            if self.in_data() {
+                if self.intern_dups.contains(&line_num) || lexems[0].starts_with("!align=") || (lexems[0] == ".org") {
+                    // shares storage with an identical const str / padding is laid out already
+                    continue;
                 }
-                let var_type_ind: u8 = match detect_ds_var_type(lexems[type_lexem_n]) {
-                    Some(val) => val,
-                    None => panic!(
-                        "ERROR: Unknown data segment variable type {} at line {}",
-                        lexems[type_lexem_n], line_num
-                    ),
+                let var: &DataVar = match self.data_vars.iter().find(|v| v.line == line_num) {
+                    Some(v) => v,
+                    None => panic!("{}: Data variable was not laid out", line_num),
                 };
-                let type_flags: u8 = match is_const {
-                    true => var_type_ind | const_mask,
-                    false => var_type_ind,
+                let bytes: Vec<u8> = encode_data_var(&line, &lexems, line_num, var.ro);
+                if bytes.len() as u64 != var.size {
+                    panic!(
+                        "{}: Size drift: first stage laid out the variable as {} bytes, second pass encoded {}",
+                        line_num,
+                        var.size,
+                        bytes.len()
+                    );
+                }
+                let buf: &mut Vec<u8> = match var.ro {
+                    true => &mut self.ro_buffer,
+                    false => &mut self.rw_buffer,
                 };
-                self.bin_buffer.push(type_flags);
-                match var_type_ind {
-                    0x1 => {
-                        let arg: &str = lexems[(type_lexem_n + 1) as usize];
-                        let res: u64;
-                        let mut num_sys: u32 = 10;
-                        let var_size: u64 = 8;
-                        if arg.to_lowercase().contains("0x") {
-                            num_sys = 16;
-                        }
-                        res = u64::from_str_radix(arg, num_sys).unwrap();
-                        self.bin_buffer.extend_from_slice(&var_size.to_be_bytes());
-                        self.bin_buffer.extend_from_slice(&res.to_be_bytes());
-                    }
-                    0x2 => {
-                        let arg: &str = lexems[(type_lexem_n + 1) as usize];
-                        let res: i64;
-                        let mut num_sys: u32 = 10;
-                        let var_size: u64 = 8;
-                        if arg.to_lowercase().contains("0x") {
-                            num_sys = 16;
-                        }
-                        res = i64::from_str_radix(arg, num_sys).unwrap();
-                        self.bin_buffer.extend_from_slice(&var_size.to_be_bytes());
-                        self.bin_buffer.extend_from_slice(&res.to_be_bytes());
-                    }
-                    0x3 => {
-                        let arg: &str = lexems[(type_lexem_n + 1) as usize];
-                        let res: f64 = arg.parse().unwrap();
-                        let var_size: u64 = 8;
-                        self.bin_buffer.extend_from_slice(&var_size.to_be_bytes());
-                        self.bin_buffer.extend_from_slice(&res.to_be_bytes());
-                    }
-                    0x4 => {
-                        let mut len_ctr: u64 = 0;
-                        let mut tmp_utf16_buf: Vec<u8> = Vec::new();
-                        let start = line.find('"').expect(&format!(
-                            "error parsing line {}: can't find opening quotemark for str",
-                            line_num
-                        ));
-                        let rel_end = line[start + 1..].rfind('"').expect(&format!(
-                            "error parsing line {}: can't find closing quotemark for str",
-                            line_num
-                        ));
-                        let end = start + 1 + rel_end;
-                        let text = &line[start + 1..end];
-                        len_ctr = (text.encode_utf16().count() * 2) as u64; // utf16 bytes
-                        for c in text.chars() {
-                            let mut buf = [0u16; 2];
-                            let utf16 = c.encode_utf16(&mut buf);
-                            let utf16_bytes = utf16[0].to_be_bytes();
-                            tmp_utf16_buf.extend_from_slice(&utf16_bytes);
-                        }
-                        self.bin_buffer.extend_from_slice(&len_ctr.to_be_bytes());
-                        self.bin_buffer.extend_from_slice(&tmp_utf16_buf);
-                    }
-                    0x6 => {
-                        if let Some(s) = lexems.get((var_type_ind + 1) as usize) {
-                            if s.starts_with("!zeros=") {
-                                let count: u64 = u64_from_str_auto(&s[7..].to_string());
-                                self.bin_buffer
-                                    .extend_from_slice(&(count * 8).to_be_bytes());
-                                let zero_64: u64 = 0;
-                                for _ in 0..count {
-                                    self.bin_buffer.extend_from_slice(&zero_64.to_be_bytes());
-                                }
-                                continue;
-                            }
-                        }
-                        let res_vec: Vec<u64> = match parse_array_string::<u64>(&line) {
-                            Ok(res) => res,
-                            Err(err) => {
-                                panic!(
-                                    "ERROR: While parsing array at line {}: {}",
-                                    line_num + 1,
-                                    err
-                                )
-                            }
-                        };
-                        let len_ctr: u64 = (res_vec.len() * 8) as u64; //64-bit
-                        self.bin_buffer.extend_from_slice(&len_ctr.to_be_bytes());
-                        for num in res_vec {
-                            self.bin_buffer.extend_from_slice(&num.to_be_bytes());
-                        }
-                    }
-                    0x7 => {
-                        if let Some(s) = lexems.get(2) {
-                            if s.starts_with("!zeros=") {
-                                let count: u64 = u64_from_str_auto(&s[7..].to_string());
-                                self.bin_buffer
-                                    .extend_from_slice(&(count * 8).to_be_bytes());
-                                let zero_i64: i64 = 0;
-                                for _ in 0..count {
-                                    self.bin_buffer.extend_from_slice(&zero_i64.to_be_bytes());
-                                }
-                                continue;
-                            }
-                        }
-                        let res_vec: Vec<i64> = match parse_array_string::<i64>(&line) {
-                            Ok(res) => res,
-                            Err(err) => {
-                                panic!(
-                                    "ERROR: While parsing array at line {}: {}",
-                                    line_num + 1,
-                                    err
-                                )
-                            }
-                        };
-                        let len_ctr: u64 = (res_vec.len() * 8) as u64; //64-bit
-                        self.bin_buffer.extend_from_slice(&len_ctr.to_be_bytes());
-                        for num in res_vec {
-                            self.bin_buffer.extend_from_slice(&num.to_be_bytes());
-                        }
-                    }
-                    0x8 => {
-                        if let Some(s) = lexems.get(2) {
-                            if s.starts_with("!zeros=") {
-                                let count: u64 = u64_from_str_auto(&s[7..].to_string());
-                                self.bin_buffer
-                                    .extend_from_slice(&(count * 8).to_be_bytes());
-                                let zero_f64: f64 = 0f64;
-                                for i in 0..count {
-                                    self.bin_buffer.extend_from_slice(&zero_f64.to_be_bytes());
-                                }
-                                continue;
-                            }
-                        }
-                        let res_vec: Vec<f64> = match parse_array_string::<f64>(&line) {
-                            Ok(res) => res,
-                            Err(err) => {
-                                panic!(
-                                    "ERROR: While parsing array at line {}: {}",
-                                    line_num + 1,
-                                    err
-                                )
-                            }
-                        };
-                        let len_ctr: u64 = (res_vec.len() * 8) as u64; //64-bit
-                        self.bin_buffer.extend_from_slice(&len_ctr.to_be_bytes());
-                        for num in res_vec {
-                            self.bin_buffer.extend_from_slice(&num.to_be_bytes());
-                        }
-                    }
-                    _ => panic!("CRITICAL at voxasm: unknown constant type."),
+                buf.resize(buf.len() + var.pad as usize, 0);
+                buf.extend_from_slice(&bytes);
+                continue;
+            }
+
+            // every code line from here on is an entry of the first stage layout
+            self.check_layout(layout_pos);
+            layout_pos += 1;
+
+            if lexems[0] == ".org" {
+                let addr: u64 = org_addr(&lexems, line_num);
+                self.bin_buffer.resize(addr as usize, NOP);
+                continue;
+            }
+
+            if lexems[0] == "table" {
+                // table name default entries..
+                // Layout: count (u64), default addr (u64), count * addr (u64)
+                if lexems.len() < 3 {
+                    panic!("{}: Table should have a name and a default target", line_num);
                 }
+                let entries: Vec<&str> = table_entries(&lexems);
+                let default_addr: u64 = self.resolve_label_addr(lexems[2], line_num);
+                self.emit_word(&(entries.len() as u64).to_be_bytes());
+                self.add_reloc(RELOC_CODE);
+                self.emit_word(&default_addr.to_be_bytes());
+                for entry in entries {
+                    let addr: u64 = self.resolve_label_addr(entry, line_num);
+                    self.add_reloc(RELOC_CODE);
+                    self.emit_word(&addr.to_be_bytes());
+                }
+                continue;
+            }
+
+            if lexems[0] == "lea" {
+                // lea Rdest @label -> uload Rdest abs_addr
+                if lexems.len() < 3 {
+                    panic!("{}: lea should be used as lea Rdest @label", line_num);
+                }
+                let reg_ind: u8 = match lexems[1].strip_prefix('r').map(|r| r.parse::<u8>()) {
+                    Some(Ok(v)) => v,
+                    _ => panic!("{}: Invalid register '{}'", line_num, lexems[1]),
+                };
+                let addr: u64 = self.resolve_lea_addr(lexems[2], line_num);
+                self.bin_buffer.push(0x10);
+                self.bin_buffer.push(reg_ind);
+                match self.labels.contains_key(&lexems[2][1..]) {
+                    true => self.add_reloc(RELOC_CODE),
+                    false => self.add_reloc(RELOC_DATA_ABS),
+                }
+                self.emit_word(&addr.to_be_bytes());
+                continue;
+            }
+
+            if let Some(data) = inline_data(&line, &lexems) {
+                // ldstr/ldbytes Rdst ..: 0xFE 0x05/0x06 Rdst, length (u64), the data
+                let data: Vec<u8> = data.unwrap_or_else(|e| panic!("{}: {}", line_num, e));
+                let reg_ind: u8 = match lexems.get(1).and_then(|r| r.strip_prefix('r')).map(|r| r.parse::<u8>()) {
+                    Some(Ok(v)) => v,
+                    _ => panic!("{}: {} should be used as {} Rdst data", line_num, lexems[0], lexems[0]),
+                };
+                let op: u8 = if lexems[0] == "ldstr" { 0x05 } else { 0x06 };
+                self.bin_buffer.extend_from_slice(&[EXT_PREFIX, op, reg_ind]);
+                self.emit_word(&(data.len() as u64).to_be_bytes());
+                self.bin_buffer.extend_from_slice(&data);
                 continue;
             }
 
+            let lexems: Vec<&str> = short_load_form(lexems);
             let instr_data = match self.instr_table.get(lexems[0]) {
-                Some(val) => val,
+                Some(val) => val.clone(), // operands are emitted through &mut self
                 None => {
                     eprintln!("ERR: No such instruction '{}'", lexems[0]);
                     continue;
                 }
             };
-            let opcode = match &instr_data[0] {
-                LexTypes::Op(value) => *value,
-                _ => panic!("ERR: First element should be an Op variant"),
+            // two-byte opcodes are 0xFEnn here
+            let opcode: u16 = match &instr_data[0] {
+                LexTypes::Op(value) => *value as u16,
+                LexTypes::ExtOp(value) => u16::from_be_bytes([EXT_PREFIX, *value]),
+                _ => panic!("ERR: First element should be an Op or ExtOp variant"),
             };
             let instr_len = match &instr_data[1] {
                 LexTypes::Size(value) => *value,
                 _ => panic!("ERR: Second element should be an Size variant"),
             };
+            if opcode > 0xFF {
+                self.bin_buffer.push(EXT_PREFIX);
+            }
             self.bin_buffer.push(opcode as u8);
 
             if (opcode >= 0x70) && (opcode < 0x80) {
+                let mut var_addr_done: bool = false; // the first address is the variable's
                 for (i, dat) in instr_data[2..].iter().enumerate() {
                     let cur_lex = lexems[i + 1];
                     match *dat {
@@ -338,7 +351,11 @@ impl VoxAssembly {
                                 Some(val) => *val,
                                 None => u64_from_str_auto(cur_lex),
                             };
-                            self.bin_buffer.extend_from_slice(&tgt_addr.to_be_bytes());
+                            if !var_addr_done {
+                                self.relocs.push((self.bin_buffer.len() as u64, RELOC_DATA_REL));
+                                var_addr_done = true;
+                            }
+                            self.emit_word(&tgt_addr.to_be_bytes());
                         }
                         _ => panic!(
                             "ERROR: Unexpected argument type for data segment operation {}",
@@ -358,7 +375,7 @@ impl VoxAssembly {
                 let mut func_ind: u64;
                 if lexems[1].contains('@') {
                     let funcname = lexems[1][1..].to_string();
-                    func_ind = match self.func_indices.get(&funcname.clone()) {
+                    func_ind = match self.func_indices.get(&funcname).or(self.stdlib_funcs.get(&funcname)) {
                         Some(n) => *n,
                         None => {
                             panic!("{}: No function named '{}' found", line_num, funcname);
@@ -367,7 +384,8 @@ impl VoxAssembly {
                 } else {
                     func_ind = u64_from_str_auto(lexems[1]);
                 }
-                self.bin_buffer.extend_from_slice(&func_ind.to_be_bytes());
+                self.add_reloc(RELOC_FUNC);
+                self.emit_word(&func_ind.to_be_bytes());
                 continue;
             }
             for (ind, arg) in lexems[1..].iter().enumerate() {
@@ -381,7 +399,7 @@ impl VoxAssembly {
                     let mut func_ind: u64;
                     if arg.contains('@') {
                         let funcname = arg[1..].to_string();
-                        func_ind = match self.func_indices.get(&funcname.clone()) {
+                        func_ind = match self.func_indices.get(&funcname).or(self.stdlib_funcs.get(&funcname)) {
                             Some(n) => *n,
                             None => {
                                 panic!("{}: No function named '{}' found", line_num, funcname);
@@ -390,7 +408,25 @@ impl VoxAssembly {
                     } else {
                         func_ind = u64_from_str_auto(arg);
                     }
-                    self.bin_buffer.extend_from_slice(&func_ind.to_be_bytes());
+                    self.relocs.push((self.bin_buffer.len() as u64, RELOC_FUNC));
+                    self.emit_word(&func_ind.to_be_bytes());
+                    continue;
+                };
+                if let Some(LexTypes::NcallNum(_)) = cur_type {
+                    let code: u16 = match arg.strip_prefix('@') {
+                        Some(name) => match self.ncall_names.get(name) {
+                            Some(n) => *n,
+                            None => {
+                                panic!("{}: No ncall named '{}' found", line_num, name);
+                            }
+                        },
+                        None => match parse_num_literal(arg).map(u16::try_from) {
+                            Some(Ok(v)) => v,
+                            _ => panic!("{}: Invalid ncall code: {}", line_num, arg),
+                        },
+                    };
+                    self.ncalls_used.push(code);
+                    self.emit_word(&code.to_be_bytes());
                     continue;
                 };
                 if let Some(LexTypes::Exception(_)) = cur_type {
@@ -406,9 +442,18 @@ impl VoxAssembly {
                     } else {
                         exc_ind = u64_from_str_auto(arg);
                     }
-                    self.bin_buffer.extend_from_slice(&exc_ind.to_be_bytes());
+                    self.emit_word(&exc_ind.to_be_bytes());
                     continue;
                 };
+                if let Some(LexTypes::Mem) = cur_type {
+                    let (reg_ind, offset) = match parse_mem_operand(arg) {
+                        Some(v) => v,
+                        None => panic!("{}: '{}' should be [rN+offset]", line_num, arg),
+                    };
+                    self.bin_buffer.push(reg_ind);
+                    self.emit_word(&offset.to_be_bytes());
+                    continue;
+                }
                 if let Some(LexTypes::Addr(_)) = cur_type {
                     let mut tgt_addr: u64;
                     if arg.contains('@') {
@@ -423,7 +468,8 @@ impl VoxAssembly {
                         tgt_addr = u64_from_str_auto(arg);
                     }
 
-                    self.bin_buffer.extend_from_slice(&tgt_addr.to_be_bytes());
+                    self.relocs.push((self.bin_buffer.len() as u64, RELOC_CODE));
+                    self.emit_word(&tgt_addr.to_be_bytes());
                     continue;
                 }
 
@@ -435,46 +481,203 @@ impl VoxAssembly {
                 if arg.contains(".") {
                     let val: f64 = arg.parse().unwrap();
                     let res = val.to_be_bytes();
-                    self.bin_buffer.extend_from_slice(&res);
+                    self.emit_word(&res);
                     continue;
                 }
 
-                let mut is_signed: bool = false;
-                if (opcode >= 0x20) && (opcode <= 0x30) {
-                    is_signed = true;
-                }
-
-                let res: [u8; 8];
-                let signed_res: i64;
-                let unsigned_res: u64;
-                let mut num_sys: u32 = 10;
                 let mut bytes_limit: usize = 8;
-                let mut arg_cleansed = arg.to_lowercase().clone();
-
                 if opcode == 0x1 {
                     bytes_limit = 2;
+                } else if (opcode == 0x1b) || (opcode == 0x2d) {
+                    bytes_limit = 4; // uload32, iload32
                 }
-                if arg.to_lowercase().starts_with("0x") {
-                    num_sys = 16;
-                    arg_cleansed = arg_cleansed.strip_prefix("0x").unwrap().to_string();
+                let bits: u32 = (bytes_limit * 8) as u32;
+                let res: [u8; 8] = match opcode {
+                    // fload r1 5 is 5.0, not the bit pattern 5
+                    0x30 => parse_int_literal(arg, 64).map(|v| (v as f64).to_be_bytes()),
+                    0x20..=0x2f => parse_int_literal(arg, bits).map(|v| v.to_be_bytes()),
+                    _ => parse_uint_literal(arg, bits).map(|v| v.to_be_bytes()),
                 }
-
-                if is_signed {
-                    signed_res = i64::from_str_radix(&arg_cleansed, num_sys).unwrap();
-                    res = signed_res.to_be_bytes();
-                } else {
-                    unsigned_res = u64::from_str_radix(&arg_cleansed, num_sys).unwrap();
-                    res = unsigned_res.to_be_bytes();
-                }
-                self.bin_buffer
-                    .extend_from_slice(&res[res.len() - bytes_limit..]);
+                .unwrap_or_else(|e| panic!("{}: {}", line_num, e));
+                self.emit_word(&res[res.len() - bytes_limit..]);
             }
         }
+        self.check_layout(layout_pos);
+        // data goes after all of the code: .rodata, then .data
+        self.bin_buffer.append(&mut self.ro_buffer);
+        self.bin_buffer.append(&mut self.rw_buffer);
         if self.is_vve {
             self.do_vve();
         } else {
             self.do_vvr();
         }
+        if let Some(report) = &self.opt_report {
+            println!("{}", report);
+        }
+    }
+
+    /// Reads the whole input from the start with the peephole rewrites applied,
+    /// line count is kept so line numbers stay the same for both stages
+    /// Source lines with macros expanded, paired with their line index
+    fn source_lines(&mut self) -> Vec<(usize, String)> {
+        let mut raw: Vec<String> = self.source.clone();
+        for d in resolve_aliases(&mut raw) {
+            match d.error {
+                true => panic!("{}: {}", d.line, d.msg),
+                false if !self.alias_warned => eprintln!("{}: WARNING: {}", d.line, d.msg),
+                false => {}
+            }
+        }
+        self.alias_warned = true;
+        let (line_nums, mut lines): (Vec<usize>, Vec<String>) = match expand_macros(raw).and_then(resolve_vregs) {
+            Ok(v) => v.into_iter().unzip(),
+            Err((line_num, e)) => panic!("{}: {}", line_num, e),
+        };
+        peephole_swaps(&mut lines);
+        if self.optimize {
+            self.opt_report = Some(match optimize(&mut lines) {
+                Ok(stats) => stats.report(),
+                Err(e) => format!("opt: skipped, {}", e),
+            });
+        }
+        line_nums.into_iter().zip(lines).collect()
+    }
+
+    /// Bytes of the assembled .vve/.vvr file
+    pub fn image(&self) -> &[u8] {
+        &self.image
+    }
+
+    /// Symbol map of the assembled program (`--vas-map`): code and data
+    /// sizes, then every function, label and data variable with its address
+    /// and size, by address. A code symbol spans up to the next one, a data
+    /// variable counts its type and length bytes; interned const strs share
+    /// the storage of the first one
+    pub fn symbol_map(&self) -> String {
+        let mut res: String = String::new();
+        res.push_str(&format!("code 0x0 size {:#x} ({} bytes)\n", self.data_start, self.data_start));
+        res.push_str(&format!(
+            "data {:#x} size {:#x} ({} bytes, {} read-only)\n",
+            self.data_start, self.data_size, self.data_size, self.ro_size
+        ));
+        res.push_str(&format!("entry {:#x}\n\n", self.entry));
+
+        // (addr, kind, name), functions before labels at the same address
+        let mut code: Vec<(u64, &str, String)> = self
+            .func_table
+            .iter()
+            .map(|(name, addr)| (*addr, "func", format!("{} (#{})", name, self.func_indices[name])))
+            .chain(self.labels.iter().map(|(name, addr)| (*addr, "label", name.clone())))
+            .collect();
+        code.sort();
+        let mut starts: Vec<u64> = code.iter().map(|(addr, _, _)| *addr).collect();
+        starts.dedup();
+        res.push_str(&format!("{:<18} {:<10} {:<5} {}\n", "address", "size", "kind", "name"));
+        for (addr, kind, name) in &code {
+            let end: u64 = starts.iter().copied().find(|s| s > addr).unwrap_or(self.data_start);
+            res.push_str(&format!("{:#018x} {:<10} {:<5} {}\n", addr, end - addr, kind, name));
+        }
+        let mut data: Vec<(u64, u64, &str, &String)> = self
+            .data_labels
+            .iter()
+            .filter_map(|(name, rel)| {
+                let var: &DataVar = self.data_vars.iter().find(|v| v.addr == *rel)?;
+                Some((self.data_start + rel, var.size, if var.ro { "ro" } else { "rw" }, name))
+            })
+            .collect();
+        data.sort();
+        for (addr, size, kind, name) in data {
+            res.push_str(&format!("{:#018x} {:<10} {:<5} {}\n", addr, size, kind, name));
+        }
+        res
+    }
+
+    /// Turns on the `--opt` passes, see asmopt.rs
+    pub fn enable_opt(&mut self) {
+        self.optimize = true;
+    }
+
+    /// Byte order of the output vve, big-endian by default
+    pub fn set_byte_order(&mut self, order: ByteOrder) {
+        self.byte_order = order;
+    }
+
+    /// Emits a big-endian operand, remembering where it is for little-endian output
+    fn emit_word(&mut self, bytes: &[u8]) {
+        if bytes.len() > 1 {
+            self.code_words.push((self.bin_buffer.len() as u64, bytes.len() as u8));
+        }
+        self.bin_buffer.extend_from_slice(bytes);
+    }
+
+    /// Records that the u64 about to be emitted needs fixing up when the image moves
+    fn add_reloc(&mut self, kind: u8) {
+        self.relocs.push((self.bin_buffer.len() as u64, kind));
+    }
+
+    /// Functions of a `--stdlib` image, callable as `call @name` under their
+    /// reserved indices. Functions of the program itself win on a name clash.
+    pub fn add_stdlib_funcs(&mut self, funcs: HashMap<String, u64>) {
+        self.stdlib_funcs.extend(funcs);
+    }
+
+    /// Names from native library configs, `ncalldef` in the source overrides them
+    pub fn add_ncall_names(&mut self, names: HashMap<String, u16>) {
+        self.ncall_names.extend(names);
+    }
+
+    fn resolve_label_addr(&self, arg: &str, line_num: usize) -> u64 {
+        if let Some(label_name) = arg.strip_prefix('@') {
+            return match self.labels.get(label_name) {
+                Some(n) => *n,
+                None => {
+                    panic!("{}: No label named '{}' found", line_num, label_name);
+                }
+            };
+        }
+        u64_from_str_auto(arg)
+    }
+
+    fn resolve_lea_addr(&self, arg: &str, line_num: usize) -> u64 {
+        // code labels first, then data labels (address of the variable's type byte)
+        let label_name: &str = match arg.strip_prefix('@') {
+            Some(v) => v,
+            None => panic!("{}: lea target should be a @label", line_num),
+        };
+        if let Some(addr) = self.labels.get(label_name) {
+            return *addr;
+        }
+        match self.data_labels.get(label_name) {
+            Some(rel_addr) => self.data_start + rel_addr,
+            None => panic!("{}: No label named '{}' found", line_num, label_name),
+        }
+    }
+
+    /// Second pass: the code emitted before layout entry `pos` must match the
+    /// first stage sizes, otherwise labels point into the wrong bytes
+    fn check_layout(&self, pos: usize) {
+        let emitted: u64 = self.bin_buffer.len() as u64;
+        let expected: u64 = match self.code_layout.get(pos) {
+            Some((_, addr, _)) => *addr,
+            None if pos == self.code_layout.len() => self.data_start,
+            None => panic!(
+                "Layout drift: second pass emits more code lines than the first stage laid out ({})",
+                self.code_layout.len()
+            ),
+        };
+        if emitted == expected {
+            return;
+        }
+        match pos.checked_sub(1).map(|p| self.code_layout[p]) {
+            Some((line, addr, size)) => panic!(
+                "{}: Size drift: first stage laid out {} bytes at {:#x}, second pass emitted {}",
+                line,
+                size,
+                addr,
+                emitted - addr
+            ),
+            None => panic!("Layout drift: code starts at {:#x} instead of 0", emitted),
+        }
     }
 
     fn save_label(&mut self, labelname: String) {
@@ -483,10 +686,8 @@ impl VoxAssembly {
         return;
     }
 
-    fn save_data_label(&mut self, labelname: String) {
-        let rel_addr: u64 = self.data_size;
-        self.data_labels.insert(labelname, rel_addr);
-        return;
+    fn in_data(&self) -> bool {
+        self.cursect == CurrentSection::Data || self.cursect == CurrentSection::ROData
     }
 
     fn save_function(&mut self, funcname: String, abs_addr: u64) {
@@ -496,9 +697,12 @@ impl VoxAssembly {
     }
 
     fn first_stage(&mut self) {
-        let lines: Vec<_> = self.read_buffer.by_ref().lines().collect();
-        for (line_num, line) in lines.into_iter().enumerate() {
-            let line = line.unwrap();
+        let mut pending_align: u64 = 1;
+        let mut pending_org: Option<u64> = None;
+        let mut var_lines: Vec<(String, usize)> = Vec::new(); // data label -> var line
+        let mut intern_lines: HashMap<String, usize> = HashMap::new(); // const str text -> var line
+        let lines: Vec<(usize, String)> = self.source_lines();
+        for (line_num, line) in lines {
             let lexems: Vec<&str> = line.trim().split_whitespace().collect();
             if lexems.is_empty() {
                 continue;
@@ -511,67 +715,156 @@ impl VoxAssembly {
                         panic!("{}: Function has no name", line_num);
                     }
                 };
+                self.cur_func = Some(funcname.clone());
                 self.save_function(funcname, self.cur_addr);
                 continue;
             }
 
+            if lexems[0] == "ncalldef" {
+                // ncalldef name code - names a native call for `ncall @name`
+                if lexems.len() < 3 {
+                    panic!("{}: ncalldef should be used as ncalldef name code", line_num);
+                }
+                let code: u16 = match parse_num_literal(lexems[2]).map(u16::try_from) {
+                    Some(Ok(v)) => v,
+                    _ => panic!("{}: Invalid ncall code: {}", line_num, lexems[2]),
+                };
+                self.ncall_names.insert(lexems[1].to_string(), code);
+                continue;
+            }
+
+            if lexems[0] == "clobbers" {
+                // clobbers r1-r5 r9 - scratch regs of the current function,
+                // all the others are callee-saved
+                let funcname: String = match &self.cur_func {
+                    Some(v) => v.clone(),
+                    None => panic!("{}: clobbers should be used inside a function", line_num),
+                };
+                let mask: u32 = match parse_reg_mask(&lexems[1..]) {
+                    Ok(v) => v,
+                    Err(e) => panic!("{}: {}", line_num, e),
+                };
+                *self.func_clobbers.entry(funcname).or_insert(0) |= mask;
+                continue;
+            }
+
             if lexems[0] == "label" {
                 self.save_label(lexems[1].to_string());
                 continue;
+            } else if lexems[0] == "table" {
+                let name: String = match lexems.get(1) {
+                    Some(v) => v.to_string(),
+                    None => {
+                        panic!("{}: Table has no name", line_num);
+                    }
+                };
+                self.save_label(name);
+                let size: u64 = 8 + 8 + (table_entries(&lexems).len() as u64) * 8;
+                self.code_layout.push((line_num, self.cur_addr, size));
+                self.cur_addr += size;
+                continue;
             } else if lexems[0] == ".start" {
                 self.entry = self.cur_addr;
                 continue;
             } else if lexems[0].contains("#") || lexems[0] == ";" {
                 continue;
+            } else if lexems[0] == ".org" {
+                // .org ADDR - the next instruction / data variable goes at ADDR
+                let addr: u64 = org_addr(&lexems, line_num);
+                if self.in_data() {
+                    pending_org = Some(addr);
+                    continue;
+                }
+                if addr < self.cur_addr {
+                    panic!("{}: .org {:#x} is behind the current address {:#x}", line_num, addr, self.cur_addr);
+                }
+                self.code_layout.push((line_num, self.cur_addr, addr - self.cur_addr));
+                self.cur_addr = addr;
             } else if lexems[0] == "section" && lexems[1] == "data" {
-                //println!("DBG CURADDR: {}", self.cur_addr);
-                self.data_start = self.cur_addr;
                 self.cursect = CurrentSection::Data;
+            } else if lexems[0] == "section" && lexems[1] == "rodata" {
+                self.cursect = CurrentSection::ROData;
             } else if lexems[0] == "section" && lexems[1] == "text" {
                 self.cursect = CurrentSection::Code;
-            } else if self.cursect == CurrentSection::Data {
+            } else if self.in_data() && lexems[0].starts_with("!align=") {
+                // the data of the next variable gets aligned
+                pending_align = match parse_num_literal(&lexems[0][7..]) {
+                    Some(v) if v.is_power_of_two() => v,
+                    _ => panic!("{}: Alignment should be a power of two: {}", line_num, lexems[0]),
+                };
+            } else if self.in_data() {
                 let mut type_lexems_n: usize = 1;
                 if let Some(&"const") = lexems.get(1) {
                     type_lexems_n = 2;
                 };
+                let ro: bool = (type_lexems_n == 2) || (self.cursect == CurrentSection::ROData);
 
-                let var_type: u8 = match detect_ds_var_type(lexems[type_lexems_n]) {
+                let var_type: DsType = match detect_ds_var_type(lexems[type_lexems_n]) {
                     Some(val) => val,
                     None => panic!("{}: Unknown var type: {}", line_num, lexems[type_lexems_n]),
                 };
-                self.save_data_label(lexems[0].to_string());
+                if (var_type == DsType::Str) && ro && pending_org.is_none() {
+                    // const strings can't change, so identical ones are interned
+                    let text: String = get_text(&line).unwrap().to_string();
+                    if let Some(orig_line) = intern_lines.get(&text) {
+                        var_lines.push((lexems[0].to_string(), *orig_line));
+                        self.intern_dups.insert(line_num);
+                        continue;
+                    }
+                    intern_lines.insert(text, line_num);
+                }
+                var_lines.push((lexems[0].to_string(), line_num));
                 let var_size: u64 = match var_type {
-                    0x1 => 8 + 8, // length + uint (length is const but
+                    DsType::Uint => 8 + 8, // length + uint (length is const but
                     // saved for consistency
-                    0x2 => 8 + 8, // int
-                    0x3 => 8 + 8, // float
-                    0x4 => {
+                    DsType::Int => 8 + 8,
+                    DsType::Float => 8 + 8,
+                    DsType::Str => {
                         // str
                         let size_contained: u64 = get_text_length(&line).unwrap() as u64; //utf16
                         8 + size_contained
                     }
-                    0x5 => {
-                        // ptr
-                        8 + 8
+                    DsType::Ptr => 8 + 8,
+                    DsType::UintArr | DsType::IntArr | DsType::FloatArr => {
+                        8 + array_elems_count(&line, &lexems[(type_lexems_n + 1)..]) * 8
                     }
-                    0x6 | 0x7 | 0x8 => {
-                        // uint, int, float arrays
-                        let size_contained: u64 = get_array_length_str(&line).unwrap() as u64;
-                        //println!("array size contained: {}", size_contained);
-                        8 + size_contained
+                    DsType::U8 | DsType::I8 | DsType::U16 | DsType::I16 | DsType::U32 | DsType::I32 => {
+                        8 + var_type.elem_width() as u64
+                    }
+                    DsType::U8Arr
+                    | DsType::I8Arr
+                    | DsType::U16Arr
+                    | DsType::I16Arr
+                    | DsType::Uint32Arr
+                    | DsType::Int32Arr
+                    | DsType::Float32Arr => {
+                        8 + array_elems_count(&line, &lexems[(type_lexems_n + 1)..]) * var_type.elem_width() as u64
                     }
-                    _ => panic!("{}: Unknown var size of: {}", line_num, var_type),
+                    DsType::Bytes => match parse_bytes(&lexems[(type_lexems_n + 1)..]) {
+                        Ok(v) => 8 + v.len() as u64,
+                        Err(err) => panic!("{}: {}", line_num, err),
+                    },
                 };
-                self.cur_addr += 1 + var_size;
-                self.data_size += 1 + var_size;
+                self.data_vars.push(DataVar {
+                    line: line_num,
+                    size: 1 + var_size,
+                    align: pending_align,
+                    ro: ro,
+                    pad: 0,
+                    org: pending_org.take(),
+                    addr: 0,
+                });
+                pending_align = 1;
             } else {
+                let lexems: Vec<&str> = short_load_form(lexems);
                 let instr_data = match self.instr_table.get(lexems[0]) {
                     Some(v) => v,
                     None => {
                         panic!("{}: Unknown operation: '{}'", line_num, lexems[0]);
                     }
                 };
-                let instr_size = match instr_data[1] {
+                self.line_table.push((self.cur_addr, (line_num + 1) as u32));
+                let mut instr_size = match instr_data[1] {
                     LexTypes::Size(val) => val,
                     _ => {
                         eprintln!(
@@ -581,40 +874,199 @@ impl VoxAssembly {
                         0
                     }
                 };
+                match inline_data(&line, &lexems) {
+                    Some(Ok(data)) => instr_size += data.len() as u64,
+                    Some(Err(e)) => panic!("{}: {}", line_num, e),
+                    None => {}
+                }
 
+                self.code_layout.push((line_num, self.cur_addr, instr_size));
                 self.cur_addr += instr_size;
             }
         }
+        if pending_org.is_some() {
+            panic!(".org at the end of data places nothing");
+        }
+        // data is placed after all of the code
+        self.data_start = self.cur_addr;
+        let mut var_addrs: HashMap<usize, u64> = HashMap::new();
+        let mut rel_addr: u64 = 0;
+        for ro in [true, false] {
+            for var in self.data_vars.iter_mut().filter(|v| v.ro == ro) {
+                let payload_addr: u64 = self.data_start + rel_addr + 1 + 8; // type, length
+                var.pad = match var.org {
+                    Some(org) => {
+                        let at: u64 = self.data_start + rel_addr;
+                        if org < at {
+                            panic!("{}: .org {:#x} is behind the current address {:#x}", var.line, org, at);
+                        }
+                        if (org + 1 + 8) % var.align != 0 {
+                            panic!("{}: .org {:#x} breaks !align={} of the variable", var.line, org, var.align);
+                        }
+                        org - at
+                    }
+                    None => (var.align - payload_addr % var.align) % var.align,
+                };
+                rel_addr += var.pad;
+                var.addr = rel_addr;
+                var_addrs.insert(var.line, rel_addr);
+                rel_addr += var.size;
+            }
+            if ro {
+                self.ro_size = rel_addr;
+            }
+        }
+        self.data_size = rel_addr;
+        for (label, line) in var_lines {
+            self.data_labels.insert(label, var_addrs[&line]);
+        }
+        for (text, line) in intern_lines {
+            self.interned.insert(text, var_addrs[&line]);
+        }
     }
 
     fn do_vvr(&mut self) {
-        match self.output_file.write_all(&self.bin_buffer) {
+        self.image = self.bin_buffer.clone();
+        let Some(file) = &mut self.output_file else {
+            return;
+        };
+        match file.write_all(&self.bin_buffer) {
             Ok(_) => return,
             Err(err) => panic!("ERR: While writing bytecode into output .vvr file: {}", err),
         }
     }
 
     fn do_vve(&mut self) {
-        const VVE_VERSION: u16 = 3;
-        let header: VoxExeHeader = VoxExeHeader::new(
+        const VVE_VERSION: u16 = 4;
+        let mut header: VoxExeHeader = VoxExeHeader::new(
             VVE_VERSION,
             self.entry,
             self.data_start,
-            0, // this fields currently unudsed
-            0,
+            self.data_start, // code is everything before data
+            self.data_size,
             self.make_fn_table(),
         );
-        VoxExeHeader::write_existing(&mut self.output_file, &header);
+        header.sections.push(self.make_intern_section());
+        header.sections.push(self.make_func_meta_section());
+        header.sections.push(self.make_stack_depth_section());
+        header.sections.push(self.make_symbols_section());
+        header.sections.push(self.make_lines_section());
+        header.sections.push(VveSection::new(SECT_RODATA, self.ro_size.to_be_bytes().to_vec()));
+        header.sections.push(self.make_relocs_section());
+        header.sections.push(ncalls_section(&self.ncalls_used));
+        let data_names: Vec<(u64, String)> = self.data_labels.iter().map(|(name, addr)| (*addr, name.clone())).collect();
+        header.sections.push(data_symbols_section(&data_names));
+        if self.byte_order == ByteOrder::Little {
+            header.byte_order = ByteOrder::Little;
+            header.sections.push(self.make_words_section());
+            header.swap_code_words(&mut self.bin_buffer);
+        }
+        self.image = header.header_bytes();
+        self.image.extend_from_slice(&self.bin_buffer);
+        let Some(file) = &mut self.output_file else {
+            return;
+        };
+        VoxExeHeader::write_existing(file, &header);
         // println!(
         //     "File seek at asm: {:#x}",
-        //     self.output_file.stream_position().unwrap()
+        //     file.stream_position().unwrap()
         // );
-        match self.output_file.write_all(&self.bin_buffer) {
+        match file.write_all(&self.bin_buffer) {
             Ok(_) => return,
             Err(err) => panic!("ERR: While writing bytecode into output .vve file: {}", err),
         }
     }
 
+    fn make_intern_section(&self) -> VveSection {
+        let mut rel_addrs: Vec<u64> = self.interned.values().cloned().collect();
+        rel_addrs.sort();
+        let mut data: Vec<u8> = Vec::new();
+        data.extend_from_slice(&(rel_addrs.len() as u64).to_be_bytes());
+        for addr in rel_addrs {
+            data.extend_from_slice(&addr.to_be_bytes());
+        }
+        VveSection::new(SECT_INTERN, data)
+    }
+
+    fn make_func_meta_section(&self) -> VveSection {
+        let mut entries: Vec<(u64, u32)> = self
+            .func_clobbers
+            .iter()
+            .map(|(name, mask)| (self.func_indices[name], *mask))
+            .collect();
+        entries.sort();
+        let mut data: Vec<u8> = Vec::new();
+        data.extend_from_slice(&(entries.len() as u64).to_be_bytes());
+        for (ind, mask) in entries {
+            data.extend_from_slice(&ind.to_be_bytes());
+            data.extend_from_slice(&(mask as u64).to_be_bytes());
+        }
+        VveSection::new(SECT_FUNC_META, data)
+    }
+
+    fn make_stack_depth_section(&self) -> VveSection {
+        let mut entries: Vec<(u64, u64)> = self
+            .stack_depths
+            .iter()
+            .filter_map(|(name, depth)| self.func_indices.get(name).map(|ind| (*ind, *depth)))
+            .collect();
+        entries.sort();
+        let mut data: Vec<u8> = Vec::new();
+        data.extend_from_slice(&(entries.len() as u64).to_be_bytes());
+        for (ind, depth) in entries {
+            data.extend_from_slice(&ind.to_be_bytes());
+            data.extend_from_slice(&depth.to_be_bytes());
+        }
+        VveSection::new(SECT_STACK_DEPTH, data)
+    }
+
+    fn make_symbols_section(&self) -> VveSection {
+        let mut entries: Vec<(u64, &String)> = self
+            .func_indices
+            .iter()
+            .map(|(name, ind)| (*ind, name))
+            .collect();
+        entries.sort();
+        let mut data: Vec<u8> = Vec::new();
+        data.extend_from_slice(&(entries.len() as u64).to_be_bytes());
+        for (ind, name) in entries {
+            data.extend_from_slice(&ind.to_be_bytes());
+            data.extend_from_slice(&(name.len() as u16).to_be_bytes());
+            data.extend_from_slice(name.as_bytes());
+        }
+        VveSection::new(SECT_SYMBOLS, data)
+    }
+
+    fn make_lines_section(&self) -> VveSection {
+        let mut data: Vec<u8> = Vec::new();
+        data.extend_from_slice(&(self.line_table.len() as u64).to_be_bytes());
+        for (addr, line) in &self.line_table {
+            data.extend_from_slice(&addr.to_be_bytes());
+            data.extend_from_slice(&line.to_be_bytes());
+        }
+        VveSection::new(SECT_LINES, data)
+    }
+
+    fn make_relocs_section(&self) -> VveSection {
+        let mut data: Vec<u8> = Vec::new();
+        data.extend_from_slice(&(self.relocs.len() as u64).to_be_bytes());
+        for (offset, kind) in &self.relocs {
+            data.extend_from_slice(&offset.to_be_bytes());
+            data.push(*kind);
+        }
+        VveSection::new(SECT_RELOCS, data)
+    }
+
+    fn make_words_section(&self) -> VveSection {
+        let mut data: Vec<u8> = Vec::new();
+        data.extend_from_slice(&(self.code_words.len() as u64).to_be_bytes());
+        for (offset, width) in &self.code_words {
+            data.extend_from_slice(&offset.to_be_bytes());
+            data.push(*width);
+        }
+        VveSection::new(SECT_WORDS, data)
+    }
+
     fn make_fn_table(&mut self) -> Vec<u64> {
         let mut res: Vec<u64> = vec![0; self.func_indices.len()];
         for (name, ind) in self.func_indices.iter() {
@@ -632,14 +1084,211 @@ impl VoxAssembly {
     }
 }
 
-fn voxasm_instr_table() -> HashMap<String, Vec<LexTypes>> {
+/// `.org ADDR` operand
+fn org_addr(lexems: &[&str], line_num: usize) -> u64 {
+    match lexems.get(1).and_then(|a| parse_num_literal(a)) {
+        Some(v) => v,
+        None => panic!("{}: .org should be used as .org ADDR", line_num),
+    }
+}
+
+/// Encodes a data segment variable line: type byte, length and payload
+pub(crate) fn encode_data_var(line: &str, lexems: &[&str], line_num: usize, ro: bool) -> Vec<u8> {
+    let mut buf: Vec<u8> = Vec::new();
+    let mut type_lexem_n: usize = 1;
+    let mut is_const: bool = ro;
+
+    if let Some(&"const") = lexems.get(1) {
+        type_lexem_n = 2;
+        is_const = true;
+    }
+    let var_type: DsType = match detect_ds_var_type(lexems[type_lexem_n]) {
+        Some(val) => val,
+        None => panic!(
+            "ERROR: Unknown data segment variable type {} at line {}",
+            lexems[type_lexem_n], line_num
+        ),
+    };
+    buf.push(var_type.encode(is_const));
+    match var_type {
+        DsType::Uint => {
+            let arg: &str = lexems[(type_lexem_n + 1) as usize];
+            let var_size: u64 = 8;
+            let res: u64 = parse_uint_literal(arg, 64).unwrap_or_else(|e| panic!("{}: {}", line_num, e));
+            buf.extend_from_slice(&var_size.to_be_bytes());
+            buf.extend_from_slice(&res.to_be_bytes());
+        }
+        DsType::Int => {
+            let arg: &str = lexems[(type_lexem_n + 1) as usize];
+            let var_size: u64 = 8;
+            let res: i64 = parse_int_literal(arg, 64).unwrap_or_else(|e| panic!("{}: {}", line_num, e));
+            buf.extend_from_slice(&var_size.to_be_bytes());
+            buf.extend_from_slice(&res.to_be_bytes());
+        }
+        DsType::Float => {
+            let arg: &str = lexems[(type_lexem_n + 1) as usize];
+            let res: f64 = arg.parse().unwrap();
+            let var_size: u64 = 8;
+            buf.extend_from_slice(&var_size.to_be_bytes());
+            buf.extend_from_slice(&res.to_be_bytes());
+        }
+        DsType::Str => {
+            let mut tmp_utf16_buf: Vec<u8> = Vec::new();
+            let start = line.find('"').expect(&format!(
+                "error parsing line {}: can't find opening quotemark for str",
+                line_num
+            ));
+            let rel_end = line[start + 1..].rfind('"').expect(&format!(
+                "error parsing line {}: can't find closing quotemark for str",
+                line_num
+            ));
+            let end = start + 1 + rel_end;
+            let text = &line[start + 1..end];
+            let len_ctr: u64 = (text.encode_utf16().count() * 2) as u64; // utf16 bytes
+            for unit in text.encode_utf16() {
+                tmp_utf16_buf.extend_from_slice(&unit.to_be_bytes());
+            }
+            buf.extend_from_slice(&len_ctr.to_be_bytes());
+            buf.extend_from_slice(&tmp_utf16_buf);
+        }
+        DsType::UintArr => {
+            if let Some(s) = lexems.get(type_lexem_n + 1) {
+                if s.starts_with("!zeros=") {
+                    let count: u64 = u64_from_str_auto(&s[7..].to_string());
+                    buf
+                        .extend_from_slice(&(count * 8).to_be_bytes());
+                    let zero_64: u64 = 0;
+                    for _ in 0..count {
+                        buf.extend_from_slice(&zero_64.to_be_bytes());
+                    }
+                    return buf;
+                }
+            }
+            let res_vec: Vec<u64> = match parse_array_with(line, |s| parse_uint_literal(s, 64)) {
+                Ok(res) => res,
+                Err(err) => {
+                    panic!(
+                        "ERROR: While parsing array at line {}: {}",
+                        line_num + 1,
+                        err
+                    )
+                }
+            };
+            let len_ctr: u64 = (res_vec.len() * 8) as u64; //64-bit
+            buf.extend_from_slice(&len_ctr.to_be_bytes());
+            for num in res_vec {
+                buf.extend_from_slice(&num.to_be_bytes());
+            }
+        }
+        DsType::IntArr => {
+            if let Some(s) = lexems.get(type_lexem_n + 1) {
+                if s.starts_with("!zeros=") {
+                    let count: u64 = u64_from_str_auto(&s[7..].to_string());
+                    buf
+                        .extend_from_slice(&(count * 8).to_be_bytes());
+                    let zero_i64: i64 = 0;
+                    for _ in 0..count {
+                        buf.extend_from_slice(&zero_i64.to_be_bytes());
+                    }
+                    return buf;
+                }
+            }
+            let res_vec: Vec<i64> = match parse_array_with(line, |s| parse_int_literal(s, 64)) {
+                Ok(res) => res,
+                Err(err) => {
+                    panic!(
+                        "ERROR: While parsing array at line {}: {}",
+                        line_num + 1,
+                        err
+                    )
+                }
+            };
+            let len_ctr: u64 = (res_vec.len() * 8) as u64; //64-bit
+            buf.extend_from_slice(&len_ctr.to_be_bytes());
+            for num in res_vec {
+                buf.extend_from_slice(&num.to_be_bytes());
+            }
+        }
+        DsType::FloatArr => {
+            if let Some(s) = lexems.get(type_lexem_n + 1) {
+                if s.starts_with("!zeros=") {
+                    let count: u64 = u64_from_str_auto(&s[7..].to_string());
+                    buf
+                        .extend_from_slice(&(count * 8).to_be_bytes());
+                    let zero_f64: f64 = 0f64;
+                    for _ in 0..count {
+                        buf.extend_from_slice(&zero_f64.to_be_bytes());
+                    }
+                    return buf;
+                }
+            }
+            let res_vec: Vec<f64> = match parse_array_string::<f64>(&line) {
+                Ok(res) => res,
+                Err(err) => {
+                    panic!(
+                        "ERROR: While parsing array at line {}: {}",
+                        line_num + 1,
+                        err
+                    )
+                }
+            };
+            let len_ctr: u64 = (res_vec.len() * 8) as u64; //64-bit
+            buf.extend_from_slice(&len_ctr.to_be_bytes());
+            for num in res_vec {
+                buf.extend_from_slice(&num.to_be_bytes());
+            }
+        }
+        DsType::U8 | DsType::I8 | DsType::U16 | DsType::I16 | DsType::U32 | DsType::I32 => {
+            let width: usize = var_type.elem_width();
+            let arg: &str = lexems[type_lexem_n + 1];
+            let res: u64 = match var_type.is_signed() {
+                true => parse_int_literal(arg, width as u32 * 8).map(|v| v as u64),
+                false => parse_uint_literal(arg, width as u32 * 8),
+            }
+            .unwrap_or_else(|e| panic!("{}: {}", line_num, e));
+            buf.extend_from_slice(&(width as u64).to_be_bytes());
+            buf.extend_from_slice(&res.to_be_bytes()[(8 - width)..]);
+        }
+        DsType::U8Arr
+        | DsType::I8Arr
+        | DsType::U16Arr
+        | DsType::I16Arr
+        | DsType::Uint32Arr
+        | DsType::Int32Arr
+        | DsType::Float32Arr => {
+            let bytes: Vec<u8> = match encode_packed_array(var_type, &line, &lexems[(type_lexem_n + 1)..]) {
+                Ok(v) => v,
+                Err(err) => panic!("ERROR: While parsing array at line {}: {}", line_num, err),
+            };
+            buf
+                .extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+            buf.extend_from_slice(&bytes);
+        }
+        DsType::Bytes => {
+            let bytes: Vec<u8> = match parse_bytes(&lexems[(type_lexem_n + 1)..]) {
+                Ok(v) => v,
+                Err(err) => panic!("ERROR: While parsing bytes at line {}: {}", line_num, err),
+            };
+            buf
+                .extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+            buf.extend_from_slice(&bytes);
+        }
+        DsType::Ptr => panic!("CRITICAL at voxasm: unknown constant type."),
+    }
+    buf
+}
+
+pub(crate) fn voxasm_instr_table() -> HashMap<String, Vec<LexTypes>> {
     // Format:
     // Opcode, length, args.
     hashmap! {
         "halt".to_string() => vec![LexTypes::Op(0xFF), LexTypes::Size(1)],
         "ncall".to_string() => vec![LexTypes::Op(0x1), LexTypes::Size(4), LexTypes::NcallNum(0), LexTypes::Reg(0)],
         "nop".to_string() => vec![LexTypes::Op(0x2), LexTypes::Size(1)],
+        "rdcnt".to_string() => vec![LexTypes::Op(0x3), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
+        "excclear".to_string() => vec![LexTypes::ExtOp(0x01), LexTypes::Size(10), LexTypes::Exception(0)],
         "uload".to_string() => vec![LexTypes::Op(0x10), LexTypes::Size(10), LexTypes::Reg(0), LexTypes::Value(0)],
+        "uload32".to_string() => vec![LexTypes::Op(0x1b), LexTypes::Size(6), LexTypes::Reg(0), LexTypes::Value(0)],
         "uadd".to_string() => vec![LexTypes::Op(0x11), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "umul".to_string() => vec![LexTypes::Op(0x12), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "usub".to_string() => vec![LexTypes::Op(0x13), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
@@ -651,6 +1300,7 @@ fn voxasm_instr_table() -> HashMap<String, Vec<LexTypes>> {
         "uinc".to_string() => vec![LexTypes::Op(0x19), LexTypes::Size(2), LexTypes::Reg(0)],
         "udec".to_string() => vec![LexTypes::Op(0x1a), LexTypes::Size(2), LexTypes::Reg(0)],
         "iload".to_string() => vec![LexTypes::Op(0x20), LexTypes::Size(10), LexTypes::Reg(0), LexTypes::Value(0)],
+        "iload32".to_string() => vec![LexTypes::Op(0x2d), LexTypes::Size(6), LexTypes::Reg(0), LexTypes::Value(0)],
         "iadd".to_string() => vec![LexTypes::Op(0x21), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "imul".to_string() => vec![LexTypes::Op(0x22), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "isub".to_string() => vec![LexTypes::Op(0x23), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
@@ -677,6 +1327,8 @@ fn voxasm_instr_table() -> HashMap<String, Vec<LexTypes>> {
         "fpow".to_string() => vec![LexTypes::Op(0x3b), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "finc".to_string() => vec![LexTypes::Op(0x3c), LexTypes::Size(2), LexTypes::Reg(0)],
         "fdec".to_string() => vec![LexTypes::Op(0x3d), LexTypes::Size(2), LexTypes::Reg(0)],
+        "fsete".to_string() => vec![LexTypes::Op(0x3e), LexTypes::Size(2), LexTypes::Reg(0)],
+        "fgete".to_string() => vec![LexTypes::Op(0x3f), LexTypes::Size(2), LexTypes::Reg(0)],
         "uinc".to_string() => vec![LexTypes::Op(0x19), LexTypes::Size(2), LexTypes::Reg(0)],
         "jmp".to_string() => vec![LexTypes::Op(0x40), LexTypes::Size(9), LexTypes::Addr(0)],
         "jz".to_string() => vec![LexTypes::Op(0x41), LexTypes::Size(9), LexTypes::Addr(0)],
@@ -687,6 +1339,13 @@ fn voxasm_instr_table() -> HashMap<String, Vec<LexTypes>> {
         "jexc".to_string() => vec![LexTypes::Op(0x46), LexTypes::Size(17), LexTypes::Exception((0)), LexTypes::Addr(0)],
         "jmpr".to_string() => vec![LexTypes::Op(0x47), LexTypes::Size(2), LexTypes::Reg(0)],
         "jnz".to_string() => vec![LexTypes::Op(0x48), LexTypes::Size(9), LexTypes::Addr(0)],
+        "switch".to_string() => vec![LexTypes::Op(0x49), LexTypes::Size(10), LexTypes::Reg(0), LexTypes::Addr(0)],
+        "sete".to_string() => vec![LexTypes::Op(0x4a), LexTypes::Size(2), LexTypes::Reg(0)],
+        "setne".to_string() => vec![LexTypes::Op(0x4b), LexTypes::Size(2), LexTypes::Reg(0)],
+        "setl".to_string() => vec![LexTypes::Op(0x4c), LexTypes::Size(2), LexTypes::Reg(0)],
+        "setg".to_string() => vec![LexTypes::Op(0x4d), LexTypes::Size(2), LexTypes::Reg(0)],
+        "setge".to_string() => vec![LexTypes::Op(0x4e), LexTypes::Size(2), LexTypes::Reg(0)],
+        "setle".to_string() => vec![LexTypes::Op(0x4f), LexTypes::Size(2), LexTypes::Reg(0)],
         "utoi".to_string() => vec![LexTypes::Op(0x50), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "itou".to_string() => vec![LexTypes::Op(0x51), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "utof".to_string() => vec![LexTypes::Op(0x52), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
@@ -695,6 +1354,7 @@ fn voxasm_instr_table() -> HashMap<String, Vec<LexTypes>> {
         "ftoi".to_string() => vec![LexTypes::Op(0x55), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "ptou".to_string() => vec![LexTypes::Op(0x56), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "utop".to_string() => vec![LexTypes::Op(0x57), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
+        "lea".to_string() => vec![LexTypes::Op(0x10), LexTypes::Size(10), LexTypes::Reg(0), LexTypes::Addr(0)], // pseudo, uload
         "movr".to_string() => vec![LexTypes::Op(0x60), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "or".to_string() => vec![LexTypes::Op(0x61), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "and".to_string() => vec![LexTypes::Op(0x62), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
@@ -704,6 +1364,10 @@ fn voxasm_instr_table() -> HashMap<String, Vec<LexTypes>> {
         "lnot".to_string() => vec![LexTypes::Op(0x66), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "shl".to_string() => vec![LexTypes::Op(0x67), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "shr".to_string() => vec![LexTypes::Op(0x68), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
+        "cmovz".to_string() => vec![LexTypes::Op(0x69), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
+        "cmovl".to_string() => vec![LexTypes::Op(0x6a), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
+        "cmovg".to_string() => vec![LexTypes::Op(0x6b), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
+        "xchg".to_string() => vec![LexTypes::Op(0x6c), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "dsload".to_string() => vec![LexTypes::Op(0x70), LexTypes::Size(18), LexTypes::Reg(0), LexTypes::Addr(0), LexTypes::Addr(0)],
         "dsrload".to_string() => vec![LexTypes::Op(0x71), LexTypes::Size(11), LexTypes::Reg(0), LexTypes::Reg(0), LexTypes::Addr(0)],
         "dssave".to_string() => vec![LexTypes::Op(0x72), LexTypes::Size(18), LexTypes::Reg(0), LexTypes::Addr(0), LexTypes::Addr(0)],
@@ -712,6 +1376,10 @@ fn voxasm_instr_table() -> HashMap<String, Vec<LexTypes>> {
         "dsderef".to_string() => vec![LexTypes::Op(0x75), LexTypes::Size(11), LexTypes::Reg(0), LexTypes::Reg(0), LexTypes::Addr(0)],
         "dsrlea".to_string() => vec![LexTypes::Op(0x76), LexTypes::Size(11), LexTypes::Reg(0), LexTypes::Reg(0), LexTypes::Addr(0)],
         "dsrderef".to_string() => vec![LexTypes::Op(0x77), LexTypes::Size(4), LexTypes::Reg(0), LexTypes::Reg(0), LexTypes::Reg(0)],
+        "dsstore".to_string() => vec![LexTypes::Op(0x78), LexTypes::Size(11), LexTypes::Reg(0), LexTypes::Reg(0), LexTypes::Addr(0)],
+        "dsindex".to_string() => vec![LexTypes::Op(0x79), LexTypes::Size(11), LexTypes::Reg(0), LexTypes::Addr(0), LexTypes::Reg(0)],
+        "strindex".to_string() => vec![LexTypes::Op(0x7A), LexTypes::Size(4), LexTypes::Reg(0), LexTypes::Reg(0), LexTypes::Reg(0)],
+        "strlen".to_string() => vec![LexTypes::Op(0x7B), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "push".to_string() => vec![LexTypes::Op(0x80), LexTypes::Size(2), LexTypes::Reg(0)],
         "pop".to_string() => vec![LexTypes::Op(0x81), LexTypes::Size(2), LexTypes::Reg(0)],
         "pushall".to_string() => vec![LexTypes::Op(0x82), LexTypes::Size(1)],
@@ -719,6 +1387,11 @@ fn voxasm_instr_table() -> HashMap<String, Vec<LexTypes>> {
         "gsf".to_string() => vec![LexTypes::Op(0x84), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "usf".to_string() => vec![LexTypes::Op(0x85), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "call".to_string() => vec![LexTypes::Op(0x90), LexTypes::Size(9), LexTypes::Value(0)],
+        "cocreate".to_string() => vec![LexTypes::Op(0x94), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
+        "coresume".to_string() => vec![LexTypes::Op(0x95), LexTypes::Size(2), LexTypes::Reg(0)],
+        "coyield".to_string() => vec![LexTypes::Op(0x96), LexTypes::Size(1)],
+        "costatus".to_string() => vec![LexTypes::Op(0x97), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
+        "coslice".to_string() => vec![LexTypes::Op(0x98), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "ret".to_string() => vec![LexTypes::Op(0x91), LexTypes::Size(1)],
         "fnstind".to_string() => vec![LexTypes::Op(0x92), LexTypes::Size(10), LexTypes::Reg((0)), LexTypes::FuncInd((0))],
         "callr".to_string() => vec![LexTypes::Op(0x93), LexTypes::Size(2), LexTypes::Reg((0))],
@@ -728,10 +1401,174 @@ fn voxasm_instr_table() -> HashMap<String, Vec<LexTypes>> {
         "allocr".to_string() => vec![LexTypes::Op(0xA3), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
         "load".to_string() => vec![LexTypes::Op(0xA4), LexTypes::Size(5), LexTypes::Reg(0), LexTypes::Reg(0), LexTypes::Reg(0), LexTypes::Reg(0)],
         "allocr_nogc".to_string() => vec![LexTypes::Op(0xA5), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
+        "allocal".to_string() => vec![LexTypes::Op(0xAE), LexTypes::Size(4), LexTypes::Reg(0), LexTypes::Reg(0), LexTypes::Reg(0)],
         "memcpy".to_string() => vec![LexTypes::Op(0xA6), LexTypes::Size(4), LexTypes::Reg(0), LexTypes::Reg(0), LexTypes::Reg(0)],
         "storedat".to_string() => vec![LexTypes::Op(0xA7), LexTypes::Size(4), LexTypes::Reg(0), LexTypes::Reg(0), LexTypes::Reg(0)],
+        "load32".to_string() => vec![LexTypes::Op(0xAA), LexTypes::Size(4), LexTypes::Reg(0), LexTypes::Reg(0), LexTypes::Reg(0)],
+        "store32".to_string() => vec![LexTypes::Op(0xAB), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
+        "storei".to_string() => vec![LexTypes::Op(0xAF), LexTypes::Size(12), LexTypes::Mem, LexTypes::Reg(0), LexTypes::Reg(0)],
+        "loadi".to_string() => vec![LexTypes::Op(0xB0), LexTypes::Size(13), LexTypes::Reg(0), LexTypes::Reg(0), LexTypes::Mem, LexTypes::Reg(0)],
+        "allocarena".to_string() => vec![LexTypes::Op(0xB1), LexTypes::Size(4), LexTypes::Reg(0), LexTypes::Reg(0), LexTypes::Reg(0)],
+        "gcadopt".to_string() => vec![LexTypes::ExtOp(0x02), LexTypes::Size(3), LexTypes::Reg(0)],
+        "gcrelease".to_string() => vec![LexTypes::ExtOp(0x03), LexTypes::Size(3), LexTypes::Reg(0)],
+        "abort".to_string() => vec![LexTypes::ExtOp(0x04), LexTypes::Size(4), LexTypes::Reg(0), LexTypes::Reg(0)],
+        // the size is without the inline data, see inline_data
+        "ldstr".to_string() => vec![LexTypes::ExtOp(0x05), LexTypes::Size(11), LexTypes::Reg(0)],
+        "ldbytes".to_string() => vec![LexTypes::ExtOp(0x06), LexTypes::Size(11), LexTypes::Reg(0)],
+        "lget".to_string() => vec![LexTypes::ExtOp(0x07), LexTypes::Size(11), LexTypes::Reg(0), LexTypes::Value(0)],
+        "lset".to_string() => vec![LexTypes::ExtOp(0x08), LexTypes::Size(11), LexTypes::Reg(0), LexTypes::Value(0)],
         "dlbc".to_string() => vec![LexTypes::Op(0xA8), LexTypes::Size(4), LexTypes::Reg(0), LexTypes::Reg(0), LexTypes::Reg(0)],
         "ubd".to_string() => vec![LexTypes::Op(0xA9), LexTypes::Size(4), LexTypes::Reg(0), LexTypes::Reg(0), LexTypes::Reg(0)],
+        "salloc".to_string() => vec![LexTypes::Op(0xAC), LexTypes::Size(3), LexTypes::Reg(0), LexTypes::Reg(0)],
+        "sfree".to_string() => vec![LexTypes::Op(0xAD), LexTypes::Size(2), LexTypes::Reg(0)],
+    }
+}
+
+/// Operands of `movr Rd Rs` line without trailing comment
+fn movr_operands(line: &str) -> Option<(&str, &str)> {
+    let lexems: Vec<&str> = line
+        .split_whitespace()
+        .take_while(|l| !l.contains('#') && (*l != ";"))
+        .collect();
+    match lexems.as_slice() {
+        ["movr", d, s] => Some((d, s)),
+        _ => None,
+    }
+}
+
+/// Rewrites swaps through a temporary register
+///     movr T A / movr A B / movr B T
+/// into
+///     movr T A / xchg A B
+/// T keeps the old A, so nothing after the swap can tell the difference.
+/// Labels and other lines between the movrs break the pattern.
+fn peephole_swaps(lines: &mut [String]) {
+    let code: Vec<usize> = (0..lines.len())
+        .filter(|&i| {
+            let t = lines[i].trim();
+            !t.is_empty() && !t.starts_with('#') && !t.starts_with(';')
+        })
+        .collect();
+
+    let mut i: usize = 0;
+    while i + 2 < code.len() {
+        let (l1, l2, l3) = (code[i], code[i + 1], code[i + 2]);
+        let swap = match (
+            movr_operands(&lines[l1]),
+            movr_operands(&lines[l2]),
+            movr_operands(&lines[l3]),
+        ) {
+            (Some((t, a)), Some((a2, b)), Some((b2, t2)))
+                if a == a2 && b == b2 && t == t2 && t != a && a != b && b != t =>
+            {
+                Some(format!("    xchg {} {}", a, b))
+            }
+            _ => None,
+        };
+        match swap {
+            Some(xchg) => {
+                lines[l2] = xchg;
+                lines[l3] = String::new();
+                i += 3;
+            }
+            None => i += 1,
+        }
+    }
+}
+
+fn get_ncall_table() -> HashMap<String, u16> {
+    // mirrors NativeService::get_std_calls
+    hashmap! {
+        "print".to_string() => 1,
+        "readin".to_string() => 2,
+        "randf".to_string() => 3,
+        "randint".to_string() => 4,
+        "unixtime".to_string() => 5,
+        "sleep".to_string() => 6,
+        "runcmd".to_string() => 7,
+        "dsintern".to_string() => 8,
+        "streq".to_string() => 9,
+        "pin".to_string() => 0xA,
+        "unpin".to_string() => 0xB,
+        "lasterr".to_string() => 0xC,
+        "regdump".to_string() => 0xD,
+        "gc_collect".to_string() => 0xE,
+        "heap_stats".to_string() => 0xF,
+        "fopen".to_string() => 0x10,
+        "fclose".to_string() => 0x11,
+        "fwrite".to_string() => 0x12,
+        "fread".to_string() => 0x13,
+        "fdel".to_string() => 0x14,
+        "fseekget".to_string() => 0x15,
+        "fseekset".to_string() => 0x16,
+        "gather_write".to_string() => 0x17,
+        "scatter_read".to_string() => 0x18,
+        "nc_bind".to_string() => 0x20,
+        "nc_close".to_string() => 0x21,
+        "nc_accept".to_string() => 0x22,
+        "nc_write".to_string() => 0x23,
+        "nc_read".to_string() => 0x24,
+        "nc_getaddr".to_string() => 0x25,
+        "nc_getpeer".to_string() => 0x26,
+        "nc_count".to_string() => 0x27,
+        "fc_usage".to_string() => 0x19,
+        "nc_usage".to_string() => 0x28,
+        "exc_count".to_string() => 0x30,
+        "exc_peek".to_string() => 0x31,
+        "exc_clear".to_string() => 0x32,
+        "heap_snap".to_string() => 0x40,
+        "heap_diff".to_string() => 0x41,
+        "heap_snap_drop".to_string() => 0x42,
+        "res_usage".to_string() => 0x43,
+        "term_goto".to_string() => 0x50,
+        "term_clear".to_string() => 0x51,
+        "term_color".to_string() => 0x52,
+        "term_size".to_string() => 0x53,
+        "term_cursor".to_string() => 0x54,
+        "term_raw".to_string() => 0x55,
+        "term_key".to_string() => 0x56,
+        "ev_count".to_string() => 0x58,
+        "ev_pop".to_string() => 0x59,
+        "ev_dropped".to_string() => 0x5A,
+        "ev_term_pump".to_string() => 0x5B,
+        "fb_create".to_string() => 0x60,
+        "fb_resize".to_string() => 0x61,
+        "fb_fill".to_string() => 0x62,
+        "fb_blit".to_string() => 0x63,
+        "fb_info".to_string() => 0x64,
+        "audio_open".to_string() => 0x70,
+        "audio_write".to_string() => 0x71,
+        "audio_close".to_string() => 0x72,
+        "audio_queued".to_string() => 0x73,
+        "str_len".to_string() => 0x80,
+        "str_slice".to_string() => 0x81,
+        "str_offset".to_string() => 0x82,
+        "printf".to_string() => 0x83,
+        "csv_split".to_string() => 0x84,
+        "csv_join".to_string() => 0x85,
+        "asm_load".to_string() => 0x90,
+        "asm_func".to_string() => 0x91,
+        "shm_create".to_string() => 0xA0,
+        "shm_query".to_string() => 0xA1,
+        "shm_release".to_string() => 0xA2,
+        "rec_limit".to_string() => 0xB0,
+        "rec_limit_set".to_string() => 0xB1,
+        "stack_limit".to_string() => 0xB2,
+        "stack_limit_set".to_string() => 0xB3,
+        "mmap_open".to_string() => 0xC0,
+        "mmap_query".to_string() => 0xC1,
+        "mmap_close".to_string() => 0xC2,
+        "native_load".to_string() => 0xD0,
+        "path_join".to_string() => 0xE0,
+        "path_norm".to_string() => 0xE1,
+        "path_name".to_string() => 0xE2,
+        "path_ext".to_string() => 0xE3,
+        "path_parent".to_string() => 0xE4,
+        "path_abs".to_string() => 0xE5,
+        "arena_new".to_string() => 0xF0,
+        "arena_free".to_string() => 0xF1,
+        "arena_reset".to_string() => 0xF2,
+        "arena_stats".to_string() => 0xF3,
     }
 }
 
@@ -748,10 +1585,233 @@ fn get_exc_table() -> HashMap<String, u64> {
         "incorrectregtype".to_string() => 9,
         "heapsegmfault".to_string() => 10,
         "mainsegmfault".to_string() => 11,
+        "callstacksmash".to_string() => 12,
+        "out_of_memory".to_string() => 13,
+        "handle_limit".to_string() => 14,
+        "coroutine_fault".to_string() => 15,
+        "stack_overflow".to_string() => 16,
     }
 }
 
-fn get_text_length(input: &str) -> Result<usize, &'static str> {
+// `table name default e0 e1 ...` -> [e0, e1, ...], up to a comment
+pub(crate) fn table_entries<'a>(lexems: &[&'a str]) -> Vec<&'a str> {
+    lexems
+        .iter()
+        .skip(3)
+        .take_while(|lex| !lex.contains('#') && (**lex != ";"))
+        .cloned()
+        .collect()
+}
+
+/// Elements count of an array variable: `[a, b, ..]` or `!zeros=N`
+fn array_elems_count(line: &str, args: &[&str]) -> u64 {
+    if let Some(zeros) = args.first().and_then(|a| a.strip_prefix("!zeros=")) {
+        return u64_from_str_auto(zeros);
+    }
+    (get_array_length_str(line).unwrap() / 8) as u64
+}
+
+/// Big-endian elements of an array narrower than 64 bits: u8..i32, float32
+fn encode_packed_array(var_type: DsType, line: &str, args: &[&str]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let width: usize = var_type.elem_width();
+    if let Some(&arg) = args.first() {
+        if arg.starts_with("!zeros=") {
+            return Ok(vec![0; array_elems_count(line, args) as usize * width]);
+        }
+    }
+    let bits: u32 = width as u32 * 8;
+    let res: Vec<u8> = match var_type {
+        DsType::Float32Arr => parse_array_string::<f32>(line)?
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect(),
+        _ if var_type.is_signed() => parse_array_with(line, |s| parse_int_literal(s, bits))?
+            .iter()
+            .flat_map(|v| v.to_be_bytes()[(8 - width)..].to_vec())
+            .collect(),
+        _ => parse_array_with(line, |s| parse_uint_literal(s, bits))?
+            .iter()
+            .flat_map(|v| v.to_be_bytes()[(8 - width)..].to_vec())
+            .collect(),
+    };
+    Ok(res)
+}
+
+/// Register list like `r1-r5 r9` as a bitmask
+pub(crate) fn parse_reg_mask(lexems: &[&str]) -> Result<u32, String> {
+    let mut mask: u32 = 0;
+    for lex in lexems.iter().take_while(|lex| !lex.contains('#') && (**lex != ";")) {
+        let (from, to) = lex.split_once('-').unwrap_or((lex, lex));
+        let parse = |r: &str| -> Result<u32, String> {
+            match r.strip_prefix('r').map(|v| v.parse::<u32>()) {
+                Some(Ok(v)) if v < 32 => Ok(v),
+                _ => Err(format!("Invalid register '{}'", r)),
+            }
+        };
+        for i in parse(from)?..=parse(to)? {
+            mask |= 1 << i;
+        }
+    }
+    Ok(mask)
+}
+
+/// Parses `bytes` variable initializers: byte literals and `!fill=value,count`
+/// Data `ldstr Rdst "text"` (utf16, like a data str) and `ldbytes Rdst
+/// bytes..` (like a data bytes var) carry right after the instruction, None
+/// for the other instructions
+pub(crate) fn inline_data(line: &str, lexems: &[&str]) -> Option<Result<Vec<u8>, String>> {
+    match lexems.first() {
+        Some(&"ldstr") => Some(
+            get_text(line)
+                .map(|text| text.encode_utf16().flat_map(|u| u.to_be_bytes()).collect())
+                .map_err(|e| e.to_string()),
+        ),
+        Some(&"ldbytes") => Some(parse_bytes(lexems.get(2..).unwrap_or_default())),
+        _ => None,
+    }
+}
+
+fn parse_bytes(lexems: &[&str]) -> Result<Vec<u8>, String> {
+    let mut res: Vec<u8> = Vec::new();
+    for lex in lexems.iter().take_while(|lex| !lex.contains('#') && (**lex != ";")) {
+        if let Some(fill) = lex.strip_prefix("!fill=") {
+            let (value, count) = match fill.split_once(',') {
+                Some(v) => v,
+                None => return Err(format!("Fill should be !fill=value,count, got {}", lex)),
+            };
+            let value: u8 = parse_byte(value)?;
+            let count: u64 = match parse_num_literal(count) {
+                Some(v) => v,
+                None => return Err(format!("Invalid fill count: {}", count)),
+            };
+            res.resize(res.len() + count as usize, value);
+            continue;
+        }
+        res.push(parse_byte(lex)?);
+    }
+    Ok(res)
+}
+
+fn parse_byte(s: &str) -> Result<u8, String> {
+    match parse_num_literal(s) {
+        Some(v) if v <= 0xFF => Ok(v as u8),
+        Some(_) => Err(format!("Byte out of range: {}", s)),
+        None => Err(format!("Invalid byte: {}", s)),
+    }
+}
+
+/// uload/iload with an immediate that fits into 32 bits become uload32/iload32
+pub(crate) fn short_load_form(mut lexems: Vec<&str>) -> Vec<&str> {
+    let arg: &str = match lexems.get(2) {
+        Some(v) => v,
+        None => return lexems,
+    };
+    // only when the literal reads as the same value in 32 bits:
+    // `uload r1 -1` stays 8 bytes of ones, `iload r1 0xFFFFFFFF` isn't -1
+    let same_u = |a: &str| parse_uint_literal(a, 32).is_ok_and(|v| Ok(v) == parse_uint_literal(a, 64));
+    let same_i = |a: &str| parse_int_literal(a, 32).is_ok_and(|v| Ok(v) == parse_int_literal(a, 64));
+    if lexems[0] == "uload" && same_u(arg) {
+        lexems[0] = "uload32";
+    } else if lexems[0] == "iload" && same_i(arg) {
+        lexems[0] = "iload32";
+    }
+    lexems
+}
+
+// Numeric literals: decimal, 0x hex, 0b binary or 0o octal, with an optional
+// sign and `_` between digits (`1_000_000`, `0xFFFF_0000`). Every immediate,
+// address, index and data initializer goes through the routines below, so a
+// literal means the same thing wherever it is written.
+
+/// Sign, magnitude and whether the literal had a radix prefix
+fn split_literal(s: &str) -> Result<(bool, u64, bool), String> {
+    let (neg, body) = match s.strip_prefix('-') {
+        Some(b) => (true, b),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let lower: String = body.to_lowercase();
+    let (radix, digits, kind) = if let Some(d) = lower.strip_prefix("0x") {
+        (16, d, "hex")
+    } else if let Some(d) = lower.strip_prefix("0b") {
+        (2, d, "binary")
+    } else if let Some(d) = lower.strip_prefix("0o") {
+        (8, d, "octal")
+    } else {
+        (10, lower.as_str(), "decimal")
+    };
+    if digits.is_empty() {
+        return Err(format!("Invalid number '{}': no digits", s));
+    }
+    if digits.starts_with('_') || digits.ends_with('_') {
+        return Err(format!("Invalid number '{}': '_' only goes between digits", s));
+    }
+    let mut mag: u64 = 0;
+    for c in digits.chars().filter(|c| *c != '_') {
+        let digit: u32 = match c.to_digit(radix) {
+            Some(d) => d,
+            None => return Err(format!("Invalid number '{}': '{}' is not a {} digit", s, c, kind)),
+        };
+        mag = match mag.checked_mul(radix as u64).and_then(|m| m.checked_add(digit as u64)) {
+            Some(m) => m,
+            None => return Err(format!("Invalid number '{}': doesn't fit into 64 bits", s)),
+        };
+    }
+    Ok((neg, mag, radix != 10))
+}
+
+fn max_of_bits(bits: u32) -> u64 {
+    match bits {
+        64 => u64::MAX,
+        _ => (1 << bits) - 1,
+    }
+}
+
+/// Unsigned `bits`-wide value. Negatives wrap: `-1` is all ones, `-0x80`
+/// in 8 bits is 0x80; anything below the signed minimum is an error
+pub fn parse_uint_literal(s: &str, bits: u32) -> Result<u64, String> {
+    let (neg, mag, _) = split_literal(s)?;
+    let max: u64 = max_of_bits(bits);
+    match neg {
+        false if mag <= max => Ok(mag),
+        true if mag <= (1 << (bits - 1)) => Ok(mag.wrapping_neg() & max),
+        _ => Err(format!("Invalid number '{}': out of the {}-bit range", s, bits)),
+    }
+}
+
+/// Signed `bits`-wide value. Prefixed literals may also spell the raw bit
+/// pattern: `0xFFFFFFFF` in 32 bits is -1, decimal 4294967295 is an error
+pub fn parse_int_literal(s: &str, bits: u32) -> Result<i64, String> {
+    let (neg, mag, prefixed) = split_literal(s)?;
+    let min_mag: u64 = 1 << (bits - 1);
+    let shift: u32 = 64 - bits;
+    match neg {
+        true if mag <= min_mag => Ok((mag as i64).wrapping_neg()),
+        false if mag < min_mag => Ok(mag as i64),
+        false if prefixed && (mag <= max_of_bits(bits)) => Ok(((mag << shift) as i64) >> shift),
+        _ => Err(format!("Invalid number '{}': out of the {}-bit signed range", s, bits)),
+    }
+}
+
+/// `[rN]`, `[rN+offset]` or `[rN-offset]` of storei/loadi: register and offset
+pub(crate) fn parse_mem_operand(s: &str) -> Option<(u8, i64)> {
+    let inner: &str = s.strip_prefix('[')?.strip_suffix(']')?;
+    let (reg, offset): (&str, i64) = match inner.find(['+', '-']) {
+        Some(pos) => {
+            let lit: &str = &inner[pos..];
+            (&inner[..pos], parse_int_literal(lit.strip_prefix('+').unwrap_or(lit), 64).ok()?)
+        }
+        None => (inner, 0),
+    };
+    let reg_ind: u8 = reg.strip_prefix('r')?.parse().ok()?;
+    Some((reg_ind, offset))
+}
+
+/// Any 64-bit literal, negatives wrapped, None if it isn't one
+pub fn parse_num_literal(s: &str) -> Option<u64> {
+    parse_uint_literal(s, 64).ok()
+}
+
+fn get_text(input: &str) -> Result<&str, &'static str> {
     let start = match input.find('"') {
         Some(pos) => pos + 1,
         None => return Err("String should be started with quotemark"),
@@ -762,7 +1822,11 @@ fn get_text_length(input: &str) -> Result<usize, &'static str> {
         None => return Err("String should be ended with quotemark"),
     };
 
-    let text = &input[start..end];
+    Ok(&input[start..end])
+}
+
+fn get_text_length(input: &str) -> Result<usize, &'static str> {
+    let text = get_text(input)?;
 
     // For UTF-16 code units:
     Ok(text.encode_utf16().count() * 2)
@@ -777,6 +1841,17 @@ fn get_array_length_str(input: &str) -> Option<usize> {
     return Some(count * 8);
 }
 
+/// Array elements through a literal parser, errors name the element
+fn parse_array_with<T>(input: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<Vec<T>, String> {
+    let start: usize = input.rfind('[').ok_or("Missing opening bracket")?;
+    let end: usize = input.rfind(']').ok_or("Missing closing bracket")?;
+    input[start + 1..end]
+        .split(',')
+        .enumerate()
+        .map(|(i, s)| parse(s.trim()).map_err(|e| format!("element {}: {}", i, e)))
+        .collect()
+}
+
 fn parse_array_string<T: FromStr>(input: &str) -> Result<Vec<T>, Box<dyn std::error::Error>>
 where
     T::Err: std::error::Error + 'static,
@@ -795,40 +1870,62 @@ where
         .collect()
 }
 
+/// Addresses, indices and counts, where a bad literal ends assembling
 pub fn u64_from_str_auto(s: &str) -> u64 {
-    let mut radix: u32 = 10;
-    if s.contains("0x") {
-        radix = 16;
-    } else if s.contains("0b") {
-        radix = 2;
-    }
-
-    let res: u64 = match u64::from_str_radix(s, radix) {
+    match parse_uint_literal(s, 64) {
         Ok(val) => val,
-        Err(err) => panic!("ERROR Parsing a number from {}: {}", s, err),
-    };
-    return res;
+        Err(err) => panic!("ERROR: {}", err),
+    }
 }
 
-pub fn detect_ds_var_type(s: &str) -> Option<u8> {
+pub fn detect_ds_var_type(s: &str) -> Option<DsType> {
     let re_uint = Regex::new(r"^uint\[\d+\]$").unwrap(); // Changed to [size]
     let re_int = Regex::new(r"^int\[\d+\]$").unwrap(); // Changed to [size]
     let re_float = Regex::new(r"^float\[\d+\]$").unwrap(); // Changed to [size]
 
     if re_uint.is_match(s) {
-        return Some(0x6);
+        return Some(DsType::UintArr);
     } else if re_int.is_match(s) {
-        return Some(0x7);
+        return Some(DsType::IntArr);
     } else if re_float.is_match(s) {
-        return Some(0x8);
+        return Some(DsType::FloatArr);
+    }
+
+    // 32-bit element arrays
+    let re_32 = Regex::new(r"^(uint|int|float)32\[\d+\]$").unwrap();
+    if let Some(caps) = re_32.captures(s) {
+        return match &caps[1] {
+            "uint" => Some(DsType::Uint32Arr),
+            "int" => Some(DsType::Int32Arr),
+            _ => Some(DsType::Float32Arr),
+        };
+    }
+    // narrow integer arrays, u32[N]/i32[N] are uint32[N]/int32[N]
+    let re_narrow = Regex::new(r"^([ui])(8|16|32)\[\d+\]$").unwrap();
+    if let Some(caps) = re_narrow.captures(s) {
+        return match (&caps[1], &caps[2]) {
+            ("u", "8") => Some(DsType::U8Arr),
+            ("i", "8") => Some(DsType::I8Arr),
+            ("u", "16") => Some(DsType::U16Arr),
+            ("i", "16") => Some(DsType::I16Arr),
+            ("u", _) => Some(DsType::Uint32Arr),
+            _ => Some(DsType::Int32Arr),
+        };
     }
 
     // Then match scalar types
     match s {
-        "uint" => Some(0x1),
-        "int" => Some(0x2),
-        "float" => Some(0x3),
-        "str" => Some(0x4),
+        "uint" => Some(DsType::Uint),
+        "int" => Some(DsType::Int),
+        "float" => Some(DsType::Float),
+        "str" => Some(DsType::Str),
+        "bytes" | "db" => Some(DsType::Bytes),
+        "u8" => Some(DsType::U8),
+        "i8" => Some(DsType::I8),
+        "u16" => Some(DsType::U16),
+        "i16" => Some(DsType::I16),
+        "u32" => Some(DsType::U32),
+        "i32" => Some(DsType::I32),
         _ => None,
     }
 }
@@ -1,17 +1,52 @@
+use crate::{registers::Register, vm::RegTypes};
+
 #[derive(Debug)]
 pub struct CallStack {
     pub stack: Vec<CSFrame>,
+    root_locals: Vec<(u64, RegTypes)>, // `lget`/`lset` slots outside of calls
 }
 
 impl CallStack {
     pub fn new() -> CallStack {
         CallStack {
             stack: (Vec::new()),
+            root_locals: Vec::new(),
+        }
+    }
+
+    /// Pushes a frame of function `func` with callee-saved registers to restore on return
+    pub fn push_saved(&mut self, retaddr: u64, func: usize, saved: Vec<(usize, Register, RegTypes)>) {
+        let mut frame: CSFrame = CSFrame::new(retaddr);
+        frame.func = Some(func);
+        frame.saved = saved;
+        self.stack.push(frame);
+    }
+
+    /// Index of the function being executed, None outside of calls
+    pub fn current_func(&self) -> Option<usize> {
+        self.stack.last().and_then(|f| f.func)
+    }
+
+    /// Takes saved registers of the top frame
+    pub fn take_saved(&mut self) -> Vec<(usize, Register, RegTypes)> {
+        match self.stack.last_mut() {
+            Some(frame) => std::mem::take(&mut frame.saved),
+            None => Vec::new(),
+        }
+    }
+
+    /// Local slots of the function being executed, the top-level ones
+    /// outside of calls
+    pub fn locals_mut(&mut self) -> &mut Vec<(u64, RegTypes)> {
+        match self.stack.last_mut() {
+            Some(frame) => &mut frame.locals,
+            None => &mut self.root_locals,
         }
     }
 
-    pub fn push(&mut self, retaddr: u64) {
-        self.stack.push(CSFrame::new(retaddr));
+    /// Local slots of every frame, the top-level ones included
+    pub fn all_locals(&self) -> impl Iterator<Item = &(u64, RegTypes)> {
+        self.root_locals.iter().chain(self.stack.iter().flat_map(|f| f.locals.iter()))
     }
 
     pub fn pop(&mut self) -> Option<u64> {
@@ -25,11 +60,19 @@ impl CallStack {
     }
 }
 
+impl Default for CallStack {
+    fn default() -> CallStack {
+        CallStack::new()
+    }
+}
+
 #[derive(Debug)]
 pub struct CSFrame {
     retaddr: u64,
-    locals: Vec<u64>,
+    locals: Vec<(u64, RegTypes)>, // value bits and type of `lset` slots
     checked: bool,
+    func: Option<usize>, // callee index
+    saved: Vec<(usize, Register, RegTypes)>, // reg ind, value, type
 }
 
 impl CSFrame {
@@ -38,6 +81,28 @@ impl CSFrame {
             retaddr: (addr),
             locals: (Vec::new()),
             checked: (false),
+            func: None,
+            saved: Vec::new(),
         }
     }
+
+    pub fn retaddr(&self) -> u64 {
+        self.retaddr
+    }
+
+    pub fn locals(&self) -> &[(u64, RegTypes)] {
+        &self.locals
+    }
+
+    pub fn checked(&self) -> bool {
+        self.checked
+    }
+
+    pub fn func(&self) -> Option<usize> {
+        self.func
+    }
+
+    pub fn saved(&self) -> &[(usize, Register, RegTypes)] {
+        &self.saved
+    }
 }
@@ -0,0 +1,376 @@
+// Control-flow graph export (`--emit-cfg=out.dot`).
+// Bytecode is decoded by following control flow from the entry and every
+// function of the function table, so switch tables and data are never decoded
+// as instructions. Basic blocks are grouped into a cluster per function.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+
+use crate::assembly::{voxasm_instr_table, LexTypes};
+use crate::fileformats::{read_line_table, read_symbols, VoxExeHeader, SECT_LINES, SECT_SYMBOLS};
+use crate::misclib::{args_to_i64, args_to_u64};
+use crate::vm::EXT_PREFIX;
+
+/// What the CFG is built from
+pub struct CfgImage {
+    pub code: Vec<u8>,
+    pub entry: u64,
+    pub funcs: Vec<u64>,             // func ind -> addr
+    pub func_names: HashMap<usize, String>,
+    pub lines: Vec<(u64, u32)>,      // instr addr -> source line
+}
+
+impl CfgImage {
+    pub fn from_vve(path: &str, min_version: u16) -> Result<CfgImage, String> {
+        let header: VoxExeHeader = VoxExeHeader::load(path, min_version)?;
+        let bytes: Vec<u8> = fs::read(path).map_err(|e| e.to_string())?;
+        let code_start: usize = header.size();
+        let code_end: usize = (code_start + header.data_base as usize).min(bytes.len());
+        let mut code: Vec<u8> = bytes[code_start..code_end].to_vec();
+        header.swap_code_words(&mut code);
+        Ok(CfgImage {
+            code,
+            entry: header.entry_point,
+            funcs: header.func_table.clone(),
+            func_names: match header.section(SECT_SYMBOLS) {
+                Some(sect) => read_symbols(&sect.data).into_iter().collect(),
+                None => HashMap::new(),
+            },
+            lines: match header.section(SECT_LINES) {
+                Some(sect) => read_line_table(&sect.data),
+                None => Vec::new(),
+            },
+        })
+    }
+
+    /// Raw images have no header: code starts at 0 and so does execution
+    pub fn from_raw(path: &str) -> Result<CfgImage, String> {
+        Ok(CfgImage {
+            code: fs::read(path).map_err(|e| e.to_string())?,
+            entry: 0,
+            funcs: Vec::new(),
+            func_names: HashMap::new(),
+            lines: Vec::new(),
+        })
+    }
+}
+
+struct OpInfo {
+    name: String,
+    size: usize,
+    operands: Vec<LexTypes>,
+}
+
+/// opcode -> mnemonic and encoding, from the assembler table. Two-byte
+/// opcodes are 0xFEnn
+fn opcode_table() -> HashMap<u16, OpInfo> {
+    let mut res: HashMap<u16, OpInfo> = HashMap::new();
+    for (name, lex) in voxasm_instr_table() {
+        if name == "lea" {
+            continue; // same encoding as uload
+        }
+        let op: u16 = match lex.first() {
+            Some(LexTypes::Op(op)) => *op as u16,
+            Some(LexTypes::ExtOp(op)) => u16::from_be_bytes([EXT_PREFIX, *op]),
+            _ => continue,
+        };
+        if let Some(LexTypes::Size(size)) = lex.get(1) {
+            res.insert(op, OpInfo { name, size: *size as usize, operands: lex[2..].to_vec() });
+        }
+    }
+    res
+}
+
+/// opcode -> instruction size in bytes, keyed like `opcode_at`
+pub fn op_sizes() -> HashMap<u16, usize> {
+    opcode_table().into_iter().map(|(op, info)| (op, info.size)).collect()
+}
+
+/// Opcode of the instruction at `at`, 0xFEnn for two-byte ones
+pub fn opcode_at(code: &[u8], at: usize) -> Option<u16> {
+    match *code.get(at)? {
+        EXT_PREFIX => Some(u16::from_be_bytes([EXT_PREFIX, *code.get(at + 1)?])),
+        op => Some(op as u16),
+    }
+}
+
+/// Bytes of `ldstr`/`ldbytes` data after the instruction at `at`, 0 for
+/// the others
+fn inline_len(code: &[u8], at: usize, op: u16) -> Option<usize> {
+    match op {
+        0xFE05 | 0xFE06 => usize::try_from(read_u64(code, at + 3)?).ok(),
+        _ => Some(0),
+    }
+}
+
+/// Size of the instruction at `at` with its inline data, None if the
+/// opcode is unknown
+pub fn instr_size_at(code: &[u8], at: usize, sizes: &HashMap<u16, usize>) -> Option<usize> {
+    let op: u16 = opcode_at(code, at)?;
+    sizes.get(&op)?.checked_add(inline_len(code, at, op)?)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum EdgeKind {
+    Taken,
+    Fallthrough,
+    Case(usize),
+    Default,
+    Call,
+}
+
+impl EdgeKind {
+    fn label(&self) -> String {
+        match self {
+            EdgeKind::Taken => "taken".to_string(),
+            EdgeKind::Fallthrough => "fallthrough".to_string(),
+            EdgeKind::Case(n) => format!("case {}", n),
+            EdgeKind::Default => "default".to_string(),
+            EdgeKind::Call => "call".to_string(),
+        }
+    }
+}
+
+pub struct Decoded {
+    pub text: String,
+    pub size: usize,
+    succs: Vec<(u64, EdgeKind)>, // Call edges go to the callee entry
+    ends_block: bool,
+}
+
+impl Decoded {
+    /// Jump target or callee entry of a jump/branch/call
+    pub fn target(&self) -> Option<u64> {
+        self.succs.iter().find(|(_, kind)| matches!(kind, EdgeKind::Taken | EdgeKind::Call)).map(|(addr, _)| *addr)
+    }
+}
+
+fn read_u64(code: &[u8], at: usize) -> Option<u64> {
+    code.get(at..(at + 8)).map(args_to_u64)
+}
+
+fn decode(code: &[u8], addr: u64, ops: &HashMap<u16, OpInfo>, funcs: &[u64]) -> Option<Decoded> {
+    let at: usize = addr as usize;
+    let op: u16 = opcode_at(code, at)?;
+    let op_len: usize = if op > 0xFF { 2 } else { 1 };
+    let info: &OpInfo = ops.get(&op)?;
+    let size: usize = info.size.checked_add(inline_len(code, at, op)?)?;
+    if at.checked_add(size)? > code.len() {
+        return None;
+    }
+
+    // registers are 1 byte, ncall numbers 2, `[rN+offset]` 9, the rest share what's left
+    let regs: usize = info.operands.iter().filter(|o| matches!(o, LexTypes::Reg(_))).count();
+    let ncalls: usize = info.operands.iter().filter(|o| matches!(o, LexTypes::NcallNum(_))).count();
+    let mems: usize = info.operands.iter().filter(|o| matches!(o, LexTypes::Mem)).count();
+    let wide_count: usize = info.operands.len() - regs - ncalls - mems;
+    let wide: usize = match wide_count {
+        0 => 0,
+        n => (info.size - op_len - regs - 2 * ncalls - 9 * mems) / n,
+    };
+    let mut text: String = info.name.clone();
+    let mut pos: usize = at + op_len;
+    let mut wides: Vec<u64> = Vec::new();
+    for operand in &info.operands {
+        match operand {
+            LexTypes::Reg(_) => {
+                text += &format!(" r{}", code[pos]);
+                pos += 1;
+            }
+            LexTypes::NcallNum(_) => {
+                text += &format!(" {:#x}", u16::from_be_bytes([code[pos], code[pos + 1]]));
+                pos += 2;
+            }
+            LexTypes::Mem => {
+                let offset: i64 = args_to_i64(&code[(pos + 1)..(pos + 9)]);
+                text += &match offset < 0 {
+                    true => format!(" [r{}-{:#x}]", code[pos], offset.unsigned_abs()),
+                    false => format!(" [r{}+{:#x}]", code[pos], offset),
+                };
+                pos += 9;
+            }
+            _ => {
+                let val: u64 = code[pos..(pos + wide)].iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+                text += &format!(" {:#x}", val);
+                wides.push(val);
+                pos += wide;
+            }
+        }
+    }
+
+    if size > info.size {
+        text += &format!(" <{} bytes>", size - info.size);
+    }
+
+    let next: u64 = addr + size as u64;
+    let target: u64 = wides.last().copied().unwrap_or(0);
+    let (succs, ends_block): (Vec<(u64, EdgeKind)>, bool) = match op {
+        0xFF | 0x91 | 0x47 | 0xFE04 => (Vec::new(), true), // halt, ret, jmpr, abort
+        0x40 => (vec![(target, EdgeKind::Taken)], true),
+        0x41..=0x46 | 0x48 => (vec![(target, EdgeKind::Taken), (next, EdgeKind::Fallthrough)], true),
+        0x49 => {
+            // switch Rind table: count, default, count * addr
+            let table: usize = target as usize;
+            let mut res: Vec<(u64, EdgeKind)> = Vec::new();
+            if let (Some(count), Some(default)) = (read_u64(code, table), read_u64(code, table + 8)) {
+                res.push((default, EdgeKind::Default));
+                for i in 0..(count as usize) {
+                    match read_u64(code, table + 16 + i * 8) {
+                        Some(a) => res.push((a, EdgeKind::Case(i))),
+                        None => break,
+                    }
+                }
+            }
+            (res, true)
+        }
+        0x90 => {
+            let mut res: Vec<(u64, EdgeKind)> = vec![(next, EdgeKind::Fallthrough)];
+            if let Some(callee) = funcs.get(target as usize) {
+                res.push((*callee, EdgeKind::Call));
+            }
+            (res, true)
+        }
+        _ => (vec![(next, EdgeKind::Fallthrough)], false),
+    };
+    Some(Decoded { text, size, succs, ends_block })
+}
+
+/// Builds the Graphviz description of the image's control flow
+/// Instructions reachable from the entry and the function table, split into
+/// basic blocks
+pub struct Blocks {
+    pub instrs: BTreeMap<u64, Decoded>,
+    pub blocks: BTreeMap<u64, Vec<u64>>, // leader -> instr addrs
+    pub bad: BTreeSet<u64>,              // targets that don't decode
+}
+
+pub fn find_blocks(img: &CfgImage) -> Blocks {
+    let ops: HashMap<u16, OpInfo> = opcode_table();
+
+    // decode everything reachable
+    let mut instrs: BTreeMap<u64, Decoded> = BTreeMap::new();
+    let mut bad: BTreeSet<u64> = BTreeSet::new(); // targets that don't decode
+    let mut leaders: BTreeSet<u64> = img.funcs.iter().copied().collect();
+    leaders.insert(img.entry);
+    let mut work: Vec<u64> = leaders.iter().copied().collect();
+    while let Some(addr) = work.pop() {
+        if instrs.contains_key(&addr) || bad.contains(&addr) {
+            continue;
+        }
+        let d: Decoded = match decode(&img.code, addr, &ops, &img.funcs) {
+            Some(d) => d,
+            None => {
+                bad.insert(addr);
+                continue;
+            }
+        };
+        for (succ, kind) in &d.succs {
+            if d.ends_block || *kind != EdgeKind::Fallthrough {
+                leaders.insert(*succ);
+            }
+            work.push(*succ);
+        }
+        instrs.insert(addr, d);
+    }
+
+    // basic blocks: leader -> instruction addrs
+    let mut blocks: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    for &leader in &leaders {
+        let mut addr: u64 = leader;
+        let mut body: Vec<u64> = Vec::new();
+        while let Some(d) = instrs.get(&addr) {
+            body.push(addr);
+            if d.ends_block {
+                break;
+            }
+            addr += d.size as u64;
+            if leaders.contains(&addr) {
+                break;
+            }
+        }
+        if !body.is_empty() {
+            blocks.insert(leader, body);
+        }
+    }
+    Blocks { instrs, blocks, bad }
+}
+
+pub fn cfg_dot(img: &CfgImage) -> String {
+    let mut roots: Vec<(u64, String)> = vec![(img.entry, "entry".to_string())];
+    for (ind, addr) in img.funcs.iter().enumerate() {
+        let name: String = match img.func_names.get(&ind) {
+            Some(n) => format!("func {}", n),
+            None => format!("func #{}", ind),
+        };
+        roots.push((*addr, name));
+    }
+    let Blocks { instrs, blocks, bad } = find_blocks(img);
+
+    let block_succs = |leader: &u64| -> Vec<(u64, EdgeKind)> {
+        let last: &u64 = blocks[leader].last().unwrap();
+        let d: &Decoded = &instrs[last];
+        match d.ends_block {
+            true => d.succs.clone(),
+            false => vec![(last + d.size as u64, EdgeKind::Fallthrough)],
+        }
+    };
+
+    // every block belongs to the first function that reaches it without calls
+    let mut owner: BTreeMap<u64, usize> = BTreeMap::new();
+    for (fi, (root, _)) in roots.iter().enumerate() {
+        let mut work: Vec<u64> = vec![*root];
+        while let Some(b) = work.pop() {
+            if !blocks.contains_key(&b) || owner.contains_key(&b) {
+                continue;
+            }
+            owner.insert(b, fi);
+            for (succ, kind) in block_succs(&b) {
+                if kind != EdgeKind::Call {
+                    work.push(succ);
+                }
+            }
+        }
+    }
+
+    let lines: HashMap<u64, u32> = img.lines.iter().copied().collect();
+    let mut res: String = String::new();
+    res += "digraph cfg {\n";
+    res += "    node [shape=box fontname=\"monospace\"];\n";
+    for (fi, (_, name)) in roots.iter().enumerate() {
+        let owned: Vec<&u64> = owner.iter().filter(|(_, f)| **f == fi).map(|(b, _)| b).collect();
+        if owned.is_empty() {
+            continue;
+        }
+        res += &format!("    subgraph cluster_{} {{\n        label=\"{}\";\n", fi, name);
+        for leader in owned {
+            let mut label: String = match lines.get(leader) {
+                Some(line) => format!("{:#06x} (line {})\\l", leader, line),
+                None => format!("{:#06x}\\l", leader),
+            };
+            for addr in &blocks[leader] {
+                label += &format!("  {}\\l", instrs[addr].text);
+            }
+            res += &format!("        b{:x} [label=\"{}\"];\n", leader, label);
+        }
+        res += "    }\n";
+    }
+    for addr in &bad {
+        res += &format!("    b{:x} [label=\"{:#06x}\\lundecodable\\l\" color=red];\n", addr, addr);
+    }
+    for leader in blocks.keys() {
+        for (succ, kind) in block_succs(leader) {
+            let style: &str = match kind {
+                EdgeKind::Call => " style=dashed",
+                _ => "",
+            };
+            res += &format!("    b{:x} -> b{:x} [label=\"{}\"{}];\n", leader, succ, kind.label(), style);
+        }
+    }
+    res += "}\n";
+    res
+}
+
+/// Writes the graph of `img` into `out_path`
+pub fn emit_cfg(img: &CfgImage, out_path: &str) -> Result<(), String> {
+    fs::write(out_path, cfg_dot(img)).map_err(|e| e.to_string())
+}
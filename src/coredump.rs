@@ -0,0 +1,418 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    exceptions::Exception,
+    misclib::RegTFromU32,
+    registers::Register,
+    vm::{RegTypes, VM},
+};
+
+// Core dumps (`--coredump_exit`, shadow stack and recursion faults) keep the
+// whole machine state: registers with their types, flags, pending
+// exceptions, main memory, the data stack with slot types, call frames with
+// locals and callee-saved registers, heap bytes and blocks, the GC object
+// table and heap references. Every number is big endian no matter the host,
+// a dump can be inspected on any machine with `--inspect-dump=file`.
+//
+// Layout: DUMP_MAGIC, u16 version, then the sections in the order of
+// CoreDump fields. Sequences are an u64 count followed by their items,
+// Option<usize> is an u64 with u64::MAX for None.
+
+pub const DUMP_MAGIC: &[u8; 6] = b"VXDUMP";
+pub const DUMP_VERSION: u16 = 2;
+const NONE_U64: u64 = u64::MAX;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpFrame {
+    pub retaddr: u64,
+    pub func: Option<usize>,
+    pub checked: bool,
+    pub locals: Vec<(u64, RegTypes)>,
+    pub saved: Vec<(usize, Register, RegTypes)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpBlock {
+    pub start: u64,
+    pub last: u64,
+    pub id: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpGcObject {
+    pub ptr: u64,
+    pub id: u64,
+    pub marked: bool,
+}
+
+/// Machine state read back from a dump
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoreDump {
+    pub ip: u64,
+    pub flags: [u8; 4],
+    pub registers: Vec<(Register, RegTypes)>,
+    pub exceptions: Vec<u64>, // exception codes, lowest first
+    pub data_base: u64,
+    pub data_size: u64,
+    pub memory: Vec<u8>,
+    pub stack: Vec<(u64, RegTypes)>,
+    pub call_stack: Vec<DumpFrame>,
+    pub heap: Vec<u8>,
+    pub heap_blocks: Vec<DumpBlock>,
+    pub gc_objects: Vec<DumpGcObject>,
+    pub refs: Vec<(u64, Vec<u64>)>, // source -> targets, sorted
+}
+
+struct Writer {
+    out: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.out.push(v);
+    }
+    fn u64(&mut self, v: u64) {
+        self.out.extend(v.to_be_bytes());
+    }
+    fn opt(&mut self, v: Option<usize>) {
+        self.u64(v.map(|v| v as u64).unwrap_or(NONE_U64));
+    }
+    fn bytes(&mut self, v: &[u8]) {
+        self.u64(v.len() as u64);
+        self.out.extend(v);
+    }
+    fn typed(&mut self, val: u64, t: RegTypes) {
+        self.u8(t as u8);
+        self.u64(val);
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], String> {
+        match self.data.get(self.pos..self.pos.saturating_add(n)) {
+            Some(s) => {
+                self.pos += n;
+                Ok(s)
+            }
+            None => Err(format!("dump is truncated at byte {:#x}", self.pos)),
+        }
+    }
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    fn opt(&mut self) -> Result<Option<usize>, String> {
+        Ok(match self.u64()? {
+            NONE_U64 => None,
+            v => Some(v as usize),
+        })
+    }
+    /// Count of a sequence, bounded by the bytes left so a corrupt count can't allocate
+    fn count(&mut self, item_min: usize) -> Result<usize, String> {
+        let at: usize = self.pos;
+        let n: u64 = self.u64()?;
+        match n.checked_mul(item_min as u64) {
+            Some(len) if len <= (self.data.len() - self.pos) as u64 => Ok(n as usize),
+            _ => Err(format!("dump has a bad count {} at byte {:#x}", n, at)),
+        }
+    }
+    fn bytes(&mut self) -> Result<Vec<u8>, String> {
+        let n: usize = self.count(1)?;
+        Ok(self.take(n)?.to_vec())
+    }
+    fn reg_type(&mut self) -> Result<RegTypes, String> {
+        let at: usize = self.pos;
+        let t: u8 = self.u8()?;
+        RegTFromU32(t as u32).ok_or(format!("dump has an unknown register type {} at byte {:#x}", t, at))
+    }
+    fn typed(&mut self) -> Result<(u64, RegTypes), String> {
+        let t: RegTypes = self.reg_type()?;
+        Ok((self.u64()?, t))
+    }
+}
+
+/// Serializes the VM state
+pub fn encode(vm: &VM) -> Vec<u8> {
+    let mut w = Writer { out: Vec::new() };
+    w.out.extend(DUMP_MAGIC);
+    w.out.extend(DUMP_VERSION.to_be_bytes());
+
+    w.u64(vm.ip as u64);
+    w.out.extend(vm.flags);
+    w.u64(vm.registers.len() as u64);
+    for (reg, t) in vm.registers.iter().zip(vm.reg_types.iter()) {
+        w.typed(reg.as_u64_bitwise(), *t);
+    }
+    w.u64(vm.exceptions_active.len() as u64);
+    for exc in vm.exceptions_active.iter() {
+        w.u64(exc.code());
+    }
+    let (data_base, data_size) = vm.data_range();
+    w.u64(data_base);
+    w.u64(data_size);
+    w.bytes(&vm.memory);
+
+    w.u64(vm.stack.stack.len() as u64);
+    for slot in &vm.stack.stack {
+        w.typed(slot.val, slot.ftype);
+    }
+    w.u64(vm.call_stack.stack.len() as u64);
+    for frame in &vm.call_stack.stack {
+        w.u64(frame.retaddr());
+        w.opt(frame.func());
+        w.u8(frame.checked() as u8);
+        w.u64(frame.locals().len() as u64);
+        for (val, t) in frame.locals() {
+            w.typed(*val, *t);
+        }
+        w.u64(frame.saved().len() as u64);
+        for (ind, reg, t) in frame.saved() {
+            w.u8(*ind as u8);
+            w.typed(reg.as_u64_bitwise(), *t);
+        }
+    }
+
+    w.bytes(&vm.heap.heap);
+    let mut blocks: Vec<_> = vm.heap.allocated.iter().collect();
+    blocks.sort_by_key(|b| b.start_byte);
+    w.u64(blocks.len() as u64);
+    for block in blocks {
+        w.u64(block.start_byte as u64);
+        w.u64(block.last_byte as u64);
+        w.u64(block.id);
+    }
+    w.u64(vm.gc.objects.len() as u64);
+    for obj in &vm.gc.objects {
+        w.u64(obj.heap_ptr());
+        w.u64(obj.id());
+        w.u8(obj.marked() as u8);
+    }
+    let refs: Vec<(u64, Vec<u64>)> = sorted_refs(&vm.heap.saved_refs);
+    w.u64(refs.len() as u64);
+    for (src, tgts) in refs {
+        w.u64(src);
+        w.u64(tgts.len() as u64);
+        for tgt in tgts {
+            w.u64(tgt);
+        }
+    }
+    w.out
+}
+
+fn sorted_refs(refs: &HashMap<u64, HashSet<u64>>) -> Vec<(u64, Vec<u64>)> {
+    let mut res: Vec<(u64, Vec<u64>)> = refs
+        .iter()
+        .map(|(src, tgts)| {
+            let mut tgts: Vec<u64> = tgts.iter().cloned().collect();
+            tgts.sort();
+            (*src, tgts)
+        })
+        .collect();
+    res.sort();
+    res
+}
+
+impl CoreDump {
+    pub fn decode(data: &[u8]) -> Result<CoreDump, String> {
+        let mut r = Reader { data, pos: 0 };
+        if r.take(DUMP_MAGIC.len()).ok() != Some(&DUMP_MAGIC[..]) {
+            return Err("not a voxvm core dump".to_string());
+        }
+        let version: u16 = u16::from_be_bytes(r.take(2)?.try_into().unwrap());
+        if version != DUMP_VERSION {
+            return Err(format!("dump version {} is not supported, expected {}", version, DUMP_VERSION));
+        }
+
+        let ip: u64 = r.u64()?;
+        let flags: [u8; 4] = r.take(4)?.try_into().unwrap();
+        let mut registers: Vec<(Register, RegTypes)> = Vec::new();
+        for _ in 0..r.count(9)? {
+            let (bits, t) = r.typed()?;
+            registers.push((Register::from_u64_bits(bits, t), t));
+        }
+        let mut exceptions: Vec<u64> = Vec::new();
+        for _ in 0..r.count(8)? {
+            exceptions.push(r.u64()?);
+        }
+        let data_base: u64 = r.u64()?;
+        let data_size: u64 = r.u64()?;
+        let memory: Vec<u8> = r.bytes()?;
+
+        let mut stack: Vec<(u64, RegTypes)> = Vec::new();
+        for _ in 0..r.count(9)? {
+            stack.push(r.typed()?);
+        }
+        let mut call_stack: Vec<DumpFrame> = Vec::new();
+        for _ in 0..r.count(33)? {
+            let retaddr: u64 = r.u64()?;
+            let func: Option<usize> = r.opt()?;
+            let checked: bool = r.u8()? != 0;
+            let mut locals: Vec<(u64, RegTypes)> = Vec::new();
+            for _ in 0..r.count(9)? {
+                locals.push(r.typed()?);
+            }
+            let mut saved: Vec<(usize, Register, RegTypes)> = Vec::new();
+            for _ in 0..r.count(10)? {
+                let ind: usize = r.u8()? as usize;
+                let (bits, t) = r.typed()?;
+                saved.push((ind, Register::from_u64_bits(bits, t), t));
+            }
+            call_stack.push(DumpFrame {
+                retaddr,
+                func,
+                checked,
+                locals,
+                saved,
+            });
+        }
+
+        let heap: Vec<u8> = r.bytes()?;
+        let mut heap_blocks: Vec<DumpBlock> = Vec::new();
+        for _ in 0..r.count(24)? {
+            heap_blocks.push(DumpBlock {
+                start: r.u64()?,
+                last: r.u64()?,
+                id: r.u64()?,
+            });
+        }
+        let mut gc_objects: Vec<DumpGcObject> = Vec::new();
+        for _ in 0..r.count(17)? {
+            gc_objects.push(DumpGcObject {
+                ptr: r.u64()?,
+                id: r.u64()?,
+                marked: r.u8()? != 0,
+            });
+        }
+        let mut refs: Vec<(u64, Vec<u64>)> = Vec::new();
+        for _ in 0..r.count(16)? {
+            let src: u64 = r.u64()?;
+            let mut tgts: Vec<u64> = Vec::new();
+            for _ in 0..r.count(8)? {
+                tgts.push(r.u64()?);
+            }
+            refs.push((src, tgts));
+        }
+        if r.pos != data.len() {
+            return Err(format!("{} trailing bytes after the dump", data.len() - r.pos));
+        }
+        Ok(CoreDump {
+            ip,
+            flags,
+            registers,
+            exceptions,
+            data_base,
+            data_size,
+            memory,
+            stack,
+            call_stack,
+            heap,
+            heap_blocks,
+            gc_objects,
+            refs,
+        })
+    }
+
+    /// Human readable listing, `--inspect-dump`
+    pub fn describe(&self) -> String {
+        let mut res: String = String::new();
+        res.push_str(&format!("ip: {:#x}\n", self.ip));
+        res.push_str(&format!(
+            "flags: of={} zf={} nf={} cf={}\n",
+            self.flags[0], self.flags[1], self.flags[2], self.flags[3]
+        ));
+        for (ind, (reg, _)) in self.registers.iter().enumerate() {
+            res.push_str(&format!("r{}: {:?}\n", ind, reg));
+        }
+        let excs: Vec<String> = self
+            .exceptions
+            .iter()
+            .map(|c| match Exception::from_code(*c) {
+                Some(e) => format!("{:?}", e),
+                None => format!("{:#x}", c),
+            })
+            .collect();
+        res.push_str(&format!("exceptions: [{}]\n", excs.join(", ")));
+        res.push_str(&format!(
+            "memory: {} bytes, data segment {:#x}+{}\n",
+            self.memory.len(),
+            self.data_base,
+            self.data_size
+        ));
+
+        res.push_str(&format!("stack slots: {}\n", self.stack.len()));
+        for (ind, (val, t)) in self.stack.iter().enumerate() {
+            res.push_str(&format!("  {}: {:?}\n", ind, Register::from_u64_bits(*val, *t)));
+        }
+        res.push_str(&format!("call frames: {}\n", self.call_stack.len()));
+        for (ind, frame) in self.call_stack.iter().enumerate() {
+            let func: String = match frame.func {
+                Some(f) => format!("func {}", f),
+                None => "no func".to_string(),
+            };
+            res.push_str(&format!("  {}: ret {:#x}, {}", ind, frame.retaddr, func));
+            if frame.checked {
+                res.push_str(", checked");
+            }
+            if !frame.locals.is_empty() {
+                let locals: Vec<Register> = frame.locals.iter().map(|(v, t)| Register::from_u64_bits(*v, *t)).collect();
+                res.push_str(&format!(", locals {:?}", locals));
+            }
+            for (reg, val, _) in &frame.saved {
+                res.push_str(&format!(", saved r{}={:?}", reg, val));
+            }
+            res.push('\n');
+        }
+
+        res.push_str(&format!("heap blocks: {}, {} bytes backing\n", self.heap_blocks.len(), self.heap.len()));
+        for block in &self.heap_blocks {
+            let content: Vec<String> = (block.start..block.last)
+                .map(|i| format!("{:02x}", self.heap.get(i as usize).cloned().unwrap_or(0)))
+                .collect();
+            res.push_str(&format!(
+                "  {:#x}+{} #{}: {}\n",
+                block.start,
+                block.last - block.start,
+                block.id,
+                content.join("")
+            ));
+        }
+        res.push_str(&format!("gc objects: {}\n", self.gc_objects.len()));
+        for obj in &self.gc_objects {
+            let mark: &str = if obj.marked { ", marked" } else { "" };
+            res.push_str(&format!("  {:#x} #{}{}\n", obj.ptr, obj.id, mark));
+        }
+        res.push_str(&format!("heap refs: {}\n", self.refs.len()));
+        for (src, tgts) in &self.refs {
+            let tgts: Vec<String> = tgts.iter().map(|t| format!("{:#x}", t)).collect();
+            res.push_str(&format!("  {:#x} -> {}\n", src, tgts.join(", ")));
+        }
+        res
+    }
+}
+
+pub fn inspect_dump_cli(path: &str) -> i32 {
+    let data: Vec<u8> = match std::fs::read(path) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("ERROR: Reading {}: {}", path, e);
+            return 1;
+        }
+    };
+    match CoreDump::decode(&data) {
+        Ok(dump) => {
+            print!("{}", dump.describe());
+            0
+        }
+        Err(e) => {
+            eprintln!("ERROR: {}: {}", path, e);
+            1
+        }
+    }
+}
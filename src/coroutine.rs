@@ -0,0 +1,288 @@
+use crate::{
+    callstack::CallStack,
+    exceptions::Exception,
+    handles::{HandleErr, HandleTable},
+    memcap::{mem_reserve, CALL_FRAME_BYTES},
+    misclib::show_runtime_err,
+    registers::Register,
+    scratch::Scratch,
+    stack::VMStack,
+    vm::{RegTypes, VM},
+};
+
+// Coroutines: `cocreate` makes one from a function index, `coresume` runs it
+// until it executes `coyield` or returns from that function, `costatus` tells
+// where it is. Every coroutine owns its data and call stacks and scratch
+// buffers, registers, the heap and the data segment are shared: values go
+// back and forth in registers, state that has to survive a yield lives on
+// the coroutine stack.
+// `coslice` gives a coroutine a budget of instructions per resume, when it
+// runs out the coroutine is switched back to its resumer as if it yielded,
+// so a guest scheduler can round-robin coroutines that never yield.
+// A coroutine that returned frees its slot, its handle then reads as dead.
+
+/// Return address of a coroutine function, returning there finishes it
+pub const CORO_RETADDR: u64 = u64::MAX - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u64)]
+pub enum CoStatus {
+    Suspended = 0, // created or yielded, can be resumed
+    Running = 1,
+    Normal = 2, // resumed another coroutine and waits for it
+    Dead = 3,
+}
+
+/// Execution state swapped in and out of the VM on every switch
+#[derive(Debug)]
+struct CoContext {
+    ip: usize,
+    stack: VMStack,
+    call_stack: CallStack,
+    shadow: Option<Vec<u64>>,
+    scratch: Scratch,
+    budget: Option<u64>, // instructions left in the current slice
+}
+
+impl CoContext {
+    fn empty() -> CoContext {
+        CoContext {
+            ip: 0,
+            stack: VMStack::new(0),
+            call_stack: CallStack::new(),
+            shadow: None,
+            scratch: Scratch::new(),
+            budget: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Coroutine {
+    status: CoStatus,
+    // own context while suspended, the resumer's one while it runs
+    ctx: CoContext,
+    slice: u64, // instructions per resume, 0 is unlimited
+}
+
+#[derive(Debug, Default)]
+pub struct Coroutines {
+    table: HandleTable<Coroutine>,
+    chain: Vec<u64>, // resumed coroutines, the running one is last
+    pub budget: Option<u64>, // of the running coroutine
+}
+
+impl Coroutines {
+    pub fn new() -> Coroutines {
+        Coroutines::default()
+    }
+
+    /// Data stack slots and call frames held by switched out contexts
+    pub fn held(&self) -> (usize, usize) {
+        self.table.iter().fold((0, 0), |(slots, frames), co| {
+            (slots + co.ctx.stack.stack.len(), frames + co.ctx.call_stack.stack.len())
+        })
+    }
+
+    /// Scratch arena bytes held by switched out contexts
+    pub fn scratch_bytes(&self) -> u64 {
+        self.table.iter().map(|co| co.ctx.scratch.used_bytes()).sum()
+    }
+
+    /// Heap pointers on switched out data stacks and local slots, GC roots
+    pub fn stack_refs(&self) -> Vec<u64> {
+        self.table
+            .iter()
+            .flat_map(|co| {
+                let slots = co.ctx.stack.stack.iter().map(|slot| (slot.val, slot.ftype));
+                slots.chain(co.ctx.call_stack.all_locals().copied())
+            })
+            .filter(|(_, t)| *t == RegTypes::address)
+            .map(|(val, _)| val)
+            .collect()
+    }
+}
+
+fn coro_fault(vm: &mut VM, msg: &str) {
+    show_runtime_err(vm, msg);
+    vm.exceptions_active.push(Exception::CoroutineFault);
+}
+
+fn handle_desc(err: HandleErr, handle: u64) -> String {
+    match err {
+        HandleErr::Invalid => format!("Invalid coroutine handle {:#x}", handle),
+        HandleErr::Stale => format!("Coroutine {:#x} is dead", handle),
+    }
+}
+
+/// Exchanges the VM execution state with `ctx`
+fn swap_ctx(vm: &mut VM, ctx: &mut CoContext) {
+    std::mem::swap(&mut vm.ip, &mut ctx.ip);
+    std::mem::swap(&mut vm.stack, &mut ctx.stack);
+    std::mem::swap(&mut vm.call_stack, &mut ctx.call_stack);
+    std::mem::swap(&mut vm.shadow_stack, &mut ctx.shadow);
+    std::mem::swap(&mut vm.scratch, &mut ctx.scratch);
+    std::mem::swap(&mut vm.coros.budget, &mut ctx.budget);
+}
+
+/// Switches from the running coroutine back to its resumer
+fn switch_out(vm: &mut VM, status: CoStatus) {
+    let handle: u64 = vm.coros.chain.pop().unwrap();
+    let mut ctx: CoContext = match vm.coros.table.get_mut(handle) {
+        Ok(co) => std::mem::replace(&mut co.ctx, CoContext::empty()),
+        Err(_) => return,
+    };
+    swap_ctx(vm, &mut ctx);
+    match status {
+        CoStatus::Dead => {
+            let _ = vm.coros.table.remove(handle);
+        }
+        _ => {
+            let co: &mut Coroutine = vm.coros.table.get_mut(handle).unwrap();
+            co.ctx = ctx;
+            co.status = status;
+        }
+    }
+    if let Some(resumer) = vm.coros.chain.last() {
+        vm.coros.table.get_mut(*resumer).unwrap().status = CoStatus::Running;
+    }
+}
+
+/// Returning to CORO_RETADDR: the coroutine is done, its resumer continues
+pub fn coro_finish(vm: &mut VM) {
+    match vm.coros.chain.is_empty() {
+        true => coro_fault(vm, "Returned to a coroutine exit outside of a coroutine"),
+        false => switch_out(vm, CoStatus::Dead),
+    }
+}
+
+/// Spends one instruction of the running coroutine's slice. Switches back to
+/// the resumer before the instruction when the slice is over
+pub fn slice_tick(vm: &mut VM) {
+    match vm.coros.budget {
+        Some(0) => switch_out(vm, CoStatus::Suspended),
+        Some(left) => vm.coros.budget = Some(left - 1),
+        None => {}
+    }
+}
+
+pub fn op_cocreate(vm: &mut VM) {
+    // 0x94, size: 3
+    // cocreate Rdest Rfunc - coroutine of the function with index in Rfunc,
+    // suspended at its first instruction. Rdest = coroutine handle
+    let r_dest_ind: usize = vm.memory[vm.ip + 1] as usize;
+    let r_func_ind: usize = vm.memory[vm.ip + 2] as usize;
+    let ind: usize = vm.registers[r_func_ind].as_u64() as usize;
+
+    let addr: usize = match vm.func_table.get(ind) {
+        Some(v) => *v as usize,
+        None => {
+            coro_fault(vm, &format!("cocreate: no function with index {}", ind));
+            vm.ip += 3;
+            return;
+        }
+    };
+    if !mem_reserve(vm, CALL_FRAME_BYTES) {
+        vm.ip += 3;
+        return;
+    }
+    let mut call_stack: CallStack = CallStack::new();
+    call_stack.push_saved(CORO_RETADDR, ind, Vec::new());
+    let ctx = CoContext {
+        ip: addr,
+        stack: VMStack::new(0),
+        call_stack,
+        shadow: vm.shadow_stack.as_ref().map(|_| vec![CORO_RETADDR]),
+        scratch: Scratch::new(),
+        budget: None,
+    };
+    let handle: u64 = vm.coros.table.insert(Coroutine {
+        status: CoStatus::Suspended,
+        ctx,
+        slice: 0,
+    });
+    vm.registers[r_dest_ind] = Register::uint(handle);
+    vm.reg_types[r_dest_ind] = RegTypes::uint64;
+    vm.ip += 3;
+}
+
+pub fn op_coresume(vm: &mut VM) {
+    // 0x95, size: 2
+    // coresume Rco - runs the coroutine until it yields or returns,
+    // then continues after this instruction
+    let r_co_ind: usize = vm.memory[vm.ip + 1] as usize;
+    let handle: u64 = vm.registers[r_co_ind].as_u64();
+    let (status, slice) = match vm.coros.table.get(handle) {
+        Ok(co) => (co.status, co.slice),
+        Err(e) => {
+            coro_fault(vm, &format!("coresume: {}", handle_desc(e, handle)));
+            vm.ip += 2;
+            return;
+        }
+    };
+    if status != CoStatus::Suspended {
+        coro_fault(vm, &format!("coresume: coroutine {:#x} is already running", handle));
+        vm.ip += 2;
+        return;
+    }
+    vm.ip += 2; // where the resumer continues
+
+    if let Some(resumer) = vm.coros.chain.last() {
+        vm.coros.table.get_mut(*resumer).unwrap().status = CoStatus::Normal;
+    }
+    let co: &mut Coroutine = vm.coros.table.get_mut(handle).unwrap();
+    co.status = CoStatus::Running;
+    let mut ctx: CoContext = std::mem::replace(&mut co.ctx, CoContext::empty());
+    ctx.budget = (slice > 0).then_some(slice);
+    swap_ctx(vm, &mut ctx);
+    vm.coros.table.get_mut(handle).unwrap().ctx = ctx;
+    vm.coros.chain.push(handle);
+}
+
+pub fn op_coyield(vm: &mut VM) {
+    // 0x96, size: 1
+    // coyield - suspends the running coroutine, its resumer continues
+    if vm.coros.chain.is_empty() {
+        coro_fault(vm, "coyield outside of a coroutine");
+        vm.ip += 1;
+        return;
+    }
+    vm.ip += 1;
+    switch_out(vm, CoStatus::Suspended);
+}
+
+pub fn op_costatus(vm: &mut VM) {
+    // 0x97, size: 3
+    // costatus Rdest Rco - Rdest = 0 suspended, 1 running,
+    // 2 normal (resumed another coroutine), 3 dead
+    let r_dest_ind: usize = vm.memory[vm.ip + 1] as usize;
+    let r_co_ind: usize = vm.memory[vm.ip + 2] as usize;
+    let handle: u64 = vm.registers[r_co_ind].as_u64();
+    let status: CoStatus = match vm.coros.table.get(handle) {
+        Ok(co) => co.status,
+        Err(HandleErr::Stale) => CoStatus::Dead,
+        Err(e) => {
+            coro_fault(vm, &format!("costatus: {}", handle_desc(e, handle)));
+            vm.ip += 3;
+            return;
+        }
+    };
+    vm.registers[r_dest_ind] = Register::uint(status as u64);
+    vm.reg_types[r_dest_ind] = RegTypes::uint64;
+    vm.ip += 3;
+}
+
+pub fn op_coslice(vm: &mut VM) {
+    // 0x98, size: 3
+    // coslice Rco Rn - the coroutine runs at most Rn instructions per resume,
+    // 0 lets it run until it yields. Applies from its next resume
+    let r_co_ind: usize = vm.memory[vm.ip + 1] as usize;
+    let r_n_ind: usize = vm.memory[vm.ip + 2] as usize;
+    let handle: u64 = vm.registers[r_co_ind].as_u64();
+    let slice: u64 = vm.registers[r_n_ind].as_u64();
+    match vm.coros.table.get_mut(handle) {
+        Ok(co) => co.slice = slice,
+        Err(e) => coro_fault(vm, &format!("coslice: {}", handle_desc(e, handle))),
+    }
+    vm.ip += 3;
+}
@@ -0,0 +1,92 @@
+use std::{collections::HashMap, fs};
+
+// Bytecode execution coverage.
+// The file is plain text: a header line, then `addr hits` (hex addr)
+// for every executed instruction, sorted by address.
+const COV_HEADER: &str = "# voxvm coverage";
+
+#[derive(Debug)]
+pub struct Coverage {
+    pub hits: HashMap<usize, u64>, // instr addr -> times executed
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            hits: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, addr: usize) {
+        *self.hits.entry(addr).or_insert(0) += 1;
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let mut addrs: Vec<(&usize, &u64)> = self.hits.iter().collect();
+        addrs.sort();
+        let mut res: String = format!("{}\n", COV_HEADER);
+        for (addr, hits) in addrs {
+            res.push_str(&format!("{:#x} {}\n", addr, hits));
+        }
+        fs::write(path, res)
+    }
+
+    pub fn load(path: &str) -> Result<Coverage, String> {
+        let text: String = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut res: Coverage = Coverage::new();
+        for (num, line) in text.lines().enumerate() {
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let parsed = line.split_once(' ').and_then(|(addr, hits)| {
+                let addr = usize::from_str_radix(addr.trim_start_matches("0x"), 16).ok()?;
+                Some((addr, hits.trim().parse::<u64>().ok()?))
+            });
+            match parsed {
+                Some((addr, hits)) => {
+                    res.hits.insert(addr, hits);
+                }
+                None => return Err(format!("{}: invalid coverage line '{}'", num + 1, line)),
+            }
+        }
+        Ok(res)
+    }
+
+    /// Annotates the source with hit counts using the VVE line table.
+    /// Lines without code are prefixed with `-`, never executed ones with `#####`.
+    pub fn report(&self, line_table: &[(u64, u32)], source: &str) -> String {
+        let mut line_hits: HashMap<u32, Option<u64>> = HashMap::new();
+        for (addr, line) in line_table {
+            let entry = line_hits.entry(*line).or_insert(None);
+            if let Some(hits) = self.hits.get(&(*addr as usize)) {
+                *entry = Some(entry.unwrap_or(0) + hits);
+            }
+        }
+
+        let mut res: String = String::new();
+        let (mut code_lines, mut hit_lines) = (0, 0);
+        for (num, text) in source.lines().enumerate() {
+            let prefix: String = match line_hits.get(&((num + 1) as u32)) {
+                Some(Some(hits)) => {
+                    code_lines += 1;
+                    hit_lines += 1;
+                    hits.to_string()
+                }
+                Some(None) => {
+                    code_lines += 1;
+                    "#####".to_string()
+                }
+                None => "-".to_string(),
+            };
+            res.push_str(&format!("{:>9}: {:>5}: {}\n", prefix, num + 1, text));
+        }
+        res.push_str(&format!("Lines executed: {}/{}\n", hit_lines, code_lines));
+        res
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Coverage {
+        Coverage::new()
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+use crate::{
+    cfgexport::{instr_size_at, op_sizes},
+    misclib::{args_to_i64, args_to_u64},
+    registers::Register,
+    vm::{Dispatch, RegTypes, BLOCK_END, VM},
+};
+
+// Decoded instruction cache (`--decode-cache`). The first time an ip runs,
+// its instruction is decoded into a DecodedOp (register indices, immediates
+// and jump targets read out of the code bytes) and kept in a table indexed
+// by ip, so code that runs again (loops, functions called over and over)
+// skips the operand slicing and byte conversions. Instructions without a
+// decoded form keep a Handler entry and run their handler as usual.
+// Writes to code (only possible with --allow-self-modify) bump
+// `VM::code_writes`, the cache then starts over. Instructions with native
+// hooks stay Handler entries, the hooks wrap their handler.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecodedOp {
+    LoadU { dst: usize, val: u64 },  // uload, uload32
+    LoadI { dst: usize, val: i64 },  // iload, iload32
+    Mov { dst: usize, src: usize },  // movr
+    Arith { opcode: u8, dst: usize, src: usize },
+    Cmp { opcode: u8, a: usize, b: usize },
+    Step { opcode: u8, reg: usize }, // inc, dec
+    Jump { target: usize },
+    Branch { opcode: u8, target: usize },
+    Handler { opcode: u8 },
+}
+
+/// Instruction at `addr` and its size, None if its opcode is unknown or it
+/// runs past the end of `memory`
+pub fn decode_op(memory: &[u8], addr: usize, sizes: &HashMap<u16, usize>) -> Option<(DecodedOp, usize)> {
+    let opcode: u8 = *memory.get(addr)?;
+    let size: usize = instr_size_at(memory, addr, sizes)?;
+    let code: &[u8] = memory.get(addr..(addr + size))?;
+    let reg = |at: usize| code[at] as usize;
+    let op: DecodedOp = match opcode {
+        0x10 => DecodedOp::LoadU { dst: reg(1), val: args_to_u64(&code[2..10]) },
+        0x1b => DecodedOp::LoadU { dst: reg(1), val: u32::from_be_bytes(code[2..6].try_into().unwrap()) as u64 },
+        0x20 => DecodedOp::LoadI { dst: reg(1), val: args_to_i64(&code[2..10]) },
+        0x2d => DecodedOp::LoadI { dst: reg(1), val: i32::from_be_bytes(code[2..6].try_into().unwrap()) as i64 },
+        0x60 => DecodedOp::Mov { dst: reg(1), src: reg(2) },
+        0x11..=0x13 | 0x21..=0x23 => DecodedOp::Arith { opcode, dst: reg(1), src: reg(2) },
+        0x16 | 0x26 => DecodedOp::Cmp { opcode, a: reg(1), b: reg(2) },
+        0x19 | 0x1a | 0x2b | 0x2c => DecodedOp::Step { opcode, reg: reg(1) },
+        0x40 => DecodedOp::Jump { target: args_to_u64(&code[1..9]) as usize },
+        0x41..=0x45 | 0x48 => DecodedOp::Branch { opcode, target: args_to_u64(&code[1..9]) as usize },
+        _ => DecodedOp::Handler { opcode },
+    };
+    Some((op, size))
+}
+
+/// Register and flag effects of the ops that neither jump nor need a handler
+#[inline(always)]
+pub fn apply(vm: &mut VM, op: DecodedOp) {
+    match op {
+        DecodedOp::LoadU { dst, val } => {
+            vm.registers[dst] = Register::uint(val);
+            vm.reg_types[dst] = RegTypes::uint64;
+        }
+        DecodedOp::LoadI { dst, val } => {
+            vm.registers[dst] = Register::int(val);
+            vm.reg_types[dst] = RegTypes::int64;
+        }
+        DecodedOp::Mov { dst, src } => {
+            vm.registers[dst] = vm.registers[src];
+            vm.reg_types[dst] = vm.reg_types[src];
+        }
+        DecodedOp::Arith { opcode, dst, src } => vm.arith(opcode, dst, src),
+        DecodedOp::Cmp { opcode, a, b } => vm.compare(opcode, a, b),
+        DecodedOp::Step { opcode, reg } => vm.step_reg(opcode, reg),
+        DecodedOp::Jump { .. } | DecodedOp::Branch { .. } | DecodedOp::Handler { .. } => {}
+    }
+}
+
+/// Executes `op` (`size` bytes long) as the instruction at ip, with the
+/// bookkeeping `VM::exec_instr` does
+#[inline(always)]
+fn exec_decoded(vm: &mut VM, op: DecodedOp, size: usize) {
+    let ip: usize = vm.ip;
+    if let DecodedOp::Handler { opcode } = op {
+        vm.exec_instr(opcode, BLOCK_END);
+        return;
+    }
+    if let Some(cov) = &mut vm.coverage {
+        cov.record(ip);
+    }
+    vm.ip = match op {
+        DecodedOp::Jump { target } => target,
+        DecodedOp::Branch { opcode, target } if vm.jump_taken(opcode) => target,
+        _ => {
+            apply(vm, op);
+            ip + size
+        }
+    };
+    vm.instr_count += 1;
+    vm.track_exceptions(ip);
+}
+
+#[derive(Debug)]
+pub struct DecodeCache {
+    entries: Vec<Option<(DecodedOp, usize)>>, // ip -> op, size
+    code_writes: u64,                          // VM::code_writes the entries are from
+    sizes: HashMap<u16, usize>,
+}
+
+impl DecodeCache {
+    pub fn new() -> DecodeCache {
+        DecodeCache {
+            entries: Vec::new(),
+            code_writes: 0,
+            sizes: op_sizes(),
+        }
+    }
+
+    /// Count of ips with a decoded entry
+    pub fn entry_count(&self) -> usize {
+        self.entries.iter().filter(|e| e.is_some()).count()
+    }
+}
+
+impl Default for DecodeCache {
+    fn default() -> DecodeCache {
+        DecodeCache::new()
+    }
+}
+
+impl Dispatch for DecodeCache {
+    #[inline(always)]
+    fn dispatch(&mut self, vm: &mut VM) -> u64 {
+        if vm.code_writes != self.code_writes {
+            self.entries.clear();
+            self.code_writes = vm.code_writes;
+        }
+        let ip: usize = vm.ip;
+        if ip >= self.entries.len() {
+            self.entries.resize(ip + 1, None);
+        }
+        let (op, size) = match self.entries[ip] {
+            Some(entry) => entry,
+            None => {
+                let opcode: u8 = vm.memory[ip];
+                let entry = match vm.nativesys.hooks_of(opcode) {
+                    // hooks wrap the handler
+                    Some(_) => (DecodedOp::Handler { opcode }, 0),
+                    None => decode_op(&vm.memory, ip, &self.sizes).unwrap_or((DecodedOp::Handler { opcode }, 0)),
+                };
+                self.entries[ip] = Some(entry);
+                entry
+            }
+        };
+        exec_decoded(vm, op, size);
+        1
+    }
+}
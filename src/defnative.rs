@@ -1,12 +1,21 @@
 use rand::Rng;
 
 use crate::{
-    misclib::{bytes_into_string_utf16, show_runtime_err, string_from_straddr, vec16_into_vec8},
+    exceptions::Exception,
+    heap::HeapStats,
+    misclib::{bytes_from_straddr, bytes_into_string_utf16, string_from_straddr, u8_slice_to_u16_vec, vec16_into_vec8},
+    native::NSysError,
+    nativeerr::{native_fault, NativeErrKind, NativeError, NativeSubsys},
     registers::Register,
     vm::{RegTypes, VM},
 };
+use sysinfo::{get_current_pid, ProcessRefreshKind, ProcessesToUpdate, System};
 use std::{char::decode_utf16, io::Write, process::{Command, Stdio}, thread::sleep, time::Duration};
 
+fn std_fault(vm: &mut VM, kind: NativeErrKind, exc: Exception, msg: &str) {
+    native_fault(vm, NativeError::new(NativeSubsys::Std, kind), exc, msg);
+}
+
 pub fn ncall_print(vm: &mut VM) {
     // r1 is rsrc (any type), r2 is stream id (1 for stdout, 2 for stderr),
     // r3 is count bytes to print, if heap addr
@@ -16,15 +25,15 @@ pub fn ncall_print(vm: &mut VM) {
     match rsrc {
         Register::uint(v) => {
             let st: String = v.to_string();
-            print_stream(stream_id, st);
+            print_stream(vm, stream_id, st);
         }
         Register::int(v) => {
             let st: String = v.to_string();
-            print_stream(stream_id, st);
+            print_stream(vm, stream_id, st);
         }
         Register::float(v) => {
             let st: String = v.to_string();
-            print_stream(stream_id, st);
+            print_stream(vm, stream_id, st);
         }
         Register::StrAddr(v) => {
             let st: String = match string_from_straddr(vm, v) {
@@ -34,10 +43,10 @@ pub fn ncall_print(vm: &mut VM) {
                     return;
                 }
             };
-            print_stream(stream_id, st);
+            print_stream(vm, stream_id, st);
         }
         Register::ds_addr(v) => {
-            print_stream(stream_id, format!("VM Data segment address: 0x{:x}", v));
+            print_stream(vm, stream_id, format!("VM Data segment address: 0x{:x}", v));
         }
         Register::address(v) => {
             let count: u64 = vm.registers[3].as_u64();
@@ -45,7 +54,7 @@ pub fn ncall_print(vm: &mut VM) {
                 let bytes = match vm.heap.read(v, count) {
                     Ok(bv) => match bytes_into_string_utf16(&bv) {
                         Some(s) => {
-                            print_stream(stream_id, s);
+                            print_stream(vm, stream_id, s);
                             return;
                         }
                         None => {}
@@ -55,28 +64,18 @@ pub fn ncall_print(vm: &mut VM) {
                     }
                 };
             }
-            print_stream(stream_id, format!("VM Heap address: 0x{:x}", v));
+            print_stream(vm, stream_id, format!("VM Heap address: 0x{:x}", v));
         }
     }
 }
 
-fn print_stream(stream_id: u64, val: String) -> Result<(), ()> {
-    match stream_id {
-        1 => {
-            // stdout
-            println!("{}", val);
-            std::io::stdout().flush();
-        }
-        2 => {
-            // stderr
-            eprintln!("{}", val);
-            std::io::stderr().flush();
-        }
-        other => {
-            return Err(());
-        }
-    }
-    Ok(())
+fn print_stream(vm: &mut VM, stream_id: u64, val: String) -> Result<(), ()> {
+    let sink = match vm.output.stream(stream_id) {
+        Some(s) => s,
+        None => return Err(()),
+    };
+    writeln!(sink, "{}", val).map_err(|_| ())?;
+    sink.flush().map_err(|_| ())
 }
 
 pub fn readin(vm: &mut VM) {
@@ -108,10 +107,9 @@ pub fn readin(vm: &mut VM) {
     match vm.heap.write(to_ptr, bytes[0..end].to_owned()) {
         Ok(()) => {},
         Err(()) => {
-            eprintln!("Runtime error: Heap write");
             vm.registers[0] = Register::uint(0);
-            vm.exceptions_active.push(crate::exceptions::Exception::HeapWriteFault);
-            return;
+            let msg: String = format!("readin: can't write the input at {:#x}", to_ptr);
+            return std_fault(vm, NativeErrKind::HeapFault, Exception::HeapWriteFault, &msg);
         }
     }
     vm.registers[0] = Register::uint(end as u64);
@@ -165,18 +163,15 @@ pub fn runcmd(vm: &mut VM) {
     let bytes = match vm.heap.read(ptr, count) {
         Ok(b) => b,
         Err(()) => {
-            show_runtime_err(vm, "Can't read heap");
-            vm.exceptions_active.push(crate::exceptions::Exception::HeapReadFault);
-            return;
+            let msg: String = format!("runcmd: no {} heap bytes at {:#x}", count, ptr);
+            return std_fault(vm, NativeErrKind::HeapFault, Exception::HeapReadFault, &msg);
         }
     };
 
     let st: String = match bytes_into_string_utf16(&bytes) {
         Some(v) => v,
         None => {
-            show_runtime_err(vm, "Error converting bytes into string");
-            vm.exceptions_active.push(crate::exceptions::Exception::HeapSegmFault);
-            return;
+            return std_fault(vm, NativeErrKind::InvalidInput, Exception::HeapSegmFault, "runcmd: command isn't valid UTF-16");
         }
     };
 
@@ -208,12 +203,228 @@ pub fn runcmd(vm: &mut VM) {
     match vm.heap.write(out_ptr, out_bytes[0..maxc].to_owned()) {
         Ok(()) => {},
         Err(()) => {
-            show_runtime_err(vm, "Error writing stdout into heap");
-            vm.exceptions_active.push(crate::exceptions::Exception::HeapWriteFault);
-            return;
+            let msg: String = format!("runcmd: can't write stdout at {:#x}", out_ptr);
+            return std_fault(vm, NativeErrKind::HeapFault, Exception::HeapWriteFault, &msg);
         }
     }
 
     vm.registers[0] = Register::uint(out_len as u64);
     
 }
+
+/// ncall 0x8
+/// r1 is StrAddr of a data segment string
+/// returns StrAddr of the interned string with the same
+/// content into r0 (or r1 itself if there's none)
+pub fn ncall_dsintern(vm: &mut VM) {
+    let addr: u64 = vm.registers[1].as_u64();
+
+    let content: Vec<u8> = match bytes_from_straddr(vm, addr) {
+        Some(v) => v,
+        None => {
+            let msg: String = format!("dsintern: string address {:#x} is out of bounds", addr);
+            return std_fault(vm, NativeErrKind::HeapFault, Exception::MainSegmFault, &msg);
+        }
+    };
+
+    let res: u64 = vm.interned.lookup(&content).unwrap_or(addr);
+    vm.registers[0] = Register::StrAddr(res);
+    vm.reg_types[0] = RegTypes::StrAddr;
+}
+
+/// ncall 0x9
+/// r1, r2 are StrAddrs
+/// returns 1 into r0 if strings are equal, 0 otherwise.
+/// O(1) if both strings are interned.
+pub fn ncall_streq(vm: &mut VM) {
+    let a: u64 = vm.registers[1].as_u64();
+    let b: u64 = vm.registers[2].as_u64();
+
+    let equal: bool = if vm.interned.contains(a) && vm.interned.contains(b) {
+        a == b
+    } else {
+        match (bytes_from_straddr(vm, a), bytes_from_straddr(vm, b)) {
+            (Some(x), Some(y)) => x == y,
+            _ => {
+                let msg: String = format!("streq: string address {:#x} or {:#x} is out of bounds", a, b);
+                return std_fault(vm, NativeErrKind::HeapFault, Exception::MainSegmFault, &msg);
+            }
+        }
+    };
+
+    vm.registers[0] = Register::uint(equal as u64);
+    vm.reg_types[0] = RegTypes::uint64;
+}
+
+/// ncall 0xA
+/// r1 is heap ptr (may point inside a block)
+/// Pins the block so GC won't collect it while native code holds it.
+/// r0 = 1 on success, 0 if ptr is not allocated.
+pub fn ncall_pin(vm: &mut VM) {
+    let ptr: u64 = vm.registers[1].as_u64();
+    let ok: bool = vm.heap.pin(ptr).is_ok();
+    vm.registers[0] = Register::uint(ok as u64);
+    vm.reg_types[0] = RegTypes::uint64;
+}
+
+/// ncall 0xB
+/// r1 is heap ptr, releases one pin of its block
+/// r0 = 1 on success, 0 if the block wasn't pinned.
+pub fn ncall_unpin(vm: &mut VM) {
+    let ptr: u64 = vm.registers[1].as_u64();
+    let ok: bool = vm.heap.unpin(ptr).is_ok();
+    vm.registers[0] = Register::uint(ok as u64);
+    vm.reg_types[0] = RegTypes::uint64;
+}
+
+/// ncall 0xE
+/// Runs full mark and sweep right away.
+/// r0 = count of collected objects.
+pub fn ncall_gc_collect(vm: &mut VM) {
+    let collected: usize = vm.gc_collect();
+    vm.registers[0] = Register::uint(collected as u64);
+    vm.reg_types[0] = RegTypes::uint64;
+}
+
+/// ncall 0xF
+/// r0 = total heap bytes, r1 = used, r2 = free, r3 = largest free block,
+/// r4 = live blocks, r5 = allocations made, r6 = frees made (incl. GC).
+pub fn ncall_heap_stats(vm: &mut VM) {
+    let stats: HeapStats = vm.heap.stats();
+    let vals: [u64; 7] = [
+        stats.total,
+        stats.used,
+        stats.free,
+        stats.largest_free,
+        stats.live_blocks,
+        stats.alloc_count,
+        stats.free_count,
+    ];
+    for (ind, val) in vals.iter().enumerate() {
+        vm.registers[ind] = Register::uint(*val);
+        vm.reg_types[ind] = RegTypes::uint64;
+    }
+}
+
+/// ncall 0x43
+/// Resource usage for self-monitoring. Host process: r0 = resident memory
+/// in bytes, r1 = virtual memory in bytes, r2 = CPU time in ms (all
+/// threads, may run ahead of the wall clock). This VM: r3 = ms since it
+/// started, r4 = instructions executed, r5 = heap bytes in use. When the
+/// host figures can't be read r0..r2 = 0 and it faults with Unsupported,
+/// the VM ones are set anyway
+pub fn ncall_res_usage(vm: &mut VM) {
+    let vm_vals: [u64; 3] = [
+        vm.clock_start.elapsed().as_millis() as u64,
+        vm.instr_count,
+        vm.heap.stats().used,
+    ];
+    for (ind, val) in vm_vals.iter().enumerate() {
+        vm.registers[ind + 3] = Register::uint(*val);
+        vm.reg_types[ind + 3] = RegTypes::uint64;
+    }
+    let host: Option<[u64; 3]> = get_current_pid().ok().and_then(|pid| {
+        let mut sys: System = System::new();
+        let refresh: ProcessRefreshKind = ProcessRefreshKind::nothing().with_memory().with_cpu();
+        sys.refresh_processes_specifics(ProcessesToUpdate::Some(&[pid]), false, refresh);
+        sys.process(pid).map(|p| [p.memory(), p.virtual_memory(), p.accumulated_cpu_time()])
+    });
+    for (ind, val) in host.unwrap_or_default().iter().enumerate() {
+        vm.registers[ind] = Register::uint(*val);
+        vm.reg_types[ind] = RegTypes::uint64;
+    }
+    if host.is_none() {
+        let err: NativeError = NativeError::new(NativeSubsys::Debug, NativeErrKind::Unsupported);
+        native_fault(vm, err, Exception::NativeFault, "res_usage: can't read this process' usage on the host");
+    }
+}
+
+/// ncall 0xD
+/// r1 is verbosity: 0 - ip, flags and non-empty registers,
+/// 1 - all registers, 2 - all registers and call stack frames.
+/// r2 is max count of stack frames to show (0 for all), top first.
+/// Prints an aligned table to stderr, registers are left untouched.
+pub fn ncall_regdump(vm: &mut VM) {
+    let verbosity: u64 = vm.registers[1].as_u64_bitwise();
+    let frames_max: usize = vm.registers[2].as_u64_bitwise() as usize;
+    let table: String = regdump_table(vm, verbosity, frames_max);
+    let _ = vm.output.stderr.write_all(table.as_bytes());
+    let _ = vm.output.stderr.flush();
+}
+
+fn regdump_table(vm: &VM, verbosity: u64, frames_max: usize) -> String {
+    let mut res: String = String::new();
+    res.push_str(&format!("---- register dump @ ip {:#018x} ----\n", vm.ip));
+    res.push_str(&format!(
+        "flags: of={} zf={} nf={} cf={}\n",
+        vm.flags[0], vm.flags[1], vm.flags[2], vm.flags[3]
+    ));
+    res.push_str(&format!("{:<5} {:<9} {:<18} {}\n", "reg", "type", "raw", "value"));
+    for (ind, reg) in vm.registers.iter().enumerate() {
+        let raw: u64 = reg.as_u64_bitwise();
+        if verbosity == 0 && raw == 0 {
+            continue;
+        }
+        res.push_str(&format!(
+            "{:<5} {:<9} {:#018x} {}\n",
+            format!("r{}", ind),
+            format!("{:?}", vm.reg_types[ind]),
+            raw,
+            reg
+        ));
+    }
+    if verbosity >= 2 {
+        let total: usize = vm.stack.stack.len();
+        let shown: usize = if frames_max == 0 { total } else { frames_max.min(total) };
+        res.push_str(&format!("stack: {} frame(s), showing top {}\n", total, shown));
+        for (depth, frame) in vm.stack.stack.iter().rev().take(shown).enumerate() {
+            res.push_str(&format!(
+                "  #{:<3} {:<9} {:#018x}\n",
+                depth,
+                format!("{:?}", frame.ftype),
+                frame.val
+            ));
+        }
+        let calls: usize = vm.call_stack.stack.len();
+        let shown: usize = if frames_max == 0 { calls } else { frames_max.min(calls) };
+        res.push_str(&format!("call stack: {} frame(s), showing top {}\n", calls, shown));
+        for (depth, frame) in vm.call_stack.stack.iter().rev().take(shown).enumerate() {
+            res.push_str(&format!("  #{:<3} ret {:#018x}\n", depth, frame.retaddr()));
+        }
+    }
+    res
+}
+
+/// ncall 0xD0
+/// r1 is heap ptr to a native config path (utf16, like fopen), r2 its
+/// size in bytes. Loads the config and its library, see
+/// `VM::load_native_config`. Needs --allow-native-load.
+/// r0 = count of ncall codes added, 0 on failure
+pub fn ncall_native_load(vm: &mut VM) {
+    let ptr: u64 = vm.registers[1].as_u64();
+    let count: u64 = vm.registers[2].as_u64();
+    vm.registers[0] = Register::uint(0);
+    vm.reg_types[0] = RegTypes::uint64;
+    if !vm.allow_native_load {
+        let err: NativeError = NativeError::new(NativeSubsys::Native, NativeErrKind::PermissionDenied);
+        return native_fault(vm, err, Exception::NativeFault, "native_load: needs --allow-native-load");
+    }
+    let path: String = match vm.heap.read(ptr, count) {
+        Ok(bytes) => String::from_utf16_lossy(&u8_slice_to_u16_vec(&bytes)),
+        Err(()) => {
+            let err: NativeError = NativeError::new(NativeSubsys::Native, NativeErrKind::HeapFault);
+            return native_fault(vm, err, Exception::HeapReadFault, "native_load: can't read the config path");
+        }
+    };
+    match vm.load_native_config(&path) {
+        Ok(codes) => vm.registers[0] = Register::uint(codes.len() as u64),
+        Err(e) => {
+            let err: NativeError = NativeError::new(NativeSubsys::Native, NativeErrKind::from(&e));
+            let msg: String = match e {
+                NSysError::CodeCollision(code) => format!("native_load: {}: ncall {:#x} is already taken", path, code),
+                other => format!("native_load: {}: {:?}", path, other),
+            };
+            native_fault(vm, err, Exception::NativeFault, &msg);
+        }
+    }
+}
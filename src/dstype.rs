@@ -0,0 +1,189 @@
+use crate::vm::RegTypes;
+
+// Data segment variables are `type byte, u64 payload length, payload`.
+// The type byte is the DsType, CONST_FLAG (never a bit of a type code) marks
+// variables the guest can't write. Narrow integers (u8..i32) are stored in
+// their own width and zero/sign-extended when loaded into a register. Assembler, VM handlers and tooling all go through
+// this table, a new variable type only has to be added here.
+
+pub const CONST_FLAG: u8 = 0x10;
+/// Type byte and length in front of every payload
+pub const DS_HEADER: usize = 1 + 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum DsType {
+    Uint = 0x1,
+    Int = 0x2,
+    Float = 0x3,
+    Str = 0x4, // utf16
+    Ptr = 0x5,
+    UintArr = 0x6,
+    IntArr = 0x7,
+    FloatArr = 0x8,
+    Bytes = 0x9,
+    Uint32Arr = 0xA,
+    Int32Arr = 0xB,
+    Float32Arr = 0xC,
+    U8 = 0x20,
+    I8 = 0x21,
+    U16 = 0x22,
+    I16 = 0x23,
+    U32 = 0x24,
+    I32 = 0x25,
+    U8Arr = 0x28,
+    I8Arr = 0x29,
+    U16Arr = 0x2A,
+    I16Arr = 0x2B,
+}
+
+impl DsType {
+    /// Type of a variable from its type byte, the const flag is ignored
+    pub fn decode(type_byte: u8) -> Option<DsType> {
+        match type_byte & !CONST_FLAG {
+            0x1 => Some(DsType::Uint),
+            0x2 => Some(DsType::Int),
+            0x3 => Some(DsType::Float),
+            0x4 => Some(DsType::Str),
+            0x5 => Some(DsType::Ptr),
+            0x6 => Some(DsType::UintArr),
+            0x7 => Some(DsType::IntArr),
+            0x8 => Some(DsType::FloatArr),
+            0x9 => Some(DsType::Bytes),
+            0xA => Some(DsType::Uint32Arr),
+            0xB => Some(DsType::Int32Arr),
+            0xC => Some(DsType::Float32Arr),
+            0x20 => Some(DsType::U8),
+            0x21 => Some(DsType::I8),
+            0x22 => Some(DsType::U16),
+            0x23 => Some(DsType::I16),
+            0x24 => Some(DsType::U32),
+            0x25 => Some(DsType::I32),
+            0x28 => Some(DsType::U8Arr),
+            0x29 => Some(DsType::I8Arr),
+            0x2A => Some(DsType::U16Arr),
+            0x2B => Some(DsType::I16Arr),
+            _ => None,
+        }
+    }
+
+    pub fn is_const(type_byte: u8) -> bool {
+        (type_byte & CONST_FLAG) != 0
+    }
+
+    pub fn encode(self, is_const: bool) -> u8 {
+        match is_const {
+            true => self as u8 | CONST_FLAG,
+            false => self as u8,
+        }
+    }
+
+    /// voxasm spelling, arrays without their length
+    pub fn name(self) -> &'static str {
+        match self {
+            DsType::Uint => "uint",
+            DsType::Int => "int",
+            DsType::Float => "float",
+            DsType::Str => "str",
+            DsType::Ptr => "ptr",
+            DsType::UintArr => "uint[]",
+            DsType::IntArr => "int[]",
+            DsType::FloatArr => "float[]",
+            DsType::Bytes => "bytes",
+            DsType::Uint32Arr => "uint32[]",
+            DsType::Int32Arr => "int32[]",
+            DsType::Float32Arr => "float32[]",
+            DsType::U8 => "u8",
+            DsType::I8 => "i8",
+            DsType::U16 => "u16",
+            DsType::I16 => "i16",
+            DsType::U32 => "u32",
+            DsType::I32 => "i32",
+            DsType::U8Arr => "u8[]",
+            DsType::I8Arr => "i8[]",
+            DsType::U16Arr => "u16[]",
+            DsType::I16Arr => "i16[]",
+        }
+    }
+
+    /// Variables `dsindex` can address elements of
+    pub fn is_indexable(self) -> bool {
+        !matches!(
+            self,
+            DsType::Uint
+                | DsType::Int
+                | DsType::Float
+                | DsType::Ptr
+                | DsType::U8
+                | DsType::I8
+                | DsType::U16
+                | DsType::I16
+                | DsType::U32
+                | DsType::I32
+        )
+    }
+
+    /// Bytes of one value: a scalar or an array element
+    pub fn elem_width(self) -> usize {
+        match self {
+            DsType::Bytes | DsType::U8 | DsType::I8 | DsType::U8Arr | DsType::I8Arr => 1,
+            DsType::Str | DsType::U16 | DsType::I16 | DsType::U16Arr | DsType::I16Arr => 2,
+            DsType::Uint32Arr | DsType::Int32Arr | DsType::Float32Arr | DsType::U32 | DsType::I32 => 4,
+            _ => 8,
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        self.reg_type() == RegTypes::int64
+    }
+
+    /// One value of this variable parsed from text (`--define`), in memory
+    /// byte order. Unsigned values may be `0x` hex, narrow ones have to fit
+    pub fn parse_value(self, text: &str) -> Option<Vec<u8>> {
+        let width: usize = self.elem_width();
+        match self.reg_type() {
+            RegTypes::float64 => {
+                let v: f64 = text.parse().ok()?;
+                Some(match width {
+                    4 => (v as f32).to_be_bytes().to_vec(),
+                    _ => v.to_be_bytes().to_vec(),
+                })
+            }
+            RegTypes::int64 => {
+                let v: i64 = text.parse().ok()?;
+                let shift: usize = 64 - width * 8;
+                if ((v << shift) >> shift) != v {
+                    return None;
+                }
+                Some(v.to_be_bytes()[(8 - width)..].to_vec())
+            }
+            _ => {
+                let v: u64 = match text.strip_prefix("0x") {
+                    Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+                    None => text.parse().ok()?,
+                };
+                if (width < 8) && ((v >> (width * 8)) != 0) {
+                    return None;
+                }
+                Some(v.to_be_bytes()[(8 - width)..].to_vec())
+            }
+        }
+    }
+
+    /// Register type a value of this variable is loaded as
+    pub fn reg_type(self) -> RegTypes {
+        match self {
+            DsType::Int
+            | DsType::IntArr
+            | DsType::Int32Arr
+            | DsType::I8
+            | DsType::I16
+            | DsType::I32
+            | DsType::I8Arr
+            | DsType::I16Arr => RegTypes::int64,
+            DsType::Float | DsType::FloatArr | DsType::Float32Arr => RegTypes::float64,
+            DsType::Str => RegTypes::StrAddr,
+            _ => RegTypes::uint64,
+        }
+    }
+}
@@ -1,4 +1,6 @@
-#[derive(Debug, PartialEq)]
+use crate::{registers::Register, vm::{RegTypes, VM}};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Exception {
     ZeroDivision,
     HeapAllocationFault,
@@ -11,4 +13,199 @@ pub enum Exception {
     IncorrectRegType,
     HeapSegmFault,
     MainSegmFault,
+    CallStackSmash, // ret address doesn't match the shadow stack
+    OutOfMemory,    // --max-total-mem budget exceeded
+    HandleLimit,    // --max-open-files / --max-connections reached
+    CoroutineFault, // bad handle, resuming a running coroutine, coyield outside of one
+    StackOverflow,  // data stack past --max-stack-slots / `ncall @stack_limit_set`
+}
+
+impl Exception {
+    // Codes match voxasm exception table (get_exc_table)
+    pub fn from_code(code: u64) -> Option<Exception> {
+        match code {
+            0x1 => Some(Exception::ZeroDivision),
+            0x2 => Some(Exception::HeapAllocationFault),
+            0x3 => Some(Exception::HeapFreeFault),
+            0x4 => Some(Exception::HeapWriteFault),
+            0x5 => Some(Exception::HeapReadFault),
+            0x6 => Some(Exception::NegativeSqrt),
+            0x7 => Some(Exception::InvalidDataType),
+            0x8 => Some(Exception::NativeFault),
+            0x9 => Some(Exception::IncorrectRegType),
+            0xA => Some(Exception::HeapSegmFault),
+            0xB => Some(Exception::MainSegmFault),
+            0xC => Some(Exception::CallStackSmash),
+            0xD => Some(Exception::OutOfMemory),
+            0xE => Some(Exception::HandleLimit),
+            0xF => Some(Exception::CoroutineFault),
+            0x10 => Some(Exception::StackOverflow),
+            _ => None,
+        }
+    }
+
+    fn bit(self) -> u32 {
+        1 << (self.code() - 1)
+    }
+
+    pub fn code(&self) -> u64 {
+        match self {
+            Exception::ZeroDivision => 0x1,
+            Exception::HeapAllocationFault => 0x2,
+            Exception::HeapFreeFault => 0x3,
+            Exception::HeapWriteFault => 0x4,
+            Exception::HeapReadFault => 0x5,
+            Exception::NegativeSqrt => 0x6,
+            Exception::InvalidDataType => 0x7,
+            Exception::NativeFault => 0x8,
+            Exception::IncorrectRegType => 0x9,
+            Exception::HeapSegmFault => 0xA,
+            Exception::MainSegmFault => 0xB,
+            Exception::CallStackSmash => 0xC,
+            Exception::OutOfMemory => 0xD,
+            Exception::HandleLimit => 0xE,
+            Exception::CoroutineFault => 0xF,
+            Exception::StackOverflow => 0x10,
+        }
+    }
+}
+
+/// Pending exceptions: one bit per Exception (bit `code - 1`), so checks
+/// are O(1) and raising an exception that is already pending keeps the
+/// first raise and its ip instead of piling up. The payload slot holds the
+/// value the last `raise_with` attached (fault address, requested size).
+#[derive(Debug, Clone, Default)]
+pub struct PendingExc {
+    bits: u32,
+    stamped: u32,   // bits whose raising ip is known
+    ips: [u64; 32], // bit -> ip of the instruction that raised it
+    payload: Option<(Exception, u64)>,
+}
+
+impl PendingExc {
+    pub fn push(&mut self, exc: Exception) {
+        self.bits |= exc.bit();
+    }
+
+    /// Raises `exc` with a value describing the fault
+    pub fn raise_with(&mut self, exc: Exception, payload: u64) {
+        self.push(exc);
+        self.payload = Some((exc, payload));
+    }
+
+    pub fn contains(&self, exc: Exception) -> bool {
+        self.bits & exc.bit() != 0
+    }
+
+    /// Drops `exc`, returns whether it was pending
+    pub fn take(&mut self, exc: Exception) -> bool {
+        let pending: bool = self.contains(exc);
+        self.bits &= !exc.bit();
+        self.stamped &= !exc.bit();
+        if self.payload.is_some_and(|(owner, _)| owner == exc) {
+            self.payload = None;
+        }
+        pending
+    }
+
+    pub fn clear(&mut self) {
+        *self = PendingExc::default();
+    }
+
+    pub fn len(&self) -> usize {
+        self.bits.count_ones() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
+    /// Pending exceptions, lowest code first
+    pub fn iter(&self) -> impl Iterator<Item = Exception> + '_ {
+        (1..=32).filter_map(Exception::from_code).filter(|exc| self.contains(*exc))
+    }
+
+    pub fn first(&self) -> Option<Exception> {
+        self.iter().next()
+    }
+
+    pub fn get(&self, ind: usize) -> Option<Exception> {
+        self.iter().nth(ind)
+    }
+
+    /// Gives exceptions raised since the last call the ip `ip`, returns
+    /// the highest of them
+    pub fn stamp(&mut self, ip: u64) -> Option<Exception> {
+        let new: u32 = self.bits & !self.stamped;
+        if new == 0 {
+            return None;
+        }
+        self.stamped |= new;
+        for bit in 0..32 {
+            if new & (1 << bit) != 0 {
+                self.ips[bit] = ip;
+            }
+        }
+        Exception::from_code(32 - new.leading_zeros() as u64)
+    }
+
+    /// Ip of the instruction that raised `exc`, 0 if it isn't pending
+    pub fn ip_of(&self, exc: Exception) -> u64 {
+        match self.contains(exc) {
+            true => self.ips[exc.code() as usize - 1],
+            false => 0,
+        }
+    }
+
+    pub fn payload_of(&self, exc: Exception) -> Option<u64> {
+        self.payload.filter(|(owner, _)| *owner == exc).map(|(_, val)| val)
+    }
+
+    /// `[ZeroDivision, HeapFreeFault]`
+    pub fn names(&self) -> String {
+        format!("{:?}", self.iter().collect::<Vec<Exception>>())
+    }
+}
+
+// Pending exceptions inspection. `jexc` only handles the exceptions a guest
+// expects, these let it see (and drop) everything that piled up.
+
+fn set_uint(vm: &mut VM, ind: usize, val: u64) {
+    vm.registers[ind] = Register::uint(val);
+    vm.reg_types[ind] = RegTypes::uint64;
+}
+
+/// ncall 0x30
+/// returns count of pending exceptions into r0
+pub fn ncall_exc_count(vm: &mut VM) {
+    let count: u64 = vm.exceptions_active.len() as u64;
+    set_uint(vm, 0, count);
+}
+
+/// ncall 0x31
+/// r1 is index of a pending exception, 0 is the one with the lowest code.
+/// Returns its code into r0, ip of the instruction that raised it into r1
+/// and its payload (fault address, requested size) into r2, r0 is 0 if
+/// there is no such exception and r2 is 0 without a payload.
+pub fn ncall_exc_peek(vm: &mut VM) {
+    let ind: usize = vm.registers[1].as_u64_bitwise() as usize;
+    let (code, ip, payload): (u64, u64, u64) = match vm.exceptions_active.get(ind) {
+        Some(exc) => (
+            exc.code(),
+            vm.exceptions_active.ip_of(exc),
+            vm.exceptions_active.payload_of(exc).unwrap_or(0),
+        ),
+        None => (0, 0, 0),
+    };
+    set_uint(vm, 0, code);
+    set_uint(vm, 1, ip);
+    set_uint(vm, 2, payload);
+}
+
+/// ncall 0x32
+/// drops all pending exceptions, returns how many there were into r0
+pub fn ncall_exc_clear(vm: &mut VM) {
+    let count: u64 = vm.exceptions_active.len() as u64;
+    vm.exceptions_active.clear();
+    set_uint(vm, 0, count);
 }
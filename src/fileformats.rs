@@ -6,6 +6,305 @@ use std::{
 
 use crate::misclib::args_to_u64;
 
+// v4: sections block right after the function table:
+// count (u64), then for each section: kind (u16), length (u64), data
+pub const SECT_INTERN: u16 = 0x1; // interned const strings: count, count * rel addr
+pub const SECT_FUNC_META: u16 = 0x2; // function ABI: count, count * (func ind, clobbers mask)
+pub const SECT_SYMBOLS: u16 = 0x3; // function names: count, count * (func ind, name len u16, utf8 name)
+pub const SECT_LINES: u16 = 0x4; // debug line table: count, count * (instr addr, source line u32)
+pub const SECT_RODATA: u16 = 0x5; // size of the read-only part at the data segment start (u64)
+pub const SECT_RELOCS: u16 = 0x6; // relocations: count, count * (code offset u64, kind u8)
+pub const SECT_WORDS: u16 = 0x7; // little-endian images: multi-byte code operands, count, count * (code offset u64, width u8)
+pub const SECT_NCALLS: u16 = 0x8; // ncall codes the code uses: count, count * code u16
+pub const SECT_DATA_SYMBOLS: u16 = 0x9; // data variable names: count, count * (rel addr, name len u16, utf8 name)
+pub const SECT_STACK_DEPTH: u16 = 0xA; // deepest data stack of function bodies: count, count * (func ind, slots)
+
+// Byte order flags live in the padding before the function table, so older
+// images read as big-endian. A little-endian image has its header, function
+// table, sections and code operands (listed by SECT_WORDS) in LE. The data
+// segment is a guest memory image and stays big-endian like the VM memory.
+// Loaders convert everything to big-endian right away, the rest of the VM
+// never sees LE bytes.
+pub const HEADER_FLAGS: usize = 0x2E;
+pub const FLAG_LITTLE_ENDIAN: u8 = 0x1;
+
+// what the u64 at a relocation's code offset holds
+pub const RELOC_CODE: u8 = 0x0; // absolute code address (jumps, tables, lea of code labels)
+pub const RELOC_DATA_ABS: u8 = 0x1; // absolute data address (lea of data variables)
+pub const RELOC_DATA_REL: u8 = 0x2; // data segment relative address (ds ops)
+pub const RELOC_FUNC: u8 = 0x3; // function table index (call, fnstind)
+
+/// Bounds-checked reads over a big-endian section payload, None once an
+/// entry runs past its end
+struct SectCursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SectCursor<'a> {
+    fn new(data: &'a [u8]) -> SectCursor<'a> {
+        SectCursor { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let end: usize = self.pos.checked_add(len)?;
+        let res: &[u8] = self.data.get(self.pos..end)?;
+        self.pos = end;
+        Some(res)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes(b.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4).map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.bytes(8).map(args_to_u64)
+    }
+}
+
+// Section readers stop at a truncated entry instead of panicking, images
+// with one are refused by VoxExeHeader::from_bytes anyway (check_section).
+
+/// Reads the SECT_LINES section into (instr addr, source line) pairs
+pub fn read_line_table(sect: &[u8]) -> Vec<(u64, u32)> {
+    let mut cur = SectCursor::new(sect);
+    let count: u64 = cur.u64().unwrap_or(0);
+    (0..count).map_while(|_| Some((cur.u64()?, cur.u32()?))).collect()
+}
+
+/// Reads the SECT_SYMBOLS section into (func ind, name) pairs
+pub fn read_symbols(sect: &[u8]) -> Vec<(usize, String)> {
+    let mut cur = SectCursor::new(sect);
+    let count: u64 = cur.u64().unwrap_or(0);
+    (0..count)
+        .map_while(|_| {
+            let ind: usize = cur.u64()? as usize;
+            let len: usize = cur.u16()? as usize;
+            Some((ind, String::from_utf8_lossy(cur.bytes(len)?).to_string()))
+        })
+        .collect()
+}
+
+/// Reads the SECT_STACK_DEPTH section into (func ind, slots) pairs
+pub fn read_stack_depths(sect: &[u8]) -> Vec<(usize, u64)> {
+    let mut cur = SectCursor::new(sect);
+    let count: u64 = cur.u64().unwrap_or(0);
+    (0..count).map_while(|_| Some((cur.u64()? as usize, cur.u64()?))).collect()
+}
+
+/// Reads the SECT_DATA_SYMBOLS section into (rel addr, name) pairs
+pub fn read_data_symbols(sect: &[u8]) -> Vec<(u64, String)> {
+    read_symbols(sect) // same layout
+        .into_iter()
+        .map(|(addr, name)| (addr as u64, name))
+        .collect()
+}
+
+/// SECT_DATA_SYMBOLS of (rel addr, name) pairs, sorted by address
+pub fn data_symbols_section(entries: &[(u64, String)]) -> VveSection {
+    let mut entries: Vec<&(u64, String)> = entries.iter().collect();
+    entries.sort();
+    let mut data: Vec<u8> = Vec::new();
+    data.extend_from_slice(&(entries.len() as u64).to_be_bytes());
+    for (addr, name) in entries {
+        data.extend_from_slice(&addr.to_be_bytes());
+        data.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        data.extend_from_slice(name.as_bytes());
+    }
+    VveSection::new(SECT_DATA_SYMBOLS, data)
+}
+
+/// Reads the SECT_RELOCS section into (code offset, kind) pairs
+pub fn read_relocs(sect: &[u8]) -> Vec<(u64, u8)> {
+    let mut cur = SectCursor::new(sect);
+    let count: u64 = cur.u64().unwrap_or(0);
+    (0..count).map_while(|_| Some((cur.u64()?, cur.u8()?))).collect()
+}
+
+/// Reads the SECT_WORDS section into (code offset, width) pairs
+pub fn read_code_words(sect: &[u8]) -> Vec<(u64, u8)> {
+    read_relocs(sect) // same layout
+}
+
+/// Reads the SECT_NCALLS section into ncall codes
+pub fn read_ncalls(sect: &[u8]) -> Vec<u16> {
+    let mut cur = SectCursor::new(sect);
+    let count: u64 = cur.u64().unwrap_or(0);
+    (0..count).map_while(|_| cur.u16()).collect()
+}
+
+/// SECT_NCALLS of sorted, deduplicated `codes`
+pub fn ncalls_section(codes: &[u16]) -> VveSection {
+    let mut codes: Vec<u16> = codes.to_vec();
+    codes.sort();
+    codes.dedup();
+    let mut data: Vec<u8> = Vec::new();
+    data.extend_from_slice(&(codes.len() as u64).to_be_bytes());
+    for code in codes {
+        data.extend_from_slice(&code.to_be_bytes());
+    }
+    VveSection::new(SECT_NCALLS, data)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteOrder {
+    Big,
+    Little,
+}
+
+impl ByteOrder {
+    pub fn native() -> ByteOrder {
+        match cfg!(target_endian = "little") {
+            true => ByteOrder::Little,
+            false => ByteOrder::Big,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ByteOrder> {
+        match name {
+            "be" => Some(ByteOrder::Big),
+            "le" => Some(ByteOrder::Little),
+            "native" => Some(ByteOrder::native()),
+            _ => None,
+        }
+    }
+
+    fn u16_bytes(self, val: u16) -> [u8; 2] {
+        match self {
+            ByteOrder::Big => val.to_be_bytes(),
+            ByteOrder::Little => val.to_le_bytes(),
+        }
+    }
+
+    fn u64_bytes(self, val: u64) -> [u8; 8] {
+        match self {
+            ByteOrder::Big => val.to_be_bytes(),
+            ByteOrder::Little => val.to_le_bytes(),
+        }
+    }
+
+    fn read_u16(self, bytes: &[u8]) -> u16 {
+        let arr: [u8; 2] = bytes[0..2].try_into().unwrap();
+        match self {
+            ByteOrder::Big => u16::from_be_bytes(arr),
+            ByteOrder::Little => u16::from_le_bytes(arr),
+        }
+    }
+
+    fn read_u64(self, bytes: &[u8]) -> u64 {
+        let arr: [u8; 8] = bytes[0..8].try_into().unwrap();
+        match self {
+            ByteOrder::Big => u64::from_be_bytes(arr),
+            ByteOrder::Little => u64::from_le_bytes(arr),
+        }
+    }
+}
+
+/// Reverses every (offset, width) field of `bytes` that fits into it
+fn swap_fields(bytes: &mut [u8], fields: &[(usize, usize)]) {
+    for (off, width) in fields {
+        if let Some(field) = bytes.get_mut(*off..(off + width)) {
+            field.reverse();
+        }
+    }
+}
+
+/// Field widths of one entry of a known section (count, then entries),
+/// symbol entries are followed by a utf8 name of their u16 length
+fn section_entry(kind: u16) -> Option<&'static [usize]> {
+    match kind {
+        SECT_INTERN => Some(&[8]),
+        SECT_FUNC_META | SECT_STACK_DEPTH => Some(&[8, 8]),
+        SECT_LINES => Some(&[8, 4]),
+        SECT_RELOCS | SECT_WORDS => Some(&[8, 1]),
+        SECT_NCALLS => Some(&[2]),
+        SECT_SYMBOLS | SECT_DATA_SYMBOLS => Some(&[8, 2]),
+        _ => None,
+    }
+}
+
+/// Multi-byte fields of a section payload whose counts are in `order`,
+/// unknown sections have none and are kept as they are
+fn section_words(kind: u16, data: &[u8], order: ByteOrder) -> Vec<(usize, usize)> {
+    if data.len() < 8 {
+        return Vec::new();
+    }
+    let count: usize = order.read_u64(&data[0..8]) as usize;
+    if kind == SECT_RODATA {
+        return vec![(0, 8)];
+    }
+    let Some(entry) = section_entry(kind) else {
+        return Vec::new();
+    };
+    let mut res: Vec<(usize, usize)> = vec![(0, 8)];
+    let mut pos: usize = 8;
+    for _ in 0..count {
+        for width in entry {
+            if pos + width > data.len() {
+                return res;
+            }
+            if *width > 1 {
+                res.push((pos, *width));
+            }
+            pos += width;
+        }
+        if matches!(kind, SECT_SYMBOLS | SECT_DATA_SYMBOLS) {
+            pos += order.read_u16(&data[(pos - 2)..pos]) as usize;
+        }
+    }
+    res
+}
+
+/// Checks the entries a known section counts fit into its payload,
+/// unknown sections are left to whoever reads them
+fn check_section(kind: u16, data: &[u8], order: ByteOrder) -> Result<(), String> {
+    let truncated = || format!("section {:#x} is truncated", kind);
+    if data.len() < 8 {
+        return match (kind == SECT_RODATA) || section_entry(kind).is_some() {
+            true => Err(truncated()),
+            false => Ok(()),
+        };
+    }
+    let Some(entry) = section_entry(kind) else {
+        return Ok(());
+    };
+    let count: u64 = order.read_u64(&data[0..8]);
+    let mut pos: usize = 8;
+    for _ in 0..count {
+        for width in entry {
+            pos = pos.checked_add(*width).filter(|end| *end <= data.len()).ok_or_else(truncated)?;
+        }
+        if matches!(kind, SECT_SYMBOLS | SECT_DATA_SYMBOLS) {
+            let len: usize = order.read_u16(&data[(pos - 2)..pos]) as usize;
+            pos = pos.checked_add(len).filter(|end| *end <= data.len()).ok_or_else(truncated)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct VveSection {
+    pub kind: u16,
+    pub data: Vec<u8>,
+}
+
+impl VveSection {
+    pub fn new(kind: u16, data: Vec<u8>) -> VveSection {
+        VveSection {
+            kind: kind,
+            data: data,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct VoxExeHeader {
     // v3
@@ -17,6 +316,9 @@ pub struct VoxExeHeader {
     pub data_size: u64,
     pub func_table_len: u64,  // number of funcs
     pub func_table: Vec<u64>, //Starts at 0x30
+    // v4
+    pub sections: Vec<VveSection>, // always big-endian in memory
+    pub byte_order: ByteOrder,     // of the file, see HEADER_FLAGS
 }
 
 impl VoxExeHeader {
@@ -38,139 +340,189 @@ impl VoxExeHeader {
             code_size: code_size,
             func_table_len: func_table.len() as u64,
             func_table: func_table,
+            sections: Vec::new(),
+            byte_order: ByteOrder::Big,
         }
     }
 
-    pub fn load(filename: &str, minVersion: u16) -> Result<VoxExeHeader, ()> {
-        match fs::read(filename) {
-            Ok(bytes) => {
-                let magic = &bytes[0..4];
-                if magic != b"VVE\0" {
-                    eprintln!("Magic number of {} is incorrect.", filename);
-                }
-
-                let version: u16 = u16::from_be_bytes(bytes[4..6].try_into().unwrap());
-                if version < minVersion {
-                    panic!(
-                        "{} file format version is {} and deprecated.",
-                        filename, version
-                    );
-                }
-                let entry_point: u64 = u64::from_be_bytes(bytes[6..14].try_into().unwrap());
-                let data_base: u64 = u64::from_be_bytes(bytes[14..22].try_into().unwrap());
-                let code_size: u64 = u64::from_be_bytes(bytes[22..30].try_into().unwrap());
-                let data_size: u64 = u64::from_be_bytes(bytes[30..38].try_into().unwrap());
-                let func_table_size: u64 = u64::from_be_bytes(bytes[38..46].try_into().unwrap());
-                let func_table = Self::read_func_table(bytes.clone(), 0x30, func_table_size * 16);
-
-                let magic_as_arr: [u8; 4] = magic[0..4].try_into().unwrap();
-
-                Ok(VoxExeHeader {
-                    magic: magic_as_arr,
-                    version: version,
-                    entry_point: entry_point,
-                    data_base: data_base,
-                    code_size: code_size,
-                    data_size: data_size,
-                    func_table_len: func_table_size,
-                    func_table: func_table,
-                })
-            }
-            Err(err) => {
-                eprintln!(
-                    "ERROR While reading .vve by path {}: \n
-                {}",
-                    filename, err
-                );
-                Err(())
+    /// Size of everything before the code, in bytes
+    pub fn size(&self) -> usize {
+        let mut res: usize = 0x30 + (self.func_table_len as usize) * 16;
+        if self.version >= 4 {
+            res += 8;
+            for sect in &self.sections {
+                res += 2 + 8 + sect.data.len();
             }
         }
+        res
     }
 
-    pub fn read_func_table(file_bytes: Vec<u8>, start_ind: u64, count_bytes: u64) -> Vec<u64> {
-        let mut res: Vec<u64> = vec![0; (count_bytes / 16) as usize];
-        for i in (start_ind..start_ind + count_bytes).step_by(16) {
-            let ind: u64 = args_to_u64(&file_bytes[(i as usize)..(i + 8) as usize]);
-            let abs_addr: u64 = args_to_u64(&file_bytes[(i + 8) as usize..(i + 16) as usize]);
-            res[ind as usize] = abs_addr;
-        }
-        res
+    pub fn section(&self, kind: u16) -> Option<&VveSection> {
+        self.sections.iter().find(|s| s.kind == kind)
     }
 
-    pub fn write(filename: &str, header: &VoxExeHeader) -> File {
-        let mut res: File = File::create(filename).unwrap();
+    /// Swaps multi-byte code operands of `body` between big-endian and
+    /// the file byte order, big-endian images are left alone
+    pub fn swap_code_words(&self, body: &mut [u8]) {
+        if self.byte_order == ByteOrder::Big {
+            return;
+        }
+        if let Some(sect) = self.section(SECT_WORDS) {
+            let fields: Vec<(usize, usize)> = read_code_words(&sect.data)
+                .into_iter()
+                .map(|(off, width)| (off as usize, width as usize))
+                .collect();
+            swap_fields(body, &fields);
+        }
+    }
 
-        res.write_all(&header.magic);
+    fn sections_bytes(&self) -> Vec<u8> {
+        let order: ByteOrder = self.byte_order;
+        let mut res: Vec<u8> = Vec::new();
+        res.extend_from_slice(&order.u64_bytes(self.sections.len() as u64));
+        for sect in &self.sections {
+            let mut data: Vec<u8> = sect.data.clone();
+            if order == ByteOrder::Little {
+                swap_fields(&mut data, &section_words(sect.kind, &sect.data, ByteOrder::Big));
+            }
+            res.extend_from_slice(&order.u16_bytes(sect.kind));
+            res.extend_from_slice(&order.u64_bytes(data.len() as u64));
+            res.extend_from_slice(&data);
+        }
+        res
+    }
 
-        let vers = header.version.to_be_bytes();
-        res.write_all(&vers);
+    fn read_sections(file_bytes: &[u8], start_ind: usize, order: ByteOrder) -> Result<Vec<VveSection>, String> {
+        let truncated = || "sections run past the end of the file".to_string();
+        let field = |from: usize, len: usize| from.checked_add(len).and_then(|end| file_bytes.get(from..end));
+        let count: u64 = order.read_u64(field(start_ind, 8).ok_or_else(truncated)?);
+        let mut res: Vec<VveSection> = Vec::new();
+        let mut cur: usize = start_ind + 8;
+        for _ in 0..count {
+            let head: &[u8] = field(cur, 10).ok_or_else(truncated)?;
+            let kind: u16 = order.read_u16(&head[0..2]);
+            let len: usize = usize::try_from(order.read_u64(&head[2..10])).map_err(|_| truncated())?;
+            cur += 10;
+            let raw: &[u8] = field(cur, len).ok_or_else(truncated)?;
+            check_section(kind, raw, order)?;
+            let mut data: Vec<u8> = raw.to_vec();
+            if order == ByteOrder::Little {
+                swap_fields(&mut data, &section_words(kind, raw, order));
+            }
+            res.push(VveSection::new(kind, data));
+            cur += len;
+        }
+        Ok(res)
+    }
 
-        let entry = header.entry_point.to_be_bytes();
-        res.write_all(&entry);
+    pub fn load(filename: &str, min_version: u16) -> Result<VoxExeHeader, String> {
+        match fs::read(filename) {
+            Ok(bytes) => Self::from_bytes(&bytes, filename, min_version),
+            Err(err) => Err(format!("Can't load {}: {}", filename, err)),
+        }
+    }
 
-        let db = header.data_base.to_be_bytes();
-        res.write_all(&db);
+    /// Header of the vve image in `bytes`, `filename` names it in messages.
+    /// Fails on truncated or corrupted headers, function tables and sections
+    pub fn from_bytes(bytes: &[u8], filename: &str, min_version: u16) -> Result<VoxExeHeader, String> {
+        if bytes.len() < 0x30 {
+            return Err(format!("{} is too short for a .vve", filename));
+        }
+        let magic = &bytes[0..4];
+        if magic != b"VVE\0" {
+            eprintln!("Magic number of {} is incorrect.", filename);
+        }
 
-        let code_size = header.code_size.to_be_bytes();
-        res.write_all(&code_size);
+        let byte_order: ByteOrder = match bytes.get(HEADER_FLAGS) {
+            Some(flags) if (flags & FLAG_LITTLE_ENDIAN) != 0 => ByteOrder::Little,
+            _ => ByteOrder::Big,
+        };
+        let version: u16 = byte_order.read_u16(&bytes[4..6]);
+        if version < min_version {
+            return Err(format!(
+                "{} file format version is {} and deprecated.",
+                filename, version
+            ));
+        }
+        let entry_point: u64 = byte_order.read_u64(&bytes[6..14]);
+        let data_base: u64 = byte_order.read_u64(&bytes[14..22]);
+        let code_size: u64 = byte_order.read_u64(&bytes[22..30]);
+        let data_size: u64 = byte_order.read_u64(&bytes[30..38]);
+        let func_table_size: u64 = byte_order.read_u64(&bytes[38..46]);
+        let func_table_end: usize = func_table_size
+            .checked_mul(16)
+            .and_then(|len| usize::try_from(len).ok())
+            .and_then(|len| len.checked_add(0x30))
+            .filter(|end| *end <= bytes.len())
+            .ok_or_else(|| format!("{}: function table runs past the end of the file", filename))?;
+        let func_table = Self::read_func_table(&bytes[0x30..func_table_end], byte_order)
+            .map_err(|e| format!("{}: {}", filename, e))?;
+        let sections: Vec<VveSection> = match version >= 4 {
+            true => Self::read_sections(bytes, func_table_end, byte_order).map_err(|e| format!("{}: {}", filename, e))?,
+            false => Vec::new(),
+        };
 
-        let data_size = header.data_size.to_be_bytes();
-        res.write_all(&data_size);
+        let magic_as_arr: [u8; 4] = magic[0..4].try_into().unwrap();
 
-        let func_table_size = header.func_table_len.to_be_bytes();
-        res.write_all(&func_table_size);
+        Ok(VoxExeHeader {
+            magic: magic_as_arr,
+            version: version,
+            entry_point: entry_point,
+            data_base: data_base,
+            code_size: code_size,
+            data_size: data_size,
+            func_table_len: func_table_size,
+            func_table: func_table,
+            sections: sections,
+            byte_order,
+        })
+    }
 
-        let curpos = res.stream_position().unwrap();
-        let tofill = (0x30 as usize).saturating_sub(curpos as usize);
-        let zeros = vec![0; tofill];
-        res.write_all(&zeros);
+    /// Function table entries (func ind, addr) into addresses by index
+    pub fn read_func_table(table: &[u8], order: ByteOrder) -> Result<Vec<u64>, String> {
+        let mut res: Vec<u64> = vec![0; table.len() / 16];
+        for entry in table.chunks_exact(16) {
+            let ind: u64 = order.read_u64(&entry[0..8]);
+            let abs_addr: u64 = order.read_u64(&entry[8..16]);
+            match res.get_mut(ind as usize) {
+                Some(slot) => *slot = abs_addr,
+                None => return Err(format!("function index {} is out of the function table", ind)),
+            }
+        }
+        Ok(res)
+    }
 
-        //res.seek(std::io::SeekFrom::Start(0x30)); // func table starts from 0x30
-        let func_table: Vec<u64> = header.func_table.clone();
-        for (ind, addr) in func_table.iter().enumerate() {
-            let ind_bytes = ind.to_be_bytes();
-            let addr_bytes = addr.to_be_bytes();
-            res.write_all(&ind_bytes);
-            res.write_all(&addr_bytes);
+    /// Everything before the code, in the header's byte order
+    pub fn header_bytes(&self) -> Vec<u8> {
+        let order: ByteOrder = self.byte_order;
+        let mut res: Vec<u8> = Vec::with_capacity(self.size());
+        res.extend_from_slice(&self.magic);
+        res.extend_from_slice(&order.u16_bytes(self.version));
+        for val in [self.entry_point, self.data_base, self.code_size, self.data_size, self.func_table_len] {
+            res.extend_from_slice(&order.u64_bytes(val));
         }
+        res.resize(0x30, 0); // func table starts from 0x30
+        if order == ByteOrder::Little {
+            res[HEADER_FLAGS] |= FLAG_LITTLE_ENDIAN;
+        }
+        for (ind, addr) in self.func_table.iter().enumerate() {
+            res.extend_from_slice(&order.u64_bytes(ind as u64));
+            res.extend_from_slice(&order.u64_bytes(*addr));
+        }
+        if self.version >= 4 {
+            res.extend_from_slice(&self.sections_bytes());
+        }
+        res
+    }
 
+    pub fn write(filename: &str, header: &VoxExeHeader) -> File {
+        let mut res: File = File::create(filename).unwrap();
+        let _ = res.write_all(&header.header_bytes());
         res
     }
 
     pub fn write_existing(file: &mut File, header: &VoxExeHeader) {
-        file.seek(std::io::SeekFrom::Start(0));
-        file.write_all(&header.magic);
-
-        let vers = header.version.to_be_bytes();
-        file.write_all(&vers);
-
-        let entry = header.entry_point.to_be_bytes();
-        file.write_all(&entry);
-
-        let db = header.data_base.to_be_bytes();
-        file.write_all(&db);
-
-        let code_size = header.code_size.to_be_bytes();
-        file.write_all(&code_size);
-
-        let data_size = header.data_size.to_be_bytes();
-        file.write_all(&data_size);
-
-        let func_table_size = header.func_table_len.to_be_bytes();
-        file.write_all(&func_table_size);
-
-        let curpos = file.stream_position().unwrap();
-        let tofill = (0x30 as usize).saturating_sub(curpos as usize);
-        let zeros = vec![0; tofill];
-        file.write_all(&zeros);
-
-        //file.seek(std::io::SeekFrom::Start(0x30)); // func table starts from 0x30
-        let func_table: Vec<u64> = header.func_table.clone();
-        for (ind, addr) in func_table.iter().enumerate() {
-            let ind_bytes = ind.to_be_bytes();
-            let addr_bytes = addr.to_be_bytes();
-            file.write_all(&ind_bytes);
-            file.write_all(&addr_bytes);
-        }
+        let _ = file.seek(std::io::SeekFrom::Start(0));
+        let _ = file.write_all(&header.header_bytes());
     }
 }
@@ -1,7 +1,10 @@
 use crate::{
+    coroutine::{coro_finish, CORO_RETADDR},
+    memcap::{mem_reserve, CALL_FRAME_BYTES},
     misclib::args_to_u64,
     registers::Register,
-    vm::{RegTypes, VM},
+    stack::call_stack_room,
+    vm::{RegTypes, HALT_RETADDR, VM},
 };
 
 pub fn op_call(vm: &mut VM) {
@@ -22,13 +25,26 @@ pub fn op_call(vm: &mut VM) {
         }
     };
 
-    vm.call_stack.push((vm.ip + 9) as u64);
+    if !call_stack_room(vm, ind as usize) || !mem_reserve(vm, CALL_FRAME_BYTES) {
+        vm.ip += 9;
+        return;
+    }
+    let saved = autosave_regs(vm, ind as usize);
+    vm.call_stack.push_saved((vm.ip + 9) as u64, ind as usize, saved);
+    if let Some(trace) = &mut vm.trace {
+        trace.enter(ind as usize);
+    }
+    vm.shadow_push((vm.ip + 9) as u64);
     vm.ip = tojmp as usize;
 }
 
 pub fn op_ret(vm: &mut VM) {
     // 0x91, size: 1
     // ret (returns to return address from call stack
+    for (ind, val, rtype) in vm.call_stack.take_saved() {
+        vm.registers[ind] = val;
+        vm.reg_types[ind] = rtype;
+    }
     let ret_addr: u64 = match vm.call_stack.pop() {
         Some(addr) => addr,
         None => {
@@ -38,7 +54,22 @@ pub fn op_ret(vm: &mut VM) {
             );
         }
     };
+    vm.scratch.release_frames(vm.call_stack.stack.len());
+    if let Some(trace) = &mut vm.trace {
+        trace.exit();
+    }
 
+    if !vm.shadow_check(ret_addr) {
+        return;
+    }
+    if ret_addr == HALT_RETADDR {
+        vm.halt(); // returned from the --entry function
+        return;
+    }
+    if ret_addr == CORO_RETADDR {
+        coro_finish(vm); // the coroutine function is done
+        return;
+    }
     vm.ip = ret_addr as usize;
 }
 
@@ -72,6 +103,33 @@ pub fn op_callr(vm: &mut VM) {
         }
     };
 
-    vm.call_stack.push((vm.ip + 2) as u64);
-    vm.ip = *addr as usize;
+    let addr: usize = *addr as usize;
+    if !call_stack_room(vm, ind) || !mem_reserve(vm, CALL_FRAME_BYTES) {
+        vm.ip += 2;
+        return;
+    }
+    let saved = autosave_regs(vm, ind);
+    vm.call_stack.push_saved((vm.ip + 2) as u64, ind, saved);
+    if let Some(trace) = &mut vm.trace {
+        trace.enter(ind);
+    }
+    vm.shadow_push((vm.ip + 2) as u64);
+    vm.ip = addr;
+}
+
+/// Callee-saved registers of function `ind` when autosave is on:
+/// every register not declared in its `clobbers` directive, except r0
+/// which carries the return value.
+fn autosave_regs(vm: &VM, ind: usize) -> Vec<(usize, Register, RegTypes)> {
+    if !vm.abi_autosave {
+        return Vec::new();
+    }
+    let clobbers: u32 = match vm.func_clobbers.get(&ind) {
+        Some(v) => *v,
+        None => return Vec::new(), // no ABI info
+    };
+    (1..vm.registers.len())
+        .filter(|i| (clobbers & (1 << i)) == 0)
+        .map(|i| (i, vm.registers[i], vm.reg_types[i]))
+        .collect()
 }
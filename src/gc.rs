@@ -9,6 +9,53 @@ pub struct GC {
     pub main_refs: HashSet<u64>,
     pub t2_refs: HashMap<u64, HashSet<u64>>,
     unmarked: Vec<usize>, // indices
+    next_id: u64,
+}
+
+/// Roots and objects copied at a safepoint for the background marker,
+/// objects are (heap ptr, alloc id) so reused addresses aren't swept
+pub struct GcSnapshot {
+    pub roots: HashSet<u64>,
+    pub t2_refs: HashMap<u64, HashSet<u64>>,
+    pub objects: Vec<(u64, u64)>,
+}
+
+impl GcSnapshot {
+    /// Marks on the snapshot, returns unreachable objects
+    pub fn unreachable(&self) -> Vec<(u64, u64)> {
+        let reachable: HashSet<u64> = reachable_from(&self.roots, &self.t2_refs);
+        self.objects
+            .iter()
+            .filter(|(ptr, _)| !reachable.contains(ptr))
+            .cloned()
+            .collect()
+    }
+}
+
+pub(crate) fn reachable_from(roots: &HashSet<u64>, t2_refs: &HashMap<u64, HashSet<u64>>) -> HashSet<u64> {
+    let mut reachable: HashSet<u64> = HashSet::new();
+    let mut queue: VecDeque<u64> = VecDeque::new();
+
+    for root in roots {
+        queue.push_back(*root);
+    }
+
+    while let Some(cur_ptr) = queue.pop_front() {
+        if reachable.contains(&cur_ptr) {
+            continue;
+        }
+
+        reachable.insert(cur_ptr);
+
+        if let Some(referenced_ptrs) = t2_refs.get(&cur_ptr) {
+            for ptr in referenced_ptrs {
+                if !reachable.contains(ptr) {
+                    queue.push_back(*ptr);
+                }
+            }
+        }
+    }
+    reachable
 }
 
 impl GC {
@@ -18,37 +65,27 @@ impl GC {
             unmarked: Vec::new(),
             main_refs: HashSet::new(),
             t2_refs: HashMap::new(),
+            next_id: 0,
         }
     }
-    pub fn pin_object(&mut self, obj: GcObject) {
+    pub fn pin_object(&mut self, mut obj: GcObject) {
+        obj.id = self.next_id;
+        self.next_id += 1;
         self.objects.push(obj);
     }
+    /// Whether the block at `ptr` is under GC control
+    pub fn owns(&self, ptr: u64) -> bool {
+        self.objects.iter().any(|obj| obj.heap_ptr == ptr)
+    }
+    pub fn forget(&mut self, ptr: u64) {
+        self.objects.retain(|obj| obj.heap_ptr != ptr);
+        self.main_refs.remove(&ptr);
+    }
     pub fn mark(&mut self, t1_refs: &HashSet<u64>, t2_refs: &HashMap<u64, HashSet<u64>>) {
         let refs: HashSet<u64> = self.main_refs.union(t1_refs).cloned().collect();
         self.t2_refs = t2_refs.clone();
 
-        let mut reachable: HashSet<u64> = HashSet::new();
-        let mut queue: VecDeque<u64> = VecDeque::new();
-
-        for root in &refs {
-            queue.push_back(*root);
-        }
-
-        while let Some(cur_ptr) = queue.pop_front() {
-            if reachable.contains(&cur_ptr) {
-                continue;
-            }
-
-            reachable.insert(cur_ptr);
-
-            if let Some(referenced_ptrs) = t2_refs.get(&cur_ptr) {
-                for ptr in referenced_ptrs {
-                    if !reachable.contains(ptr) {
-                        queue.push_back(*ptr);
-                    }
-                }
-            }
-        }
+        let reachable: HashSet<u64> = reachable_from(&refs, t2_refs);
 
         self.unmarked.clear();
         for (idx, obj) in self.objects.iter_mut().enumerate() {
@@ -68,7 +105,8 @@ impl GC {
         self.unmarked.sort_unstable_by(|a, b| b.cmp(a));
         self.unmarked.dedup();
 
-        for &idx in self.unmarked.iter().rev() {
+        // highest index first, removing shifts the ones after it
+        for &idx in self.unmarked.iter() {
             if idx < self.objects.len() {
                 let gc_obj = self.objects.remove(idx);
                 res.push(gc_obj.heap_ptr);
@@ -78,12 +116,42 @@ impl GC {
         self.unmarked.clear();
         res
     }
+
+    pub fn snapshot(&self, t1_refs: &HashSet<u64>, t2_refs: &HashMap<u64, HashSet<u64>>) -> GcSnapshot {
+        GcSnapshot {
+            roots: self.main_refs.union(t1_refs).cloned().collect(),
+            t2_refs: t2_refs.clone(),
+            objects: self.objects.iter().map(|obj| (obj.heap_ptr, obj.id)).collect(),
+        }
+    }
+
+    /// Sweep part of a background mark: drops objects that are still
+    /// the same allocation, returns ptrs to free
+    pub fn sweep_dead(&mut self, dead: &[(u64, u64)]) -> Vec<u64> {
+        let dead: HashSet<(u64, u64)> = dead.iter().cloned().collect();
+        let mut res: Vec<u64> = Vec::new();
+        self.objects.retain(|obj| {
+            if dead.contains(&(obj.heap_ptr, obj.id)) {
+                res.push(obj.heap_ptr);
+                return false;
+            }
+            true
+        });
+        res
+    }
+}
+
+impl Default for GC {
+    fn default() -> GC {
+        GC::new()
+    }
 }
 
 #[derive(Debug)]
 pub struct GcObject {
     heap_ptr: u64,
     marked: bool,
+    id: u64, // set by GC on pin
 }
 
 impl GcObject {
@@ -91,6 +159,19 @@ impl GcObject {
         GcObject {
             heap_ptr: ptr,
             marked: false,
+            id: 0,
         }
     }
+
+    pub fn heap_ptr(&self) -> u64 {
+        self.heap_ptr
+    }
+
+    pub fn marked(&self) -> bool {
+        self.marked
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
 }
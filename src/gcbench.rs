@@ -0,0 +1,257 @@
+use std::time::{Duration, Instant};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    gc::GcObject,
+    misclib::{pretty_fmt_size, pretty_input_tobytes},
+    registers::Register,
+    vm::{RegTypes, VM},
+};
+
+// `voxvm bench-gc`: GC pause and throughput numbers without a guest program.
+// Every round builds one object graph of the chosen shape straight through
+// the Heap/GC APIs and roots it in a register, the `--live` latest graphs stay
+// rooted, older ones become garbage. Then a full collection runs and its
+// pause is recorded. Shapes:
+//   list  - singly linked list
+//   tree  - binary tree, breadth-first
+//   cycle - ring, the last object points back to the first
+//   mixed - every object points to the previous one and to a random one,
+//           so the graph has cycles and shared objects
+// Object sizes are uniform in --size=MIN-MAX, the generator is seeded, so
+// runs with the same arguments build the same heaps.
+
+const SHAPES: [&str; 4] = ["list", "tree", "cycle", "mixed"];
+const PTR_BYTES: usize = 8;
+const FIRST_ROOT_REG: usize = 1; // graphs are rooted in r1..
+
+struct BenchCfg {
+    shapes: Vec<&'static str>,
+    objects: usize,
+    rounds: usize,
+    live: usize,
+    size_min: usize,
+    size_max: usize,
+    heap: usize,
+    seed: u64,
+}
+
+impl Default for BenchCfg {
+    fn default() -> BenchCfg {
+        BenchCfg {
+            shapes: SHAPES.to_vec(),
+            objects: 2000,
+            rounds: 20,
+            live: 4,
+            size_min: 16,
+            size_max: 64,
+            heap: 64 * 1024 * 1024,
+            seed: 1,
+        }
+    }
+}
+
+struct ShapeResult {
+    pauses: Vec<Duration>,
+    alloc_time: Duration,
+    allocated: usize,
+    collected: usize,
+    heap_used: u64,
+}
+
+fn parse_args(args: &[String]) -> Result<BenchCfg, String> {
+    let mut cfg: BenchCfg = BenchCfg::default();
+    for arg in args {
+        let (key, val) = match arg.split_once('=') {
+            Some(kv) => kv,
+            None => return Err(format!("unknown argument '{}'", arg)),
+        };
+        let num = || val.parse::<usize>().map_err(|_| format!("{} takes a number, got '{}'", key, val));
+        match key {
+            "--shape" => {
+                cfg.shapes = match val {
+                    "all" => SHAPES.to_vec(),
+                    _ => match SHAPES.iter().find(|s| **s == val) {
+                        Some(s) => vec![*s],
+                        None => return Err(format!("unknown shape '{}', expected {} or all", val, SHAPES.join("|"))),
+                    },
+                }
+            }
+            "--objects" => cfg.objects = num()?,
+            "--rounds" => cfg.rounds = num()?,
+            "--live" => cfg.live = num()?,
+            "--seed" => cfg.seed = num()? as u64,
+            "--size" => {
+                let (min, max) = val.split_once('-').unwrap_or((val, val));
+                match (min.parse::<usize>(), max.parse::<usize>()) {
+                    (Ok(min), Ok(max)) if min <= max => (cfg.size_min, cfg.size_max) = (min, max),
+                    _ => return Err(format!("--size takes MIN-MAX bytes, got '{}'", val)),
+                }
+            }
+            "--heap" => {
+                cfg.heap = match pretty_input_tobytes(val.to_string()) {
+                    Some(v) => v,
+                    None => return Err(format!("--heap takes a size, e.g. 64MB, got '{}'", val)),
+                }
+            }
+            _ => return Err(format!("unknown argument '{}'", arg)),
+        }
+    }
+    if (cfg.objects == 0) || (cfg.rounds == 0) {
+        return Err("--objects and --rounds have to be at least 1".to_string());
+    }
+    if cfg.live > 31 - FIRST_ROOT_REG {
+        return Err(format!("--live is at most {}", 31 - FIRST_ROOT_REG));
+    }
+    Ok(cfg)
+}
+
+/// Pointer slots an object of `shape` needs
+fn slots_of(shape: &str) -> usize {
+    match shape {
+        "tree" | "mixed" => 2,
+        _ => 1,
+    }
+}
+
+/// Stores pointer `tgt` in slot `slot` of object `obj` the way `store` does
+fn link(vm: &mut VM, obj: u64, slot: usize, tgt: u64) {
+    let at: u64 = obj + (slot * PTR_BYTES) as u64;
+    vm.heap.write(at, tgt.to_be_bytes().to_vec()).unwrap();
+    vm.heap.set_ref(at, tgt);
+}
+
+/// Allocates one graph, returns its root
+fn build_graph(vm: &mut VM, cfg: &BenchCfg, shape: &str, rng: &mut StdRng) -> Result<u64, String> {
+    let min_size: usize = slots_of(shape) * PTR_BYTES;
+    let mut objs: Vec<u64> = Vec::with_capacity(cfg.objects);
+    for _ in 0..cfg.objects {
+        let size: usize = rng.random_range(cfg.size_min..=cfg.size_max).max(min_size);
+        let ptr: u64 = match vm.heap.alloc(size) {
+            Some(p) => p,
+            None => {
+                return Err(format!(
+                    "heap of {} is full with {} in use, try a bigger --heap or fewer --objects/--live",
+                    pretty_fmt_size(cfg.heap as u64),
+                    pretty_fmt_size(vm.heap.used_bytes())
+                ))
+            }
+        };
+        vm.gc.pin_object(GcObject::new(ptr));
+        objs.push(ptr);
+    }
+    for i in 1..objs.len() {
+        match shape {
+            "tree" => link(vm, objs[(i - 1) / 2], (i - 1) % 2, objs[i]),
+            "mixed" => {
+                link(vm, objs[i], 0, objs[i - 1]);
+                let other: u64 = objs[rng.random_range(0..objs.len())];
+                link(vm, objs[i], 1, other);
+            }
+            _ => link(vm, objs[i - 1], 0, objs[i]),
+        }
+    }
+    match shape {
+        "cycle" => link(vm, *objs.last().unwrap(), 0, objs[0]),
+        "mixed" => return Ok(*objs.last().unwrap()), // reaches the others through slot 0
+        _ => {}
+    }
+    Ok(objs[0])
+}
+
+fn run_shape(cfg: &BenchCfg, shape: &str) -> Result<ShapeResult, String> {
+    let mut vm: VM = VM::new(0, 0, cfg.heap, 0);
+    let mut rng: StdRng = StdRng::seed_from_u64(cfg.seed);
+    let mut res = ShapeResult {
+        pauses: Vec::with_capacity(cfg.rounds),
+        alloc_time: Duration::ZERO,
+        allocated: 0,
+        collected: 0,
+        heap_used: 0,
+    };
+    for round in 0..cfg.rounds {
+        let start: Instant = Instant::now();
+        let root: u64 = build_graph(&mut vm, cfg, shape, &mut rng)?;
+        res.alloc_time += start.elapsed();
+        res.allocated += cfg.objects;
+
+        // the graph replaces the oldest live one, --live=0 keeps nothing
+        if cfg.live > 0 {
+            let reg: usize = FIRST_ROOT_REG + round % cfg.live;
+            vm.registers[reg] = Register::address(root);
+            vm.reg_types[reg] = RegTypes::address;
+        }
+        let start: Instant = Instant::now();
+        res.collected += vm.gc_collect();
+        res.pauses.push(start.elapsed());
+    }
+    res.heap_used = vm.heap.used_bytes();
+    Ok(res)
+}
+
+/// Nearest-rank percentile of sorted samples
+fn percentile(sorted: &[Duration], pct: usize) -> Duration {
+    let rank: usize = (sorted.len() * pct).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+fn micros(d: Duration) -> String {
+    format!("{:.1}", d.as_secs_f64() * 1e6)
+}
+
+fn report(shape: &str, res: &mut ShapeResult) -> String {
+    res.pauses.sort();
+    let gc_time: Duration = res.pauses.iter().sum();
+    let total: f64 = (res.alloc_time + gc_time).as_secs_f64().max(f64::EPSILON);
+    format!(
+        "{:<6} {:>9} {:>9} {:>9} {:>9} {:>9} {:>12.0} {:>10} {:>9}",
+        shape,
+        micros(percentile(&res.pauses, 50)),
+        micros(percentile(&res.pauses, 90)),
+        micros(percentile(&res.pauses, 99)),
+        micros(*res.pauses.last().unwrap()),
+        micros(gc_time / res.pauses.len() as u32),
+        res.allocated as f64 / total,
+        res.collected,
+        pretty_fmt_size(res.heap_used),
+    )
+}
+
+pub fn bench_gc_cli(args: &[String]) -> i32 {
+    let cfg: BenchCfg = match parse_args(args) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            eprintln!(
+                "Usage: voxvm bench-gc [--shape=list|tree|cycle|mixed|all] [--objects=N] [--rounds=N] \
+                 [--live=N] [--size=MIN-MAX] [--heap=SIZE] [--seed=N]"
+            );
+            return 1;
+        }
+    };
+    println!(
+        "bench-gc: {} rounds of {} objects, {}-{} bytes each, {} graphs live, heap {}, seed {}",
+        cfg.rounds,
+        cfg.objects,
+        cfg.size_min,
+        cfg.size_max,
+        cfg.live,
+        pretty_fmt_size(cfg.heap as u64),
+        cfg.seed
+    );
+    println!(
+        "{:<6} {:>9} {:>9} {:>9} {:>9} {:>9} {:>12} {:>10} {:>9}",
+        "shape", "p50 us", "p90 us", "p99 us", "max us", "mean us", "allocs/s", "collected", "heap used"
+    );
+    for shape in &cfg.shapes {
+        match run_shape(&cfg, shape) {
+            Ok(mut res) => println!("{}", report(shape, &mut res)),
+            Err(e) => {
+                eprintln!("ERROR: {}: {}", shape, e);
+                return 1;
+            }
+        }
+    }
+    0
+}
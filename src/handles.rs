@@ -0,0 +1,104 @@
+// Stable handles for native resources (open files, net connections).
+// A handle is generation << 32 | slot. Closing a handle frees its slot and
+// bumps the slot generation, so the slot can be reused while the old handle
+// stays invalid instead of silently referring to the new resource. Fresh
+// slots start at generation 0, the first handles are 0, 1, 2..
+
+const SLOT_BITS: u32 = 32;
+const SLOT_MASK: u64 = (1 << SLOT_BITS) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HandleErr {
+    Invalid, // never handed out
+    Stale,   // was closed, slot may be reused
+}
+
+#[derive(Debug)]
+struct Slot<T> {
+    generation: u32,
+    item: Option<T>,
+}
+
+#[derive(Debug)]
+pub struct HandleTable<T> {
+    slots: Vec<Slot<T>>,
+    live: usize,
+}
+
+impl<T> Default for HandleTable<T> {
+    fn default() -> HandleTable<T> {
+        HandleTable { slots: Vec::new(), live: 0 }
+    }
+}
+
+impl<T> HandleTable<T> {
+    pub fn new() -> HandleTable<T> {
+        HandleTable::default()
+    }
+
+    /// Count of open handles
+    pub fn count(&self) -> usize {
+        self.live
+    }
+
+    pub fn insert(&mut self, item: T) -> u64 {
+        self.live += 1;
+        let slot: usize = match self.slots.iter().position(|s| s.item.is_none()) {
+            Some(ind) => {
+                self.slots[ind].item = Some(item);
+                ind
+            }
+            None => {
+                self.slots.push(Slot { generation: 0, item: Some(item) });
+                self.slots.len() - 1
+            }
+        };
+        ((self.slots[slot].generation as u64) << SLOT_BITS) | slot as u64
+    }
+
+    fn slot(&self, handle: u64) -> Result<usize, HandleErr> {
+        let ind: usize = (handle & SLOT_MASK) as usize;
+        let generation: u64 = handle >> SLOT_BITS;
+        match self.slots.get(ind) {
+            Some(s) if (s.generation as u64 == generation) && s.item.is_some() => Ok(ind),
+            Some(s) if (generation < s.generation as u64) => Err(HandleErr::Stale),
+            _ => Err(HandleErr::Invalid),
+        }
+    }
+
+    pub fn get(&self, handle: u64) -> Result<&T, HandleErr> {
+        let ind: usize = self.slot(handle)?;
+        Ok(self.slots[ind].item.as_ref().unwrap())
+    }
+
+    pub fn get_mut(&mut self, handle: u64) -> Result<&mut T, HandleErr> {
+        let ind: usize = self.slot(handle)?;
+        Ok(self.slots[ind].item.as_mut().unwrap())
+    }
+
+    /// Open items, in slot order
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.slots.iter().filter_map(|s| s.item.as_ref())
+    }
+
+    pub fn remove(&mut self, handle: u64) -> Result<T, HandleErr> {
+        let ind: usize = self.slot(handle)?;
+        let slot: &mut Slot<T> = &mut self.slots[ind];
+        slot.generation = slot.generation.wrapping_add(1);
+        self.live -= 1;
+        Ok(slot.item.take().unwrap())
+    }
+
+    /// Takes every open item out, their handles become stale
+    pub fn drain(&mut self) -> Vec<T> {
+        let mut res: Vec<T> = Vec::new();
+        for slot in self.slots.iter_mut() {
+            if let Some(item) = slot.item.take() {
+                slot.generation = slot.generation.wrapping_add(1);
+                res.push(item);
+            }
+        }
+        self.live = 0;
+        res
+    }
+}
@@ -1,4 +1,7 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+};
 
 use rand::Rng;
 
@@ -6,19 +9,49 @@ use rand::Rng;
 // On allocation: find the first free block with at least n bytes of size,
 // take only n bytes.
 // On free: free the block, merge freed block with other free blocks nearby
+// Blocks start at multiples of HEAP_ALIGN (or of the `allocal` alignment),
+// the bytes skipped to get there stay in the free list as their own block
+// and merge back when their neighbours are freed.
 use crate::{
     gc::GcObject,
+    memcap::mem_reserve,
+    memprof::record_heap,
     misclib::{args_to_f64, args_to_i64, args_to_u64, bytes_into_string_utf16, pad_to, show_runtime_err, vec16_into_vec8, RegTFromU32},
     registers::Register,
+    scratch::{is_heap_ptr, mem_read, mem_write},
+    segments::SegmKind,
     vm::{RegTypes, VM},
 };
 
+/// Alignment of every block start
+pub const HEAP_ALIGN: usize = 8;
+
 #[derive(Debug)]
 pub struct Heap {
     pub heap: Vec<u8>,
     pub free_list: Vec<HeapBlock>,
     pub allocated: Vec<HeapBlock>,
     pub saved_refs: HashMap<u64, HashSet<u64>>, // source -> tgt
+    ref_slots: HashMap<u64, HashMap<u64, u64>>, // source -> (slot -> tgt)
+    pinned: HashMap<u64, u64>,                  // block start -> pin count
+    shared: HashMap<u64, u64>,                  // shared region start -> size, see nativeshm.rs
+    size: usize,
+    alloc_count: u64,                           // successful allocs since start
+    used: u64,                                  // bytes in allocated blocks
+    pub debug_sites: bool,                      // --heap-debug: remember allocation sites
+    free_count: u64,
+}
+
+/// Heap usage snapshot, sizes are in bytes
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub total: u64,
+    pub used: u64,
+    pub free: u64,
+    pub largest_free: u64,
+    pub live_blocks: u64,
+    pub alloc_count: u64,
+    pub free_count: u64,
 }
 
 impl Heap {
@@ -31,37 +64,86 @@ impl Heap {
             free_list: freelist,
             allocated: alloced_list,
             saved_refs: HashMap::new(),
+            ref_slots: HashMap::new(),
+            pinned: HashMap::new(),
+            shared: HashMap::new(),
+            size: heap_size,
+            alloc_count: 0,
+            used: 0,
+            debug_sites: false,
+            free_count: 0,
         }
     }
+
+    pub fn used_bytes(&self) -> u64 {
+        self.used
+    }
+
+    /// `alloc` that records `site` into the block in --heap-debug mode
+    pub fn alloc_at(&mut self, count_bytes: usize, site: AllocSite) -> Option<u64> {
+        self.alloc_aligned_at(count_bytes, HEAP_ALIGN, site)
+    }
+
+    /// `alloc_aligned` that records `site` into the block in --heap-debug mode
+    pub fn alloc_aligned_at(&mut self, count_bytes: usize, align: usize, site: AllocSite) -> Option<u64> {
+        let ptr: u64 = self.alloc_aligned(count_bytes, align)?;
+        if self.debug_sites {
+            if let Some(block) = self.allocated.last_mut() {
+                block.site = Some(site);
+            }
+        }
+        Some(ptr)
+    }
+
     pub fn alloc(&mut self, count_bytes: usize) -> Option<u64> {
-        // Strategy: find first free block with at least `count_bytes` size;
-        // Take only the needed part.
+        self.alloc_aligned(count_bytes, HEAP_ALIGN)
+    }
+
+    /// Block starting at a multiple of `align` (a power of two, HEAP_ALIGN
+    /// at least)
+    pub fn alloc_aligned(&mut self, count_bytes: usize, align: usize) -> Option<u64> {
+        // Strategy: find first free block with at least `count_bytes` size
+        // past its first aligned byte; Take only the needed part.
+        let align: usize = align.max(HEAP_ALIGN);
         for (ind, free_block) in self.free_list.iter_mut().enumerate() {
-            if free_block.size >= count_bytes {
-                let start_ptr = free_block.start_byte;
-                let end_ptr = start_ptr + count_bytes;
+            let start_ptr: usize = free_block.start_byte.next_multiple_of(align);
+            let end_ptr: usize = match start_ptr.checked_add(count_bytes) {
+                Some(end) if end <= free_block.last_byte => end,
+                _ => continue,
+            };
+            let pad_start: usize = free_block.start_byte;
 
-                let new_alloc = HeapBlock::new(start_ptr, end_ptr);
-                self.allocated.push(new_alloc);
+            let mut new_alloc = HeapBlock::new(start_ptr, end_ptr);
+            new_alloc.id = self.alloc_count + 1;
+            self.allocated.push(new_alloc);
 
-                if (free_block.last_byte.saturating_sub(end_ptr) == 0) {
-                    let _ = self.free_list.remove(ind);
-                } else {
-                    free_block.realloc(end_ptr + 1, free_block.last_byte);
-                }
-
-                return Some(start_ptr as u64);
+            if (free_block.last_byte.saturating_sub(end_ptr) == 0) {
+                let _ = self.free_list.remove(ind);
+            } else {
+                free_block.realloc(end_ptr + 1, free_block.last_byte);
+            }
+            if start_ptr > pad_start {
+                // alignment padding stays free, before the rest of the block
+                self.free_list.insert(ind, HeapBlock::new(pad_start, start_ptr - 1));
             }
+
+            self.alloc_count += 1;
+            self.used += count_bytes as u64;
+            return Some(start_ptr as u64);
         }
         return None;
     }
 
     pub fn free(&mut self, ptr: u64) -> Result<(), ()> {
         // Strategy: free the block, merge with near free blocks.
+        if self.shared.contains_key(&ptr) {
+            return Err(()); // plugins may hold pointers into it
+        }
         let mut freed_end: Option<usize> = None;
         let mut to_free: Option<usize> = None;
         for (ind, alloced_block) in self.allocated.iter().enumerate() {
             if alloced_block.start_byte == ptr as usize {
+                self.used -= alloced_block.size as u64;
                 freed_end = Some(alloced_block.last_byte);
                 to_free = Some(ind);
                 break;
@@ -71,6 +153,11 @@ impl Heap {
             return Err(());
         }
         self.allocated.remove(to_free.unwrap());
+        self.free_count += 1;
+        // outgoing edges of the freed block are dead now
+        self.saved_refs.remove(&ptr);
+        self.ref_slots.remove(&ptr);
+        self.pinned.remove(&ptr);
 
         //Merging free blocks for less fragmentation
         let new_free_block: HeapBlock = HeapBlock::new(ptr as usize, freed_end.unwrap());
@@ -144,6 +231,7 @@ impl Heap {
                     }
                     self.heap[(ptr as usize) + ind] = *byte_towrite;
                 }
+                self.clear_refs_in(ptr, last_towrite);
                 return Ok(());
             }
         }
@@ -172,6 +260,20 @@ impl Heap {
         Err(())
     }
         
+    /// Borrowing `read`
+    pub fn slice(&self, ptr: u64, count_bytes: u64) -> Result<&[u8], ()> {
+        let last_toread = ptr + count_bytes.saturating_sub(1);
+        for alloced_block in &self.allocated {
+            if (ptr >= alloced_block.start_byte as u64)
+                && (ptr <= alloced_block.last_byte as u64)
+                && (last_toread <= alloced_block.last_byte as u64)
+            {
+                return self.heap.get((ptr as usize)..((ptr + count_bytes) as usize)).ok_or(());
+            }
+        }
+        Err(())
+    }
+
     pub fn copy(&mut self, from_st: usize, from_end: usize, 
                 to_ptr: usize) 
         -> Result<(), HeapError> {
@@ -202,26 +304,165 @@ impl Heap {
                 return Err(HeapError::Overflow);
             }
             
+            // pointers stored in the copied range are now stored at dst too.
+            // Taken before the write, which clears the edges it overwrites
+            // (the source ones too when the ranges overlap)
+            let src_owner = found_start.unwrap() as u64;
+            let copied: Vec<(u64, u64)> = match self.ref_slots.get(&src_owner) {
+                Some(slots) => slots
+                    .iter()
+                    .filter(|(slot, _)| {
+                        (**slot >= from_st as u64) && (**slot + 8 <= from_end as u64)
+                    })
+                    .map(|(slot, tgt)| (*slot, *tgt))
+                    .collect(),
+                None => Vec::new(),
+            };
+
             match self.write(to_ptr as u64, self.heap[from_st..from_end].to_vec()) {
                 Ok(()) => {},
                 Err(_) => {
                     return Err(HeapError::Write);
                 }
             }
-            
-            let mut refs_set: Option<HashSet<u64>> = None;
-            if let Some(v) = self.saved_refs
-                .get_mut(&(found_start.unwrap() as u64)) {
-                refs_set = Some(v.clone());
-                self.saved_refs.insert(found_start.unwrap() as u64,
-                    refs_set.unwrap());
+            for (slot, tgt) in copied {
+                self.set_ref(to_ptr as u64 + (slot - from_st as u64), tgt);
             }
-            
-            
+
             Ok(())
     }
 
+    /// Records that the 8 bytes at `slot` hold pointer `tgt`.
+    /// Overwrites the edge previously stored at that slot, if any.
+    pub fn set_ref(&mut self, slot: u64, tgt: u64) {
+        let owner: u64 = match self.owner_of(slot) {
+            Some(v) => v,
+            None => return,
+        };
+        self.ref_slots.entry(owner).or_default().insert(slot, tgt);
+        self.rebuild_refs(owner);
+    }
+
+    /// Drops every edge whose slot overlaps bytes [from, to).
+    /// Called on each heap write, so non-pointer stores kill old edges.
+    pub fn clear_refs_in(&mut self, from: u64, to: u64) {
+        let owner: u64 = match self.owner_of(from) {
+            Some(v) => v,
+            None => return,
+        };
+        let slots = match self.ref_slots.get_mut(&owner) {
+            Some(v) => v,
+            None => return,
+        };
+        let before = slots.len();
+        slots.retain(|slot, _| (*slot >= to) || (*slot + 8 <= from));
+        if slots.len() != before {
+            self.rebuild_refs(owner);
+        }
+    }
+
+    fn rebuild_refs(&mut self, owner: u64) {
+        let tgts: HashSet<u64> = match self.ref_slots.get(&owner) {
+            Some(slots) => slots.values().cloned().collect(),
+            None => HashSet::new(),
+        };
+        if tgts.is_empty() {
+            self.saved_refs.remove(&owner);
+            self.ref_slots.remove(&owner);
+        } else {
+            self.saved_refs.insert(owner, tgts);
+        }
+    }
+
+    /// Start of the allocated block containing `ptr`
+    pub fn owner_of(&self, ptr: u64) -> Option<u64> {
+        self.allocated
+            .iter()
+            .find(|b| (ptr >= b.start_byte as u64) && (ptr <= b.last_byte as u64))
+            .map(|b| b.start_byte as u64)
+    }
+
+    /// Bytes of the allocated block containing `ptr` from `ptr` to its end
+    pub fn bytes_from(&self, ptr: u64) -> Option<u64> {
+        self.allocated
+            .iter()
+            .find(|b| (ptr >= b.start_byte as u64) && (ptr < (b.start_byte + b.size) as u64))
+            .map(|b| (b.start_byte + b.size) as u64 - ptr)
+    }
+
+    /// Pins the block containing ptr: GC treats it as a root and
+    /// it must never be moved. Pins are counted, each needs an unpin.
+    pub fn pin(&mut self, ptr: u64) -> Result<(), ()> {
+        let owner: u64 = self.owner_of(ptr).ok_or(())?;
+        *self.pinned.entry(owner).or_insert(0) += 1;
+        Ok(())
+    }
+
+    pub fn unpin(&mut self, ptr: u64) -> Result<(), ()> {
+        let owner: u64 = self.owner_of(ptr).ok_or(())?;
+        match self.pinned.get_mut(&owner) {
+            Some(count) if *count > 1 => *count -= 1,
+            Some(_) => {
+                self.pinned.remove(&owner);
+            }
+            None => return Err(()),
+        }
+        Ok(())
+    }
+
+    pub fn pinned_ptrs(&self) -> HashSet<u64> {
+        self.pinned.keys().cloned().collect()
+    }
+
+    /// Shares the block starting at `ptr` with native plugins: zeroes and
+    /// pins it, `free` refuses it until `unshare`
+    pub fn share(&mut self, ptr: u64) -> Result<(), ()> {
+        let block: &HeapBlock = self.allocated.iter().find(|b| b.start_byte as u64 == ptr).ok_or(())?;
+        let (start, end): (usize, usize) = (block.start_byte, block.start_byte + block.size);
+        // the bytes have to exist for the raw pointer, the buffer never grows past its capacity
+        if self.heap.len() < end {
+            self.heap.resize(end, 0);
+        }
+        self.heap[start..end].fill(0);
+        self.pin(ptr)?;
+        self.shared.insert(ptr, (end - start) as u64);
+        Ok(())
+    }
+
+    pub fn unshare(&mut self, ptr: u64) -> Result<(), ()> {
+        self.shared.remove(&ptr).ok_or(())?;
+        self.unpin(ptr)
+    }
+
+    /// Start and size of the shared region containing `ptr`
+    pub fn shared_region(&self, ptr: u64) -> Option<(u64, u64)> {
+        self.shared
+            .iter()
+            .find(|(start, size)| (ptr >= **start) && (ptr < **start + **size))
+            .map(|(start, size)| (*start, *size))
+    }
+
+    /// Host pointer to the shared region containing `ptr` and the region size
+    pub fn shared_host_ptr(&mut self, ptr: u64) -> Option<(*mut u8, u64)> {
+        let (start, size) = self.shared_region(ptr)?;
+        Some((self.heap[(start as usize)..].as_mut_ptr(), size))
+    }
+
     // for tests
+    pub fn stats(&self) -> HeapStats {
+        let used: u64 = self.allocated.iter().map(|b| b.size as u64).sum();
+        let free: u64 = self.free_list.iter().map(|b| b.size as u64).sum();
+        HeapStats {
+            total: self.size as u64,
+            used: used,
+            free: free,
+            largest_free: self.free_list.iter().map(|b| b.size as u64).max().unwrap_or(0),
+            live_blocks: self.allocated.len() as u64,
+            alloc_count: self.alloc_count,
+            free_count: self.free_count,
+        }
+    }
+
     pub fn stress_heap(&mut self) {
         for _ in 0..10000 {
             let size_alloc = self.random_8_to_256() as u64;
@@ -262,11 +503,20 @@ pub enum HeapError {
     Write
 }
 
+/// Where a block was allocated: instruction and the function executing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AllocSite {
+    pub ip: u64,
+    pub func: Option<usize>, // None outside of calls
+}
+
 #[derive(Debug)]
 pub struct HeapBlock {
     pub start_byte: usize,
     pub last_byte: usize,
     pub size: usize, // last - start
+    pub id: u64,     // allocation number, ids aren't reused; 0 for free blocks
+    pub site: Option<AllocSite>, // --heap-debug only
 }
 
 impl HeapBlock {
@@ -278,6 +528,8 @@ impl HeapBlock {
             start_byte: start,
             last_byte: end,
             size: end - start,
+            id: 0,
+            site: None,
         }
     }
     pub fn realloc(&mut self, start: usize, end: usize) {
@@ -296,6 +548,61 @@ impl HeapBlock {
     }
 }
 
+const ALLOC_FAULT_SITES: usize = 5; // biggest sites listed in a fault report
+
+/// Raises HeapAllocationFault, with --heap-debug also reports
+/// which allocation sites hold the heap
+/// GC managed block for ncalls, fails the way `alloc` does
+pub fn gc_alloc(vm: &mut VM, size_bytes: u64) -> Option<u64> {
+    if !mem_reserve(vm, size_bytes) {
+        return None;
+    }
+    match vm.heap.alloc_at(size_bytes as usize, vm.alloc_site()) {
+        Some(addr) => {
+            vm.gc.pin_object(GcObject::new(addr));
+            Some(addr)
+        }
+        None => {
+            alloc_fault(vm, size_bytes);
+            None
+        }
+    }
+}
+
+pub(crate) fn alloc_fault(vm: &mut VM, size_bytes: u64) {
+    vm.exceptions_active.raise_with(crate::exceptions::Exception::HeapAllocationFault, size_bytes);
+    if !vm.heap.debug_sites {
+        return;
+    }
+    let stats: HeapStats = vm.heap.stats();
+    let mut report: Vec<String> = vec![format!(
+        "heap: can't allocate {} bytes at {}: {} bytes in {} live blocks, largest free {}",
+        size_bytes,
+        vm.site_desc(&vm.alloc_site()),
+        stats.used,
+        stats.live_blocks,
+        stats.largest_free
+    )];
+    let mut by_site: HashMap<Option<AllocSite>, (u64, u64)> = HashMap::new(); // site -> (bytes, blocks)
+    for block in &vm.heap.allocated {
+        let entry = by_site.entry(block.site).or_insert((0, 0));
+        entry.0 += block.size as u64;
+        entry.1 += 1;
+    }
+    let mut sites: Vec<(Option<AllocSite>, (u64, u64))> = by_site.into_iter().collect();
+    sites.sort_by_key(|(site, (bytes, _))| (std::cmp::Reverse(*bytes), site.map(|s| s.ip)));
+    for (site, (bytes, blocks)) in sites.iter().take(ALLOC_FAULT_SITES) {
+        let from: String = match site {
+            Some(s) => vm.site_desc(s),
+            None => "unknown sites".to_string(),
+        };
+        report.push(format!("  {} bytes in {} blocks from {}", bytes, blocks, from));
+    }
+    for line in report {
+        let _ = writeln!(vm.output.stderr, "{}", line);
+    }
+}
+
 pub fn op_alloc(vm: &mut VM) {
     // 0xA0, size: 10
     let instr_size: usize = 10;
@@ -306,11 +613,17 @@ pub fn op_alloc(vm: &mut VM) {
     let r_dest_ind: usize = vm.memory[(vm.ip + 1)] as usize;
     let size_bytes: u64 = args_to_u64(&vm.memory[(vm.ip + 2)..(vm.ip + 10)]);
 
-    let res = match vm.heap.alloc(size_bytes as usize) {
+    if !mem_reserve(vm, size_bytes) {
+        vm.registers[r_dest_ind] = Register::address(0);
+        vm.reg_types[r_dest_ind] = RegTypes::address;
+        vm.ip += instr_size;
+        return;
+    }
+
+    let res = match vm.heap.alloc_at(size_bytes as usize, vm.alloc_site()) {
         Some(addr) => addr,
         None => {
-            vm.exceptions_active
-                .push(crate::exceptions::Exception::HeapAllocationFault);
+            alloc_fault(vm, size_bytes);
             0
         }
     };
@@ -332,11 +645,17 @@ pub fn op_allocr(vm: &mut VM) {
     let r_size_ind: usize = vm.memory[(vm.ip + 2)] as usize;
     let size_bytes: u64 = vm.registers[r_size_ind].as_u64();
 
-    let res = match vm.heap.alloc(size_bytes as usize) {
+    if !mem_reserve(vm, size_bytes) {
+        vm.registers[r_dest_ind] = Register::address(0);
+        vm.reg_types[r_dest_ind] = RegTypes::address;
+        vm.ip += 3;
+        return;
+    }
+
+    let res = match vm.heap.alloc_at(size_bytes as usize, vm.alloc_site()) {
         Some(addr) => addr,
         None => {
-            vm.exceptions_active
-                .push(crate::exceptions::Exception::HeapAllocationFault);
+            alloc_fault(vm, size_bytes);
             0
         }
     };
@@ -359,11 +678,17 @@ pub fn op_allocr_nogc(vm: &mut VM) {
     let r_size_ind: usize = vm.memory[(vm.ip + 2)] as usize;
     let size_bytes: u64 = vm.registers[r_size_ind].as_u64();
 
-    let res = match vm.heap.alloc(size_bytes as usize) {
+    if !mem_reserve(vm, size_bytes) {
+        vm.registers[r_dest_ind] = Register::address(0);
+        vm.reg_types[r_dest_ind] = RegTypes::address;
+        vm.ip += 3;
+        return;
+    }
+
+    let res = match vm.heap.alloc_at(size_bytes as usize, vm.alloc_site()) {
         Some(addr) => addr,
         None => {
-            vm.exceptions_active
-                .push(crate::exceptions::Exception::HeapAllocationFault);
+            alloc_fault(vm, size_bytes);
             0
         }
     };
@@ -375,6 +700,74 @@ pub fn op_allocr_nogc(vm: &mut VM) {
     return;
 }
 
+pub fn op_gcadopt(vm: &mut VM) {
+    // 0xFE 0x02, size: 3
+    // gcadopt Rptr
+    // hands the manual block starting at Rptr (`allocr_nogc`, `gcrelease`d)
+    // to GC control, it's collected once unreachable.
+    // HeapSegmFault if Rptr isn't the start of a block, the block is a
+    // shared region or the GC already owns it
+    let r_ptr_ind: usize = vm.memory[vm.ip + 2] as usize;
+    let ptr: u64 = vm.registers[r_ptr_ind].as_u64();
+    let adoptable: bool = (vm.heap.owner_of(ptr) == Some(ptr))
+        && vm.heap.shared_region(ptr).is_none()
+        && !vm.gc.owns(ptr);
+    match adoptable {
+        true => vm.gc.pin_object(GcObject::new(ptr)),
+        false => vm.exceptions_active.raise_with(crate::exceptions::Exception::HeapSegmFault, ptr),
+    }
+    vm.ip += 3;
+}
+
+pub fn op_gcrelease(vm: &mut VM) {
+    // 0xFE 0x03, size: 3
+    // gcrelease Rptr
+    // takes the block starting at Rptr out of GC control, from now on it
+    // lives until `free`. HeapSegmFault if the GC doesn't own such a block
+    let r_ptr_ind: usize = vm.memory[vm.ip + 2] as usize;
+    let ptr: u64 = vm.registers[r_ptr_ind].as_u64();
+    match vm.gc.owns(ptr) {
+        true => vm.gc.forget(ptr),
+        false => vm.exceptions_active.raise_with(crate::exceptions::Exception::HeapSegmFault, ptr),
+    }
+    vm.ip += 3;
+}
+
+pub fn op_allocal(vm: &mut VM) {
+    // 0xAE, size: 4
+    // allocal Rdest Rsize Ralign
+    // `allocr` of a block starting at a multiple of Ralign, a power of two;
+    // alignments under 8 give 8 like every allocation.
+    // The object goes to GC control
+    let r_dest_ind: usize = vm.memory[vm.ip + 1] as usize;
+    let r_size_ind: usize = vm.memory[vm.ip + 2] as usize;
+    let r_align_ind: usize = vm.memory[vm.ip + 3] as usize;
+    let size_bytes: u64 = vm.registers[r_size_ind].as_u64();
+    let align: u64 = vm.registers[r_align_ind].as_u64();
+
+    vm.registers[r_dest_ind] = Register::address(0);
+    vm.reg_types[r_dest_ind] = RegTypes::address;
+    vm.ip += 4;
+    if !align.is_power_of_two() {
+        vm.exceptions_active.push(crate::exceptions::Exception::HeapAllocationFault);
+        return;
+    }
+    if !mem_reserve(vm, size_bytes) {
+        return;
+    }
+
+    let res = match vm.heap.alloc_aligned_at(size_bytes as usize, align as usize, vm.alloc_site()) {
+        Some(addr) => addr,
+        None => {
+            alloc_fault(vm, size_bytes);
+            0
+        }
+    };
+    vm.gc.pin_object(GcObject::new(res));
+
+    vm.registers[r_dest_ind] = Register::address(res);
+}
+
 pub fn op_free(vm: &mut VM) {
     // 0xA1, size: 2
     // free Rsrc
@@ -383,10 +776,13 @@ pub fn op_free(vm: &mut VM) {
 
     let r_src_val = vm.registers[r_src_ind];
     match vm.heap.free(r_src_val.as_u64()) {
-        Ok(()) => {}
+        Ok(()) => {
+            // manually freed, so the GC must not free this address again
+            vm.gc.forget(r_src_val.as_u64());
+        }
         Err(()) => {
             vm.exceptions_active
-                .push(crate::exceptions::Exception::HeapFreeFault);
+                .raise_with(crate::exceptions::Exception::HeapFreeFault, r_src_val.as_u64());
         }
     }
 
@@ -404,15 +800,39 @@ pub fn op_store(vm: &mut VM) {
     let r_dest_ind: usize = vm.memory[(vm.ip + 1)] as usize;
     let r_count_ind: usize = vm.memory[(vm.ip + 3)] as usize;
 
+    let ptr: u64 = vm.registers[r_dest_ind].as_u64();
+    store_at(vm, ptr, r_src_ind, r_count_ind);
+
+    vm.ip += instr_size;
+}
+
+pub fn op_storei(vm: &mut VM) {
+    // 0xAF, size: 12
+    let instr_size: usize = 12;
+    // storei [Rdest+imm] Rsrc Rcount
+    // store with the address Rdest + imm (i64, may be negative),
+    // saves the instructions computing struct field addresses
+    let r_dest_ind: usize = vm.memory[vm.ip + 1] as usize;
+    let offset: i64 = args_to_i64(&vm.memory[(vm.ip + 2)..(vm.ip + 10)]);
+    let r_src_ind: usize = vm.memory[vm.ip + 10] as usize;
+    let r_count_ind: usize = vm.memory[vm.ip + 11] as usize;
+
+    let ptr: u64 = vm.registers[r_dest_ind].as_u64().wrapping_add_signed(offset);
+    store_at(vm, ptr, r_src_ind, r_count_ind);
+
+    vm.ip += instr_size;
+}
+
+/// Stores the first Rcount (1..8) bytes of Rsrc at `ptr`
+fn store_at(vm: &mut VM, ptr: u64, r_src_ind: usize, r_count_ind: usize) {
     let val: u64 = vm.registers[r_src_ind].as_u64_bitwise();
     let count: usize = (vm.registers[r_count_ind].as_u64() as usize).clamp(1, 8);
 
-    let ptr: u64 = vm.registers[r_dest_ind].as_u64();
     let write_vec = val.to_be_bytes();
-    match vm.heap.write(ptr, write_vec[0..count].to_vec()) {
+    match mem_write(vm, ptr, write_vec[0..count].to_vec()) {
         Ok(()) => {
-            if (vm.reg_types[r_src_ind] == RegTypes::address) {
-                vm.heap.saved_refs.entry(ptr).or_default().insert(val);
+            if (vm.reg_types[r_src_ind] == RegTypes::address) && is_heap_ptr(ptr) {
+                vm.heap.set_ref(ptr, val);
             }
         }
         Err(()) => {
@@ -420,8 +840,6 @@ pub fn op_store(vm: &mut VM) {
                 .push(crate::exceptions::Exception::HeapWriteFault);
         }
     }
-
-    vm.ip += instr_size;
 }
 
 pub fn op_load(vm: &mut VM) {
@@ -435,15 +853,38 @@ pub fn op_load(vm: &mut VM) {
     let r_src_ind: usize = vm.memory[(vm.ip + 3)] as usize;
     let r_count_ind: usize = vm.memory[(vm.ip + 4)] as usize;
 
-    let type_ind: u64 = vm.registers[r_type_ind].as_u64();
     let addr: u64 = vm.registers[r_src_ind].as_u64();
+    load_at(vm, r_type_ind, r_dst_ind, addr, r_count_ind);
+
+    vm.ip += instr_size;
+}
+
+pub fn op_loadi(vm: &mut VM) {
+    // 0xB0, size: 13
+    let instr_size: usize = 13;
+    // loadi Rtype Rdst [Rsrc+imm] Rcount
+    // load from the address Rsrc + imm (i64, may be negative)
+    let r_type_ind: usize = vm.memory[vm.ip + 1] as usize;
+    let r_dst_ind: usize = vm.memory[vm.ip + 2] as usize;
+    let r_src_ind: usize = vm.memory[vm.ip + 3] as usize;
+    let offset: i64 = args_to_i64(&vm.memory[(vm.ip + 4)..(vm.ip + 12)]);
+    let r_count_ind: usize = vm.memory[vm.ip + 12] as usize;
+
+    let addr: u64 = vm.registers[r_src_ind].as_u64().wrapping_add_signed(offset);
+    load_at(vm, r_type_ind, r_dst_ind, addr, r_count_ind);
+
+    vm.ip += instr_size;
+}
+
+/// Loads Rcount (1..8) bytes at `addr` into Rdst as the type in Rtype
+fn load_at(vm: &mut VM, r_type_ind: usize, r_dst_ind: usize, addr: u64, r_count_ind: usize) {
+    let type_ind: u64 = vm.registers[r_type_ind].as_u64();
     let count: u64 = vm.registers[r_count_ind].as_u64().clamp(1, 8);
-    let mut res_bytes: Vec<u8> = match vm.heap.read(addr, count) {
+    let mut res_bytes: Vec<u8> = match mem_read(vm, addr, count) {
         Ok(vec) => vec,
         Err(_) => {
             vm.exceptions_active
                 .push(crate::exceptions::Exception::HeapReadFault);
-            vm.ip += instr_size;
             return;
         }
     };
@@ -460,8 +901,8 @@ pub fn op_load(vm: &mut VM) {
                 0x8 => RegTypes::address,
                 0x9 => RegTypes::ds_addr,
                 _ => panic!(
-                    "Type {} is incorrect for `load 1 r{} r{}`, at IP = {}",
-                    val, r_dst_ind, r_src_ind, vm.ip
+                    "Type {} is incorrect for `load` into r{}, at IP = {}",
+                    val, r_dst_ind, vm.ip
                 ),
             };
         }
@@ -484,6 +925,65 @@ pub fn op_load(vm: &mut VM) {
             );
         }
     }
+}
+
+pub fn op_store32(vm: &mut VM) {
+    // 0xAB, size: 3
+    let instr_size: usize = 3;
+    // store32 Rdest Rsrc
+    // stores Rsrc narrowed to 32 bits by its type
+    // (uint -> u32, int -> i32, float -> f32) in heap addr.
+    let r_dest_ind: usize = vm.memory[(vm.ip + 1)] as usize;
+    let r_src_ind: usize = vm.memory[(vm.ip + 2)] as usize;
+
+    let src: Register = vm.registers[r_src_ind];
+    let write_vec: [u8; 4] = match vm.reg_types[r_src_ind] {
+        RegTypes::int64 => (src.as_i64() as i32).to_be_bytes(),
+        RegTypes::float64 => (src.as_f64() as f32).to_be_bytes(),
+        _ => (src.as_u64() as u32).to_be_bytes(),
+    };
+    let ptr: u64 = vm.registers[r_dest_ind].as_u64();
+    if let Err(()) = mem_write(vm, ptr, write_vec.to_vec()) {
+        vm.exceptions_active
+            .push(crate::exceptions::Exception::HeapWriteFault);
+    }
+
+    vm.ip += instr_size;
+}
+
+pub fn op_load32(vm: &mut VM) {
+    // 0xAA, size: 4
+    let instr_size: usize = 4;
+    // load32 rtype rdst rsrc
+    // loads 4 bytes as u32 (type 1), i32 (2) or f32 (3)
+    // widening them into 64-bit rdst
+    let r_type_ind: usize = vm.memory[(vm.ip + 1)] as usize;
+    let r_dst_ind: usize = vm.memory[(vm.ip + 2)] as usize;
+    let r_src_ind: usize = vm.memory[(vm.ip + 3)] as usize;
+
+    let type_ind: u64 = vm.registers[r_type_ind].as_u64();
+    let addr: u64 = vm.registers[r_src_ind].as_u64();
+    let bytes: [u8; 4] = match mem_read(vm, addr, 4) {
+        Ok(vec) => vec.try_into().unwrap(),
+        Err(_) => {
+            vm.exceptions_active
+                .push(crate::exceptions::Exception::HeapReadFault);
+            vm.ip += instr_size;
+            return;
+        }
+    };
+
+    (vm.registers[r_dst_ind], vm.reg_types[r_dst_ind]) = match type_ind {
+        0x1 => (Register::uint(u32::from_be_bytes(bytes) as u64), RegTypes::uint64),
+        0x2 => (Register::int(i32::from_be_bytes(bytes) as i64), RegTypes::int64),
+        0x3 => (Register::float(f32::from_be_bytes(bytes) as f64), RegTypes::float64),
+        other => {
+            panic!(
+                "Type {} is incorrect for `load32` instruction, at IP = {}",
+                other, vm.ip
+            );
+        }
+    };
 
     vm.ip += instr_size;
 }
@@ -505,7 +1005,20 @@ pub fn op_memcpy(vm: &mut VM) {
         .as_u64() as usize;
 
     let src_end: usize = src_ptr + count;
-    
+
+    if !is_heap_ptr(src_ptr as u64) || !is_heap_ptr(dst_ptr as u64) {
+        // scratch and mapped files hold no tracked pointers, a plain byte copy
+        let copied = mem_read(vm, src_ptr as u64, count as u64)
+            .and_then(|bytes| mem_write(vm, dst_ptr as u64, bytes));
+        if copied.is_err() {
+            vm.exceptions_active.push(crate::exceptions::Exception::HeapSegmFault);
+        }
+        vm.ip += instr_size;
+        return;
+    }
+
+    record_heap(vm, src_ptr as u64, false);
+    record_heap(vm, dst_ptr as u64, true);
     match vm.heap.copy(src_ptr, src_end, dst_ptr) {
         Ok(()) => {},
         Err(e) => {
@@ -535,14 +1048,13 @@ pub fn op_storedat(vm: &mut VM) {
         .as_u64() as usize;
     
     let from_end = from_ptr + count;
-    if (vm.memory.len() < from_end) {
-        eprintln!("ERROR: memory len ({:#x}) <= from_end (={:#x}) at ip = {:#x}", vm.memory.len(), from_end, vm.ip);
-        vm.exceptions_active.push(crate::exceptions::Exception::MainSegmFault);
+    if !vm.segm_check(from_ptr, count, false) {
         vm.ip += instr_size;
         return;
-    } 
+    }
 
     let tocopy = vm.memory[from_ptr..from_end].to_vec();
+    record_heap(vm, to_ptr as u64, true);
     match vm.heap.write(to_ptr as u64, tocopy) {
         Ok(()) => {},
         Err(()) => {
@@ -570,6 +1082,10 @@ pub fn op_dlbc(vm: &mut VM) {
     let from_ptr: u64 = vm.registers[rsrc_ind].as_u64();
     let count: u64 = vm.registers[rcount_ind].as_u64();
 
+    if !mem_reserve(vm, count) {
+        vm.ip += instr_size;
+        return;
+    }
     if vm.memory.capacity() < (vm.memory.len() + (count as usize)) {
         eprintln!("Attempting to overflow main memory at IP = {:#x}", vm.ip);
         vm.exceptions_active.push(crate::exceptions::Exception::MainSegmFault);
@@ -587,8 +1103,10 @@ pub fn op_dlbc(vm: &mut VM) {
         } 
     };
 
-    vm.registers[rdst_ind] = Register::ds_addr(vm.memory.len() as u64);
+    let start: usize = vm.memory.len();
+    vm.registers[rdst_ind] = Register::ds_addr(start as u64);
     vm.memory.extend(bytes.iter());
+    vm.segments.push(SegmKind::Dynamic, start, vm.memory.len());
 
     vm.ip += instr_size;
 }
@@ -0,0 +1,145 @@
+use std::{collections::HashSet, io::Write};
+
+use crate::{
+    exceptions::Exception,
+    heap::AllocSite,
+    misclib::string_from_straddr,
+    nativeerr::{native_fault, NativeErrKind, NativeError, NativeSubsys},
+    registers::Register,
+    vm::{RegTypes, VM},
+};
+
+// Named heap snapshots for leak hunting. A snapshot records every live
+// block with its size, allocation id and site (ip of the alloc instruction)
+// and whether the GC could reach it at that point. Diffing A and B reports
+// blocks allocated after A that are still live at B. Sites are recorded
+// with --heap-debug only.
+// Snapshot names are strings (r1 = StrAddr) or numbers.
+
+#[derive(Debug, Clone)]
+pub struct SnapBlock {
+    pub ptr: u64,
+    pub size: u64,
+    pub id: u64, // alloc id
+    pub site: Option<AllocSite>,
+    pub reachable: bool,
+}
+
+fn set_uint(vm: &mut VM, ind: usize, val: u64) {
+    vm.registers[ind] = Register::uint(val);
+    vm.reg_types[ind] = RegTypes::uint64;
+}
+
+fn snap_name(vm: &mut VM, ind: usize) -> Option<String> {
+    match vm.registers[ind] {
+        Register::StrAddr(addr) => string_from_straddr(vm, addr),
+        reg => Some(reg.as_u64_bitwise().to_string()),
+    }
+}
+
+fn unknown_snap(vm: &mut VM, name: &str) {
+    native_fault(
+        vm,
+        NativeError::new(NativeSubsys::Debug, NativeErrKind::NotFound),
+        Exception::NativeFault,
+        &format!("No heap snapshot named '{}'", name),
+    );
+}
+
+/// Live blocks of the heap right now, ordered by address
+pub fn take_snapshot(vm: &mut VM) -> Vec<SnapBlock> {
+    let reachable: HashSet<u64> = vm.heap_reachable();
+    let mut res: Vec<SnapBlock> = vm
+        .heap
+        .allocated
+        .iter()
+        .map(|b| {
+            let ptr: u64 = b.start_byte as u64;
+            SnapBlock {
+                ptr,
+                size: b.size as u64,
+                id: b.id,
+                site: b.site,
+                reachable: reachable.contains(&ptr),
+            }
+        })
+        .collect();
+    res.sort_by_key(|b| b.ptr);
+    res
+}
+
+/// Blocks of `after` allocated after `before` was taken
+pub fn snapshot_diff<'a>(before: &[SnapBlock], after: &'a [SnapBlock]) -> Vec<&'a SnapBlock> {
+    let old: HashSet<u64> = before.iter().map(|b| b.id).collect();
+    after.iter().filter(|b| !old.contains(&b.id)).collect()
+}
+
+/// ncall 0x40
+/// r1 - snapshot name, an existing one is replaced;
+/// returns count of live blocks into r0
+pub fn ncall_heap_snap(vm: &mut VM) {
+    let name: String = match snap_name(vm, 1) {
+        Some(n) => n,
+        None => return unknown_snap(vm, "<invalid string>"),
+    };
+    let snap: Vec<SnapBlock> = take_snapshot(vm);
+    let count: u64 = snap.len() as u64;
+    vm.heap_snaps.insert(name, snap);
+    set_uint(vm, 0, count);
+}
+
+/// ncall 0x41
+/// r1 - snapshot A, r2 - snapshot B, r3 - stream to print blocks into
+/// (1 - stdout, 2 - stderr, anything else - don't print);
+/// returns count of blocks allocated after A and live at B into r0, their bytes into r1
+pub fn ncall_heap_diff(vm: &mut VM) {
+    let (name_a, name_b) = match (snap_name(vm, 1), snap_name(vm, 2)) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return unknown_snap(vm, "<invalid string>"),
+    };
+    for name in [&name_a, &name_b] {
+        if !vm.heap_snaps.contains_key(name) {
+            return unknown_snap(vm, name);
+        }
+    }
+    let stream_id: u64 = vm.registers[3].as_u64_bitwise();
+    let (snap_a, snap_b) = (&vm.heap_snaps[&name_a], &vm.heap_snaps[&name_b]);
+    let grown: Vec<&SnapBlock> = snapshot_diff(snap_a, snap_b);
+    let bytes: u64 = grown.iter().map(|b| b.size).sum();
+
+    let mut report: Vec<String> = Vec::with_capacity(grown.len() + 1);
+    report.push(format!(
+        "heap diff {} -> {}: {} blocks, {} bytes still live",
+        name_a,
+        name_b,
+        grown.len(),
+        bytes
+    ));
+    for b in &grown {
+        let site: String = match &b.site {
+            Some(s) => format!("alloc at {}", vm.site_desc(s)),
+            None => "alloc site unknown".to_string(),
+        };
+        let state: &str = if b.reachable { "reachable" } else { "unreachable" };
+        report.push(format!("  {} bytes, {}, {}", b.size, site, state));
+    }
+    let count: u64 = grown.len() as u64;
+
+    if let Some(out) = vm.output.stream(stream_id) {
+        for line in report {
+            let _ = writeln!(out, "{}", line);
+        }
+    }
+    set_uint(vm, 0, count);
+    set_uint(vm, 1, bytes);
+}
+
+/// ncall 0x42
+/// r1 - snapshot name, forgets it; returns 1 into r0 if it existed, 0 otherwise
+pub fn ncall_heap_snap_drop(vm: &mut VM) {
+    let existed: bool = match snap_name(vm, 1) {
+        Some(name) => vm.heap_snaps.remove(&name).is_some(),
+        None => false,
+    };
+    set_uint(vm, 0, existed as u64);
+}
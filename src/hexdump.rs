@@ -0,0 +1,190 @@
+// `voxvm hexdump file.vve|file.vvr`: header fields, function table and
+// sections of a vve, then annotated hex of its code (rows break at function
+// entries) and data (one block per data variable with its type). Raw vvr
+// images have no header, they are dumped as code only.
+
+use std::{collections::HashMap, fs};
+
+use crate::{
+    dstype::DsType,
+    fileformats::{
+        read_symbols, ByteOrder, SECT_DATA_SYMBOLS, SECT_FUNC_META, SECT_INTERN, SECT_LINES, SECT_NCALLS, SECT_RELOCS,
+        SECT_RODATA, SECT_STACK_DEPTH, SECT_SYMBOLS, SECT_WORDS,
+    },
+    misclib::args_to_u64,
+    vvelink::VveImage,
+};
+
+const ROW: usize = 16; // bytes per hex row
+
+pub fn sect_name(kind: u16) -> &'static str {
+    match kind {
+        SECT_INTERN => "intern",
+        SECT_FUNC_META => "func_meta",
+        SECT_SYMBOLS => "symbols",
+        SECT_LINES => "lines",
+        SECT_RODATA => "rodata",
+        SECT_RELOCS => "relocs",
+        SECT_WORDS => "words",
+        SECT_NCALLS => "ncalls",
+        SECT_DATA_SYMBOLS => "data_syms",
+        SECT_STACK_DEPTH => "stack_depth",
+        _ => "unknown",
+    }
+}
+
+/// `addr  hex..  |ascii|` rows of `bytes`, `addr` is the address of bytes[0]
+fn hex_rows(bytes: &[u8], addr: usize) -> String {
+    let mut res: String = String::new();
+    for (i, row) in bytes.chunks(ROW).enumerate() {
+        let hex: Vec<String> = row.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = row
+            .iter()
+            .map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' })
+            .collect();
+        res.push_str(&format!(
+            "{:08x}  {:<width$}  |{}|\n",
+            addr + i * ROW,
+            hex.join(" "),
+            ascii,
+            width = ROW * 3 - 1
+        ));
+    }
+    res
+}
+
+/// Code hex, split at every labeled address (function entries, entry point)
+fn dump_code(code: &[u8], marks: &HashMap<usize, Vec<String>>) -> String {
+    let mut starts: Vec<usize> = marks.keys().copied().filter(|a| *a < code.len()).collect();
+    starts.push(0);
+    starts.sort();
+    starts.dedup();
+
+    let mut res: String = String::new();
+    for (i, start) in starts.iter().enumerate() {
+        let end: usize = starts.get(i + 1).copied().unwrap_or(code.len());
+        if let Some(names) = marks.get(start) {
+            res.push_str(&format!("<{}>:\n", names.join(", ")));
+        }
+        res.push_str(&hex_rows(&code[*start..end], *start));
+    }
+    res
+}
+
+/// Data hex, one block per variable: type byte, u64 length, payload.
+/// Zero bytes between variables are `!align=` padding.
+fn dump_data(data: &[u8], base: usize, ro_size: usize) -> String {
+    let mut res: String = String::new();
+    let mut pos: usize = 0;
+    while pos < data.len() {
+        let pad: usize = data[pos..].iter().take_while(|b| **b == 0).count();
+        if pad > 0 {
+            res.push_str(&format!("; padding, {} bytes\n", pad));
+            res.push_str(&hex_rows(&data[pos..(pos + pad)], base + pos));
+            pos += pad;
+            continue;
+        }
+        let type_ind: u8 = data[pos];
+        let len: Option<usize> = data.get((pos + 1)..(pos + 9)).map(|l| args_to_u64(l) as usize);
+        let end: Option<usize> = len.map(|l| pos + 9 + l).filter(|e| *e <= data.len());
+        let (name, end) = match (DsType::decode(type_ind).map(DsType::name), end) {
+            (Some(name), Some(end)) => (name, end),
+            _ => {
+                res.push_str(&format!("; unknown bytes at {:#x}\n", base + pos));
+                res.push_str(&hex_rows(&data[pos..], base + pos));
+                break;
+            }
+        };
+        let mut flags: Vec<&str> = Vec::new();
+        if DsType::is_const(type_ind) {
+            flags.push("const");
+        }
+        if pos < ro_size {
+            flags.push("rodata");
+        }
+        res.push_str(&format!(
+            "; var at {:#x} (payload {:#x}): {}, {} bytes{}\n",
+            base + pos,
+            base + pos + 9,
+            name,
+            end - pos - 9,
+            match flags.is_empty() {
+                true => String::new(),
+                false => format!(" [{}]", flags.join(" ")),
+            }
+        ));
+        res.push_str(&hex_rows(&data[pos..end], base + pos));
+        pos = end;
+    }
+    res
+}
+
+/// Full annotated dump of a vve image
+pub fn dump_vve(img: &VveImage) -> String {
+    let hdr = &img.header;
+    let mut res: String = String::new();
+    res.push_str(&format!("magic: {}\n", String::from_utf8_lossy(&hdr.magic).trim_end_matches('\0')));
+    res.push_str(&format!("version: {}\n", hdr.version));
+    let order: &str = match hdr.byte_order {
+        ByteOrder::Big => "big-endian",
+        ByteOrder::Little => "little-endian",
+    };
+    res.push_str(&format!("byte order: {}\n", order));
+    res.push_str(&format!("entry point: {:#x}\n", hdr.entry_point));
+    res.push_str(&format!("data base: {:#x}\n", hdr.data_base));
+    res.push_str(&format!("code size: {}\n", hdr.code_size));
+    res.push_str(&format!("data size: {}\n", hdr.data_size));
+
+    let names: HashMap<usize, String> = match hdr.section(SECT_SYMBOLS) {
+        Some(sect) => read_symbols(&sect.data).into_iter().collect(),
+        None => HashMap::new(),
+    };
+    res.push_str(&format!("functions: {}\n", hdr.func_table.len()));
+    let mut marks: HashMap<usize, Vec<String>> = HashMap::new();
+    marks.entry(hdr.entry_point as usize).or_default().push(".start".to_string());
+    for (ind, addr) in hdr.func_table.iter().enumerate() {
+        let name: String = names.get(&ind).cloned().unwrap_or_else(|| format!("func #{}", ind));
+        res.push_str(&format!("  {:>3}  {:#010x}  {}\n", ind, addr, name));
+        marks.entry(*addr as usize).or_default().push(name);
+    }
+    res.push_str(&format!("sections: {}\n", hdr.sections.len()));
+    for sect in &hdr.sections {
+        res.push_str(&format!("  {:#06x}  {:<10} {} bytes\n", sect.kind, sect_name(sect.kind), sect.data.len()));
+    }
+
+    let data_base: usize = (hdr.data_base as usize).min(img.body.len());
+    let ro_size: usize = match hdr.section(SECT_RODATA) {
+        Some(sect) => args_to_u64(&sect.data[0..8]) as usize,
+        None => 0,
+    };
+    res.push_str(&format!("\ncode [{:#x}..{:#x}):\n", 0, data_base));
+    res.push_str(&dump_code(&img.body[..data_base], &marks));
+    res.push_str(&format!("\ndata [{:#x}..{:#x}):\n", data_base, img.body.len()));
+    res.push_str(&dump_data(&img.body[data_base..], data_base, ro_size));
+    res
+}
+
+pub fn hexdump_cli(args: &[String]) -> i32 {
+    const MIN_VVE_VERSION: u16 = 3;
+    if args.len() != 1 {
+        eprintln!("Usage: voxvm hexdump file.vve|file.vvr");
+        return 1;
+    }
+    let path: &str = &args[0];
+    let dump: Result<String, String> = match path.ends_with(".vvr") {
+        true => fs::read(path)
+            .map(|code| format!("raw image, {} bytes\n\ncode:\n{}", code.len(), dump_code(&code, &HashMap::new())))
+            .map_err(|e| e.to_string()),
+        false => VveImage::load(path, MIN_VVE_VERSION).map(|img| dump_vve(&img)),
+    };
+    match dump {
+        Ok(text) => {
+            print!("{}", text);
+            0
+        }
+        Err(e) => {
+            eprintln!("ERROR: Can't read {}: {}", path, e);
+            1
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+use crate::{
+    cfgexport::{instr_size_at, op_sizes},
+    decodecache::{apply, decode_op, DecodeCache, DecodedOp},
+    vm::{Dispatch, Interpreter, BLOCK_END, VM},
+};
+
+// Hot loop threading (`--hot-loops=N`). Taken backward jumps are counted
+// per target, once a target is jumped back to N times the code from it to
+// the jump is decoded once into a loop body: DecodedOps (decodecache.rs)
+// with branches inside the body turned into op indices.
+// Later visits of the target run the body instead of parsing bytes. Ops
+// without a pre-decoded form run their handler at their address.
+// A body is left when a branch goes out of it, a handler moves ip elsewhere
+// (calls, switch, jexc) or halts, or LOOP_SLICE instructions ran, so GC,
+// pauses and data reloads of the run loop still happen. Runs with coverage,
+// tracing, native hooks, coroutine slices or self-modifying code execute
+// on the plain interpreter.
+
+/// Instructions a body runs per dispatch at most
+pub const LOOP_SLICE: u64 = 1024;
+/// Longest loop that gets a body, in bytes of code
+const MAX_LOOP_BYTES: usize = 4096;
+
+#[derive(Debug, Clone, Copy)]
+enum LoopOp {
+    Op(DecodedOp), // neither a jump nor a handler
+    Jump { to: usize },                // op index
+    Branch { opcode: u8, to: usize },  // op index
+    Exit { addr: usize },              // jump out of the body
+    BranchExit { opcode: u8, addr: usize },
+    Handler { opcode: u8 },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BodyOp {
+    addr: usize,
+    next: usize,
+    op: LoopOp,
+}
+
+#[derive(Debug)]
+pub struct HotLoops {
+    threshold: u32,
+    counters: HashMap<usize, u32>, // backward jump target -> times taken
+    bodies: HashMap<usize, Vec<BodyOp>>, // loop head -> body
+    sizes: HashMap<u16, usize>,
+    cache: Option<DecodeCache>, // runs what's outside of bodies if set
+}
+
+impl HotLoops {
+    pub fn new(threshold: u32) -> HotLoops {
+        HotLoops {
+            threshold: threshold.max(1),
+            counters: HashMap::new(),
+            bodies: HashMap::new(),
+            sizes: op_sizes(),
+            cache: None,
+        }
+    }
+
+    /// Code outside of loop bodies goes through a decoded instruction cache
+    pub fn with_cache(mut self) -> HotLoops {
+        self.cache = Some(DecodeCache::new());
+        self
+    }
+
+    /// Count of loops that got a body
+    pub fn body_count(&self) -> usize {
+        self.bodies.len()
+    }
+
+    /// Decodes `head..=back_jump`, None if something in it doesn't decode
+    fn compile(&self, vm: &VM, head: usize, back_jump: usize) -> Option<Vec<BodyOp>> {
+        let end: usize = back_jump + instr_size_at(&vm.memory, back_jump, &self.sizes)?;
+        if end - head > MAX_LOOP_BYTES {
+            return None;
+        }
+        let mut addrs: Vec<usize> = Vec::new();
+        let mut addr: usize = head;
+        while addr < end {
+            addrs.push(addr);
+            addr += instr_size_at(&vm.memory, addr, &self.sizes)?;
+        }
+        if (addr != end) || (end > vm.memory.len()) {
+            return None;
+        }
+        let index: HashMap<usize, usize> = addrs.iter().enumerate().map(|(i, a)| (*a, i)).collect();
+
+        let mut body: Vec<BodyOp> = Vec::new();
+        for (i, &addr) in addrs.iter().enumerate() {
+            let op: LoopOp = match decode_op(&vm.memory, addr, &self.sizes)?.0 {
+                DecodedOp::Jump { target } => match index.get(&target) {
+                    Some(to) => LoopOp::Jump { to: *to },
+                    None => LoopOp::Exit { addr: target },
+                },
+                DecodedOp::Branch { opcode, target } => match index.get(&target) {
+                    Some(to) => LoopOp::Branch { opcode, to: *to },
+                    None => LoopOp::BranchExit { opcode, addr: target },
+                },
+                DecodedOp::Handler { opcode } => LoopOp::Handler { opcode },
+                op => LoopOp::Op(op),
+            };
+            let next: usize = addrs.get(i + 1).copied().unwrap_or(end);
+            body.push(BodyOp { addr, next, op });
+        }
+        Some(body)
+    }
+}
+
+/// Whether the run needs every instruction to go through the interpreter
+fn plain_only(vm: &VM) -> bool {
+    vm.coverage.is_some()
+        || vm.trace.is_some()
+        || vm.coros.budget.is_some()
+        || vm.segments.allow_self_modify
+        || vm.nativesys.has_hooks()
+}
+
+/// Runs `body` from its head, returns the count of instructions executed
+fn run_body(vm: &mut VM, body: &[BodyOp]) -> u64 {
+    let mut ran: u64 = 0;
+    let mut i: usize = 0;
+    loop {
+        let step: BodyOp = body[i];
+        vm.ip = step.addr;
+        ran += 1;
+        let mut to: Option<usize> = None; // op index of a taken branch
+        match step.op {
+            LoopOp::Op(op) => apply(vm, op),
+            LoopOp::Jump { to: t } => to = Some(t),
+            LoopOp::Branch { opcode, to: t } => {
+                if vm.jump_taken(opcode) {
+                    to = Some(t);
+                }
+            }
+            LoopOp::Exit { addr } => {
+                vm.instr_count += 1;
+                vm.ip = addr;
+                return ran;
+            }
+            LoopOp::BranchExit { opcode, addr } => {
+                if vm.jump_taken(opcode) {
+                    vm.instr_count += 1;
+                    vm.ip = addr;
+                    return ran;
+                }
+            }
+            LoopOp::Handler { opcode } => {
+                // counts the instruction and records its exceptions itself
+                vm.exec_instr(opcode, BLOCK_END);
+                if (vm.ip != step.next) || !vm.is_running() || vm.coros.budget.is_some() {
+                    return ran;
+                }
+                i += 1;
+                if (i == body.len()) || (ran >= LOOP_SLICE) {
+                    return ran;
+                }
+                continue;
+            }
+        }
+        vm.instr_count += 1;
+        vm.track_exceptions(step.addr);
+        if !vm.is_running() {
+            vm.ip = step.next;
+            return ran;
+        }
+        match to {
+            Some(t) => {
+                vm.ip = body[t].addr;
+                if (t <= i) && (ran >= LOOP_SLICE) {
+                    return ran;
+                }
+                i = t;
+            }
+            None => {
+                vm.ip = step.next;
+                i += 1;
+                if (i == body.len()) || (ran >= LOOP_SLICE) {
+                    return ran;
+                }
+            }
+        }
+    }
+}
+
+impl Dispatch for HotLoops {
+    fn dispatch(&mut self, vm: &mut VM) -> u64 {
+        if plain_only(vm) {
+            return Interpreter.dispatch(vm);
+        }
+        if let Some(body) = self.bodies.get(&vm.ip) {
+            return run_body(vm, body);
+        }
+        let (ip, opcode) = (vm.ip, vm.memory[vm.ip]);
+        match &mut self.cache {
+            Some(cache) => cache.dispatch(vm),
+            None => Interpreter.dispatch(vm),
+        };
+        let backward: bool = matches!(opcode, 0x40..=0x45 | 0x48) && (vm.ip <= ip);
+        if backward {
+            let head: usize = vm.ip;
+            let count: &mut u32 = self.counters.entry(head).or_insert(0);
+            *count += 1;
+            if *count != self.threshold {
+                return 1;
+            }
+            if let Some(body) = self.compile(vm, head, ip) {
+                self.bodies.insert(head, body);
+            }
+        }
+        1
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    fs,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{dstype::DsType, fileformats::VoxExeHeader, misclib::args_to_u64};
+
+// Data segment hot reload.
+// Re-reads the data part of a .vve and copies changed values of mutable
+// variables into the running VM. Variables layout has to stay the same.
+
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug)]
+pub struct DataWatch {
+    pub path: String,
+    mtime: Option<SystemTime>,
+    pending: Option<SystemTime>, // changed, waiting for the writer to finish
+    last_check: Instant,
+}
+
+impl DataWatch {
+    pub fn new(path: String) -> DataWatch {
+        let mtime: Option<SystemTime> = modified(&path);
+        DataWatch {
+            path: path,
+            mtime: mtime,
+            pending: None,
+            last_check: Instant::now(),
+        }
+    }
+
+    /// True once the file has changed and stayed the same for one check interval
+    pub fn poll(&mut self) -> bool {
+        if self.last_check.elapsed() < WATCH_INTERVAL {
+            return false;
+        }
+        self.last_check = Instant::now();
+        let mtime: Option<SystemTime> = modified(&self.path);
+        if mtime.is_none() || mtime == self.mtime {
+            self.pending = None;
+            return false;
+        }
+        if self.pending == mtime {
+            self.mtime = mtime;
+            self.pending = None;
+            return true;
+        }
+        self.pending = mtime;
+        false
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Reads the data segment image of a .vve
+pub fn read_data_image(path: &str) -> Result<Vec<u8>, String> {
+    let bytes: Vec<u8> = fs::read(path).map_err(|e| e.to_string())?;
+    let header: VoxExeHeader = VoxExeHeader::from_bytes(&bytes, path, 0)?;
+    let start: usize = header.size() + header.data_base as usize;
+    let end: usize = start + header.data_size as usize;
+    match bytes.get(start..end) {
+        Some(v) => Ok(v.to_vec()),
+        None => Err(format!("{} data segment is out of the file", path)),
+    }
+}
+
+/// Walks variables of `prev` and `new` images from `rw_start` and writes
+/// payloads of mutable variables that differ into `mem`.
+/// Returns count of updated variables.
+pub fn merge_data(mem: &mut [u8], prev: &[u8], new: &[u8], rw_start: usize) -> Result<usize, String> {
+    if prev.len() != new.len() {
+        return Err(format!("data segment size changed: {} -> {}", prev.len(), new.len()));
+    }
+    let mut updated: usize = 0;
+    let mut pos: usize = rw_start;
+    while pos < prev.len() {
+        if prev[pos] != new[pos] {
+            return Err(format!("variable type changed at {:#x}", pos));
+        }
+        if prev[pos] == 0 {
+            pos += 1; // !align padding
+            continue;
+        }
+        if pos + 9 > prev.len() {
+            return Err(format!("truncated variable at {:#x}", pos));
+        }
+        let len: usize = args_to_u64(&prev[(pos + 1)..(pos + 9)]) as usize;
+        if prev[(pos + 1)..(pos + 9)] != new[(pos + 1)..(pos + 9)] {
+            return Err(format!("variable length changed at {:#x}", pos));
+        }
+        let payload = (pos + 9)..(pos + 9 + len);
+        if payload.end > prev.len() {
+            return Err(format!("truncated variable at {:#x}", pos));
+        }
+        if !DsType::is_const(prev[pos]) && prev[payload.clone()] != new[payload.clone()] {
+            if DsType::decode(prev[pos]) == Some(DsType::Str) {
+                // dsstore shortens the runtime length, the new value has its full one
+                mem[(pos + 1)..(pos + 9)].copy_from_slice(&new[(pos + 1)..(pos + 9)]);
+            }
+            mem[payload.clone()].copy_from_slice(&new[payload.clone()]);
+            updated += 1;
+        }
+        pos = payload.end;
+    }
+    Ok(updated)
+}
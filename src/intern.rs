@@ -0,0 +1,38 @@
+use std::collections::{HashMap, HashSet};
+
+// Interned data segment strings.
+// voxasm deduplicates identical `const str` variables and lists them in
+// the VVE intern section, so equal interned strings share one StrAddr.
+#[derive(Debug)]
+pub struct InternTable {
+    addrs: HashSet<u64>,               // StrAddr of each interned string
+    by_content: HashMap<Vec<u8>, u64>, // utf16 bytes -> StrAddr
+}
+
+impl InternTable {
+    pub fn new() -> InternTable {
+        InternTable {
+            addrs: HashSet::new(),
+            by_content: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, addr: u64, content: Vec<u8>) {
+        self.addrs.insert(addr);
+        self.by_content.entry(content).or_insert(addr);
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        self.addrs.contains(&addr)
+    }
+
+    pub fn lookup(&self, content: &[u8]) -> Option<u64> {
+        self.by_content.get(content).cloned()
+    }
+}
+
+impl Default for InternTable {
+    fn default() -> InternTable {
+        InternTable::new()
+    }
+}
@@ -0,0 +1,76 @@
+// voxvm as a library: the VM with its assembler, image formats and native
+// calls, for hosts embedding it (`vm::VM`, `VM::register_ncall`,
+// `VM::run_function`, output sinks in `output`). The voxvm binary is a
+// command line front end over the same modules.
+
+// The heap, arena and scratch allocators report failure as a bare `Err(())`,
+// the caller maps it to the VM exception.
+#![allow(clippy::result_unit_err)]
+
+pub mod abort;
+pub mod aot;
+pub mod arena;
+pub mod asmalias;
+pub mod asmmacro;
+pub mod assembly;
+pub mod asmopt;
+pub mod asmvreg;
+pub mod callstack;
+pub mod cfgexport;
+pub mod coroutine;
+pub mod coredump;
+pub mod coverage;
+pub mod decodecache;
+pub mod dstype;
+pub mod exceptions;
+pub mod fileformats;
+pub mod func_ops;
+pub mod gc;
+pub mod gcbench;
+pub mod handles;
+pub mod heap;
+pub mod heapsnap;
+pub mod hexdump;
+pub mod hotloop;
+pub mod hotreload;
+pub mod intern;
+pub mod limits;
+pub mod memcap;
+pub mod memprof;
+pub mod native;
+#[macro_use]
+pub mod registers;
+pub mod misclib;
+pub mod stack;
+pub mod vm;
+pub mod vmmemory;
+pub mod defnative;
+pub mod nativeasm;
+pub mod nativeaudio;
+pub mod nativecsv;
+pub mod nativeerr;
+pub mod nativeevent;
+pub mod nativefiles;
+pub mod nativefb;
+pub mod nativeiov;
+pub mod nativemmap;
+pub mod nativeshm;
+pub mod nativepath;
+pub mod nativestr;
+pub mod nativeterm;
+pub mod nativenet;
+#[cfg(test)]
+mod opcodetests;
+pub mod output;
+pub mod pause;
+pub mod runstatus;
+pub mod scratch;
+pub mod segments;
+pub mod selftest;
+#[cfg(test)]
+mod testsupport;
+pub mod trace;
+pub mod vasfmt;
+pub mod vaslint;
+pub mod vvediff;
+pub mod vvelink;
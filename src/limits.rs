@@ -0,0 +1,74 @@
+use crate::{
+    exceptions::Exception,
+    nativeerr::{native_fault, NativeErrKind, NativeError, NativeSubsys},
+    registers::Register,
+    vm::{RegTypes, VM},
+};
+
+// Run-time recursion and data stack limits. `--max-recursion` and
+// `--max-stack-slots` are where a run starts, a phase that recurses deeply
+// raises its limit with `ncall @rec_limit_set` / `@stack_limit_set` and
+// lowers it again afterwards. Limits never go past the ceilings given with
+// `--recursion-ceiling` / `--stack-slots-ceiling` (the starting limits
+// without them), or below what is in use right now. The data stack limit
+// is u64::MAX (unlimited) unless set. Coroutines have own stacks, the
+// limits apply to the running one.
+
+fn set_uint(vm: &mut VM, ind: usize, val: u64) {
+    vm.registers[ind] = Register::uint(val);
+    vm.reg_types[ind] = RegTypes::uint64;
+}
+
+/// New limit from r1 if it lies in `in_use..=ceiling`, faults otherwise
+fn checked_limit(vm: &mut VM, name: &str, in_use: usize, ceiling: usize) -> Option<usize> {
+    let new: u64 = vm.registers[1].as_u64();
+    let (kind, msg): (NativeErrKind, String) = if new > ceiling as u64 {
+        (NativeErrKind::LimitReached, format!("{}: {} is above the ceiling {}", name, new, ceiling))
+    } else if new < in_use as u64 {
+        (NativeErrKind::InvalidInput, format!("{}: {} is below the {} in use", name, new, in_use))
+    } else {
+        return Some(new as usize);
+    };
+    native_fault(vm, NativeError::new(NativeSubsys::Limits, kind), Exception::NativeFault, &msg);
+    None
+}
+
+/// ncall 0xB0
+/// r0 = recursion limit, r1 = its ceiling, r2 = current call depth
+pub fn ncall_rec_limit(vm: &mut VM) {
+    set_uint(vm, 0, vm.rec_depth_max as u64);
+    set_uint(vm, 1, vm.rec_depth_ceiling as u64);
+    set_uint(vm, 2, vm.call_stack.stack.len() as u64);
+}
+
+/// ncall 0xB1
+/// r1 is the new recursion limit. r0 = previous limit, 0 if r1 is above
+/// the ceiling or below the current call depth (the limit stays)
+pub fn ncall_rec_limit_set(vm: &mut VM) {
+    let (depth, ceiling): (usize, usize) = (vm.call_stack.stack.len(), vm.rec_depth_ceiling);
+    let prev: u64 = match checked_limit(vm, "rec_limit_set", depth, ceiling) {
+        Some(new) => std::mem::replace(&mut vm.rec_depth_max, new) as u64,
+        None => 0,
+    };
+    set_uint(vm, 0, prev);
+}
+
+/// ncall 0xB2
+/// r0 = data stack limit in slots, r1 = its ceiling, r2 = slots in use
+pub fn ncall_stack_limit(vm: &mut VM) {
+    set_uint(vm, 0, vm.stack_slots_max as u64);
+    set_uint(vm, 1, vm.stack_slots_ceiling as u64);
+    set_uint(vm, 2, vm.stack.stack.len() as u64);
+}
+
+/// ncall 0xB3
+/// r1 is the new data stack limit in slots. r0 = previous limit, 0 if r1
+/// is above the ceiling or below the slots in use (the limit stays)
+pub fn ncall_stack_limit_set(vm: &mut VM) {
+    let (in_use, ceiling): (usize, usize) = (vm.stack.stack.len(), vm.stack_slots_ceiling);
+    let prev: u64 = match checked_limit(vm, "stack_limit_set", in_use, ceiling) {
+        Some(new) => std::mem::replace(&mut vm.stack_slots_max, new) as u64,
+        None => 0,
+    };
+    set_uint(vm, 0, prev);
+}
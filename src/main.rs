@@ -1,28 +1,65 @@
 use std::{env, fs::File, io::Write, process::exit, time::Instant};
 
-use assembly::VoxAssembly;
-use regex::Regex;
 use sysinfo::System;
-use vm::VM;
-
-mod assembly;
-mod callstack;
-mod exceptions;
-mod fileformats;
-mod func_ops;
-mod gc;
-mod heap;
-mod native;
-#[macro_use]
-mod registers;
-mod misclib;
-mod stack;
-mod vm;
-mod defnative;
-mod nativefiles;
-mod nativenet;
+use voxvm::{
+    abort::ABORT_EXIT_CODE,
+    aot,
+    assembly::VoxAssembly,
+    cfgexport::{self, CfgImage},
+    coredump,
+    coverage::Coverage,
+    decodecache::DecodeCache,
+    fileformats::{self, ByteOrder},
+    gcbench, hexdump,
+    hotloop::HotLoops,
+    hotreload::DataWatch,
+    memcap,
+    memprof::MemProfile,
+    misclib::{pretty_fmt_size, pretty_input_tobytes},
+    native::read_cfg_ncall_names,
+    nativeevent::{EventQueue, Overflow, DEFAULT_CAPACITY},
+    nativefb,
+    nativeterm::{AnsiMode, TermState},
+    pause,
+    registers::Register,
+    runstatus::{RunStatus, Sizes, StatusTarget},
+    selftest,
+    trace::Trace,
+    vasfmt, vaslint,
+    vm::VM,
+    vvediff,
+    vvelink::{self, VveImage},
+};
 
 fn main() {
+    if env::args().nth(1).as_deref() == Some("selftest") {
+        exit(if selftest::selftest(env::args().nth(2).as_deref()) { 0 } else { 1 });
+    }
+    if env::args().nth(1).as_deref() == Some("fmt") {
+        let args: Vec<String> = env::args().skip(2).collect();
+        exit(vasfmt::fmt_cli(&args));
+    }
+    if env::args().nth(1).as_deref() == Some("hexdump") {
+        let args: Vec<String> = env::args().skip(2).collect();
+        exit(hexdump::hexdump_cli(&args));
+    }
+    if env::args().nth(1).as_deref() == Some("lint") {
+        let args: Vec<String> = env::args().skip(2).collect();
+        exit(vaslint::lint_cli(&args));
+    }
+    if env::args().nth(1).as_deref() == Some("aot") {
+        let args: Vec<String> = env::args().skip(2).collect();
+        exit(aot::aot_cli(&args));
+    }
+    if env::args().nth(1).as_deref() == Some("diff-vve") {
+        let args: Vec<String> = env::args().skip(2).collect();
+        exit(vvediff::diff_vve_cli(&args));
+    }
+    if env::args().nth(1).as_deref() == Some("bench-gc") {
+        let args: Vec<String> = env::args().skip(2).collect();
+        exit(gcbench::bench_gc_cli(&args));
+    }
+
     let mut sys = System::new();
     sys.refresh_memory();
     let available_ram = sys.available_memory();
@@ -37,25 +74,73 @@ fn main() {
     let mut heap_size: Option<usize> = None;
 
     let mut vvr_filename: Option<String> = None;
+    let mut vvr_entry: usize = 0;
+    let mut vvr_max_size: Option<usize> = None;
     let mut vve_filename: Option<String> = None;
+    let mut stdlib_filename: Option<String> = None;
     const MIN_VVE_VERSION: u16 = 3;
 
     let mut vas_input_filename: Option<String> = None;
     let mut vas_out_filename: Option<String> = None;
+    let mut vas_opt: bool = false;
+    let mut vas_byte_order: ByteOrder = ByteOrder::Big;
 
     let mut coredump_on_exit: bool = false;
 
     let mut recursion_depth_limit: Option<usize> = None;
+    let mut recursion_ceiling: Option<usize> = None;
+    let mut stack_slots_max: Option<usize> = None;
+    let mut stack_precheck: bool = false;
+    let mut stack_slots_ceiling: Option<usize> = None;
 
     let mut native_cfgs: Option<String> = None;
 
+    let mut allow_self_modify: bool = false;
+    let mut allow_native_load: bool = false;
+    let mut abi_autosave: bool = false;
+    let mut shadow_stack: bool = false;
+    let mut max_pending_exc: Option<usize> = None;
+    let mut abort_coredump: bool = false;
+    let mut abort_exit_code: i32 = ABORT_EXIT_CODE;
+    let mut max_open_files: Option<usize> = None;
+    let mut max_connections: Option<usize> = None;
+    let mut max_total_mem: Option<usize> = None;
+    let mut gc_concurrent: bool = false;
+    let mut term_ansi: AnsiMode = AnsiMode::Auto;
+    let mut fb_dump_dir: Option<String> = None;
+    let mut audio_wav: Option<String> = None;
+    let mut quiet: bool = false;
+    let mut json_status: Option<StatusTarget> = None;
+    let mut event_capacity: usize = DEFAULT_CAPACITY;
+    let mut event_overflow: Overflow = Overflow::DropOldest;
+    let mut heap_debug: bool = false;
+    let mut pause_signals: bool = false;
+    let mut watch_data: Option<String> = None;
+    let mut float_eps: Option<f64> = None;
+    let mut hot_loops: Option<u32> = None;
+    let mut decode_cache: bool = false;
+
+    let mut dump_state_filename: Option<String> = None;
+
+    let mut coverage_filename: Option<String> = None;
+    let mut trace_filename: Option<String> = None;
+    let mut mem_profile_filename: Option<String> = None;
+    let mut cov_report_filename: Option<String> = None;
+    let mut emit_cfg_filename: Option<String> = None;
+    let mut vas_map_filename: Option<String> = None;
+    let mut cov_src_filename: Option<String> = None;
+
+    let mut entry_func: Option<String> = None;
+    let mut entry_args: Vec<Register> = Vec::new();
+    let mut defines: Vec<(String, String)> = Vec::new();
+
     for arg in env::args() {
         if let Some(val) = arg.strip_prefix("--init-ram=") {
             match pretty_input_tobytes(val.to_string()) {
                 Some(num) => ram_size = Some(num),
                 None => {
                     eprintln!(
-                        "ERROR: Init ram value is incorrect.\nHint: specify unit, e.g. `--init-ram=100MB`"
+                        "ERROR: Init ram value is incorrect.\nHint: bytes or a unit (KB, MiB, GB, TB..), e.g. `--init-ram=100MB`"
                     );
                     return;
                 }
@@ -66,7 +151,7 @@ fn main() {
                 Some(num) => stack_size = Some(num),
                 None => {
                     eprintln!(
-                        "ERROR: Init stack size is incorrect.\nHint: specify unit, e.g. `--init-stack-size=100MB`"
+                        "ERROR: Init stack size is incorrect.\nHint: bytes or a unit (KB, MiB, GB, TB..), e.g. `--init-stack-size=100MB`"
                     );
                     return;
                 }
@@ -77,7 +162,7 @@ fn main() {
                 Some(num) => heap_size = Some(num),
                 None => {
                     eprintln!(
-                        "ERROR: Init heap size is incorrect.\nHint: specify unit, e.g. `--init-heap-size=100MB`"
+                        "ERROR: Init heap size is incorrect.\nHint: bytes or a unit (KB, MiB, GB, TB..), e.g. `--init-heap-size=100MB`"
                     );
                     return;
                 }
@@ -91,6 +176,26 @@ fn main() {
                 }
             }
         }
+        if let Some(val) = arg.strip_prefix("--vvr-entry=") {
+            match parse_addr_arg(val) {
+                Some(addr) => vvr_entry = addr,
+                None => {
+                    eprintln!("ERROR: Invalid --vvr-entry address: {}", val);
+                    exit(1);
+                }
+            }
+        }
+        if let Some(val) = arg.strip_prefix("--vvr-max-size=") {
+            match pretty_input_tobytes(val.to_string()) {
+                Some(num) => vvr_max_size = Some(num),
+                None => {
+                    eprintln!(
+                        "ERROR: Vvr max size is incorrect.\nHint: bytes or a unit (KB, MiB, GB, TB..), e.g. `--vvr-max-size=64KB`"
+                    );
+                    exit(1);
+                }
+            }
+        }
         if let Some(val) = arg.strip_prefix("--vve=") {
             match val.parse::<String>() {
                 Ok(st) => vve_filename = Some(st.to_string()),
@@ -118,6 +223,9 @@ fn main() {
         if let Some(val) = arg.strip_prefix("--coredump_exit") {
             coredump_on_exit = true;
         }
+        if let Some(val) = arg.strip_prefix("--inspect-dump=") {
+            exit(coredump::inspect_dump_cli(val));
+        }
         if let Some(val) = arg.strip_prefix("--max-recursion=") {
             match val.parse::<usize>() {
                 Ok(v) => {
@@ -126,6 +234,224 @@ fn main() {
                 Err(_) => {}
             }
         }
+        if arg == "--allow-self-modify" {
+            allow_self_modify = true;
+        }
+        if arg == "--allow-native-load" {
+            allow_native_load = true;
+        }
+        if let Some(val) = arg.strip_prefix("--float-eps=") {
+            match val.parse::<f64>() {
+                Ok(v) if v.is_finite() && v >= 0.0 => float_eps = Some(v),
+                _ => {
+                    eprintln!("ERROR: Invalid --float-eps value: {}", val);
+                }
+            }
+        }
+        if arg == "--opt" {
+            vas_opt = true;
+        }
+        if let Some(val) = arg.strip_prefix("--vas-map=") {
+            vas_map_filename = Some(val.to_string());
+        }
+        if let Some(val) = arg.strip_prefix("--vas-byte-order=") {
+            match ByteOrder::from_name(val) {
+                Some(order) => vas_byte_order = order,
+                None => {
+                    eprintln!("ERROR: --vas-byte-order should be be, le or native, got '{}'", val);
+                    exit(1);
+                }
+            }
+        }
+        if arg == "--shadow-stack" {
+            shadow_stack = true;
+        }
+        if let Some(val) = arg.strip_prefix("--max-total-mem=") {
+            match pretty_input_tobytes(val.to_string()) {
+                Some(num) => max_total_mem = Some(num),
+                None => {
+                    eprintln!(
+                        "ERROR: Max total memory is incorrect.\nHint: bytes or a unit (KB, MiB, GB, TB..), e.g. `--max-total-mem=64MB`"
+                    );
+                    return;
+                }
+            }
+        }
+        if let Some(val) = arg.strip_prefix("--max-pending-exc=") {
+            match val.parse::<usize>() {
+                Ok(v) => max_pending_exc = Some(v),
+                Err(_) => {
+                    eprintln!("ERROR: Invalid --max-pending-exc value: {}", val);
+                }
+            }
+        }
+        if arg == "--abort-coredump" {
+            abort_coredump = true;
+        }
+        if let Some(val) = arg.strip_prefix("--abort-exit-code=") {
+            match val.parse::<u8>() {
+                Ok(v) => abort_exit_code = v as i32,
+                Err(_) => {
+                    eprintln!("ERROR: --abort-exit-code should be 0..255, got '{}'", val);
+                    exit(1);
+                }
+            }
+        }
+        if let Some(val) = arg.strip_prefix("--recursion-ceiling=") {
+            match val.parse::<usize>() {
+                Ok(v) => recursion_ceiling = Some(v),
+                Err(_) => {
+                    eprintln!("ERROR: Invalid --recursion-ceiling value: {}", val);
+                }
+            }
+        }
+        if let Some(val) = arg.strip_prefix("--max-stack-slots=") {
+            match val.parse::<usize>() {
+                Ok(v) => stack_slots_max = Some(v),
+                Err(_) => {
+                    eprintln!("ERROR: Invalid --max-stack-slots value: {}", val);
+                }
+            }
+        }
+        if arg == "--stack-precheck" {
+            stack_precheck = true;
+        }
+        if let Some(val) = arg.strip_prefix("--stack-slots-ceiling=") {
+            match val.parse::<usize>() {
+                Ok(v) => stack_slots_ceiling = Some(v),
+                Err(_) => {
+                    eprintln!("ERROR: Invalid --stack-slots-ceiling value: {}", val);
+                }
+            }
+        }
+        if let Some(val) = arg.strip_prefix("--max-open-files=") {
+            match val.parse::<usize>() {
+                Ok(v) => max_open_files = Some(v),
+                Err(_) => {
+                    eprintln!("ERROR: Invalid --max-open-files value: {}", val);
+                }
+            }
+        }
+        if let Some(val) = arg.strip_prefix("--max-connections=") {
+            match val.parse::<usize>() {
+                Ok(v) => max_connections = Some(v),
+                Err(_) => {
+                    eprintln!("ERROR: Invalid --max-connections value: {}", val);
+                }
+            }
+        }
+        if let Some(val) = arg.strip_prefix("--watch-data=") {
+            watch_data = Some(val.to_string());
+        }
+        if let Some(val) = arg.strip_prefix("--term-ansi=") {
+            match AnsiMode::from_name(val) {
+                Some(mode) => term_ansi = mode,
+                None => {
+                    eprintln!("ERROR: --term-ansi should be auto, always or never, got '{}'", val);
+                    exit(1);
+                }
+            }
+        }
+        if let Some(val) = arg.strip_prefix("--event-queue=") {
+            match val.parse::<usize>() {
+                Ok(v) if v > 0 => event_capacity = v,
+                _ => {
+                    eprintln!("ERROR: --event-queue takes a count of events, at least 1, got '{}'", val);
+                    exit(1);
+                }
+            }
+        }
+        if let Some(val) = arg.strip_prefix("--event-overflow=") {
+            match Overflow::from_name(val) {
+                Some(policy) => event_overflow = policy,
+                None => {
+                    eprintln!("ERROR: --event-overflow should be drop-oldest or drop-newest, got '{}'", val);
+                    exit(1);
+                }
+            }
+        }
+        if let Some(val) = arg.strip_prefix("--json-status=") {
+            json_status = Some(StatusTarget::parse(val));
+        }
+        if arg == "--quiet" {
+            quiet = true;
+        }
+        if let Some(val) = arg.strip_prefix("--audio-wav=") {
+            audio_wav = Some(val.to_string());
+        }
+        if let Some(val) = arg.strip_prefix("--fb-dump=") {
+            fb_dump_dir = Some(val.to_string());
+        }
+        if arg == "--gc-concurrent" {
+            gc_concurrent = true;
+        }
+        if arg == "--heap-debug" {
+            heap_debug = true;
+        }
+        if arg == "--decode-cache" {
+            decode_cache = true;
+        }
+        if let Some(val) = arg.strip_prefix("--hot-loops=") {
+            match val.parse::<u32>() {
+                Ok(n) if n > 0 => hot_loops = Some(n),
+                _ => {
+                    eprintln!("ERROR: Invalid --hot-loops value: {}", val);
+                }
+            }
+        }
+        if arg == "--pause-signals" {
+            pause_signals = true;
+        }
+        if arg == "--abi-autosave" {
+            abi_autosave = true;
+        }
+        if let Some(val) = arg.strip_prefix("--coverage=") {
+            coverage_filename = Some(val.to_string());
+        }
+        if let Some(val) = arg.strip_prefix("--trace=") {
+            trace_filename = Some(val.to_string());
+        }
+        if let Some(val) = arg.strip_prefix("--mem-profile=") {
+            mem_profile_filename = Some(val.to_string());
+        }
+        if let Some(val) = arg.strip_prefix("--cov-report=") {
+            cov_report_filename = Some(val.to_string());
+        }
+        if let Some(val) = arg.strip_prefix("--stdlib=") {
+            stdlib_filename = Some(val.to_string());
+        }
+        if let Some(val) = arg.strip_prefix("--emit-cfg=") {
+            emit_cfg_filename = Some(val.to_string());
+        }
+        if let Some(val) = arg.strip_prefix("--src=") {
+            cov_src_filename = Some(val.to_string());
+        }
+        if let Some(val) = arg.strip_prefix("--entry=") {
+            entry_func = Some(val.to_string());
+        }
+        if let Some(val) = arg.strip_prefix("--entry-args=") {
+            for v in val.split(',').filter(|v| !v.is_empty()) {
+                match parse_entry_arg(v) {
+                    Some(reg) => entry_args.push(reg),
+                    None => {
+                        eprintln!("ERROR: Invalid entry argument: {}", v);
+                        exit(1);
+                    }
+                }
+            }
+        }
+        if let Some(val) = arg.strip_prefix("--define=") {
+            match val.split_once('=') {
+                Some((name, value)) => defines.push((name.to_string(), value.to_string())),
+                None => {
+                    eprintln!("ERROR: --define takes NAME=value, got: {}", val);
+                    exit(1);
+                }
+            }
+        }
+        if let Some(val) = arg.strip_prefix("--dump-state=") {
+            dump_state_filename = Some(val.to_string());
+        }
         if let Some(val) = arg.strip_prefix("--native-configs=") {
             match val.parse::<String>() {
                 Ok(st) => native_cfgs = Some(st.to_string()),
@@ -139,68 +465,215 @@ fn main() {
     match vas_input_filename {
         Some(st) => {
             let default_out_filename = st.replace(".vvs", ".vve");
-            let mut asm =
-                VoxAssembly::new(st, vas_out_filename.unwrap_or_else(|| default_out_filename));
+            let out_filename: String = vas_out_filename.unwrap_or_else(|| default_out_filename);
+            let mut asm = VoxAssembly::new(st, out_filename.clone());
+            if let Some(dir) = &native_cfgs {
+                match read_cfg_ncall_names(dir) {
+                    Ok(names) => asm.add_ncall_names(names),
+                    Err(e) => eprintln!("ERROR: While reading ncall names from native configs: {:#?}", e),
+                }
+            }
+            if vas_opt {
+                asm.enable_opt();
+            }
+            asm.set_byte_order(vas_byte_order);
+            let stdlib: Option<VveImage> = match &stdlib_filename {
+                Some(path) => match VveImage::load(path, MIN_VVE_VERSION) {
+                    Ok(img) => Some(img),
+                    Err(e) => {
+                        eprintln!("ERROR: --stdlib: {}", e);
+                        exit(1);
+                    }
+                },
+                None => None,
+            };
+            if let Some(std) = &stdlib {
+                asm.add_stdlib_funcs(vvelink::stdlib_funcs(std));
+            }
             asm.assemble();
+            let map_written = vas_map_filename.as_ref().map(|map| std::fs::write(map, asm.symbol_map()).map_err(|e| (map, e)));
+            if let Some(Err((map, e))) = map_written {
+                eprintln!("ERROR: --vas-map: {}: {}", map, e);
+                exit(1);
+            }
+            if let Some(std) = &stdlib {
+                // link time: the output carries the stdlib along
+                let linked = VveImage::load(&out_filename, MIN_VVE_VERSION)
+                    .and_then(|prog| vvelink::link_stdlib(&prog, std))
+                    .and_then(|img| img.write(&out_filename));
+                if let Err(e) = linked {
+                    eprintln!("ERROR: --stdlib: {}", e);
+                    exit(1);
+                }
+            }
+            if let Some(dot) = emit_cfg_filename {
+                let img = match out_filename.contains(".vve") {
+                    true => CfgImage::from_vve(&out_filename, MIN_VVE_VERSION),
+                    false => CfgImage::from_raw(&out_filename),
+                };
+                if let Err(e) = img.and_then(|img| cfgexport::emit_cfg(&img, &dot)) {
+                    eprintln!("ERROR: --emit-cfg: {}", e);
+                    exit(1);
+                }
+            }
             return;
         }
         None => {}
     }
 
+    if let Some(dot) = emit_cfg_filename {
+        // only exports the graph, nothing is run
+        let img = match (&vve_filename, &vvr_filename) {
+            (Some(vve), _) => CfgImage::from_vve(vve, MIN_VVE_VERSION),
+            (None, Some(vvr)) => CfgImage::from_raw(vvr),
+            (None, None) => Err("--vve=, --vvr= or --vas= is required".to_string()),
+        };
+        if let Err(e) = img.and_then(|img| cfgexport::emit_cfg(&img, &dot)) {
+            eprintln!("ERROR: --emit-cfg: {}", e);
+            exit(1);
+        }
+        return;
+    }
+
+    if let Some(cov_path) = cov_report_filename {
+        match coverage_report(&cov_path, vve_filename, cov_src_filename, MIN_VVE_VERSION) {
+            Ok(report) => print!("{}", report),
+            Err(e) => {
+                eprintln!("ERROR: --cov-report: {}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    // --quiet keeps host chatter out of guest stdout
+    let banner = |line: String| {
+        if !quiet {
+            println!("{}", line);
+        }
+    };
     match ram_size {
-        Some(size) => println!(
+        Some(size) => banner(format!(
             "Initializing VM with init RAM size = {}",
             pretty_fmt_size(size as u64)
-        ),
+        )),
         None => {
-            println!(
+            banner(format!(
                 "Init RAM size is not specified, using {} by default.",
                 pretty_fmt_size(DEFAULT_INIT_RAM as u64)
-            );
+            ));
             ram_size = Some(DEFAULT_INIT_RAM);
         }
     }
     match stack_size {
-        Some(size) => println!(
+        Some(size) => banner(format!(
             "Initializing VM with init stack size = {}",
             pretty_fmt_size(size as u64)
-        ),
+        )),
         None => {
-            println!(
+            banner(format!(
                 "Init stack size is not specified, using {} by default.",
                 pretty_fmt_size(DEFAULT_INIT_STACK as u64)
-            );
+            ));
             stack_size = Some(DEFAULT_INIT_STACK);
         }
     }
     match heap_size {
-        Some(size) => println!(
+        Some(size) => banner(format!(
             "Initializing VM with init heap size = {}",
             pretty_fmt_size(size as u64)
-        ),
+        )),
         None => {
-            println!(
+            banner(format!(
                 "Init heap size is not specified, using {} by default.",
                 pretty_fmt_size(DEFAULT_INIT_HEAP as u64)
-            );
+            ));
             heap_size = Some(DEFAULT_INIT_HEAP);
         }
     }
+    match check_init_sizes(ram_size.unwrap(), stack_size.unwrap(), heap_size.unwrap(), available_ram) {
+        Ok(Some(warning)) => eprintln!("{}", warning),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            exit(1);
+        }
+    }
     let mut vm_instance = VM::new(
         ram_size.unwrap(),
         stack_size.unwrap(),
         heap_size.unwrap(),
         recursion_depth_limit.unwrap_or(DEFAULT_RECURSION_LIMIT),
     );
+    vm_instance.segments.allow_self_modify = allow_self_modify;
+    vm_instance.abi_autosave = abi_autosave;
+    vm_instance.gc_concurrent = gc_concurrent;
+    vm_instance.quiet = quiet;
+    vm_instance.term = TermState::new(term_ansi);
+    vm_instance.audio.wav_path = audio_wav;
+    vm_instance.events = EventQueue::new(event_capacity, event_overflow);
+    vm_instance.heap.debug_sites = heap_debug;
+    if let Some(eps) = float_eps {
+        vm_instance.float_epsilon = eps;
+    }
+    if shadow_stack {
+        vm_instance.shadow_stack = Some(Vec::new());
+    }
+    vm_instance.max_pending_exc = max_pending_exc;
+    vm_instance.abort_coredump = abort_coredump;
+    vm_instance.allow_native_load = allow_native_load;
+    // ceilings default to the starting limits, nothing can be raised then
+    vm_instance.rec_depth_ceiling = recursion_ceiling.unwrap_or(vm_instance.rec_depth_max).max(vm_instance.rec_depth_max);
+    vm_instance.stack_slots_max = stack_slots_max.unwrap_or(usize::MAX);
+    vm_instance.stack_precheck = stack_precheck;
+    vm_instance.stack_slots_ceiling = stack_slots_ceiling.unwrap_or(vm_instance.stack_slots_max).max(vm_instance.stack_slots_max);
+    vm_instance.fc.max_open = max_open_files;
+    vm_instance.nc.max_conns = max_connections;
+    if coverage_filename.is_some() {
+        vm_instance.coverage = Some(Coverage::new());
+    }
+    if trace_filename.is_some() {
+        vm_instance.trace = Some(Trace::new());
+    }
+    if mem_profile_filename.is_some() {
+        vm_instance.mem_profile = Some(MemProfile::new());
+        vm_instance.heap.debug_sites = true; // names heap blocks by site
+    }
     let curdir = env::current_dir().unwrap();
 
     match vvr_filename {
-        Some(ref st) => vm_instance.load_vvr(&st),
+        Some(ref st) => {
+            // raw images can't be bigger than the initial RAM
+            let max_size: usize = vvr_max_size.unwrap_or(ram_size.unwrap());
+            if let Err(e) = vm_instance.load_vvr(&st, vvr_entry, max_size) {
+                eprintln!("ERROR: Can't load {}: {}", st, e);
+                exit(1);
+            }
+        }
         None => {}
     }
-    match vve_filename {
-        Some(ref st) => vm_instance.load_vve(&st, MIN_VVE_VERSION),
-        None => {}
+    #[cfg(feature = "aot")]
+    let vve_filename: Option<String> = match (&vve_filename, &vvr_filename) {
+        (None, None) => Some(aot::EMBEDDED.to_string()),
+        _ => vve_filename,
+    };
+    match (&vve_filename, &stdlib_filename) {
+        #[cfg(feature = "aot")]
+        (Some(st), None) if st == aot::EMBEDDED => vm_instance.load_vve_image(aot::embedded_image(MIN_VVE_VERSION)),
+        (Some(st), Some(std)) => {
+            let linked = VveImage::load(st, MIN_VVE_VERSION).and_then(|prog| {
+                VveImage::load(std, MIN_VVE_VERSION).and_then(|std| vvelink::link_stdlib(&prog, &std))
+            });
+            match linked {
+                Ok(img) => vm_instance.load_vve_image(img),
+                Err(e) => {
+                    eprintln!("ERROR: --stdlib: {}", e);
+                    exit(1);
+                }
+            }
+        }
+        (Some(st), None) => vm_instance.load_vve(st, MIN_VVE_VERSION),
+        (None, _) => {}
     }
 
     if (vvr_filename.is_none()) && (vve_filename.is_none()) {
@@ -210,6 +683,22 @@ fn main() {
         exit(0);
     }
 
+    for (name, value) in &defines {
+        if let Err(e) = vm_instance.define_data(name, value) {
+            eprintln!("ERROR: --define {}: {}", name, e);
+            exit(1);
+        }
+    }
+
+    if let Some(max) = max_total_mem {
+        let in_use: u64 = memcap::mem_in_use(&vm_instance);
+        if in_use > max as u64 {
+            eprintln!("ERROR: The program takes {} bytes, more than --max-total-mem={}", in_use, max);
+            exit(1);
+        }
+        vm_instance.max_total_mem = Some(max as u64);
+    }
+
     match native_cfgs {
         Some(v) => {
             let res = vm_instance.nativesys.read_cfg(&v);
@@ -223,54 +712,190 @@ fn main() {
         None => {}
     }
 
-    vm_instance.run();
+    if let Some(dir) = fb_dump_dir {
+        vm_instance.register_ncall(nativefb::FB_PRESENT, nativefb::ppm_presenter(dir));
+    }
+    let missing: Vec<u16> = vm_instance.missing_ncalls();
+    if !missing.is_empty() && allow_native_load {
+        // `ncall @native_load` may provide them later
+        let codes: Vec<String> = missing.iter().map(|c| format!("{:#x}", c)).collect();
+        if !quiet {
+            eprintln!("INFO: ncalls {} aren't provided yet, expecting `ncall @native_load`", codes.join(", "));
+        }
+    } else if !missing.is_empty() {
+        let codes: Vec<String> = missing.iter().map(|c| format!("{:#x}", c)).collect();
+        eprintln!(
+            "ERROR: The program uses ncalls this VM doesn't provide: {} (missing --native-configs=?)",
+            codes.join(", ")
+        );
+        exit(1);
+    }
+
+    if let Some(path) = watch_data {
+        vm_instance.data_watch = Some(DataWatch::new(path));
+    }
+
+    if pause_signals {
+        if let Err(e) = pause::install_signal_handler(vm_instance.pause_handle()) {
+            eprintln!("WARNING: Can't install --pause-signals handler: {}", e);
+        }
+    }
+
+    if let Some(name) = entry_func {
+        if let Err(e) = vm_instance.prepare_function(&name, &entry_args) {
+            eprintln!("ERROR: --entry: {}", e);
+            exit(1);
+        }
+    }
+
+    let run_start: Instant = Instant::now();
+    match (hot_loops, decode_cache) {
+        (Some(threshold), _) => {
+            let mut loops: HotLoops = HotLoops::new(threshold);
+            if decode_cache {
+                loops = loops.with_cache();
+            }
+            vm_instance.run_with(&mut loops);
+            if !quiet {
+                eprintln!("INFO: --hot-loops: {} loop bodies threaded", loops.body_count());
+            }
+        }
+        (None, true) => {
+            let mut cache: DecodeCache = DecodeCache::new();
+            vm_instance.run_with(&mut cache);
+            if !quiet {
+                eprintln!("INFO: --decode-cache: {} instructions decoded", cache.entry_count());
+            }
+        }
+        #[cfg(feature = "aot")]
+        (None, false) => aot::run_embedded(&mut vm_instance, vve_filename.as_deref() == Some(aot::EMBEDDED)),
+        #[cfg(not(feature = "aot"))]
+        (None, false) => vm_instance.run(),
+    }
+    let elapsed = run_start.elapsed();
+
+    if let (Some(path), Some(cov)) = (coverage_filename, &vm_instance.coverage) {
+        if let Err(e) = cov.save(&path) {
+            eprintln!("ERROR: While saving coverage: {}", e);
+        }
+    }
+
+    if let (Some(path), Some(trace)) = (trace_filename, &mut vm_instance.trace) {
+        trace.finish();
+        if let Err(e) = trace.save(&path, &vm_instance.func_names) {
+            eprintln!("ERROR: While saving trace: {}", e);
+        }
+    }
+
+    if let (Some(path), Some(prof)) = (mem_profile_filename, &vm_instance.mem_profile) {
+        if let Err(e) = prof.save(&vm_instance, &path) {
+            eprintln!("ERROR: While saving memory profile: {}", e);
+        }
+    }
+
+    if let Some(path) = dump_state_filename {
+        if let Err(e) = std::fs::write(&path, vm_instance.state_dump()) {
+            eprintln!("ERROR: While saving state dump: {}", e);
+        }
+    }
+
+    if let Some(target) = json_status {
+        let _ = vm_instance.output.stdout.flush();
+        let (program, format) = match (&vve_filename, &vvr_filename) {
+            (Some(vve), _) => (vve.clone(), "vve"),
+            (None, Some(vvr)) => (vvr.clone(), "vvr"),
+            (None, None) => (String::new(), "vvr"),
+        };
+        let vve_version: Option<u16> = vve_filename
+            .as_ref()
+            .and_then(|p| fileformats::VoxExeHeader::load(p, MIN_VVE_VERSION).ok())
+            .map(|h| h.version);
+        let sizes = Sizes {
+            ram: ram_size.unwrap(),
+            stack: stack_size.unwrap(),
+            heap: heap_size.unwrap(),
+        };
+        let status = RunStatus::collect(&vm_instance, program, format, vve_version, sizes, elapsed);
+        if let Err(e) = target.write(&status.to_json()) {
+            eprintln!("ERROR: While writing --json-status: {}", e);
+        }
+    }
 
     if coredump_on_exit {
         let dump = vm_instance.coredump();
-        let mut out_file = match File::create("voxvm.dump") {
-            Ok(f) => f,
-            Err(e) => {
-                println!("While saving coredump: {}", e.to_string());
-                return;
+        match File::create("voxvm.dump") {
+            Ok(mut out_file) => {
+                if let Err(e) = out_file.write_all(&dump) {
+                    eprintln!("ERROR: While saving coredump: {}", e);
+                }
             }
-        };
-        out_file.write_all(&dump);
+            Err(e) => println!("While saving coredump: {}", e.to_string()),
+        }
     }
-}
 
-pub fn pretty_input_tobytes(s: String) -> Option<usize> {
-    let re = Regex::new(r"(?i)(\d+(?:\.\d+)?)\s*(b|kb|mb|gb)").unwrap();
+    if vm_instance.aborted {
+        let _ = vm_instance.output.stdout.flush();
+        exit(abort_exit_code);
+    }
+}
 
-    for cap in re.captures_iter(&s) {
-        let size = &cap[1];
-        let unit = &cap[2];
+/// Annotated source (`--src`) for a coverage file of the vve
+fn coverage_report(
+    cov_path: &str,
+    vve: Option<String>,
+    src: Option<String>,
+    min_version: u16,
+) -> Result<String, String> {
+    let cov: Coverage = Coverage::load(cov_path)?;
+    let vve: String = vve.ok_or("--vve= is required to map addresses to lines")?;
+    let src: String = src.ok_or("--src= (the .vvs source) is required")?;
+    let header = fileformats::VoxExeHeader::load(&vve, min_version)?;
+    let lines: Vec<(u64, u32)> = match header.section(fileformats::SECT_LINES) {
+        Some(sect) => fileformats::read_line_table(&sect.data),
+        None => return Err(format!("{} has no line table, reassemble it", vve)),
+    };
+    let source: String = std::fs::read_to_string(&src).map_err(|e| e.to_string())?;
+    Ok(cov.report(&lines, &source))
+}
 
-        let multiplier: u64 = match unit.to_lowercase().as_str() {
-            "gb" => 1024 * 1024 * 1024, // why not pow? because.
-            "mb" => 1024 * 1024,
-            "kb" => 1024,
-            "b" => 1,
-            _ => 0, // we wont reach it
-        };
-        let size_u64: f64 = size.parse::<f64>().unwrap();
-        let res: usize = (size_u64 * (multiplier as f64)).round() as usize;
-        return Some(res);
+/// `5` is uint, `-5` is int, `5.0` is float
+fn parse_entry_arg(s: &str) -> Option<Register> {
+    if s.contains('.') {
+        return s.parse::<f64>().ok().map(Register::float);
+    }
+    if s.starts_with('-') {
+        return s.parse::<i64>().ok().map(Register::int);
     }
-    None
+    s.parse::<u64>().ok().map(Register::uint)
 }
 
-pub fn pretty_fmt_size(size: u64) -> String {
-    if size >= (1024 * 1024 * 1024) {
-        let gbytes: f64 = size as f64 / (1024 * 1024 * 1024) as f64;
-        return format!("{:.1}GB", gbytes);
+/// `0x10` is hex, `16` is decimal
+fn parse_addr_arg(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse::<usize>().ok(),
     }
-    if size >= (1024 * 1024) {
-        let mbytes: f64 = size as f64 / (1024 * 1024) as f64;
-        return format!("{:.1}MB", mbytes);
+}
+
+
+/// Error if the VM would need more than `available` bytes (0 is unknown,
+/// nothing is checked then), a warning line if stack + heap outgrow RAM
+fn check_init_sizes(ram: usize, stack: usize, heap: usize, available: u64) -> Result<Option<String>, String> {
+    let total: u64 = ram as u64 + stack as u64 + heap as u64;
+    if (available > 0) && (total > available) {
+        return Err(format!(
+            "RAM + stack + heap = {} is more than the {} of memory available.\nHint: lower --init-ram, --init-stack-size or --init-heap-size",
+            pretty_fmt_size(total),
+            pretty_fmt_size(available)
+        ));
     }
-    if size >= (1024) {
-        let kbytes: f64 = size as f64 / 1024 as f64;
-        return format!("{:.1}KB", kbytes);
+    if (stack as u64 + heap as u64) > ram as u64 {
+        return Ok(Some(format!(
+            "WARNING: stack + heap ({}) is more than init RAM ({})",
+            pretty_fmt_size(stack as u64 + heap as u64),
+            pretty_fmt_size(ram as u64)
+        )));
     }
-    return format!("{}B", size);
+    Ok(None)
 }
+
@@ -0,0 +1,44 @@
+use std::mem::size_of;
+
+use crate::{callstack::CSFrame, exceptions::Exception, misclib::show_runtime_err, stack::StackFrame, vm::VM};
+
+// `--max-total-mem=`: one budget over main memory, heap blocks in use,
+// scratch buffers, arenas, the data stack and the call stack. An instruction that would grow past it
+// raises OutOfMemory and does nothing else, `jexc` catches it like any other
+// exception (`--max-pending-exc=0` turns it into a clean halt).
+
+pub const STACK_SLOT_BYTES: u64 = size_of::<StackFrame>() as u64;
+pub const CALL_FRAME_BYTES: u64 = size_of::<CSFrame>() as u64;
+
+/// Bytes the guest holds right now
+pub fn mem_in_use(vm: &VM) -> u64 {
+    let (co_slots, co_frames) = vm.coros.held(); // switched out coroutine stacks
+    vm.memory.len() as u64
+        + vm.heap.used_bytes()
+        + vm.scratch.used_bytes()
+        + vm.arenas.used_bytes()
+        + vm.coros.scratch_bytes()
+        + (vm.stack.stack.len() + co_slots) as u64 * STACK_SLOT_BYTES
+        + (vm.call_stack.stack.len() + co_frames) as u64 * CALL_FRAME_BYTES
+}
+
+/// True if `bytes` more fit into the budget, raises OutOfMemory otherwise
+pub fn mem_reserve(vm: &mut VM, bytes: u64) -> bool {
+    let max: u64 = match vm.max_total_mem {
+        Some(v) => v,
+        None => return true,
+    };
+    let in_use: u64 = mem_in_use(vm);
+    if in_use.saturating_add(bytes) <= max {
+        return true;
+    }
+    show_runtime_err(
+        vm,
+        &format!(
+            "Out of memory: {} bytes in use, {} more requested, budget is {}",
+            in_use, bytes, max
+        ),
+    );
+    vm.exceptions_active.raise_with(Exception::OutOfMemory, bytes);
+    false
+}
@@ -0,0 +1,133 @@
+use std::{collections::HashMap, fs};
+
+use crate::{dstype::DsType, heap::AllocSite, vm::VM};
+
+// Memory access profile (`--mem-profile=out.txt`). Counts reads and writes
+// per data segment variable (dsload, dssave, dsderef, dsstore) and per heap
+// block (load, store, memcpy, storedat and dsstore reads), then lists
+// the hottest ones with their symbol names. A const variable near the top
+// usually is a dsload inside a loop that could be hoisted into a register.
+// Heap blocks are told apart by allocation id, a freed block's address
+// taken by a new one starts a new entry. Scratch buffers aren't counted.
+
+/// Entries the report lists
+pub const MEMPROF_TOP: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MemTarget {
+    Data(usize),     // variable rel addr
+    Heap(u64, u64),  // block start, alloc id
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemCounts {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+#[derive(Debug)]
+struct BlockInfo {
+    size: u64,
+    site: Option<AllocSite>,
+}
+
+#[derive(Debug)]
+pub struct MemProfile {
+    pub counts: HashMap<MemTarget, MemCounts>,
+    blocks: HashMap<(u64, u64), BlockInfo>, // (start, id) -> size and site when first seen
+}
+
+impl MemProfile {
+    pub fn new() -> MemProfile {
+        MemProfile {
+            counts: HashMap::new(),
+            blocks: HashMap::new(),
+        }
+    }
+
+    fn count(&mut self, target: MemTarget, write: bool) {
+        let entry: &mut MemCounts = self.counts.entry(target).or_default();
+        match write {
+            true => entry.writes += 1,
+            false => entry.reads += 1,
+        }
+    }
+
+    /// Most accessed first, ties by target
+    pub fn hottest(&self) -> Vec<(MemTarget, MemCounts)> {
+        let mut res: Vec<(MemTarget, MemCounts)> = self.counts.iter().map(|(t, c)| (*t, *c)).collect();
+        res.sort_by(|a, b| (b.1.reads + b.1.writes).cmp(&(a.1.reads + a.1.writes)).then(a.0.cmp(&b.0)));
+        res
+    }
+
+    /// Top `limit` entries as text, names from the image symbols
+    pub fn report(&self, vm: &VM, limit: usize) -> String {
+        let names: HashMap<usize, &String> = vm.data_names.iter().map(|(n, a)| (*a, n)).collect();
+        let (data_base, _) = vm.data_range();
+        let mut res: String = String::from("# voxvm memory profile\n");
+        res.push_str(&format!("{:>10} {:>10}  {:<18} {}\n", "reads", "writes", "address", "target"));
+        for (target, counts) in self.hottest().into_iter().take(limit) {
+            let (addr, desc) = match target {
+                MemTarget::Data(rel) => {
+                    let var_addr: usize = data_base as usize + rel;
+                    let name: String = match names.get(&rel) {
+                        Some(n) => n.to_string(),
+                        None => format!("<data+{:#x}>", rel),
+                    };
+                    let is_const: bool = vm.memory.get(var_addr).is_some_and(|t| DsType::is_const(*t));
+                    let desc: String = match is_const {
+                        true => format!("data {} (const)", name),
+                        false => format!("data {}", name),
+                    };
+                    (var_addr as u64, desc)
+                }
+                MemTarget::Heap(start, id) => {
+                    let mut desc: String = format!("heap block #{}", id);
+                    if let Some(info) = self.blocks.get(&(start, id)) {
+                        desc.push_str(&format!(", {} bytes", info.size));
+                        if let Some(site) = &info.site {
+                            desc.push_str(&format!(", allocated at {}", vm.site_desc(site)));
+                        }
+                    }
+                    (start, desc)
+                }
+            };
+            res.push_str(&format!("{:>10} {:>10}  {:<18} {}\n", counts.reads, counts.writes, format!("{:#x}", addr), desc));
+        }
+        res
+    }
+
+    pub fn save(&self, vm: &VM, path: &str) -> std::io::Result<()> {
+        fs::write(path, self.report(vm, MEMPROF_TOP))
+    }
+}
+
+impl Default for MemProfile {
+    fn default() -> MemProfile {
+        MemProfile::new()
+    }
+}
+
+/// Counts an access to the data variable at `rel_addr`
+pub fn record_data(vm: &mut VM, rel_addr: usize, write: bool) {
+    if let Some(prof) = &mut vm.mem_profile {
+        prof.count(MemTarget::Data(rel_addr), write);
+    }
+}
+
+/// Counts an access to the heap block containing `ptr`, misses are ignored
+pub fn record_heap(vm: &mut VM, ptr: u64, write: bool) {
+    let Some(prof) = &mut vm.mem_profile else {
+        return;
+    };
+    let block = vm
+        .heap
+        .allocated
+        .iter()
+        .find(|b| (ptr >= b.start_byte as u64) && (ptr <= b.last_byte as u64));
+    if let Some(block) = block {
+        let key: (u64, u64) = (block.start_byte as u64, block.id);
+        prof.blocks.entry(key).or_insert(BlockInfo { size: block.size as u64, site: block.site });
+        prof.count(MemTarget::Heap(key.0, key.1), write);
+    }
+}
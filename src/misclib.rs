@@ -1,3 +1,5 @@
+use regex::Regex;
+
 use crate::{
     native::VMValue,
     registers::Register,
@@ -120,6 +122,19 @@ pub fn string_from_straddr(vm: &mut VM, abs_addr: u64) -> Option<String> {
     bytes_into_string_utf16(bytes_str)
 }
 
+/// Bounds-checked utf16 bytes of the string at StrAddr
+pub fn bytes_from_straddr(vm: &VM, abs_addr: u64) -> Option<Vec<u8>> {
+    let start: usize = abs_addr as usize;
+    if (start < 8) || (start > vm.memory.len()) {
+        return None;
+    }
+    let size: usize = args_to_u64(&vm.memory[(start - 8)..start]) as usize;
+    match vm.memory.get(start..(start.saturating_add(size))) {
+        Some(v) => Some(v.to_vec()),
+        None => None,
+    }
+}
+
 pub fn bytes_into_string_utf16(bytes: &[u8]) -> Option<String> {
     let utf16_data = u8_slice_to_u16_vec(bytes);
 
@@ -147,3 +162,47 @@ pub fn vec16_into_vec8(v: Vec<u16>) -> Vec<u8> {
     }
     res
 }
+
+/// `4096`, `64KB`, `1.5MiB`, `2tb`.. into bytes. Units are binary
+/// (KB = KiB = 1024), a bare number is bytes
+pub fn pretty_input_tobytes(s: String) -> Option<usize> {
+    let re = Regex::new(r"(?i)^\s*(\d+(?:\.\d+)?)\s*(b|kb|kib|mb|mib|gb|gib|tb|tib)?\s*$").unwrap();
+
+    let cap = re.captures(&s)?;
+    let size = &cap[1];
+    let unit: String = cap.get(2).map_or("b".to_string(), |u| u.as_str().to_lowercase());
+
+    let multiplier: u64 = match unit.trim_end_matches("ib").trim_end_matches('b') {
+        "t" => 1024 * 1024 * 1024 * 1024,
+        "g" => 1024 * 1024 * 1024, // why not pow? because.
+        "m" => 1024 * 1024,
+        "k" => 1024,
+        _ => 1,
+    };
+    let size_u64: f64 = size.parse::<f64>().ok()?;
+    let res: f64 = (size_u64 * (multiplier as f64)).round();
+    if res >= usize::MAX as f64 {
+        return None;
+    }
+    Some(res as usize)
+}
+
+pub fn pretty_fmt_size(size: u64) -> String {
+    if size >= (1024 * 1024 * 1024 * 1024) {
+        let tbytes: f64 = size as f64 / (1024u64 * 1024 * 1024 * 1024) as f64;
+        return format!("{:.1}TB", tbytes);
+    }
+    if size >= (1024 * 1024 * 1024) {
+        let gbytes: f64 = size as f64 / (1024 * 1024 * 1024) as f64;
+        return format!("{:.1}GB", gbytes);
+    }
+    if size >= (1024 * 1024) {
+        let mbytes: f64 = size as f64 / (1024 * 1024) as f64;
+        return format!("{:.1}MB", mbytes);
+    }
+    if size >= (1024) {
+        let kbytes: f64 = size as f64 / 1024 as f64;
+        return format!("{:.1}KB", kbytes);
+    }
+    return format!("{}B", size);
+}
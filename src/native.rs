@@ -1,6 +1,5 @@
 use std::{
     collections::HashMap,
-    env,
     ffi::c_void,
     fs::{File, read_dir},
     io,
@@ -10,7 +9,7 @@ use libloading::{Library, Symbol};
 use maplit::hashmap;
 use serde::Deserialize;
 
-use crate::{defnative::{getunixtime, ncall_print, randf, randint, readin, runcmd, sleepcall}, nativefiles::{ncall_fclose, ncall_fdel, ncall_fopen, ncall_fread, ncall_fseekget, ncall_fseekset, ncall_fwrite}, nativenet::{ncall_nc_accept, ncall_nc_bind, ncall_nc_close, ncall_nc_getaddr, ncall_nc_read, ncall_nc_write}, vm::InstructionHandler};
+use crate::{arena::{ncall_arena_free, ncall_arena_new, ncall_arena_reset, ncall_arena_stats}, exceptions::{ncall_exc_clear, ncall_exc_count, ncall_exc_peek}, defnative::{getunixtime, ncall_dsintern, ncall_gc_collect, ncall_heap_stats, ncall_native_load, ncall_pin, ncall_print, ncall_regdump, ncall_res_usage, ncall_streq, ncall_unpin, randf, randint, readin, runcmd, sleepcall}, heapsnap::{ncall_heap_diff, ncall_heap_snap, ncall_heap_snap_drop}, nativefiles::{ncall_fclose, ncall_fdel, ncall_fopen, ncall_fread, ncall_fc_usage, ncall_fseekget, ncall_fseekset, ncall_fwrite}, nativeerr::ncall_lasterr, nativeasm::{ncall_asm_func, ncall_asm_load}, nativeshm::{ncall_shm_create, ncall_shm_query, ncall_shm_release}, nativemmap::{ncall_mmap_close, ncall_mmap_open, ncall_mmap_query}, limits::{ncall_rec_limit, ncall_rec_limit_set, ncall_stack_limit, ncall_stack_limit_set}, nativeaudio::{ncall_audio_close, ncall_audio_open, ncall_audio_queued, ncall_audio_write}, nativeevent::{ncall_ev_count, ncall_ev_dropped, ncall_ev_pop, ncall_ev_term_pump}, nativefb::{ncall_fb_blit, ncall_fb_create, ncall_fb_fill, ncall_fb_info, ncall_fb_resize}, nativecsv::{ncall_csv_join, ncall_csv_split}, nativepath::{ncall_path_abs, ncall_path_ext, ncall_path_join, ncall_path_name, ncall_path_norm, ncall_path_parent}, nativestr::{ncall_printf, ncall_str_len, ncall_str_offset, ncall_str_slice}, nativeiov::{ncall_gather_write, ncall_scatter_read}, nativeterm::{ncall_term_clear, ncall_term_color, ncall_term_cursor, ncall_term_goto, ncall_term_key, ncall_term_raw, ncall_term_size}, nativenet::{ncall_nc_accept, ncall_nc_bind, ncall_nc_close, ncall_nc_count, ncall_nc_getaddr, ncall_nc_getpeer, ncall_nc_read, ncall_nc_usage, ncall_nc_write}, heap::Heap, vm::{InstructionHandler, VM}};
 
 pub const REPO_LINK: &str = "https://github.com/Freemorger/voxvm";
 
@@ -22,12 +21,142 @@ pub struct VMValue {
 }
 type VMFFIFunction = unsafe extern "C" fn(args: *const VMValue, len: u32) -> VMValue;
 
+/// Heap access for native functions declared with `heap_api = true`: they
+/// take a third argument, `const HeapApi*`, and reach heap buffers by their
+/// guest address through it instead of raw pointers into the heap, which
+/// can move when it grows. `len` gives the bytes from `ptr` to the end of
+/// its block (0 if ptr isn't in an allocated block), `read` and `write` copy
+/// `count` bytes and return 0, or 1 without copying anything if the range
+/// leaves the block. The table is only valid during the call. GC doesn't run
+/// while a native function does, blocks kept by the plugin across calls have
+/// to stay reachable or be pinned (`ncall @pin`); addresses written by
+/// plugins aren't tracked as references. `shared` gives a host pointer to
+/// the shared region (`ncall @shm_create`) containing ptr and writes its
+/// size, null if ptr isn't in one. Unlike the table, that pointer stays
+/// valid after the call, see nativeshm.rs.
+#[derive(Debug)]
+#[repr(C)]
+pub struct HeapApi {
+    pub ctx: *mut c_void,
+    pub len: unsafe extern "C" fn(ctx: *mut c_void, ptr: u64) -> u64,
+    pub read: unsafe extern "C" fn(ctx: *mut c_void, ptr: u64, dst: *mut u8, count: u64) -> u32,
+    pub write: unsafe extern "C" fn(ctx: *mut c_void, ptr: u64, src: *const u8, count: u64) -> u32,
+    pub shared: unsafe extern "C" fn(ctx: *mut c_void, ptr: u64, size: *mut u64) -> *mut u8,
+}
+type VMFFIHeapFunction =
+    unsafe extern "C" fn(args: *const VMValue, len: u32, heap: *const HeapApi) -> VMValue;
+
+impl HeapApi {
+    fn new(heap: &mut Heap) -> HeapApi {
+        HeapApi {
+            ctx: (heap as *mut Heap).cast(),
+            len: heap_api_len,
+            read: heap_api_read,
+            write: heap_api_write,
+            shared: heap_api_shared,
+        }
+    }
+}
+
+unsafe extern "C" fn heap_api_len(ctx: *mut c_void, ptr: u64) -> u64 {
+    // SAFETY: ctx is the &mut Heap HeapApi::new got, it outlives the call
+    let heap: &Heap = unsafe { &*(ctx as *const Heap) };
+    heap.allocated
+        .iter()
+        .find(|b| (ptr >= b.start_byte as u64) && (ptr <= b.last_byte as u64))
+        .map_or(0, |b| b.last_byte as u64 - ptr)
+}
+
+unsafe extern "C" fn heap_api_read(ctx: *mut c_void, ptr: u64, dst: *mut u8, count: u64) -> u32 {
+    let heap: &mut Heap = unsafe { &mut *(ctx as *mut Heap) };
+    if (count == 0) || (count > unsafe { heap_api_len(ctx, ptr) }) {
+        return (count != 0) as u32;
+    }
+    match heap.read(ptr, count) {
+        Ok(bytes) => {
+            // SAFETY: the caller passes a dst of at least count bytes
+            unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), dst, bytes.len()) };
+            0
+        }
+        Err(()) => 1,
+    }
+}
+
+unsafe extern "C" fn heap_api_write(ctx: *mut c_void, ptr: u64, src: *const u8, count: u64) -> u32 {
+    let heap: &mut Heap = unsafe { &mut *(ctx as *mut Heap) };
+    if (count == 0) || (count > unsafe { heap_api_len(ctx, ptr) }) {
+        return (count != 0) as u32;
+    }
+    // SAFETY: the caller passes a src of at least count bytes
+    let bytes: Vec<u8> = unsafe { std::slice::from_raw_parts(src, count as usize) }.to_vec();
+    heap.write(ptr, bytes).map_or(1, |_| 0)
+}
+
+unsafe extern "C" fn heap_api_shared(ctx: *mut c_void, ptr: u64, size: *mut u64) -> *mut u8 {
+    let heap: &mut Heap = unsafe { &mut *(ctx as *mut Heap) };
+    match heap.shared_host_ptr(ptr) {
+        Some((host, len)) => {
+            if !size.is_null() {
+                // SAFETY: a non-null size points to a u64 of the caller
+                unsafe { *size = len };
+            }
+            host
+        }
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// What an opcode hook sees, registers are passed as VMValues
+/// and written back after the hook returns
+#[derive(Debug)]
+#[repr(C)]
+pub struct HookFrame {
+    pub opcode: u32,
+    pub ip: u64, // pre hooks skipping the instruction set where to continue
+    pub regs: *mut VMValue,
+    pub reg_count: u32,
+    pub code: *const u8, // instruction bytes from ip on
+    pub code_len: u64,
+}
+/// Pre hooks return non-zero to skip (emulate) the instruction,
+/// return values of post hooks are ignored
+pub type VMHookFunction = unsafe extern "C" fn(frame: *mut HookFrame) -> u32;
+
+/// Rust function an embedder exposes to the guest as an ncall,
+/// it works on the VM like std call handlers do
+pub type HostCall = Box<dyn FnMut(&mut VM)>;
+
+struct HostFn(HostCall);
+
+impl std::fmt::Debug for HostFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("HostFn")
+    }
+}
+
+/// What answers an ncall code. Host calls shadow std calls,
+/// std calls shadow functions of native config libraries
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NcallSource {
+    Host,
+    Std,
+    Library,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct OpHooks {
+    pub pre: Vec<VMHookFunction>,
+    pub post: Vec<VMHookFunction>,
+}
+
 #[derive(Debug)]
 pub struct NativeService {
     libs: Vec<NativeLibrary>,
     platform: NSysOS,
     ncall_codes: HashMap<u16, (usize, NFuncCfg)>, // value is (lib ind, funcname)
     pub std_calls: HashMap<u16, InstructionHandler>,
+    host_calls: HashMap<u16, HostFn>, // registered by embedders
+    hooks: HashMap<u8, OpHooks>,      // opcode -> hooks of native plugins
 }
 
 impl NativeService {
@@ -47,7 +176,53 @@ impl NativeService {
             libs: (Vec::new()),
             platform: os,
             ncall_codes: HashMap::new(),
-            std_calls: Self::get_std_calls()
+            std_calls: Self::get_std_calls(),
+            host_calls: HashMap::new(),
+            hooks: HashMap::new(),
+        }
+    }
+
+    /// Registers `hook` to run before (`pre`) or after the handler of `opcode`
+    pub fn add_hook(&mut self, opcode: u8, pre: bool, hook: VMHookFunction) {
+        let entry: &mut OpHooks = self.hooks.entry(opcode).or_default();
+        match pre {
+            true => entry.pre.push(hook),
+            false => entry.post.push(hook),
+        }
+    }
+
+    /// False lets the VM skip hook lookups entirely
+    pub fn has_hooks(&self) -> bool {
+        !self.hooks.is_empty()
+    }
+
+    pub fn hooks_of(&self, opcode: u8) -> Option<&OpHooks> {
+        self.hooks.get(&opcode)
+    }
+
+    fn load_hooks(&mut self, cfg: &NSysCfg) {
+        let hooks: &HashMap<String, NHookCfg> = match &cfg.hooks {
+            Some(v) => v,
+            None => return,
+        };
+        for hook_cfg in hooks.values() {
+            let pre: bool = match hook_cfg.when.as_deref() {
+                None | Some("pre") => true,
+                Some("post") => false,
+                Some(other) => {
+                    eprintln!("Hook {} of {}: unknown `when` '{}', use pre or post", hook_cfg.name, cfg.name, other);
+                    continue;
+                }
+            };
+            let hook: VMHookFunction = match self.libs.last().map(|l| l.get_hook(&hook_cfg.name)) {
+                Some(Ok(v)) => v,
+                Some(Err(e)) => {
+                    eprintln!("Hook {} of {}: {}", hook_cfg.name, cfg.name, e);
+                    continue;
+                }
+                None => return,
+            };
+            self.add_hook(hook_cfg.opcode, pre, hook);
         }
     }
 
@@ -60,6 +235,14 @@ impl NativeService {
             5 => getunixtime as InstructionHandler,
             6 => sleepcall as InstructionHandler,
             7 => runcmd as InstructionHandler,
+            8 => ncall_dsintern as InstructionHandler,
+            9 => ncall_streq as InstructionHandler,
+            0xA => ncall_pin as InstructionHandler,
+            0xB => ncall_unpin as InstructionHandler,
+            0xC => ncall_lasterr as InstructionHandler,
+            0xD => ncall_regdump as InstructionHandler,
+            0xE => ncall_gc_collect as InstructionHandler,
+            0xF => ncall_heap_stats as InstructionHandler,
             0x10 => ncall_fopen as InstructionHandler,
             0x11 => ncall_fclose as InstructionHandler,
             0x12 => ncall_fwrite as InstructionHandler,
@@ -67,12 +250,74 @@ impl NativeService {
             0x14 => ncall_fdel as InstructionHandler,
             0x15 => ncall_fseekget as InstructionHandler,
             0x16 => ncall_fseekset as InstructionHandler,
+            0x17 => ncall_gather_write as InstructionHandler,
+            0x18 => ncall_scatter_read as InstructionHandler,
+            0x19 => ncall_fc_usage as InstructionHandler,
             0x20 => ncall_nc_bind as InstructionHandler,
             0x21 => ncall_nc_close as InstructionHandler,
             0x22 => ncall_nc_accept as InstructionHandler,
             0x23 => ncall_nc_write as InstructionHandler,
             0x24 => ncall_nc_read as InstructionHandler,
             0x25 => ncall_nc_getaddr as InstructionHandler,
+            0x26 => ncall_nc_getpeer as InstructionHandler,
+            0x27 => ncall_nc_count as InstructionHandler,
+            0x28 => ncall_nc_usage as InstructionHandler,
+            0x30 => ncall_exc_count as InstructionHandler,
+            0x31 => ncall_exc_peek as InstructionHandler,
+            0x32 => ncall_exc_clear as InstructionHandler,
+            0x40 => ncall_heap_snap as InstructionHandler,
+            0x41 => ncall_heap_diff as InstructionHandler,
+            0x42 => ncall_heap_snap_drop as InstructionHandler,
+            0x43 => ncall_res_usage as InstructionHandler,
+            0x50 => ncall_term_goto as InstructionHandler,
+            0x51 => ncall_term_clear as InstructionHandler,
+            0x52 => ncall_term_color as InstructionHandler,
+            0x53 => ncall_term_size as InstructionHandler,
+            0x54 => ncall_term_cursor as InstructionHandler,
+            0x55 => ncall_term_raw as InstructionHandler,
+            0x56 => ncall_term_key as InstructionHandler,
+            0x58 => ncall_ev_count as InstructionHandler,
+            0x59 => ncall_ev_pop as InstructionHandler,
+            0x5A => ncall_ev_dropped as InstructionHandler,
+            0x5B => ncall_ev_term_pump as InstructionHandler,
+            0x60 => ncall_fb_create as InstructionHandler,
+            0x61 => ncall_fb_resize as InstructionHandler,
+            0x62 => ncall_fb_fill as InstructionHandler,
+            0x63 => ncall_fb_blit as InstructionHandler,
+            0x64 => ncall_fb_info as InstructionHandler,
+            0x70 => ncall_audio_open as InstructionHandler,
+            0x71 => ncall_audio_write as InstructionHandler,
+            0x72 => ncall_audio_close as InstructionHandler,
+            0x73 => ncall_audio_queued as InstructionHandler,
+            0x80 => ncall_str_len as InstructionHandler,
+            0x81 => ncall_str_slice as InstructionHandler,
+            0x82 => ncall_str_offset as InstructionHandler,
+            0x83 => ncall_printf as InstructionHandler,
+            0x84 => ncall_csv_split as InstructionHandler,
+            0x85 => ncall_csv_join as InstructionHandler,
+            0x90 => ncall_asm_load as InstructionHandler,
+            0x91 => ncall_asm_func as InstructionHandler,
+            0xA0 => ncall_shm_create as InstructionHandler,
+            0xA1 => ncall_shm_query as InstructionHandler,
+            0xA2 => ncall_shm_release as InstructionHandler,
+            0xB0 => ncall_rec_limit as InstructionHandler,
+            0xB1 => ncall_rec_limit_set as InstructionHandler,
+            0xB2 => ncall_stack_limit as InstructionHandler,
+            0xB3 => ncall_stack_limit_set as InstructionHandler,
+            0xC0 => ncall_mmap_open as InstructionHandler,
+            0xC1 => ncall_mmap_query as InstructionHandler,
+            0xC2 => ncall_mmap_close as InstructionHandler,
+            0xD0 => ncall_native_load as InstructionHandler,
+            0xE0 => ncall_path_join as InstructionHandler,
+            0xE1 => ncall_path_norm as InstructionHandler,
+            0xE2 => ncall_path_name as InstructionHandler,
+            0xE3 => ncall_path_ext as InstructionHandler,
+            0xE4 => ncall_path_parent as InstructionHandler,
+            0xE5 => ncall_path_abs as InstructionHandler,
+            0xF0 => ncall_arena_new as InstructionHandler,
+            0xF1 => ncall_arena_free as InstructionHandler,
+            0xF2 => ncall_arena_reset as InstructionHandler,
+            0xF3 => ncall_arena_stats as InstructionHandler,
         }
     }
 
@@ -86,67 +331,34 @@ impl NativeService {
         };
 
         for filepath in filepaths {
-            let curdir = env::current_dir().unwrap();
-
-            let cfg_s = std::fs::read_to_string(format!("{}/{}", cfg_dir, filepath)).unwrap();
-            let cfg: NSysCfg = match toml::from_str(&cfg_s) {
+            let cfg: NSysCfg = match read_cfg_file(&format!("{}/{}", cfg_dir, filepath)) {
                 Ok(v) => v,
+                Err(NSysError::Other(e)) => {
+                    eprintln!("{}", e);
+                    continue;
+                }
                 Err(e) => {
-                    eprintln!("{}", e.to_string());
+                    eprintln!("{:?}", e);
                     continue;
                 }
             };
-
-            match cfg.functions {
-                Some(ref v) => {
-                    for key in v {
-                        self.ncall_codes
-                            .insert(key.1.ncall_code, (self.libs.len(), key.1.clone()));
-                    }
-                }
-                None => {}
-            }
-
-            let cfg_clone = cfg.clone();
-            let lib_filename: String = match self.platform {
-                NSysOS::Linux => match cfg_clone.lib_filename_linux {
-                    Some(v) => v,
-                    None => {
-                        eprintln!(
-                            "Can't get config for {} library for this platform",
-                            cfg_clone.name
-                        );
-                        "".to_string()
-                    }
-                },
-                NSysOS::MacOS => match cfg_clone.lib_filename_linux {
-                    Some(v) => v,
-                    None => {
-                        eprintln!(
-                            "Can't get config for {} library for this platform",
-                            cfg_clone.name
-                        );
-                        "".to_string()
-                    }
-                },
-                NSysOS::Windows => match cfg_clone.lib_filename_linux {
-                    Some(v) => v,
-                    None => {
-                        eprintln!(
-                            "Can't get config for {} library for this platform",
-                            cfg.name
-                        );
-                        "".to_string()
+            let lib_filename: String = self.lib_filename(&cfg)?;
+
+            // codes of a library that failed to load stay missing
+            let lib_ind: usize = self.libs.len();
+            match self.loadname(&lib_filename, cfg.clone()) {
+                Ok(_) => {
+                    for func in cfg.functions.iter().flat_map(|f| f.values()) {
+                        if let Some(src) = self.source_of(func.ncall_code).filter(|s| *s != NcallSource::Library) {
+                            eprintln!(
+                                "WARNING: ncall {:#x} ({} of {}) is shadowed by a {:?} call",
+                                func.ncall_code, func.name, cfg.name, src
+                            );
+                        }
+                        self.ncall_codes.insert(func.ncall_code, (lib_ind, func.clone()));
                     }
-                },
-                NSysOS::Other => {
-                    eprintln!("This system isn't yet supported for non-standard native calls.\n You may contribute at {}", REPO_LINK);
-                    return Err(NSysError::UnknownOS());
+                    self.load_hooks(&cfg)
                 }
-            };
-
-            match self.loadname(&lib_filename, cfg) {
-                Ok(_) => {}
                 Err(e) => {
                     eprintln!("{}", e.to_string());
                     continue;
@@ -157,7 +369,93 @@ impl NativeService {
         Ok(())
     }
 
-    pub fn call_code(&mut self, call_code: u16, args: &[VMValue]) -> Result<VMValue, NSysError> {
+    /// Loads one more native config while the VM runs (`ncall @native_load`,
+    /// `VM::load_native_config`) and returns the ncall codes it registered.
+    /// Unlike `--native-configs` at startup nothing gets shadowed: a config
+    /// with a code that already runs something (or twice) is refused before
+    /// its library loads. Hooks are startup only, decoded instructions
+    /// (--decode-cache, --hot-loops) wouldn't see them
+    pub fn load_cfg(&mut self, path: &str) -> Result<Vec<u16>, NSysError> {
+        let cfg: NSysCfg = read_cfg_file(path)?;
+        if cfg.hooks.as_ref().is_some_and(|h| !h.is_empty()) {
+            return Err(NSysError::Other(format!("{}: hooks can only be loaded with --native-configs", cfg.name)));
+        }
+        let mut codes: Vec<u16> = cfg.functions.iter().flat_map(|f| f.values()).map(|f| f.ncall_code).collect();
+        codes.sort_unstable();
+        let taken: Option<u16> = codes
+            .windows(2)
+            .find(|w| w[0] == w[1])
+            .map(|w| w[0])
+            .or_else(|| codes.iter().copied().find(|c| self.has_code(*c)));
+        if let Some(code) = taken {
+            return Err(NSysError::CodeCollision(code));
+        }
+        let lib_filename: String = self.lib_filename(&cfg)?;
+        let lib_ind: usize = self.libs.len();
+        self.loadname(&lib_filename, cfg.clone()).map_err(NSysError::Other)?;
+        for func in cfg.functions.iter().flat_map(|f| f.values()) {
+            self.ncall_codes.insert(func.ncall_code, (lib_ind, func.clone()));
+        }
+        Ok(codes)
+    }
+
+    /// Library file of `cfg` for this platform
+    fn lib_filename(&self, cfg: &NSysCfg) -> Result<String, NSysError> {
+        let lib_filename: Option<String> = match self.platform {
+            NSysOS::Linux | NSysOS::MacOS | NSysOS::Windows => cfg.lib_filename_linux.clone(),
+            NSysOS::Other => {
+                eprintln!("This system isn't yet supported for non-standard native calls.\n You may contribute at {}", REPO_LINK);
+                return Err(NSysError::UnknownOS());
+            }
+        };
+        Ok(lib_filename.unwrap_or_else(|| {
+            eprintln!("Can't get config for {} library for this platform", cfg.name);
+            "".to_string()
+        }))
+    }
+
+    /// A host call, a std call or a function of a loaded native config
+    pub fn has_code(&self, call_code: u16) -> bool {
+        self.source_of(call_code).is_some()
+    }
+
+    /// What `ncall call_code` runs, see NcallSource for the precedence
+    pub fn source_of(&self, call_code: u16) -> Option<NcallSource> {
+        if self.host_calls.contains_key(&call_code) {
+            Some(NcallSource::Host)
+        } else if self.std_calls.contains_key(&call_code) {
+            Some(NcallSource::Std)
+        } else if self.ncall_codes.contains_key(&call_code) {
+            Some(NcallSource::Library)
+        } else {
+            None
+        }
+    }
+
+    /// Makes `ncall call_code` run `f`, replacing an earlier host call.
+    /// Returns what the code ran before
+    pub fn register_host(&mut self, call_code: u16, f: HostCall) -> Option<NcallSource> {
+        let prev: Option<NcallSource> = self.source_of(call_code);
+        self.host_calls.insert(call_code, HostFn(f));
+        prev
+    }
+
+    /// Drops the host call, the code falls back to a std call or library
+    pub fn unregister_host(&mut self, call_code: u16) -> bool {
+        self.host_calls.remove(&call_code).is_some()
+    }
+
+    /// Host calls get `&mut VM`, so the VM lends them out while they run
+    pub(crate) fn take_host(&mut self, call_code: u16) -> Option<HostCall> {
+        self.host_calls.remove(&call_code).map(|f| f.0)
+    }
+
+    /// Puts a lent host call back, unless it registered a replacement meanwhile
+    pub(crate) fn restore_host(&mut self, call_code: u16, f: HostCall) {
+        self.host_calls.entry(call_code).or_insert(HostFn(f));
+    }
+
+    pub fn call_code(&mut self, call_code: u16, args: &[VMValue], heap: &mut Heap) -> Result<VMValue, NSysError> {
 
         let funcdat = match self.ncall_codes.get(&call_code) {
             Some(v) => v,
@@ -180,7 +478,10 @@ impl NativeService {
             return Err(NSysError::InvalidArgs());
         }   
         let args_foo = args.get(1..f.argc).unwrap_or(&[]);
-        let res = lib.call_foo(f.name, args_foo, f.argc as u32); // r0 is for res
+        let res = match f.heap_api {
+            true => lib.call_foo_heap(f.name, args_foo, f.argc as u32, &HeapApi::new(heap)),
+            false => lib.call_foo(f.name, args_foo, f.argc as u32), // r0 is for res
+        };
 
         match res {
             Ok(v) => {
@@ -205,6 +506,12 @@ impl NativeService {
     }
 }
 
+impl Default for NativeService {
+    fn default() -> NativeService {
+        NativeService::new()
+    }
+}
+
 #[derive(Debug)]
 pub enum NSysError {
     Libloading(libloading::Error),
@@ -213,6 +520,7 @@ pub enum NSysError {
     NoLibrary(),
     InvalidArgs(),
     UnknownOS(),
+    CodeCollision(u16), // a runtime loaded config reuses a registered ncall code
     Other(String),
 }
 
@@ -226,6 +534,7 @@ pub struct NSysCfg {
     lib_filename_win: Option<String>,
 
     functions: Option<HashMap<String, NFuncCfg>>,
+    hooks: Option<HashMap<String, NHookCfg>>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -233,6 +542,15 @@ pub struct NFuncCfg {
     name: String,
     ncall_code: u16,
     argc: usize,
+    #[serde(default)]
+    heap_api: bool, // takes a HeapApi* after argc
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct NHookCfg {
+    name: String,
+    opcode: u8,
+    when: Option<String>, // "pre" (default) or "post"
 }
 
 #[derive(Debug)]
@@ -288,6 +606,53 @@ impl NativeLibrary {
 
         Ok(res)
     }
+
+    pub fn call_foo_heap(
+        &mut self,
+        name: String,
+        args: &[VMValue],
+        argc: u32,
+        heap: &HeapApi,
+    ) -> Result<VMValue, libloading::Error> {
+        let symb: Symbol<VMFFIHeapFunction> = unsafe { self.library.get(name.as_bytes())? };
+        let res = unsafe { symb(args.as_ptr(), argc, heap) };
+
+        Ok(res)
+    }
+
+    /// The library stays loaded for the VM lifetime, so the pointer does too
+    fn get_hook(&self, name: &str) -> Result<VMHookFunction, libloading::Error> {
+        let symb: Symbol<VMHookFunction> = unsafe { self.library.get(name.as_bytes())? };
+        Ok(*symb)
+    }
+}
+
+fn read_cfg_file(path: &str) -> Result<NSysCfg, NSysError> {
+    let cfg_s: String = std::fs::read_to_string(path).map_err(NSysError::fs)?;
+    toml::from_str(&cfg_s).map_err(|e| NSysError::Other(e.to_string()))
+}
+
+/// Reads only the ncall names of configs in `cfg_dir`, for the assembler
+pub fn read_cfg_ncall_names(cfg_dir: &str) -> Result<HashMap<String, u16>, NSysError> {
+    let filepaths = get_files_in_directory(cfg_dir).map_err(NSysError::fs)?;
+    let mut res: HashMap<String, u16> = HashMap::new();
+    for filepath in filepaths {
+        let cfg_s = match std::fs::read_to_string(format!("{}/{}", cfg_dir, filepath)) {
+            Ok(v) => v,
+            Err(e) => return Err(NSysError::fs(e)),
+        };
+        let cfg: NSysCfg = match toml::from_str(&cfg_s) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("{}", e.to_string());
+                continue;
+            }
+        };
+        for func in cfg.functions.unwrap_or_default().into_values() {
+            res.insert(func.name, func.ncall_code);
+        }
+    }
+    Ok(res)
 }
 
 fn get_files_in_directory(path: &str) -> std::io::Result<Vec<String>> {
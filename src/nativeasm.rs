@@ -0,0 +1,207 @@
+use std::{
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+};
+
+use crate::{
+    assembly::VoxAssembly,
+    exceptions::Exception,
+    fileformats::{
+        read_ncalls, read_relocs, read_stack_depths, read_symbols, RELOC_CODE, RELOC_DATA_ABS, RELOC_DATA_REL, RELOC_FUNC, SECT_FUNC_META,
+        SECT_INTERN, SECT_NCALLS, SECT_RELOCS, SECT_RODATA, SECT_STACK_DEPTH, SECT_SYMBOLS,
+    },
+    memcap::mem_reserve,
+    misclib::{args_to_u64, bytes_from_straddr},
+    nativeerr::{native_fault, NativeErrKind, NativeError, NativeSubsys},
+    nativestr::read_str,
+    registers::Register,
+    segments::SegmKind,
+    vm::{RegTypes, VM},
+    vvelink::VveImage,
+};
+
+// Runtime assembly: guest code hands voxasm source to `ncall @asm_load`
+// and gets back a module handle, `ncall @asm_func` turns a function name of
+// the module into a function table index for `callr` (or `invoker`).
+// The source is a string like the text ncalls take (see nativestr.rs),
+// lines are separated by newlines. It's assembled the way `--vas` would,
+// then copied after everything else in main memory at a multiple of
+// MODULE_ALIGN, so `!align=` of its data still holds, and relocated: code
+// and data addresses point into the copy, function indices continue the
+// VM's function table. Module code is a Dynamic segment, its data gets
+// ROData/Data segments like the program's. Modules can't call functions of
+// the program by name (only through indices in registers) and stay loaded
+// until the VM exits.
+
+const MODULE_ALIGN: usize = 64;
+
+#[derive(Debug)]
+pub struct AsmModule {
+    pub funcs: HashMap<String, usize>, // name -> function table index
+}
+
+/// Vve image of `src`, Err with the assembler's message
+pub fn assemble_source(src: &str) -> Result<Vec<u8>, String> {
+    // the assembler reports source errors by panicking
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut asm = VoxAssembly::from_source(src);
+        asm.assemble();
+        asm.image().to_vec()
+    }));
+    panic::set_hook(default_hook);
+    res.map_err(|e| match (e.downcast_ref::<String>(), e.downcast_ref::<&str>()) {
+        (Some(msg), _) => msg.clone(),
+        (None, Some(msg)) => msg.to_string(),
+        (None, None) => "assembler failed".to_string(),
+    })
+}
+
+/// Copies `img` into main memory from `start` on and registers its
+/// functions, returns the module
+fn place_module(vm: &mut VM, img: &VveImage, start: usize) -> Result<AsmModule, String> {
+    let relocs: Vec<(u64, u8)> = match img.header.section(SECT_RELOCS) {
+        Some(sect) => read_relocs(&sect.data),
+        None => Vec::new(),
+    };
+    let img_data: u64 = img.header.data_base;
+    let (vm_data, _) = vm.data_range();
+    let func_base: usize = vm.func_table.len();
+    let mut body: Vec<u8> = img.body.clone();
+    for (offset, kind) in relocs {
+        let at: usize = offset as usize;
+        let Some(bytes) = body.get(at..(at + 8)) else {
+            return Err(format!("relocation at {:#x} is out of the module", at));
+        };
+        let val: u64 = args_to_u64(bytes);
+        let fixed: u64 = match kind {
+            RELOC_CODE | RELOC_DATA_ABS => val + start as u64,
+            RELOC_DATA_REL => (start as u64 + img_data + val).wrapping_sub(vm_data),
+            RELOC_FUNC => val + func_base as u64,
+            k => return Err(format!("unknown relocation kind {:#x}", k)),
+        };
+        body[at..(at + 8)].copy_from_slice(&fixed.to_be_bytes());
+    }
+
+    let code_end: usize = start + img_data as usize;
+    let ro_end: usize = match img.header.section(SECT_RODATA) {
+        Some(sect) => code_end + args_to_u64(&sect.data[0..8]) as usize,
+        None => code_end,
+    };
+    vm.memory.resize(start, 0);
+    vm.memory.extend_from_slice(&body);
+    vm.segments.push(SegmKind::Dynamic, start, code_end);
+    if ro_end > code_end {
+        vm.segments.push(SegmKind::ROData, code_end, ro_end);
+    }
+    vm.segments.push(SegmKind::Data, ro_end, vm.memory.len());
+
+    vm.func_table.extend(img.header.func_table.iter().map(|addr| addr + start as u64));
+    let mut funcs: HashMap<String, usize> = HashMap::new();
+    if let Some(sect) = img.header.section(SECT_SYMBOLS) {
+        for (ind, name) in read_symbols(&sect.data) {
+            // traces and reports name module functions too, program names win
+            vm.func_names.entry(name.clone()).or_insert(func_base + ind);
+            funcs.insert(name, func_base + ind);
+        }
+    }
+    if let Some(sect) = img.header.section(SECT_FUNC_META) {
+        let count: usize = args_to_u64(&sect.data[0..8]) as usize;
+        for i in 0..count {
+            let entry: &[u8] = &sect.data[(8 + i * 16)..(24 + i * 16)];
+            let ind: usize = args_to_u64(&entry[0..8]) as usize;
+            vm.func_clobbers.insert(func_base + ind, args_to_u64(&entry[8..16]) as u32);
+        }
+    }
+    if let Some(sect) = img.header.section(SECT_STACK_DEPTH) {
+        for (ind, depth) in read_stack_depths(&sect.data) {
+            vm.func_stack_depths.insert(func_base + ind, depth);
+        }
+    }
+    if let Some(sect) = img.header.section(SECT_INTERN) {
+        let count: usize = args_to_u64(&sect.data[0..8]) as usize;
+        for i in 0..count {
+            let rel_addr: u64 = args_to_u64(&sect.data[(8 + i * 8)..(16 + i * 8)]);
+            let str_addr: u64 = code_end as u64 + rel_addr + 1 + 8; // type, length
+            if let Some(content) = bytes_from_straddr(vm, str_addr) {
+                vm.interned.insert(str_addr, content);
+            }
+        }
+    }
+    Ok(AsmModule { funcs })
+}
+
+fn asm_fault(vm: &mut VM, kind: NativeErrKind, msg: &str) {
+    native_fault(vm, NativeError::new(NativeSubsys::Asm, kind), Exception::NativeFault, msg);
+}
+
+/// ncall 0x90
+/// r1 is voxasm source (r2 byte count if r1 is a heap address).
+/// Assembles and loads it, r0 = module handle, 0 if it failed
+pub fn ncall_asm_load(vm: &mut VM) {
+    vm.registers[0] = Register::uint(0);
+    vm.reg_types[0] = RegTypes::uint64;
+    let Some(src) = read_str(vm, NativeSubsys::Asm, "asm_load") else {
+        return;
+    };
+    let image: Vec<u8> = match assemble_source(&src) {
+        Ok(v) => v,
+        Err(e) => return asm_fault(vm, NativeErrKind::InvalidInput, &format!("asm_load: {}", e)),
+    };
+    let img: VveImage = match VveImage::from_bytes(&image, "<asm_load>", 0) {
+        Ok(v) => v,
+        Err(e) => return asm_fault(vm, NativeErrKind::InvalidInput, &format!("asm_load: {}", e)),
+    };
+    let missing: Vec<String> = match img.header.section(SECT_NCALLS) {
+        Some(sect) => read_ncalls(&sect.data)
+            .into_iter()
+            .filter(|c| !vm.nativesys.has_code(*c))
+            .map(|c| format!("{:#x}", c))
+            .collect(),
+        None => Vec::new(),
+    };
+    if !missing.is_empty() {
+        let msg: String = format!("asm_load: no ncalls {} in this VM", missing.join(", "));
+        return asm_fault(vm, NativeErrKind::NotFound, &msg);
+    }
+
+    let start: usize = vm.memory.len().next_multiple_of(MODULE_ALIGN);
+    let end: usize = start + img.body.len();
+    if !mem_reserve(vm, (end - vm.memory.len()) as u64) {
+        return;
+    }
+    if end > vm.memory.capacity() {
+        let msg: String = format!("asm_load: {} byte module doesn't fit into main memory", img.body.len());
+        native_fault(vm, NativeError::new(NativeSubsys::Asm, NativeErrKind::LimitReached), Exception::MainSegmFault, &msg);
+        return;
+    }
+    match place_module(vm, &img, start) {
+        Ok(module) => {
+            vm.asm_modules.push(module);
+            vm.registers[0] = Register::uint(vm.asm_modules.len() as u64);
+        }
+        Err(e) => asm_fault(vm, NativeErrKind::InvalidInput, &format!("asm_load: {}", e)),
+    }
+}
+
+/// ncall 0x91
+/// r1 is function name (r2 byte count if r1 is a heap address),
+/// r3 is module handle. r0 = function table index of the function, for `callr`
+pub fn ncall_asm_func(vm: &mut VM) {
+    let handle: u64 = vm.registers[3].as_u64();
+    let Some(name) = read_str(vm, NativeSubsys::Asm, "asm_func") else {
+        return;
+    };
+    let ind: Option<usize> = match (handle as usize).checked_sub(1).and_then(|i| vm.asm_modules.get(i)) {
+        Some(module) => module.funcs.get(&name).copied(),
+        None => return asm_fault(vm, NativeErrKind::BadHandle, &format!("asm_func: invalid module handle {:#x}", handle)),
+    };
+    match ind {
+        Some(ind) => {
+            vm.registers[0] = Register::uint(ind as u64);
+            vm.reg_types[0] = RegTypes::uint64;
+        }
+        None => asm_fault(vm, NativeErrKind::NotFound, &format!("asm_func: module {} has no function '{}'", handle, name)),
+    }
+}
@@ -0,0 +1,322 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "audio")]
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+#[cfg(feature = "audio")]
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+use crate::{
+    exceptions::Exception,
+    handles::HandleTable,
+    nativeerr::{handle_fault, native_fault, NativeErrKind, NativeError, NativeSubsys},
+    registers::Register,
+    vm::{RegTypes, VM},
+};
+
+// Audio output ncalls (0x70..0x77).
+// `audio_open` makes an output for a sample rate, channel count and sample
+// format, `audio_write` submits interleaved PCM frames from a heap buffer,
+// big endian like every other heap value. Where they go:
+//   --audio-wav=path - a 16-bit WAV file written on close, the n-th output
+//                      after the first one goes to path-n.wav
+//   the default output device - with the `audio` cargo feature (cpal),
+//                      frames are queued and played as the device pulls them
+// Without either, `audio_open` fails with the Unsupported error.
+
+/// Seconds of sound a device output buffers before `audio_write` takes less
+#[cfg(feature = "audio")]
+const MAX_QUEUED_SECS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleFmt {
+    S16 = 1, // signed 16-bit
+    F32 = 2, // 32-bit float, -1.0..1.0
+    U8 = 3,  // unsigned 8-bit, 128 is silence
+}
+
+impl SampleFmt {
+    fn from_code(code: u64) -> Option<SampleFmt> {
+        match code {
+            1 => Some(SampleFmt::S16),
+            2 => Some(SampleFmt::F32),
+            3 => Some(SampleFmt::U8),
+            _ => None,
+        }
+    }
+
+    fn width(self) -> u64 {
+        match self {
+            SampleFmt::S16 => 2,
+            SampleFmt::F32 => 4,
+            SampleFmt::U8 => 1,
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> f32 {
+        match self {
+            SampleFmt::S16 => i16::from_be_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            SampleFmt::F32 => f32::from_be_bytes(bytes.try_into().unwrap()).clamp(-1.0, 1.0),
+            SampleFmt::U8 => (bytes[0] as f32 - 128.0) / 128.0,
+        }
+    }
+}
+
+enum Sink {
+    Wav { path: PathBuf, samples: Vec<i16> },
+    #[cfg(feature = "audio")]
+    Device {
+        _stream: cpal::Stream, // plays while alive
+        queue: Arc<Mutex<VecDeque<f32>>>,
+    },
+}
+
+pub struct AudioOut {
+    rate: u32,
+    channels: u16,
+    fmt: SampleFmt,
+    sink: Sink,
+}
+
+impl AudioOut {
+    /// Frames submitted but not played yet
+    fn queued(&self) -> u64 {
+        match &self.sink {
+            Sink::Wav { .. } => 0,
+            #[cfg(feature = "audio")]
+            Sink::Device { queue, .. } => (queue.lock().unwrap().len() / self.channels as usize) as u64,
+        }
+    }
+
+    /// Queues samples, returns how many frames were taken
+    fn push(&mut self, samples: Vec<f32>) -> u64 {
+        let frames: u64 = (samples.len() / self.channels as usize) as u64;
+        match &mut self.sink {
+            Sink::Wav { samples: out, .. } => {
+                out.extend(samples.iter().map(|s| (s * 32768.0).round().clamp(-32768.0, 32767.0) as i16));
+                frames
+            }
+            #[cfg(feature = "audio")]
+            Sink::Device { queue, .. } => {
+                let mut queue = queue.lock().unwrap();
+                let room: usize = (self.rate as usize * self.channels as usize * MAX_QUEUED_SECS).saturating_sub(queue.len());
+                let take: usize = (samples.len().min(room) / self.channels as usize) * self.channels as usize;
+                queue.extend(&samples[..take]);
+                (take / self.channels as usize) as u64
+            }
+        }
+    }
+
+    /// Writes a WAV sink out, device sinks stop when dropped
+    fn finish(self) -> std::io::Result<()> {
+        match self.sink {
+            Sink::Wav { path, samples } => fs::write(path, wav_bytes(self.rate, self.channels, &samples)),
+            #[cfg(feature = "audio")]
+            Sink::Device { .. } => Ok(()),
+        }
+    }
+}
+
+/// 16-bit PCM WAV file, little endian as the format wants
+fn wav_bytes(rate: u32, channels: u16, samples: &[i16]) -> Vec<u8> {
+    let data_len: u32 = (samples.len() * 2) as u32;
+    let mut res: Vec<u8> = Vec::with_capacity(44 + data_len as usize);
+    res.extend(b"RIFF");
+    res.extend((36 + data_len).to_le_bytes());
+    res.extend(b"WAVEfmt ");
+    res.extend(16u32.to_le_bytes());
+    res.extend(1u16.to_le_bytes()); // PCM
+    res.extend(channels.to_le_bytes());
+    res.extend(rate.to_le_bytes());
+    res.extend((rate * channels as u32 * 2).to_le_bytes()); // byte rate
+    res.extend((channels * 2).to_le_bytes()); // block align
+    res.extend(16u16.to_le_bytes());
+    res.extend(b"data");
+    res.extend(data_len.to_le_bytes());
+    res.extend(samples.iter().flat_map(|s| s.to_le_bytes()));
+    res
+}
+
+#[derive(Default)]
+pub struct AudioController {
+    outputs: HandleTable<AudioOut>,
+    pub wav_path: Option<String>, // --audio-wav
+    opened: u64,                  // outputs opened so far, names WAV files
+}
+
+impl AudioController {
+    pub fn new() -> AudioController {
+        AudioController::default()
+    }
+
+    fn open_sink(&mut self, rate: u32, channels: u16) -> Result<Sink, (NativeErrKind, String)> {
+        if let Some(path) = &self.wav_path {
+            let path: PathBuf = match self.opened {
+                0 => PathBuf::from(path),
+                n => {
+                    let p: &Path = Path::new(path);
+                    let stem: String = p.file_stem().map_or(String::new(), |s| s.to_string_lossy().to_string());
+                    p.with_file_name(format!("{}-{}.wav", stem, n))
+                }
+            };
+            return Ok(Sink::Wav { path, samples: Vec::new() });
+        }
+        open_device(rate, channels)
+    }
+}
+
+#[cfg(feature = "audio")]
+fn open_device(rate: u32, channels: u16) -> Result<Sink, (NativeErrKind, String)> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or((NativeErrKind::NotFound, "No audio output device".to_string()))?;
+    let config = cpal::StreamConfig {
+        channels,
+        sample_rate: cpal::SampleRate(rate),
+        buffer_size: cpal::BufferSize::Default,
+    };
+    let queue: Arc<Mutex<VecDeque<f32>>> = Arc::new(Mutex::new(VecDeque::new()));
+    let feed: Arc<Mutex<VecDeque<f32>>> = queue.clone();
+    let stream = device
+        .build_output_stream(
+            &config,
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                let mut feed = feed.lock().unwrap();
+                for sample in data.iter_mut() {
+                    *sample = feed.pop_front().unwrap_or(0.0); // silence on underrun
+                }
+            },
+            |e| eprintln!("Audio output error: {}", e),
+            None,
+        )
+        .map_err(|e| (NativeErrKind::Unsupported, format!("Can't open audio output: {}", e)))?;
+    stream
+        .play()
+        .map_err(|e| (NativeErrKind::Other, format!("Can't start audio output: {}", e)))?;
+    Ok(Sink::Device { _stream: stream, queue })
+}
+
+#[cfg(not(feature = "audio"))]
+fn open_device(_rate: u32, _channels: u16) -> Result<Sink, (NativeErrKind, String)> {
+    Err((
+        NativeErrKind::Unsupported,
+        "No audio output: this voxvm is built without the `audio` feature, use --audio-wav=file".to_string(),
+    ))
+}
+
+impl std::fmt::Debug for AudioController {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AudioController {{ outputs: {} }}", self.outputs.count())
+    }
+}
+
+impl Drop for AudioController {
+    fn drop(&mut self) {
+        // outputs the guest didn't close still get their WAV files
+        for out in self.outputs.drain() {
+            let _ = out.finish();
+        }
+    }
+}
+
+fn audio_fault(vm: &mut VM, kind: NativeErrKind, exc: Exception, msg: &str) {
+    native_fault(vm, NativeError::new(NativeSubsys::Audio, kind), exc, msg);
+}
+
+/// ncall 0x70
+/// r1 is sample rate in Hz, r2 is channels (1 or 2),
+/// r3 is sample format: 1 - s16, 2 - f32, 3 - u8.
+/// r0 = audio output handle
+pub fn ncall_audio_open(vm: &mut VM) {
+    let rate: u64 = vm.registers[1].as_u64();
+    let channels: u64 = vm.registers[2].as_u64();
+    let fmt_code: u64 = vm.registers[3].as_u64();
+
+    let fmt: SampleFmt = match SampleFmt::from_code(fmt_code) {
+        Some(f) if (1000..=384000).contains(&rate) && (1..=2).contains(&channels) => f,
+        _ => {
+            let msg: String = format!("audio_open: bad output {} Hz, {} channels, format {}", rate, channels, fmt_code);
+            audio_fault(vm, NativeErrKind::InvalidInput, Exception::NativeFault, &msg);
+            return;
+        }
+    };
+    let sink: Sink = match vm.audio.open_sink(rate as u32, channels as u16) {
+        Ok(s) => s,
+        Err((kind, msg)) => {
+            audio_fault(vm, kind, Exception::NativeFault, &msg);
+            return;
+        }
+    };
+    vm.audio.opened += 1;
+    let handle: u64 = vm.audio.outputs.insert(AudioOut {
+        rate: rate as u32,
+        channels: channels as u16,
+        fmt,
+        sink,
+    });
+    vm.registers[0] = Register::uint(handle);
+    vm.reg_types[0] = RegTypes::uint64;
+}
+
+/// ncall 0x71
+/// r1 is audio output handle, r2 is heap ptr to interleaved frames,
+/// r3 is frames count. r0 = frames taken, fewer than r3 when a device
+/// output has seconds of sound queued already
+pub fn ncall_audio_write(vm: &mut VM) {
+    let handle: u64 = vm.registers[1].as_u64();
+    let ptr: u64 = vm.registers[2].as_u64();
+    let frames: u64 = vm.registers[3].as_u64();
+    let (fmt, channels) = match vm.audio.outputs.get(handle) {
+        Ok(out) => (out.fmt, out.channels as u64),
+        Err(e) => return handle_fault(vm, NativeSubsys::Audio, e, "audio output", handle),
+    };
+    let bytes: Vec<u8> = match frames
+        .checked_mul(channels * fmt.width())
+        .ok_or(())
+        .and_then(|len| vm.heap.read(ptr, len))
+    {
+        Ok(b) => b,
+        Err(()) => {
+            let msg: String = format!("audio_write: {} frames at {:#x} are out of heap", frames, ptr);
+            audio_fault(vm, NativeErrKind::HeapFault, Exception::HeapReadFault, &msg);
+            return;
+        }
+    };
+    let samples: Vec<f32> = bytes.chunks_exact(fmt.width() as usize).map(|s| fmt.decode(s)).collect();
+    let taken: u64 = vm.audio.outputs.get_mut(handle).unwrap().push(samples);
+    vm.registers[0] = Register::uint(taken);
+    vm.reg_types[0] = RegTypes::uint64;
+}
+
+/// ncall 0x72
+/// r1 is audio output handle. Closes it, a WAV output is written now
+pub fn ncall_audio_close(vm: &mut VM) {
+    let handle: u64 = vm.registers[1].as_u64();
+    let out: AudioOut = match vm.audio.outputs.remove(handle) {
+        Ok(o) => o,
+        Err(e) => return handle_fault(vm, NativeSubsys::Audio, e, "audio output", handle),
+    };
+    if let Err(e) = out.finish() {
+        let err: NativeError = NativeError::from_io(NativeSubsys::Audio, &e);
+        native_fault(vm, err, Exception::NativeFault, &format!("audio_close: {}", e));
+    }
+}
+
+/// ncall 0x73
+/// r1 is audio output handle. r0 = frames submitted but not played yet
+/// (always 0 for WAV outputs), for pacing generators
+pub fn ncall_audio_queued(vm: &mut VM) {
+    let handle: u64 = vm.registers[1].as_u64();
+    let queued: u64 = match vm.audio.outputs.get(handle) {
+        Ok(out) => out.queued(),
+        Err(e) => return handle_fault(vm, NativeSubsys::Audio, e, "audio output", handle),
+    };
+    vm.registers[0] = Register::uint(queued);
+    vm.reg_types[0] = RegTypes::uint64;
+}
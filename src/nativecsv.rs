@@ -0,0 +1,183 @@
+use crate::{
+    exceptions::Exception,
+    misclib::{args_to_u64, u8_slice_to_u16_vec, vec16_into_vec8},
+    nativeerr::{native_fault, NativeErrKind, NativeError, NativeSubsys},
+    nativestr::return_str,
+    registers::Register,
+    vm::{RegTypes, VM},
+};
+
+// CSV ncalls (RFC 4180) over UTF-16 heap text. A field list is a heap array
+// of (offset u64, len u64) big-endian pairs in bytes, like an io vector of
+// nativeiov.rs but relative to a base address: `csv_split` gives offsets
+// into the line, `csv_join` adds its base to them (base 0 takes plain
+// (ptr, len) io vectors). Fields are quoted when they hold the delimiter,
+// a quote or a line break, quotes inside are doubled.
+
+const QUOTE: u16 = b'"' as u16;
+const CR: u16 = b'\r' as u16;
+const LF: u16 = b'\n' as u16;
+
+fn csv_fault(vm: &mut VM, kind: NativeErrKind, exc: Exception, msg: &str) {
+    native_fault(vm, NativeError::new(NativeSubsys::Text, kind), exc, msg);
+}
+
+/// The delimiter in `reg`, 0 is a comma. None after a fault for quotes,
+/// line breaks and values that aren't one UTF-16 unit
+fn delimiter(vm: &mut VM, reg: usize, what: &str) -> Option<u16> {
+    let delim: u64 = match vm.registers[reg].as_u64() {
+        0 => b',' as u64,
+        d => d,
+    };
+    match u16::try_from(delim) {
+        Ok(d) if ![QUOTE, CR, LF].contains(&d) => Some(d),
+        _ => {
+            let msg: String = format!("{}: {:#x} can't be a delimiter", what, delim);
+            csv_fault(vm, NativeErrKind::InvalidInput, Exception::InvalidDataType, &msg);
+            None
+        }
+    }
+}
+
+/// Fields of `line` as (start, len) in UTF-16 units. Quoted fields are
+/// unescaped in place, their doubled quotes collapsed
+fn split_fields(line: &mut [u16], delim: u16) -> Result<Vec<(usize, usize)>, String> {
+    let mut end: usize = line.len();
+    if line[..end].last() == Some(&LF) {
+        end -= 1;
+    }
+    if line[..end].last() == Some(&CR) {
+        end -= 1;
+    }
+    let mut fields: Vec<(usize, usize)> = Vec::new();
+    let mut at: usize = 0;
+    loop {
+        if line.get(at) == Some(&QUOTE) && at < end {
+            let (start, mut read, mut written) = (at + 1, at + 1, at + 1);
+            loop {
+                match line[..end].get(read) {
+                    None => return Err(format!("quoted field at unit {} isn't closed", at)),
+                    Some(&QUOTE) if line[..end].get(read + 1) == Some(&QUOTE) => {
+                        line[written] = QUOTE;
+                        read += 2;
+                    }
+                    Some(&QUOTE) => break,
+                    Some(&c) => {
+                        line[written] = c;
+                        read += 1;
+                    }
+                }
+                written += 1;
+            }
+            fields.push((start, written - start));
+            at = read + 1;
+            match line[..end].get(at) {
+                None => break,
+                Some(&c) if c == delim => at += 1,
+                Some(_) => return Err(format!("text after the closing quote at unit {}", read)),
+            }
+        } else {
+            let len: usize = line[at..end].iter().position(|c| *c == delim).unwrap_or(end - at);
+            fields.push((at, len));
+            at += len;
+            if at == end {
+                break;
+            }
+            at += 1;
+        }
+    }
+    Ok(fields)
+}
+
+/// `field` quoted if it needs to be
+fn escape_field(field: &[u16], delim: u16, out: &mut Vec<u16>) {
+    if !field.iter().any(|c| [delim, QUOTE, CR, LF].contains(c)) {
+        out.extend_from_slice(field);
+        return;
+    }
+    out.push(QUOTE);
+    for c in field {
+        if *c == QUOTE {
+            out.push(QUOTE);
+        }
+        out.push(*c);
+    }
+    out.push(QUOTE);
+}
+
+/// ncall 0x84
+/// r1 is heap ptr to a CSV line (utf16), r2 its size in bytes, r3 heap ptr
+/// to the field list, r4 how many pairs it has room for, r5 the delimiter
+/// character (0 is `,`). A trailing line break is ignored. Fills the list
+/// with the fields' byte offsets into the line and their sizes, without the
+/// quotes; quoted fields with doubled quotes are unescaped in place, so the
+/// line changes. r0 = count of fields, past r4 the rest aren't written.
+/// An unclosed quote or text after a closing one fault with InvalidInput
+pub fn ncall_csv_split(vm: &mut VM) {
+    let line_ptr: u64 = vm.registers[1].as_u64();
+    let line_len: u64 = vm.registers[2].as_u64();
+    let list_ptr: u64 = vm.registers[3].as_u64();
+    let capacity: u64 = vm.registers[4].as_u64();
+    let Some(delim) = delimiter(vm, 5, "csv_split") else {
+        return;
+    };
+    let Ok(bytes) = vm.heap.read(line_ptr, line_len) else {
+        let msg: String = format!("csv_split: no {} heap bytes at {:#x}", line_len, line_ptr);
+        return csv_fault(vm, NativeErrKind::HeapFault, Exception::HeapReadFault, &msg);
+    };
+    let mut line: Vec<u16> = u8_slice_to_u16_vec(&bytes);
+    let fields: Vec<(usize, usize)> = match split_fields(&mut line, delim) {
+        Ok(v) => v,
+        Err(e) => return csv_fault(vm, NativeErrKind::InvalidInput, Exception::InvalidDataType, &format!("csv_split: {}", e)),
+    };
+    let list: Vec<u8> = fields
+        .iter()
+        .take(capacity.min(usize::MAX as u64) as usize)
+        .flat_map(|(start, len)| [(*start as u64 * 2).to_be_bytes(), (*len as u64 * 2).to_be_bytes()])
+        .flatten()
+        .collect();
+    let unescaped: Vec<u8> = vec16_into_vec8(line);
+    let written: bool = ((unescaped == bytes) || vm.heap.write(line_ptr, unescaped).is_ok())
+        && (list.is_empty() || vm.heap.write(list_ptr, list).is_ok());
+    if !written {
+        let msg: String = format!("csv_split: can't write the fields at {:#x}", list_ptr);
+        return csv_fault(vm, NativeErrKind::HeapFault, Exception::HeapWriteFault, &msg);
+    }
+    vm.registers[0] = Register::uint(fields.len() as u64);
+    vm.reg_types[0] = RegTypes::uint64;
+}
+
+/// ncall 0x85
+/// r1 is the base address, r2 heap ptr to the field list, r3 count of its
+/// pairs, r4 the delimiter character (0 is `,`). Joins the utf16 fields at
+/// base + offset into one line without a line break, quoting the ones that
+/// need it. Returns a new GC managed heap string, r0 = address, r1 = size
+/// in bytes, like `str_slice`
+pub fn ncall_csv_join(vm: &mut VM) {
+    let base: u64 = vm.registers[1].as_u64();
+    let list_ptr: u64 = vm.registers[2].as_u64();
+    let count: u64 = vm.registers[3].as_u64();
+    let Some(delim) = delimiter(vm, 4, "csv_join") else {
+        return;
+    };
+    let Ok(list) = vm.heap.read(list_ptr, count.saturating_mul(16)) else {
+        let msg: String = format!("csv_join: no list of {} fields at {:#x}", count, list_ptr);
+        return csv_fault(vm, NativeErrKind::HeapFault, Exception::HeapReadFault, &msg);
+    };
+    let mut line: Vec<u16> = Vec::new();
+    for (ind, pair) in list.chunks_exact(16).enumerate() {
+        let (ptr, len) = (base.wrapping_add(args_to_u64(&pair[0..8])), args_to_u64(&pair[8..16]));
+        let Ok(field) = vm.heap.read(ptr, len) else {
+            let msg: String = format!("csv_join: field {} [{:#x}; {}] is out of heap", ind, ptr, len);
+            return csv_fault(vm, NativeErrKind::HeapFault, Exception::HeapReadFault, &msg);
+        };
+        if ind > 0 {
+            line.push(delim);
+        }
+        escape_field(&u8_slice_to_u16_vec(&field), delim, &mut line);
+    }
+    match String::from_utf16(&line) {
+        Ok(s) => return_str(vm, &s),
+        Err(_) => csv_fault(vm, NativeErrKind::InvalidInput, Exception::InvalidDataType, "csv_join: fields aren't valid UTF-16"),
+    }
+}
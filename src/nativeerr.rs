@@ -0,0 +1,161 @@
+use std::io;
+
+use crate::{exceptions::Exception, handles::HandleErr, misclib::show_runtime_err, native::NSysError, nativenet::NCError, registers::Register, vm::{RegTypes, VM}};
+
+// Typed ncall errors.
+// A failed ncall stores its error into the VM last-error slot,
+// guest code reads the code with `ncall 0xC` (lasterr).
+// Code layout: subsystem << 8 | kind, subsystems match ncall ranges.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NativeSubsys {
+    Std = 0x00,
+    Files = 0x10,
+    Net = 0x20,
+    Debug = 0x40,
+    Term = 0x50,
+    Input = 0x58,
+    Gfx = 0x60,
+    Audio = 0x70,
+    Text = 0x80,
+    Asm = 0x90,
+    Shm = 0xA0,
+    Limits = 0xB0,
+    Mmap = 0xC0,
+    Native = 0xD0,
+    Path = 0xE0,
+    Arena = 0xF0,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NativeErrKind {
+    NotFound = 0x1,
+    PermissionDenied = 0x2,
+    AlreadyExists = 0x3,
+    InvalidInput = 0x4,
+    BadHandle = 0x5, // no such file/connection handle
+    WrongMode = 0x6, // e.g. writing into readonly file
+    UnexpectedEof = 0x7,
+    ConnRefused = 0x8,
+    ConnReset = 0x9,
+    ConnAborted = 0xA,
+    NotConnected = 0xB,
+    AddrInUse = 0xC,
+    AddrNotAvailable = 0xD,
+    TimedOut = 0xE,
+    WouldBlock = 0xF,
+    Interrupted = 0x10,
+    BrokenPipe = 0x11,
+    HeapFault = 0x12, // can't read/write ncall args in heap
+    Unsupported = 0x13,
+    LimitReached = 0x14, // --max-open-files / --max-connections
+    StaleHandle = 0x15, // file/connection handle was closed
+    Other = 0xFF,
+}
+
+impl NativeErrKind {
+    pub fn from_io(kind: io::ErrorKind) -> NativeErrKind {
+        match kind {
+            io::ErrorKind::NotFound => NativeErrKind::NotFound,
+            io::ErrorKind::PermissionDenied => NativeErrKind::PermissionDenied,
+            io::ErrorKind::AlreadyExists => NativeErrKind::AlreadyExists,
+            io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => NativeErrKind::InvalidInput,
+            io::ErrorKind::UnexpectedEof => NativeErrKind::UnexpectedEof,
+            io::ErrorKind::ConnectionRefused => NativeErrKind::ConnRefused,
+            io::ErrorKind::ConnectionReset => NativeErrKind::ConnReset,
+            io::ErrorKind::ConnectionAborted => NativeErrKind::ConnAborted,
+            io::ErrorKind::NotConnected => NativeErrKind::NotConnected,
+            io::ErrorKind::AddrInUse => NativeErrKind::AddrInUse,
+            io::ErrorKind::AddrNotAvailable => NativeErrKind::AddrNotAvailable,
+            io::ErrorKind::TimedOut => NativeErrKind::TimedOut,
+            io::ErrorKind::WouldBlock => NativeErrKind::WouldBlock,
+            io::ErrorKind::Interrupted => NativeErrKind::Interrupted,
+            io::ErrorKind::BrokenPipe => NativeErrKind::BrokenPipe,
+            io::ErrorKind::Unsupported => NativeErrKind::Unsupported,
+            _ => NativeErrKind::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NativeError {
+    pub subsys: NativeSubsys,
+    pub kind: NativeErrKind,
+}
+
+impl NativeError {
+    pub fn new(subsys: NativeSubsys, kind: NativeErrKind) -> NativeError {
+        NativeError {
+            subsys: subsys,
+            kind: kind,
+        }
+    }
+
+    pub fn from_io(subsys: NativeSubsys, err: &io::Error) -> NativeError {
+        NativeError::new(subsys, NativeErrKind::from_io(err.kind()))
+    }
+
+    pub fn code(&self) -> u64 {
+        ((self.subsys as u64) << 8) | (self.kind as u64)
+    }
+}
+
+impl From<&NSysError> for NativeErrKind {
+    fn from(err: &NSysError) -> NativeErrKind {
+        match err {
+            NSysError::fs(e) => NativeErrKind::from_io(e.kind()),
+            NSysError::InvalidArgs() | NSysError::InvalidCallCode(_) => NativeErrKind::InvalidInput,
+            NSysError::NoLibrary() => NativeErrKind::NotFound,
+            NSysError::UnknownOS() => NativeErrKind::Unsupported,
+            NSysError::CodeCollision(_) => NativeErrKind::AlreadyExists,
+            _ => NativeErrKind::Other,
+        }
+    }
+}
+
+impl From<&NCError> for NativeErrKind {
+    fn from(err: &NCError) -> NativeErrKind {
+        match err {
+            NCError::Native(e) => NativeErrKind::from_io(e.kind()),
+            NCError::InvalidType() | NCError::Parse() => NativeErrKind::InvalidInput,
+        }
+    }
+}
+
+impl From<HandleErr> for NativeErrKind {
+    fn from(err: HandleErr) -> NativeErrKind {
+        match err {
+            HandleErr::Invalid => NativeErrKind::BadHandle,
+            HandleErr::Stale => NativeErrKind::StaleHandle,
+        }
+    }
+}
+
+/// Reports a failed ncall: fills the last-error slot,
+/// prints `msg` and raises `exc`
+pub fn native_fault(vm: &mut VM, err: NativeError, exc: Exception, msg: &str) {
+    vm.last_native_err = Some(err);
+    show_runtime_err(vm, msg);
+    vm.exceptions_active.push(exc);
+}
+
+/// Reports use of a bad `what` ("file", "net conn") handle
+pub fn handle_fault(vm: &mut VM, subsys: NativeSubsys, err: HandleErr, what: &str, handle: u64) {
+    let msg: String = match err {
+        HandleErr::Invalid => format!("Invalid {} handle {:#x}", what, handle),
+        HandleErr::Stale => format!("Stale {} handle {:#x}, it was closed", what, handle),
+    };
+    native_fault(vm, NativeError::new(subsys, NativeErrKind::from(err)), Exception::NativeFault, &msg);
+}
+
+/// ncall 0xC
+/// returns the code of the last failed ncall into r0
+/// (0 if there was none) and clears it
+pub fn ncall_lasterr(vm: &mut VM) {
+    let code: u64 = match vm.last_native_err.take() {
+        Some(e) => e.code(),
+        None => 0,
+    };
+    vm.registers[0] = Register::uint(code);
+    vm.reg_types[0] = RegTypes::uint64;
+}
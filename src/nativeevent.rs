@@ -0,0 +1,290 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    exceptions::Exception,
+    nativeerr::{native_fault, NativeError, NativeSubsys},
+    nativeterm::read_key,
+    registers::Register,
+    vm::{RegTypes, VM},
+};
+
+// Input event queue. Producers (the terminal pump below, windowing plugins,
+// embedder threads through a cloned EventQueue) push events, guest code pops
+// them one by one with `ncall @ev_pop`, so reading input doesn't depend on
+// where it comes from. The queue is bounded: when it's full the overflow
+// policy drops the oldest or the incoming event and counts it, the count is
+// read (and reset) with `ncall @ev_dropped`.
+// An event is 4 values, ev_pop returns them in r0..r3:
+//   kind - EV_KEY, kinds from 0x100 up are free for plugins
+//   code - key code: the character for character keys, control keys are
+//          their ASCII code (tab 9, enter 13, escape 27, backspace 8),
+//          other keys are KEY_* from 0x1000
+//   ch   - unicode character the key types, 0 if it types none
+//   mods - MOD_* bits
+
+pub const EV_KEY: u64 = 1;
+
+pub const MOD_SHIFT: u64 = 1;
+pub const MOD_CTRL: u64 = 2;
+pub const MOD_ALT: u64 = 4;
+
+pub const KEY_UP: u64 = 0x1001;
+pub const KEY_DOWN: u64 = 0x1002;
+pub const KEY_RIGHT: u64 = 0x1003;
+pub const KEY_LEFT: u64 = 0x1004;
+pub const KEY_HOME: u64 = 0x1005;
+pub const KEY_END: u64 = 0x1006;
+pub const KEY_PGUP: u64 = 0x1007;
+pub const KEY_PGDN: u64 = 0x1008;
+pub const KEY_INSERT: u64 = 0x1009;
+pub const KEY_DELETE: u64 = 0x100A;
+
+pub const DEFAULT_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputEvent {
+    pub kind: u64,
+    pub code: u64,
+    pub ch: u64,
+    pub mods: u64,
+}
+
+impl InputEvent {
+    pub fn key(code: u64, ch: u64, mods: u64) -> InputEvent {
+        InputEvent {
+            kind: EV_KEY,
+            code: code,
+            ch: ch,
+            mods: mods,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    DropOldest,
+    DropNewest,
+}
+
+impl Overflow {
+    pub fn from_name(name: &str) -> Option<Overflow> {
+        match name {
+            "drop-oldest" => Some(Overflow::DropOldest),
+            "drop-newest" => Some(Overflow::DropNewest),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct QueueState {
+    events: VecDeque<InputEvent>,
+    capacity: usize,
+    overflow: Overflow,
+    dropped: u64,
+}
+
+/// Bounded event queue, clones feed the same VM
+#[derive(Debug, Clone)]
+pub struct EventQueue {
+    state: Arc<Mutex<QueueState>>,
+}
+
+impl EventQueue {
+    pub fn new(capacity: usize, overflow: Overflow) -> EventQueue {
+        EventQueue {
+            state: Arc::new(Mutex::new(QueueState {
+                events: VecDeque::new(),
+                capacity: capacity.max(1),
+                overflow: overflow,
+                dropped: 0,
+            })),
+        }
+    }
+
+    /// Queues an event, false if the overflow policy dropped it
+    pub fn push(&self, ev: InputEvent) -> bool {
+        let mut st = self.state.lock().unwrap();
+        if st.events.len() >= st.capacity {
+            st.dropped += 1;
+            match st.overflow {
+                Overflow::DropNewest => return false,
+                Overflow::DropOldest => {
+                    st.events.pop_front();
+                }
+            }
+        }
+        st.events.push_back(ev);
+        true
+    }
+
+    pub fn pop(&self) -> Option<InputEvent> {
+        self.state.lock().unwrap().events.pop_front()
+    }
+
+    /// Count of queued events
+    pub fn count(&self) -> usize {
+        self.state.lock().unwrap().events.len()
+    }
+
+    /// Events lost to overflow since the last call
+    pub fn take_dropped(&self) -> u64 {
+        std::mem::take(&mut self.state.lock().unwrap().dropped)
+    }
+}
+
+impl Default for EventQueue {
+    fn default() -> EventQueue {
+        EventQueue::new(DEFAULT_CAPACITY, Overflow::DropOldest)
+    }
+}
+
+/// Key events of raw terminal input. A lone escape at the end of `bytes`
+/// is the escape key, escape + a character is alt + that character
+pub fn decode_term_keys(bytes: &[u8]) -> Vec<InputEvent> {
+    let mut res: Vec<InputEvent> = Vec::new();
+    let mut i: usize = 0;
+    while i < bytes.len() {
+        let (ev, used) = decode_one(&bytes[i..]);
+        if let Some(ev) = ev {
+            res.push(ev);
+        }
+        i += used;
+    }
+    res
+}
+
+fn decode_one(bytes: &[u8]) -> (Option<InputEvent>, usize) {
+    match bytes {
+        [0x1b, b'[', rest @ ..] | [0x1b, b'O', rest @ ..] => {
+            // CSI: parameter digits and ';', then the final byte
+            let len: usize = rest.iter().take_while(|b| b.is_ascii_digit() || (**b == b';')).count();
+            let Some(fin) = rest.get(len) else {
+                return (None, bytes.len());
+            };
+            let params: Vec<u64> = std::str::from_utf8(&rest[..len])
+                .unwrap()
+                .split(';')
+                .map(|p| p.parse::<u64>().unwrap_or(1))
+                .collect();
+            // xterm modifier parameter is 1 + shift | alt << 1 | ctrl << 2
+            let mods: u64 = match params.get(1) {
+                Some(m) if *m > 1 => {
+                    let m: u64 = m - 1;
+                    ((m & 1) * MOD_SHIFT) | (((m >> 1) & 1) * MOD_ALT) | (((m >> 2) & 1) * MOD_CTRL)
+                }
+                _ => 0,
+            };
+            let code: Option<u64> = match (fin, params[0]) {
+                (b'A', _) => Some(KEY_UP),
+                (b'B', _) => Some(KEY_DOWN),
+                (b'C', _) => Some(KEY_RIGHT),
+                (b'D', _) => Some(KEY_LEFT),
+                (b'H', _) | (b'~', 1) | (b'~', 7) => Some(KEY_HOME),
+                (b'F', _) | (b'~', 4) | (b'~', 8) => Some(KEY_END),
+                (b'~', 2) => Some(KEY_INSERT),
+                (b'~', 3) => Some(KEY_DELETE),
+                (b'~', 5) => Some(KEY_PGUP),
+                (b'~', 6) => Some(KEY_PGDN),
+                _ => None, // unknown sequences are skipped whole
+            };
+            (code.map(|c| InputEvent::key(c, 0, mods)), 2 + len + 1)
+        }
+        [0x1b] => (Some(InputEvent::key(27, 0, 0)), 1),
+        [0x1b, ..] => {
+            let (ev, used) = decode_one(&bytes[1..]);
+            (ev.map(|e| InputEvent { mods: e.mods | MOD_ALT, ..e }), 1 + used)
+        }
+        [b @ (9 | 13), ..] => (Some(InputEvent::key(*b as u64, 0, 0)), 1),
+        [b'\n', ..] => (Some(InputEvent::key(13, 0, 0)), 1),
+        [0x7f | 0x08, ..] => (Some(InputEvent::key(8, 0, 0)), 1),
+        [0, ..] => (Some(InputEvent::key(b' ' as u64, 0, MOD_CTRL)), 1),
+        [b @ 1..=26, ..] => (Some(InputEvent::key((b'a' + b - 1) as u64, 0, MOD_CTRL)), 1),
+        [b, ..] if *b < 0x20 => (None, 1),
+        [b, ..] => {
+            let len: usize = match b {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => 1,
+            };
+            let ch: Option<char> = bytes
+                .get(..len)
+                .and_then(|s| std::str::from_utf8(s).ok())
+                .and_then(|s| s.chars().next());
+            match ch {
+                Some(c) => {
+                    let mods: u64 = if c.is_uppercase() { MOD_SHIFT } else { 0 };
+                    (Some(InputEvent::key(c as u64, c as u64, mods)), len)
+                }
+                None => (None, 1),
+            }
+        }
+        [] => (None, 0),
+    }
+}
+
+fn set_uint(vm: &mut VM, reg: usize, val: u64) {
+    vm.registers[reg] = Register::uint(val);
+    vm.reg_types[reg] = RegTypes::uint64;
+}
+
+/// ncall 0x58
+/// r0 = count of queued events
+pub fn ncall_ev_count(vm: &mut VM) {
+    let count: u64 = vm.events.count() as u64;
+    set_uint(vm, 0, count);
+}
+
+/// ncall 0x59
+/// Takes the oldest event: r0 = kind, r1 = code, r2 = ch, r3 = mods.
+/// r0 = 0 and the rest 0 when the queue is empty
+pub fn ncall_ev_pop(vm: &mut VM) {
+    let ev: InputEvent = vm.events.pop().unwrap_or(InputEvent {
+        kind: 0,
+        code: 0,
+        ch: 0,
+        mods: 0,
+    });
+    set_uint(vm, 0, ev.kind);
+    set_uint(vm, 1, ev.code);
+    set_uint(vm, 2, ev.ch);
+    set_uint(vm, 3, ev.mods);
+}
+
+/// ncall 0x5A
+/// r0 = events dropped because the queue was full, since the last call
+pub fn ncall_ev_dropped(vm: &mut VM) {
+    let dropped: u64 = vm.events.take_dropped();
+    set_uint(vm, 0, dropped);
+}
+
+/// ncall 0x5B
+/// Feeds terminal input into the queue: r1 is how long to wait for the
+/// first byte in ms (0 - don't wait), then everything already on stdin is
+/// decoded into key events. r0 = events decoded. Use with `term_raw`
+pub fn ncall_ev_term_pump(vm: &mut VM) {
+    let wait_ms: u64 = vm.registers[1].as_u64();
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut wait: u64 = wait_ms;
+    loop {
+        match read_key(wait) {
+            Ok(Some(b)) => bytes.push(b),
+            Ok(None) => break,
+            Err(e) => {
+                let err: NativeError = NativeError::from_io(NativeSubsys::Input, &e);
+                native_fault(vm, err, Exception::NativeFault, &format!("ev_term_pump: {}", e));
+                return;
+            }
+        }
+        wait = 0;
+    }
+    let events: Vec<InputEvent> = decode_term_keys(&bytes);
+    for ev in &events {
+        vm.events.push(*ev);
+    }
+    set_uint(vm, 0, events.len() as u64);
+}
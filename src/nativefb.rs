@@ -0,0 +1,212 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    exceptions::Exception,
+    heap::gc_alloc,
+    native::HostCall,
+    nativeerr::{native_fault, NativeErrKind, NativeError, NativeSubsys},
+    registers::Register,
+    vm::{RegTypes, VM},
+};
+
+// Software framebuffers.
+// A framebuffer is a GC managed heap block: width u64, height u64 (big
+// endian), then width * height RGBA8 pixels row by row, so guest code can
+// also draw with plain `store`. Ncalls 0x60..0x6F are the graphics range:
+//   0x60..0x67 - built-in software calls below
+//   0x68..0x6F - left to windowing plugins, registered by embedders as host
+//                calls (VM::register_ncall). FB_PRESENT (0x68) shows a
+//                framebuffer: r1 is its pointer, r0 = 1 once the window is
+//                closed, 0 otherwise. fb_view gives plugins the pixels,
+//                `--fb-dump=dir` registers a presenter writing PPM files.
+// Rectangles are clipped to the framebuffers, a fill or blit outside of
+// them draws nothing.
+
+pub const FB_HEADER: u64 = 16;
+pub const FB_PRESENT: u16 = 0x68;
+/// Width and height limit, keeps pixel sizes far from u64 overflow
+pub const FB_MAX_SIDE: u64 = 1 << 16;
+
+/// (width, height, RGBA8 pixels) of the framebuffer at `ptr`
+pub fn fb_view(vm: &VM, ptr: u64) -> Result<(u64, u64, &[u8]), ()> {
+    let header: &[u8] = vm.heap.slice(ptr, FB_HEADER)?;
+    let width: u64 = u64::from_be_bytes(header[0..8].try_into().unwrap());
+    let height: u64 = u64::from_be_bytes(header[8..16].try_into().unwrap());
+    if (width > FB_MAX_SIDE) || (height > FB_MAX_SIDE) {
+        return Err(());
+    }
+    let pixels: &[u8] = vm.heap.slice(ptr + FB_HEADER, width * height * 4)?;
+    Ok((width, height, pixels))
+}
+
+fn fb_dims(vm: &mut VM, ptr: u64, what: &str) -> Option<(u64, u64)> {
+    match fb_view(vm, ptr) {
+        Ok((w, h, _)) => Some((w, h)),
+        Err(()) => {
+            let msg: String = format!("{}: no framebuffer at {:#x}", what, ptr);
+            native_fault(vm, NativeError::new(NativeSubsys::Gfx, NativeErrKind::HeapFault), Exception::HeapReadFault, &msg);
+            None
+        }
+    }
+}
+
+fn check_size(vm: &mut VM, width: u64, height: u64, what: &str) -> bool {
+    if (width == 0) || (height == 0) || (width > FB_MAX_SIDE) || (height > FB_MAX_SIDE) {
+        let msg: String = format!("{}: framebuffer size {}x{} isn't 1..{} per side", what, width, height, FB_MAX_SIDE);
+        native_fault(vm, NativeError::new(NativeSubsys::Gfx, NativeErrKind::InvalidInput), Exception::InvalidDataType, &msg);
+        return false;
+    }
+    true
+}
+
+/// Allocates a black, transparent framebuffer
+fn fb_alloc(vm: &mut VM, width: u64, height: u64) -> Option<u64> {
+    let ptr: u64 = gc_alloc(vm, FB_HEADER + width * height * 4)?;
+    let mut init: Vec<u8> = vec![0; (FB_HEADER + width * height * 4) as usize];
+    init[0..8].copy_from_slice(&width.to_be_bytes());
+    init[8..16].copy_from_slice(&height.to_be_bytes());
+    vm.heap.write(ptr, init).ok()?;
+    Some(ptr)
+}
+
+fn row_offset(width: u64, x: u64, y: u64) -> u64 {
+    FB_HEADER + (y * width + x) * 4
+}
+
+fn set_result(vm: &mut VM, val: Register, t: RegTypes) {
+    vm.registers[0] = val;
+    vm.reg_types[0] = t;
+}
+
+/// ncall 0x60
+/// r1 is width, r2 is height in pixels.
+/// r0 = pointer to a new framebuffer, all pixels 0
+pub fn ncall_fb_create(vm: &mut VM) {
+    let width: u64 = vm.registers[1].as_u64();
+    let height: u64 = vm.registers[2].as_u64();
+    if !check_size(vm, width, height, "fb_create") {
+        return;
+    }
+    let ptr: u64 = fb_alloc(vm, width, height).unwrap_or(0);
+    set_result(vm, Register::address(ptr), RegTypes::address);
+}
+
+/// ncall 0x61
+/// r1 is framebuffer, r2 is new width, r3 is new height.
+/// r0 = pointer to the resized framebuffer, pixels keep their place
+/// (cut or padded with 0 at right and bottom). The old one is freed
+pub fn ncall_fb_resize(vm: &mut VM) {
+    let old: u64 = vm.registers[1].as_u64();
+    let width: u64 = vm.registers[2].as_u64();
+    let height: u64 = vm.registers[3].as_u64();
+    let Some((old_w, old_h)) = fb_dims(vm, old, "fb_resize") else {
+        return;
+    };
+    if !check_size(vm, width, height, "fb_resize") {
+        return;
+    }
+    let Some(ptr) = fb_alloc(vm, width, height) else {
+        set_result(vm, Register::address(0), RegTypes::address);
+        return;
+    };
+    let row_bytes: u64 = old_w.min(width) * 4;
+    for y in 0..old_h.min(height) {
+        let row: Vec<u8> = vm.heap.read(old + row_offset(old_w, 0, y), row_bytes).unwrap();
+        vm.heap.write(ptr + row_offset(width, 0, y), row).unwrap();
+    }
+    if vm.heap.free(old).is_ok() {
+        vm.gc.forget(old);
+    }
+    set_result(vm, Register::address(ptr), RegTypes::address);
+}
+
+/// ncall 0x62
+/// r1 is framebuffer, r2 x, r3 y, r4 width, r5 height of a rectangle,
+/// r6 is 0xRRGGBBAA color. Fills the rectangle, r0 = pixels filled
+pub fn ncall_fb_fill(vm: &mut VM) {
+    let fb: u64 = vm.registers[1].as_u64();
+    let (x, y) = (vm.registers[2].as_u64(), vm.registers[3].as_u64());
+    let (rw, rh) = (vm.registers[4].as_u64(), vm.registers[5].as_u64());
+    let color: [u8; 4] = (vm.registers[6].as_u64() as u32).to_be_bytes();
+    let Some((width, height)) = fb_dims(vm, fb, "fb_fill") else {
+        return;
+    };
+    let w: u64 = rw.min(width.saturating_sub(x));
+    let h: u64 = if w == 0 { 0 } else { rh.min(height.saturating_sub(y)) };
+    let row: Vec<u8> = color.repeat(w as usize);
+    for dy in 0..h {
+        vm.heap.write(fb + row_offset(width, x, y + dy), row.clone()).unwrap();
+    }
+    set_result(vm, Register::uint(w * h), RegTypes::uint64);
+}
+
+/// ncall 0x63
+/// r1 is destination, r2 is source framebuffer (can be the same one),
+/// r3 x, r4 y, r5 width, r6 height of the source rectangle,
+/// r7 x, r8 y of where it goes. Copies the pixels, r0 = pixels copied
+pub fn ncall_fb_blit(vm: &mut VM) {
+    let dst: u64 = vm.registers[1].as_u64();
+    let src: u64 = vm.registers[2].as_u64();
+    let (sx, sy) = (vm.registers[3].as_u64(), vm.registers[4].as_u64());
+    let (rw, rh) = (vm.registers[5].as_u64(), vm.registers[6].as_u64());
+    let (dx, dy) = (vm.registers[7].as_u64(), vm.registers[8].as_u64());
+    let Some((dst_w, dst_h)) = fb_dims(vm, dst, "fb_blit") else {
+        return;
+    };
+    let Some((src_w, src_h)) = fb_dims(vm, src, "fb_blit") else {
+        return;
+    };
+    let w: u64 = rw.min(src_w.saturating_sub(sx)).min(dst_w.saturating_sub(dx));
+    let h: u64 = if w == 0 { 0 } else { rh.min(src_h.saturating_sub(sy)).min(dst_h.saturating_sub(dy)) };
+    // whole rectangle first, overlapping blits within one framebuffer stay intact
+    let rows: Vec<Vec<u8>> = (0..h)
+        .map(|i| vm.heap.read(src + row_offset(src_w, sx, sy + i), w * 4).unwrap())
+        .collect();
+    for (i, row) in rows.into_iter().enumerate() {
+        vm.heap.write(dst + row_offset(dst_w, dx, dy + i as u64), row).unwrap();
+    }
+    set_result(vm, Register::uint(w * h), RegTypes::uint64);
+}
+
+/// ncall 0x64
+/// r1 is framebuffer. r0 = width, r1 = height
+pub fn ncall_fb_info(vm: &mut VM) {
+    let fb: u64 = vm.registers[1].as_u64();
+    let Some((width, height)) = fb_dims(vm, fb, "fb_info") else {
+        return;
+    };
+    set_result(vm, Register::uint(width), RegTypes::uint64);
+    vm.registers[1] = Register::uint(height);
+    vm.reg_types[1] = RegTypes::uint64;
+}
+
+/// FB_PRESENT host call of `--fb-dump=dir`: every presented frame becomes
+/// dir/frame_NNNN.ppm (alpha dropped), the "window" never closes
+pub fn ppm_presenter(dir: String) -> HostCall {
+    let mut frame: u64 = 0;
+    Box::new(move |vm: &mut VM| {
+        let fb: u64 = vm.registers[1].as_u64();
+        let ppm: Vec<u8> = match fb_view(vm, fb) {
+            Ok((width, height, pixels)) => {
+                let mut ppm: Vec<u8> = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+                ppm.extend(pixels.chunks_exact(4).flat_map(|px| px[0..3].to_vec()));
+                ppm
+            }
+            Err(()) => {
+                fb_dims(vm, fb, "fb_present");
+                return;
+            }
+        };
+        let path: PathBuf = Path::new(&dir).join(format!("frame_{:04}.ppm", frame));
+        if let Err(e) = fs::write(&path, ppm) {
+            let err: NativeError = NativeError::from_io(NativeSubsys::Gfx, &e);
+            native_fault(vm, err, Exception::NativeFault, &format!("fb_present: {}: {}", path.display(), e));
+            return;
+        }
+        frame += 1;
+        set_result(vm, Register::uint(0), RegTypes::uint64);
+    })
+}
@@ -1,6 +1,6 @@
 use std::{collections::HashMap, fs::{File, OpenOptions}, io::{self, Read, Seek, Write}};
 
-use crate::{misclib::{bytes_into_string_utf16, show_runtime_err, u8_slice_to_u16_vec}, native::NSysError, registers::Register, vm::VM};
+use crate::{exceptions::Exception, misclib::u8_slice_to_u16_vec, handles::{HandleErr, HandleTable}, native::NSysError, nativeerr::{handle_fault, native_fault, NativeErrKind, NativeError, NativeSubsys}, registers::Register, vm::VM};
 
 #[derive(Debug, PartialEq)]
 pub enum FileModes {
@@ -26,18 +26,29 @@ impl NatSFile {
 
 #[derive(Debug)]
 pub struct FileController {
-    opened_files: Vec<NatSFile>,
+    opened_files: HandleTable<NatSFile>,
+    pub max_open: Option<usize>, // --max-open-files
 }
 
 impl FileController {
     pub fn new() -> FileController {
         FileController { 
-            opened_files: (Vec::new()),
+            opened_files: HandleTable::new(),
+            max_open: None,
         }
     }
 
+    pub fn count(&self) -> usize {
+        self.opened_files.count()
+    }
+
+    /// No more files can be opened
+    pub fn at_limit(&self) -> bool {
+        self.max_open.is_some_and(|max| self.opened_files.count() >= max)
+    }
+
     pub fn open(&mut self, filename: String, mode: FileModes) 
-        -> Result<usize, NSysError> {
+        -> Result<u64, NSysError> {
         let mut options = OpenOptions::new();
         match mode {
             FileModes::Write => {
@@ -64,8 +75,22 @@ impl FileController {
         };
         f.seek(io::SeekFrom::Start(0));
         let nf = NatSFile::new(f, mode, filename);
-        self.opened_files.push(nf);
-        Ok(self.opened_files.len().saturating_sub(1))
+        Ok(self.opened_files.insert(nf))
+    }
+
+    pub fn get_mut(&mut self, handle: u64) -> Result<&mut NatSFile, HandleErr> {
+        self.opened_files.get_mut(handle)
+    }
+
+    /// Closes the file, `handle` becomes stale
+    pub fn close(&mut self, handle: u64) -> Result<NatSFile, HandleErr> {
+        self.opened_files.remove(handle)
+    }
+}
+
+impl Default for FileController {
+    fn default() -> FileController {
+        FileController::new()
     }
 }
 
@@ -73,7 +98,7 @@ pub fn ncall_fopen(vm: &mut VM) {
     // r1 is heap ptr to filename string 
     // r2 is bytes count to read 
     // r3 is mode uint 
-    // returns file index into r0 
+    // returns file handle into r0 
 
     let from_ptr: u64 = vm.registers[1].as_u64();
     let count: u64 = vm.registers[2].as_u64();
@@ -83,7 +108,7 @@ pub fn ncall_fopen(vm: &mut VM) {
         match vm.heap.read(from_ptr, count) {
             Ok(b) => b,
             Err(()) => {
-                show_runtime_err(vm, "Can't read heap!");
+                native_fault(vm, NativeError::new(NativeSubsys::Files, NativeErrKind::HeapFault), Exception::HeapReadFault, "Can't read heap!");
                 return;
             }
     };
@@ -91,6 +116,12 @@ pub fn ncall_fopen(vm: &mut VM) {
         &u8_slice_to_u16_vec(&fname_bytes)
     );
 
+    if vm.fc.at_limit() {
+        let msg: String = format!("Open files limit ({}) reached", vm.fc.max_open.unwrap_or(0));
+        native_fault(vm, NativeError::new(NativeSubsys::Files, NativeErrKind::LimitReached), Exception::HandleLimit, &msg);
+        return;
+    }
+
     let mode: FileModes = match mode_idx {
         1 => FileModes::Write,
         2 => FileModes::Read,
@@ -98,8 +129,7 @@ pub fn ncall_fopen(vm: &mut VM) {
         4 => FileModes::ReadWrite,
         5 => FileModes::ReadAppend,
         other => {
-            show_runtime_err(vm, &format!("Unknown file mode: {}", mode_idx));
-            vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
+            native_fault(vm, NativeError::new(NativeSubsys::Files, NativeErrKind::InvalidInput), Exception::NativeFault, &format!("Unknown file mode: {}", mode_idx));
             return;
         }
     };
@@ -107,65 +137,55 @@ pub fn ncall_fopen(vm: &mut VM) {
     let res = match vm.fc.open(fname, mode) {
         Ok(v) => v,
         Err(e) => {
-            show_runtime_err(vm, &format!("FC error: {:#?}", e));
-            vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
+            let err: NativeError = NativeError::new(NativeSubsys::Files, NativeErrKind::from(&e));
+            native_fault(vm, err, Exception::NativeFault, &format!("FC error: {:#?}", e));
             return;
         }
     };
 
-    vm.registers[0] = Register::uint(res as u64);
+    vm.registers[0] = Register::uint(res);
 }
 
 pub fn ncall_fclose(vm: &mut VM) {
     // ncall 0x11
-    // r1 is file index 
-    let idx: usize = vm.registers[1].as_u64() as usize;
-    if idx >= vm.fc.opened_files.len() {
-        show_runtime_err(vm, "File index out of range");
-        vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
-        return;
-    }   
-
-    vm.fc.opened_files.remove(idx);
+    // r1 is file handle 
+    let handle: u64 = vm.registers[1].as_u64();
+    if let Err(e) = vm.fc.close(handle) {
+        handle_fault(vm, NativeSubsys::Files, e, "file", handle);
+    }
 }
 
 pub fn ncall_fwrite(vm: &mut VM) {
     // ncall 0x12 
-    // r1 is file ind
+    // r1 is file handle
     // r2 is heap ptr to start to copy
     // r3 is count 
 
-    let f_idx: usize = vm.registers[1].as_u64() as usize;
+    let f_idx: u64 = vm.registers[1].as_u64();
     let tocopy: u64 = vm.registers[2].as_u64();
     let count: u64 = vm.registers[3].as_u64();
 
-    let mut f = match vm.fc.opened_files.get_mut(f_idx) {
-        Some(v) => v,
-        None => {
-            show_runtime_err(vm, "File index out of range");
-            vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
-            return;
-        }
+    let f = match vm.fc.get_mut(f_idx) {
+        Ok(v) => v,
+        Err(e) => return handle_fault(vm, NativeSubsys::Files, e, "file", f_idx),
     };
 
     if f.mode == FileModes::Read {
-        show_runtime_err(vm, &format!("File with idx {} is readonly", f_idx));
-        vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
+        native_fault(vm, NativeError::new(NativeSubsys::Files, NativeErrKind::WrongMode), Exception::NativeFault, &format!("File with handle {:#x} is readonly", f_idx));
         return;
     }
 
     let bytes = match vm.heap.read(tocopy, count) {
         Ok(v) => v,
         Err(()) => {
-            show_runtime_err(vm, "Heap read fault!");
-            vm.exceptions_active.push(crate::exceptions::Exception::HeapReadFault);
+            native_fault(vm, NativeError::new(NativeSubsys::Files, NativeErrKind::HeapFault), Exception::HeapReadFault, "Heap read fault!");
             return;
         }
     };
 
     if let Err(e) = f.file.write_all(&bytes) {
-        show_runtime_err(vm, "Can't write buf into file!");
-        vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
+        let err: NativeError = NativeError::from_io(NativeSubsys::Files, &e);
+        native_fault(vm, err, Exception::NativeFault, "Can't write buf into file!");
         return;
     }
     
@@ -174,79 +194,73 @@ pub fn ncall_fwrite(vm: &mut VM) {
 
 pub fn ncall_fread(vm: &mut VM) {
     // ncall 0x12 
-    // r1 is file idx 
+    // r1 is file handle 
     // r2 is bytes count 
     // r3 is heap dst ptr 
     // reads count bytes from file seek into vm heap 
-    let f_idx = vm.registers[1].as_u64() as usize;
+    let f_idx: u64 = vm.registers[1].as_u64();
     let count = vm.registers[2].as_u64();
     let dst = vm.registers[3].as_u64();
 
-    let mut f = match vm.fc.opened_files.get_mut(f_idx) {
-        Some(v) => v,
-        None => {
-            show_runtime_err(vm, "File index out of range");
-            vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
-            return;
-        }
+    let f = match vm.fc.get_mut(f_idx) {
+        Ok(v) => v,
+        Err(e) => return handle_fault(vm, NativeSubsys::Files, e, "file", f_idx),
     };
 
     if (f.mode == FileModes::Write) || (f.mode == FileModes::Append) {
-        show_runtime_err(vm, &format!("File with idx {} is writeonly", f_idx));
-        vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
+        native_fault(vm, NativeError::new(NativeSubsys::Files, NativeErrKind::WrongMode), Exception::NativeFault, &format!("File with handle {:#x} is writeonly", f_idx));
         return;
     }
 
     let mut buf = vec![0u8; count as usize];
-    let _ = f.file.read(&mut buf);
+    if let Err(e) = f.file.read(&mut buf) {
+        let err: NativeError = NativeError::from_io(NativeSubsys::Files, &e);
+        native_fault(vm, err, Exception::NativeFault, &format!("Can't read file: {}", e));
+        return;
+    }
 
     if let Err(()) = vm.heap.write(dst, buf) {
-        show_runtime_err(vm, "Can't write into heap!");
-        vm.exceptions_active.push(crate::exceptions::Exception::HeapWriteFault);
+        native_fault(vm, NativeError::new(NativeSubsys::Files, NativeErrKind::HeapFault), Exception::HeapWriteFault, "Can't write into heap!");
         return;
     }
 }
 
 pub fn ncall_fdel(vm: &mut VM) {
     // ncall 0x14
-    // r1 is file index 
+    // r1 is file handle 
     // deletes file from the filesystem AND filecontroller 
-    let f_idx: usize = vm.registers[1].as_u64() as usize;
+    let f_idx: u64 = vm.registers[1].as_u64();
 
-    if f_idx >= vm.fc.opened_files.len() {
-        show_runtime_err(vm, "File index out of range");
-        vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
-        return;
-};
-
-    let f = vm.fc.opened_files.remove(f_idx);
+    let f = match vm.fc.close(f_idx) {
+        Ok(v) => v,
+        Err(e) => return handle_fault(vm, NativeSubsys::Files, e, "file", f_idx),
+    };
     let fname = f.path.clone();
 
     drop(f);
 
-    std::fs::remove_file(fname);
+    if let Err(e) = std::fs::remove_file(fname) {
+        let err: NativeError = NativeError::from_io(NativeSubsys::Files, &e);
+        native_fault(vm, err, Exception::NativeFault, &format!("Can't delete file: {}", e));
+    }
 }
 
 /// ncall 0x15
-/// r1 is file index
+/// r1 is file handle
 /// will return current seek into r0
 pub fn ncall_fseekget(vm: &mut VM) {
-        let f_idx: usize = vm.registers[1].as_u64() as usize;
+        let f_idx: u64 = vm.registers[1].as_u64();
     
-    let mut f = match vm.fc.opened_files.get_mut(f_idx) {
-        Some(v) => v,
-        None => {
-            show_runtime_err(vm, "File index out of range");
-            vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
-            return;
-        }
+    let f = match vm.fc.get_mut(f_idx) {
+        Ok(v) => v,
+        Err(e) => return handle_fault(vm, NativeSubsys::Files, e, "file", f_idx),
     };
 
     let seek: u64 = match f.file.stream_position() {
         Ok(v) => v,
         Err(e) => {
-            show_runtime_err(vm, &format!("Error getting seek: {:#?}", e));
-            vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
+            let err: NativeError = NativeError::from_io(NativeSubsys::Files, &e);
+            native_fault(vm, err, Exception::NativeFault, &format!("Error getting seek: {:#?}", e));
             return; 
         }
     };
@@ -255,20 +269,28 @@ pub fn ncall_fseekget(vm: &mut VM) {
 }
 
 /// ncall 0x16 
-/// r1 is file index 
+/// r1 is file handle 
 /// r2 is new seek (current one could be obtained from `ncall_fseekget`)
 pub fn ncall_fseekset(vm: &mut VM) {
-    let f_idx: usize = vm.registers[1].as_u64() as usize;
+    let f_idx: u64 = vm.registers[1].as_u64();
     let newseek: u64 = vm.registers[2].as_u64();
 
-    let mut f = match vm.fc.opened_files.get_mut(f_idx) {
-        Some(v) => v,
-        None => {
-            show_runtime_err(vm, "File index out of range");
-            vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
-            return;
-        }
+    let f = match vm.fc.get_mut(f_idx) {
+        Ok(v) => v,
+        Err(e) => return handle_fault(vm, NativeSubsys::Files, e, "file", f_idx),
     };
 
-    f.file.seek(io::SeekFrom::Start(newseek));
+    if let Err(e) = f.file.seek(io::SeekFrom::Start(newseek)) {
+        let err: NativeError = NativeError::from_io(NativeSubsys::Files, &e);
+        native_fault(vm, err, Exception::NativeFault, &format!("Error setting seek: {:#?}", e));
+    }
+}
+
+/// ncall 0x19
+/// returns count of open files into r0, --max-open-files into r1
+/// (u64::MAX if unlimited)
+pub fn ncall_fc_usage(vm: &mut VM) {
+    let limit: u64 = vm.fc.max_open.map_or(u64::MAX, |m| m as u64);
+    vm.registers[0] = Register::uint(vm.fc.count() as u64);
+    vm.registers[1] = Register::uint(limit);
 }
@@ -0,0 +1,193 @@
+use std::io::{IoSlice, Read};
+
+use crate::{exceptions::Exception, misclib::args_to_u64, nativeerr::{handle_fault, native_fault, NativeErrKind, NativeError, NativeSubsys}, nativefiles::FileModes, nativenet::write_all_vectored, registers::Register, vm::{RegTypes, VM}};
+
+// Scatter/gather ncalls.
+// An io vector is a heap array of (ptr u64, len u64) big-endian pairs,
+// the other side is picked by kind: 1 - file handle, 2 - net conn handle, 3 - heap ptr.
+
+const IOV_FILE: u64 = 1;
+const IOV_NET: u64 = 2;
+const IOV_HEAP: u64 = 3;
+
+fn iov_subsys(kind: u64) -> NativeSubsys {
+    match kind {
+        IOV_FILE => NativeSubsys::Files,
+        IOV_NET => NativeSubsys::Net,
+        _ => NativeSubsys::Std,
+    }
+}
+
+fn read_iovec(vm: &mut VM, list_ptr: u64, count: u64) -> Result<Vec<(u64, u64)>, ()> {
+    let raw: Vec<u8> = vm.heap.read(list_ptr, count * 16)?;
+    Ok(raw
+        .chunks_exact(16)
+        .map(|pair| (args_to_u64(&pair[0..8]), args_to_u64(&pair[8..16])))
+        .collect())
+}
+
+/// ncall 0x17
+/// r1 is heap ptr to io vector, r2 is count of (ptr, len) pairs
+/// r3 is dst kind, r4 is dst file / net conn handle / heap ptr
+/// Writes all the pieces in order, r0 = bytes written.
+pub fn ncall_gather_write(vm: &mut VM) {
+    let list_ptr: u64 = vm.registers[1].as_u64();
+    let count: u64 = vm.registers[2].as_u64();
+    let kind: u64 = vm.registers[3].as_u64();
+    let dst: u64 = vm.registers[4].as_u64();
+    let subsys: NativeSubsys = iov_subsys(kind);
+
+    let iov: Vec<(u64, u64)> = match read_iovec(vm, list_ptr, count) {
+        Ok(v) => v,
+        Err(()) => {
+            native_fault(vm, NativeError::new(subsys, NativeErrKind::HeapFault), Exception::HeapReadFault, "Can't read io vector from heap");
+            return;
+        }
+    };
+    let mut pieces: Vec<&[u8]> = Vec::with_capacity(iov.len());
+    for (ptr, len) in &iov {
+        match vm.heap.slice(*ptr, *len) {
+            Ok(v) => pieces.push(v),
+            Err(()) => {
+                let msg: String = format!("Io vector piece [{:#x}; {}] is out of heap", ptr, len);
+                native_fault(vm, NativeError::new(subsys, NativeErrKind::HeapFault), Exception::HeapReadFault, &msg);
+                return;
+            }
+        }
+    }
+
+    let res: Result<usize, std::io::Error> = match kind {
+        IOV_FILE => match vm.fc.get_mut(dst) {
+            Ok(f) if f.mode == FileModes::Read => {
+                native_fault(vm, NativeError::new(subsys, NativeErrKind::WrongMode), Exception::NativeFault, &format!("File with handle {:#x} is readonly", dst));
+                return;
+            }
+            Ok(f) => {
+                let mut slices: Vec<IoSlice> = pieces.iter().map(|p| IoSlice::new(p)).collect();
+                write_all_vectored(&mut f.file, &mut slices)
+            }
+            Err(e) => return handle_fault(vm, subsys, e, "file", dst),
+        },
+        IOV_NET => match vm.nc.get_mut(dst) {
+            Ok(conn) => conn.send_vectored(&pieces),
+            Err(e) => return handle_fault(vm, subsys, e, "net conn", dst),
+        },
+        IOV_HEAP => {
+            let data: Vec<u8> = pieces.concat();
+            let len: usize = data.len();
+            match vm.heap.write(dst, data) {
+                Ok(()) => Ok(len),
+                Err(()) => {
+                    native_fault(vm, NativeError::new(subsys, NativeErrKind::HeapFault), Exception::HeapWriteFault, "Can't write heap!");
+                    return;
+                }
+            }
+        }
+        other => {
+            native_fault(vm, NativeError::new(subsys, NativeErrKind::InvalidInput), Exception::InvalidDataType, &format!("Invalid io vector dst kind: {}", other));
+            return;
+        }
+    };
+
+    match res {
+        Ok(written) => {
+            vm.registers[0] = Register::uint(written as u64);
+            vm.reg_types[0] = RegTypes::uint64;
+        }
+        Err(e) => {
+            let err: NativeError = NativeError::from_io(subsys, &e);
+            native_fault(vm, err, Exception::NativeFault, &format!("Gather write failed: {}", e));
+        }
+    }
+}
+
+/// ncall 0x18
+/// r1 is heap ptr to io vector, r2 is count of (ptr, len) pairs
+/// r3 is src kind, r4 is src file / net conn handle / heap ptr
+/// Fills the pieces in order with one read, r0 = bytes read
+/// (files are read until all the pieces are full or EOF).
+pub fn ncall_scatter_read(vm: &mut VM) {
+    let list_ptr: u64 = vm.registers[1].as_u64();
+    let count: u64 = vm.registers[2].as_u64();
+    let kind: u64 = vm.registers[3].as_u64();
+    let src: u64 = vm.registers[4].as_u64();
+    let subsys: NativeSubsys = iov_subsys(kind);
+
+    let iov: Vec<(u64, u64)> = match read_iovec(vm, list_ptr, count) {
+        Ok(v) => v,
+        Err(()) => {
+            native_fault(vm, NativeError::new(subsys, NativeErrKind::HeapFault), Exception::HeapReadFault, "Can't read io vector from heap");
+            return;
+        }
+    };
+    let total: u64 = iov.iter().map(|(_, len)| *len).sum();
+    let mut buf: Vec<u8> = vec![0u8; total as usize];
+
+    let res: Result<usize, std::io::Error> = match kind {
+        IOV_FILE => match vm.fc.get_mut(src) {
+            Ok(f) if (f.mode == FileModes::Write) || (f.mode == FileModes::Append) => {
+                native_fault(vm, NativeError::new(subsys, NativeErrKind::WrongMode), Exception::NativeFault, &format!("File with handle {:#x} is writeonly", src));
+                return;
+            }
+            Ok(f) => read_full(&mut f.file, &mut buf),
+            Err(e) => return handle_fault(vm, subsys, e, "file", src),
+        },
+        IOV_NET => match vm.nc.get_mut(src) {
+            Ok(conn) => conn.recv(&mut buf),
+            Err(e) => return handle_fault(vm, subsys, e, "net conn", src),
+        },
+        IOV_HEAP => match vm.heap.slice(src, total) {
+            Ok(v) => {
+                buf.copy_from_slice(v);
+                Ok(buf.len())
+            }
+            Err(()) => {
+                native_fault(vm, NativeError::new(subsys, NativeErrKind::HeapFault), Exception::HeapReadFault, "Can't read heap!");
+                return;
+            }
+        },
+        other => {
+            native_fault(vm, NativeError::new(subsys, NativeErrKind::InvalidInput), Exception::InvalidDataType, &format!("Invalid io vector src kind: {}", other));
+            return;
+        }
+    };
+
+    let readc: usize = match res {
+        Ok(v) => v,
+        Err(e) => {
+            let err: NativeError = NativeError::from_io(subsys, &e);
+            native_fault(vm, err, Exception::NativeFault, &format!("Scatter read failed: {}", e));
+            return;
+        }
+    };
+
+    let mut pos: usize = 0;
+    for (ptr, len) in iov {
+        if pos >= readc {
+            break;
+        }
+        let end: usize = (pos + len as usize).min(readc);
+        if vm.heap.write(ptr, buf[pos..end].to_vec()).is_err() {
+            let msg: String = format!("Io vector piece [{:#x}; {}] is out of heap", ptr, len);
+            native_fault(vm, NativeError::new(subsys, NativeErrKind::HeapFault), Exception::HeapWriteFault, &msg);
+            return;
+        }
+        pos = end;
+    }
+
+    vm.registers[0] = Register::uint(readc as u64);
+    vm.reg_types[0] = RegTypes::uint64;
+}
+
+fn read_full<R: Read>(r: &mut R, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+    let mut readc: usize = 0;
+    while readc < buf.len() {
+        match r.read(&mut buf[readc..]) {
+            Ok(0) => break,
+            Ok(n) => readc += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(readc)
+}
@@ -0,0 +1,205 @@
+use std::{fs::File, os::fd::AsRawFd, ops::Range};
+
+use crate::{
+    exceptions::Exception,
+    misclib::u8_slice_to_u16_vec,
+    nativeerr::{native_fault, NativeErrKind, NativeError, NativeSubsys},
+    registers::Register,
+    vm::{RegTypes, VM},
+};
+
+// Host files mapped into guest address space, for datasets too big to go
+// through fread into the heap. `ncall @mmap_open` maps a whole file with
+// the OS (mmap, private mapping), read-only or copy-on-write: writes of a
+// cow mapping stay in the VM and never reach the file. Mapped pointers are
+// MMAP_BASE + region offset, `load`, `store`, `load32`, `store32` and
+// `memcpy` take them like heap pointers, storing into a read-only mapping
+// raises HeapWriteFault. Regions sit MMAP_ALIGN apart and their addresses
+// aren't reused, a stale pointer faults instead of reading another file.
+// The GC doesn't scan mappings and they don't count against --max-mem,
+// the OS pages them in and out. `@mmap_close` unmaps, so does VM exit.
+
+/// Mapped pointers start here, between arena and scratch pointers
+pub const MMAP_BASE: u64 = 1 << 61;
+/// Region start alignment in guest address space
+pub const MMAP_ALIGN: u64 = 1 << 32;
+
+pub const MMAP_READONLY: u64 = 1;
+pub const MMAP_COW: u64 = 2;
+
+#[derive(Debug)]
+struct MappedFile {
+    start: u64, // guest address
+    len: usize,
+    host: *mut u8,
+    writable: bool,
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.host.cast(), self.len) };
+    }
+}
+
+#[derive(Debug)]
+pub struct MmapTable {
+    maps: Vec<MappedFile>,
+    next: u64, // start of the next region
+}
+
+impl MmapTable {
+    pub fn new() -> MmapTable {
+        MmapTable {
+            maps: Vec::new(),
+            next: MMAP_BASE,
+        }
+    }
+
+    /// Maps all of `file`, returns the guest address of the region
+    fn map(&mut self, file: &File, len: usize, writable: bool) -> std::io::Result<u64> {
+        let prot: i32 = match writable {
+            true => libc::PROT_READ | libc::PROT_WRITE,
+            false => libc::PROT_READ,
+        };
+        let host = unsafe { libc::mmap(std::ptr::null_mut(), len, prot, libc::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if host == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        let start: u64 = self.next;
+        self.next += (len as u64).div_ceil(MMAP_ALIGN).max(1) * MMAP_ALIGN;
+        self.maps.push(MappedFile {
+            start,
+            len,
+            host: host.cast(),
+            writable,
+        });
+        Ok(start)
+    }
+
+    pub fn unmap(&mut self, start: u64) -> Result<(), ()> {
+        let ind: usize = self.maps.iter().position(|m| m.start == start).ok_or(())?;
+        self.maps.remove(ind);
+        Ok(())
+    }
+
+    /// Start and size of the region containing `ptr`
+    pub fn region(&self, ptr: u64) -> Option<(u64, u64)> {
+        self.maps
+            .iter()
+            .find(|m| (ptr >= m.start) && (ptr < m.start + m.len as u64))
+            .map(|m| (m.start, m.len as u64))
+    }
+
+    /// The mapping and byte range of `count` bytes at `ptr`, all in one region
+    fn range(&self, ptr: u64, count: u64) -> Option<(&MappedFile, Range<usize>)> {
+        let map: &MappedFile = self.maps.iter().find(|m| (ptr >= m.start) && (ptr < m.start + m.len as u64))?;
+        let start: usize = (ptr - map.start) as usize;
+        let end: usize = start.checked_add(count as usize)?;
+        (end <= map.len).then_some((map, start..end))
+    }
+
+    pub fn read(&self, ptr: u64, count: u64) -> Result<Vec<u8>, ()> {
+        let (map, r) = self.range(ptr, count).ok_or(())?;
+        let bytes: &[u8] = unsafe { std::slice::from_raw_parts(map.host, map.len) };
+        Ok(bytes[r].to_vec())
+    }
+
+    pub fn write(&mut self, ptr: u64, data: &[u8]) -> Result<(), ()> {
+        let (map, r) = self.range(ptr, data.len() as u64).ok_or(())?;
+        if !map.writable {
+            return Err(());
+        }
+        let bytes: &mut [u8] = unsafe { std::slice::from_raw_parts_mut(map.host, map.len) };
+        bytes[r].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+impl Default for MmapTable {
+    fn default() -> MmapTable {
+        MmapTable::new()
+    }
+}
+
+pub fn is_mmap_ptr(ptr: u64) -> bool {
+    (MMAP_BASE..(MMAP_BASE << 1)).contains(&ptr)
+}
+
+fn mmap_fault(vm: &mut VM, kind: NativeErrKind, msg: &str) {
+    native_fault(vm, NativeError::new(NativeSubsys::Mmap, kind), Exception::NativeFault, msg);
+}
+
+/// ncall 0xC0
+/// r1 is heap ptr to the file name (utf16, like fopen), r2 its size in
+/// bytes, r3 the mode: 1 read-only, 2 copy-on-write. r0 = address of the
+/// mapped file, r1 = its size, both 0 on failure
+pub fn ncall_mmap_open(vm: &mut VM) {
+    let name_ptr: u64 = vm.registers[1].as_u64();
+    let name_len: u64 = vm.registers[2].as_u64();
+    let mode: u64 = vm.registers[3].as_u64();
+    vm.registers[0] = Register::address(0);
+    vm.reg_types[0] = RegTypes::address;
+    vm.registers[1] = Register::uint(0);
+    vm.reg_types[1] = RegTypes::uint64;
+
+    let fname: String = match vm.heap.read(name_ptr, name_len) {
+        Ok(bytes) => String::from_utf16_lossy(&u8_slice_to_u16_vec(&bytes)),
+        Err(()) => {
+            let err: NativeError = NativeError::new(NativeSubsys::Mmap, NativeErrKind::HeapFault);
+            return native_fault(vm, err, Exception::HeapReadFault, "mmap_open: can't read the file name");
+        }
+    };
+    let writable: bool = match mode {
+        MMAP_READONLY => false,
+        MMAP_COW => true,
+        other => return mmap_fault(vm, NativeErrKind::InvalidInput, &format!("mmap_open: unknown mode {}", other)),
+    };
+    let opened = File::open(&fname).and_then(|f| f.metadata().map(|meta| (f, meta.len())));
+    let (file, len) = match opened {
+        Ok(v) => v,
+        Err(e) => {
+            let err: NativeError = NativeError::from_io(NativeSubsys::Mmap, &e);
+            return native_fault(vm, err, Exception::NativeFault, &format!("mmap_open: {}: {}", fname, e));
+        }
+    };
+    if len == 0 {
+        // mmap refuses empty mappings
+        return mmap_fault(vm, NativeErrKind::InvalidInput, &format!("mmap_open: {} is empty", fname));
+    }
+    match vm.mmaps.map(&file, len as usize, writable) {
+        Ok(start) => {
+            vm.registers[0] = Register::address(start);
+            vm.registers[1] = Register::uint(len);
+        }
+        Err(e) => {
+            let err: NativeError = NativeError::from_io(NativeSubsys::Mmap, &e);
+            native_fault(vm, err, Exception::NativeFault, &format!("mmap_open: {}: {}", fname, e));
+        }
+    }
+}
+
+/// ncall 0xC1
+/// r1 is a mapped file address. r0 = start of its region, r1 = the size,
+/// both 0 if the address isn't mapped
+pub fn ncall_mmap_query(vm: &mut VM) {
+    let ptr: u64 = vm.registers[1].as_u64();
+    let (start, size) = vm.mmaps.region(ptr).unwrap_or((0, 0));
+    vm.registers[0] = Register::address(start);
+    vm.reg_types[0] = RegTypes::address;
+    vm.registers[1] = Register::uint(size);
+    vm.reg_types[1] = RegTypes::uint64;
+}
+
+/// ncall 0xC2
+/// r1 is a mapped file start. Unmaps it, cow writes are dropped.
+/// r0 = 1, 0 on failure
+pub fn ncall_mmap_close(vm: &mut VM) {
+    let ptr: u64 = vm.registers[1].as_u64();
+    vm.registers[0] = Register::uint(0);
+    vm.reg_types[0] = RegTypes::uint64;
+    if vm.mmaps.unmap(ptr).is_err() {
+        let msg: String = format!("mmap_close: {:#x} isn't the start of a mapped file", ptr);
+        return mmap_fault(vm, NativeErrKind::BadHandle, &msg);
+    }
+    vm.registers[0] = Register::uint(1);
+}
@@ -1,15 +1,31 @@
-use std::{io::{Read, Write}, net::{SocketAddr, TcpListener, TcpStream, UdpSocket}};
+use std::{io::{self, IoSlice, Read, Write}, net::{SocketAddr, TcpListener, TcpStream, UdpSocket}};
 
-use crate::{misclib::{show_runtime_err, u8_slice_to_u16_vec, vec16_into_vec8}, registers::Register, vm::VM};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::{exceptions::Exception, handles::{HandleErr, HandleTable}, misclib::{u8_slice_to_u16_vec, vec16_into_vec8}, nativeerr::{handle_fault, native_fault, NativeErrKind, NativeError, NativeSubsys}, registers::Register, vm::VM};
 
 #[derive(Debug)]
 pub struct NetController {
-    connections: Vec<NetConnection>    
+    connections: HandleTable<NetConnection>,
+    pub max_conns: Option<usize>, // --max-connections
 }
 
 impl NetController {
     pub fn new() -> NetController {
-        NetController { connections: Vec::new() }
+        NetController { connections: HandleTable::new(), max_conns: None }
+    }
+
+    pub fn count(&self) -> usize {
+        self.connections.count()
+    }
+
+    /// No more connections can be opened
+    pub fn at_limit(&self) -> bool {
+        self.max_conns.is_some_and(|max| self.connections.count() >= max)
+    }
+
+    pub fn get_mut(&mut self, nind: u64) -> Result<&mut NetConnection, HandleErr> {
+        self.connections.get_mut(nind)
     }
 
     fn tryaddr(s: &str) -> Result<SocketAddr, NCError> {
@@ -22,49 +38,65 @@ impl NetController {
     }
 
     pub fn openconn(&mut self, ntype: NetConnType, addr: &str)
-        -> Result<usize, NCError> {
+        -> Result<u64, NCError> {
         let nc: NetConnection = match ntype {
             NetConnType::NewTcpS() => {
                 let tcps: TcpStream = match TcpStream::connect(addr) {
                     Ok(v) => v,
                     Err(e) => {return Err(NCError::Native(e));}
                 }; 
+                let local: SocketAddr = tcps.local_addr().map_err(NCError::Native)?;
+                let peer: SocketAddr = tcps.peer_addr().map_err(NCError::Native)?;
 
-                NetConnection::new(
-                    NetConnType::TcpS(tcps), 
-                    NetController::tryaddr(addr)?    
-                )
+                let mut nc = NetConnection::new(NetConnType::TcpS(tcps), local);
+                nc.peer = Some(peer);
+                nc
             }
-            NetConnType::NewTcpL() => {
-                let tcpl: TcpListener = match TcpListener::bind(addr) {
+            NetConnType::NewTcpL(backlog) => {
+                let saddr: SocketAddr = NetController::tryaddr(addr)?;
+                let tcpl: TcpListener = match listen_backlog(saddr, backlog) {
                     Ok(v) => v,
                     Err(e) => {return Err(NCError::Native(e));}
                 };
-                NetConnection::new(
-                    NetConnType::TcpL(tcpl), 
-                    NetController::tryaddr(addr)?
-                )
+                // port 0 gets picked by the OS
+                let local: SocketAddr = tcpl.local_addr().map_err(NCError::Native)?;
+                let mut nc = NetConnection::new(NetConnType::TcpL(tcpl), local);
+                nc.backlog = Some(backlog);
+                nc
             }
             NetConnType::NewUdpS() => {
                 let udps: UdpSocket = match UdpSocket::bind(addr) {
                     Ok(v) => v,
                     Err(e) => {return Err(NCError::Native(e));}
                 };
-                NetConnection::new(
-                    NetConnType::UdpS(udps),
-                    NetController::tryaddr(addr)?
-                )
+                let local: SocketAddr = udps.local_addr().map_err(NCError::Native)?;
+                NetConnection::new(NetConnType::UdpS(udps), local)
             }
             _ => {
                 return Err(NCError::InvalidType());
             }
         };
 
-        self.connections.push(nc);
-        Ok(self.connections.len().saturating_sub(1))
+        Ok(self.connections.insert(nc))
+    }
+}
+
+impl Default for NetController {
+    fn default() -> NetController {
+        NetController::new()
     }
 }
 
+/// Binds a tcp listener with the given pending connections queue size
+fn listen_backlog(addr: SocketAddr, backlog: i32) -> std::io::Result<TcpListener> {
+    let sock: Socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    #[cfg(unix)]
+    sock.set_reuse_address(true)?; // same as std TcpListener::bind
+    sock.bind(&addr.into())?;
+    sock.listen(backlog)?;
+    Ok(sock.into())
+}
+
 #[derive(Debug)]
 pub enum NCError {
     Native(std::io::Error),
@@ -75,22 +107,65 @@ pub enum NCError {
 #[derive(Debug)]
 pub struct NetConnection {
     conn: NetConnType,
-    pub addr: SocketAddr
+    pub addr: SocketAddr, // local
+    pub peer: Option<SocketAddr>, // remote side of tcp streams
+    pub backlog: Option<i32>, // tcp listeners only
 }
 
 impl NetConnection {
     pub fn new(ntype: NetConnType, addr: SocketAddr) -> NetConnection {
-        NetConnection { conn: ntype, addr: addr }
+        NetConnection { conn: ntype, addr: addr, peer: None, backlog: None }
+    }
+}
+
+impl NetConnection {
+    /// Writes all of `bufs`, a udp socket sends them as a single datagram
+    pub fn send_vectored(&mut self, bufs: &[&[u8]]) -> io::Result<usize> {
+        match &mut self.conn {
+            NetConnType::TcpS(ts) => {
+                let mut slices: Vec<IoSlice> = bufs.iter().map(|b| IoSlice::new(b)).collect();
+                write_all_vectored(ts, &mut slices)
+            }
+            NetConnType::UdpS(us) => us.send(&bufs.concat()),
+            other => Err(io::Error::new(io::ErrorKind::Unsupported, format!("{:?} can't write data", other))),
+        }
+    }
+
+    pub fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.conn {
+            NetConnType::TcpS(ts) => ts.read(buf),
+            NetConnType::UdpS(us) => us.recv(buf),
+            other => Err(io::Error::new(io::ErrorKind::Unsupported, format!("{:?} can't read data", other))),
+        }
     }
 }
 
+/// `Write::write_all` for io vectors, returns total bytes written
+pub fn write_all_vectored<W: Write>(w: &mut W, mut bufs: &mut [IoSlice]) -> io::Result<usize> {
+    let mut total: usize = 0;
+    while !bufs.is_empty() {
+        match w.write_vectored(bufs) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+            Ok(n) => {
+                total += n;
+                IoSlice::advance_slices(&mut bufs, n);
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(total)
+}
+
+pub const DEFAULT_BACKLOG: i32 = 128;
+
 #[derive(Debug)]
 pub enum NetConnType {
     TcpS(TcpStream),
     TcpL(TcpListener),
     UdpS(UdpSocket),
     NewTcpS(),
-    NewTcpL(),
+    NewTcpL(i32), // backlog
     NewUdpS(),
 }
 
@@ -100,29 +175,36 @@ pub enum NetConnType {
 /// 3 - udpsocket)
 /// r2 is heap ptr to addr
 /// r3 is count
-/// returns idx into r0 
+/// r4 is backlog for tcplistener (0 for default)
+/// returns conn handle into r0 
 pub fn ncall_nc_bind(vm: &mut VM) {
     let conn_type_idx = vm.registers[1].as_u64();
+    let backlog: i32 = match vm.registers[4].as_u64() {
+        0 => DEFAULT_BACKLOG,
+        v => v.min(i32::MAX as u64) as i32,
+    };
 
     let conn_type: NetConnType = match conn_type_idx {
         1 => NetConnType::NewTcpS(),
-        2 => NetConnType::NewTcpL(),
+        2 => NetConnType::NewTcpL(backlog),
         3 => NetConnType::NewUdpS(),
         other => {
-            show_runtime_err(vm, &format!("Invalid connection type: {}", other));
-            vm.exceptions_active.push(crate::exceptions::Exception::InvalidDataType);
+            native_fault(vm, NativeError::new(NativeSubsys::Net, NativeErrKind::InvalidInput), Exception::InvalidDataType, &format!("Invalid connection type: {}", other));
             return;
         }
     };
 
+    if vm.nc.at_limit() {
+        return conn_limit_fault(vm);
+    }
+
     let src_ptr = vm.registers[2].as_u64();
     let count = vm.registers[3].as_u64();
 
     let addr_bytes: Vec<u8> = match vm.heap.read(src_ptr, count) {
         Ok(v) => v,
         Err(()) => {
-            show_runtime_err(vm, "Can't read from heap");
-            vm.exceptions_active.push(crate::exceptions::Exception::HeapReadFault);
+            native_fault(vm, NativeError::new(NativeSubsys::Net, NativeErrKind::HeapFault), Exception::HeapReadFault, "Can't read from heap");
             return;
         }
     };
@@ -131,99 +213,93 @@ pub fn ncall_nc_bind(vm: &mut VM) {
         &u8_slice_to_u16_vec(&addr_bytes)
     );
 
-    let idx: usize = match vm.nc.openconn(conn_type, &addr) {
+    let idx: u64 = match vm.nc.openconn(conn_type, &addr) {
         Ok(v) => v,
         Err(e) => {
-            show_runtime_err(vm, &format!("Error opening connection: {:#?}", e));
-            vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
+            let err: NativeError = NativeError::new(NativeSubsys::Net, NativeErrKind::from(&e));
+            native_fault(vm, err, Exception::NativeFault, &format!("Error opening connection: {:#?}", e));
             return;
         }
     };
 
-    vm.registers[0] = Register::uint(idx as u64);
+    vm.registers[0] = Register::uint(idx);
 }
 
 /// ncall 0x21 
-/// r1 is net conn idx 
+/// r1 is net conn handle 
 pub fn ncall_nc_close(vm: &mut VM) {
-    let nind: usize = vm.registers[1].as_u64() as usize;
+    let nind: u64 = vm.registers[1].as_u64();
 
-    if nind >= vm.nc.connections.len() {
-        show_runtime_err(vm, "Net conn idx >= nc conns len");
-        vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
-        return;
+    if let Err(e) = vm.nc.connections.remove(nind) {
+        handle_fault(vm, NativeSubsys::Net, e, "net conn", nind);
     }
-
-    vm.nc.connections.remove(nind);
 }
 
 /// ncall 0x22 
-/// r1 is net conn idx 
+/// r1 is net conn handle 
 /// only for tcp listener
-/// returns nind for new tcpstream into r0 
+/// returns handle of the new tcpstream into r0 
 pub fn ncall_nc_accept(vm: &mut VM) {
-    let nind: usize = vm.registers[1].as_u64() as usize;
+    let nind: u64 = vm.registers[1].as_u64();
     
     let conn: &NetConnection = match vm.nc.connections.get(nind) {
-        Some(v) => v,
-        None => {
-            show_runtime_err(vm, "Net conn idx is invalid");
-            vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
-            return;
-        }
+        Ok(v) => v,
+        Err(e) => return handle_fault(vm, NativeSubsys::Net, e, "net conn", nind),
     };
 
-    let mut res_idx: usize = 0;
-    match &conn.conn {
+    let res_idx: u64 = match &conn.conn {
+        NetConnType::TcpL(_) if vm.nc.at_limit() => {
+            return conn_limit_fault(vm);
+        },
         NetConnType::TcpL(tl) => {
             let new_tcps = match tl.accept() {
                 Ok(v) => v,
                 Err(e) => {
-                    show_runtime_err(vm, &format!("Error accepting connection: {}", e.to_string()));
-                    vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
+                    let err: NativeError = NativeError::from_io(NativeSubsys::Net, &e);
+                    native_fault(vm, err, Exception::NativeFault, &format!("Error accepting connection: {}", e));
                     return;
                 }
             };
 
-            let newconn = NetConnection::new(
-                NetConnType::TcpS(new_tcps.0), new_tcps.1
-            );
-            vm.nc.connections.push(newconn);
-            res_idx = vm.nc.connections.len().saturating_sub(1);
+            let local: SocketAddr = match new_tcps.0.local_addr() {
+                Ok(v) => v,
+                Err(e) => {
+                    let err: NativeError = NativeError::from_io(NativeSubsys::Net, &e);
+                    native_fault(vm, err, Exception::NativeFault, &format!("Error accepting connection: {}", e));
+                    return;
+                }
+            };
+            let mut newconn = NetConnection::new(NetConnType::TcpS(new_tcps.0), local);
+            newconn.peer = Some(new_tcps.1);
+            vm.nc.connections.insert(newconn)
         },
         _ => {
-            show_runtime_err(vm, "`accept` is not implemented for not-tcplistener types");
-            vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
+            native_fault(vm, NativeError::new(NativeSubsys::Net, NativeErrKind::Unsupported), Exception::NativeFault, "`accept` is not implemented for not-tcplistener types");
             return;
         }
-    }
+    };
 
-    vm.registers[0] = Register::uint(res_idx as u64);
+    vm.registers[0] = Register::uint(res_idx);
 }
 
 /// ncall 0x23 
-/// r1 is nind 
+/// r1 is net conn handle 
 /// r2 is heap ptr to data 
 /// r3 is count 
 pub fn ncall_nc_write(vm: &mut VM) {
-    let nind: usize  = vm.registers[1].as_u64() as usize;
+    let nind: u64 = vm.registers[1].as_u64();
     let from_ptr: u64 = vm.registers[2].as_u64();
     let count:  u64 = vm.registers[3].as_u64();
 
     let conn: &mut NetConnection = match vm.nc.connections.get_mut(nind) {
-        Some(v) => v,
-        None => {
-            show_runtime_err(vm, "Net conn idx is invalid");
-            vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
-            return;
-        }
+        Ok(v) => v,
+        Err(e) => return handle_fault(vm, NativeSubsys::Net, e, "net conn", nind),
     };
 
     let data: Vec<u8> = match vm.heap.read(from_ptr, count) {
         Ok(b) => b,
         Err(()) => {
-            show_runtime_err(vm, "Error while reading heap data for net write!");
-            vm.exceptions_active.push(crate::exceptions::Exception::HeapReadFault);
+            native_fault(vm, NativeError::new(NativeSubsys::Net, NativeErrKind::HeapFault), Exception::HeapReadFault, "Error while reading heap data for net write!");
             return;
         }
     };
@@ -236,8 +312,8 @@ pub fn ncall_nc_write(vm: &mut VM) {
                     count_written = c;
                 }
                 Err(e) => {
-                    show_runtime_err(vm, &format!("While writing data over network: {}", e.to_string()));
-                    vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
+                    let err: NativeError = NativeError::from_io(NativeSubsys::Net, &e);
+                    native_fault(vm, err, Exception::NativeFault, &format!("While writing data over network: {}", e));
                     return;
                 }
             }
@@ -248,17 +324,16 @@ pub fn ncall_nc_write(vm: &mut VM) {
                     count_written = c;
                 }
                 Err(e) => {
-                    show_runtime_err(vm, &format!("While writing data over network: {}", e.to_string()));
-                    vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
+                    let err: NativeError = NativeError::from_io(NativeSubsys::Net, &e);
+                    native_fault(vm, err, Exception::NativeFault, &format!("While writing data over network: {}", e));
                     return;
                 }
             }
         }
         other => {
-            eprintln!("{:#?} can't write data!", other);
-            vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
+            let msg: String = format!("{:#?} can't write data!", other);
+            native_fault(vm, NativeError::new(NativeSubsys::Net, NativeErrKind::Unsupported), Exception::NativeFault, &msg);
             return;
-
         }
         
     }
@@ -266,24 +341,20 @@ pub fn ncall_nc_write(vm: &mut VM) {
 }
 
 // ncall 0x24 
-// r1 is nind 
+// r1 is net conn handle 
 // r2 is dst heap ptr 
 // r3 is max to read 
 // in case of udps, it will also add some 
 // addr utf16be bytes into the begging. 
 // make sure to have enough space
 pub fn ncall_nc_read(vm: &mut VM) {
-    let nind: usize = vm.registers[1].as_u64() as usize;
+    let nind: u64 = vm.registers[1].as_u64();
     let dst_ptr: u64 = vm.registers[2].as_u64();
     let maxc: usize = vm.registers[3].as_u64() as usize;
 
     let conn: &mut NetConnection = match vm.nc.connections.get_mut(nind) {
-        Some(v) => v,
-        None => {
-            show_runtime_err(vm, "Net conn idx is invalid");
-            vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
-            return;
-        }
+        Ok(v) => v,
+        Err(e) => return handle_fault(vm, NativeSubsys::Net, e, "net conn", nind),
     };
     let mut buf = vec![0u8; maxc];
     let mut readc: usize = 0;
@@ -296,8 +367,8 @@ pub fn ncall_nc_read(vm: &mut VM) {
                     readc = v;
                 }
                 Err(e) => {
-                    eprintln!("Error while reading from tcp stream: {}", e.to_string());
-                    vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
+                    let err: NativeError = NativeError::from_io(NativeSubsys::Net, &e);
+                    native_fault(vm, err, Exception::NativeFault, &format!("Error while reading from network: {}", e));
                     return;
                 }
             }
@@ -309,15 +380,15 @@ pub fn ncall_nc_read(vm: &mut VM) {
                     from_addr = Some(dat.1);
                 },
                 Err(e) => {
-                    eprintln!("Error while reading from tcp stream: {}", e.to_string());
-                    vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
+                    let err: NativeError = NativeError::from_io(NativeSubsys::Net, &e);
+                    native_fault(vm, err, Exception::NativeFault, &format!("Error while reading from network: {}", e));
                     return;
                 }
             }
         }
         other => {
-            eprintln!("{:#?} can't write data!", other);
-            vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
+            let msg: String = format!("{:#?} can't read data!", other);
+            native_fault(vm, NativeError::new(NativeSubsys::Net, NativeErrKind::Unsupported), Exception::NativeFault, &msg);
             return;
         }
     }
@@ -330,8 +401,7 @@ pub fn ncall_nc_read(vm: &mut VM) {
 
     let buf_len = buf.len();
     if let Err(()) = vm.heap.write(dst_ptr, buf) {
-        show_runtime_err(vm, "Can't write heap!");
-        vm.exceptions_active.push(crate::exceptions::Exception::HeapWriteFault);
+        native_fault(vm, NativeError::new(NativeSubsys::Net, NativeErrKind::HeapFault), Exception::HeapWriteFault, "Can't write heap!");
         return;
     }
 
@@ -339,31 +409,70 @@ pub fn ncall_nc_read(vm: &mut VM) {
 }
 
 // ncall 0x25 
-// r1 is nind 
+// r1 is net conn handle 
 // r2 is dst heap ptr 
-// writes conn addr into heap 
+// writes local conn addr into heap 
 // returns written  bytes count into r0
 pub fn ncall_nc_getaddr(vm: &mut VM) {
-    let nind: usize = vm.registers[1].as_u64() as usize;
+    let nind: u64 = vm.registers[1].as_u64();
     let dst_ptr: u64 = vm.registers[2].as_u64();
 
-    let conn: &NetConnection = match vm.nc.connections.get(nind) {
-        Some(v) => v,
+    let addr: SocketAddr = match vm.nc.connections.get(nind) {
+        Ok(v) => v.addr,
+        Err(e) => return handle_fault(vm, NativeSubsys::Net, e, "net conn", nind),
+    };
+    write_addr(vm, addr, dst_ptr);
+}
+
+// ncall 0x26 
+// r1 is net conn handle 
+// r2 is dst heap ptr 
+// writes peer addr of a tcp stream into heap 
+// returns written  bytes count into r0
+pub fn ncall_nc_getpeer(vm: &mut VM) {
+    let nind: u64 = vm.registers[1].as_u64();
+    let dst_ptr: u64 = vm.registers[2].as_u64();
+
+    let peer: Option<SocketAddr> = match vm.nc.connections.get(nind) {
+        Ok(v) => v.peer,
+        Err(e) => return handle_fault(vm, NativeSubsys::Net, e, "net conn", nind),
+    };
+    match peer {
+        Some(addr) => write_addr(vm, addr, dst_ptr),
         None => {
-            show_runtime_err(vm, "Net conn idx is invalid");
-            vm.exceptions_active.push(crate::exceptions::Exception::NativeFault);
-            return;
+            native_fault(vm, NativeError::new(NativeSubsys::Net, NativeErrKind::NotConnected), Exception::NativeFault, "Net conn has no peer");
         }
-    };
+    }
+}
+
+// ncall 0x27 
+// returns count of open net conns (listeners included) into r0
+pub fn ncall_nc_count(vm: &mut VM) {
+    vm.registers[0] = Register::uint(vm.nc.count() as u64);
+}
+
+// ncall 0x28
+// returns count of open net conns into r0, --max-connections into r1
+// (u64::MAX if unlimited)
+pub fn ncall_nc_usage(vm: &mut VM) {
+    let limit: u64 = vm.nc.max_conns.map_or(u64::MAX, |m| m as u64);
+    vm.registers[0] = Register::uint(vm.nc.count() as u64);
+    vm.registers[1] = Register::uint(limit);
+}
+
+fn conn_limit_fault(vm: &mut VM) {
+    let msg: String = format!("Connections limit ({}) reached", vm.nc.max_conns.unwrap_or(0));
+    native_fault(vm, NativeError::new(NativeSubsys::Net, NativeErrKind::LimitReached), Exception::HandleLimit, &msg);
+}
 
+fn write_addr(vm: &mut VM, addr: SocketAddr, dst_ptr: u64) {
     let addr_dbytes: Vec<u16> = 
-        conn.addr.to_string().encode_utf16().collect();
+        addr.to_string().encode_utf16().collect();
     let addr_bytes: Vec<u8> = vec16_into_vec8(addr_dbytes);
     let bcount: usize = addr_bytes.len();
 
     if let Err(()) = vm.heap.write(dst_ptr, addr_bytes) {
-        show_runtime_err(vm, "Can't write heap!");
-        vm.exceptions_active.push(crate::exceptions::Exception::HeapWriteFault);
+        native_fault(vm, NativeError::new(NativeSubsys::Net, NativeErrKind::HeapFault), Exception::HeapWriteFault, "Can't write heap!");
         return;
     }
 
@@ -0,0 +1,132 @@
+use std::path::{self, Component, Path, PathBuf, MAIN_SEPARATOR_STR};
+
+use crate::{
+    exceptions::Exception,
+    nativeerr::{native_fault, NativeErrKind, NativeError, NativeSubsys},
+    nativestr::{read_str, read_str_at, return_str},
+    vm::VM,
+};
+
+// Path ncalls over std::path, so guest code doesn't glue paths together with
+// the host separator by hand. Strings are read like the text ncalls do: r1
+// is a data segment string or, holding a heap address, a heap buffer of r2
+// bytes (r3/r4 for the second path of `path_join`). Results are new GC
+// managed UTF-16 heap strings, r0 = address, r1 = size in bytes, like
+// `str_slice`; a missing part (no extension, the parent of `/`) gives
+// r0 = r1 = 0. Both `/` and `\` are taken as separators, results use the
+// host one. Nothing but `path_abs` looks at the file system, and it only
+// asks for the working directory.
+
+/// Both separators turned into the host one
+fn host_separators(s: &str) -> PathBuf {
+    PathBuf::from(s.replace(['/', '\\'], MAIN_SEPARATOR_STR))
+}
+
+/// `path` with `.` dropped and `..` taking the previous name back out,
+/// lexically: symlinks aren't followed. `..` above a root is dropped, above
+/// the start of a relative path kept. An empty result is `.`
+fn normalize(path: &Path) -> PathBuf {
+    let mut res: Vec<Component> = Vec::new();
+    for comp in path.components() {
+        match comp {
+            Component::CurDir => {}
+            Component::ParentDir => match res.last() {
+                Some(Component::Normal(_)) => {
+                    res.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => res.push(comp),
+            },
+            _ => res.push(comp),
+        }
+    }
+    match res.is_empty() {
+        true => PathBuf::from("."),
+        false => res.iter().collect(),
+    }
+}
+
+fn path_arg(vm: &mut VM, what: &str) -> Option<PathBuf> {
+    read_str(vm, NativeSubsys::Path, what).map(|s| host_separators(&s))
+}
+
+/// ncall 0xE0
+/// r1 is the base path (r2 byte count if r1 is a heap address), r3 the path
+/// to add (r4 byte count). Joins them with the host separator, an absolute
+/// second path replaces the base
+pub fn ncall_path_join(vm: &mut VM) {
+    let Some(base) = path_arg(vm, "path_join") else {
+        return;
+    };
+    let Some(tail) = read_str_at(vm, 3, NativeSubsys::Path, "path_join") else {
+        return;
+    };
+    let joined: PathBuf = base.join(host_separators(&tail));
+    return_str(vm, &joined.to_string_lossy());
+}
+
+/// ncall 0xE1
+/// r1 is a path (r2 byte count if r1 is a heap address). Returns it with
+/// host separators, without repeated ones, `.` and resolvable `..`
+pub fn ncall_path_norm(vm: &mut VM) {
+    let Some(path) = path_arg(vm, "path_norm") else {
+        return;
+    };
+    return_str(vm, &normalize(&path).to_string_lossy());
+}
+
+/// ncall 0xE2
+/// r1 is a path (r2 byte count if r1 is a heap address). Returns its last
+/// name, none if it ends in `..` or is a root
+pub fn ncall_path_name(vm: &mut VM) {
+    let Some(path) = path_arg(vm, "path_name") else {
+        return;
+    };
+    let name: String = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    return_str(vm, &name);
+}
+
+/// ncall 0xE3
+/// r1 is a path (r2 byte count if r1 is a heap address). Returns the
+/// extension of its last name without the dot, none for `.hidden` names
+pub fn ncall_path_ext(vm: &mut VM) {
+    let Some(path) = path_arg(vm, "path_ext") else {
+        return;
+    };
+    let ext: String = path.extension().map(|e| e.to_string_lossy().into_owned()).unwrap_or_default();
+    return_str(vm, &ext);
+}
+
+/// ncall 0xE4
+/// r1 is a path (r2 byte count if r1 is a heap address). Returns it without
+/// its last component, none for a root. A single relative name has the
+/// empty parent
+pub fn ncall_path_parent(vm: &mut VM) {
+    let Some(path) = path_arg(vm, "path_parent") else {
+        return;
+    };
+    let parent: String = path.parent().map(|p| p.to_string_lossy().into_owned()).unwrap_or_default();
+    return_str(vm, &parent);
+}
+
+/// ncall 0xE5
+/// r1 is a path (r2 byte count if r1 is a heap address). Returns it made
+/// absolute against the working directory (std::path::absolute: `..` is
+/// kept, the file doesn't need to exist). An empty path faults with
+/// InvalidInput
+pub fn ncall_path_abs(vm: &mut VM) {
+    let Some(path) = path_arg(vm, "path_abs") else {
+        return;
+    };
+    match path::absolute(&path) {
+        Ok(abs) => return_str(vm, &abs.to_string_lossy()),
+        Err(e) => {
+            let err: NativeError = NativeError::from_io(NativeSubsys::Path, &e);
+            let exc: Exception = match err.kind {
+                NativeErrKind::InvalidInput => Exception::InvalidDataType,
+                _ => Exception::NativeFault,
+            };
+            native_fault(vm, err, exc, &format!("path_abs: {}: {}", path.display(), e));
+        }
+    }
+}
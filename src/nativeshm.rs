@@ -0,0 +1,78 @@
+use crate::{
+    exceptions::Exception,
+    heap::alloc_fault,
+    memcap::mem_reserve,
+    nativeerr::{native_fault, NativeErrKind, NativeError, NativeSubsys},
+    registers::Register,
+    vm::{RegTypes, VM},
+};
+
+// Heap regions shared with native plugins, for bulk data (pixels, audio
+// samples) that shouldn't be copied through HeapApi read/write on every
+// call. `ncall @shm_create` allocates a zeroed heap block at a multiple of
+// SHM_ALIGN, pins it (a GC root that is never collected) and marks it
+// shared. Plugin functions with `heap_api = true` turn a guest address in
+// it into a host pointer with `HeapApi::shared` and may keep that pointer:
+// the heap buffer is allocated at its full size once and never moves, and
+// `free` refuses shared regions, so it stays valid until `shm_release`.
+// The guest reads and writes the region with the usual load/store.
+// Nothing synchronizes the two sides, a plugin writing from its own thread
+// has to agree with the guest on when data is ready (a flag word in the
+// region, or an ncall).
+
+/// Region start alignment, cache lines and SIMD loads of plugins
+pub const SHM_ALIGN: usize = 64;
+
+fn shm_fault(vm: &mut VM, kind: NativeErrKind, msg: &str) {
+    native_fault(vm, NativeError::new(NativeSubsys::Shm, kind), Exception::NativeFault, msg);
+}
+
+/// ncall 0xA0
+/// r1 is size in bytes. r0 = address of a new zeroed shared region,
+/// HeapAllocationFault if it couldn't be allocated
+pub fn ncall_shm_create(vm: &mut VM) {
+    let size: u64 = vm.registers[1].as_u64();
+    vm.registers[0] = Register::address(0);
+    vm.reg_types[0] = RegTypes::address;
+    if size == 0 {
+        return shm_fault(vm, NativeErrKind::InvalidInput, "shm_create: size is 0");
+    }
+    if !mem_reserve(vm, size) {
+        return;
+    }
+    let Some(ptr) = vm.heap.alloc_aligned_at(size as usize, SHM_ALIGN, vm.alloc_site()) else {
+        return alloc_fault(vm, size);
+    };
+    // allocated right above, so it can be shared
+    vm.heap.share(ptr).unwrap();
+    vm.registers[0] = Register::address(ptr);
+}
+
+/// ncall 0xA1
+/// r1 is an address. r0 = start of the shared region containing it,
+/// r1 = its size, both 0 if the address isn't in a shared region (0 is a
+/// valid start, the size tells)
+pub fn ncall_shm_query(vm: &mut VM) {
+    let ptr: u64 = vm.registers[1].as_u64();
+    let (start, size) = vm.heap.shared_region(ptr).unwrap_or((0, 0));
+    vm.registers[0] = Register::address(start);
+    vm.reg_types[0] = RegTypes::address;
+    vm.registers[1] = Register::uint(size);
+    vm.reg_types[1] = RegTypes::uint64;
+}
+
+/// ncall 0xA2
+/// r1 is a shared region start. Stops sharing and frees it, plugins
+/// must not use their pointers into it anymore. r0 = 1, 0 on failure
+pub fn ncall_shm_release(vm: &mut VM) {
+    let ptr: u64 = vm.registers[1].as_u64();
+    vm.registers[0] = Register::uint(0);
+    vm.reg_types[0] = RegTypes::uint64;
+    if vm.heap.unshare(ptr).is_err() {
+        let msg: String = format!("shm_release: {:#x} isn't the start of a shared region", ptr);
+        return shm_fault(vm, NativeErrKind::BadHandle, &msg);
+    }
+    // pinned and out of GC control, only `free` could have taken it
+    vm.heap.free(ptr).unwrap();
+    vm.registers[0] = Register::uint(1);
+}
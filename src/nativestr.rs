@@ -0,0 +1,262 @@
+use std::io::Write;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    exceptions::Exception,
+    heap::gc_alloc,
+    misclib::{bytes_from_straddr, u8_slice_to_u16_vec, vec16_into_vec8},
+    nativeerr::{native_fault, NativeErrKind, NativeError, NativeSubsys},
+    registers::Register,
+    vm::{RegTypes, RegistersCount, VM},
+};
+
+// Text ncalls counting and slicing by characters instead of UTF-16 bytes.
+// The string in r1 is either a data segment string (StrAddr, or the address
+// `dslea var 9` gives) or, when r1 holds a heap address, a heap buffer of
+// r2 bytes, like `readin` fills. Both are UTF-16 big endian; lone surrogates
+// fail with InvalidInput. Characters are counted in one of two units:
+//   0 - unicode scalar values (code points)
+//   1 - grapheme clusters, what a reader sees as one character
+//       ("e" + combining accent, flags, emoji sequences)
+// `ncall @printf` formats registers by the specs of a format string instead
+// of their tracked types, so a value keeps printing the same after casts.
+
+pub const UNIT_SCALARS: u64 = 0;
+pub const UNIT_GRAPHEMES: u64 = 1;
+
+/// The string r1 (and r2) describe, None after raising a fault of `subsys`
+pub fn read_str(vm: &mut VM, subsys: NativeSubsys, what: &str) -> Option<String> {
+    read_str_at(vm, 1, subsys, what)
+}
+
+/// The string register `reg` (and the next one for a heap byte count) describe
+pub fn read_str_at(vm: &mut VM, reg: usize, subsys: NativeSubsys, what: &str) -> Option<String> {
+    let addr: u64 = vm.registers[reg].as_u64();
+    let bytes: Option<Vec<u8>> = match vm.reg_types[reg] {
+        RegTypes::address => vm.heap.read(addr, vm.registers[reg + 1].as_u64()).ok(),
+        _ => bytes_from_straddr(vm, addr),
+    };
+    let Some(bytes) = bytes else {
+        let (exc, place) = match vm.reg_types[reg] {
+            RegTypes::address => (Exception::HeapReadFault, "heap"),
+            _ => (Exception::MainSegmFault, "data segment"),
+        };
+        let msg: String = format!("{}: no {} string at {:#x}", what, place, addr);
+        native_fault(vm, NativeError::new(subsys, NativeErrKind::HeapFault), exc, &msg);
+        return None;
+    };
+    match String::from_utf16(&u8_slice_to_u16_vec(&bytes)) {
+        Ok(s) => Some(s),
+        Err(_) => {
+            let msg: String = format!("{}: string at {:#x} isn't valid UTF-16", what, addr);
+            native_fault(vm, NativeError::new(subsys, NativeErrKind::InvalidInput), Exception::InvalidDataType, &msg);
+            None
+        }
+    }
+}
+
+/// The string cut into characters of `unit`
+fn split_units(vm: &mut VM, s: &str, unit: u64, what: &str) -> Option<Vec<String>> {
+    match unit {
+        UNIT_SCALARS => Some(s.chars().map(String::from).collect()),
+        UNIT_GRAPHEMES => Some(s.graphemes(true).map(String::from).collect()),
+        other => {
+            let msg: String = format!("{}: unknown unit {}, 0 is code points, 1 graphemes", what, other);
+            native_fault(vm, NativeError::new(NativeSubsys::Text, NativeErrKind::InvalidInput), Exception::InvalidDataType, &msg);
+            None
+        }
+    }
+}
+
+fn set_uint(vm: &mut VM, reg: usize, val: u64) {
+    vm.registers[reg] = Register::uint(val);
+    vm.reg_types[reg] = RegTypes::uint64;
+}
+
+/// ncall 0x80
+/// r1 is string (r2 byte count if r1 is a heap address), r3 is unit.
+/// r0 = count of characters in the unit
+pub fn ncall_str_len(vm: &mut VM) {
+    let unit: u64 = vm.registers[3].as_u64();
+    let Some(s) = read_str(vm, NativeSubsys::Text, "str_len") else {
+        return;
+    };
+    let Some(chars) = split_units(vm, &s, unit, "str_len") else {
+        return;
+    };
+    set_uint(vm, 0, chars.len() as u64);
+}
+
+/// ncall 0x81
+/// r1 is string (r2 byte count if r1 is a heap address), r3 is unit,
+/// r4 is index of the first character, r5 is count of characters.
+/// Copies them into a new GC managed heap buffer, r0 = its address,
+/// r1 = its size in bytes (print it with `ncall @print`, r3 = r1).
+/// The range is cut at the string end, an empty slice gives r0 = r1 = 0
+pub fn ncall_str_slice(vm: &mut VM) {
+    let unit: u64 = vm.registers[3].as_u64();
+    let start: u64 = vm.registers[4].as_u64();
+    let count: u64 = vm.registers[5].as_u64();
+    let Some(s) = read_str(vm, NativeSubsys::Text, "str_slice") else {
+        return;
+    };
+    let Some(chars) = split_units(vm, &s, unit, "str_slice") else {
+        return;
+    };
+    let slice: String = chars
+        .iter()
+        .skip(start.min(usize::MAX as u64) as usize)
+        .take(count.min(usize::MAX as u64) as usize)
+        .map(String::as_str)
+        .collect();
+    return_str(vm, &slice);
+}
+
+/// Copies `s` into a new GC managed heap buffer, r0 = its address, r1 = its
+/// size in bytes. An empty string gives r0 = r1 = 0
+pub fn return_str(vm: &mut VM, s: &str) {
+    let bytes: Vec<u8> = vec16_into_vec8(s.encode_utf16().collect());
+    if bytes.is_empty() {
+        vm.registers[0] = Register::address(0);
+        vm.reg_types[0] = RegTypes::address;
+        set_uint(vm, 1, 0);
+        return;
+    }
+    let Some(ptr) = gc_alloc(vm, bytes.len() as u64) else {
+        return;
+    };
+    let size: u64 = bytes.len() as u64;
+    vm.heap.write(ptr, bytes).unwrap();
+    vm.registers[0] = Register::address(ptr);
+    vm.reg_types[0] = RegTypes::address;
+    set_uint(vm, 1, size);
+}
+
+/// ncall 0x82
+/// r1 is string (r2 byte count if r1 is a heap address), r3 is unit,
+/// r4 is a character index. r0 = UTF-16 byte offset where that character
+/// starts (the string size in bytes for the index past the last one),
+/// r1 = its size in bytes. Indices past the end fault with InvalidInput
+pub fn ncall_str_offset(vm: &mut VM) {
+    let unit: u64 = vm.registers[3].as_u64();
+    let index: u64 = vm.registers[4].as_u64();
+    let Some(s) = read_str(vm, NativeSubsys::Text, "str_offset") else {
+        return;
+    };
+    let Some(chars) = split_units(vm, &s, unit, "str_offset") else {
+        return;
+    };
+    if index > chars.len() as u64 {
+        let msg: String = format!("str_offset: index {} is past the {} characters", index, chars.len());
+        native_fault(vm, NativeError::new(NativeSubsys::Text, NativeErrKind::InvalidInput), Exception::InvalidDataType, &msg);
+        return;
+    }
+    let utf16_len = |c: &String| c.encode_utf16().count() as u64 * 2;
+    let offset: u64 = chars.iter().take(index as usize).map(utf16_len).sum();
+    let size: u64 = chars.get(index as usize).map_or(0, utf16_len);
+    set_uint(vm, 0, offset);
+    set_uint(vm, 1, size);
+}
+
+/// First argument register of `printf`
+const PRINTF_FIRST_ARG: usize = 4;
+
+/// A failed `printf`: error kind, exception and message
+type FormatErr = (NativeErrKind, Exception, String);
+
+/// Next argument register of `printf`, past r31 is an error
+fn next_arg(vm: &VM, next: &mut usize, spec: char) -> Result<Register, FormatErr> {
+    let Some(reg) = vm.registers.get(*next) else {
+        let msg: String = format!("printf: no register left for %{}, arguments end at r{}", spec, RegistersCount - 1);
+        return Err((NativeErrKind::InvalidInput, Exception::InvalidDataType, msg));
+    };
+    *next += 1;
+    Ok(*reg)
+}
+
+/// `fmt` with its specs filled from r4, r5..
+fn format_args(vm: &mut VM, fmt: &str) -> Result<String, FormatErr> {
+    let mut res: String = String::new();
+    let mut next: usize = PRINTF_FIRST_ARG;
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            res.push(c);
+            continue;
+        }
+        let spec: char = chars.next().unwrap_or(' ');
+        match spec {
+            '%' => res.push('%'),
+            'u' => res.push_str(&next_arg(vm, &mut next, spec)?.as_u64_bitwise().to_string()),
+            'd' => res.push_str(&(next_arg(vm, &mut next, spec)?.as_u64_bitwise() as i64).to_string()),
+            'f' => res.push_str(&f64::from_bits(next_arg(vm, &mut next, spec)?.as_u64_bitwise()).to_string()),
+            'x' => res.push_str(&format!("{:#x}", next_arg(vm, &mut next, spec)?.as_u64_bitwise())),
+            's' => {
+                let addr: u64 = next_arg(vm, &mut next, spec)?.as_u64_bitwise();
+                let Some(bytes) = bytes_from_straddr(vm, addr) else {
+                    let msg: String = format!("printf: no data segment string at {:#x} for %s", addr);
+                    return Err((NativeErrKind::HeapFault, Exception::MainSegmFault, msg));
+                };
+                res.push_str(&utf16_arg(&bytes, addr, spec)?);
+            }
+            'h' => {
+                let addr: u64 = next_arg(vm, &mut next, spec)?.as_u64_bitwise();
+                let count: u64 = next_arg(vm, &mut next, spec)?.as_u64_bitwise();
+                let Ok(bytes) = vm.heap.read(addr, count) else {
+                    let msg: String = format!("printf: no {} heap bytes at {:#x} for %h", count, addr);
+                    return Err((NativeErrKind::HeapFault, Exception::HeapReadFault, msg));
+                };
+                res.push_str(&utf16_arg(&bytes, addr, spec)?);
+            }
+            other => {
+                let msg: String = format!("printf: unknown spec '%{}', use %u %d %f %x %s %h or %%", other);
+                return Err((NativeErrKind::InvalidInput, Exception::InvalidDataType, msg));
+            }
+        }
+    }
+    Ok(res)
+}
+
+fn utf16_arg(bytes: &[u8], addr: u64, spec: char) -> Result<String, FormatErr> {
+    String::from_utf16(&u8_slice_to_u16_vec(bytes)).map_err(|_| {
+        let msg: String = format!("printf: %{} string at {:#x} isn't valid UTF-16", spec, addr);
+        (NativeErrKind::InvalidInput, Exception::InvalidDataType, msg)
+    })
+}
+
+/// ncall 0x83
+/// r1 is format string (r2 byte count if r1 is a heap address), r3 is
+/// stream id (1 for stdout, 2 for stderr), arguments are r4, r5.. in order.
+/// Specs read the raw register bits, whatever type the register tracks:
+///   %u uint, %d int, %f float, %x hex uint,
+///   %s data segment string (StrAddr, or the `dslea var 9` address),
+///   %h heap string, takes two registers: address and byte count,
+///   %% a literal `%`.
+/// Prints the text and a newline. Unknown specs, running out of registers
+/// and unreadable strings fault and print nothing
+pub fn ncall_printf(vm: &mut VM) {
+    let stream_id: u64 = vm.registers[3].as_u64_bitwise();
+    let Some(fmt) = read_str(vm, NativeSubsys::Text, "printf") else {
+        return;
+    };
+    let text: String = match format_args(vm, &fmt) {
+        Ok(text) => text,
+        Err((kind, exc, msg)) => {
+            native_fault(vm, NativeError::new(NativeSubsys::Text, kind), exc, &msg);
+            return;
+        }
+    };
+    let written: bool = match vm.output.stream(stream_id) {
+        Some(sink) => writeln!(sink, "{}", text).and_then(|_| sink.flush()).is_ok(),
+        None => {
+            let msg: String = format!("printf: unknown stream {}, 1 is stdout, 2 stderr", stream_id);
+            native_fault(vm, NativeError::new(NativeSubsys::Text, NativeErrKind::InvalidInput), Exception::InvalidDataType, &msg);
+            return;
+        }
+    };
+    if !written {
+        let msg: String = format!("printf: writing stream {} failed", stream_id);
+        native_fault(vm, NativeError::new(NativeSubsys::Text, NativeErrKind::Other), Exception::NativeFault, &msg);
+    }
+}
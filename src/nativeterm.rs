@@ -0,0 +1,276 @@
+use std::{
+    fmt,
+    io::{IsTerminal, Write},
+};
+
+use crate::{
+    exceptions::Exception,
+    nativeerr::{native_fault, NativeErrKind, NativeError, NativeSubsys},
+    registers::Register,
+    vm::{RegTypes, VM},
+};
+
+// Terminal control ncalls for TUI programs: cursor, screen, colors, size and
+// unbuffered key input. Escape sequences go to the guest stdout sink, but only
+// when the host stdout is a terminal (`--term-ansi` overrides the check):
+// otherwise the drawing calls write nothing and return 0 in r0, so output
+// piped into a file stays plain text. Raw mode turns off line buffering and
+// echo of the terminal on stdin, the VM leaves it when dropped.
+
+/// Color value of `term_color` that restores the terminal default
+pub const COLOR_DEFAULT: u64 = 256;
+/// Terminal size when neither the terminal nor COLUMNS/LINES tell it
+const FALLBACK_SIZE: (u64, u64) = (80, 24);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnsiMode {
+    Auto, // when stdout is a terminal
+    Always,
+    Never,
+}
+
+impl AnsiMode {
+    pub fn from_name(name: &str) -> Option<AnsiMode> {
+        match name {
+            "auto" => Some(AnsiMode::Auto),
+            "always" => Some(AnsiMode::Always),
+            "never" => Some(AnsiMode::Never),
+            _ => None,
+        }
+    }
+}
+
+pub struct TermState {
+    pub ansi: bool, // drawing calls emit escape sequences
+    #[cfg(unix)]
+    saved: Option<libc::termios>, // stdin settings before raw mode
+}
+
+impl TermState {
+    pub fn new(mode: AnsiMode) -> TermState {
+        TermState {
+            ansi: match mode {
+                AnsiMode::Auto => std::io::stdout().is_terminal(),
+                AnsiMode::Always => true,
+                AnsiMode::Never => false,
+            },
+            #[cfg(unix)]
+            saved: None,
+        }
+    }
+
+    pub fn is_raw(&self) -> bool {
+        #[cfg(unix)]
+        return self.saved.is_some();
+        #[cfg(not(unix))]
+        return false;
+    }
+
+    /// Turns raw mode of stdin on or off, false when stdin isn't a terminal
+    #[cfg(unix)]
+    fn set_raw(&mut self, on: bool) -> bool {
+        if !std::io::stdin().is_terminal() {
+            return false;
+        }
+        match (on, self.saved) {
+            (true, None) => {
+                // SAFETY: termios is plain data filled by tcgetattr, fd 0 is open
+                let mut cur: libc::termios = unsafe { std::mem::zeroed() };
+                if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut cur) } != 0 {
+                    return false;
+                }
+                let mut raw: libc::termios = cur;
+                raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+                raw.c_cc[libc::VMIN] = 1;
+                raw.c_cc[libc::VTIME] = 0;
+                if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &raw) } != 0 {
+                    return false;
+                }
+                self.saved = Some(cur);
+            }
+            (false, Some(saved)) => {
+                unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &saved) };
+                self.saved = None;
+            }
+            _ => {}
+        }
+        true
+    }
+
+    #[cfg(not(unix))]
+    fn set_raw(&mut self, _on: bool) -> bool {
+        false
+    }
+}
+
+impl Drop for TermState {
+    fn drop(&mut self) {
+        self.set_raw(false);
+    }
+}
+
+impl fmt::Debug for TermState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TermState {{ ansi: {}, raw: {} }}", self.ansi, self.is_raw())
+    }
+}
+
+/// Writes an escape sequence to the guest stdout, r0 = 1 if it was written
+fn emit(vm: &mut VM, seq: &str) {
+    let mut written: u64 = 0;
+    if vm.term.ansi {
+        let res = vm.output.stdout.write_all(seq.as_bytes()).and_then(|_| vm.output.stdout.flush());
+        match res {
+            Ok(()) => written = 1,
+            Err(e) => {
+                let err: NativeError = NativeError::from_io(NativeSubsys::Term, &e);
+                native_fault(vm, err, Exception::NativeFault, &format!("Terminal write failed: {}", e));
+            }
+        }
+    }
+    vm.registers[0] = Register::uint(written);
+    vm.reg_types[0] = RegTypes::uint64;
+}
+
+fn bad_arg(vm: &mut VM, msg: &str) {
+    native_fault(vm, NativeError::new(NativeSubsys::Term, NativeErrKind::InvalidInput), Exception::InvalidDataType, msg);
+}
+
+/// ncall 0x50
+/// r1 is row, r2 is column, both from 0.
+/// Moves the cursor there
+pub fn ncall_term_goto(vm: &mut VM) {
+    let row: u64 = vm.registers[1].as_u64();
+    let col: u64 = vm.registers[2].as_u64();
+    emit(vm, &format!("\x1b[{};{}H", row.saturating_add(1), col.saturating_add(1)));
+}
+
+/// ncall 0x51
+/// r1 is what to clear: 0 - the screen, the cursor goes to 0, 0;
+/// 1 - the cursor line
+pub fn ncall_term_clear(vm: &mut VM) {
+    match vm.registers[1].as_u64() {
+        0 => emit(vm, "\x1b[2J\x1b[H"),
+        1 => emit(vm, "\x1b[2K"),
+        other => bad_arg(vm, &format!("term_clear: unknown mode {}", other)),
+    }
+}
+
+/// ncall 0x52
+/// r1 is foreground, r2 is background color: 0-255 from the 256 color
+/// palette (0-15 are the basic ones), 256 - terminal default
+pub fn ncall_term_color(vm: &mut VM) {
+    let fg: u64 = vm.registers[1].as_u64();
+    let bg: u64 = vm.registers[2].as_u64();
+    if (fg > COLOR_DEFAULT) || (bg > COLOR_DEFAULT) {
+        bad_arg(vm, &format!("term_color: colors are 0-255 or 256 for default, got {} and {}", fg, bg));
+        return;
+    }
+    let part = |val: u64, base: u64| match val {
+        COLOR_DEFAULT => format!("{}", base + 1),
+        v => format!("{};5;{}", base, v),
+    };
+    emit(vm, &format!("\x1b[{};{}m", part(fg, 38), part(bg, 48)));
+}
+
+/// ncall 0x53
+/// r0 = columns, r1 = rows of the terminal; without one COLUMNS and LINES
+/// or 80x24
+pub fn ncall_term_size(vm: &mut VM) {
+    let (cols, rows) = term_size();
+    vm.registers[0] = Register::uint(cols);
+    vm.reg_types[0] = RegTypes::uint64;
+    vm.registers[1] = Register::uint(rows);
+    vm.reg_types[1] = RegTypes::uint64;
+}
+
+fn term_size() -> (u64, u64) {
+    #[cfg(unix)]
+    {
+        // SAFETY: winsize is plain data filled by the ioctl
+        let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+        if (unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws) } == 0) && (ws.ws_col > 0) {
+            return (ws.ws_col as u64, ws.ws_row as u64);
+        }
+    }
+    let env_num = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+    match (env_num("COLUMNS"), env_num("LINES")) {
+        (Some(cols), Some(rows)) => (cols, rows),
+        _ => FALLBACK_SIZE,
+    }
+}
+
+/// ncall 0x54
+/// r1 is 0 to hide the cursor, 1 to show it
+pub fn ncall_term_cursor(vm: &mut VM) {
+    match vm.registers[1].as_u64() {
+        0 => emit(vm, "\x1b[?25l"),
+        _ => emit(vm, "\x1b[?25h"),
+    }
+}
+
+/// ncall 0x55
+/// r1 is 1 to turn raw mode on (keys arrive one by one, without echo),
+/// 0 to turn it off. r0 = 1 if stdin is a terminal and the mode changed,
+/// 0 if it isn't one: keys then arrive as the host delivers them
+pub fn ncall_term_raw(vm: &mut VM) {
+    let on: bool = vm.registers[1].as_u64() != 0;
+    let changed: bool = vm.term.set_raw(on);
+    vm.registers[0] = Register::uint(changed as u64);
+    vm.reg_types[0] = RegTypes::uint64;
+}
+
+/// ncall 0x56
+/// r1 is how long to wait in ms, u64 max waits until a key comes.
+/// r0 = next byte of stdin (escape sequences of special keys come byte by
+/// byte), -1 if there was none in time or stdin is closed
+pub fn ncall_term_key(vm: &mut VM) {
+    let wait_ms: u64 = vm.registers[1].as_u64();
+    let key: i64 = match read_key(wait_ms) {
+        Ok(Some(b)) => b as i64,
+        Ok(None) => -1,
+        Err(e) => {
+            let err: NativeError = NativeError::from_io(NativeSubsys::Term, &e);
+            native_fault(vm, err, Exception::NativeFault, &format!("Key read failed: {}", e));
+            return;
+        }
+    };
+    vm.registers[0] = Register::int(key);
+    vm.reg_types[0] = RegTypes::int64;
+}
+
+/// One byte straight from fd 0, bypassing the stdin buffer so a poll
+/// doesn't miss bytes it already took
+#[cfg(unix)]
+pub fn read_key(wait_ms: u64) -> std::io::Result<Option<u8>> {
+    if wait_ms != u64::MAX {
+        let mut fds = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout: i32 = wait_ms.min(i32::MAX as u64) as i32;
+        // SAFETY: one valid pollfd
+        match unsafe { libc::poll(&mut fds, 1, timeout) } {
+            0 => return Ok(None),
+            n if n < 0 => return Err(std::io::Error::last_os_error()),
+            _ => {}
+        }
+    }
+    let mut byte: u8 = 0;
+    // SAFETY: reads at most one byte into `byte`
+    match unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) } {
+        1 => Ok(Some(byte)),
+        0 => Ok(None),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn read_key(_wait_ms: u64) -> std::io::Result<Option<u8>> {
+    let mut byte: [u8; 1] = [0];
+    match std::io::Read::read(&mut std::io::stdin(), &mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
@@ -0,0 +1,372 @@
+// Opcode unit tests: every arithmetic, compare, bitwise, conversion, set,
+// cmov and jump opcode swept over operand tables, checking results, all
+// four flags and that ip lands on the halt. Cases are built and run with
+// testsupport.rs, a test fails listing each of its failing cases.
+
+use crate::exceptions::Exception;
+use crate::registers::Register;
+use crate::selftest::{case, expect, Case, Code};
+use crate::testsupport::{
+    after_compare, all_flags, binop, check, compare_code, holds, sign_flags, unop, zero_flag, Flags, NO_FLAGS, RELATIONS,
+};
+
+const UINT_PAIRS: [(u64, u64); 8] = [
+    (0, 0),
+    (1, 0),
+    (7, 5),
+    (5, 5),
+    (100, 7),
+    (1 << 32, 3),
+    (u64::MAX / 2, 2),
+    (123_456_789, 1000),
+];
+
+const INT_PAIRS: [(i64, i64); 8] = [
+    (0, 0),
+    (-3, 5),
+    (5, -3),
+    (-7, -7),
+    (100, 7),
+    (-100, 7),
+    (i64::MIN / 2, 2),
+    (1 << 40, -3),
+];
+
+const FLOAT_PAIRS: [(f64, f64); 6] = [(0.0, 1.0), (1.5, 2.25), (-2.5, 0.5), (7.5, 2.5), (1e10, -3.0), (0.1, 0.2)];
+
+#[test]
+fn uint_arith() {
+    let mut res: Vec<Case> = Vec::new();
+    for (a, b) in UINT_PAIRS {
+        let (ra, rb) = (Register::uint(a), Register::uint(b));
+        res.push(binop("uint ops", "uadd", ra, rb, Ok(Register::uint(a + b)), NO_FLAGS));
+        res.push(binop("uint ops", "umul", ra, rb, Ok(Register::uint(a * b)), NO_FLAGS));
+        res.push(binop("uint ops", "usub", ra, rb, Ok(Register::uint(a - b)), zero_flag(a == b)));
+        match b {
+            0 => res.push(binop("uint ops", "udiv", ra, rb, Err(Exception::ZeroDivision), NO_FLAGS)),
+            _ => {
+                res.push(binop("uint ops", "udiv", ra, rb, Ok(Register::uint(a / b)), NO_FLAGS));
+                res.push(binop("uint ops", "urem", ra, rb, Ok(Register::uint(a % b)), NO_FLAGS));
+            }
+        }
+    }
+    check(res);
+}
+
+#[test]
+fn uint_compare() {
+    let mut res: Vec<Case> = Vec::new();
+    for (a, b) in UINT_PAIRS {
+        for (x, y) in [(a, b), (b, a)] {
+            let fl: Flags = sign_flags(x == y, x < y);
+            res.push(binop("uint ops", "ucmp", Register::uint(x), Register::uint(y), Ok(Register::uint(x)), fl));
+        }
+    }
+    check(res);
+}
+
+#[test]
+fn uint_unary() {
+    let mut res: Vec<Case> = Vec::new();
+    for a in [0u64, 1, 41, u64::MAX - 1] {
+        res.push(unop("uint ops", "uinc", Register::uint(a), Ok(Register::uint(a + 1)), NO_FLAGS));
+    }
+    for a in [1u64, 2, 100, u64::MAX] {
+        res.push(unop("uint ops", "udec", Register::uint(a), Ok(Register::uint(a - 1)), zero_flag(a == 1)));
+    }
+    for a in [0u64, 1, 2, 15, 16, 17, 1 << 40] {
+        res.push(unop("uint ops", "usqrt", Register::uint(a), Ok(Register::uint(a.isqrt())), zero_flag(a == 0)));
+    }
+    check(res);
+}
+
+#[test]
+fn uint_pow() {
+    let mut res: Vec<Case> = Vec::new();
+    for (a, b) in [(2u64, 10u64), (3, 0), (0, 5), (10, 3), (1, 63)] {
+        let want: u64 = a.pow(b as u32);
+        res.push(binop("uint ops", "upow", Register::uint(a), Register::uint(b), Ok(Register::uint(want)), zero_flag(want == 0)));
+    }
+    check(res);
+}
+
+#[test]
+fn uint_operand_type() {
+    let code: Code = Code::new().uload(1, 1).fload(2, 1.0).ins("uadd", &[1, 2]);
+    let at: u64 = code.here();
+    check(vec![case("uint ops", "uadd uint float", code.halt(), expect(&[], &[]).at(at).raises(Exception::IncorrectRegType))]);
+}
+
+#[test]
+fn int_arith() {
+    let mut res: Vec<Case> = Vec::new();
+    for (a, b) in INT_PAIRS {
+        let (ra, rb) = (Register::int(a), Register::int(b));
+        res.push(binop("int ops", "iadd", ra, rb, Ok(Register::int(a + b)), NO_FLAGS));
+        res.push(binop("int ops", "isub", ra, rb, Ok(Register::int(a - b)), NO_FLAGS));
+        res.push(binop("int ops", "imul", ra, rb, Ok(Register::int(a * b)), NO_FLAGS));
+        if b != 0 {
+            res.push(binop("int ops", "idiv", ra, rb, Ok(Register::int(a / b)), NO_FLAGS));
+            res.push(binop("int ops", "irem", ra, rb, Ok(Register::int(a % b)), NO_FLAGS));
+        }
+    }
+    check(res);
+}
+
+#[test]
+fn int_compare() {
+    let mut res: Vec<Case> = Vec::new();
+    for (a, b) in INT_PAIRS {
+        for (x, y) in [(a, b), (b, a)] {
+            let fl: Flags = sign_flags(x == y, x < y);
+            res.push(binop("int ops", "icmp", Register::int(x), Register::int(y), Ok(Register::int(x)), fl));
+        }
+    }
+    check(res);
+}
+
+#[test]
+fn int_unary() {
+    let mut res: Vec<Case> = Vec::new();
+    for a in [-1i64, 0, 1, -100, 41] {
+        let (inc, dec): (i64, i64) = (a + 1, a - 1);
+        res.push(unop("int ops", "iinc", Register::int(a), Ok(Register::int(inc)), sign_flags(inc == 0, inc < 0)));
+        res.push(unop("int ops", "idec", Register::int(a), Ok(Register::int(dec)), sign_flags(dec == 0, dec < 0)));
+        res.push(unop("int ops", "iabs", Register::int(a), Ok(Register::int(a.abs())), zero_flag(a == 0)));
+        res.push(unop("int ops", "ineg", Register::int(a), Ok(Register::int(-a)), sign_flags(a == 0, -a < 0)));
+    }
+    for a in [0i64, 1, 15, 16, 99] {
+        res.push(unop("int ops", "isqrt", Register::int(a), Ok(Register::int(a.isqrt())), zero_flag(a == 0)));
+    }
+    res.push(unop("int ops", "isqrt", Register::int(-4), Err(Exception::NegativeSqrt), NO_FLAGS));
+    check(res);
+}
+
+#[test]
+fn int_pow() {
+    let mut res: Vec<Case> = Vec::new();
+    for (a, b) in [(-2i64, 3i64), (-2, 4), (5, 0), (0, 3), (7, 2)] {
+        let want: i64 = a.pow(b as u32);
+        res.push(binop("int ops", "ipow", Register::int(a), Register::int(b), Ok(Register::int(want)), sign_flags(want == 0, want < 0)));
+    }
+    check(res);
+}
+
+#[test]
+fn float_arith() {
+    let mut res: Vec<Case> = Vec::new();
+    for (a, b) in FLOAT_PAIRS {
+        let (ra, rb) = (Register::float(a), Register::float(b));
+        res.push(binop("float ops", "fadd", ra, rb, Ok(Register::float(a + b)), NO_FLAGS));
+        res.push(binop("float ops", "fsub", ra, rb, Ok(Register::float(a - b)), NO_FLAGS));
+        res.push(binop("float ops", "fmul", ra, rb, Ok(Register::float(a * b)), NO_FLAGS));
+        res.push(binop("float ops", "fdiv", ra, rb, Ok(Register::float(a / b)), NO_FLAGS));
+        res.push(binop("float ops", "frem", ra, rb, Ok(Register::float(a % b)), NO_FLAGS));
+    }
+    res.push(binop("float ops", "fdiv", Register::float(1.0), Register::float(0.0), Err(Exception::ZeroDivision), NO_FLAGS));
+    check(res);
+}
+
+#[test]
+fn float_compare() {
+    let mut res: Vec<Case> = Vec::new();
+    for (a, b) in FLOAT_PAIRS {
+        for (x, y) in [(a, b), (b, a), (a, a)] {
+            let fl: Flags = sign_flags(x == y, x < y);
+            res.push(binop("float ops", "fcmp", Register::float(x), Register::float(y), Ok(Register::float(x)), fl));
+        }
+    }
+    check(res);
+}
+
+#[test]
+fn float_unary() {
+    let mut res: Vec<Case> = Vec::new();
+    for a in [-1.0f64, 0.0, 2.5, -0.5, 1e6] {
+        let (inc, dec): (f64, f64) = (a + 1.0, a - 1.0);
+        res.push(unop("float ops", "finc", Register::float(a), Ok(Register::float(inc)), sign_flags(inc == 0.0, inc < 0.0)));
+        res.push(unop("float ops", "fdec", Register::float(a), Ok(Register::float(dec)), sign_flags(dec == 0.0, dec < 0.0)));
+        res.push(unop("float ops", "fabs", Register::float(a), Ok(Register::float(a.abs())), zero_flag(a == 0.0)));
+        res.push(unop("float ops", "fneg", Register::float(a), Ok(Register::float(-a)), sign_flags(a == 0.0, -a < 0.0)));
+    }
+    for a in [0.0f64, 2.25, 1e6] {
+        res.push(unop("float ops", "fsqrt", Register::float(a), Ok(Register::float(a.sqrt())), zero_flag(a == 0.0)));
+    }
+    res.push(unop("float ops", "fsqrt", Register::float(-1.0), Err(Exception::NegativeSqrt), NO_FLAGS));
+    check(res);
+}
+
+#[test]
+fn bitwise_logic() {
+    let mut res: Vec<Case> = Vec::new();
+    let pairs: [(u64, u64); 6] = [(0, 0), (0b1100, 0b1010), (u64::MAX, 1), (0xF0F0, 0x0F0F), (1 << 63, 1 << 63), (5, 0)];
+    for (a, b) in pairs {
+        let (ra, rb) = (Register::uint(a), Register::uint(b));
+        res.push(binop("bitwise ops", "or", ra, rb, Ok(Register::uint(a | b)), zero_flag(a | b == 0)));
+        res.push(binop("bitwise ops", "and", ra, rb, Ok(Register::uint(a & b)), zero_flag(a & b == 0)));
+        res.push(binop("bitwise ops", "xor", ra, rb, Ok(Register::uint(a ^ b)), zero_flag(a ^ b == 0)));
+        res.push(binop("bitwise ops", "test", ra, rb, Ok(ra), zero_flag(a & b == 0)));
+        res.push(unop("bitwise ops", "not", ra, Ok(Register::uint(!a)), zero_flag(!a == 0)));
+        res.push(unop("bitwise ops", "lnot", ra, Ok(Register::uint((a == 0) as u64)), zero_flag(a != 0)));
+    }
+    check(res);
+}
+
+#[test]
+fn bitwise_shifts() {
+    let mut res: Vec<Case> = Vec::new();
+    for (a, b) in [(3u64, 4u64), (1, 63), (0x80, 7), (12345, 0), (u64::MAX, 8)] {
+        let (ra, rb) = (Register::uint(a), Register::uint(b));
+        res.push(binop("bitwise ops", "shl", ra, rb, Ok(Register::uint(a << b)), NO_FLAGS));
+        res.push(binop("bitwise ops", "shr", ra, rb, Ok(Register::uint(a >> b)), NO_FLAGS));
+    }
+    check(res);
+}
+
+/// One test per instruction run right after a compare by `after_compare`:
+/// `sets` ones take the flags from their result alone, none of the
+/// compare's stay; `keeps` ones leave the compare's ZF and NF as they were
+macro_rules! after_compare_tests {
+    ($mode:ident: $($test:ident => $name:literal $a:expr, $b:expr => $want:expr;)*) => {
+        $(
+            #[test]
+            fn $test() {
+                check(after_compare($name, $a, $b, $want, after_compare_tests!(@sets $mode)));
+            }
+        )*
+    };
+    (@sets sets) => { true };
+    (@sets keeps) => { false };
+}
+
+after_compare_tests! { sets:
+    usub_sets_flags => "usub" Register::uint(7), Some(Register::uint(2)) => Register::uint(5);
+    usub_zero_sets_flags => "usub" Register::uint(5), Some(Register::uint(5)) => Register::uint(0);
+    udec_sets_flags => "udec" Register::uint(6), None => Register::uint(5);
+    usqrt_sets_flags => "usqrt" Register::uint(36), None => Register::uint(6);
+    upow_sets_flags => "upow" Register::uint(2), Some(Register::uint(3)) => Register::uint(8);
+    iinc_sets_flags => "iinc" Register::int(4), None => Register::int(5);
+    idec_sets_flags => "idec" Register::int(-4), None => Register::int(-5);
+    iabs_sets_flags => "iabs" Register::int(-5), None => Register::int(5);
+    ineg_sets_flags => "ineg" Register::int(5), None => Register::int(-5);
+    isqrt_sets_flags => "isqrt" Register::int(36), None => Register::int(6);
+    ipow_sets_flags => "ipow" Register::int(-2), Some(Register::int(3)) => Register::int(-8);
+    finc_sets_flags => "finc" Register::float(1.5), None => Register::float(2.5);
+    fdec_sets_flags => "fdec" Register::float(1.0), None => Register::float(0.0);
+    fabs_sets_flags => "fabs" Register::float(-2.5), None => Register::float(2.5);
+    fneg_sets_flags => "fneg" Register::float(2.5), None => Register::float(-2.5);
+    fsqrt_sets_flags => "fsqrt" Register::float(6.25), None => Register::float(2.5);
+    fpow_sets_flags => "fpow" Register::float(-2.0), Some(Register::float(3.0)) => Register::float(-8.0);
+    or_sets_flags => "or" Register::uint(4), Some(Register::uint(1)) => Register::uint(5);
+    and_sets_flags => "and" Register::uint(7), Some(Register::uint(5)) => Register::uint(5);
+    xor_sets_flags => "xor" Register::uint(5), Some(Register::uint(5)) => Register::uint(0);
+    xor_int_sets_flags => "xor" Register::int(6), Some(Register::int(-3)) => Register::int(-5);
+    test_sets_flags => "test" Register::uint(7), Some(Register::uint(5)) => Register::uint(7);
+    not_sets_flags => "not" Register::uint(5), None => Register::uint(!5);
+    lnot_sets_flags => "lnot" Register::uint(0), None => Register::uint(1);
+}
+
+// add/mul/isub deliberately don't touch ZF and NF (VM::update_flags)
+after_compare_tests! { keeps:
+    uadd_keeps_flags => "uadd" Register::uint(2), Some(Register::uint(3)) => Register::uint(5);
+    umul_keeps_flags => "umul" Register::uint(0), Some(Register::uint(3)) => Register::uint(0);
+    iadd_keeps_flags => "iadd" Register::int(2), Some(Register::int(-7)) => Register::int(-5);
+    imul_keeps_flags => "imul" Register::int(4), Some(Register::int(0)) => Register::int(0);
+    isub_keeps_flags => "isub" Register::int(5), Some(Register::int(5)) => Register::int(0);
+}
+
+#[test]
+fn conversions() {
+    let mut res: Vec<Case> = Vec::new();
+    for a in [0u64, 7, 1 << 53, u64::MAX] {
+        res.push(unop("convert ops", "utoi", Register::uint(a), Ok(Register::int(a as i64)), NO_FLAGS));
+        res.push(unop("convert ops", "utof", Register::uint(a), Ok(Register::float(a as f64)), NO_FLAGS));
+        res.push(unop("convert ops", "utop", Register::uint(a), Ok(Register::address(a)), NO_FLAGS));
+    }
+    for a in [-1i64, 0, 5, i64::MIN] {
+        res.push(unop("convert ops", "itou", Register::int(a), Ok(Register::uint(a as u64)), NO_FLAGS));
+        res.push(unop("convert ops", "itof", Register::int(a), Ok(Register::float(a as f64)), NO_FLAGS));
+    }
+    for a in [2.9f64, -7.9, 0.0, 1e12] {
+        res.push(unop("convert ops", "ftou", Register::float(a), Ok(Register::uint(a as u64)), NO_FLAGS));
+        res.push(unop("convert ops", "ftoi", Register::float(a), Ok(Register::int(a as i64)), NO_FLAGS));
+    }
+    check(res);
+}
+
+#[test]
+fn set_after_compare() {
+    let mut res: Vec<Case> = Vec::new();
+    for domain in ["ucmp", "icmp", "fcmp"] {
+        for (rel, a, b) in RELATIONS {
+            let fl: Flags = sign_flags(rel == "eq", rel == "lt");
+            for cond in ["e", "ne", "l", "g", "ge", "le"] {
+                let name: String = format!("set{}", cond);
+                let code: Code = compare_code(domain, a, b).ins(&name, &[3]);
+                let at: u64 = code.here();
+                let want: Register = Register::uint(holds(cond, rel) as u64);
+                res.push(case("set ops", format!("{} after {} {}", name, domain, rel), code.halt(), expect(&[(3, want)], &all_flags(fl)).at(at)));
+            }
+        }
+    }
+    check(res);
+}
+
+#[test]
+fn cmov_after_compare() {
+    let mut res: Vec<Case> = Vec::new();
+    for domain in ["ucmp", "icmp", "fcmp"] {
+        for (rel, a, b) in RELATIONS {
+            let fl: Flags = sign_flags(rel == "eq", rel == "lt");
+            for cond in ["z", "l", "g"] {
+                let name: String = format!("cmov{}", cond);
+                let code: Code = compare_code(domain, a, b).uload(4, 111).uload(9, 222).ins(&name, &[4, 9]);
+                let at: u64 = code.here();
+                let want: Register = Register::uint(if holds(cond, rel) { 222 } else { 111 });
+                res.push(case("cmov ops", format!("{} after {} {}", name, domain, rel), code.halt(), expect(&[(4, want)], &all_flags(fl)).at(at)));
+            }
+        }
+    }
+    check(res);
+}
+
+#[test]
+fn jump_after_compare() {
+    let mut res: Vec<Case> = Vec::new();
+    for domain in ["ucmp", "icmp", "fcmp"] {
+        for (rel, a, b) in RELATIONS {
+            let fl: Flags = sign_flags(rel == "eq", rel == "lt");
+            // a taken jump skips `uload r3 1`, both paths end on the same halt
+            for cond in ["z", "nz", "l", "g", "ge", "le"] {
+                let name: String = format!("j{}", cond);
+                let code: Code = compare_code(domain, a, b);
+                let target: u64 = code.here() + 9 + 10;
+                let code: Code = code.ins(&name, &target.to_be_bytes()).uload(3, 1);
+                let want: Register = Register::uint(!holds(cond, rel) as u64);
+                res.push(case("jump ops", format!("{} after {} {}", name, domain, rel), code.halt(), expect(&[(3, want)], &all_flags(fl)).at(target)));
+            }
+        }
+    }
+    check(res);
+}
+
+#[test]
+fn unconditional_jumps() {
+    let code: Code = Code::new();
+    let target: u64 = code.here() + 9 + 10;
+    let jmp: Case = case(
+        "jump ops",
+        "jmp",
+        code.ins("jmp", &target.to_be_bytes()).uload(3, 1).halt(),
+        expect(&[(3, Register::uint(0))], &all_flags(NO_FLAGS)).at(target),
+    );
+    let code: Code = Code::new();
+    let target: u64 = code.here() + 10 + 2 + 10;
+    let jmpr: Case = case(
+        "jump ops",
+        "jmpr",
+        code.uload(5, target).ins("jmpr", &[5]).uload(3, 1).halt(),
+        expect(&[(3, Register::uint(0))], &all_flags(NO_FLAGS)).at(target),
+    );
+    check(vec![jmp, jmpr]);
+}
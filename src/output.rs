@@ -0,0 +1,70 @@
+use std::{
+    fmt,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+// Where guest output goes. Printing ncalls (print, regdump) write through
+// VM::output instead of the host process stdout/stderr, so embedders can
+// capture, redirect or annotate it by swapping in their own sinks.
+
+pub type OutputSink = Box<dyn Write + Send>;
+
+pub struct VmOutput {
+    pub stdout: OutputSink,
+    pub stderr: OutputSink,
+}
+
+impl VmOutput {
+    /// Host process stdout and stderr
+    pub fn stdio() -> VmOutput {
+        VmOutput {
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+        }
+    }
+
+    /// Sink for guest stream `stream_id` (1 - stdout, 2 - stderr)
+    pub fn stream(&mut self, stream_id: u64) -> Option<&mut OutputSink> {
+        match stream_id {
+            1 => Some(&mut self.stdout),
+            2 => Some(&mut self.stderr),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for VmOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("VmOutput")
+    }
+}
+
+/// In-memory sink, clones share the buffer: hand one to the VM, read the
+/// other after the run
+#[derive(Clone, Default)]
+pub struct SharedBuffer {
+    buf: Arc<Mutex<Vec<u8>>>,
+}
+
+impl SharedBuffer {
+    pub fn new() -> SharedBuffer {
+        SharedBuffer::default()
+    }
+
+    /// Everything written so far, lossily decoded
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.buf.lock().unwrap()).to_string()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.buf.lock().unwrap().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Condvar, Mutex,
+};
+
+// Pausing a running VM from the host. The dispatch loop checks the flag
+// between instructions, so a paused VM keeps its whole state (registers,
+// stacks, heap, open handles) and continues from the next instruction.
+// A paused `run()` either blocks until `resume()` (another thread or a
+// signal resumes it) or, with VM::yield_on_pause, returns so embedders
+// can schedule several VMs cooperatively and call `run()` again later.
+
+#[derive(Debug, Default)]
+struct PauseState {
+    paused: AtomicBool,
+    lock: Mutex<()>,
+    resumed: Condvar,
+}
+
+/// Shared pause flag, clones control the same VM
+#[derive(Debug, Clone, Default)]
+pub struct PauseHandle {
+    state: Arc<PauseState>,
+}
+
+impl PauseHandle {
+    pub fn new() -> PauseHandle {
+        PauseHandle::default()
+    }
+
+    /// The VM stops before its next instruction
+    pub fn pause(&self) {
+        self.state.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        let _guard = self.state.lock.lock().unwrap();
+        self.state.paused.store(false, Ordering::SeqCst);
+        self.state.resumed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.load(Ordering::Relaxed)
+    }
+
+    /// Blocks the calling thread while paused
+    pub fn wait_resumed(&self) {
+        let mut guard = self.state.lock.lock().unwrap();
+        while self.state.paused.load(Ordering::SeqCst) {
+            guard = self.state.resumed.wait(guard).unwrap();
+        }
+    }
+}
+
+/// `--pause-signals`: SIGTSTP (Ctrl-Z) pauses the VM, another SIGTSTP or
+/// SIGCONT resumes it. The host process itself keeps running.
+#[cfg(unix)]
+pub fn install_signal_handler(handle: PauseHandle) -> std::io::Result<()> {
+    use signal_hook::{
+        consts::{SIGCONT, SIGTSTP},
+        iterator::Signals,
+    };
+
+    let mut signals = Signals::new([SIGTSTP, SIGCONT])?;
+    std::thread::spawn(move || {
+        for sig in signals.forever() {
+            match (sig, handle.is_paused()) {
+                (SIGTSTP, false) => handle.pause(),
+                (SIGTSTP, true) | (SIGCONT, _) => handle.resume(),
+                _ => {}
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn install_signal_handler(_handle: PauseHandle) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "pause signals are unix only",
+    ))
+}
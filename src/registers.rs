@@ -342,7 +342,57 @@ impl Neg for Register {
     }
 }
 
+// Mixed-type operands of typed instructions (u*, i*, f* arithmetic and
+// cmp). Operands of the same kind, and address with uint, go to the
+// operators above as they are. Any other pair is converted into the domain
+// of the instruction first, or the instruction raises IncorrectRegType and
+// leaves its destination and flags alone:
+//                     | uint domain | int domain     | float domain
+//   uint              | as is       | if <= i64 max  | nearest float
+//   int               | if >= 0     | as is          | nearest float
+//   float             | -           | -              | as is
+//   address, ds_addr, | as is       | -              | -
+//   StrAddr           |             |                |
+// Converted results have the domain type.
+
 impl Register {
+    pub fn kind(&self) -> RegTypes {
+        match self {
+            Register::uint(_) => RegTypes::uint64,
+            Register::int(_) => RegTypes::int64,
+            Register::float(_) => RegTypes::float64,
+            Register::StrAddr(_) => RegTypes::StrAddr,
+            Register::address(_) => RegTypes::address,
+            Register::ds_addr(_) => RegTypes::ds_addr,
+        }
+    }
+
+    /// The value in `domain` (uint64, int64 or float64), None where the
+    /// coercion matrix has no conversion
+    pub fn coerce(self, domain: RegTypes) -> Option<Register> {
+        match (domain, self) {
+            (RegTypes::uint64, Register::int(v)) => u64::try_from(v).ok().map(Register::uint),
+            (RegTypes::uint64, Register::float(_)) => None,
+            (RegTypes::uint64, other) => Some(Register::uint(other.as_u64())),
+            (RegTypes::int64, Register::uint(v)) => i64::try_from(v).ok().map(Register::int),
+            (RegTypes::int64, Register::int(v)) => Some(Register::int(v)),
+            (RegTypes::float64, Register::uint(v)) => Some(Register::float(v as f64)),
+            (RegTypes::float64, Register::int(v)) => Some(Register::float(v as f64)),
+            (RegTypes::float64, Register::float(v)) => Some(Register::float(v)),
+            _ => None,
+        }
+    }
+
+    /// Operands `a`, `b` of an instruction working in `domain`,
+    /// converted when their kinds differ
+    pub fn coerce_pair(a: Register, b: Register, domain: RegTypes) -> Option<(Register, Register)> {
+        match (a.kind(), b.kind()) {
+            (x, y) if x == y => Some((a, b)),
+            (RegTypes::address, RegTypes::uint64) | (RegTypes::uint64, RegTypes::address) => Some((a, b)),
+            _ => Some((a.coerce(domain)?, b.coerce(domain)?)),
+        }
+    }
+
     pub fn from_u64_bits(val: u64, to_type: RegTypes) -> Register {
         match to_type {
             RegTypes::uint64 => Register::uint(val),
@@ -0,0 +1,113 @@
+use std::{fs, io::Write, time::Duration};
+
+use serde::Serialize;
+
+use crate::{heap::HeapStats, vm::VM};
+
+// `--json-status`: one JSON object describing a finished run, written apart
+// from guest output so pipelines can read both. The target is a file path or
+// the number of a file descriptor the parent opened (1 and 2 are stdout and
+// stderr, other numbers need a unix host). Runs the VM refuses to start
+// (bad arguments, missing ncalls) report on stderr only.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatusTarget {
+    Fd(i32),
+    File(String),
+}
+
+impl StatusTarget {
+    pub fn parse(val: &str) -> StatusTarget {
+        match val.parse::<i32>() {
+            Ok(fd) if fd >= 0 => StatusTarget::Fd(fd),
+            _ => StatusTarget::File(val.to_string()),
+        }
+    }
+
+    pub fn write(&self, json: &str) -> std::io::Result<()> {
+        let line: String = format!("{}\n", json);
+        match self {
+            StatusTarget::File(path) => fs::write(path, line),
+            StatusTarget::Fd(1) => std::io::stdout().write_all(line.as_bytes()),
+            StatusTarget::Fd(2) => std::io::stderr().write_all(line.as_bytes()),
+            #[cfg(unix)]
+            StatusTarget::Fd(fd) => {
+                use std::os::fd::{BorrowedFd, OwnedFd};
+                // SAFETY: the descriptor belongs to the caller, validated by
+                // fcntl and duplicated so ours can be closed
+                if unsafe { libc::fcntl(*fd, libc::F_GETFD) } < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                let owned: OwnedFd = unsafe { BorrowedFd::borrow_raw(*fd) }.try_clone_to_owned()?;
+                let mut file: fs::File = fs::File::from(owned);
+                file.write_all(line.as_bytes())
+            }
+            #[cfg(not(unix))]
+            StatusTarget::Fd(fd) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("fd {} isn't stdout or stderr", fd),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Sizes {
+    pub ram: usize,
+    pub stack: usize,
+    pub heap: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunStats {
+    pub instructions: u64,
+    pub elapsed_us: u64,
+    pub heap_used: u64,
+    pub heap_blocks: u64,
+    pub allocs: u64,
+    pub frees: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunStatus {
+    pub voxvm: &'static str,
+    pub program: String,
+    pub format: &'static str, // "vve" or "vvr"
+    pub vve_version: Option<u16>,
+    pub sizes: Sizes,
+    pub status: &'static str, // "halted", "aborted" (`abort`) or "exception_limit" (--max-pending-exc)
+    pub exceptions: Vec<String>,
+    pub stats: RunStats,
+}
+
+impl RunStatus {
+    pub fn collect(vm: &VM, program: String, format: &'static str, vve_version: Option<u16>, sizes: Sizes, elapsed: Duration) -> RunStatus {
+        let heap: HeapStats = vm.heap.stats();
+        let over_limit: bool = vm.max_pending_exc.is_some_and(|max| vm.exceptions_active.len() > max);
+        RunStatus {
+            voxvm: env!("CARGO_PKG_VERSION"),
+            program: program,
+            format: format,
+            vve_version: vve_version,
+            sizes: sizes,
+            status: match (vm.aborted, over_limit) {
+                (true, _) => "aborted",
+                (false, true) => "exception_limit",
+                (false, false) => "halted",
+            },
+            exceptions: vm.exceptions_active.iter().map(|e| format!("{:?}", e)).collect(),
+            stats: RunStats {
+                instructions: vm.instr_count,
+                elapsed_us: elapsed.as_micros() as u64,
+                heap_used: heap.used,
+                heap_blocks: heap.live_blocks,
+                allocs: heap.alloc_count,
+                frees: heap.free_count,
+            },
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
@@ -0,0 +1,162 @@
+use std::ops::Range;
+
+use crate::{
+    arena::is_arena_ptr,
+    exceptions::Exception,
+    memcap::mem_reserve,
+    memprof::record_heap,
+    nativemmap::is_mmap_ptr,
+    registers::Register,
+    vm::{RegTypes, VM},
+};
+
+// Scratch buffers: `salloc` reserves zeroed bytes in the scratch arena of the
+// running context, without heap search and GC bookkeeping. A buffer lives
+// until the function that allocated it returns, `sfree` releases it earlier
+// together with every buffer allocated after it (the arena is a stack).
+// Scratch pointers are SCRATCH_BASE + arena offset, `load`, `store`,
+// `load32`, `store32` and `memcpy` take them like heap pointers, other
+// instructions and ncalls only take heap pointers. The GC doesn't scan
+// scratch: a heap pointer kept only there doesn't keep its block alive.
+// Every coroutine has its own arena, scratch pointers don't cross them.
+
+/// Scratch pointers start here, heap pointers are always below
+pub const SCRATCH_BASE: u64 = 1 << 62;
+/// Arena size limit of one context
+pub const SCRATCH_MAX: usize = 1 << 20;
+
+#[derive(Debug, Default)]
+pub struct Scratch {
+    bytes: Vec<u8>,
+    bufs: Vec<(usize, usize)>, // (call depth, arena offset), in allocation order
+}
+
+impl Scratch {
+    pub fn new() -> Scratch {
+        Scratch::default()
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        self.bytes.len() as u64
+    }
+
+    fn range(&self, ptr: u64, count: u64) -> Option<Range<usize>> {
+        let start: usize = ptr.checked_sub(SCRATCH_BASE)? as usize;
+        let end: usize = start.checked_add(count as usize)?;
+        (end <= self.bytes.len()).then_some(start..end)
+    }
+
+    pub fn read(&self, ptr: u64, count: u64) -> Result<Vec<u8>, ()> {
+        match self.range(ptr, count) {
+            Some(r) => Ok(self.bytes[r].to_vec()),
+            None => Err(()),
+        }
+    }
+
+    pub fn write(&mut self, ptr: u64, data: &[u8]) -> Result<(), ()> {
+        match self.range(ptr, data.len() as u64) {
+            Some(r) => {
+                self.bytes[r].copy_from_slice(data);
+                Ok(())
+            }
+            None => Err(()),
+        }
+    }
+
+    /// Releases buffers of frames deeper than `depth`
+    pub fn release_frames(&mut self, depth: usize) {
+        let keep: usize = self.bufs.iter().position(|(d, _)| *d > depth).unwrap_or(self.bufs.len());
+        if let Some((_, start)) = self.bufs.get(keep) {
+            self.bytes.truncate(*start);
+        }
+        self.bufs.truncate(keep);
+    }
+}
+
+pub fn is_scratch_ptr(ptr: u64) -> bool {
+    ptr >= SCRATCH_BASE
+}
+
+/// Heap pointers are below scratch, mapped file and arena pointers
+pub fn is_heap_ptr(ptr: u64) -> bool {
+    !is_scratch_ptr(ptr) && !is_mmap_ptr(ptr) && !is_arena_ptr(ptr)
+}
+
+/// Reads `count` bytes at a heap, scratch, mapped file or arena pointer
+pub fn mem_read(vm: &mut VM, ptr: u64, count: u64) -> Result<Vec<u8>, ()> {
+    if is_scratch_ptr(ptr) {
+        return vm.scratch.read(ptr, count);
+    }
+    if is_mmap_ptr(ptr) {
+        return vm.mmaps.read(ptr, count);
+    }
+    if is_arena_ptr(ptr) {
+        return vm.arenas.read(ptr, count);
+    }
+    record_heap(vm, ptr, false);
+    vm.heap.read(ptr, count)
+}
+
+/// Writes `data` at a heap, scratch, mapped file or arena pointer
+pub fn mem_write(vm: &mut VM, ptr: u64, data: Vec<u8>) -> Result<(), ()> {
+    if is_scratch_ptr(ptr) {
+        return vm.scratch.write(ptr, &data);
+    }
+    if is_mmap_ptr(ptr) {
+        return vm.mmaps.write(ptr, &data);
+    }
+    if is_arena_ptr(ptr) {
+        return vm.arenas.write(ptr, &data);
+    }
+    record_heap(vm, ptr, true);
+    vm.heap.write(ptr, data)
+}
+
+pub fn op_salloc(vm: &mut VM) {
+    // 0xAC, size: 3
+    // salloc Rdest Rsize - Rdest = pointer to Rsize zeroed scratch bytes,
+    // freed when the current function returns
+    let r_dest_ind: usize = vm.memory[vm.ip + 1] as usize;
+    let r_size_ind: usize = vm.memory[vm.ip + 2] as usize;
+    let size: u64 = vm.registers[r_size_ind].as_u64();
+
+    let start: usize = vm.scratch.bytes.len();
+    if size > (SCRATCH_MAX - start) as u64 {
+        vm.exceptions_active.push(Exception::HeapAllocationFault);
+        vm.ip += 3;
+        return;
+    }
+    if !mem_reserve(vm, size) {
+        vm.ip += 3;
+        return;
+    }
+    let depth: usize = vm.call_stack.stack.len();
+    vm.scratch.bytes.resize(start + size as usize, 0);
+    vm.scratch.bufs.push((depth, start));
+    vm.registers[r_dest_ind] = Register::uint(SCRATCH_BASE + start as u64);
+    vm.reg_types[r_dest_ind] = RegTypes::uint64;
+    vm.ip += 3;
+}
+
+pub fn op_sfree(vm: &mut VM) {
+    // 0xAD, size: 2
+    // sfree Rptr - frees the scratch buffer at Rptr and every buffer
+    // allocated after it. Only buffers of the current function
+    let r_ptr_ind: usize = vm.memory[vm.ip + 1] as usize;
+    let ptr: u64 = vm.registers[r_ptr_ind].as_u64();
+    let depth: usize = vm.call_stack.stack.len();
+
+    let found: Option<usize> = vm
+        .scratch
+        .bufs
+        .iter()
+        .position(|(d, start)| (*d == depth) && (SCRATCH_BASE + *start as u64 == ptr));
+    match found {
+        Some(ind) => {
+            vm.scratch.bytes.truncate(vm.scratch.bufs[ind].1);
+            vm.scratch.bufs.truncate(ind);
+        }
+        None => vm.exceptions_active.push(Exception::HeapFreeFault),
+    }
+    vm.ip += 2;
+}
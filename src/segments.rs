@@ -0,0 +1,91 @@
+// Main memory layout descriptors.
+// Memory is laid out as: [code][rodata][data][dynamically loaded code (dlbc)
+// and `asm_load` modules with their rodata and data...]
+// Every write into main memory has to go through `check_write`.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SegmKind {
+    Code,
+    ROData, // const variables, never written after load
+    Data,
+    Dynamic, // code pushed by dlbc
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub kind: SegmKind,
+    pub start: usize,
+    pub end: usize, // exclusive
+}
+
+impl Segment {
+    pub fn new(kind: SegmKind, start: usize, end: usize) -> Segment {
+        Segment {
+            kind: kind,
+            start: start,
+            end: end,
+        }
+    }
+
+    pub fn contains(&self, addr: usize, len: usize) -> bool {
+        (addr >= self.start) && (addr.saturating_add(len) <= self.end)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SegmError {
+    OutOfBounds,
+    CodeWrite,
+    ReadOnlyWrite,
+}
+
+#[derive(Debug)]
+pub struct SegmentTable {
+    pub segments: Vec<Segment>,
+    pub allow_self_modify: bool,
+}
+
+impl SegmentTable {
+    pub fn new() -> SegmentTable {
+        SegmentTable {
+            segments: Vec::new(),
+            allow_self_modify: false,
+        }
+    }
+
+    pub fn push(&mut self, kind: SegmKind, start: usize, end: usize) {
+        self.segments.push(Segment::new(kind, start, end));
+    }
+
+    pub fn find(&self, addr: usize, len: usize) -> Option<&Segment> {
+        self.segments.iter().find(|s| s.contains(addr, len))
+    }
+
+    /// Read-only part of the data, same for every instance of an image
+    pub fn rodata(&self) -> Option<&Segment> {
+        self.segments.iter().find(|s| s.kind == SegmKind::ROData)
+    }
+
+    pub fn check_read(&self, addr: usize, len: usize) -> Result<(), SegmError> {
+        match self.find(addr, len) {
+            Some(_) => Ok(()),
+            None => Err(SegmError::OutOfBounds),
+        }
+    }
+
+    pub fn check_write(&self, addr: usize, len: usize) -> Result<(), SegmError> {
+        match self.find(addr, len) {
+            Some(s) if s.kind == SegmKind::Data => Ok(()),
+            Some(s) if s.kind == SegmKind::ROData => Err(SegmError::ReadOnlyWrite),
+            Some(_) if self.allow_self_modify => Ok(()),
+            Some(_) => Err(SegmError::CodeWrite),
+            None => Err(SegmError::OutOfBounds),
+        }
+    }
+}
+
+impl Default for SegmentTable {
+    fn default() -> SegmentTable {
+        SegmentTable::new()
+    }
+}
@@ -0,0 +1,710 @@
+// `voxvm selftest`: opcode conformance battery, grouped by family
+// (`voxvm selftest uint` runs one). Every case is a tiny bytecode program
+// built in here (no assembler involved), executed on a fresh VM, then
+// registers, flags, ip and exceptions are compared with expected ones.
+// `Code` builds the instruction bytes by raw opcode, `run_case` runs a case.
+// The per-opcode sweep over operand tables is in the unit tests
+// (opcodetests.rs), this is the quick check of a built voxvm.
+
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::exceptions::Exception;
+use crate::output::SharedBuffer;
+use crate::registers::Register;
+use crate::segments::SegmKind;
+use crate::vm::{RegTypes, EXT_PREFIX, VM};
+
+const TEST_RAM: usize = 64 * 1024;
+const TEST_STACK: usize = 16 * 1024;
+const TEST_HEAP: usize = 16 * 1024;
+const MAX_STEPS: usize = 10_000; // a broken jump must not hang the test
+
+// flag indices in VM::flags, of is 0, cf 3
+pub(crate) const ZF: usize = 1;
+pub(crate) const NF: usize = 2;
+
+/// Bytecode builder, encodings match the assembler's instruction table
+pub(crate) struct Code {
+    pub bytes: Vec<u8>,
+}
+
+impl Code {
+    pub(crate) fn new() -> Code {
+        Code { bytes: Vec::new() }
+    }
+
+    /// Address of the next instruction
+    pub(crate) fn here(&self) -> u64 {
+        self.bytes.len() as u64
+    }
+
+    pub(crate) fn op(mut self, op: u8, regs: &[u8]) -> Code {
+        self.bytes.push(op);
+        self.bytes.extend_from_slice(regs);
+        self
+    }
+
+    /// Two-byte opcode, EXT_PREFIX then `op`
+    pub(crate) fn ext(mut self, op: u8, regs: &[u8]) -> Code {
+        self.bytes.push(EXT_PREFIX);
+        self.op(op, regs)
+    }
+
+    pub(crate) fn imm(mut self, op: u8, reg: u8, val: u64) -> Code {
+        self.bytes.push(op);
+        self.bytes.push(reg);
+        self.bytes.extend_from_slice(&val.to_be_bytes());
+        self
+    }
+
+    pub(crate) fn uload(self, reg: u8, val: u64) -> Code {
+        self.imm(0x10, reg, val)
+    }
+
+    pub(crate) fn iload(self, reg: u8, val: i64) -> Code {
+        self.imm(0x20, reg, val as u64)
+    }
+
+    pub(crate) fn fload(self, reg: u8, val: f64) -> Code {
+        self.imm(0x30, reg, val.to_bits())
+    }
+
+    /// storei/loadi: `before` registers, `[rN+offset]`, `after` registers
+    pub(crate) fn mem_op(mut self, op: u8, before: &[u8], reg: u8, offset: i64, after: &[u8]) -> Code {
+        self.bytes.push(op);
+        self.bytes.extend_from_slice(before);
+        self.bytes.push(reg);
+        self.bytes.extend_from_slice(&offset.to_be_bytes());
+        self.bytes.extend_from_slice(after);
+        self
+    }
+
+    pub(crate) fn jump(mut self, op: u8, addr: u64) -> Code {
+        self.bytes.push(op);
+        self.bytes.extend_from_slice(&addr.to_be_bytes());
+        self
+    }
+
+    pub(crate) fn halt(self) -> Code {
+        self.op(0xFF, &[])
+    }
+}
+
+/// State a case has to end with, what isn't listed isn't checked
+pub(crate) struct Expect {
+    pub regs: Vec<(usize, Register)>,
+    pub flags: Vec<(usize, u8)>, // flag index (of, zf, nf, cf) -> value
+    pub ip: Option<usize>,       // where the halt is
+    pub exc: Option<Exception>,  // the only pending exception, none if None
+}
+
+pub(crate) fn expect(regs: &[(usize, Register)], flags: &[(usize, u8)]) -> Expect {
+    Expect {
+        regs: regs.to_vec(),
+        flags: flags.to_vec(),
+        ip: None,
+        exc: None,
+    }
+}
+
+impl Expect {
+    /// Expects `exc` pending after the run
+    pub(crate) fn raises(mut self, exc: Exception) -> Expect {
+        self.exc = Some(exc);
+        self
+    }
+}
+
+pub(crate) struct Case {
+    pub family: &'static str,
+    pub name: String,
+    pub code: Code,
+    pub funcs: Vec<u64>,            // function table for call tests
+    pub setup: Option<fn(&mut VM)>, // embedder side of the case, before running
+    pub expect: Expect,
+    pub stdout: &'static str, // guest output expected on the captured streams
+    pub stderr: &'static str,
+}
+
+pub(crate) fn case(family: &'static str, name: impl Into<String>, code: Code, expect: Expect) -> Case {
+    Case {
+        family,
+        name: name.into(),
+        code,
+        funcs: Vec::new(),
+        setup: None,
+        expect,
+        stdout: "",
+        stderr: "",
+    }
+}
+
+/// Runs the case on a fresh VM, Err describes the first mismatch
+pub(crate) fn run_case(case: &Case) -> Result<(), String> {
+    let mut vm = VM::new(TEST_RAM, TEST_STACK, TEST_HEAP, 16);
+    vm.quiet = true;
+    vm.memory.extend_from_slice(&case.code.bytes);
+    vm.segments.push(SegmKind::Code, 0, case.code.bytes.len());
+    vm.func_table = case.funcs.clone();
+    let (stdout, stderr) = (SharedBuffer::new(), SharedBuffer::new());
+    vm.output.stdout = Box::new(stdout.clone());
+    vm.output.stderr = Box::new(stderr.clone());
+    if let Some(setup) = case.setup {
+        setup(&mut vm);
+    }
+
+    let mut steps: usize = 0;
+    while vm.is_running() {
+        if steps >= MAX_STEPS {
+            return Err(format!("no halt after {} instructions", MAX_STEPS));
+        }
+        if vm.ip >= vm.memory.len() {
+            return Err(format!("ip 0x{:x} ran past the program", vm.ip));
+        }
+        vm.step();
+        steps += 1;
+    }
+
+    match (vm.exceptions_active.first(), case.expect.exc) {
+        (None, None) => {}
+        (Some(got), Some(want)) if (got == want) && (vm.exceptions_active.len() == 1) => {}
+        (_, want) => {
+            let want: String = want.map_or("[]".to_string(), |e| format!("[{:?}]", e));
+            return Err(format!("exceptions: expected {}, got {}", want, vm.exceptions_active.names()));
+        }
+    }
+    if let Some(ip) = case.expect.ip.filter(|ip| *ip != vm.ip) {
+        return Err(format!("ip: expected 0x{:x}, got 0x{:x}", ip, vm.ip));
+    }
+    for (ind, val) in &case.expect.regs {
+        if vm.registers[*ind] != *val {
+            return Err(format!("r{}: expected {}, got {}", ind, val, vm.registers[*ind]));
+        }
+    }
+    for (ind, val) in &case.expect.flags {
+        if vm.flags[*ind] != *val {
+            let name: &str = ["of", "zf", "nf", "cf"][*ind];
+            return Err(format!("{}: expected {}, got {}", name, val, vm.flags[*ind]));
+        }
+    }
+    for (name, buf, want) in [("stdout", &stdout, case.stdout), ("stderr", &stderr, case.stderr)] {
+        if buf.contents() != want {
+            return Err(format!("{}: expected {:?}, got {:?}", name, want, buf.contents()));
+        }
+    }
+    Ok(())
+}
+
+fn cases() -> Vec<Case> {
+    let mut res: Vec<Case> = Vec::new();
+
+    res.push(case(
+        "control",
+        "nop halt",
+        Code::new().op(0x02, &[]).uload(1, 1).halt(),
+        expect(&[(1, Register::uint(1))], &[]),
+    ));
+    res.push(case(
+        "control",
+        "excclear",
+        Code::new()
+            .fload(1, 1.0)
+            .fload(2, 0.0)
+            .op(0x34, &[3, 1, 2]) // fdiv r3 r1 r2, ZeroDivision
+            .ext(0x01, &1u64.to_be_bytes()) // excclear @zero_division
+            .halt(),
+        expect(&[], &[]),
+    ));
+
+    res.push(case(
+        "uint",
+        "uadd umul usub",
+        Code::new()
+            .uload(1, 7)
+            .uload(2, 5)
+            .op(0x11, &[1, 2]) // 12
+            .op(0x12, &[1, 2]) // 60
+            .uload(3, 5)
+            .op(0x13, &[3, 2]) // 0, sets zf
+            .halt(),
+        expect(&[(1, Register::uint(60)), (3, Register::uint(0))], &[(ZF, 1)]),
+    ));
+    res.push(case(
+        "uint",
+        "udiv urem",
+        Code::new()
+            .uload(1, 17)
+            .uload(2, 5)
+            .op(0x14, &[3, 1, 2])
+            .op(0x15, &[4, 1, 2])
+            .halt(),
+        expect(&[(3, Register::uint(3)), (4, Register::uint(2))], &[]),
+    ));
+    res.push(case(
+        "uint",
+        "ucmp less",
+        Code::new().uload(1, 3).uload(2, 5).op(0x16, &[1, 2]).halt(),
+        expect(&[], &[(ZF, 0), (NF, 1)]),
+    ));
+    res.push(case(
+        "uint",
+        "ucmp equal",
+        Code::new().uload(1, 5).uload(2, 5).op(0x16, &[1, 2]).halt(),
+        expect(&[], &[(ZF, 1), (NF, 0)]),
+    ));
+
+    res.push(case(
+        "int",
+        "iadd isub imul",
+        Code::new()
+            .iload(1, -3)
+            .iload(2, 5)
+            .op(0x21, &[1, 2]) // 2
+            .iload(3, 4)
+            .op(0x23, &[3, 2]) // -1
+            .op(0x22, &[2, 3]) // -5
+            .halt(),
+        expect(
+            &[
+                (1, Register::int(2)),
+                (3, Register::int(-1)),
+                (2, Register::int(-5)),
+            ],
+            &[],
+        ),
+    ));
+    res.push(case(
+        "int",
+        "idiv",
+        Code::new().iload(1, -17).iload(2, 5).op(0x24, &[3, 1, 2]).halt(),
+        expect(&[(3, Register::int(-3))], &[]),
+    ));
+    res.push(case(
+        "int",
+        "icmp",
+        Code::new().iload(1, -3).iload(2, 5).op(0x26, &[1, 2]).halt(),
+        expect(&[], &[(ZF, 0), (NF, 1)]),
+    ));
+
+    res.push(case(
+        "float",
+        "fadd fsub fmul",
+        Code::new()
+            .fload(1, 1.5)
+            .fload(2, 2.25)
+            .op(0x31, &[1, 2]) // 3.75
+            .fload(3, 1.0)
+            .op(0x33, &[3, 2]) // -1.25
+            .op(0x32, &[2, 3]) // -2.8125
+            .halt(),
+        expect(
+            &[
+                (1, Register::float(3.75)),
+                (3, Register::float(-1.25)),
+                (2, Register::float(-2.8125)),
+            ],
+            &[],
+        ),
+    ));
+    res.push(case(
+        "float",
+        "fdiv",
+        Code::new().fload(1, 7.5).fload(2, 2.5).op(0x34, &[3, 1, 2]).halt(),
+        expect(&[(3, Register::float(3.0))], &[]),
+    ));
+    res.push(case(
+        "float",
+        "fcmp",
+        Code::new().fload(1, 2.5).fload(2, 2.5).op(0x36, &[1, 2]).halt(),
+        expect(&[], &[(ZF, 1), (NF, 0)]),
+    ));
+
+    res.push(case(
+        "convert",
+        "utoi utof itof ftoi",
+        Code::new()
+            .uload(1, 9)
+            .op(0x50, &[2, 1])
+            .op(0x52, &[3, 1])
+            .iload(4, -2)
+            .op(0x53, &[5, 4])
+            .fload(6, -7.0)
+            .op(0x55, &[7, 6])
+            .halt(),
+        expect(
+            &[
+                (2, Register::int(9)),
+                (3, Register::float(9.0)),
+                (5, Register::float(-2.0)),
+                (7, Register::int(-7)),
+            ],
+            &[],
+        ),
+    ));
+
+    res.push(case(
+        "bitwise",
+        "or and xor",
+        Code::new()
+            .uload(1, 0b1100)
+            .uload(2, 0b1010)
+            .op(0x61, &[1, 2]) // 0b1110
+            .uload(3, 0b1100)
+            .op(0x62, &[3, 2]) // 0b1000
+            .uload(4, 0b1010)
+            .op(0x64, &[4, 2]) // 0, sets zf
+            .halt(),
+        expect(
+            &[
+                (1, Register::uint(0b1110)),
+                (3, Register::uint(0b1000)),
+                (4, Register::uint(0)),
+            ],
+            &[(ZF, 1)],
+        ),
+    ));
+    res.push(case(
+        "bitwise",
+        "shl shr",
+        Code::new()
+            .uload(1, 3)
+            .uload(2, 4)
+            .op(0x67, &[1, 2]) // 48
+            .uload(3, 48)
+            .op(0x68, &[3, 2]) // 3
+            .halt(),
+        expect(&[(1, Register::uint(48)), (3, Register::uint(3))], &[]),
+    ));
+
+    res.push(case(
+        "move",
+        "movr xchg",
+        Code::new()
+            .uload(1, 7)
+            .iload(2, -1)
+            .op(0x60, &[3, 1])
+            .op(0x6c, &[1, 2])
+            .halt(),
+        expect(
+            &[
+                (1, Register::int(-1)),
+                (2, Register::uint(7)),
+                (3, Register::uint(7)),
+            ],
+            &[],
+        ),
+    ));
+    res.push(case(
+        "move",
+        "cmovz",
+        Code::new()
+            .uload(1, 5)
+            .uload(2, 5)
+            .uload(3, 1)
+            .op(0x16, &[1, 2]) // zf = 1
+            .op(0x69, &[4, 3])
+            .halt(),
+        expect(&[(4, Register::uint(1))], &[]),
+    ));
+
+    // jumps skip a load, so r3 stays 0 only if the jump is taken
+    let jz = Code::new().uload(1, 5).uload(2, 5).op(0x16, &[1, 2]);
+    let target: u64 = jz.here() + 9 + 10;
+    res.push(case(
+        "jump",
+        "jz taken",
+        jz.jump(0x41, target).uload(3, 1).halt(),
+        expect(&[(3, Register::uint(0))], &[]),
+    ));
+    let jnz = Code::new().uload(1, 5).uload(2, 5).op(0x16, &[1, 2]);
+    let target: u64 = jnz.here() + 9 + 10;
+    res.push(case(
+        "jump",
+        "jnz not taken",
+        jnz.jump(0x48, target).uload(3, 1).halt(),
+        expect(&[(3, Register::uint(1))], &[]),
+    ));
+    let jl = Code::new().uload(1, 3).uload(2, 5).op(0x16, &[1, 2]);
+    let target: u64 = jl.here() + 9 + 10;
+    res.push(case(
+        "jump",
+        "jl taken",
+        jl.jump(0x42, target).uload(3, 1).halt(),
+        expect(&[(3, Register::uint(0))], &[]),
+    ));
+
+    res.push(case(
+        "set",
+        "setl setge setne",
+        Code::new()
+            .uload(1, 3)
+            .uload(2, 5)
+            .op(0x16, &[1, 2]) // nf = 1
+            .op(0x4c, &[3])
+            .op(0x4e, &[4])
+            .op(0x4b, &[5])
+            .halt(),
+        expect(
+            &[
+                (3, Register::uint(1)),
+                (4, Register::uint(0)),
+                (5, Register::uint(1)),
+            ],
+            &[],
+        ),
+    ));
+    res.push(case(
+        "set",
+        "sete setg setle",
+        Code::new()
+            .iload(1, -2)
+            .iload(2, -2)
+            .op(0x26, &[1, 2]) // zf = 1
+            .op(0x4a, &[3])
+            .op(0x4d, &[4])
+            .op(0x4f, &[5])
+            .halt(),
+        expect(
+            &[
+                (3, Register::uint(1)),
+                (4, Register::uint(0)),
+                (5, Register::uint(1)),
+            ],
+            &[],
+        ),
+    ));
+
+    res.push(case(
+        "stack",
+        "push pop",
+        Code::new()
+            .fload(1, 2.5)
+            .iload(2, -4)
+            .op(0x80, &[1])
+            .op(0x80, &[2])
+            .op(0x81, &[3])
+            .op(0x81, &[4])
+            .halt(),
+        expect(&[(3, Register::int(-4)), (4, Register::float(2.5))], &[]),
+    ));
+
+    // call 0 / halt / func 0: uload r1 42 / ret
+    let call = Code::new().jump(0x90, 0).halt();
+    let func_addr: u64 = call.here();
+    let mut call_case = case(
+        "func",
+        "call ret",
+        call.uload(1, 42).op(0x91, &[]),
+        expect(&[(1, Register::uint(42))], &[]),
+    );
+    call_case.funcs.push(func_addr);
+    res.push(call_case);
+
+    res.push(case(
+        "heap",
+        "alloc store load",
+        Code::new()
+            .imm(0xA0, 1, 16) // alloc r1 16
+            .uload(2, 0xBEEF)
+            .uload(3, 8)
+            .op(0xA2, &[1, 2, 3]) // store r1 r2 r3
+            .uload(4, 1) // uint
+            .op(0xA4, &[4, 5, 1, 3]) // load r4 r5 r1 r3
+            .halt(),
+        expect(&[(5, Register::uint(0xBEEF))], &[]),
+    ));
+    res.push(case(
+        "heap",
+        "storei loadi",
+        Code::new()
+            .imm(0xA0, 1, 32) // alloc r1 32
+            .uload(2, 0xBEEF)
+            .uload(3, 8)
+            .mem_op(0xAF, &[], 1, 16, &[2, 3]) // storei [r1+16] r2 r3
+            .uload(6, 24)
+            .op(0x11, &[1, 6]) // uadd r1 r6
+            .uload(4, 1) // uint
+            .mem_op(0xB0, &[4, 5], 1, -8, &[3]) // loadi r4 r5 [r1-8] r3
+            .halt(),
+        expect(&[(5, Register::uint(0xBEEF))], &[]),
+    ));
+    res.push(case(
+        "heap",
+        "gcadopt gcrelease",
+        Code::new()
+            .uload(2, 16)
+            .op(0xA5, &[1, 2]) // allocr_nogc r1 r2
+            .ext(0x02, &[1]) // gcadopt r1
+            .op(0xA5, &[4, 2]) // allocr_nogc r4 r2
+            .ext(0x02, &[4]) // gcadopt r4
+            .op(0xA3, &[3, 2]) // allocr r3 r2
+            .ext(0x03, &[3]) // gcrelease r3
+            .uload(1, 0)
+            .uload(3, 0)
+            .uload(4, 0)
+            .op(0x01, &[0x00, 0x0E, 0]) // ncall 0xE r0 (gc_collect)
+            .halt(),
+        expect(&[(0, Register::uint(2))], &[]), // r1 and r4, not r3
+    ));
+    res.push(case(
+        "heap",
+        "allocal",
+        Code::new()
+            .imm(0xA0, 1, 5) // alloc r1 5
+            .uload(2, 16)
+            .uload(3, 64)
+            .op(0xAE, &[4, 2, 3]) // allocal r4 r2 r3
+            .halt(),
+        expect(&[(4, Register::address(64))], &[]),
+    ));
+    res.push(case(
+        "heap",
+        "salloc store load sfree",
+        Code::new()
+            .uload(2, 8)
+            .op(0xAC, &[1, 2]) // salloc r1 r2
+            .uload(3, 0xBEEF)
+            .op(0xA2, &[1, 3, 2]) // store r1 r3 r2
+            .uload(4, 1) // uint
+            .op(0xA4, &[4, 5, 1, 2]) // load r4 r5 r1 r2
+            .op(0xAD, &[1]) // sfree r1
+            .op(0xAC, &[6, 2]) // salloc r6 r2, same bytes again
+            .op(0xA4, &[4, 7, 6, 2]) // load r4 r7 r6 r2
+            .halt(),
+        expect(&[(5, Register::uint(0xBEEF)), (7, Register::uint(0))], &[]),
+    ));
+    res.push(case(
+        "heap",
+        "allocarena store load",
+        Code::new()
+            .uload(1, 0)
+            .op(0x01, &[0, 0xF0, 0]) // ncall @arena_new r0
+            .uload(2, 8)
+            .op(0xB1, &[3, 0, 2]) // allocarena r3 r0 r2
+            .op(0xB1, &[4, 0, 2]) // allocarena r4 r0 r2
+            .uload(5, 0xBEEF)
+            .op(0xA2, &[4, 5, 2]) // store r4 r5 r2
+            .uload(6, 1) // uint
+            .op(0xA4, &[6, 7, 4, 2]) // load r6 r7 r4 r2
+            .halt(),
+        expect(
+            &[
+                (3, Register::uint(1 << 60)),
+                (4, Register::uint((1 << 60) + 8)),
+                (7, Register::uint(0xBEEF)),
+            ],
+            &[],
+        ),
+    ));
+
+    // ldstr r1 "hi€", then strlen/strindex over it and one unit past the end
+    let mut hi: Vec<u8> = vec![1];
+    hi.extend_from_slice(&6u64.to_be_bytes());
+    hi.extend_from_slice(&[0x00, 0x68, 0x00, 0x69, 0x20, 0xAC]);
+    res.push(case(
+        "string",
+        "strlen strindex",
+        Code::new()
+            .ext(0x05, &hi)
+            .op(0x7B, &[2, 1]) // strlen r2 r1
+            .uload(3, 2)
+            .op(0x7A, &[4, 1, 3]) // strindex r4 r1 r3
+            .halt(),
+        expect(&[(2, Register::uint(3)), (4, Register::uint(0x20AC))], &[]),
+    ));
+    res.push(case(
+        "string",
+        "strindex past the end",
+        Code::new().ext(0x05, &hi).uload(3, 3).op(0x7A, &[4, 1, 3]).halt(),
+        expect(&[], &[]).raises(Exception::MainSegmFault),
+    ));
+
+    // ncall 1 r0 prints r1 to stream r2
+    for (stream, name) in [(1, "print stdout"), (2, "print stderr")] {
+        let mut print_case = case(
+            "native",
+            name,
+            Code::new().iload(1, -42).uload(2, stream).op(0x01, &[0, 1, 0]).halt(),
+            expect(&[], &[]),
+        );
+        match stream {
+            1 => print_case.stdout = "-42\n",
+            _ => print_case.stderr = "-42\n",
+        }
+        res.push(print_case);
+    }
+
+    // host calls shadow std ones, unregistering brings print back
+    let host_code = || Code::new().uload(1, 21).uload(2, 1).op(0x01, &[0, 1, 0]).halt();
+    fn double_r1(vm: &mut VM) {
+        let doubled = Box::new(|vm: &mut VM| {
+            vm.registers[0] = Register::uint(vm.registers[1].as_u64() * 2);
+            vm.reg_types[0] = RegTypes::uint64;
+        });
+        vm.register_ncall(0x1, doubled);
+    }
+    let mut host_case = case("native", "host ncall", host_code(), expect(&[(0, Register::uint(42))], &[]));
+    host_case.setup = Some(double_r1);
+    res.push(host_case);
+    let mut unreg_case = case("native", "host ncall dropped", host_code(), expect(&[(0, Register::uint(0))], &[]));
+    unreg_case.setup = Some(|vm: &mut VM| {
+        double_r1(vm);
+        vm.unregister_ncall(0x1);
+    });
+    unreg_case.stdout = "21\n";
+    res.push(unreg_case);
+
+    res
+}
+
+/// Runs the battery (or the cases of one family) and prints the pass/fail
+/// matrix, true if everything passed
+pub fn selftest(family: Option<&str>) -> bool {
+    let cases: Vec<Case> = cases()
+        .into_iter()
+        .filter(|c| family.is_none_or(|f| c.family == f))
+        .collect();
+    if cases.is_empty() {
+        println!("no selftest family '{}'", family.unwrap_or_default());
+        return false;
+    }
+
+    // handlers report misuse by panicking, that's a failed case, not a crash
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let results: Vec<Result<(), String>> = cases
+        .iter()
+        .map(|c| match panic::catch_unwind(AssertUnwindSafe(|| run_case(c))) {
+            Ok(res) => res,
+            Err(e) => Err(match e.downcast_ref::<String>() {
+                Some(msg) => format!("panicked: {}", msg),
+                None => match e.downcast_ref::<&str>() {
+                    Some(msg) => format!("panicked: {}", msg),
+                    None => "panicked".to_string(),
+                },
+            }),
+        })
+        .collect();
+    panic::set_hook(default_hook);
+
+    let mut families: Vec<&str> = Vec::new();
+    for c in &cases {
+        if !families.contains(&c.family) {
+            families.push(c.family);
+        }
+    }
+
+    println!("{:<10} {:<24} {}", "family", "case", "result");
+    for family in &families {
+        for (c, res) in cases.iter().zip(&results).filter(|(c, _)| c.family == *family) {
+            match res {
+                Ok(()) => println!("{:<10} {:<24} PASS", c.family, c.name),
+                Err(e) => println!("{:<10} {:<24} FAIL ({})", c.family, c.name, e),
+            }
+        }
+    }
+
+    let passed: usize = results.iter().filter(|r| r.is_ok()).count();
+    println!("\n{}/{} passed", passed, results.len());
+    passed == results.len()
+}
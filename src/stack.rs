@@ -1,4 +1,7 @@
 use crate::{
+    exceptions::Exception,
+    memcap::{mem_reserve, STACK_SLOT_BYTES},
+    misclib::args_to_u64,
     registers::Register,
     vm::{RegTypes, VM},
 };
@@ -60,6 +63,26 @@ impl StackFrame {
     }
 }
 
+/// Whether `count` more slots fit under the stack limit, raises
+/// StackOverflow if they don't
+fn stack_room(vm: &mut VM, count: usize) -> bool {
+    if vm.stack.stack.len().saturating_add(count) <= vm.stack_slots_max {
+        return true;
+    }
+    vm.exceptions_active.push(Exception::StackOverflow);
+    false
+}
+
+/// --stack-precheck: whether the deepest data stack of function `func`
+/// fits under the stack limit, raises StackOverflow at the call if it
+/// doesn't. Functions the assembler couldn't size always fit
+pub fn call_stack_room(vm: &mut VM, func: usize) -> bool {
+    match vm.func_stack_depths.get(&func) {
+        Some(&depth) if vm.stack_precheck => stack_room(vm, depth as usize),
+        _ => true,
+    }
+}
+
 pub fn op_push(vm: &mut VM) {
     // 0x80, size: 2
     // push Rsrc
@@ -67,6 +90,10 @@ pub fn op_push(vm: &mut VM) {
     let r_src_ind: usize = vm.memory[(vm.ip + 1)] as usize;
     let val: u64 = vm.registers[r_src_ind].as_u64_bitwise();
     let r_type: RegTypes = vm.reg_types[r_src_ind];
+    if !stack_room(vm, 1) || !mem_reserve(vm, STACK_SLOT_BYTES) {
+        vm.ip += 2;
+        return;
+    }
 
     // Pushes value, then type.
     vm.stack.push(val, r_type);
@@ -112,6 +139,11 @@ pub fn op_pop(vm: &mut VM) {
 pub fn op_pushall(vm: &mut VM) {
     // 0x82, size: 1
     // pushall - pushes all register values into the stack. (with metadata - types)
+    let count: u64 = vm.registers.len().saturating_sub(1) as u64;
+    if !stack_room(vm, count as usize) || !mem_reserve(vm, count * STACK_SLOT_BYTES) {
+        vm.ip += 1;
+        return;
+    }
     for i in 0..vm.registers.len().saturating_sub(1) {
         vm.stack
             .push(vm.registers[i].as_u64_bitwise(), vm.reg_types[i]);
@@ -196,3 +228,45 @@ pub fn op_usf(vm: &mut VM) {
     vm.ip += 3;
     return;
 }
+
+/// Local slots a frame may have, `lset` past it raises StackOverflow
+pub const MAX_LOCALS: usize = 1 << 16;
+
+/// Slot index of `lget`/`lset`, None after raising StackOverflow
+fn local_ind(vm: &mut VM) -> Option<usize> {
+    let ind: u64 = args_to_u64(&vm.memory[(vm.ip + 3)..(vm.ip + 11)]);
+    if ind >= MAX_LOCALS as u64 {
+        vm.exceptions_active.push(Exception::StackOverflow);
+        return None;
+    }
+    Some(ind as usize)
+}
+
+pub fn op_lget(vm: &mut VM) {
+    // 0xFE 0x07, size: 11
+    // lget Rdst ind - loads local slot `ind` of the current call frame
+    // with its type, slots never set read as uint 0. Flags stay
+    let r_dest_ind: usize = vm.memory[vm.ip + 2] as usize;
+    if let Some(ind) = local_ind(vm) {
+        let (val, t) = vm.call_stack.locals_mut().get(ind).copied().unwrap_or((0, RegTypes::uint64));
+        vm.registers[r_dest_ind] = Register::from_u64_bits(val, t);
+        vm.reg_types[r_dest_ind] = t;
+    }
+    vm.ip += 11;
+}
+
+pub fn op_lset(vm: &mut VM) {
+    // 0xFE 0x08, size: 11
+    // lset Rsrc ind - saves Rsrc with its type into local slot `ind` of
+    // the current call frame, the slots go away at its ret. Flags stay
+    let r_src_ind: usize = vm.memory[vm.ip + 2] as usize;
+    if let Some(ind) = local_ind(vm) {
+        let slot: (u64, RegTypes) = (vm.registers[r_src_ind].as_u64_bitwise(), vm.reg_types[r_src_ind]);
+        let locals: &mut Vec<(u64, RegTypes)> = vm.call_stack.locals_mut();
+        if locals.len() <= ind {
+            locals.resize(ind + 1, (0, RegTypes::uint64));
+        }
+        locals[ind] = slot;
+    }
+    vm.ip += 11;
+}
@@ -0,0 +1,189 @@
+use std::{collections::HashMap, sync::LazyLock};
+
+use crate::assembly::{voxasm_instr_table, LexTypes};
+use crate::exceptions::Exception;
+use crate::registers::Register;
+use crate::selftest::{case, expect, run_case, Case, Code, Expect, NF, ZF};
+use crate::vm::EXT_PREFIX;
+
+// Opcode unit test support (opcodetests.rs), on top of the selftest `Code`
+// builder and case runner. `ins` encodes instructions by mnemonic, taking
+// the opcode from the assembler's instruction table and checking the
+// operand bytes add up to the size the table gives; `binop` and `unop` are
+// the usual case shapes, the flag helpers give what a result has to set.
+
+pub const OF: usize = 0;
+pub const CF: usize = 3;
+
+static INSTR_TABLE: LazyLock<HashMap<String, Vec<LexTypes>>> = LazyLock::new(voxasm_instr_table);
+
+impl Code {
+    /// Instruction `name` of the assembler table with `operands` bytes after
+    /// the opcode. Panics on unknown names and operand bytes not matching
+    /// the table size, a broken test program isn't worth running
+    pub fn ins(mut self, name: &str, operands: &[u8]) -> Code {
+        let (opcode, size): (Vec<u8>, u64) = match INSTR_TABLE.get(name).map(|lex| (lex.first(), lex.get(1))) {
+            Some((Some(LexTypes::Op(op)), Some(LexTypes::Size(size)))) => (vec![*op], *size),
+            Some((Some(LexTypes::ExtOp(op)), Some(LexTypes::Size(size)))) => (vec![EXT_PREFIX, *op], *size),
+            _ => panic!("testsupport: no instruction named '{}'", name),
+        };
+        if (opcode.len() + operands.len()) as u64 != size {
+            panic!(
+                "testsupport: {} takes {} operand bytes, got {}",
+                name,
+                size as usize - opcode.len(),
+                operands.len()
+            );
+        }
+        self.bytes.extend_from_slice(&opcode);
+        self.bytes.extend_from_slice(operands);
+        self
+    }
+
+    /// Loads `val` by its register kind (uint, int or float)
+    pub fn load(self, reg: u8, val: Register) -> Code {
+        match val {
+            Register::int(v) => self.iload(reg, v),
+            Register::float(v) => self.fload(reg, v),
+            other => self.uload(reg, other.as_u64_bitwise()),
+        }
+    }
+}
+
+impl Expect {
+    /// Also checks the VM halted at `ip`
+    pub fn at(mut self, ip: u64) -> Expect {
+        self.ip = Some(ip as usize);
+        self
+    }
+}
+
+/// Runs every case, panics listing the ones that failed
+pub fn check(cases: Vec<Case>) {
+    let failed: Vec<String> = cases
+        .iter()
+        .filter_map(|c| run_case(c).err().map(|e| format!("{}: {}", c.name, e)))
+        .collect();
+    assert!(failed.is_empty(), "{}/{} cases failed:\n{}", failed.len(), cases.len(), failed.join("\n"));
+}
+
+/// of, zf, nf (cf is always expected clear)
+pub type Flags = (u8, u8, u8);
+
+pub const NO_FLAGS: Flags = (0, 0, 0);
+
+pub fn all_flags((of, zf, nf): Flags) -> [(usize, u8); 4] {
+    [(OF, of), (ZF, zf), (NF, nf), (CF, 0)]
+}
+
+/// zf and nf of a result
+pub fn sign_flags(zero: bool, neg: bool) -> Flags {
+    (0, zero as u8, neg as u8)
+}
+
+pub fn zero_flag(zero: bool) -> Flags {
+    (0, zero as u8, 0)
+}
+
+/// Flags an ALU result sets (`VM::update_flags`): zf if zero, nf if negative
+pub fn result_flags(res: Register) -> Flags {
+    match res {
+        Register::int(v) => sign_flags(v == 0, v < 0),
+        Register::float(v) => sign_flags(v == 0.0, v < 0.0),
+        other => zero_flag(other.as_u64_bitwise() == 0),
+    }
+}
+
+/// `name` of r1 and r2: two-register forms update r1, three-register ones
+/// write r3. `want` Err(exc) is a case that raises exc instead
+pub fn binop(family: &'static str, name: &str, a: Register, b: Register, want: Result<Register, Exception>, fl: Flags) -> Case {
+    let three: bool = matches!(name, "udiv" | "urem" | "idiv" | "irem" | "fdiv" | "frem");
+    let code: Code = Code::new().load(1, a).load(2, b);
+    let (code, dst): (Code, usize) = match three {
+        true => (code.ins(name, &[3, 1, 2]), 3),
+        false => (code.ins(name, &[1, 2]), 1),
+    };
+    let at: u64 = code.here();
+    let mut regs: Vec<(usize, Register)> = vec![(2, b)];
+    if let Ok(w) = want {
+        regs.push((dst, w));
+    }
+    let mut exp = expect(&regs, &all_flags(fl)).at(at);
+    if let Err(exc) = want {
+        exp = exp.raises(exc);
+    }
+    case(family, format!("{} {} {}", name, a, b), code.halt(), exp)
+}
+
+/// `name r3 r1` (or `name r1` for one-register forms updating r1) of `a`
+pub fn unop(family: &'static str, name: &str, a: Register, want: Result<Register, Exception>, fl: Flags) -> Case {
+    let one: bool = matches!(name, "uinc" | "udec" | "iinc" | "idec" | "finc" | "fdec");
+    let code: Code = Code::new().load(1, a);
+    let (code, dst): (Code, usize) = match one {
+        true => (code.ins(name, &[1]), 1),
+        false => (code.ins(name, &[3, 1]), 3),
+    };
+    let at: u64 = code.here();
+    let mut exp = match want {
+        Ok(w) => expect(&[(dst, w)], &all_flags(fl)),
+        Err(exc) => expect(&[], &all_flags(fl)).raises(exc),
+    }
+    .at(at);
+    if !one && want.is_ok() {
+        exp.regs.push((1, a)); // the source stays
+    }
+    case(family, format!("{} {}", name, a), code.halt(), exp)
+}
+
+/// `name` of `a` (and `b`) right after an icmp left NF (lt) or ZF (eq)
+/// set. With `sets` the flags have to be those of the result, else the
+/// compare's have to stay
+pub fn after_compare(name: &str, a: Register, b: Option<Register>, want: Register, sets: bool) -> Vec<Case> {
+    // `test` sets the flags of a & b without writing it
+    let res_flags: Flags = match (name, a, b) {
+        ("test", Register::uint(x), Some(Register::uint(y))) => result_flags(Register::uint(x & y)),
+        _ => result_flags(want),
+    };
+    let mut res: Vec<Case> = Vec::new();
+    for (rel, x) in [("lt", 3), ("eq", 5)] {
+        let fl: Flags = match sets {
+            true => res_flags,
+            false => sign_flags(rel == "eq", rel == "lt"),
+        };
+        let code: Code = Code::new().iload(7, x).iload(8, 5).ins("icmp", &[7, 8]).load(1, a);
+        let (code, dst): (Code, usize) = match b {
+            Some(b) => (code.load(2, b).ins(name, &[1, 2]), 1),
+            None if matches!(name, "udec" | "iinc" | "idec" | "finc" | "fdec") => (code.ins(name, &[1]), 1),
+            None => (code.ins(name, &[3, 1]), 3),
+        };
+        let at: u64 = code.here();
+        let exp = expect(&[(dst, want)], &all_flags(fl)).at(at);
+        res.push(case("flags", format!("{} {} after {}", name, a, rel), code.halt(), exp));
+    }
+    res
+}
+
+/// Operands a compare leaves less, equal and greater
+pub const RELATIONS: [(&str, u64, u64); 3] = [("lt", 3, 5), ("eq", 5, 5), ("gt", 7, 5)];
+
+/// Whether a condition (`set*`, `cmov*`, `j*` suffix) holds after a compare
+pub fn holds(cond: &str, rel: &str) -> bool {
+    match cond {
+        "e" | "z" => rel == "eq",
+        "ne" | "nz" => rel != "eq",
+        "l" => rel == "lt",
+        "g" => rel == "gt",
+        "ge" => rel != "lt",
+        _ => rel != "gt", // le
+    }
+}
+
+/// Compare in each domain giving `rel` for `a` and `b`
+pub fn compare_code(domain: &str, a: u64, b: u64) -> Code {
+    match domain {
+        "ucmp" => Code::new().uload(1, a).uload(2, b).ins("ucmp", &[1, 2]),
+        "icmp" => Code::new().iload(1, a as i64 - 6).iload(2, b as i64 - 6).ins("icmp", &[1, 2]),
+        _ => Code::new().fload(1, a as f64 / 4.0).fload(2, b as f64 / 4.0).ins("fcmp", &[1, 2]),
+    }
+}
+
@@ -0,0 +1,102 @@
+use std::{collections::HashMap, fs, time::Instant};
+
+use serde::Serialize;
+
+// Function-level timeline (`--trace=out.json`) in the Chrome trace event
+// format, opens in chrome://tracing, Perfetto and friends.
+// call/callr record a "B" (begin) event, ret the matching "E" (end) one.
+// Timestamps are microseconds since the trace was created.
+
+#[derive(Serialize)]
+struct TraceEvent {
+    name: String,
+    cat: &'static str,
+    ph: &'static str,
+    ts: f64,
+    pid: u32,
+    tid: u32,
+}
+
+#[derive(Serialize)]
+struct TraceFile {
+    #[serde(rename = "traceEvents")]
+    trace_events: Vec<TraceEvent>,
+    #[serde(rename = "displayTimeUnit")]
+    display_time_unit: &'static str,
+}
+
+#[derive(Debug)]
+pub struct Trace {
+    start: Instant,
+    events: Vec<(usize, bool, f64)>, // func ind, is begin, ts
+    open: Vec<usize>,                // funcs entered and not returned from yet
+}
+
+impl Trace {
+    pub fn new() -> Trace {
+        Trace {
+            start: Instant::now(),
+            events: Vec::new(),
+            open: Vec::new(),
+        }
+    }
+
+    fn now(&self) -> f64 {
+        self.start.elapsed().as_nanos() as f64 / 1000.0
+    }
+
+    pub fn enter(&mut self, func_ind: usize) {
+        let ts: f64 = self.now();
+        self.events.push((func_ind, true, ts));
+        self.open.push(func_ind);
+    }
+
+    pub fn exit(&mut self) {
+        let ts: f64 = self.now();
+        if let Some(ind) = self.open.pop() {
+            self.events.push((ind, false, ts));
+        }
+    }
+
+    /// Ends the functions still running when the VM halted
+    pub fn finish(&mut self) {
+        while !self.open.is_empty() {
+            self.exit();
+        }
+    }
+
+    /// The trace as JSON, functions without a symbol are named `func #ind`
+    pub fn to_json(&self, func_names: &HashMap<String, usize>) -> String {
+        let names: HashMap<usize, &String> = func_names.iter().map(|(n, i)| (*i, n)).collect();
+        let trace_events: Vec<TraceEvent> = self
+            .events
+            .iter()
+            .map(|(ind, begin, ts)| TraceEvent {
+                name: match names.get(ind) {
+                    Some(n) => n.to_string(),
+                    None => format!("func #{}", ind),
+                },
+                cat: "function",
+                ph: if *begin { "B" } else { "E" },
+                ts: *ts,
+                pid: 1,
+                tid: 1,
+            })
+            .collect();
+        let file = TraceFile {
+            trace_events,
+            display_time_unit: "ns",
+        };
+        serde_json::to_string(&file).unwrap()
+    }
+
+    pub fn save(&self, path: &str, func_names: &HashMap<String, usize>) -> std::io::Result<()> {
+        fs::write(path, self.to_json(func_names))
+    }
+}
+
+impl Default for Trace {
+    fn default() -> Trace {
+        Trace::new()
+    }
+}
@@ -0,0 +1,308 @@
+// `voxvm fmt`: voxasm source formatter.
+// Lexing follows the assembler: whitespace separated lexems, a lexem with `#`
+// (or a lone `;`) starts a comment. Every input line gives exactly one output
+// line, so line numbers in diagnostics and the vve line table stay the same.
+
+use std::fs;
+
+use crate::assembly::parse_num_literal;
+
+const INDENT: &str = "    ";
+// lines that live at column 0
+const DIRECTIVES: [&str; 8] = ["section", "label", ".start", "func", "table", "ncalldef", "alias", ".org"];
+
+#[derive(PartialEq)]
+enum FmtSection {
+    Code,
+    Data,
+}
+
+enum FmtLine {
+    Blank,
+    Comment { text: String, top: bool }, // top: was at column 0
+    Directive { code: String, comment: Option<String> },
+    Instr { mnem: String, args: Vec<String>, comment: Option<String> },
+    DataVar { name: String, ty: String, value: String, comment: Option<String> },
+    DataOther { code: String, comment: Option<String> }, // !align= and such
+}
+
+/// Splits code from a trailing comment the way the assembler does
+fn split_code_comment(line: &str) -> (&str, Option<&str>) {
+    let mut pos: usize = 0;
+    for tok in line.split_whitespace() {
+        let start = pos + line[pos..].find(tok).unwrap();
+        if tok.contains('#') || tok == ";" {
+            return (line[..start].trim_end(), Some(&line[start..]));
+        }
+        pos = start + tok.len();
+    }
+    (line, None)
+}
+
+/// Same for data lines, where `#` inside a quoted string isn't a comment
+fn split_data_comment(line: &str) -> (&str, Option<&str>) {
+    let mut in_quotes: bool = false;
+    let mut prev_ws: bool = true;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '#' | ';' if !in_quotes && prev_ws => {
+                return (line[..i].trim_end(), Some(&line[i..]));
+            }
+            _ => {}
+        }
+        prev_ws = c.is_whitespace();
+    }
+    (line, None)
+}
+
+/// First `n` lexems and the untouched rest of the line
+fn split_head(s: &str, n: usize) -> (Vec<&str>, &str) {
+    let mut head: Vec<&str> = Vec::new();
+    let mut rest: &str = s.trim_start();
+    while head.len() < n && !rest.is_empty() {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        head.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    (head, rest)
+}
+
+/// `0xca` -> `0xCA`, `0B101` -> `0b101`, `007` -> `7`, anything else is kept.
+/// `_` separators are the author's grouping, such literals stay as written
+fn norm_num(tok: &str) -> String {
+    let (sign, body) = match tok.strip_prefix('-') {
+        Some(b) => ("-", b),
+        None => ("", tok),
+    };
+    if body.contains('_') || body.starts_with(['-', '+']) || parse_num_literal(body).is_none() {
+        return tok.to_string();
+    }
+    let lower: String = body.to_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        return format!("{}0x{}", sign, hex.to_uppercase());
+    }
+    if let Some(bin) = lower.strip_prefix("0b") {
+        return format!("{}0b{}", sign, bin);
+    }
+    if let Some(oct) = lower.strip_prefix("0o") {
+        return format!("{}0o{}", sign, oct);
+    }
+    format!("{}{}", sign, parse_num_literal(body).unwrap())
+}
+
+fn norm_lexems(s: &str) -> Vec<String> {
+    s.split_whitespace().map(norm_num).collect()
+}
+
+fn parse_lines(src: &str) -> Vec<FmtLine> {
+    let mut section: FmtSection = FmtSection::Code;
+    let mut res: Vec<FmtLine> = Vec::new();
+
+    for raw in src.lines() {
+        let line: &str = raw.trim();
+        if line.is_empty() {
+            res.push(FmtLine::Blank);
+            continue;
+        }
+        let first: &str = line.split_whitespace().next().unwrap();
+        if first.starts_with('#') || first == ";" {
+            let top: bool = !raw.starts_with(char::is_whitespace);
+            res.push(FmtLine::Comment { text: line.to_string(), top });
+            continue;
+        }
+
+        if DIRECTIVES.contains(&first) {
+            let (code, comment) = split_code_comment(line);
+            let lexems: Vec<String> = norm_lexems(code);
+            if first == "section" {
+                section = match lexems.get(1).map(|s| s.as_str()) {
+                    Some("data") | Some("rodata") => FmtSection::Data,
+                    _ => FmtSection::Code,
+                };
+            }
+            res.push(FmtLine::Directive {
+                code: lexems.join(" "),
+                comment: comment.map(|c| c.to_string()),
+            });
+            continue;
+        }
+
+        if section == FmtSection::Code {
+            let (code, comment) = split_code_comment(line);
+            let lexems: Vec<String> = norm_lexems(code);
+            res.push(FmtLine::Instr {
+                mnem: lexems[0].clone(),
+                args: lexems[1..].to_vec(),
+                comment: comment.map(|c| c.to_string()),
+            });
+            continue;
+        }
+
+        let (code, comment) = split_data_comment(line);
+        let comment: Option<String> = comment.map(|c| c.to_string());
+        let (head, rest) = split_head(code, 2);
+        if first.starts_with('!') || head.len() < 2 {
+            res.push(FmtLine::DataOther { code: code.to_string(), comment });
+            continue;
+        }
+        // name [const] type value
+        let (ty, value): (String, &str) = match head[1] {
+            "const" => {
+                let (ty, rest) = split_head(rest, 1);
+                (format!("const {}", ty.first().unwrap_or(&"")), rest)
+            }
+            ty => (ty.to_string(), rest),
+        };
+        // quoted strings are kept byte for byte
+        let value: String = match value.contains('"') {
+            true => value.to_string(),
+            false => norm_lexems(value).join(" "),
+        };
+        res.push(FmtLine::DataVar {
+            name: head[0].to_string(),
+            ty,
+            value,
+            comment,
+        });
+    }
+    res
+}
+
+fn comment_of(line: &FmtLine) -> Option<&String> {
+    match line {
+        FmtLine::Directive { comment, .. }
+        | FmtLine::Instr { comment, .. }
+        | FmtLine::DataVar { comment, .. }
+        | FmtLine::DataOther { comment, .. } => comment.as_ref(),
+        _ => None,
+    }
+}
+
+/// Formats voxasm source. Runs of instructions or data variables (comment
+/// lines don't break a run) get their operands and trailing comments aligned.
+pub fn format_source(src: &str) -> String {
+    let lines: Vec<FmtLine> = parse_lines(src);
+    let mut out: Vec<String> = Vec::with_capacity(lines.len());
+
+    let mut i: usize = 0;
+    while i < lines.len() {
+        // a block ends at a blank line or a directive
+        let mut end: usize = i + 1;
+        if matches!(lines[i], FmtLine::Instr { .. } | FmtLine::DataVar { .. } | FmtLine::DataOther { .. }) {
+            while end < lines.len()
+                && matches!(
+                    lines[end],
+                    FmtLine::Instr { .. }
+                        | FmtLine::DataVar { .. }
+                        | FmtLine::DataOther { .. }
+                        | FmtLine::Comment { top: false, .. }
+                )
+            {
+                end += 1;
+            }
+        }
+        let block: &[FmtLine] = &lines[i..end];
+
+        let mut mnem_w: usize = 0;
+        let mut name_w: usize = 0;
+        let mut ty_w: usize = 0;
+        for line in block {
+            match line {
+                FmtLine::Instr { mnem, .. } => mnem_w = mnem_w.max(mnem.len()),
+                FmtLine::DataVar { name, ty, .. } => {
+                    name_w = name_w.max(name.len());
+                    ty_w = ty_w.max(ty.len());
+                }
+                _ => {}
+            }
+        }
+
+        let codes: Vec<String> = block
+            .iter()
+            .map(|line| match line {
+                FmtLine::Blank => String::new(),
+                FmtLine::Comment { text, top: true } => text.clone(),
+                FmtLine::Comment { text, top: false } => format!("{}{}", INDENT, text),
+                FmtLine::Directive { code, .. } => code.clone(),
+                FmtLine::Instr { mnem, args, .. } if args.is_empty() => format!("{}{}", INDENT, mnem),
+                FmtLine::Instr { mnem, args, .. } => {
+                    format!("{}{:<w$} {}", INDENT, mnem, args.join(" "), w = mnem_w)
+                }
+                FmtLine::DataVar { name, ty, value, .. } if value.is_empty() => {
+                    format!("{}{:<nw$} {}", INDENT, name, ty, nw = name_w)
+                }
+                FmtLine::DataVar { name, ty, value, .. } => format!(
+                    "{}{:<nw$} {:<tw$} {}",
+                    INDENT,
+                    name,
+                    ty,
+                    value,
+                    nw = name_w,
+                    tw = ty_w
+                ),
+                FmtLine::DataOther { code, .. } => format!("{}{}", INDENT, code),
+            })
+            .collect();
+
+        let comment_col: usize = block
+            .iter()
+            .zip(&codes)
+            .filter(|(l, _)| comment_of(l).is_some())
+            .map(|(_, c)| c.len())
+            .max()
+            .unwrap_or(0);
+        for (line, code) in block.iter().zip(codes) {
+            match comment_of(line) {
+                Some(c) if code.is_empty() => out.push(c.clone()),
+                Some(c) => out.push(format!("{:<w$}  {}", code, c, w = comment_col)),
+                None => out.push(code),
+            }
+        }
+        i = end;
+    }
+
+    let mut res: String = out.join("\n");
+    res.push('\n');
+    res
+}
+
+/// `voxvm fmt [--check] file.vvs..`, returns the process exit code.
+/// With --check files are only compared, unformatted ones make the exit code 1.
+pub fn fmt_cli(args: &[String]) -> i32 {
+    let check: bool = args.iter().any(|a| a == "--check");
+    let files: Vec<&String> = args.iter().filter(|a| !a.starts_with("--")).collect();
+    if files.is_empty() {
+        eprintln!("Usage: voxvm fmt [--check] file.vvs..");
+        return 1;
+    }
+
+    let mut code: i32 = 0;
+    for path in files {
+        let src: String = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("ERROR: Can't read {}: {}", path, e);
+                code = 1;
+                continue;
+            }
+        };
+        let formatted: String = format_source(&src);
+        if formatted == src {
+            continue;
+        }
+        if check {
+            println!("would reformat {}", path);
+            code = 1;
+            continue;
+        }
+        match fs::write(path, formatted) {
+            Ok(()) => println!("formatted {}", path),
+            Err(e) => {
+                eprintln!("ERROR: Can't write {}: {}", path, e);
+                code = 1;
+            }
+        }
+    }
+    code
+}
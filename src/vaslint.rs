@@ -0,0 +1,694 @@
+// `voxvm lint`: static checks for voxasm sources.
+// Sources are lexed the way the assembler does it, then the code items are
+// walked from every entry (.start, funcs, labels taken by `lea`) with a small
+// dataflow: which registers are surely written, their known types and the
+// data stack depth.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+
+use crate::asmalias::resolve_aliases;
+use crate::asmmacro::{expand_line, scan_clobbers};
+use crate::asmvreg::resolve_vregs;
+use crate::assembly::{parse_mem_operand, table_entries, voxasm_instr_table, LexTypes};
+use crate::vm::RegistersCount;
+
+const ALL_REGS: u32 = u32::MAX; // RegistersCount bits
+const NCALL_RET_REGS: usize = 7; // ncalls return up to r0..r6 (heap_stats)
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Ty {
+    Unknown,
+    Uint,
+    Int,
+    Float,
+    Addr,
+}
+
+impl Ty {
+    fn name(&self) -> &'static str {
+        match self {
+            Ty::Unknown => "unknown",
+            Ty::Uint => "uint",
+            Ty::Int => "int",
+            Ty::Float => "float",
+            Ty::Addr => "address",
+        }
+    }
+
+    fn join(self, other: Ty) -> Ty {
+        if self == other { self } else { Ty::Unknown }
+    }
+
+    /// Can a register of type `self` be used where `expected` is needed
+    fn fits(self, expected: Ty) -> bool {
+        match (self, expected) {
+            (Ty::Unknown, _) => true,
+            (Ty::Addr, Ty::Uint) => true, // pointer arithmetic
+            (a, b) => a == b,
+        }
+    }
+}
+
+/// How an instruction uses a register operand
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Acc {
+    R,
+    W,
+    RW,
+    Skip, // handled by the instruction itself
+}
+
+use Acc::*;
+
+pub(crate) fn reg_access(mnem: &str) -> &'static [Acc] {
+    match mnem {
+        "uload" | "uload32" | "iload" | "iload32" | "fload" | "lea" | "fgete" | "pop" | "dsload"
+        | "dslea" | "fnstind" | "alloc" | "sete" | "setne" | "setl" | "setg" | "setge" | "setle"
+        | "ldstr" | "ldbytes" | "lget" => &[W],
+        "cocreate" | "costatus" | "salloc" => &[W, R],
+        "rdcnt" => &[W, W],
+        "usqrt" | "iabs" | "ineg" | "isqrt" | "fabs" | "fneg" | "fsqrt" | "utoi" | "itou"
+        | "utof" | "itof" | "ftou" | "ftoi" | "ptou" | "utop" | "not" | "lnot" | "dsrload"
+        | "dsrlea" | "allocr" | "allocr_nogc" | "gsf" | "dsindex" | "strlen" => &[W, R],
+        "dsderef" => &[R, W],
+        "dsrderef" | "load32" => &[R, W, R],
+        "load" | "loadi" => &[R, W, R, R],
+        "udiv" | "urem" | "idiv" | "irem" | "fdiv" | "frem" | "dlbc" | "allocal" | "allocarena" | "strindex" => &[W, R, R],
+        "uinc" | "udec" | "iinc" | "idec" | "finc" | "fdec" => &[RW],
+        "uadd" | "umul" | "usub" | "upow" | "iadd" | "imul" | "isub" | "ipow" | "fadd"
+        | "fmul" | "fsub" | "fpow" | "or" | "and" | "xor" | "shl" | "shr" => &[RW, R],
+        "movr" | "cmovz" | "cmovl" | "cmovg" => &[Skip, R],
+        "xchg" | "ncall" => &[Skip, Skip],
+        _ => &[R, R, R, R], // compares, stores, push and friends only read
+    }
+}
+
+fn expected_type(mnem: &str) -> Option<Ty> {
+    match mnem {
+        "uadd" | "umul" | "usub" | "udiv" | "urem" | "ucmp" | "usqrt" | "upow" | "uinc"
+        | "udec" | "utoi" | "utof" | "utop" => Some(Ty::Uint),
+        "iadd" | "imul" | "isub" | "idiv" | "irem" | "icmp" | "iabs" | "ineg" | "isqrt"
+        | "ipow" | "iinc" | "idec" | "itou" | "itof" => Some(Ty::Int),
+        "fadd" | "fmul" | "fsub" | "fdiv" | "frem" | "fcmp" | "fcmp_eps" | "fabs" | "fneg"
+        | "fsqrt" | "fpow" | "finc" | "fdec" | "fsete" | "ftou" | "ftoi" => Some(Ty::Float),
+        "ptou" => Some(Ty::Addr),
+        _ => None,
+    }
+}
+
+fn result_type(mnem: &str) -> Ty {
+    match mnem {
+        "uload" | "uload32" | "lea" | "uadd" | "umul" | "usub" | "udiv" | "urem" | "usqrt"
+        | "upow" | "uinc" | "udec" | "itou" | "ftou" | "ptou" | "rdcnt" | "sete" | "setne" | "setl"
+        | "setg" | "setge" | "setle" | "cocreate" | "costatus" | "salloc" | "allocarena" | "dsindex" | "strindex" | "strlen" => Ty::Uint,
+        "iload" | "iload32" | "iadd" | "imul" | "isub" | "idiv" | "irem" | "iabs" | "ineg"
+        | "isqrt" | "ipow" | "iinc" | "idec" | "utoi" | "ftoi" => Ty::Int,
+        "fload" | "fgete" | "fadd" | "fmul" | "fsub" | "fdiv" | "frem" | "fabs" | "fneg"
+        | "fsqrt" | "fpow" | "finc" | "fdec" | "utof" | "itof" => Ty::Float,
+        "alloc" | "allocr" | "allocr_nogc" | "allocal" | "utop" => Ty::Addr,
+        _ => Ty::Unknown,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Diag {
+    pub line: usize, // 1-based
+    pub error: bool,
+    pub msg: String,
+}
+
+fn warn(line: usize, msg: String) -> Diag {
+    Diag { line, error: false, msg }
+}
+
+fn error(line: usize, msg: String) -> Diag {
+    Diag { line, error: true, msg }
+}
+
+struct Instr {
+    line: usize,
+    mnem: String,
+    regs: Vec<usize>,
+    label: Option<String>, // `@label` jump/lea target or switch table
+    func: Option<String>,  // `@func` of call/fnstind
+}
+
+enum Item {
+    Instr(Instr),
+    Table { line: usize, name: String, targets: Vec<String> },
+}
+
+#[derive(Clone, PartialEq)]
+struct State {
+    init: u32, // registers written on every path
+    types: [Ty; RegistersCount],
+    depth: Option<i64>, // data stack depth from the entry, None if paths disagree
+}
+
+struct Program {
+    items: Vec<Item>,
+    labels: HashMap<String, (usize, usize)>, // name -> (item index, line)
+    tables: HashMap<String, usize>,          // name -> item index
+    funcs: HashMap<String, usize>,           // name -> item index
+    start: Option<usize>,
+}
+
+fn parse_reg(arg: &str) -> Option<usize> {
+    match arg.strip_prefix('r').map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) if n < RegistersCount => Some(n),
+        _ => None,
+    }
+}
+
+fn parse(src: &str, diags: &mut BTreeSet<Diag>) -> Program {
+    let instr_table = voxasm_instr_table();
+    let mut prog = Program {
+        items: Vec::new(),
+        labels: HashMap::new(),
+        tables: HashMap::new(),
+        funcs: HashMap::new(),
+        start: None,
+    };
+
+    let mut src_lines: Vec<String> = src.lines().map(|l| l.to_string()).collect();
+    for d in resolve_aliases(&mut src_lines) {
+        diags.insert(match d.error {
+            true => error(d.line + 1, d.msg),
+            false => warn(d.line + 1, d.msg),
+        });
+    }
+    let clobbers: HashMap<String, u32> = scan_clobbers(&src_lines);
+    let mut expanded: Vec<(usize, String)> = Vec::with_capacity(src_lines.len());
+    for (ind, raw) in src_lines.into_iter().enumerate() {
+        match expand_line(&raw, &clobbers) {
+            None => expanded.push((ind, raw)),
+            Some(Ok(instrs)) => expanded.extend(instrs.into_iter().map(|i| (ind, i))),
+            Some(Err(e)) => {
+                diags.insert(error(ind + 1, e));
+            }
+        }
+    }
+
+    let expanded: Vec<(usize, String)> = match resolve_vregs(expanded) {
+        Ok(lines) => lines,
+        Err((ind, e)) => {
+            diags.insert(error(ind + 1, e));
+            Vec::new()
+        }
+    };
+
+    let mut in_code: bool = true;
+    for (ind, raw) in expanded {
+        let line: usize = ind + 1;
+        let lexems: Vec<&str> = raw
+            .split_whitespace()
+            .take_while(|l| !l.contains('#') && (*l != ";"))
+            .collect();
+        if lexems.is_empty() {
+            continue;
+        }
+        match lexems[0] {
+            "section" => {
+                in_code = lexems.get(1) == Some(&"text");
+                continue;
+            }
+            _ if !in_code => continue,
+            "ncalldef" | "clobbers" | ".org" => continue,
+            ".start" => {
+                prog.start = Some(prog.items.len());
+                continue;
+            }
+            "label" => {
+                if let Some(name) = lexems.get(1) {
+                    prog.labels.insert(name.to_string(), (prog.items.len(), line));
+                }
+                continue;
+            }
+            "func" => {
+                if let Some(name) = lexems.get(1) {
+                    prog.funcs.insert(name.to_string(), prog.items.len());
+                }
+                continue;
+            }
+            "table" => {
+                if let Some(name) = lexems.get(1) {
+                    let mut targets: Vec<String> = Vec::new();
+                    if let Some(default) = lexems.get(2) {
+                        targets.push(default.trim_start_matches('@').to_string());
+                    }
+                    for entry in table_entries(&lexems) {
+                        targets.push(entry.trim_start_matches('@').to_string());
+                    }
+                    prog.tables.insert(name.to_string(), prog.items.len());
+                    prog.items.push(Item::Table { line, name: name.to_string(), targets });
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        let ops: &Vec<LexTypes> = match instr_table.get(lexems[0]) {
+            Some(v) => v,
+            None => {
+                diags.insert(error(line, format!("unknown instruction '{}'", lexems[0])));
+                continue;
+            }
+        };
+        let ds_op: bool = matches!(ops[0], LexTypes::Op(0x70..=0x7F));
+        let mut instr = Instr {
+            line,
+            mnem: lexems[0].to_string(),
+            regs: Vec::new(),
+            label: None,
+            func: None,
+        };
+        for (arg, kind) in lexems[1..].iter().zip(ops.iter().skip(2)) {
+            match kind {
+                LexTypes::Reg(_) => match parse_reg(arg) {
+                    Some(r) => instr.regs.push(r),
+                    None => {
+                        diags.insert(error(line, format!("'{}' is not a register", arg)));
+                    }
+                },
+                LexTypes::Mem => match parse_mem_operand(arg).map(|(r, _)| r as usize).filter(|r| *r < RegistersCount) {
+                    Some(r) => instr.regs.push(r),
+                    None => {
+                        diags.insert(error(line, format!("'{}' is not a [rN+offset] operand", arg)));
+                    }
+                },
+                LexTypes::Addr(_) if arg.starts_with('@') && !ds_op => {
+                    instr.label = Some(arg[1..].to_string());
+                }
+                LexTypes::Value(_) | LexTypes::FuncInd(_) if arg.starts_with('@') => {
+                    instr.func = Some(arg[1..].to_string());
+                }
+                _ => {}
+            }
+        }
+        prog.items.push(Item::Instr(instr));
+    }
+    prog
+}
+
+impl Program {
+    fn line_of(&self, ind: usize) -> usize {
+        match &self.items[ind] {
+            Item::Instr(i) => i.line,
+            Item::Table { line, .. } => *line,
+        }
+    }
+
+    fn label_ind(&self, name: &str) -> Option<usize> {
+        self.labels.get(name).map(|(ind, _)| *ind)
+    }
+
+    /// Items control can go to after `ind`, fallthrough into a table or
+    /// past the end of the code isn't a successor
+    fn successors(&self, ind: usize) -> Vec<usize> {
+        let ins: &Instr = match &self.items[ind] {
+            Item::Instr(i) => i,
+            Item::Table { .. } => return Vec::new(),
+        };
+        let target: Option<usize> = ins.label.as_deref().and_then(|l| self.label_ind(l));
+        let mut res: Vec<usize> = match ins.mnem.as_str() {
+            "halt" | "ret" | "jmpr" | "abort" => return Vec::new(),
+            "jmp" => return target.into_iter().collect(),
+            "switch" => {
+                let table: Option<&Item> = ins
+                    .label
+                    .as_deref()
+                    .and_then(|t| self.tables.get(t))
+                    .map(|t| &self.items[*t]);
+                return match table {
+                    Some(Item::Table { targets, .. }) => {
+                        targets.iter().filter_map(|t| self.label_ind(t)).collect()
+                    }
+                    _ => Vec::new(),
+                };
+            }
+            "jz" | "jl" | "jg" | "jge" | "jle" | "jnz" | "jexc" => target.into_iter().collect(),
+            _ => Vec::new(),
+        };
+        if let Some(Item::Instr(_)) = self.items.get(ind + 1) {
+            res.push(ind + 1);
+        }
+        res
+    }
+
+    /// Does `ind` fall through to the next item
+    fn falls_through(&self, ind: usize) -> bool {
+        match &self.items[ind] {
+            Item::Instr(i) => !matches!(
+                i.mnem.as_str(),
+                "halt" | "ret" | "jmpr" | "jmp" | "switch" | "abort"
+            ),
+            Item::Table { .. } => false,
+        }
+    }
+
+    fn transfer(&self, ins: &Instr, st: &mut State, from_start: bool) -> Vec<Diag> {
+        let mut diags: Vec<Diag> = Vec::new();
+        let acc: &[Acc] = reg_access(&ins.mnem);
+        let expected: Option<Ty> = expected_type(&ins.mnem);
+
+        for (k, &r) in ins.regs.iter().enumerate() {
+            let a: Acc = acc.get(k).copied().unwrap_or(R);
+            if (a != R) && (a != RW) {
+                continue;
+            }
+            if st.init & (1 << r) == 0 {
+                diags.push(warn(ins.line, format!("r{} may be read before it is written", r)));
+            }
+            if let Some(exp) = expected {
+                if !st.types[r].fits(exp) {
+                    diags.push(warn(
+                        ins.line,
+                        format!(
+                            "{} expects {} in r{}, but it holds {}",
+                            ins.mnem,
+                            exp.name(),
+                            r,
+                            st.types[r].name()
+                        ),
+                    ));
+                }
+            }
+        }
+
+        let regs: &[usize] = &ins.regs;
+        match ins.mnem.as_str() {
+            "movr" if regs.len() == 2 => {
+                st.types[regs[0]] = st.types[regs[1]];
+                st.init |= 1 << regs[0];
+            }
+            "xchg" if regs.len() == 2 => {
+                let (a, b) = (regs[0], regs[1]);
+                st.types.swap(a, b);
+                let (init_a, init_b) = ((st.init >> a) & 1, (st.init >> b) & 1);
+                st.init = (st.init & !(1 << a) & !(1 << b)) | (init_b << a) | (init_a << b);
+            }
+            "cmovz" | "cmovl" | "cmovg" if regs.len() == 2 => {
+                // the destination may keep its old value
+                st.types[regs[0]] = st.types[regs[0]].join(st.types[regs[1]]);
+            }
+            "or" | "and" | "xor" | "not" | "shl" | "shr" | "lnot" if regs.len() == 2 => {
+                st.types[regs[0]] = st.types[regs[1]];
+                st.init |= 1 << regs[0];
+            }
+            "ncall" => {
+                for r in 0..NCALL_RET_REGS {
+                    st.types[r] = Ty::Unknown;
+                    st.init |= 1 << r;
+                }
+            }
+            "call" | "callr" | "popall" | "coresume" | "coyield" => {
+                // anything could be written by the callee, the restored frame
+                // or the other side of a coroutine switch
+                st.types = [Ty::Unknown; RegistersCount];
+                st.init = ALL_REGS;
+            }
+            _ => {
+                for (k, &r) in regs.iter().enumerate() {
+                    if matches!(acc.get(k), Some(W) | Some(RW)) {
+                        st.types[r] = result_type(&ins.mnem);
+                        st.init |= 1 << r;
+                    }
+                }
+            }
+        }
+
+        diags.extend(transfer_depth(ins, st, from_start));
+        diags
+    }
+
+    /// Analyzes from .start, every func and every label taken by `lea`,
+    /// returns the reached items
+    fn analyze_all(&self, diags: &mut BTreeSet<Diag>) -> HashSet<usize> {
+        let mut reached: HashSet<usize> = HashSet::new();
+        if self.items.is_empty() {
+            return reached;
+        }
+        self.analyze(self.start.unwrap_or(0), true, &mut reached, diags);
+
+        let taken_labels: Vec<usize> = self
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Instr(ins) if ins.mnem == "lea" => ins.label.as_deref().and_then(|l| self.label_ind(l)),
+                _ => None,
+            })
+            .collect();
+        for &ind in self.funcs.values().chain(&taken_labels) {
+            if ind < self.items.len() {
+                self.analyze(ind, false, &mut reached, diags);
+            }
+        }
+        reached
+    }
+
+    /// Walks everything reachable from `entry` until the states settle,
+    /// then reports what the settled states show
+    fn analyze(&self, entry: usize, from_start: bool, reached: &mut HashSet<usize>, diags: &mut BTreeSet<Diag>) {
+        for (&ind, st) in &self.settle(entry, from_start, diags) {
+            let ins: &Instr = match &self.items[ind] {
+                Item::Instr(i) => i,
+                Item::Table { .. } => continue,
+            };
+            reached.insert(ind);
+            diags.extend(self.transfer(ins, &mut st.clone(), from_start));
+            if self.falls_through(ind) {
+                match self.items.get(ind + 1) {
+                    Some(Item::Table { name, .. }) => {
+                        diags.insert(warn(ins.line, format!("execution falls through into table '{}'", name)));
+                    }
+                    None => {
+                        diags.insert(warn(ins.line, "execution runs past the end of the code".to_string()));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// States before every item reachable from `entry`, joins of paths with
+    /// different data stack depths are reported
+    fn settle(&self, entry: usize, from_start: bool, diags: &mut BTreeSet<Diag>) -> HashMap<usize, State> {
+        let initial = State {
+            init: if from_start { 0 } else { ALL_REGS }, // funcs get arguments in registers
+            types: [Ty::Unknown; RegistersCount],
+            depth: Some(0),
+        };
+        let mut states: HashMap<usize, State> = HashMap::new();
+        states.insert(entry, initial);
+        let mut work: Vec<usize> = vec![entry];
+
+        while let Some(ind) = work.pop() {
+            let ins: &Instr = match &self.items[ind] {
+                Item::Instr(i) => i,
+                Item::Table { .. } => continue,
+            };
+            let mut st: State = states[&ind].clone();
+            self.transfer(ins, &mut st, from_start);
+            for succ in self.successors(ind) {
+                match states.get_mut(&succ) {
+                    None => {
+                        states.insert(succ, st.clone());
+                        work.push(succ);
+                    }
+                    Some(old) => {
+                        let mut joined: State = old.clone();
+                        joined.init &= st.init;
+                        for r in 0..RegistersCount {
+                            joined.types[r] = old.types[r].join(st.types[r]);
+                        }
+                        joined.depth = match (old.depth, st.depth) {
+                            (Some(a), Some(b)) if a != b => {
+                                diags.insert(warn(
+                                    self.line_of(succ),
+                                    format!("data stack depth differs on paths joining here ({} vs {})", a.min(b), a.max(b)),
+                                ));
+                                None
+                            }
+                            (Some(a), Some(_)) => Some(a),
+                            _ => None,
+                        };
+                        if joined != *old {
+                            *old = joined;
+                            work.push(succ);
+                        }
+                    }
+                }
+            }
+        }
+        states
+    }
+}
+
+/// Data stack slots `mnem` pushes, negative for pops
+fn stack_slots(mnem: &str) -> i64 {
+    let all: i64 = RegistersCount as i64 - 1; // pushall leaves out the last register
+    match mnem {
+        "push" => 1,
+        "pushall" => all,
+        "pop" => -1,
+        "popall" => -all,
+        _ => 0,
+    }
+}
+
+/// Data stack depth effect of `ins`, in slots
+fn transfer_depth(ins: &Instr, st: &mut State, from_start: bool) -> Vec<Diag> {
+    let mut diags: Vec<Diag> = Vec::new();
+    let slots: i64 = stack_slots(&ins.mnem);
+    match (ins.mnem.as_str(), st.depth) {
+        ("ret", Some(d)) if d > 0 => {
+            diags.push(warn(
+                ins.line,
+                format!("{} value(s) pushed in this function are still on the data stack at ret", d),
+            ));
+        }
+        // functions may pop arguments pushed by the caller
+        (_, Some(d)) if from_start && (slots < 0) && (d + slots < 0) => {
+            diags.push(warn(ins.line, "pop from an empty data stack".to_string()));
+            st.depth = Some(d + slots);
+        }
+        (_, Some(d)) => st.depth = Some(d + slots),
+        _ => {}
+    }
+    diags
+}
+
+/// Deepest data stack of every function, in slots over the depth at its
+/// entry, with the depth warnings of its body. Functions whose paths
+/// disagree on the depth have no entry
+pub(crate) fn stack_usage(src: &str) -> (HashMap<String, u64>, Vec<Diag>) {
+    let mut diags: BTreeSet<Diag> = BTreeSet::new();
+    let prog: Program = parse(src, &mut diags);
+    diags.clear(); // the assembler reports its own errors
+    let mut res: HashMap<String, u64> = HashMap::new();
+    for (name, &entry) in &prog.funcs {
+        if entry >= prog.items.len() {
+            continue;
+        }
+        let mut max_depth: Option<i64> = Some(0);
+        for (ind, st) in prog.settle(entry, false, &mut diags) {
+            let Item::Instr(ins) = &prog.items[ind] else {
+                continue;
+            };
+            let mut after: State = st.clone();
+            diags.extend(transfer_depth(ins, &mut after, false));
+            max_depth = match (max_depth, st.depth, after.depth) {
+                (Some(m), Some(before), Some(after)) => Some(m.max(before).max(after)),
+                _ => None,
+            };
+        }
+        if let Some(depth) = max_depth {
+            res.insert(name.clone(), depth as u64);
+        }
+    }
+    (res, diags.into_iter().collect())
+}
+
+/// Source lines of instructions that no entry reaches, for the `--opt` pass
+pub(crate) fn unreachable_lines(src: &str) -> Vec<usize> {
+    let mut diags: BTreeSet<Diag> = BTreeSet::new();
+    let prog: Program = parse(src, &mut diags);
+    let reached: HashSet<usize> = prog.analyze_all(&mut diags);
+    prog.items
+        .iter()
+        .enumerate()
+        .filter_map(|(ind, item)| match item {
+            Item::Instr(ins) if !reached.contains(&ind) => Some(ins.line),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Lints voxasm source, diagnostics are sorted by line
+pub fn lint_source(src: &str) -> Vec<Diag> {
+    let mut diags: BTreeSet<Diag> = BTreeSet::new();
+    let prog: Program = parse(src, &mut diags);
+
+    let mut used_labels: HashSet<&str> = HashSet::new();
+    for item in &prog.items {
+        match item {
+            Item::Instr(ins) => {
+                if let Some(label) = &ins.label {
+                    used_labels.insert(label);
+                    if prog.label_ind(label).is_none() && !prog.tables.contains_key(label) {
+                        diags.insert(error(ins.line, format!("undefined label '{}'", label)));
+                    }
+                }
+                if let Some(func) = &ins.func {
+                    if !prog.funcs.contains_key(func) {
+                        diags.insert(error(ins.line, format!("call to undefined function '{}'", func)));
+                    }
+                }
+            }
+            Item::Table { line, targets, .. } => {
+                for t in targets {
+                    used_labels.insert(t);
+                    if prog.label_ind(t).is_none() {
+                        diags.insert(error(*line, format!("undefined label '{}' in table", t)));
+                    }
+                }
+            }
+        }
+    }
+    for (name, (_, line)) in &prog.labels {
+        if !used_labels.contains(name.as_str()) {
+            diags.insert(warn(*line, format!("label '{}' is never used", name)));
+        }
+    }
+    for (name, ind) in &prog.tables {
+        if !used_labels.contains(name.as_str()) {
+            diags.insert(warn(prog.line_of(*ind), format!("table '{}' is never used", name)));
+        }
+    }
+
+    let reached: HashSet<usize> = prog.analyze_all(&mut diags);
+
+    // one report per run of unreachable instructions
+    let mut in_run: bool = false;
+    for (ind, item) in prog.items.iter().enumerate() {
+        match item {
+            Item::Instr(ins) if !reached.contains(&ind) => {
+                if !in_run {
+                    diags.insert(warn(ins.line, "unreachable code".to_string()));
+                }
+                in_run = true;
+            }
+            Item::Instr(_) => in_run = false,
+            Item::Table { .. } => {}
+        }
+    }
+
+    diags.into_iter().collect()
+}
+
+/// `voxvm lint file.vvs..`, returns the process exit code: 1 if anything was found
+pub fn lint_cli(args: &[String]) -> i32 {
+    if args.is_empty() {
+        eprintln!("Usage: voxvm lint file.vvs..");
+        return 1;
+    }
+
+    let mut code: i32 = 0;
+    for path in args {
+        let src: String = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("ERROR: Can't read {}: {}", path, e);
+                code = 1;
+                continue;
+            }
+        };
+        for d in lint_source(&src) {
+            let level: &str = if d.error { "error" } else { "warning" };
+            println!("{}:{}: {}: {}", path, d.line, level, d.msg);
+            code = 1;
+        }
+    }
+    code
+}
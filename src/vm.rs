@@ -3,7 +3,7 @@
 use rand::rngs::ThreadRng;
 
 use crate::{
-    callstack::CallStack, defnative, exceptions::Exception, fileformats::VoxExeHeader, func_ops::{op_call, op_callr, op_fnstind, op_ret}, gc::GC, heap::{op_alloc, op_allocr, op_allocr_nogc, op_dlbc, op_free, op_load, op_memcpy, op_store, op_storedat, op_ubd, Heap}, misclib::*, native::{NativeService, VMValue}, nativefiles::FileController, nativenet::NetController, registers::{self, Register}, stack::{op_gsf, op_pop, op_popall, op_push, op_pushall, op_usf, VMStack}
+    abort::op_abort, arena::{op_allocarena, ArenaTable}, callstack::CallStack, coredump, coroutine::{op_cocreate, op_coresume, op_coslice, op_costatus, op_coyield, slice_tick, Coroutines}, coverage::Coverage, dstype::{DsType, DS_HEADER}, exceptions::{Exception, PendingExc}, fileformats::{VoxExeHeader, SECT_DATA_SYMBOLS, SECT_FUNC_META, SECT_INTERN, SECT_LINES, SECT_NCALLS, SECT_RODATA, SECT_STACK_DEPTH, SECT_SYMBOLS, read_data_symbols, read_line_table, read_ncalls, read_stack_depths, read_symbols}, func_ops::{op_call, op_callr, op_fnstind, op_ret}, gc::{reachable_from, GcSnapshot, GC}, hotreload::{merge_data, read_data_image, DataWatch}, intern::InternTable, heapsnap::SnapBlock, heap::{op_alloc, op_allocal, op_allocr, op_allocr_nogc, op_dlbc, op_free, op_gcadopt, op_gcrelease, op_load, op_load32, op_loadi, op_memcpy, op_store, op_storedat, op_storei, op_store32, op_ubd, AllocSite, Heap, HeapBlock}, memprof::{record_data, record_heap, MemProfile}, misclib::*, native::{HookFrame, HostCall, NSysError, NativeService, NcallSource, OpHooks, VMHookFunction, VMValue}, nativeasm::AsmModule, nativeaudio::AudioController, nativeevent::EventQueue, nativeerr::{native_fault, NativeErrKind, NativeError, NativeSubsys}, nativefiles::FileController, nativemmap::MmapTable, nativenet::NetController, nativeterm::{AnsiMode, TermState}, output::VmOutput, pause::PauseHandle, registers::Register, scratch::{op_salloc, op_sfree, Scratch}, segments::{SegmKind, SegmentTable}, stack::{op_gsf, op_lget, op_lset, op_pop, op_popall, op_push, op_pushall, op_usf, VMStack}, trace::Trace, vmmemory::VmMemory, vvelink::VveImage
 };
 use core::panic;
 use std::{convert::TryFrom, time::Duration};
@@ -12,6 +12,7 @@ use std::{
     fmt::Result,
     fs::{self, File},
     io::Write,
+    thread::{self, JoinHandle},
     time::Instant,
 };
 
@@ -32,9 +33,9 @@ pub enum RegTypes {
 pub struct VM {
     pub registers: [Register; RegistersCount],
     pub reg_types: [RegTypes; RegistersCount],
-    flags: [u8; 4], // of, zf, nf, cf
+    pub flags: [u8; 4], // of, zf, nf, cf
     pub ip: usize,
-    pub memory: Vec<u8>, // dividing by each bytes, then can be grouped
+    pub memory: VmMemory, // dividing by each bytes, then can be grouped
     pub stack: VMStack,
     pub heap: Heap,
     pub gc: GC,
@@ -42,18 +43,91 @@ pub struct VM {
     data_size: u64,
     pub nativesys: NativeService,
     running: bool,
-    float_epsilon: f64,
+    pub float_epsilon: f64,
     pub func_table: Vec<u64>,
+    pub func_clobbers: HashMap<usize, u32>, // func ind -> clobbered regs mask
+    pub func_stack_depths: HashMap<usize, u64>, // func ind -> deepest data stack of its body, in slots
+    pub stack_precheck: bool, // --stack-precheck: calls check func_stack_depths against the stack limit
+    pub abi_autosave: bool,
+    pub func_names: HashMap<String, usize>, // func name -> func ind
+    pub data_names: HashMap<String, usize>, // data var name -> rel addr
+    pub line_table: Vec<(u64, u32)>,        // instr addr -> source line
+    pub required_ncalls: Vec<u16>,          // SECT_NCALLS of the loaded image
+    pub coverage: Option<Coverage>,
+    pub trace: Option<Trace>,
+    pub mem_profile: Option<MemProfile>, // --mem-profile, see memprof.rs
+    pub output: VmOutput, // guest stdout/stderr sinks
+    pub last_native_err: Option<NativeError>,
+    pub shadow_stack: Option<Vec<u64>>, // return addresses copy, integrity mode
+    pub quiet: bool,                      // --quiet: no INFO/DBG chatter
+    pub gc_concurrent: bool,              // mark on a helper thread, sweep at safepoints
+    gc_pending: Option<JoinHandle<Vec<(u64, u64)>>>,
+    data_image: Vec<u8>,                  // data segment as loaded, base for hot reload
+    ds_str_caps: HashMap<usize, u64>,     // str var rel addr -> bytes allocated by the assembler
+    pub data_watch: Option<DataWatch>,
     pub call_stack: CallStack,
     pub rec_depth_max: usize,
-    pub exceptions_active: Vec<Exception>,
+    pub rec_depth_ceiling: usize,   // highest rec_depth_max `ncall @rec_limit_set` may set
+    pub stack_slots_max: usize,     // data stack slots, usize::MAX is no limit
+    pub stack_slots_ceiling: usize, // highest stack_slots_max `ncall @stack_limit_set` may set
+    pub exceptions_active: PendingExc,
+    pub max_pending_exc: Option<usize>, // halt when more exceptions go unhandled
+    pub max_total_mem: Option<u64>,     // memory + heap + stacks budget, see memcap.rs
+    pub heap_snaps: HashMap<String, Vec<SnapBlock>>, // named snapshots, see heapsnap.rs
     pub randgen: ThreadRng,
     pub fc: FileController,
     pub nc: NetController,
+    pub term: TermState, // terminal ncalls, see nativeterm.rs
+    pub audio: AudioController, // audio outputs, see nativeaudio.rs
+    pub events: EventQueue, // input events, clones feed it from other threads, see nativeevent.rs
+    pub segments: SegmentTable,
+    pub interned: InternTable,
+    pub instr_count: u64, // instructions executed so far
+    pub code_writes: u64, // writes outside of the data segments (self-modifying code)
+    pub clock_start: Instant,
+    pub pause: PauseHandle,   // host-side pause flag, see pause.rs
+    pub yield_on_pause: bool, // paused `run()` returns instead of blocking
+    pub coros: Coroutines,    // guest coroutines, see coroutine.rs
+    pub scratch: Scratch,     // frame-scoped buffers, see scratch.rs
+    pub mmaps: MmapTable,     // host files mapped into guest memory, see nativemmap.rs
+    pub arenas: ArenaTable,   // request-scoped allocation arenas, see arena.rs
+    pub aborted: bool,        // stopped by `abort`, see abort.rs
+    pub abort_coredump: bool, // --abort-coredump
+    pub allow_native_load: bool, // --allow-native-load: `ncall @native_load` may load libraries
+    pub asm_modules: Vec<AsmModule>, // runtime assembled code, see nativeasm.rs
 }
 
 pub type InstructionHandler = fn(&mut VM);
 
+/// How `VM::run_with` executes code: one call runs the instruction at ip,
+/// or more of them, and returns how many ran (at least 1). GC, pauses,
+/// data reload and coroutine slicing stay with the run loop, between calls
+pub trait Dispatch {
+    fn dispatch(&mut self, vm: &mut VM) -> u64;
+}
+
+/// Decodes the opcode at ip from memory, one instruction per call
+pub struct Interpreter;
+
+impl Dispatch for Interpreter {
+    #[inline(always)]
+    fn dispatch(&mut self, vm: &mut VM) -> u64 {
+        let opcode: u8 = vm.memory[vm.ip];
+        vm.exec_instr(opcode, BLOCK_END);
+        1
+    }
+}
+
+/// `next` of `VM::exec_instr` for the last instruction of a block
+pub const BLOCK_END: usize = usize::MAX;
+
+/// First byte of two-byte opcodes, the second one indexes EXT_OPERATIONS.
+/// New and rarely used instructions go there, the one-byte space is nearly full
+pub const EXT_PREFIX: u8 = 0xFE;
+
+/// Return address that halts the VM instead of jumping
+pub const HALT_RETADDR: u64 = u64::MAX;
+
 impl VM {
     pub fn new(
         init_mem: usize,
@@ -66,7 +140,7 @@ impl VM {
             reg_types: [RegTypes::uint64; 32],
             flags: [0; 4],
             ip: 0x0,
-            memory: Vec::with_capacity(init_mem),
+            memory: VmMemory::with_capacity(init_mem),
             stack: VMStack::new(init_stack),
             heap: Heap::new(init_heap),
             data_base: 0x0,
@@ -75,84 +149,705 @@ impl VM {
             running: true,
             float_epsilon: 1e-10,
             func_table: Vec::new(),
+            func_clobbers: HashMap::new(),
+            func_stack_depths: HashMap::new(),
+            stack_precheck: false,
+            abi_autosave: false,
+            func_names: HashMap::new(),
+            data_names: HashMap::new(),
+            line_table: Vec::new(),
+            required_ncalls: Vec::new(),
+            coverage: None,
+            trace: None,
+            mem_profile: None,
+            output: VmOutput::stdio(),
+            last_native_err: None,
+            shadow_stack: None,
+            quiet: false,
+            gc_concurrent: false,
+            gc_pending: None,
+            data_image: Vec::new(),
+            ds_str_caps: HashMap::new(),
+            data_watch: None,
             call_stack: CallStack::new(),
             rec_depth_max: max_recursion_depth,
-            exceptions_active: Vec::new(),
+            rec_depth_ceiling: max_recursion_depth,
+            stack_slots_max: usize::MAX,
+            stack_slots_ceiling: usize::MAX,
+            exceptions_active: PendingExc::default(),
+            max_pending_exc: None,
+            max_total_mem: None,
+            heap_snaps: HashMap::new(),
             gc: GC::new(),
             randgen: ThreadRng::default(),
             fc: FileController::new(),
             nc: NetController::new(),
-        }
-    }
-    pub fn load_vvr(&mut self, input_file_name: &str) {
+            term: TermState::new(AnsiMode::Auto),
+            audio: AudioController::new(),
+            events: EventQueue::default(),
+            segments: SegmentTable::new(),
+            interned: InternTable::new(),
+            instr_count: 0,
+            code_writes: 0,
+            clock_start: Instant::now(),
+            pause: PauseHandle::new(),
+            yield_on_pause: false,
+            coros: Coroutines::new(),
+            scratch: Scratch::new(),
+            mmaps: MmapTable::new(),
+            arenas: ArenaTable::new(),
+            aborted: false,
+            abort_coredump: false,
+            allow_native_load: false,
+            asm_modules: Vec::new(),
+        }
+    }
+    /// Loads a raw image at address 0 and starts it from `entry`.
+    /// Images bigger than `max_size` bytes are rejected.
+    pub fn load_vvr(
+        &mut self,
+        input_file_name: &str,
+        entry: usize,
+        max_size: usize,
+    ) -> std::result::Result<(), String> {
         // vvr = voxvm raw
-        let mut bctr: usize = 0;
-        match fs::read(input_file_name) {
-            Ok(bytes) => {
-                for byte in &bytes {
-                    self.memory[bctr] = *byte;
-                    bctr += 1;
-                }
+        let too_big = |len: u64| format!("image is {} bytes, limit is {} bytes", len, max_size);
+        // checked before reading too, so a huge file isn't read just to be refused
+        let file_len: u64 = fs::metadata(input_file_name).map_err(|err| err.to_string())?.len();
+        if file_len > max_size as u64 {
+            return Err(too_big(file_len));
+        }
+        let bytes: Vec<u8> = fs::read(input_file_name).map_err(|err| err.to_string())?;
+        if bytes.len() > max_size {
+            return Err(too_big(bytes.len() as u64));
+        }
+        if entry >= bytes.len() {
+            return Err(format!(
+                "entry 0x{:x} is outside of the {} bytes image",
+                entry,
+                bytes.len()
+            ));
+        }
+
+        self.memory.extend_from_slice(&bytes);
+        // raw images have no layout info, so it's all code
+        self.segments.push(SegmKind::Code, 0, bytes.len());
+        self.ip = entry;
+        Ok(())
+    }
+
+    pub fn load_vve(&mut self, input_file_name: &str, minVveVersion: u16) {
+        // vve = voxvm executable
+        #[cfg(feature = "mmap-vve")]
+        match crate::vmmemory::map_vve(input_file_name, minVveVersion) {
+            Ok(Some((header, body))) => {
+                self.memory.adopt(body);
+                return self.load_vve_header(header);
             }
+            Ok(None) => {}
+            Err(err) => panic!("CRITICAL: Can't read .vve file. Error: {}", err),
+        }
+        match VveImage::load(input_file_name, minVveVersion) {
+            Ok(img) => self.load_vve_image(img),
             Err(err) => {
-                panic!("CRITICAL: Can't read .vvr file. Error: {}", err)
+                panic!("CRITICAL: Can't read .vve file. Error: {}", err)
             }
         }
     }
 
-    pub fn load_vve(&mut self, input_file_name: &str, minVveVersion: u16) {
-        // vve = voxvm executable
-        let fileHeader: VoxExeHeader = VoxExeHeader::load(input_file_name, minVveVersion).unwrap();
+    /// Loads an already read (or linked, see `--stdlib`) vve image
+    pub fn load_vve_image(&mut self, img: VveImage) {
+        self.memory.extend_from_slice(&img.body);
+        self.load_vve_header(img.header);
+    }
 
-        let header_size: usize = (0x30 + fileHeader.func_table_len * 16) as usize;
+    /// Layout, entry point and sections of an image whose body is in memory
+    fn load_vve_header(&mut self, fileHeader: VoxExeHeader) {
         self.ip = fileHeader.entry_point as usize;
         self.data_base = fileHeader.data_base;
         self.data_size = fileHeader.data_size;
         self.func_table = fileHeader.func_table.clone();
 
-        match fs::read(input_file_name) {
-            Ok(bytes) => {
-                for byte in &bytes[header_size..] {
-                    self.memory.push(*byte);
+        let data_base = self.data_base as usize;
+        let data_end: usize = match self.data_size {
+            0 => self.memory.len(), // older assemblers left data_size empty
+            size => data_base + size as usize,
+        };
+        // images without SECT_RODATA rely on the per-variable const bit only
+        let ro_end: usize = match fileHeader.section(SECT_RODATA) {
+            Some(sect) => (data_base + args_to_u64(&sect.data[0..8]) as usize).min(data_end),
+            None => data_base,
+        };
+        self.segments.push(SegmKind::Code, 0, data_base);
+        if ro_end > data_base {
+            self.segments.push(SegmKind::ROData, data_base, ro_end);
+        }
+        self.segments.push(SegmKind::Data, ro_end, data_end);
+        self.data_image = self.memory[data_base..data_end].to_vec();
+
+        if let Some(sect) = fileHeader.section(SECT_INTERN) {
+            self.load_interned(&sect.data);
+        }
+        if let Some(sect) = fileHeader.section(SECT_SYMBOLS) {
+            self.load_symbols(&sect.data);
+        }
+        if let Some(sect) = fileHeader.section(SECT_DATA_SYMBOLS) {
+            for (rel_addr, name) in read_data_symbols(&sect.data) {
+                self.data_names.insert(name, rel_addr as usize);
+            }
+        }
+        if let Some(sect) = fileHeader.section(SECT_LINES) {
+            self.line_table = read_line_table(&sect.data);
+        }
+        if let Some(sect) = fileHeader.section(SECT_NCALLS) {
+            self.required_ncalls = read_ncalls(&sect.data);
+        }
+        if let Some(sect) = fileHeader.section(SECT_FUNC_META) {
+            let count: usize = args_to_u64(&sect.data[0..8]) as usize;
+            for i in 0..count {
+                let entry: &[u8] = &sect.data[(8 + i * 16)..(24 + i * 16)];
+                let ind: usize = args_to_u64(&entry[0..8]) as usize;
+                let clobbers: u32 = args_to_u64(&entry[8..16]) as u32;
+                self.func_clobbers.insert(ind, clobbers);
+            }
+        }
+        if let Some(sect) = fileHeader.section(SECT_STACK_DEPTH) {
+            self.func_stack_depths = read_stack_depths(&sect.data).into_iter().collect();
+        }
+    }
+
+    /// Ncall codes the loaded image uses that neither the std calls nor the
+    /// loaded native configs provide. Check after reading native configs
+    pub fn missing_ncalls(&self) -> Vec<u16> {
+        self.required_ncalls.iter().copied().filter(|c| !self.nativesys.has_code(*c)).collect()
+    }
+
+    fn load_symbols(&mut self, sect: &[u8]) {
+        for (ind, name) in read_symbols(sect) {
+            self.func_names.insert(name, ind);
+        }
+    }
+
+    /// `--define name=value`: overwrites the data variable `name` before the
+    /// run. The value is parsed by the variable's type, arrays take comma
+    /// separated elements (the rest of the array is zeroed), a str value has
+    /// to fit into the length the assembler allocated. Const variables stay
+    pub fn define_data(&mut self, name: &str, value: &str) -> std::result::Result<(), String> {
+        let rel_addr: usize = match self.data_names.get(name) {
+            Some(v) => *v,
+            None => return Err(format!("No data variable named '{}'", name)),
+        };
+        let var_addr: usize = self.data_base as usize + rel_addr;
+        let type_byte: u8 = self.memory[var_addr];
+        let ds_type: DsType = match DsType::decode(type_byte) {
+            Some(t) => t,
+            None => return Err(format!("'{}' has an unknown type {:#x}", name, type_byte)),
+        };
+        if DsType::is_const(type_byte) {
+            return Err(format!("'{}' is const", name));
+        }
+        let len: u64 = args_to_u64(&self.memory[(var_addr + 1)..(var_addr + 9)]);
+        let payload: usize = var_addr + DS_HEADER;
+
+        if ds_type == DsType::Str {
+            let text: &str = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+            let bytes: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+            let cap: u64 = *self.ds_str_caps.entry(rel_addr).or_insert(len);
+            if bytes.len() as u64 > cap {
+                return Err(format!("'{}' has room for {} utf16 bytes, \"{}\" takes {}", name, cap, text, bytes.len()));
+            }
+            self.memory[payload..(payload + bytes.len())].copy_from_slice(&bytes);
+            self.memory[(payload + bytes.len())..(payload + cap as usize)].fill(0);
+            self.memory[(var_addr + 1)..(var_addr + 9)].copy_from_slice(&(bytes.len() as u64).to_be_bytes());
+            return Ok(());
+        }
+
+        let elems: Vec<&str> = match ds_type.is_indexable() {
+            true => value.split(',').map(str::trim).collect(),
+            false => vec![value.trim()],
+        };
+        let mut bytes: Vec<u8> = Vec::with_capacity(len as usize);
+        for elem in &elems {
+            match ds_type.parse_value(elem) {
+                Some(v) => bytes.extend_from_slice(&v),
+                None => return Err(format!("'{}' is not a {} value", elem, ds_type.name())),
+            }
+        }
+        if bytes.len() as u64 > len {
+            let room: u64 = len / ds_type.elem_width() as u64;
+            return Err(format!("'{}' has room for {} values, got {}", name, room, elems.len()));
+        }
+        bytes.resize(len as usize, 0);
+        self.memory[payload..(payload + bytes.len())].copy_from_slice(&bytes);
+        Ok(())
+    }
+
+    /// Prepares the VM to run a single function: sets IP to it,
+    /// loads args into r1.. and pushes a return that halts the VM.
+    /// Call `run` (or `run_with`) afterwards, r0 holds the result,
+    /// `run_function` does both.
+    pub fn prepare_function(&mut self, name: &str, args: &[Register]) -> std::result::Result<(), String> {
+        let ind: usize = match self.func_names.get(name) {
+            Some(v) => *v,
+            None => return Err(format!("No function named '{}'", name)),
+        };
+        let addr: u64 = match self.func_table.get(ind) {
+            Some(v) => *v,
+            None => return Err(format!("Function '{}' has no address", name)),
+        };
+        if args.len() >= RegistersCount {
+            return Err(format!("Too many arguments: {}", args.len()));
+        }
+        for (i, arg) in args.iter().enumerate() {
+            self.registers[i + 1] = *arg;
+            self.reg_types[i + 1] = match arg {
+                Register::uint(_) => RegTypes::uint64,
+                Register::int(_) => RegTypes::int64,
+                Register::float(_) => RegTypes::float64,
+                Register::StrAddr(_) => RegTypes::StrAddr,
+                Register::address(_) => RegTypes::address,
+                Register::ds_addr(_) => RegTypes::ds_addr,
+            };
+        }
+        self.call_stack.push_saved(HALT_RETADDR, ind, Vec::new());
+        self.shadow_push(HALT_RETADDR);
+        if let Some(trace) = &mut self.trace {
+            trace.enter(ind);
+        }
+        self.ip = addr as usize;
+        Ok(())
+    }
+
+    /// Calls function `name` with `args` in r1.., runs it until it returns
+    /// and gives back r0. Fails if the function is unknown or left
+    /// exceptions unhandled.
+    pub fn run_function(&mut self, name: &str, args: &[Register]) -> std::result::Result<Register, String> {
+        self.prepare_function(name, args)?;
+        self.running = true;
+        self.run();
+        if !self.exceptions_active.is_empty() {
+            return Err(format!("'{}' raised {}", name, self.exceptions_active.names()));
+        }
+        Ok(self.registers[0])
+    }
+
+    pub fn shadow_push(&mut self, retaddr: u64) {
+        if let Some(shadow) = &mut self.shadow_stack {
+            shadow.push(retaddr);
+        }
+    }
+
+    /// Validates a popped return address against the shadow stack.
+    /// On mismatch coredumps, raises CallStackSmash and halts.
+    pub fn shadow_check(&mut self, retaddr: u64) -> bool {
+        let expected: Option<u64> = match &mut self.shadow_stack {
+            Some(shadow) => shadow.pop(),
+            None => return true,
+        };
+        if expected == Some(retaddr) {
+            return true;
+        }
+        if let Err(e) = self.err_coredump() {
+            eprintln!("Error creating coredump: {}", e);
+        }
+        show_runtime_err(
+            self,
+            &format!(
+                "Return address {:#x} doesn't match shadow stack ({:?}). Coredump created.",
+                retaddr, expected
+            ),
+        );
+        self.exceptions_active.push(Exception::CallStackSmash);
+        self.running = false;
+        false
+    }
+
+    /// Re-reads the data segment of a .vve built from the same source
+    /// and updates mutable variables whose values changed in the file.
+    /// Returns count of updated variables.
+    pub fn reload_data(&mut self, path: &str) -> std::result::Result<usize, String> {
+        let new_image: Vec<u8> = read_data_image(path)?;
+        let data_base: usize = self.data_base as usize;
+        let rw_start: usize = match self.segments.rodata() {
+            Some(s) => s.end - data_base,
+            None => 0,
+        };
+        let data_end: usize = data_base + self.data_image.len();
+        let updated: usize = merge_data(
+            &mut self.memory[data_base..data_end],
+            &self.data_image,
+            &new_image,
+            rw_start,
+        )?;
+        self.data_image = new_image;
+        Ok(updated)
+    }
+
+    /// Stops the VM, used as a return address by `prepare_function`
+    pub fn halt(&mut self) {
+        self.running = false;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Pauses the VM before its next instruction (state is kept) and
+    /// resumes it, also from another thread while it runs
+    pub fn pause_handle(&self) -> PauseHandle {
+        self.pause.clone()
+    }
+
+    /// Exposes a Rust function to the guest as `ncall code`. It shadows a std
+    /// call or native config function with the same code, returns what the
+    /// code ran before. Registering the same code again replaces the function
+    pub fn register_ncall(&mut self, code: u16, f: HostCall) -> Option<NcallSource> {
+        self.nativesys.register_host(code, f)
+    }
+
+    pub fn unregister_ncall(&mut self, code: u16) -> bool {
+        self.nativesys.unregister_host(code)
+    }
+
+    /// Loads the native config (toml) at `path` and its library into the
+    /// running VM, returns the ncall codes it added. Refused if one of them
+    /// is already taken, see `NativeService::load_cfg`
+    pub fn load_native_config(&mut self, path: &str) -> std::result::Result<Vec<u16>, NSysError> {
+        self.nativesys.load_cfg(path)
+    }
+
+    /// Executes one instruction at ip, without GC and coverage bookkeeping of `run`
+    pub fn step(&mut self) {
+        if self.coros.budget.is_some() {
+            slice_tick(self);
+        }
+        let (ip, opcode) = (self.ip, self.memory[self.ip]);
+        self.exec_op(opcode);
+        self.instr_count += 1;
+        self.track_exceptions(ip);
+    }
+
+    /// Executes the instruction at ip, which must have opcode `opcode`
+    /// (translated code passes constants, so the handler call is static).
+    /// True if execution goes on at `next` and the coroutine slice allows
+    /// it, code of a basic block then runs its next instruction
+    #[inline(always)]
+    pub fn exec_instr(&mut self, opcode: u8, next: usize) -> bool {
+        let ip: usize = self.ip;
+        if let Some(cov) = &mut self.coverage {
+            cov.record(ip);
+        }
+        self.exec_op(opcode);
+        self.instr_count += 1;
+        self.track_exceptions(ip);
+        if (self.ip != next) || !self.running {
+            return false;
+        }
+        if self.coros.budget.is_some() {
+            // the run loop ticks before each dispatch, this is the tick of `next`
+            slice_tick(self);
+        }
+        self.ip == next
+    }
+
+    /// Runs the handler of `opcode`, with hooks of native plugins around it if any
+    #[inline(always)]
+    fn exec_op(&mut self, opcode: u8) {
+        if !self.nativesys.has_hooks() {
+            Self::OPERATIONS[opcode as usize](self);
+            return;
+        }
+        let hooks: OpHooks = match self.nativesys.hooks_of(opcode) {
+            Some(h) => h.clone(),
+            None => {
+                Self::OPERATIONS[opcode as usize](self);
+                return;
+            }
+        };
+        let mut skip: bool = false;
+        for hook in &hooks.pre {
+            skip |= self.call_hook(*hook, opcode) != 0;
+        }
+        if !skip {
+            Self::OPERATIONS[opcode as usize](self);
+        }
+        for hook in &hooks.post {
+            self.call_hook(*hook, opcode);
+        }
+    }
+
+    fn call_hook(&mut self, hook: VMHookFunction, opcode: u8) -> u32 {
+        let mut regs: [VMValue; RegistersCount] = CollectRegsVMVal(&self.registers);
+        let code: &[u8] = self.memory.get(self.ip..).unwrap_or(&[]);
+        let mut frame = HookFrame {
+            opcode: opcode as u32,
+            ip: self.ip as u64,
+            regs: regs.as_mut_ptr(),
+            reg_count: RegistersCount as u32,
+            code: code.as_ptr(),
+            code_len: code.len() as u64,
+        };
+        let res: u32 = unsafe { hook(&mut frame) };
+        self.ip = frame.ip as usize;
+        for (ind, val) in regs.iter().enumerate() {
+            match RegTFromU32(val.typeind) {
+                Some(t) => {
+                    self.registers[ind] = Register::from_u64_bits(val.data, t);
+                    self.reg_types[ind] = t;
                 }
+                None => self.exceptions_active.push(Exception::InvalidDataType),
             }
-            Err(err) => {
-                panic!("CRITICAL: Can't read .vve file. Error: {}", err)
+        }
+        res
+    }
+
+    /// Records where exceptions raised by the instruction at `ip` came from,
+    /// halts if more than `max_pending_exc` are pending
+    pub fn track_exceptions(&mut self, ip: usize) {
+        if self.exceptions_active.is_empty() {
+            return; // nearly every instruction
+        }
+        let Some(raised) = self.exceptions_active.stamp(ip as u64) else {
+            return;
+        };
+        let pending: usize = self.exceptions_active.len();
+        if let Some(max) = self.max_pending_exc {
+            if pending > max {
+                eprintln!(
+                    "ERROR: {} exceptions pending (max {}), last {:?} at IP {:#x}. Halting.",
+                    pending, max, raised, ip
+                );
+                self.running = false;
+            }
+        }
+    }
+
+    fn load_interned(&mut self, sect: &[u8]) {
+        // count, count * rel addr of a str variable
+        let count: usize = args_to_u64(&sect[0..8]) as usize;
+        for i in 0..count {
+            let rel_addr: u64 = args_to_u64(&sect[(8 + i * 8)..(16 + i * 8)]);
+            let str_addr: u64 = self.data_base + rel_addr + 1 + 8; // type, length
+            match bytes_from_straddr(self, str_addr) {
+                Some(content) => self.interned.insert(str_addr, content),
+                None => {
+                    eprintln!("WARNING: Interned string at {:#x} is out of bounds", str_addr);
+                }
             }
         }
     }
 
+    /// Checks main memory access against the segment descriptors.
+    /// Pushes MainSegmFault and returns false on violation.
+    pub fn segm_check(&mut self, addr: usize, len: usize, write: bool) -> bool {
+        let res = match write {
+            true => self.segments.check_write(addr, len),
+            false => self.segments.check_read(addr, len),
+        };
+        match res {
+            Ok(()) => {
+                if write && self.segments.allow_self_modify {
+                    if self.segments.find(addr, len).is_some_and(|s| s.kind != SegmKind::Data) {
+                        self.code_writes += 1;
+                    }
+                }
+                true
+            }
+            Err(e) => {
+                show_runtime_err(
+                    self,
+                    &format!("Segment violation ({:?}) at {:#x}, {} bytes", e, addr, len),
+                );
+                self.exceptions_active.push(Exception::MainSegmFault);
+                false
+            }
+        }
+    }
+
+    /// Type of the data segment variable at absolute address `var_addr`
+    fn ds_type_at(&self, var_addr: usize) -> DsType {
+        match DsType::decode(self.memory[var_addr]) {
+            Some(t) => t,
+            None => panic!(
+                "CRITICAL: Unknown constant type: {}. IP: {}",
+                self.memory[var_addr], self.ip
+            ),
+        }
+    }
+
+    /// Reads one value of a `ds_type` variable, zero/sign-extending it into a register
+    fn ds_read_value(&self, addr: usize, ds_type: DsType) -> (Register, RegTypes) {
+        let bytes: &[u8] = &self.memory[addr..(addr + ds_type.elem_width())];
+        let reg: Register = match ds_type.reg_type() {
+            RegTypes::int64 => Register::int(match bytes.len() {
+                1 => bytes[0] as i8 as i64,
+                2 => i16::from_be_bytes(bytes.try_into().unwrap()) as i64,
+                4 => i32::from_be_bytes(bytes.try_into().unwrap()) as i64,
+                _ => args_to_i64(bytes),
+            }),
+            RegTypes::float64 => Register::float(match bytes.len() {
+                4 => f32::from_be_bytes(bytes.try_into().unwrap()) as f64,
+                _ => args_to_f64(bytes),
+            }),
+            _ => Register::uint(match bytes.len() {
+                1 => bytes[0] as u64, // raw bytes are loaded one by one
+                2 => u16::from_be_bytes(bytes.try_into().unwrap()) as u64,
+                4 => u32::from_be_bytes(bytes.try_into().unwrap()) as u64,
+                _ => args_to_u64(bytes),
+            }),
+        };
+        (reg, ds_type.reg_type())
+    }
+
+    /// Rsrc encoded as one value of a `ds_type` variable. Narrow values are
+    /// truncated by the variable type, 64-bit values keep Rsrc's one
+    fn ds_value_bytes(&self, r_src_ind: usize, ds_type: DsType) -> Vec<u8> {
+        let src: Register = self.registers[r_src_ind];
+        match (ds_type.elem_width(), ds_type.reg_type()) {
+            (4, RegTypes::float64) => (src.as_f64() as f32).to_be_bytes().to_vec(),
+            (8, _) => match self.reg_types[r_src_ind] {
+                RegTypes::int64 => src.as_i64().to_be_bytes().to_vec(),
+                RegTypes::float64 => src.as_f64().to_be_bytes().to_vec(),
+                _ => src.as_u64().to_be_bytes().to_vec(),
+            },
+            (width, RegTypes::int64) => src.as_i64().to_be_bytes()[(8 - width)..].to_vec(),
+            (width, _) => src.as_u64().to_be_bytes()[(8 - width)..].to_vec(),
+        }
+    }
+
+    /// dsload/dsrload: the value `offset` bytes into the payload of the variable at `rel_addr`
+    fn ds_load(&mut self, r_dest_ind: usize, rel_addr: usize, offset: usize) {
+        let var_addr: usize = self.data_base as usize + rel_addr;
+        if !self.segm_check(var_addr, 1, false) {
+            return;
+        }
+        record_data(self, rel_addr, false);
+        let ds_type: DsType = self.ds_type_at(var_addr);
+        let abs_addr: usize = var_addr + DS_HEADER + offset;
+        if ds_type == DsType::Str {
+            // dsload only loads values, a string is loaded as its address
+            self.registers[r_dest_ind] = Register::StrAddr(abs_addr as u64);
+            self.reg_types[r_dest_ind] = RegTypes::StrAddr;
+            return;
+        }
+        if self.segm_check(abs_addr, ds_type.elem_width(), false) {
+            (self.registers[r_dest_ind], self.reg_types[r_dest_ind]) = self.ds_read_value(abs_addr, ds_type);
+        }
+    }
+
+    /// dssave/dsrsave: writes Rsrc `offset` bytes into the payload of the variable at `rel_addr`
+    fn ds_save(&mut self, r_src_ind: usize, rel_addr: usize, offset: usize) {
+        let var_addr: usize = self.data_base as usize + rel_addr;
+        if !self.segm_check(var_addr, 1, false) {
+            return;
+        }
+        let ds_type: DsType = self.ds_type_at(var_addr);
+        let abs_addr: usize = var_addr + DS_HEADER + offset;
+        if !self.segm_check(abs_addr, ds_type.elem_width(), true) {
+            return;
+        }
+        if DsType::is_const(self.memory[var_addr]) {
+            panic!(
+                "CRITICAL: Attempting to write new value into DS constant at IP {}",
+                self.ip
+            );
+        }
+        if ds_type == DsType::Str {
+            show_runtime_err(self, "dssave target is a str variable, use dsstore");
+            self.exceptions_active.push(Exception::InvalidDataType);
+            return;
+        }
+        record_data(self, rel_addr, true);
+        let bytes: Vec<u8> = self.ds_value_bytes(r_src_ind, ds_type);
+        self.memory[abs_addr..(abs_addr + bytes.len())].copy_from_slice(&bytes);
+    }
+
+    /// dsderef/dsrderef: the first value of the variable `offset` bytes below the address in Rsrc
+    fn ds_deref(&mut self, r_src_ind: usize, r_dest_ind: usize, offset: usize) {
+        let src_val = self.registers[r_src_ind].as_u64() as usize;
+        if !self.segm_check(src_val - offset, 1 + 8 + 8, false) {
+            return;
+        }
+        let var_addr: usize = src_val - offset;
+        let ds_type: DsType = match DsType::decode(self.memory[var_addr]) {
+            Some(DsType::Str) | None => {
+                if let Err(e) = self.err_coredump() {
+                    eprintln!("Error creating coredump: {}", e);
+                };
+                panic!(
+                    "CRITICAL: At Instruction {:#x}:\n {:#x} is not a dereferenceable value (type {}). \nCoredump created.",
+                    self.ip, var_addr, self.memory[var_addr]
+                );
+            }
+            Some(t) => t,
+        };
+        record_data(self, var_addr - self.data_base as usize, false);
+        (self.registers[r_dest_ind], self.reg_types[r_dest_ind]) =
+            self.ds_read_value(var_addr + DS_HEADER, ds_type);
+    }
+
     pub fn run(&mut self) {
-        let mut since_cleanup: usize = 0;
+        self.run_with(&mut Interpreter);
+    }
+
+    /// `run` with code executed by `dispatch`, see `Dispatch`
+    pub fn run_with<D: Dispatch>(&mut self, dispatch: &mut D) {
+        let mut since_cleanup: u64 = 0;
 
         let run_start = Instant::now();
         while (self.ip < self.memory.capacity()) && (self.running) {
-            let opcode = self.memory[self.ip];
-            //println!("DBG: cur opcode: {:#x}, IP: {:#x}", opcode, self.ip);
-            Self::OPERATIONS[opcode as usize](self);
+            if self.pause.is_paused() {
+                if self.yield_on_pause {
+                    // `run()` again continues from here
+                    break;
+                }
+                if !self.quiet {
+                    eprintln!("INFO: VM paused at ip {:#x} after {} instructions", self.ip, self.instr_count);
+                }
+                self.pause.wait_resumed();
+                if !self.quiet {
+                    eprintln!("INFO: VM resumed");
+                }
+                continue;
+            }
+            if self.coros.budget.is_some() {
+                slice_tick(self);
+            }
+            //println!("DBG: cur opcode: {:#x}, IP: {:#x}", self.memory[self.ip], self.ip);
+            let ran: u64 = dispatch.dispatch(self);
+            if self.data_watch.as_mut().is_some_and(|w| w.poll()) {
+                // between instructions, so no handler sees a half-updated variable
+                let path: String = self.data_watch.as_ref().unwrap().path.clone();
+                match self.reload_data(&path) {
+                    Ok(count) if !self.quiet => eprintln!("INFO: Reloaded {} data variable(s) from {}", count, path),
+                    Ok(_) => {}
+                    Err(e) => eprintln!("WARNING: Data reload from {} failed: {}", path, e),
+                }
+            }
 
             if (since_cleanup >= 250) {
                 // running gc after each 250 instructions
                 let start = Instant::now();
 
-                let regs_hashset: HashSet<u64> = self.gc_gen_reg_set();
-                let dstack_hashset: HashSet<u64> = self.fetch_dstack_refs();
-                let final_hset: HashSet<u64> =
-                    regs_hashset.union(&dstack_hashset).cloned().collect();
-                let t2: HashMap<u64, HashSet<u64>> = self.heap.saved_refs.clone();
-
-                self.gc.mark(&final_hset, &t2);
-                let addrs = self.gc.sweep();
-                self.gc_finish_cleanup(addrs);
+                match self.gc_concurrent {
+                    true => self.gc_safepoint(),
+                    false => {
+                        self.gc_collect();
+                    }
+                }
 
                 let elapsed = start.elapsed();
 
                 //println!("elapsed on gc: {:?}", elapsed);
                 since_cleanup = 0;
             } else {
-                since_cleanup += 1;
+                since_cleanup += ran;
             }
         }
+        if let Some(handle) = self.gc_pending.take() {
+            // finishing the background mark, so the final heap state is settled
+            self.gc_apply_marked(handle);
+        }
         if self.ip >= self.memory.capacity() {
             panic!(
                 "CRITICAL: Instruction overflow! VM Memory capacity: {}, latest opcode: {}.
@@ -171,6 +866,7 @@ impl VM {
         handlers[0xFF] = Self::op_halt as InstructionHandler;
         handlers[0x01] = Self::op_ncall as InstructionHandler;
         handlers[0x02] = Self::op_nop as InstructionHandler;
+        handlers[0x03] = Self::op_rdcnt as InstructionHandler;
         handlers[0x10] = Self::op_uload as InstructionHandler;
         handlers[0x11] = Self::op_uadd as InstructionHandler;
         handlers[0x12] = Self::op_umul as InstructionHandler;
@@ -182,6 +878,7 @@ impl VM {
         handlers[0x18] = Self::op_upow as InstructionHandler;
         handlers[0x19] = Self::op_uinc as InstructionHandler;
         handlers[0x1a] = Self::op_udec as InstructionHandler;
+        handlers[0x1b] = Self::op_uload32 as InstructionHandler;
         handlers[0x20] = Self::op_iload as InstructionHandler;
         handlers[0x21] = Self::op_iadd as InstructionHandler;
         handlers[0x22] = Self::op_imul as InstructionHandler;
@@ -195,6 +892,7 @@ impl VM {
         handlers[0x2a] = Self::op_ipow as InstructionHandler;
         handlers[0x2b] = Self::op_iinc as InstructionHandler;
         handlers[0x2c] = Self::op_idec as InstructionHandler;
+        handlers[0x2d] = Self::op_iload32 as InstructionHandler;
         handlers[0x30] = Self::op_fload as InstructionHandler;
         handlers[0x31] = Self::op_fadd as InstructionHandler;
         handlers[0x32] = Self::op_fmul as InstructionHandler;
@@ -209,6 +907,8 @@ impl VM {
         handlers[0x3b] = Self::op_fpow as InstructionHandler;
         handlers[0x3c] = Self::op_finc as InstructionHandler;
         handlers[0x3d] = Self::op_fdec as InstructionHandler;
+        handlers[0x3e] = Self::op_fsete as InstructionHandler;
+        handlers[0x3f] = Self::op_fgete as InstructionHandler;
         handlers[0x40] = Self::op_jmp as InstructionHandler;
         handlers[0x41] = Self::op_jz as InstructionHandler;
         handlers[0x42] = Self::op_jl as InstructionHandler;
@@ -218,6 +918,13 @@ impl VM {
         handlers[0x46] = Self::op_jexc as InstructionHandler;
         handlers[0x47] = Self::op_jmpr as InstructionHandler;
         handlers[0x48] = Self::op_jnz as InstructionHandler;
+        handlers[0x49] = Self::op_switch as InstructionHandler;
+        handlers[0x4a] = Self::op_sete as InstructionHandler;
+        handlers[0x4b] = Self::op_setne as InstructionHandler;
+        handlers[0x4c] = Self::op_setl as InstructionHandler;
+        handlers[0x4d] = Self::op_setg as InstructionHandler;
+        handlers[0x4e] = Self::op_setge as InstructionHandler;
+        handlers[0x4f] = Self::op_setle as InstructionHandler;
         handlers[0x50] = Self::op_utoi as InstructionHandler;
         handlers[0x51] = Self::op_itou as InstructionHandler;
         handlers[0x52] = Self::op_utof as InstructionHandler;
@@ -235,6 +942,10 @@ impl VM {
         handlers[0x66] = Self::op_lnot as InstructionHandler;
         handlers[0x67] = Self::op_shl as InstructionHandler;
         handlers[0x68] = Self::op_shr as InstructionHandler;
+        handlers[0x69] = Self::op_cmovz as InstructionHandler;
+        handlers[0x6a] = Self::op_cmovl as InstructionHandler;
+        handlers[0x6b] = Self::op_cmovg as InstructionHandler;
+        handlers[0x6c] = Self::op_xchg as InstructionHandler;
         handlers[0x70] = Self::op_dsload as InstructionHandler;
         handlers[0x71] = Self::op_dsrload as InstructionHandler;
         handlers[0x72] = Self::op_dssave as InstructionHandler;
@@ -243,6 +954,10 @@ impl VM {
         handlers[0x75] = Self::op_dsderef as InstructionHandler;
         handlers[0x76] = Self::op_dsrlea as InstructionHandler;
         handlers[0x77] = Self::op_dsrderef as InstructionHandler;
+        handlers[0x78] = Self::op_dsstore as InstructionHandler;
+        handlers[0x79] = Self::op_dsindex as InstructionHandler;
+        handlers[0x7a] = Self::op_strindex as InstructionHandler;
+        handlers[0x7b] = Self::op_strlen as InstructionHandler;
         handlers[0x80] = op_push as InstructionHandler;
         handlers[0x81] = op_pop as InstructionHandler;
         handlers[0x82] = op_pushall as InstructionHandler;
@@ -253,6 +968,11 @@ impl VM {
         handlers[0x91] = op_ret as InstructionHandler;
         handlers[0x92] = op_fnstind as InstructionHandler;
         handlers[0x93] = op_callr as InstructionHandler;
+        handlers[0x94] = op_cocreate as InstructionHandler;
+        handlers[0x95] = op_coresume as InstructionHandler;
+        handlers[0x96] = op_coyield as InstructionHandler;
+        handlers[0x97] = op_costatus as InstructionHandler;
+        handlers[0x98] = op_coslice as InstructionHandler;
         handlers[0xA0] = op_alloc as InstructionHandler;
         handlers[0xA1] = op_free as InstructionHandler;
         handlers[0xA2] = op_store as InstructionHandler;
@@ -263,12 +983,40 @@ impl VM {
         handlers[0xA7] = op_storedat as InstructionHandler;
         handlers[0xA8] = op_dlbc as InstructionHandler;
         handlers[0xA9] = op_ubd as InstructionHandler;
+        handlers[0xAA] = op_load32 as InstructionHandler;
+        handlers[0xAB] = op_store32 as InstructionHandler;
+        handlers[0xAC] = op_salloc as InstructionHandler;
+        handlers[0xAD] = op_sfree as InstructionHandler;
+        handlers[0xAE] = op_allocal as InstructionHandler;
+        handlers[0xAF] = op_storei as InstructionHandler;
+        handlers[0xB0] = op_loadi as InstructionHandler;
+        handlers[0xB1] = op_allocarena as InstructionHandler;
+        handlers[0xFE] = Self::op_ext as InstructionHandler;
+        // ...
+        handlers
+    };
+
+    /// Instructions after EXT_PREFIX, indexed by their second byte. Their
+    /// operands start at ip + 2
+    const EXT_OPERATIONS: [InstructionHandler; 256] = {
+        let mut handlers = [Self::op_ext_unimplemented as InstructionHandler; 256];
+        handlers[0x01] = Self::op_excclear as InstructionHandler;
+        handlers[0x02] = op_gcadopt as InstructionHandler;
+        handlers[0x03] = op_gcrelease as InstructionHandler;
+        handlers[0x04] = op_abort as InstructionHandler;
+        handlers[0x05] = Self::op_ldstr as InstructionHandler;
+        handlers[0x06] = Self::op_ldstr as InstructionHandler; // ldbytes
+        handlers[0x07] = op_lget as InstructionHandler;
+        handlers[0x08] = op_lset as InstructionHandler;
         // ...
         handlers
     };
 
     fn fetch_dstack_refs(&mut self) -> HashSet<u64> {
         let mut res: HashSet<u64> = HashSet::new();
+        res.extend(self.coros.stack_refs()); // stacks of switched out coroutines
+        let locals = self.call_stack.all_locals().filter(|(_, t)| *t == RegTypes::address);
+        res.extend(locals.map(|(val, _)| *val));
         let len = self.stack.stack.len();
         if (len == 0) {
             return res;
@@ -282,15 +1030,95 @@ impl VM {
         res
     }
 
+    /// Allocation site of the current instruction
+    pub fn alloc_site(&self) -> AllocSite {
+        AllocSite {
+            ip: self.ip as u64,
+            func: self.call_stack.current_func(),
+        }
+    }
+
+    /// `ip 0x23 (line 8) in name` for reports, line and name when known.
+    /// The line is the one of the instruction at or right before ip
+    pub fn site_desc(&self, site: &AllocSite) -> String {
+        let mut res: String = format!("ip {:#x}", site.ip);
+        let line = self.line_table.iter().filter(|(addr, _)| *addr <= site.ip).max_by_key(|(addr, _)| *addr);
+        if let Some((_, line)) = line {
+            res.push_str(&format!(" (line {})", line));
+        }
+        if let Some(ind) = site.func {
+            match self.func_names.iter().find(|(_, i)| **i == ind) {
+                Some((name, _)) => res.push_str(&format!(" in {}", name)),
+                None => res.push_str(&format!(" in func #{}", ind)),
+            }
+        }
+        res
+    }
+
+    /// Heap blocks the GC would keep right now
+    pub fn heap_reachable(&mut self) -> HashSet<u64> {
+        let mut roots: HashSet<u64> = self.gc_gen_reg_set();
+        roots.extend(self.fetch_dstack_refs());
+        roots.extend(self.heap.pinned_ptrs());
+        reachable_from(&roots, &self.heap.saved_refs)
+    }
+
+    /// Full mark and sweep, returns count of collected objects
+    pub fn gc_collect(&mut self) -> usize {
+        let regs_hashset: HashSet<u64> = self.gc_gen_reg_set();
+        let dstack_hashset: HashSet<u64> = self.fetch_dstack_refs();
+        let mut final_hset: HashSet<u64> = regs_hashset.union(&dstack_hashset).cloned().collect();
+        final_hset.extend(self.heap.pinned_ptrs()); // held by natives
+        let t2: HashMap<u64, HashSet<u64>> = self.heap.saved_refs.clone();
+
+        self.gc.mark(&final_hset, &t2);
+        let addrs = self.gc.sweep();
+        let collected: usize = addrs.len();
+        self.gc_finish_cleanup(addrs);
+        collected
+    }
+
+    /// Concurrent GC safepoint: sweeps the finished background mark,
+    /// then snapshots roots for the next one
+    fn gc_safepoint(&mut self) {
+        match self.gc_pending.take() {
+            Some(handle) if handle.is_finished() => self.gc_apply_marked(handle),
+            Some(handle) => {
+                self.gc_pending = Some(handle); // still marking
+                return;
+            }
+            None => {}
+        }
+        let mut roots: HashSet<u64> = self.gc_gen_reg_set();
+        roots.extend(self.fetch_dstack_refs());
+        roots.extend(self.heap.pinned_ptrs());
+        let snapshot: GcSnapshot = self.gc.snapshot(&roots, &self.heap.saved_refs);
+        self.gc_pending = Some(thread::spawn(move || snapshot.unreachable()));
+    }
+
+    fn gc_apply_marked(&mut self, handle: JoinHandle<Vec<(u64, u64)>>) {
+        match handle.join() {
+            Ok(dead) => {
+                let addrs: Vec<u64> = self.gc.sweep_dead(&dead);
+                self.gc_finish_cleanup(addrs);
+            }
+            Err(_) => {
+                eprintln!("WARNING: Background GC marker panicked, skipping sweep");
+            }
+        }
+    }
+
     fn gc_finish_cleanup(&mut self, ptrs: Vec<u64>) {
         for ptr in ptrs {
             match self.heap.free(ptr) {
                 Ok(_) => {}
                 Err(_) => {
-                    println!(
-                        "INFO: No object with ptr {:x} found in heap in GC cleanup",
-                        ptr
-                    );
+                    if !self.quiet {
+                        println!(
+                            "INFO: No object with ptr {:x} found in heap in GC cleanup",
+                            ptr
+                        );
+                    }
                     self.gc.main_refs.remove(&ptr);
                 }
             }
@@ -315,50 +1143,200 @@ impl VM {
         );
     }
 
+    fn op_ext_unimplemented(&mut self) {
+        panic!(
+            "CRITICAL: Unknown extended operation code at {:#x}: {:#x} {:#x}.",
+            self.ip, EXT_PREFIX, self.memory[self.ip + 1]
+        );
+    }
+
+    fn op_ext(&mut self) {
+        // 0xFE, two-byte opcodes
+        let ext_op: u8 = self.memory[self.ip + 1];
+        Self::EXT_OPERATIONS[ext_op as usize](self);
+    }
+
     fn op_halt(&mut self) {
         self.running = false;
     }
 
     fn op_ncall(&mut self) {
         // 0x1, size: 4
+        // ncall code Rarg - every code goes through NativeService, see
+        // NcallSource for which of host, std and library calls answers it.
+        // Handlers don't move ip, the instruction is 4 bytes for any code
         let instr_size: usize = 4;
 
         let ncall_num: u16 = args_to_u16(&self.memory[(self.ip + 1)..(self.ip + 3)]);
-        match self.nativesys.std_calls.get(&ncall_num) {
-            Some(v) => {
-                v(self);
-                self.ip += instr_size;
-                return;
+        match self.nativesys.source_of(ncall_num) {
+            Some(NcallSource::Host) => {
+                let mut f: HostCall = self.nativesys.take_host(ncall_num).unwrap();
+                f(self);
+                self.nativesys.restore_host(ncall_num, f);
             }
-            None => {}
+            Some(NcallSource::Std) => {
+                let f: InstructionHandler = self.nativesys.std_calls[&ncall_num];
+                f(self);
+            }
+            Some(NcallSource::Library) | None => self.ncall_library(ncall_num),
         }
+        self.ip += instr_size;
+    }
 
+    /// Calls a native config library function, its result goes to r0
+    fn ncall_library(&mut self, ncall_num: u16) {
         let args = &CollectRegsVMVal(&self.registers);
-        let res = self.nativesys.call_code(ncall_num, args);
-                match res {
-                    Ok(v) => {
-                        //self.registers[0] = v.data;
-                        self.reg_types[0] = match RegTFromU32(v.typeind) {
-                            Some(v) => v,
-                            None => {
-                                self.exceptions_active.push(Exception::InvalidDataType);
-                                self.ip += instr_size;
-                                return;
-                            }
-                        };
-                        self.registers[0] = Register::from_u64_bits(v.data, self.reg_types[0]);
-                    }
-                    Err(e) => {
-                        eprintln!("{:#?}", e);
-                        self.exceptions_active.push(Exception::NativeFault);
-                    }
+        match self.nativesys.call_code(ncall_num, args, &mut self.heap) {
+            Ok(v) => match RegTFromU32(v.typeind) {
+                Some(t) => {
+                    self.reg_types[0] = t;
+                    self.registers[0] = Register::from_u64_bits(v.data, t);
                 }
-        self.ip += instr_size;
+                None => {
+                    let err: NativeError = NativeError::new(NativeSubsys::Std, NativeErrKind::InvalidInput);
+                    let msg: String = format!("ncall {:#x}: result type {} is unknown", ncall_num, v.typeind);
+                    native_fault(self, err, Exception::InvalidDataType, &msg);
+                }
+            },
+            Err(e) => {
+                let err: NativeError = NativeError::new(NativeSubsys::Std, NativeErrKind::from(&e));
+                native_fault(self, err, Exception::NativeFault, &format!("ncall {:#x}: {:?}", ncall_num, e));
+            }
+        }
+    }
+
+    /// Operands of a typed arithmetic or cmp instruction, converted by the
+    /// coercion matrix in registers.rs. Raises IncorrectRegType if they can't be
+    fn typed_operands(&mut self, a: usize, b: usize, domain: RegTypes, op: &str) -> Option<(Register, Register)> {
+        let (x, y) = (self.registers[a], self.registers[b]);
+        match Register::coerce_pair(x, y, domain) {
+            Some(pair) => Some(pair),
+            None => {
+                show_runtime_err(self, &format!("{}: can't use {:?} with {:?}", op, x, y));
+                self.exceptions_active.push(Exception::IncorrectRegType);
+                None
+            }
+        }
+    }
+
+    /// uadd, umul, usub, iadd, imul, isub (by `opcode`) of registers `dst`
+    /// and `src` into `dst`. Handlers and hot loop bodies (hotloop.rs) share it
+    #[inline(always)]
+    pub fn arith(&mut self, opcode: u8, dst: usize, src: usize) {
+        let (domain, name): (RegTypes, &str) = match opcode {
+            0x11 => (RegTypes::uint64, "uadd"),
+            0x12 => (RegTypes::uint64, "umul"),
+            0x13 => (RegTypes::uint64, "usub"),
+            0x21 => (RegTypes::int64, "iadd"),
+            0x22 => (RegTypes::int64, "imul"),
+            _ => (RegTypes::int64, "isub"),
+        };
+        let Some((a, b)) = self.typed_operands(dst, src, domain, name) else {
+            return;
+        };
+        let res: Register = match opcode {
+            0x11 | 0x21 => a + b,
+            0x12 | 0x22 => a * b,
+            _ => a - b,
+        };
+        self.registers[dst] = res;
+        self.reg_types[dst] = res.kind();
+        // only usub sets flags, see update_flags
+        if opcode == 0x13 {
+            self.update_flags(res);
+        }
+    }
+
+    /// ZF and NF of an ALU result, the one rule every flag-setting handler
+    /// follows: ZF when the result is zero, NF when it is negative. Unsigned
+    /// and address results are never negative and clear NF, a NaN sets
+    /// neither. OF and CF are left alone.
+    /// uadd, umul, iadd, imul and isub deliberately don't come here and
+    /// leave ZF and NF as they were (so do div/rem, uinc, float add, sub,
+    /// mul, div, rem and the shifts): flags of a compare survive arithmetic
+    /// between it and the branch. usub is the one of `arith` that sets them.
+    /// Compares (ucmp/icmp, fcmp, fcmp_eps) have no result to go through
+    /// here and set the flags from their operands: NF if a < b, ZF if a == b
+    #[inline(always)]
+    pub fn update_flags(&mut self, res: Register) {
+        let (zero, neg): (bool, bool) = match res {
+            Register::int(v) => (v == 0, v < 0),
+            Register::float(v) => (v == 0.0, v < 0.0),
+            Register::uint(v) | Register::StrAddr(v) | Register::address(v) | Register::ds_addr(v) => (v == 0, false),
+        };
+        self.flags[1] = zero as u8; // zf
+        self.flags[2] = neg as u8; // nf
+    }
+
+    /// ucmp (0x16) or icmp of registers `a` and `b`: nf if a < b, zf if equal
+    #[inline(always)]
+    pub fn compare(&mut self, opcode: u8, a: usize, b: usize) {
+        let domain: RegTypes = if opcode == 0x16 { RegTypes::uint64 } else { RegTypes::int64 };
+        let name: &str = if opcode == 0x16 { "ucmp" } else { "icmp" };
+        let Some((a, b)) = self.typed_operands(a, b, domain, name) else {
+            return;
+        };
+        self.flags[2] = (a < b) as u8; // nf
+        self.flags[1] = (a == b) as u8; // zf
+    }
+
+    /// uinc, udec, iinc, idec (by `opcode`) of register `reg`
+    #[inline(always)]
+    pub fn step_reg(&mut self, opcode: u8, reg: usize) {
+        match opcode {
+            0x19 => {
+                self.registers[reg] += Register::uint(1);
+                self.flags[0] = (self.registers[reg] == Register::uint(0)) as u8; // of
+            }
+            0x1a => {
+                self.registers[reg] -= Register::uint(1);
+                self.update_flags(self.registers[reg]);
+            }
+            _ => {
+                let new_val: Register = match opcode {
+                    0x2b => self.registers[reg] + Register::int(1),
+                    _ => self.registers[reg] - Register::int(1),
+                };
+                self.registers[reg] = new_val;
+                self.update_flags(new_val);
+            }
+        }
+    }
+
+    /// Whether conditional jump `opcode` (jz, jl, jg, jge, jle, jnz) is taken
+    #[inline(always)]
+    pub fn jump_taken(&self, opcode: u8) -> bool {
+        let (zf, nf) = (self.flags[1], self.flags[2]);
+        match opcode {
+            0x41 => zf != 0,
+            0x42 => nf != 0,
+            0x43 => (zf == 0) && (nf == 0),
+            0x44 => nf == 0,
+            0x45 => (nf == 1) || (zf == 1),
+            _ => zf == 0,
+        }
     }
 
     fn op_nop(&mut self) {
         // 0x2, size: 1
-        return;
+        self.ip += 1;
+    }
+
+    fn op_rdcnt(&mut self) {
+        // 0x3, size: 3
+        // rdcnt Rinstr Rns
+        // Rinstr = instructions executed before this one,
+        // Rns = monotonic nanoseconds since VM start
+        let instr_size: usize = 3;
+        let r_instr_ind: usize = self.memory[self.ip + 1] as usize;
+        let r_ns_ind: usize = self.memory[self.ip + 2] as usize;
+        let ns: u64 = self.clock_start.elapsed().as_nanos() as u64;
+
+        self.registers[r_instr_ind] = Register::uint(self.instr_count);
+        self.reg_types[r_instr_ind] = RegTypes::uint64;
+        self.registers[r_ns_ind] = Register::uint(ns);
+        self.reg_types[r_ns_ind] = RegTypes::uint64;
+        self.ip += instr_size;
     }
 
     fn op_uload(&mut self) {
@@ -372,39 +1350,37 @@ impl VM {
         return;
     }
 
+    fn op_uload32(&mut self) {
+        // 0x1b, size: 6
+        // uload32 Rdst imm32 - zero-extended
+        let register_ind: u8 = self.memory[(self.ip + 1) as usize];
+        let bytes: [u8; 4] = self.memory[(self.ip + 2)..(self.ip + 6)].try_into().unwrap();
+
+        self.registers[register_ind as usize] = Register::uint(u32::from_be_bytes(bytes) as u64);
+        self.reg_types[register_ind as usize] = RegTypes::uint64;
+        self.ip += 6;
+        return;
+    }
+
     fn op_uadd(&mut self) {
         // 0x11, size: 3
-        let in_reg_ind: u8 = self.memory[(self.ip + 1) as usize];
-        let toadd_reg_ind: u8 = self.memory[(self.ip + 2) as usize];
-
-        self.registers[in_reg_ind as usize] += self.registers[toadd_reg_ind as usize];
+        let (dst, src) = (self.memory[self.ip + 1] as usize, self.memory[self.ip + 2] as usize);
+        self.arith(0x11, dst, src);
         self.ip += 3;
-        return;
     }
 
     fn op_umul(&mut self) {
         // 0x12, size: 3
-        let in_reg_ind: u8 = self.memory[(self.ip + 1) as usize];
-        let toadd_reg_ind: u8 = self.memory[(self.ip + 2) as usize];
-
-        self.registers[in_reg_ind as usize] *= self.registers[toadd_reg_ind as usize];
+        let (dst, src) = (self.memory[self.ip + 1] as usize, self.memory[self.ip + 2] as usize);
+        self.arith(0x12, dst, src);
         self.ip += 3;
-        return;
     }
 
     fn op_usub(&mut self) {
         // 0x13, size: 3
-        let in_reg_ind: u8 = self.memory[(self.ip + 1) as usize];
-        let toadd_reg_ind: u8 = self.memory[(self.ip + 2) as usize];
-
-        self.registers[in_reg_ind as usize] -= self.registers[toadd_reg_ind as usize];
-        if self.registers[in_reg_ind as usize] == Register::uint(0) {
-            self.flags[1] = 1;
-        } else {
-            self.flags[1] = 0;
-        }
+        let (dst, src) = (self.memory[self.ip + 1] as usize, self.memory[self.ip + 2] as usize);
+        self.arith(0x13, dst, src);
         self.ip += 3;
-        return;
     }
 
     fn op_udiv(&mut self) {
@@ -412,13 +1388,18 @@ impl VM {
         let reg_out: u8 = self.memory[self.ip + 1];
         let reg_1: u8 = self.memory[self.ip + 2];
         let reg_2: u8 = self.memory[self.ip + 3];
-        if self.registers[reg_2 as usize] == Register::uint(0) {
+        let Some((a, b)) = self.typed_operands(reg_1 as usize, reg_2 as usize, RegTypes::uint64, "udiv") else {
+            self.ip += 4;
+            return;
+        };
+        if b.as_u64() == 0 {
             eprintln!("DIVZERO Exception at addr {}", self.ip);
             self.exceptions_active.push(Exception::ZeroDivision);
+            self.ip += 4;
+            return;
         }
 
-        self.registers[reg_out as usize] =
-            self.registers[reg_1 as usize] / self.registers[reg_2 as usize];
+        self.registers[reg_out as usize] = a / b;
 
         self.reg_types[reg_out as usize] = RegTypes::uint64;
 
@@ -431,8 +1412,11 @@ impl VM {
         let reg_1: u8 = self.memory[self.ip + 2];
         let reg_2: u8 = self.memory[self.ip + 3];
 
-        self.registers[reg_dest as usize] =
-            self.registers[reg_1 as usize] % self.registers[reg_2 as usize];
+        let Some((a, b)) = self.typed_operands(reg_1 as usize, reg_2 as usize, RegTypes::uint64, "urem") else {
+            self.ip += 4;
+            return;
+        };
+        self.registers[reg_dest as usize] = a % b;
 
         self.reg_types[reg_dest as usize] = RegTypes::uint64;
 
@@ -441,24 +1425,8 @@ impl VM {
 
     fn op_ucmp(&mut self) {
         // 0x16, size: 3
-        let reg_dest: u8 = self.memory[self.ip + 1];
-        let reg_src: u8 = self.memory[self.ip + 2];
-
-        let isLess: bool = self.registers[reg_dest as usize] < self.registers[reg_src as usize];
-        let isEqu: bool = self.registers[reg_dest as usize] == self.registers[reg_src as usize];
-
-        if isLess {
-            self.flags[2] = 1;
-        } else {
-            self.flags[2] = 0;
-        }
-
-        if isEqu {
-            self.flags[1] = 1;
-        } else {
-            self.flags[1] = 0;
-        }
-
+        let (a, b) = (self.memory[self.ip + 1] as usize, self.memory[self.ip + 2] as usize);
+        self.compare(0x16, a, b);
         self.ip += 3;
     }
 
@@ -472,11 +1440,7 @@ impl VM {
         self.registers[reg_dest] = Register::uint(res);
         self.reg_types[reg_dest] = RegTypes::uint64;
 
-        if res == 0 {
-            self.flags[1] = 1; //zf
-        } else {
-            self.flags[1] = 0;
-        }
+        self.update_flags(Register::uint(res));
 
         self.ip += 3;
         return;
@@ -492,11 +1456,7 @@ impl VM {
             .as_u64()
             .pow(self.registers[reg_src].as_u64() as u32);
         self.registers[reg_dest] = Register::uint(res);
-        if res == 0 {
-            self.flags[1] = 1; //zf
-        } else {
-            self.flags[1] = 0;
-        }
+        self.update_flags(Register::uint(res));
 
         self.ip += 3;
         return;
@@ -504,32 +1464,16 @@ impl VM {
 
     fn op_uinc(&mut self) {
         // 0x19, size: 2
-        // uinc Rdest
-        let r_dest_int: usize = self.memory[(self.ip + 1)] as usize;
-        self.registers[r_dest_int] += Register::uint(1);
-        if self.registers[r_dest_int] == Register::uint(0) {
-            self.flags[0] = 1; // of
-        } else {
-            self.flags[0] = 0;
-        }
-
+        let r_dest_ind: usize = self.memory[self.ip + 1] as usize;
+        self.step_reg(0x19, r_dest_ind);
         self.ip += 2;
-        return;
     }
 
     fn op_udec(&mut self) {
         // 0x1a, size: 2
-        // udec Rdest
-        let r_dest_int: usize = self.memory[(self.ip + 1)] as usize;
-        self.registers[r_dest_int] -= Register::uint(1);
-        if self.registers[r_dest_int] == Register::uint(0) {
-            self.flags[1] = 1; // zf
-        } else {
-            self.flags[1] = 0;
-        }
-
+        let r_dest_ind: usize = self.memory[self.ip + 1] as usize;
+        self.step_reg(0x1a, r_dest_ind);
         self.ip += 2;
-        return;
     }
 
     fn op_iload(&mut self) {
@@ -544,43 +1488,37 @@ impl VM {
         return;
     }
 
-    fn op_iadd(&mut self) {
-        //0x21, size: 3
-        let dest_r_ind: u8 = self.memory[(self.ip + 1) as usize];
-        let src_r_ind: u8 = self.memory[(self.ip + 2) as usize];
+    fn op_iload32(&mut self) {
+        // 0x2d, size: 6
+        // iload32 Rdst imm32 - sign-extended
+        let register_ind: u8 = self.memory[(self.ip + 1) as usize];
+        let bytes: [u8; 4] = self.memory[(self.ip + 2)..(self.ip + 6)].try_into().unwrap();
 
-        let res: Register =
-            self.registers[dest_r_ind as usize] + self.registers[src_r_ind as usize];
-        self.registers[dest_r_ind as usize] = res;
+        self.registers[register_ind as usize] = Register::int(i32::from_be_bytes(bytes) as i64);
+        self.reg_types[register_ind as usize] = RegTypes::int64;
+        self.ip += 6;
+        return;
+    }
 
+    fn op_iadd(&mut self) {
+        // 0x21, size: 3
+        let (dst, src) = (self.memory[self.ip + 1] as usize, self.memory[self.ip + 2] as usize);
+        self.arith(0x21, dst, src);
         self.ip += 3;
-        return;
     }
 
     fn op_imul(&mut self) {
-        //0x22, size: 3
-        let dest_r_ind: u8 = self.memory[(self.ip + 1) as usize];
-        let src_r_ind: u8 = self.memory[(self.ip + 2) as usize];
-
-        let res: Register =
-            self.registers[dest_r_ind as usize] * self.registers[src_r_ind as usize];
-        self.registers[dest_r_ind as usize] = res;
-
+        // 0x22, size: 3
+        let (dst, src) = (self.memory[self.ip + 1] as usize, self.memory[self.ip + 2] as usize);
+        self.arith(0x22, dst, src);
         self.ip += 3;
-        return;
     }
 
     fn op_isub(&mut self) {
-        //0x23, size: 3
-        let dest_r_ind: u8 = self.memory[(self.ip + 1) as usize];
-        let src_r_ind: u8 = self.memory[(self.ip + 2) as usize];
-
-        let res: Register =
-            self.registers[dest_r_ind as usize] - self.registers[src_r_ind as usize];
-        self.registers[dest_r_ind as usize] = res;
-
+        // 0x23, size: 3
+        let (dst, src) = (self.memory[self.ip + 1] as usize, self.memory[self.ip + 2] as usize);
+        self.arith(0x23, dst, src);
         self.ip += 3;
-        return;
     }
 
     fn op_idiv(&mut self) {
@@ -589,10 +1527,14 @@ impl VM {
         let reg_1: u8 = self.memory[(self.ip + 2) as usize];
         let reg_2: u8 = self.memory[(self.ip + 3) as usize];
 
-        if self.registers[reg_2 as usize] == Register::int(0) {
+        let Some((a, b)) = self.typed_operands(reg_1 as usize, reg_2 as usize, RegTypes::int64, "idiv") else {
+            self.ip += 4;
+            return;
+        };
+        if b.as_i64() == 0 {
             panic!("DIVZERO exception at {}", self.ip);
         }
-        let res: Register = self.registers[reg_1 as usize] / self.registers[reg_2 as usize];
+        let res: Register = a / b;
         self.registers[dest_r_ind as usize] = res;
 
         self.reg_types[dest_r_ind as usize] = RegTypes::int64;
@@ -607,10 +1549,14 @@ impl VM {
         let reg_1: u8 = self.memory[(self.ip + 2) as usize];
         let reg_2: u8 = self.memory[(self.ip + 3) as usize];
 
-        if self.registers[reg_2 as usize] == Register::int(0) {
+        let Some((a, b)) = self.typed_operands(reg_1 as usize, reg_2 as usize, RegTypes::int64, "irem") else {
+            self.ip += 4;
+            return;
+        };
+        if b.as_i64() == 0 {
             panic!("DIVZERO exception at {}", self.ip);
         }
-        let res: Register = self.registers[reg_1 as usize] % self.registers[reg_2 as usize];
+        let res: Register = a % b;
         self.registers[dest_r_ind as usize] = res;
 
         self.reg_types[dest_r_ind as usize] = RegTypes::int64;
@@ -621,25 +1567,9 @@ impl VM {
 
     fn op_icmp(&mut self) {
         // 0x26, size: 3
-        let dest_r_ind: u8 = self.memory[(self.ip + 1) as usize];
-        let src_r_ind: u8 = self.memory[(self.ip + 2) as usize];
-
-        let isLess: bool = self.registers[dest_r_ind as usize] < self.registers[src_r_ind as usize];
-        let isEqu: bool = self.registers[dest_r_ind as usize] == self.registers[src_r_ind as usize];
-
-        if isLess {
-            self.flags[2] = 1; // nf
-        } else {
-            self.flags[2] = 0;
-        }
-        if isEqu {
-            self.flags[1] = 1;
-        } else {
-            self.flags[1] = 0;
-        }
-
+        let (a, b) = (self.memory[self.ip + 1] as usize, self.memory[self.ip + 2] as usize);
+        self.compare(0x26, a, b);
         self.ip += 3;
-        return;
     }
 
     fn op_iabs(&mut self) {
@@ -652,11 +1582,7 @@ impl VM {
         self.registers[reg_dest_ind] = Register::int(res);
         self.reg_types[reg_dest_ind] = RegTypes::int64;
 
-        if res == 0 {
-            self.flags[1] = 1; // zf
-        } else {
-            self.flags[1] = 0;
-        }
+        self.update_flags(Register::int(res));
 
         self.ip += 3;
         return;
@@ -672,16 +1598,7 @@ impl VM {
         self.registers[reg_dest_ind] = res;
         self.reg_types[reg_dest_ind] = RegTypes::int64;
 
-        if res == Register::int(0) {
-            self.flags[1] = 1; // zf
-        } else {
-            self.flags[1] = 0;
-        }
-        if res < Register::int(0) {
-            self.flags[2] = 1; // nf
-        } else {
-            self.flags[2] = 0;
-        }
+        self.update_flags(res);
 
         self.ip += 3;
         return;
@@ -702,11 +1619,7 @@ impl VM {
         self.registers[reg_dest_ind] = Register::int(res);
         self.reg_types[reg_dest_ind] = RegTypes::int64;
 
-        if res == 0 {
-            self.flags[1] = 1; // zf
-        } else {
-            self.flags[1] = 0;
-        }
+        self.update_flags(Register::int(res));
 
         self.ip += 3;
         return;
@@ -722,62 +1635,23 @@ impl VM {
             .pow(self.registers[reg_src_ind].as_i64() as u32);
         self.registers[reg_dest_ind] = Register::int(res);
 
-        if res == 0 {
-            self.flags[1] = 1; // zf
-        } else {
-            self.flags[1] = 0;
-        }
-        if (res as i64) < 0 {
-            self.flags[2] = 1;
-        } else {
-            self.flags[2] = 0;
-        }
+        self.update_flags(Register::int(res));
         self.ip += 3;
         return;
     }
 
     fn op_iinc(&mut self) {
         // 0x2b, size: 2
-        // iinc rdst
-        let r_dst_ind: usize = self.memory[(self.ip + 1)] as usize;
-
-        let new_val: Register = self.registers[r_dst_ind] + Register::int(1);
-        self.registers[r_dst_ind] = new_val;
-        if (new_val == Register::int(0)) {
-            self.flags[1] = 1; // zf
-        } else {
-            self.flags[1] = 0;
-        }
-        if (new_val < Register::int(0)) {
-            self.flags[2] = 1; // nf
-        } else {
-            self.flags[2] = 0;
-        }
-
+        let r_dest_ind: usize = self.memory[self.ip + 1] as usize;
+        self.step_reg(0x2b, r_dest_ind);
         self.ip += 2;
-        return;
     }
 
     fn op_idec(&mut self) {
         // 0x2c, size: 2
-        // idec rdst
-        let r_dst_ind: usize = self.memory[(self.ip + 1)] as usize;
-
-        let new_val: Register = self.registers[r_dst_ind] - Register::int(1);
-        self.registers[r_dst_ind] = new_val;
-        if (new_val == Register::int(0)) {
-            self.flags[1] = 1; // zf
-        } else {
-            self.flags[1] = 0;
-        }
-        if (new_val < Register::int(0)) {
-            self.flags[2] = 1; // nf
-        } else {
-            self.flags[2] = 0;
-        }
-
+        let r_dest_ind: usize = self.memory[self.ip + 1] as usize;
+        self.step_reg(0x2c, r_dest_ind);
         self.ip += 2;
-        return;
     }
 
     fn op_fload(&mut self) {
@@ -798,9 +1672,13 @@ impl VM {
         let dest_r_ind: u8 = self.memory[(self.ip + 1) as usize];
         let src_r_ind: u8 = self.memory[(self.ip + 2) as usize];
 
-        let result: Register =
-            self.registers[dest_r_ind as usize] + self.registers[src_r_ind as usize];
+        let Some((a, b)) = self.typed_operands(dest_r_ind as usize, src_r_ind as usize, RegTypes::float64, "fadd") else {
+            self.ip += 3;
+            return;
+        };
+        let result: Register = a + b;
         self.registers[dest_r_ind as usize] = result;
+        self.reg_types[dest_r_ind as usize] = result.kind();
 
         self.ip += 3;
         return;
@@ -811,9 +1689,13 @@ impl VM {
         let dest_r_ind: u8 = self.memory[(self.ip + 1) as usize];
         let src_r_ind: u8 = self.memory[(self.ip + 2) as usize];
 
-        let result: Register =
-            self.registers[dest_r_ind as usize] * self.registers[src_r_ind as usize];
+        let Some((a, b)) = self.typed_operands(dest_r_ind as usize, src_r_ind as usize, RegTypes::float64, "fmul") else {
+            self.ip += 3;
+            return;
+        };
+        let result: Register = a * b;
         self.registers[dest_r_ind as usize] = result;
+        self.reg_types[dest_r_ind as usize] = result.kind();
 
         self.ip += 3;
         return;
@@ -824,9 +1706,13 @@ impl VM {
         let dest_r_ind: u8 = self.memory[(self.ip + 1) as usize];
         let src_r_ind: u8 = self.memory[(self.ip + 2) as usize];
 
-        let result: Register =
-            self.registers[dest_r_ind as usize] - self.registers[src_r_ind as usize];
+        let Some((a, b)) = self.typed_operands(dest_r_ind as usize, src_r_ind as usize, RegTypes::float64, "fsub") else {
+            self.ip += 3;
+            return;
+        };
+        let result: Register = a - b;
         self.registers[dest_r_ind as usize] = result;
+        self.reg_types[dest_r_ind as usize] = result.kind();
 
         self.ip += 3;
         return;
@@ -838,13 +1724,16 @@ impl VM {
         let reg_1_ind: u8 = self.memory[(self.ip + 2) as usize];
         let reg_2_ind: u8 = self.memory[(self.ip + 3) as usize];
 
-        if self.registers[reg_2_ind as usize] == Register::float(0f64) {
+        let Some((a, b)) = self.typed_operands(reg_1_ind as usize, reg_2_ind as usize, RegTypes::float64, "fdiv") else {
+            self.ip += 4;
+            return;
+        };
+        if b.as_f64() == 0.0 {
             self.exceptions_active.push(Exception::ZeroDivision);
             self.ip += 4;
             return;
         }
-        let result: Register =
-            self.registers[reg_1_ind as usize] / self.registers[reg_2_ind as usize];
+        let result: Register = a / b;
         self.registers[dest_r_ind as usize] = result;
         self.reg_types[dest_r_ind as usize] = RegTypes::float64;
 
@@ -858,8 +1747,11 @@ impl VM {
         let reg_1_ind: u8 = self.memory[(self.ip + 2) as usize];
         let reg_2_ind: u8 = self.memory[(self.ip + 3) as usize];
 
-        let result: Register =
-            self.registers[reg_1_ind as usize] % self.registers[reg_2_ind as usize];
+        let Some((a, b)) = self.typed_operands(reg_1_ind as usize, reg_2_ind as usize, RegTypes::float64, "frem") else {
+            self.ip += 4;
+            return;
+        };
+        let result: Register = a % b;
         self.registers[dest_r_ind as usize] = result;
         self.reg_types[dest_r_ind as usize] = RegTypes::float64;
 
@@ -872,19 +1764,12 @@ impl VM {
         let dest_r_ind: u8 = self.memory[(self.ip + 1) as usize];
         let src_r_ind: u8 = self.memory[(self.ip + 2) as usize];
 
-        let isLess: bool = self.registers[dest_r_ind as usize] < self.registers[src_r_ind as usize];
-        let isEqu: bool = self.registers[dest_r_ind as usize] == self.registers[src_r_ind as usize];
-
-        if isLess {
-            self.flags[2] = 1; // nf
-        } else {
-            self.flags[2] = 0;
-        }
-        if isEqu {
-            self.flags[1] = 1; // zf
-        } else {
-            self.flags[1] = 0;
-        }
+        let Some((a, b)) = self.typed_operands(dest_r_ind as usize, src_r_ind as usize, RegTypes::float64, "fcmp") else {
+            self.ip += 3;
+            return;
+        };
+        self.flags[2] = (a < b) as u8; // nf
+        self.flags[1] = (a == b) as u8; // zf
 
         self.ip += 3;
         return;
@@ -895,23 +1780,15 @@ impl VM {
         let dest_r_ind: u8 = self.memory[(self.ip + 1) as usize];
         let src_r_ind: u8 = self.memory[(self.ip + 2) as usize];
 
-        let dest_val: Register = self.registers[dest_r_ind as usize];
-        let src_val: Register = self.registers[src_r_ind as usize];
-        let epsilon: Register = Register::float(self.float_epsilon);
-
-        let isLess: bool = (src_val - dest_val) > (epsilon);
-        let isEqu: bool = (dest_val - src_val).as_f64().abs() < (epsilon.as_f64());
+        let Some((dest_val, src_val)) = self.typed_operands(dest_r_ind as usize, src_r_ind as usize, RegTypes::float64, "fcmp_eps") else {
+            self.ip += 3;
+            return;
+        };
+        let epsilon: f64 = self.float_epsilon;
 
-        if isLess {
-            self.flags[2] = 1; // nf
-        } else {
-            self.flags[2] = 0;
-        }
-        if isEqu {
-            self.flags[1] = 1; // zf
-        } else {
-            self.flags[1] = 0;
-        }
+        // less only by more than epsilon, equal within it
+        self.flags[2] = ((src_val.as_f64() - dest_val.as_f64()) > epsilon) as u8; // nf
+        self.flags[1] = ((dest_val.as_f64() - src_val.as_f64()).abs() < epsilon) as u8; // zf
 
         self.ip += 3;
         return;
@@ -927,11 +1804,7 @@ impl VM {
         self.registers[reg_dest_ind] = Register::float(res);
         self.reg_types[reg_dest_ind] = RegTypes::float64;
 
-        if res == 0.0f64 {
-            self.flags[1] = 1; // zf
-        } else {
-            self.flags[1] = 0;
-        }
+        self.update_flags(Register::float(res));
 
         self.ip += 3;
         return;
@@ -947,16 +1820,7 @@ impl VM {
         self.registers[reg_dest_ind] = res;
         self.reg_types[reg_dest_ind] = RegTypes::float64;
 
-        if res == Register::float(0.0f64) {
-            self.flags[1] = 1; // zf
-        } else {
-            self.flags[1] = 0;
-        }
-        if res < Register::float(0.0f64) {
-            self.flags[2] = 1; // nf
-        } else {
-            self.flags[2] = 0;
-        }
+        self.update_flags(res);
 
         self.ip += 3;
         return;
@@ -977,11 +1841,7 @@ impl VM {
         self.registers[reg_dest_ind] = Register::float(res);
         self.reg_types[reg_dest_ind] = RegTypes::float64;
 
-        if res == 0.0f64 {
-            self.flags[1] = 1; // zf
-        } else {
-            self.flags[1] = 0;
-        }
+        self.update_flags(Register::float(res));
 
         self.ip += 3;
         return;
@@ -996,20 +1856,18 @@ impl VM {
         let res: f64 = self.registers[reg_dest_ind]
             .as_f64()
             .powf(self.registers[reg_src_ind].as_f64());
-        println!(
-            "DBG {} ** {} = {}",
-            self.registers[reg_dest_ind].as_f64(),
-            self.registers[reg_src_ind].as_f64(),
-            res
-        );
+        if !self.quiet {
+            println!(
+                "DBG {} ** {} = {}",
+                self.registers[reg_dest_ind].as_f64(),
+                self.registers[reg_src_ind].as_f64(),
+                res
+            );
+        }
         self.registers[reg_dest_ind] = Register::float(res);
         self.reg_types[reg_dest_ind] = RegTypes::float64;
 
-        if res == 0.0f64 {
-            self.flags[1] = 1; // zf
-        } else {
-            self.flags[1] = 0;
-        }
+        self.update_flags(Register::float(res));
 
         self.ip += 3;
         return;
@@ -1022,19 +1880,40 @@ impl VM {
         let res: Register = self.registers[r_dst_ind] + Register::float(1f64);
 
         self.registers[r_dst_ind] = res;
-        if res == Register::float(0.0f64) {
-            self.flags[1] = 1; // zf
-        } else {
-            self.flags[1] = 0;
+        self.update_flags(res);
+
+        self.ip += 2;
+        return;
+    }
+
+    fn op_fsete(&mut self) {
+        // 0x3e, size: 2
+        // fsete rsrc - sets epsilon used by fcmp_eps
+        let r_src_ind: usize = self.memory[(self.ip + 1)] as usize;
+        if self.reg_types[r_src_ind] != RegTypes::float64 {
+            show_runtime_err(self, "fsete expects a float register");
+            self.exceptions_active.push(Exception::IncorrectRegType);
+            self.ip += 2;
+            return;
         }
-        if res < Register::float(0.0f64) {
-            self.flags[2] = 1; // nf
-        } else {
-            self.flags[2] = 0;
+        let eps: f64 = self.registers[r_src_ind].as_f64();
+        if !(eps.is_finite() && eps >= 0.0) {
+            show_runtime_err(self, &format!("Invalid float epsilon: {}", eps));
+            self.exceptions_active.push(Exception::InvalidDataType);
+            self.ip += 2;
+            return;
         }
+        self.float_epsilon = eps;
+        self.ip += 2;
+    }
 
+    fn op_fgete(&mut self) {
+        // 0x3f, size: 2
+        // fgete rdst - loads current fcmp_eps epsilon
+        let r_dst_ind: usize = self.memory[(self.ip + 1)] as usize;
+        self.registers[r_dst_ind] = Register::float(self.float_epsilon);
+        self.reg_types[r_dst_ind] = RegTypes::float64;
         self.ip += 2;
-        return;
     }
 
     fn op_fdec(&mut self) {
@@ -1044,16 +1923,7 @@ impl VM {
         let res: Register = self.registers[r_dst_ind] - Register::float(1f64);
 
         self.registers[r_dst_ind] = res;
-        if res == Register::float(0.0f64) {
-            self.flags[1] = 1; // zf
-        } else {
-            self.flags[1] = 0;
-        }
-        if res < Register::float(0.0f64) {
-            self.flags[2] = 1; // nf
-        } else {
-            self.flags[2] = 0;
-        }
+        self.update_flags(res);
 
         self.ip += 2;
         return;
@@ -1068,63 +1938,41 @@ impl VM {
 
     fn op_jz(&mut self) {
         // 0x41, size: 9
-        //println!("DBG: JZ, ZF = {}", self.flags[1]);
-        if self.flags[1] != 0 {
-            let target_addr: u64 = args_to_u64(&self.memory[(self.ip + 1)..(self.ip + 9)]);
-            self.ip = target_addr as usize;
-            return;
-        } else {
-            self.ip += 9;
-            return;
+        match self.jump_taken(0x41) {
+            true => self.ip = args_to_u64(&self.memory[(self.ip + 1)..(self.ip + 9)]) as usize,
+            false => self.ip += 9,
         }
     }
 
     fn op_jl(&mut self) {
         // 0x42, size: 9
-        //println!("DBG: NF = {}", self.flags[2]);
-        if self.flags[2] != 0 {
-            let target_addr: u64 = args_to_u64(&self.memory[(self.ip + 1)..(self.ip + 9)]);
-            self.ip = target_addr as usize;
-            return;
-        } else {
-            self.ip += 9;
-            return;
+        match self.jump_taken(0x42) {
+            true => self.ip = args_to_u64(&self.memory[(self.ip + 1)..(self.ip + 9)]) as usize,
+            false => self.ip += 9,
         }
     }
 
     fn op_jg(&mut self) {
         // 0x43, size: 9
-        if (self.flags[1] == 0) && (self.flags[2] == 0) {
-            let target_addr: u64 = args_to_u64(&self.memory[(self.ip + 1)..(self.ip + 9)]);
-            self.ip = target_addr as usize;
-            return;
-        } else {
-            self.ip += 9;
-            return;
+        match self.jump_taken(0x43) {
+            true => self.ip = args_to_u64(&self.memory[(self.ip + 1)..(self.ip + 9)]) as usize,
+            false => self.ip += 9,
         }
     }
 
     fn op_jge(&mut self) {
         // 0x44, size: 9
-        if self.flags[2] == 0 {
-            let target_addr: u64 = args_to_u64(&self.memory[(self.ip + 1)..(self.ip + 9)]);
-            self.ip = target_addr as usize;
-            return;
-        } else {
-            self.ip += 9;
-            return;
+        match self.jump_taken(0x44) {
+            true => self.ip = args_to_u64(&self.memory[(self.ip + 1)..(self.ip + 9)]) as usize,
+            false => self.ip += 9,
         }
     }
 
     fn op_jle(&mut self) {
         // 0x45, size: 9
-        if (self.flags[2] == 1) || (self.flags[1] == 1) {
-            let target_addr: u64 = args_to_u64(&self.memory[(self.ip + 1)..(self.ip + 9)]);
-            self.ip = target_addr as usize;
-            return;
-        } else {
-            self.ip += 9;
-            return;
+        match self.jump_taken(0x45) {
+            true => self.ip = args_to_u64(&self.memory[(self.ip + 1)..(self.ip + 9)]) as usize,
+            false => self.ip += 9,
         }
     }
 
@@ -1135,29 +1983,38 @@ impl VM {
         let exc_n = args_to_u64(&self.memory[(self.ip + 1)..(self.ip + 9)]);
         let tojump = args_to_u64(&self.memory[(self.ip + 9)..(self.ip + 17)]);
 
-        let exception: Exception = match exc_n {
-            0x1 => Exception::ZeroDivision,
-            0x2 => Exception::HeapAllocationFault,
-            0x3 => Exception::HeapFreeFault,
-            0x4 => Exception::HeapWriteFault,
-            0x5 => Exception::HeapReadFault,
-            0x6 => Exception::NegativeSqrt,
-            other => {
-                panic!("Unknown exception: {} at IP {}", other, self.ip);
+        let exception: Exception = match Exception::from_code(exc_n) {
+            Some(v) => v,
+            None => {
+                panic!("Unknown exception: {} at IP {}", exc_n, self.ip);
             }
         };
 
-        for (ind, ex) in self.exceptions_active.iter().enumerate() {
-            if *ex == exception {
-                self.ip = tojump as usize;
-                self.exceptions_active.remove(ind);
-                return;
-            }
+        if self.exceptions_active.take(exception) {
+            self.ip = tojump as usize;
+            return;
         }
 
         self.ip += 17;
     }
 
+    fn op_excclear(&mut self) {
+        // 0xFE 0x01, size: 10
+        // excclear exception_num
+        // drops the exception if it's pending, without jumping anywhere
+        let exc_n = args_to_u64(&self.memory[(self.ip + 2)..(self.ip + 10)]);
+        match Exception::from_code(exc_n) {
+            Some(exception) => {
+                self.exceptions_active.take(exception);
+            }
+            None => {
+                panic!("Unknown exception: {} at IP {}", exc_n, self.ip);
+            }
+        }
+
+        self.ip += 10;
+    }
+
     fn op_jmpr(&mut self) {
         // 0x47, size: 2 
         let instr_size: usize = 2;
@@ -1170,17 +2027,87 @@ impl VM {
     }
 
     fn op_jnz(&mut self) {
-        // 0x48, size: 9 
-        // jnz dstadddr
-        // jumps to addr if not zero 
-        if self.flags[1] == 0 {
-            let target_addr: u64 = args_to_u64(&self.memory[(self.ip + 1)..(self.ip + 9)]);
-            self.ip = target_addr as usize;
+        // 0x48, size: 9
+        match self.jump_taken(0x48) {
+            true => self.ip = args_to_u64(&self.memory[(self.ip + 1)..(self.ip + 9)]) as usize,
+            false => self.ip += 9,
+        }
+    }
+
+    fn op_switch(&mut self) {
+        // 0x49, size: 10
+        let instr_size: usize = 10;
+        // switch Rind table_addr
+        // jumps to table[Rind], or to table default if Rind is out of range.
+        // Table (voxasm `table` directive): count, default, count * addr
+        let r_ind: usize = self.memory[self.ip + 1] as usize;
+        let table_addr: usize = args_to_u64(&self.memory[(self.ip + 2)..(self.ip + 10)]) as usize;
+
+        if !self.segm_check(table_addr, 16, false) {
+            self.ip += instr_size;
+            return;
+        }
+        let count: u64 = args_to_u64(&self.memory[table_addr..(table_addr + 8)]);
+        let default_addr: u64 = args_to_u64(&self.memory[(table_addr + 8)..(table_addr + 16)]);
+
+        let ind: u64 = self.registers[r_ind].as_u64();
+        if ind >= count {
+            self.ip = default_addr as usize;
             return;
-        } else {
-            self.ip += 9;
+        }
+        // count comes from memory, a corrupted one lets the offset overflow
+        let entry_addr: usize = match (ind as usize).checked_mul(8).and_then(|off| (table_addr + 16).checked_add(off)) {
+            Some(v) => v,
+            None => {
+                show_runtime_err(self, &format!("Switch table at {:#x} has no entry {}", table_addr, ind));
+                self.exceptions_active.push(Exception::MainSegmFault);
+                self.ip += instr_size;
+                return;
+            }
+        };
+        if !self.segm_check(entry_addr, 8, false) {
+            self.ip += instr_size;
             return;
         }
+        self.ip = args_to_u64(&self.memory[entry_addr..(entry_addr + 8)]) as usize;
+    }
+
+    /// setX Rdst - uint 1 into Rdst if the condition of jX holds, 0 otherwise
+    fn set_cond(&mut self, cond: bool) {
+        let r_dst_ind: usize = self.memory[(self.ip + 1)] as usize;
+        self.registers[r_dst_ind] = Register::uint(cond as u64);
+        self.reg_types[r_dst_ind] = RegTypes::uint64;
+        self.ip += 2;
+    }
+
+    fn op_sete(&mut self) {
+        // 0x4a, size: 2
+        self.set_cond(self.flags[1] != 0);
+    }
+
+    fn op_setne(&mut self) {
+        // 0x4b, size: 2
+        self.set_cond(self.flags[1] == 0);
+    }
+
+    fn op_setl(&mut self) {
+        // 0x4c, size: 2
+        self.set_cond(self.flags[2] != 0);
+    }
+
+    fn op_setg(&mut self) {
+        // 0x4d, size: 2
+        self.set_cond((self.flags[1] == 0) && (self.flags[2] == 0));
+    }
+
+    fn op_setge(&mut self) {
+        // 0x4e, size: 2
+        self.set_cond(self.flags[2] == 0);
+    }
+
+    fn op_setle(&mut self) {
+        // 0x4f, size: 2
+        self.set_cond((self.flags[2] == 1) || (self.flags[1] == 1));
     }
 
     fn op_utoi(&mut self) {
@@ -1315,6 +2242,49 @@ impl VM {
         return;
     }
 
+    fn cmov_if(&mut self, cond: bool) {
+        // Copies R src into R dest like movr if cond holds
+        let r_dest_ind: usize = self.memory[(self.ip + 1) as usize] as usize;
+        let r_src_ind: usize = self.memory[(self.ip + 2) as usize] as usize;
+
+        if cond {
+            self.registers[r_dest_ind] = self.registers[r_src_ind];
+            self.reg_types[r_dest_ind] = self.reg_types[r_src_ind];
+        }
+
+        self.ip += 3;
+    }
+
+    fn op_xchg(&mut self) {
+        // 0x6c, size: 3
+        // xchg Ra Rb - swaps values of two registers with their types
+        let r_a_ind: usize = self.memory[(self.ip + 1) as usize] as usize;
+        let r_b_ind: usize = self.memory[(self.ip + 2) as usize] as usize;
+
+        self.registers.swap(r_a_ind, r_b_ind);
+        self.reg_types.swap(r_a_ind, r_b_ind);
+
+        self.ip += 3;
+    }
+
+    fn op_cmovz(&mut self) {
+        // 0x69, size: 3
+        // cmovz Rdest Rsrc - moves if ZF is set
+        self.cmov_if(self.flags[1] != 0);
+    }
+
+    fn op_cmovl(&mut self) {
+        // 0x6a, size: 3
+        // cmovl Rdest Rsrc - moves if NF is set
+        self.cmov_if(self.flags[2] != 0);
+    }
+
+    fn op_cmovg(&mut self) {
+        // 0x6b, size: 3
+        // cmovg Rdest Rsrc - moves if neither ZF nor NF is set
+        self.cmov_if((self.flags[1] == 0) && (self.flags[2] == 0));
+    }
+
     fn op_or(&mut self) {
         // 0x61, size: 3
         // Bitwise OR of R dest and R src, save into R dest
@@ -1325,11 +2295,7 @@ impl VM {
         let res: Register = self.registers[r_dest_ind] | self.registers[r_src_ind];
         self.registers[r_dest_ind] = res;
         self.reg_types[r_dest_ind] = self.reg_types[r_src_ind];
-        if res.as_u64() == 0 {
-            self.flags[1] = 1;
-        } else {
-            self.flags[1] = 0;
-        }
+        self.update_flags(res);
 
         self.ip += 3;
         return;
@@ -1345,11 +2311,7 @@ impl VM {
         let res: Register = self.registers[r_dest_ind] & self.registers[r_src_ind];
         self.registers[r_dest_ind] = res;
         self.reg_types[r_dest_ind] = self.reg_types[r_src_ind];
-        if res.as_u64() == 0 {
-            self.flags[1] = 1;
-        } else {
-            self.flags[1] = 0;
-        }
+        self.update_flags(res);
 
         self.ip += 3;
         return;
@@ -1365,11 +2327,7 @@ impl VM {
         let res: Register = !self.registers[r_src_ind];
         self.registers[r_dest_ind] = res;
         self.reg_types[r_dest_ind] = self.reg_types[r_src_ind];
-        if res.as_u64() == 0 {
-            self.flags[1] = 1;
-        } else {
-            self.flags[1] = 0;
-        }
+        self.update_flags(res);
 
         self.ip += 3;
         return;
@@ -1385,11 +2343,7 @@ impl VM {
         let res: Register = self.registers[r_dest_ind] ^ self.registers[r_src_ind];
         self.registers[r_dest_ind] = res;
         self.reg_types[r_dest_ind] = self.reg_types[r_src_ind];
-        if res.as_u64() == 0 {
-            self.flags[1] = 1;
-        } else {
-            self.flags[1] = 0;
-        }
+        self.update_flags(res);
 
         self.ip += 3;
         return;
@@ -1403,11 +2357,7 @@ impl VM {
         let r_src_ind: usize = self.memory[(self.ip + 2) as usize] as usize;
 
         let res: Register = self.registers[r_dest_ind] & self.registers[r_src_ind];
-        if res.as_u64() == 0 {
-            self.flags[1] = 1;
-        } else {
-            self.flags[1] = 0;
-        }
+        self.update_flags(res);
 
         self.ip += 3;
         return;
@@ -1423,11 +2373,7 @@ impl VM {
         let res: Register = self.registers[r_src_ind].logical_not();
         self.registers[r_dest_ind] = res;
         self.reg_types[r_dest_ind] = self.reg_types[r_src_ind];
-        if res.as_u64() == 0 {
-            self.flags[1] = 1;
-        } else {
-            self.flags[1] = 0;
-        }
+        self.update_flags(res);
 
         self.ip += 3;
         return;
@@ -1468,65 +2414,10 @@ impl VM {
     fn op_dsload(&mut self) {
         // 0x70, size: 18
         // dsload Rdest reladdr offset
-        const const_flag: u8 = 0x10;
-        let rel_addr: usize =
-            args_to_u64(&self.memory[(self.ip + 2 as usize)..(self.ip + 10 as usize)]) as usize; // relative address of target variable in VM memory
-        let offset: usize =
-            args_to_u64(&self.memory[(self.ip + 10 as usize)..(self.ip + 18 as usize)]) as usize
-                + 8
-                + 1; // 8 for length skip, 1 for type
-        let abs_addr: usize = (self.data_base as usize) + rel_addr + offset; // absolute addr.
-        let mut var_type_ind: u8 = self.memory[abs_addr - offset];
-        var_type_ind = var_type_ind & !const_flag; // getting clear type
-        if var_type_ind >= 0x6 && var_type_ind <= 0x8 {
-            var_type_ind -= 5; // dsload only loading value. use dslea for loading addr
-        }
-        let var_type: RegTypes = match var_type_ind {
-            0x1 => RegTypes::uint64,
-            0x2 => RegTypes::int64,
-            0x3 => RegTypes::float64,
-            0x4 => RegTypes::StrAddr,
-            other => panic!(
-                "CRITICAL: Unknown constant type: {}. IP: {}",
-                other, self.ip
-            ),
-        };
-        match var_type {
-            RegTypes::uint64 => {
-                let dest_reg_ind: u8 = self.memory[(self.ip + 1) as usize];
-                self.registers[dest_reg_ind as usize] =
-                    Register::uint(args_to_u64(&self.memory[(abs_addr)..(abs_addr + 8)]));
-
-                self.reg_types[dest_reg_ind as usize] = RegTypes::uint64;
-            }
-            RegTypes::int64 => {
-                let res: i64 = args_to_i64(&self.memory[(abs_addr)..(abs_addr + 8)]);
-                let dest_reg_ind: u8 = self.memory[(self.ip + 1) as usize];
-                self.registers[dest_reg_ind as usize] = Register::int(res);
-                self.reg_types[dest_reg_ind as usize] = RegTypes::int64;
-            }
-            RegTypes::float64 => {
-                let dest_reg_ind: u8 = self.memory[(self.ip + 1) as usize];
-                let res: f64 = args_to_f64(&self.memory[(abs_addr)..(abs_addr + 8)]);
-                self.registers[dest_reg_ind as usize] = Register::float(res);
-                self.reg_types[dest_reg_ind as usize] = RegTypes::float64;
-            }
-            RegTypes::StrAddr => {
-                let dest_reg_ind: u8 = self.memory[(self.ip + 1) as usize];
-                self.registers[dest_reg_ind as usize] = Register::StrAddr(abs_addr as u64); // +1 for type, +8 for length
-                self.reg_types[dest_reg_ind as usize] = RegTypes::StrAddr;
-            }
-            RegTypes::address => {
-                let dest_reg_ind: u8 = self.memory[(self.ip + 1) as usize];
-                self.registers[dest_reg_ind as usize] = Register::address(abs_addr as u64); // +1 for type, +8 for length
-                self.reg_types[dest_reg_ind as usize] = RegTypes::address;
-            }
-            RegTypes::ds_addr => {
-                let dest_reg_ind: u8 = self.memory[(self.ip + 1) as usize];
-                self.registers[dest_reg_ind as usize] = Register::ds_addr(abs_addr as u64); // +1 for type, +8 for length
-                self.reg_types[dest_reg_ind as usize] = RegTypes::ds_addr;
-            }
-        }
+        let r_dest_ind: usize = self.memory[self.ip + 1] as usize;
+        let rel_addr: usize = args_to_u64(&self.memory[(self.ip + 2)..(self.ip + 10)]) as usize; // relative address of target variable in VM memory
+        let offset: usize = args_to_u64(&self.memory[(self.ip + 10)..(self.ip + 18)]) as usize;
+        self.ds_load(r_dest_ind, rel_addr, offset);
 
         self.ip += 18;
         return;
@@ -1535,65 +2426,10 @@ impl VM {
     fn op_dsrload(&mut self) {
         // 0x71, size: 11
         // dsload Rdest Roffset reladdr
-        const const_flag: u8 = 0x10;
-        let offset: usize = (self.registers[self.memory[(self.ip + 2) as usize] as usize].as_u64()
-            + 8
-            + 1) as usize; // 8 for
-                           // length skip
-        let rel_addr: usize =
-            args_to_u64(&self.memory[(self.ip + 3 as usize)..(self.ip + 11 as usize)]) as usize; // relative address of target variable in VM memory
-        let abs_addr: usize = (self.data_base as usize) + rel_addr + offset;
-        let mut var_type_ind: u8 = self.memory[abs_addr - offset];
-        var_type_ind = var_type_ind & !const_flag;
-        if var_type_ind >= 0x6 && var_type_ind <= 0x8 {
-            var_type_ind -= 5; // dsload only loading value. use dslea for loading addr
-        }
-        let var_type: RegTypes = match var_type_ind {
-            0x1 => RegTypes::uint64,
-            0x2 => RegTypes::int64,
-            0x3 => RegTypes::float64,
-            0x4 => RegTypes::StrAddr,
-            other => panic!(
-                "CRITICAL: Unknown constant type: {}. IP: {}",
-                other, self.ip
-            ),
-        };
-        match var_type {
-            RegTypes::uint64 => {
-                let dest_reg_ind: u8 = self.memory[(self.ip + 1) as usize];
-                self.registers[dest_reg_ind as usize] =
-                    Register::uint(args_to_u64(&self.memory[(abs_addr)..(abs_addr + 8)]));
-                self.reg_types[dest_reg_ind as usize] = RegTypes::uint64;
-                //println!("DBG start addr: {}", abs_addr + 2);
-            }
-            RegTypes::int64 => {
-                let res: i64 = args_to_i64(&self.memory[(abs_addr)..(abs_addr + 8)]);
-                let dest_reg_ind: u8 = self.memory[(self.ip + 1) as usize];
-                self.registers[dest_reg_ind as usize] = Register::int(res);
-                self.reg_types[dest_reg_ind as usize] = RegTypes::int64;
-            }
-            RegTypes::float64 => {
-                let dest_reg_ind: u8 = self.memory[(self.ip + 1) as usize];
-                let res: f64 = args_to_f64(&self.memory[(abs_addr)..(abs_addr + 8)]);
-                self.registers[dest_reg_ind as usize] = Register::float(res);
-                self.reg_types[dest_reg_ind as usize] = RegTypes::float64;
-            }
-            RegTypes::StrAddr => {
-                let dest_reg_ind: u8 = self.memory[(self.ip + 1) as usize];
-                self.registers[dest_reg_ind as usize] = Register::StrAddr(abs_addr as u64); // +1 for type, +8 for length
-                self.reg_types[dest_reg_ind as usize] = RegTypes::StrAddr;
-            }
-            RegTypes::address => {
-                let dest_reg_ind: u8 = self.memory[(self.ip + 1) as usize];
-                self.registers[dest_reg_ind as usize] = Register::address(abs_addr as u64); // +1 for type, +8 for length
-                self.reg_types[dest_reg_ind as usize] = RegTypes::address;
-            }
-            RegTypes::ds_addr => {
-                let dest_reg_ind: u8 = self.memory[(self.ip + 1) as usize];
-                self.registers[dest_reg_ind as usize] = Register::ds_addr(abs_addr as u64); // +1 for type, +8 for length
-                self.reg_types[dest_reg_ind as usize] = RegTypes::ds_addr;
-            }
-        }
+        let r_dest_ind: usize = self.memory[self.ip + 1] as usize;
+        let offset: usize = self.registers[self.memory[self.ip + 2] as usize].as_u64() as usize;
+        let rel_addr: usize = args_to_u64(&self.memory[(self.ip + 3)..(self.ip + 11)]) as usize; // relative address of target variable in VM memory
+        self.ds_load(r_dest_ind, rel_addr, offset);
 
         self.ip += 11;
         return;
@@ -1603,39 +2439,10 @@ impl VM {
         // 0x72, size: 18
         // dssave Rsrc rel_addr offset
         // Updates the value in data segment
-        const CONST_MASK: u8 = 0x10;
         let r_src_ind: usize = self.memory[(self.ip + 1) as usize] as usize;
         let rel_addr: usize = args_to_u64(&self.memory[(self.ip + 2)..(self.ip + 10)]) as usize;
         let offset: usize = args_to_u64(&self.memory[(self.ip + 10)..(self.ip + 18)]) as usize;
-
-        let abs_addr: usize = (self.data_base as usize) + rel_addr + offset + 1 + 8; // +1 for var
-        if (self.memory[self.data_base as usize + rel_addr] & CONST_MASK) != 0 {
-            panic!(
-                "CRITICAL: Attempting to write new value into DS constant at IP {}",
-                self.ip
-            );
-        }
-        // type, +1 for var size
-        match self.reg_types[r_src_ind] {
-            RegTypes::uint64 | RegTypes::StrAddr | RegTypes::address | RegTypes::ds_addr => {
-                let val: [u8; 8] = self.registers[r_src_ind].as_u64().to_be_bytes();
-                for i in 0..8 {
-                    self.memory[abs_addr + i] = val[i];
-                }
-            }
-            RegTypes::int64 => {
-                let val: [u8; 8] = self.registers[r_src_ind].as_i64().to_be_bytes();
-                for i in 0..8 {
-                    self.memory[abs_addr + i] = val[i];
-                }
-            }
-            RegTypes::float64 => {
-                let val: [u8; 8] = (self.registers[r_src_ind].as_f64()).to_be_bytes();
-                for i in 0..8 {
-                    self.memory[abs_addr + i] = val[i];
-                }
-            }
-        }
+        self.ds_save(r_src_ind, rel_addr, offset);
 
         self.ip += 18;
         return;
@@ -1643,41 +2450,11 @@ impl VM {
     fn op_dsrsave(&mut self) {
         // 0x73, size: 11
         // dsrsave Rsrc Roffset rel_addr
-        const CONST_MASK: u8 = 0x10;
         let r_src_ind: usize = self.memory[(self.ip + 1) as usize] as usize;
         let r_offset_ind: usize = self.memory[(self.ip + 2) as usize] as usize;
-        let offset = self.registers[r_offset_ind];
+        let offset: usize = self.registers[r_offset_ind].as_u64() as usize;
         let rel_addr: usize = args_to_u64(&self.memory[(self.ip + 3)..(self.ip + 11)]) as usize;
-
-        let abs_addr: usize =
-            (self.data_base as usize) + rel_addr + (offset.as_u64() as usize) + 1 + 8; // +1 for var
-                                                                                       // type, +1 for var size
-        if (self.memory[self.data_base as usize + rel_addr] & CONST_MASK) != 0 {
-            panic!(
-                "CRITICAL: Attempting to write new value into DS constant at IP {}",
-                self.ip
-            );
-        }
-        match self.reg_types[r_src_ind] {
-            RegTypes::uint64 | RegTypes::StrAddr | RegTypes::address | RegTypes::ds_addr => {
-                let val: [u8; 8] = self.registers[r_src_ind].as_u64().to_be_bytes();
-                for i in 0..8 {
-                    self.memory[abs_addr + i] = val[i];
-                }
-            }
-            RegTypes::int64 => {
-                let val: [u8; 8] = self.registers[r_src_ind].as_i64().to_be_bytes();
-                for i in 0..8 {
-                    self.memory[abs_addr + i] = val[i];
-                }
-            }
-            RegTypes::float64 => {
-                let val: [u8; 8] = self.registers[r_src_ind].as_f64().to_be_bytes();
-                for i in 0..8 {
-                    self.memory[abs_addr + i] = val[i];
-                }
-            }
-        }
+        self.ds_save(r_src_ind, rel_addr, offset);
 
         self.ip += 11;
         return;
@@ -1698,6 +2475,21 @@ impl VM {
         self.ip += 18;
         return;
     }
+    fn op_ldstr(&mut self) {
+        // 0xFE 0x05 (ldstr) / 0xFE 0x06 (ldbytes), size: 11 + length
+        // ldstr Rdest length data.. - the data is laid out like a data
+        // segment str, length right before it. Strings load as dsload
+        // loads them, byte blobs as dslea addresses
+        let r_dest_ind: usize = self.memory[self.ip + 2] as usize;
+        let len: u64 = args_to_u64(&self.memory[(self.ip + 3)..(self.ip + 11)]);
+        let abs_addr: u64 = (self.ip + 11) as u64;
+        (self.registers[r_dest_ind], self.reg_types[r_dest_ind]) = match self.memory[self.ip + 1] {
+            0x05 => (Register::StrAddr(abs_addr), RegTypes::StrAddr),
+            _ => (Register::uint(abs_addr), RegTypes::ds_addr),
+        };
+
+        self.ip += 11 + len as usize;
+    }
     fn op_dsderef(&mut self) {
         // 0x75, size: 11
         // dsderef Rsrc Rdest Offset
@@ -1705,28 +2497,7 @@ impl VM {
         let r_dest_ind: usize = self.memory[(self.ip + 2) as usize] as usize;
         let offset: usize =
             args_to_u64(&self.memory[(self.ip + 3) as usize..(self.ip + 11) as usize]) as usize;
-
-        let src_val = self.registers[r_src_ind].as_u64() as usize;
-        let val_type = self.memory[src_val - offset];
-        if val_type == 0x4 {
-            panic!(
-                "CRITICAL: At Instruction {:#x}:\n String constant cannot be dereferenced. \nCoredump created.",
-                self.ip
-            );
-        }
-
-        let tgt_addr: usize = src_val - offset + 8 + 1; // 8 for length skip
-        self.registers[r_dest_ind] =
-            Register::uint(args_to_u64(&self.memory[tgt_addr..(tgt_addr + 8)]));
-        self.reg_types[r_dest_ind] = match val_type {
-            0x1 | 0x5 => RegTypes::uint64,
-            0x2 | 0x6 => RegTypes::int64,
-            0x3 | 0x7 => RegTypes::float64,
-            0x4 => RegTypes::StrAddr, //wont be reached anyway
-            other => {
-                panic!("Unknown data type: {}", other);
-            }
-        };
+        self.ds_deref(r_src_ind, r_dest_ind, offset);
 
         self.ip += 11;
         return;
@@ -1753,61 +2524,245 @@ impl VM {
         let r_src_ind: usize = self.memory[(self.ip + 1) as usize] as usize;
         let r_dest_ind: usize = self.memory[(self.ip + 2) as usize] as usize;
         let r_offset_ind: usize = self.memory[(self.ip + 3) as usize] as usize;
-
         let offset: usize = self.registers[r_offset_ind].as_u64() as usize;
+        self.ds_deref(r_src_ind, r_dest_ind, offset);
 
-        let src_val = self.registers[r_src_ind].as_u64() as usize;
-        let val_type = self.memory[src_val - offset];
-        if val_type == 0x4 {
-            if let Err(e) = self.err_coredump() {
-                eprintln!("Error creating coredump: {}", e);
-            };
+        self.ip += 4;
+        return;
+    }
+
+    fn op_dsstore(&mut self) {
+        // 0x78, size: 11
+        // dsstore Rptr Rcount rel_addr
+        // Copies Rcount utf16 bytes from heap into a non-const str variable,
+        // sets of flag if the string was cut to the variable's allocated length
+        let r_ptr_ind: usize = self.memory[(self.ip + 1)] as usize;
+        let r_count_ind: usize = self.memory[(self.ip + 2)] as usize;
+        let rel_addr: usize = args_to_u64(&self.memory[(self.ip + 3)..(self.ip + 11)]) as usize;
+        let var_addr: usize = self.data_base as usize + rel_addr;
+
+        if !self.segm_check(var_addr, 1 + 8, false) {
+            self.ip += 11;
+            return;
+        }
+        if DsType::decode(self.memory[var_addr]) != Some(DsType::Str) {
+            show_runtime_err(self, "dsstore target is not a str variable");
+            self.exceptions_active.push(Exception::InvalidDataType);
+            self.ip += 11;
+            return;
+        }
+        // the length prefix shrinks with shorter strings, so the allocated one is kept aside
+        let cur_len: u64 = args_to_u64(&self.memory[(var_addr + 1)..(var_addr + 9)]);
+        let cap: u64 = *self.ds_str_caps.entry(rel_addr).or_insert(cur_len);
+        if !self.segm_check(var_addr + 1, 8 + cap as usize, true) {
+            self.ip += 11;
+            return;
+        }
+        if DsType::is_const(self.memory[var_addr]) {
             panic!(
-                "CRITICAL: At Instruction {:#x}:\n String constant cannot be dereferenced. \nCoredump created.",
+                "CRITICAL: Attempting to write new value into DS constant at IP {}",
                 self.ip
             );
         }
 
-        let tgt_addr: usize = src_val - offset + 8 + 1; // 8 for length skip
-        self.registers[r_dest_ind] =
-            Register::uint(args_to_u64(&self.memory[tgt_addr..(tgt_addr + 8)]));
-        self.reg_types[r_dest_ind] = match val_type {
-            0x1 | 0x5 => RegTypes::uint64,
-            0x2 | 0x6 => RegTypes::int64,
-            0x3 | 0x7 => RegTypes::float64,
-            0x4 => RegTypes::StrAddr, //wont be reached anyway
-            other => {
-                self.err_coredump();
-                panic!(
-                    "Unknown data type: {} at IP = {:#x}, src val at {:#x}",
-                    other,
-                    self.ip,
-                    src_val - offset
-                );
+        let ptr: u64 = self.registers[r_ptr_ind].as_u64();
+        let count: u64 = self.registers[r_count_ind].as_u64() & !1; // whole utf16 units
+        let len: u64 = count.min(cap);
+        record_heap(self, ptr, false);
+        let bytes: Vec<u8> = match self.heap.read(ptr, len) {
+            Ok(v) => v,
+            Err(()) => {
+                show_runtime_err(self, &format!("Can't read {} bytes of heap at {:#x}", len, ptr));
+                self.exceptions_active.push(Exception::HeapReadFault);
+                self.ip += 11;
+                return;
             }
         };
+        record_data(self, rel_addr, true);
+        let payload: usize = var_addr + 9;
+        self.memory[payload..(payload + len as usize)].copy_from_slice(&bytes);
+        self.memory[(payload + len as usize)..(payload + cap as usize)].fill(0);
+        self.memory[(var_addr + 1)..(var_addr + 9)].copy_from_slice(&len.to_be_bytes());
+        self.flags[0] = (count > cap) as u8;
 
-        self.ip += 4;
+        self.ip += 11;
         return;
     }
 
-        
+    fn op_dsindex(&mut self) {
+        // 0x79, size: 11
+        // dsindex Rdest rel_addr Ridx
+        // Rdest = payload offset of element Ridx of an array, bytes or str
+        // variable, ready for dsrload/dsrsave/dsrlea. The index is checked
+        // against the stored length, past it raises mainsegmfault
+        let r_dest_ind: usize = self.memory[self.ip + 1] as usize;
+        let rel_addr: usize = args_to_u64(&self.memory[(self.ip + 2)..(self.ip + 10)]) as usize;
+        let r_idx_ind: usize = self.memory[self.ip + 10] as usize;
+        let var_addr: usize = self.data_base as usize + rel_addr;
+
+        if !self.segm_check(var_addr, DS_HEADER, false) {
+            self.ip += 11;
+            return;
+        }
+        let ds_type: DsType = self.ds_type_at(var_addr);
+        if !ds_type.is_indexable() {
+            show_runtime_err(self, &format!("dsindex: a {} variable has no elements", ds_type.name()));
+            self.exceptions_active.push(Exception::InvalidDataType);
+            self.ip += 11;
+            return;
+        }
+        let width: u64 = ds_type.elem_width() as u64;
+        let count: u64 = args_to_u64(&self.memory[(var_addr + 1)..(var_addr + DS_HEADER)]) / width;
+        let idx: u64 = self.registers[r_idx_ind].as_u64();
+        if idx >= count {
+            show_runtime_err(self, &format!("dsindex: index {} is out of {} elements", idx, count));
+            self.exceptions_active.push(Exception::MainSegmFault);
+            self.ip += 11;
+            return;
+        }
+        self.registers[r_dest_ind] = Register::uint(idx * width);
+        self.reg_types[r_dest_ind] = RegTypes::uint64;
+
+        self.ip += 11;
+        return;
+    }
+
+    /// Byte length of the string register `reg` points at: the length
+    /// prefix of a data segment string (StrAddr or the `dslea var 9`
+    /// address) or ldstr literal, the rest of its block for a heap
+    /// address. None after raising mainsegmfault / heapsegmfault
+    fn str_bytes(&mut self, reg: usize, what: &str) -> Option<u64> {
+        let addr: u64 = self.registers[reg].as_u64();
+        if self.reg_types[reg] == RegTypes::address {
+            let len: Option<u64> = self.heap.bytes_from(addr);
+            if len.is_none() {
+                show_runtime_err(self, &format!("{}: no heap string at {:#x}", what, addr));
+                self.exceptions_active.push(Exception::HeapSegmFault);
+            }
+            return len;
+        }
+        let start: usize = addr as usize;
+        // a str variable's header (the type byte may carry the const flag)
+        // or the `0xFE 0x05 Rdst length` of an ldstr literal
+        let is_ldstr = |mem: &[u8], v: usize| (v >= 2) && (mem[(v - 2)..v] == [0xFE, 0x05]);
+        let var_addr: usize = match start.checked_sub(DS_HEADER) {
+            Some(v) if !self.segm_check(v, DS_HEADER, false) => return None,
+            Some(v) if DsType::decode(self.memory[v]) == Some(DsType::Str) || is_ldstr(&self.memory, v) => v,
+            _ => {
+                show_runtime_err(self, &format!("{}: no data segment string at {:#x}", what, addr));
+                self.exceptions_active.push(Exception::MainSegmFault);
+                return None;
+            }
+        };
+        let len: u64 = args_to_u64(&self.memory[(var_addr + 1)..start]);
+        match self.segm_check(start, len as usize, false) {
+            true => Some(len),
+            false => None,
+        }
+    }
+
+    fn op_strindex(&mut self) {
+        // 0x7A, size: 4
+        // strindex Rdest Rstr Ridx
+        // Rdest = UTF-16 code unit Ridx of the string at Rstr, a data
+        // segment string or a heap address. Past the end raises
+        // mainsegmfault (heapsegmfault for heap strings)
+        let r_dest_ind: usize = self.memory[self.ip + 1] as usize;
+        let r_str_ind: usize = self.memory[self.ip + 2] as usize;
+        let r_idx_ind: usize = self.memory[self.ip + 3] as usize;
+        let idx: u64 = self.registers[r_idx_ind].as_u64();
+        self.ip += 4;
+
+        let Some(len) = self.str_bytes(r_str_ind, "strindex") else {
+            return;
+        };
+        let on_heap: bool = self.reg_types[r_str_ind] == RegTypes::address;
+        if idx >= len / 2 {
+            show_runtime_err(self, &format!("strindex: index {} is out of {} code units", idx, len / 2));
+            self.exceptions_active.push(match on_heap {
+                true => Exception::HeapSegmFault,
+                false => Exception::MainSegmFault,
+            });
+            return;
+        }
+        let unit_addr: u64 = self.registers[r_str_ind].as_u64() + idx * 2;
+        let unit: u16 = match on_heap {
+            true => match self.heap.read(unit_addr, 2) {
+                Ok(b) => u16::from_be_bytes([b[0], b[1]]),
+                Err(()) => {
+                    self.exceptions_active.push(Exception::HeapReadFault);
+                    return;
+                }
+            },
+            false => u16::from_be_bytes([self.memory[unit_addr as usize], self.memory[unit_addr as usize + 1]]),
+        };
+        self.registers[r_dest_ind] = Register::uint(unit as u64);
+        self.reg_types[r_dest_ind] = RegTypes::uint64;
+    }
+
+    fn op_strlen(&mut self) {
+        // 0x7B, size: 3
+        // strlen Rdest Rstr
+        // Rdest = count of UTF-16 code units of the string at Rstr: its
+        // length prefix for a data segment string, the rest of its block
+        // for a heap address
+        let r_dest_ind: usize = self.memory[self.ip + 1] as usize;
+        let r_str_ind: usize = self.memory[self.ip + 2] as usize;
+        self.ip += 3;
+
+        if let Some(len) = self.str_bytes(r_str_ind, "strlen") {
+            self.registers[r_dest_ind] = Register::uint(len / 2);
+            self.reg_types[r_dest_ind] = RegTypes::uint64;
+        }
+    }
+
+    /// Full machine state, see coredump.rs
     pub fn coredump(&mut self) -> Vec<u8> {
-        let mut res: Vec<u8> = Vec::new();
-        let zeros: Vec<u8> = vec![0; 16];
-        res.extend(&clone_placed(&self.memory));
-        res.extend(&(zeros.clone()));
-
-        // let stack_u8_cl: Vec<u8> = clone_placed_64(&self.stack)
-        //     .iter()
-        //     .flat_map(|num| num.to_be_bytes())
-        //     .collect();
-        // res.extend(&stack_u8_cl);
-        //res.extend(&zeros);
-        //res.extend(&(clone_placed(&self.heap.heap)));
+        coredump::encode(self)
+    }
+
+    /// (start pointer, size) of the data segment
+    pub fn data_range(&self) -> (u64, u64) {
+        (self.data_base, self.data_size)
+    }
+
+    /// Human readable final state, used by the golden tests
+    pub fn state_dump(&self) -> String {
+        let mut res: String = String::new();
+        res.push_str(&format!("ip: {:#x}\n", self.ip));
+        res.push_str(&format!(
+            "flags: of={} zf={} nf={} cf={}\n",
+            self.flags[0], self.flags[1], self.flags[2], self.flags[3]
+        ));
+        for (ind, reg) in self.registers.iter().enumerate() {
+            res.push_str(&format!("r{}: {:?}\n", ind, reg));
+        }
+        res.push_str(&format!("exceptions: {}\n", self.exceptions_active.names()));
+        res.push_str(&format!("stack frames: {}\n", self.stack.stack.len()));
+
+        let mut blocks: Vec<&HeapBlock> = self.heap.allocated.iter().collect();
+        blocks.sort_by_key(|b| b.start_byte);
+        res.push_str(&format!("heap blocks: {}\n", blocks.len()));
+        for block in blocks {
+            let content: Vec<String> = (block.start_byte..block.last_byte)
+                .map(|i| format!("{:02x}", self.heap.heap.get(i).cloned().unwrap_or(0)))
+                .collect();
+            let site: String = match &block.site {
+                Some(s) => format!(" <- {}", self.site_desc(s)),
+                None => String::new(),
+            };
+            res.push_str(&format!(
+                "  {:#x}+{}: {}{}\n",
+                block.start_byte,
+                block.size,
+                content.join(""),
+                site
+            ));
+        }
         res
     }
-    fn err_coredump(&mut self) -> std::result::Result<(), String> {
+
+    pub fn err_coredump(&mut self) -> std::result::Result<(), String> {
         let dump = self.coredump();
         let mut out_file: File = match File::create("voxvm_err.dump") {
             Ok(f) => f,
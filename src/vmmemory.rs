@@ -0,0 +1,188 @@
+use std::ops::{Deref, DerefMut};
+
+#[cfg(feature = "mmap-vve")]
+use std::{fs::File, os::fd::AsRawFd};
+
+#[cfg(feature = "mmap-vve")]
+use crate::fileformats::VoxExeHeader;
+
+// VM memory (code, then data, then whatever `alloc`/`asm_load` append) as a
+// byte slice. Normally an owned Vec; with the `mmap-vve` cargo feature big
+// vve files are mapped instead of read (a private, copy-on-write mapping),
+// so startup doesn't copy the program and the OS only reads the pages the
+// run touches. Writes (data segment stores, little-endian code words being
+// swapped at load) copy just the pages they hit and never reach the file.
+// Growing the memory turns it into an owned Vec first.
+
+/// vve files from this size on are mapped with the `mmap-vve` feature,
+/// smaller ones are cheaper to read
+#[cfg(feature = "mmap-vve")]
+pub const MMAP_VVE_MIN: u64 = 256 * 1024;
+
+#[derive(Debug)]
+enum Backing {
+    Owned(Vec<u8>),
+    #[cfg(feature = "mmap-vve")]
+    Mapped(MappedVve),
+}
+
+#[derive(Debug)]
+pub struct VmMemory {
+    backing: Backing,
+}
+
+impl VmMemory {
+    pub fn with_capacity(init_mem: usize) -> VmMemory {
+        VmMemory {
+            backing: Backing::Owned(Vec::with_capacity(init_mem)),
+        }
+    }
+
+    /// Upper bound of code addresses and of what `alloc` may grow the
+    /// memory to, like the capacity of a Vec
+    pub fn capacity(&self) -> usize {
+        match &self.backing {
+            Backing::Owned(v) => v.capacity(),
+            #[cfg(feature = "mmap-vve")]
+            Backing::Mapped(m) => m.len.max(m.reserved),
+        }
+    }
+
+    /// Whether the memory is a mapped vve file (still)
+    pub fn is_mapped(&self) -> bool {
+        !matches!(self.backing, Backing::Owned(_))
+    }
+
+    /// The owned Vec, copying a mapping into one first
+    fn owned(&mut self) -> &mut Vec<u8> {
+        if self.is_mapped() {
+            let mut v: Vec<u8> = Vec::with_capacity(self.capacity());
+            v.extend_from_slice(self);
+            self.backing = Backing::Owned(v);
+        }
+        match &mut self.backing {
+            Backing::Owned(v) => v,
+            #[cfg(feature = "mmap-vve")]
+            Backing::Mapped(_) => unreachable!(),
+        }
+    }
+
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        self.owned().extend_from_slice(bytes);
+    }
+
+    pub fn resize(&mut self, len: usize, value: u8) {
+        self.owned().resize(len, value);
+    }
+
+    /// Takes the body of a mapped vve file as the whole memory. Memory that
+    /// already holds something gets a copy appended instead
+    #[cfg(feature = "mmap-vve")]
+    pub fn adopt(&mut self, mut body: MappedVve) {
+        match self.is_empty() {
+            true => {
+                body.reserved = self.capacity();
+                self.backing = Backing::Mapped(body);
+            }
+            false => self.extend_from_slice(&body),
+        }
+    }
+}
+
+impl Extend<u8> for VmMemory {
+    fn extend<T: IntoIterator<Item = u8>>(&mut self, iter: T) {
+        self.owned().extend(iter);
+    }
+}
+
+impl<'a> Extend<&'a u8> for VmMemory {
+    fn extend<T: IntoIterator<Item = &'a u8>>(&mut self, iter: T) {
+        self.owned().extend(iter);
+    }
+}
+
+impl Deref for VmMemory {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.backing {
+            Backing::Owned(v) => v,
+            #[cfg(feature = "mmap-vve")]
+            Backing::Mapped(m) => m,
+        }
+    }
+}
+
+impl DerefMut for VmMemory {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match &mut self.backing {
+            Backing::Owned(v) => v,
+            #[cfg(feature = "mmap-vve")]
+            Backing::Mapped(m) => m,
+        }
+    }
+}
+
+/// The part of a mapped vve file after its header
+#[cfg(feature = "mmap-vve")]
+#[derive(Debug)]
+pub struct MappedVve {
+    host: *mut u8, // start of the mapping (the header)
+    map_len: usize,
+    offset: usize, // header size
+    len: usize,
+    reserved: usize, // capacity of the memory it replaced
+}
+
+#[cfg(feature = "mmap-vve")]
+impl Drop for MappedVve {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.host.cast(), self.map_len) };
+    }
+}
+
+#[cfg(feature = "mmap-vve")]
+impl Deref for MappedVve {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.host.add(self.offset), self.len) }
+    }
+}
+
+#[cfg(feature = "mmap-vve")]
+impl DerefMut for MappedVve {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.host.add(self.offset), self.len) }
+    }
+}
+
+/// Maps the vve file at `path` and reads its header. The body comes back
+/// with its code words already in host order. None for files under
+/// MMAP_VVE_MIN, read those with VveImage::load
+#[cfg(feature = "mmap-vve")]
+pub fn map_vve(path: &str, min_version: u16) -> Result<Option<(VoxExeHeader, MappedVve)>, String> {
+    let file: File = File::open(path).map_err(|e| format!("Can't load {}: {}", path, e))?;
+    let map_len: u64 = file.metadata().map_err(|e| format!("Can't load {}: {}", path, e))?.len();
+    if map_len < MMAP_VVE_MIN {
+        return Ok(None);
+    }
+    let map_len: usize = map_len as usize;
+    let prot: i32 = libc::PROT_READ | libc::PROT_WRITE;
+    let host = unsafe { libc::mmap(std::ptr::null_mut(), map_len, prot, libc::MAP_PRIVATE, file.as_raw_fd(), 0) };
+    if host == libc::MAP_FAILED {
+        return Err(format!("Can't map {}: {}", path, std::io::Error::last_os_error()));
+    }
+    let mut body: MappedVve = MappedVve {
+        host: host.cast(),
+        map_len,
+        offset: 0,
+        len: map_len,
+        reserved: 0,
+    };
+    let header: VoxExeHeader = VoxExeHeader::from_bytes(&body, path, min_version)?;
+    body.offset = header.size().min(map_len);
+    body.len = map_len - body.offset;
+    header.swap_code_words(&mut body);
+    Ok(Some((header, body)))
+}
@@ -0,0 +1,278 @@
+// `voxvm diff-vve a.vve b.vve`: what changed between two builds of a
+// program, by structure instead of bytes. Header fields and sections,
+// functions matched by name (`func #N` without a symbols section), the
+// disassembly of every function both builds have, and data variables
+// matched by name (`var@addr` without data symbols). Jump targets print as
+// `<func+offset>` and call targets as `<func>`, so code that only moved
+// doesn't show up as changed. Exit status is 0 without differences, 1 with
+// some and 2 if a file can't be read, like diff(1).
+
+use std::collections::HashMap;
+
+use crate::{
+    cfgexport::{find_blocks, Blocks, CfgImage, Decoded},
+    dstype::{DsType, DS_HEADER},
+    fileformats::{read_data_symbols, ByteOrder, SECT_DATA_SYMBOLS},
+    hexdump::sect_name,
+    misclib::args_to_u64,
+    vvelink::VveImage,
+};
+
+const MIN_VVE_VERSION: u16 = 3;
+const CONTEXT: usize = 2; // unchanged lines shown around a change
+const SHOWN_BYTES: usize = 32; // of a data variable payload
+
+struct DataVar {
+    name: String,
+    desc: String,
+    bytes: Vec<u8>, // type byte, length and payload
+}
+
+struct Program {
+    header: Vec<String>,
+    funcs: Vec<(String, Vec<String>)>, // in address order
+    data: Vec<DataVar>,
+}
+
+impl Program {
+    fn load(path: &str) -> Result<Program, String> {
+        let img: VveImage = VveImage::load(path, MIN_VVE_VERSION)?;
+        let cfg: CfgImage = CfgImage::from_vve(path, MIN_VVE_VERSION)?;
+        Ok(Program { header: header_lines(&img), funcs: functions(&cfg), data: data_vars(&img) })
+    }
+}
+
+fn header_lines(img: &VveImage) -> Vec<String> {
+    let hdr = &img.header;
+    let order: &str = match hdr.byte_order {
+        ByteOrder::Big => "big-endian",
+        ByteOrder::Little => "little-endian",
+    };
+    let mut res: Vec<String> = vec![
+        format!("version: {}", hdr.version),
+        format!("byte order: {}", order),
+        format!("entry point: {:#x}", hdr.entry_point),
+        format!("code size: {}", hdr.code_size),
+        format!("data size: {}", hdr.data_size),
+        format!("functions: {}", hdr.func_table.len()),
+    ];
+    for sect in &hdr.sections {
+        res.push(format!("section {} ({:#06x}): {} bytes", sect_name(sect.kind), sect.kind, sect.data.len()));
+    }
+    res
+}
+
+/// Disassembly of every function and of `.start`, the code from the entry
+/// up to the next function. Only instructions reachable from the entry or
+/// the function table are listed, as in the CFG export.
+fn functions(img: &CfgImage) -> Vec<(String, Vec<String>)> {
+    let Blocks { instrs, .. } = find_blocks(img);
+    let mut starts: Vec<(u64, String)> = vec![(img.entry, ".start".to_string())];
+    for (ind, addr) in img.funcs.iter().enumerate() {
+        let name: String = img.func_names.get(&ind).cloned().unwrap_or_else(|| format!("func #{}", ind));
+        starts.push((*addr, name));
+    }
+    starts.sort();
+
+    let label = |addr: u64| -> String {
+        match starts.iter().rev().find(|(start, _)| *start <= addr) {
+            Some((start, name)) if *start == addr => format!("<{}>", name),
+            Some((start, name)) => format!("<{}+{:#x}>", name, addr - start),
+            None => format!("{:#x}", addr),
+        }
+    };
+    let text = |d: &Decoded| -> String {
+        match (d.target(), d.text.rsplit_once(' ')) {
+            (Some(target), Some((op, _))) => format!("{} {}", op, label(target)),
+            _ => d.text.clone(),
+        }
+    };
+    let mut res: Vec<(String, Vec<String>)> = Vec::new();
+    for (i, (start, name)) in starts.iter().enumerate() {
+        let end: u64 = starts.get(i + 1).map(|(addr, _)| *addr).unwrap_or(u64::MAX);
+        let listing: Vec<String> = instrs.range(*start..end).map(|(_, d)| text(d)).collect();
+        res.push((name.clone(), listing));
+    }
+    res
+}
+
+/// Data variables: type byte, u64 length, payload. Zero bytes between them
+/// are `!align=` padding.
+fn data_vars(img: &VveImage) -> Vec<DataVar> {
+    let names: HashMap<usize, String> = match img.header.section(SECT_DATA_SYMBOLS) {
+        Some(sect) => read_data_symbols(&sect.data).into_iter().map(|(addr, name)| (addr as usize, name)).collect(),
+        None => HashMap::new(),
+    };
+    let data: &[u8] = &img.body[(img.header.data_base as usize).min(img.body.len())..];
+    let mut res: Vec<DataVar> = Vec::new();
+    let mut pos: usize = 0;
+    while pos < data.len() {
+        if data[pos] == 0 {
+            pos += 1;
+            continue;
+        }
+        let type_ind: u8 = data[pos];
+        let end: Option<usize> = data
+            .get((pos + 1)..(pos + DS_HEADER))
+            .map(|len| pos + DS_HEADER + args_to_u64(len) as usize)
+            .filter(|end| *end <= data.len());
+        let (type_name, end) = match (DsType::decode(type_ind).map(DsType::name), end) {
+            (Some(name), Some(end)) => (name, end),
+            _ => {
+                res.push(DataVar {
+                    name: format!("bytes@{:#x}", pos),
+                    desc: format!("{} unknown bytes", data.len() - pos),
+                    bytes: data[pos..].to_vec(),
+                });
+                break;
+            }
+        };
+        let payload: &[u8] = &data[(pos + DS_HEADER)..end];
+        let hex: Vec<String> = payload.iter().take(SHOWN_BYTES).map(|b| format!("{:02x}", b)).collect();
+        let desc: String = format!(
+            "{}{}, {} bytes: {}{}",
+            type_name,
+            if DsType::is_const(type_ind) { " const" } else { "" },
+            payload.len(),
+            hex.join(" "),
+            if payload.len() > SHOWN_BYTES { " .." } else { "" }
+        );
+        let name: String = names.get(&pos).cloned().unwrap_or_else(|| format!("var@{:#x}", pos));
+        res.push(DataVar { name, desc, bytes: data[pos..end].to_vec() });
+        pos = end;
+    }
+    res
+}
+
+/// Edit script turning `a` into `b`: (' ', '-' or '+', line)
+fn diff_lines<'a>(a: &'a [String], b: &'a [String]) -> Vec<(char, &'a str)> {
+    // lcs[i][j]: longest common subsequence of a[i..] and b[j..]
+    let mut lcs: Vec<Vec<u32>> = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = match a[i] == b[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+    let mut res: Vec<(char, &str)> = Vec::new();
+    let (mut i, mut j): (usize, usize) = (0, 0);
+    while (i < a.len()) || (j < b.len()) {
+        if (i < a.len()) && (j < b.len()) && (a[i] == b[j]) {
+            res.push((' ', &a[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if (j == b.len()) || ((i < a.len()) && (lcs[i + 1][j] >= lcs[i][j + 1])) {
+            res.push(('-', &a[i]));
+            i += 1;
+        } else {
+            res.push(('+', &b[j]));
+            j += 1;
+        }
+    }
+    res
+}
+
+/// Changed lines of an edit script with CONTEXT lines around them, `..`
+/// for unchanged runs left out. Empty if nothing changed.
+fn hunks(ops: &[(char, &str)]) -> String {
+    let changed: Vec<usize> = (0..ops.len()).filter(|i| ops[*i].0 != ' ').collect();
+    if changed.is_empty() {
+        return String::new();
+    }
+    let near = |i: usize| changed.iter().any(|c| c.abs_diff(i) <= CONTEXT);
+    let mut res: String = String::new();
+    let mut skipped: bool = false;
+    for (i, (tag, line)) in ops.iter().enumerate() {
+        if !near(i) {
+            skipped = true;
+            continue;
+        }
+        if skipped {
+            res += "   ..\n";
+            skipped = false;
+        }
+        res += &format!("{}  {}\n", tag, line);
+    }
+    if skipped {
+        res += "   ..\n";
+    }
+    res
+}
+
+/// Structural diff of two programs, empty if they are the same
+fn diff_programs(a: &Program, b: &Program) -> String {
+    let mut res: String = String::new();
+    let header: String = hunks(&diff_lines(&a.header, &b.header));
+    if !header.is_empty() {
+        res += &format!("header:\n{}", header);
+    }
+
+    let a_funcs: HashMap<&str, &Vec<String>> = a.funcs.iter().map(|(n, l)| (n.as_str(), l)).collect();
+    let b_funcs: HashMap<&str, &Vec<String>> = b.funcs.iter().map(|(n, l)| (n.as_str(), l)).collect();
+    let mut funcs: String = String::new();
+    for (name, listing) in &a.funcs {
+        if !b_funcs.contains_key(name.as_str()) {
+            funcs += &format!("-  {} ({} instructions)\n", name, listing.len());
+        }
+    }
+    for (name, listing) in &b.funcs {
+        if !a_funcs.contains_key(name.as_str()) {
+            funcs += &format!("+  {} ({} instructions)\n", name, listing.len());
+        }
+    }
+    if !funcs.is_empty() {
+        res += &format!("functions:\n{}", funcs);
+    }
+    for (name, listing) in &a.funcs {
+        if let Some(other) = b_funcs.get(name.as_str()) {
+            let code: String = hunks(&diff_lines(listing, other));
+            if !code.is_empty() {
+                res += &format!("code of {}:\n{}", name, code);
+            }
+        }
+    }
+
+    let b_data: HashMap<&str, &DataVar> = b.data.iter().map(|v| (v.name.as_str(), v)).collect();
+    let a_names: Vec<&str> = a.data.iter().map(|v| v.name.as_str()).collect();
+    let mut data: String = String::new();
+    for var in &a.data {
+        match b_data.get(var.name.as_str()) {
+            None => data += &format!("-  {}: {}\n", var.name, var.desc),
+            Some(other) if other.bytes != var.bytes => {
+                data += &format!("-  {}: {}\n+  {}: {}\n", var.name, var.desc, other.name, other.desc);
+            }
+            Some(_) => {}
+        }
+    }
+    for var in b.data.iter().filter(|v| !a_names.contains(&v.name.as_str())) {
+        data += &format!("+  {}: {}\n", var.name, var.desc);
+    }
+    if !data.is_empty() {
+        res += &format!("data:\n{}", data);
+    }
+    res
+}
+
+pub fn diff_vve_cli(args: &[String]) -> i32 {
+    let [a_path, b_path] = args else {
+        eprintln!("Usage: voxvm diff-vve a.vve b.vve");
+        return 2;
+    };
+    let mut progs: Vec<Program> = Vec::new();
+    for path in [a_path, b_path] {
+        match Program::load(path) {
+            Ok(prog) => progs.push(prog),
+            Err(e) => {
+                eprintln!("ERROR: Can't read {}: {}", path, e);
+                return 2;
+            }
+        }
+    }
+    let diff: String = diff_programs(&progs[0], &progs[1]);
+    if diff.is_empty() {
+        return 0;
+    }
+    print!("--- {}\n+++ {}\n{}", a_path, b_path, diff);
+    1
+}
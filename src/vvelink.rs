@@ -0,0 +1,300 @@
+// `--stdlib=path.vve`: links a library image into a program image.
+// Both images need the SECT_RELOCS section of current assemblers. The merged
+// layout is
+//     program code | stdlib code | program ro | stdlib ro | program rw | stdlib rw
+// with every part moved by a multiple of DATA_ALIGN from where it was, so
+// `!align=` of data variables still holds. Library functions get the reserved
+// indices STDLIB_FUNC_BASE.. of the function table.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+
+use crate::fileformats::{
+    data_symbols_section, ncalls_section, read_data_symbols, read_ncalls, read_relocs, read_symbols, VoxExeHeader, VveSection,
+    RELOC_CODE, RELOC_DATA_ABS, RELOC_DATA_REL, RELOC_FUNC, SECT_DATA_SYMBOLS, SECT_FUNC_META, SECT_INTERN, SECT_LINES,
+    SECT_NCALLS, SECT_RELOCS, SECT_RODATA, SECT_STACK_DEPTH, SECT_SYMBOLS,
+};
+use crate::misclib::args_to_u64;
+
+pub const STDLIB_FUNC_BASE: u64 = 0x100; // programs may have up to 256 functions
+const DATA_ALIGN: u64 = 64; // biggest `!align=` that survives linking
+
+/// Header and everything after it (code, then data)
+pub struct VveImage {
+    pub header: VoxExeHeader,
+    pub body: Vec<u8>,
+}
+
+impl VveImage {
+    pub fn load(path: &str, min_version: u16) -> Result<VveImage, String> {
+        let bytes: Vec<u8> = fs::read(path).map_err(|e| format!("Can't load {}: {}", path, e))?;
+        VveImage::from_bytes(&bytes, path, min_version)
+    }
+
+    /// Image of a whole vve file already in memory, `name` names it in messages
+    pub fn from_bytes(bytes: &[u8], name: &str, min_version: u16) -> Result<VveImage, String> {
+        let header: VoxExeHeader = VoxExeHeader::from_bytes(bytes, name, min_version)?;
+        let mut body: Vec<u8> = bytes[header.size()..].to_vec();
+        header.swap_code_words(&mut body);
+        Ok(VveImage { header, body })
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let mut file = VoxExeHeader::write(path, &self.header);
+        let mut body: Vec<u8> = self.body.clone();
+        self.header.swap_code_words(&mut body);
+        file.write_all(&body).map_err(|e| e.to_string())
+    }
+
+    fn code_len(&self) -> u64 {
+        self.header.data_base
+    }
+
+    fn ro_size(&self) -> u64 {
+        match self.header.section(SECT_RODATA) {
+            Some(sect) => args_to_u64(&sect.data[0..8]),
+            None => 0,
+        }
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.body[(self.header.data_base as usize)..]
+    }
+
+    fn relocs(&self, path: &str) -> Result<Vec<(u64, u8)>, String> {
+        match self.header.section(SECT_RELOCS) {
+            Some(sect) => Ok(read_relocs(&sect.data)),
+            None => Err(format!("{} has no relocations, rebuild it with this assembler", path)),
+        }
+    }
+}
+
+/// Library function names -> the indices a linked program calls them by
+pub fn stdlib_funcs(std: &VveImage) -> HashMap<String, u64> {
+    match std.header.section(SECT_SYMBOLS) {
+        Some(sect) => read_symbols(&sect.data)
+            .into_iter()
+            .map(|(ind, name)| (name, STDLIB_FUNC_BASE + ind as u64))
+            .collect(),
+        None => HashMap::new(),
+    }
+}
+
+/// First address from `cursor` on that is `old` modulo DATA_ALIGN,
+/// empty parts take no padding
+fn place(cursor: u64, old: u64, size: u64) -> u64 {
+    match size {
+        0 => cursor,
+        _ => cursor + (old % DATA_ALIGN + DATA_ALIGN - cursor % DATA_ALIGN) % DATA_ALIGN,
+    }
+}
+
+/// Where the parts of one image went
+struct Moved {
+    code: u64,   // code shift
+    ro: u64,     // new data relative address of the ro part
+    rw: u64,     // and of the rw part
+    ro_size: u64,
+    func_base: u64,
+}
+
+impl Moved {
+    fn data_rel(&self, rel: u64) -> u64 {
+        match rel < self.ro_size {
+            true => self.ro + rel,
+            false => self.rw + (rel - self.ro_size),
+        }
+    }
+}
+
+fn u64_at(body: &[u8], at: usize) -> Result<u64, String> {
+    match body.get(at..(at + 8)) {
+        Some(b) => Ok(args_to_u64(b)),
+        None => Err(format!("relocation at {:#x} is out of the image", at)),
+    }
+}
+
+/// Fixes up the relocations of `img` after it was copied into `body` as
+/// `moved` says, returns them moved along
+fn apply_relocs(
+    body: &mut [u8],
+    relocs: &[(u64, u8)],
+    img: &VveImage,
+    moved: &Moved,
+    new_data_base: u64,
+) -> Result<Vec<(u64, u8)>, String> {
+    let mut res: Vec<(u64, u8)> = Vec::with_capacity(relocs.len());
+    for (offset, kind) in relocs {
+        let at: usize = (moved.code + offset) as usize;
+        let val: u64 = u64_at(body, at)?;
+        let fixed: u64 = match *kind {
+            RELOC_CODE => val + moved.code,
+            RELOC_DATA_ABS => new_data_base + moved.data_rel(val.wrapping_sub(img.header.data_base)),
+            RELOC_DATA_REL => moved.data_rel(val),
+            RELOC_FUNC => val + moved.func_base,
+            k => return Err(format!("unknown relocation kind {:#x}", k)),
+        };
+        body[at..(at + 8)].copy_from_slice(&fixed.to_be_bytes());
+        res.push((moved.code + offset, *kind));
+    }
+    Ok(res)
+}
+
+/// (ind, u64) entry lists of SECT_INTERN, SECT_FUNC_META and SECT_STACK_DEPTH
+fn read_u64_list(sect: Option<&VveSection>, width: usize) -> Vec<Vec<u64>> {
+    let data: &[u8] = match sect {
+        Some(s) => &s.data,
+        None => return Vec::new(),
+    };
+    let count: usize = args_to_u64(&data[0..8]) as usize;
+    (0..count)
+        .map(|i| {
+            (0..width)
+                .map(|k| args_to_u64(&data[(8 + (i * width + k) * 8)..(16 + (i * width + k) * 8)]))
+                .collect()
+        })
+        .collect()
+}
+
+fn write_u64_list(kind: u16, entries: &[Vec<u64>]) -> VveSection {
+    let mut data: Vec<u8> = Vec::new();
+    data.extend_from_slice(&(entries.len() as u64).to_be_bytes());
+    for v in entries.iter().flatten() {
+        data.extend_from_slice(&v.to_be_bytes());
+    }
+    VveSection::new(kind, data)
+}
+
+/// Appends the code and data of `std` to `prog`
+pub fn link_stdlib(prog: &VveImage, std: &VveImage) -> Result<VveImage, String> {
+    let prog_relocs: Vec<(u64, u8)> = prog.relocs("the program")?;
+    let std_relocs: Vec<(u64, u8)> = std.relocs("the stdlib")?;
+    if prog.header.func_table.len() as u64 > STDLIB_FUNC_BASE {
+        return Err(format!(
+            "the program uses function indices from {:#x} on, they are reserved for the stdlib \
+             (is it linked already?)",
+            STDLIB_FUNC_BASE
+        ));
+    }
+
+    let (prog_ro, std_ro): (u64, u64) = (prog.ro_size(), std.ro_size());
+    let prog_rw: u64 = prog.data().len() as u64 - prog_ro;
+    let std_rw: u64 = std.data().len() as u64 - std_ro;
+    let std_code_at: u64 = prog.code_len();
+    let data_base: u64 = place(std_code_at + std.code_len(), prog.header.data_base, prog_ro + prog_rw);
+    let std_ro_at: u64 = place(data_base + prog_ro, std.header.data_base, std_ro) - data_base;
+    let prog_rw_at: u64 =
+        place(data_base + std_ro_at + std_ro, prog.header.data_base + prog_ro, prog_rw) - data_base;
+    let std_rw_at: u64 =
+        place(data_base + prog_rw_at + prog_rw, std.header.data_base + std_ro, std_rw) - data_base;
+    let data_size: u64 = std_rw_at + std_rw;
+
+    let prog_moved = Moved { code: 0, ro: 0, rw: prog_rw_at, ro_size: prog_ro, func_base: 0 };
+    let std_moved = Moved {
+        code: std_code_at,
+        ro: std_ro_at,
+        rw: std_rw_at,
+        ro_size: std_ro,
+        func_base: STDLIB_FUNC_BASE,
+    };
+
+    let mut body: Vec<u8> = vec![0; (data_base + data_size) as usize];
+    let copy = |body: &mut Vec<u8>, at: u64, src: &[u8]| {
+        body[(at as usize)..(at as usize + src.len())].copy_from_slice(src);
+    };
+    copy(&mut body, 0, &prog.body[..(prog.code_len() as usize)]);
+    copy(&mut body, std_code_at, &std.body[..(std.code_len() as usize)]);
+    copy(&mut body, data_base, &prog.data()[..(prog_ro as usize)]);
+    copy(&mut body, data_base + std_ro_at, &std.data()[..(std_ro as usize)]);
+    copy(&mut body, data_base + prog_rw_at, &prog.data()[(prog_ro as usize)..]);
+    copy(&mut body, data_base + std_rw_at, &std.data()[(std_ro as usize)..]);
+
+    let mut relocs: Vec<(u64, u8)> = apply_relocs(&mut body, &prog_relocs, prog, &prog_moved, data_base)?;
+    relocs.extend(apply_relocs(&mut body, &std_relocs, std, &std_moved, data_base)?);
+
+    let mut func_table: Vec<u64> = prog.header.func_table.clone();
+    func_table.resize(STDLIB_FUNC_BASE as usize, 0);
+    func_table.extend(std.header.func_table.iter().map(|addr| addr + std_code_at));
+
+    let mut header: VoxExeHeader = VoxExeHeader::new(
+        prog.header.version,
+        prog.header.entry_point,
+        data_base,
+        data_base,
+        data_size,
+        func_table,
+    );
+
+    let mut interned: Vec<Vec<u64>> = read_u64_list(prog.header.section(SECT_INTERN), 1);
+    for v in read_u64_list(std.header.section(SECT_INTERN), 1) {
+        interned.push(vec![std_moved.data_rel(v[0])]);
+    }
+    header.sections.push(write_u64_list(SECT_INTERN, &interned));
+
+    let mut meta: Vec<Vec<u64>> = read_u64_list(prog.header.section(SECT_FUNC_META), 2);
+    for v in read_u64_list(std.header.section(SECT_FUNC_META), 2) {
+        meta.push(vec![STDLIB_FUNC_BASE + v[0], v[1]]);
+    }
+    header.sections.push(write_u64_list(SECT_FUNC_META, &meta));
+
+    let mut depths: Vec<Vec<u64>> = read_u64_list(prog.header.section(SECT_STACK_DEPTH), 2);
+    for v in read_u64_list(std.header.section(SECT_STACK_DEPTH), 2) {
+        depths.push(vec![STDLIB_FUNC_BASE + v[0], v[1]]);
+    }
+    header.sections.push(write_u64_list(SECT_STACK_DEPTH, &depths));
+
+    // program names win, the library's clashing ones stay callable by index
+    let mut symbols: Vec<(usize, String)> = match prog.header.section(SECT_SYMBOLS) {
+        Some(sect) => read_symbols(&sect.data),
+        None => Vec::new(),
+    };
+    let mut std_symbols: Vec<(String, u64)> = stdlib_funcs(std).into_iter().collect();
+    std_symbols.sort_by_key(|(_, ind)| *ind);
+    for (name, ind) in std_symbols {
+        if !symbols.iter().any(|(_, n)| *n == name) {
+            symbols.push((ind as usize, name));
+        }
+    }
+    let mut data: Vec<u8> = Vec::new();
+    data.extend_from_slice(&(symbols.len() as u64).to_be_bytes());
+    for (ind, name) in symbols {
+        data.extend_from_slice(&(ind as u64).to_be_bytes());
+        data.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        data.extend_from_slice(name.as_bytes());
+    }
+    header.sections.push(VveSection::new(SECT_SYMBOLS, data));
+
+    // the library's lines belong to another source file
+    if let Some(sect) = prog.header.section(SECT_LINES) {
+        header.sections.push(sect.clone());
+    }
+    let ro_size: u64 = std_ro_at + std_ro;
+    header.sections.push(VveSection::new(SECT_RODATA, ro_size.to_be_bytes().to_vec()));
+
+    let mut data: Vec<u8> = Vec::new();
+    data.extend_from_slice(&(relocs.len() as u64).to_be_bytes());
+    for (offset, kind) in relocs {
+        data.extend_from_slice(&offset.to_be_bytes());
+        data.push(kind);
+    }
+    header.sections.push(VveSection::new(SECT_RELOCS, data));
+
+    let ncalls: Vec<u16> = [prog, std]
+        .iter()
+        .filter_map(|img| img.header.section(SECT_NCALLS))
+        .flat_map(|sect| read_ncalls(&sect.data))
+        .collect();
+    header.sections.push(ncalls_section(&ncalls));
+
+    // only the program's variables can be --define'd
+    if let Some(sect) = prog.header.section(SECT_DATA_SYMBOLS) {
+        let names: Vec<(u64, String)> = read_data_symbols(&sect.data)
+            .into_iter()
+            .map(|(addr, name)| (prog_moved.data_rel(addr), name))
+            .collect();
+        header.sections.push(data_symbols_section(&names));
+    }
+
+    Ok(VveImage { header, body })
+}
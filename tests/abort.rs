@@ -0,0 +1,87 @@
+// `abort Rstr Rlen` prints the message, ip and a backtrace on stderr and
+// exits with the abort exit code, optionally after a coredump.
+
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use common::{VOXVM, assemble};
+
+const SRC: &str = "section text
+.start
+    uload r1 42
+    uload r2 1
+    ncall @print r0
+    call @outer
+    halt
+
+func outer
+    call @inner
+    ret
+
+func inner
+    dslea r4 msg 9
+    abort r4 r0
+    ret
+section data
+    msg str \"bad state\"
+";
+
+const HEAP_SRC: &str = "section text
+.start
+    alloc r20 16
+    dslea r4 msg 9
+    uload r3 8
+    storedat r20 r4 r3
+    abort r20 r3
+    halt
+section data
+    msg str \"oops\"
+";
+
+fn run(work: &Path, vve: &Path, extra: &[&str]) -> Output {
+    Command::new(VOXVM)
+        .current_dir(work)
+        .arg(format!("--vve={}", vve.display()))
+        .arg("--quiet")
+        .args(extra)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn abort_reports_and_exits() {
+    let work: PathBuf = common::work_dir("abort");
+    let vve: PathBuf = assemble(&work, "a", SRC, &[]);
+    let heap_vve: PathBuf = assemble(&work, "h", HEAP_SRC, &[]);
+
+    let plain: Output = run(&work, &vve, &["--json-status=status.json"]);
+    let status: String = fs::read_to_string(work.join("status.json")).unwrap_or_default();
+    let dumped: Output = run(&work, &heap_vve, &["--abort-exit-code=7", "--abort-coredump"]);
+    let dump_written: bool = work.join("voxvm_err.dump").exists();
+    let bad_code: Output = run(&work, &vve, &["--abort-exit-code=300"]);
+    let _ = fs::remove_dir_all(&work);
+
+    let stderr: String = String::from_utf8_lossy(&plain.stderr).to_string();
+    assert_eq!(plain.status.code(), Some(134), "{}", stderr);
+    assert_eq!(String::from_utf8_lossy(&plain.stdout), "42\n");
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert_eq!(lines[0], "ABORT: bad state", "{}", stderr);
+    assert_eq!(lines[2], "Backtrace:", "{}", stderr);
+    assert!(lines[3].starts_with("  #0 ip ") && lines[3].ends_with("(line 15) in inner"), "{}", stderr);
+    assert!(lines[4].ends_with("(line 10) in outer"), "{}", stderr);
+    assert!(lines[5].starts_with("  #2 ip 0x") && lines[5].ends_with("(line 6)"), "{}", stderr);
+    let json: serde_json::Value = serde_json::from_str(&status).unwrap();
+    assert_eq!(json["status"], "aborted");
+
+    let stderr: String = String::from_utf8_lossy(&dumped.stderr).to_string();
+    assert_eq!(dumped.status.code(), Some(7), "{}", stderr);
+    assert!(stderr.starts_with("ABORT: oops\n"), "{}", stderr);
+    assert!(dump_written, "{}", stderr);
+
+    assert_eq!(bad_code.status.code(), Some(1));
+}
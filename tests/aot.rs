@@ -0,0 +1,48 @@
+// `voxvm aot` turns every basic block into a function and embeds the image.
+// Building the output takes a whole `--features aot` build, so only the
+// translation is checked here.
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+const SRC: &str = "section text
+.start
+    uload r1 3
+    uload r2 1
+label loop
+    usub r1 r2
+    jnz @loop
+    halt
+";
+
+#[test]
+fn aot_translates_basic_blocks() {
+    let work: PathBuf = env::temp_dir().join(format!("voxvm-aot-{}", std::process::id()));
+    fs::create_dir_all(&work).unwrap();
+    let (vvs, vve, rs) = (work.join("a.vvs"), work.join("a.vve"), work.join("a.rs"));
+    fs::write(&vvs, SRC).unwrap();
+    let asm = Command::new(VOXVM)
+        .arg(format!("--vas={}", vvs.display()))
+        .arg(format!("--vas-out={}", vve.display()))
+        .output()
+        .unwrap();
+    assert!(asm.status.success(), "{}", String::from_utf8_lossy(&asm.stderr));
+
+    let aot = Command::new(VOXVM).arg("aot").arg(&vve).arg("-o").arg(&rs).output().unwrap();
+    let src: String = fs::read_to_string(&rs).unwrap_or_default();
+    let image_len: usize = fs::read(&vve).unwrap().len();
+    let usage = Command::new(VOXVM).args(["aot", "a.vve"]).output().unwrap();
+    let _ = fs::remove_dir_all(&work);
+
+    assert!(aot.status.success(), "{}", String::from_utf8_lossy(&aot.stderr));
+    assert!(src.contains(&format!("pub static IMAGE: [u8; {}]", image_len)), "{}", src);
+    assert!(src.contains("impl Dispatch for Blocks"), "{}", src);
+    // entry, the loop head and the fallthrough to halt
+    assert_eq!(src.matches("fn b_").count(), 3, "{}", src);
+    assert!(src.contains("_ => Interpreter.dispatch(vm),"), "{}", src);
+    assert!(src.contains("vm.exec_instr(0xff, BLOCK_END);"), "{}", src);
+
+    assert!(!usage.status.success());
+    assert!(String::from_utf8_lossy(&usage.stderr).contains("Usage: voxvm aot"));
+}
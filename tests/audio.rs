@@ -0,0 +1,66 @@
+// `--audio-wav` writes audio outputs (ncalls 0x70..) to a WAV file.
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+// three mono s16 frames: 0, 32767, -32768 (store takes the first 6 bytes)
+const SRC: &str = "section text
+.start
+    uload r1 8000
+    uload r2 1
+    uload r3 1
+    ncall @audio_open r0
+    movr r10 r0
+    alloc r5 6
+    uload r6 0x7FFF80000000
+    uload r7 6
+    store r5 r6 r7
+    movr r1 r10
+    movr r2 r5
+    uload r3 3
+    ncall @audio_write r0
+    movr r11 r0
+    movr r1 r10
+    ncall @audio_close r0
+    halt
+";
+
+#[test]
+fn audio_output_becomes_wav() {
+    let work: PathBuf = env::temp_dir().join(format!("voxvm-audio-{}", std::process::id()));
+    fs::create_dir_all(&work).unwrap();
+    let (vvs, vve, wav, state) = (work.join("a.vvs"), work.join("a.vve"), work.join("out.wav"), work.join("a.state"));
+    fs::write(&vvs, SRC).unwrap();
+    let asm = Command::new(VOXVM)
+        .arg(format!("--vas={}", vvs.display()))
+        .arg(format!("--vas-out={}", vve.display()))
+        .output()
+        .unwrap();
+    assert!(asm.status.success(), "{}", String::from_utf8_lossy(&asm.stderr));
+
+    let run = Command::new(VOXVM)
+        .arg(format!("--vve={}", vve.display()))
+        .arg(format!("--audio-wav={}", wav.display()))
+        .arg(format!("--dump-state={}", state.display()))
+        .output()
+        .unwrap();
+    let out: Option<Vec<u8>> = fs::read(&wav).ok();
+    let dump: String = fs::read_to_string(&state).unwrap_or_default();
+    let plain = Command::new(VOXVM).arg(format!("--vve={}", vve.display())).output().unwrap();
+    let _ = fs::remove_dir_all(&work);
+
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    assert!(dump.contains("r11: uint(3)"), "{}", dump);
+    let out: Vec<u8> = out.expect("no WAV written");
+    assert_eq!(&out[0..4], b"RIFF");
+    assert_eq!(&out[8..16], b"WAVEfmt ");
+    assert_eq!(u16::from_le_bytes([out[22], out[23]]), 1); // channels
+    assert_eq!(u32::from_le_bytes(out[24..28].try_into().unwrap()), 8000);
+    assert_eq!(&out[36..40], b"data");
+    assert_eq!(&out[40..], [6, 0, 0, 0, 0, 0, 0xFF, 0x7F, 0x00, 0x80]);
+
+    if !cfg!(feature = "audio") {
+        assert!(String::from_utf8_lossy(&plain.stderr).contains("built without the `audio` feature"));
+    }
+}
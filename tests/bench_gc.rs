@@ -0,0 +1,33 @@
+// `voxvm bench-gc` collects exactly the graphs that fell out of the live window.
+
+use std::process::Command;
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+fn bench(args: &[&str]) -> (bool, String, String) {
+    let out = Command::new(VOXVM).arg("bench-gc").args(args).output().unwrap();
+    (
+        out.status.success(),
+        String::from_utf8_lossy(&out.stdout).to_string(),
+        String::from_utf8_lossy(&out.stderr).to_string(),
+    )
+}
+
+#[test]
+fn every_shape_collects_unrooted_graphs() {
+    let (ok, out, err) = bench(&["--objects=50", "--rounds=3", "--live=1", "--shape=all"]);
+    assert!(ok, "{}", err);
+    for shape in ["list", "tree", "cycle", "mixed"] {
+        let row: &str = out.lines().find(|l| l.starts_with(shape)).unwrap_or_else(|| panic!("{}", out));
+        // rounds 2 and 3 each drop the previous graph
+        let cols: Vec<&str> = row.split_whitespace().collect();
+        assert_eq!(cols[7], "100", "{}", row);
+    }
+}
+
+#[test]
+fn small_heap_fails() {
+    let (ok, _, err) = bench(&["--heap=1KB", "--objects=100"]);
+    assert!(!ok);
+    assert!(err.contains("heap of 1.0KB is full"), "{}", err);
+}
@@ -0,0 +1,42 @@
+// `--emit-cfg` builds the same graph from a .vvs and from the assembled .vve.
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+#[test]
+fn emit_cfg_from_vvs_and_vve() {
+    let src: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/flow.vvs");
+    let work: PathBuf = env::temp_dir().join(format!("voxvm-cfg-{}", std::process::id()));
+    fs::create_dir_all(&work).unwrap();
+    let vve: PathBuf = work.join("flow.vve");
+    let from_vvs: PathBuf = work.join("vvs.dot");
+    let from_vve: PathBuf = work.join("vve.dot");
+
+    let asm = Command::new(VOXVM)
+        .arg(format!("--vas={}", src.display()))
+        .arg(format!("--vas-out={}", vve.display()))
+        .arg(format!("--emit-cfg={}", from_vvs.display()))
+        .output()
+        .unwrap();
+    assert!(asm.status.success(), "{}", String::from_utf8_lossy(&asm.stderr));
+    let run = Command::new(VOXVM)
+        .arg(format!("--vve={}", vve.display()))
+        .arg(format!("--emit-cfg={}", from_vve.display()))
+        .output()
+        .unwrap();
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+
+    let dot: String = fs::read_to_string(&from_vvs).unwrap();
+    assert_eq!(dot, fs::read_to_string(&from_vve).unwrap());
+    for edge in [
+        "bc -> b60 [label=\"default\"]",
+        "bc -> b36 [label=\"case 0\"]",
+        "bc -> b4b [label=\"case 1\"]",
+        "b36 -> bc [label=\"taken\"]",
+        "b0 -> bc [label=\"fallthrough\"]",
+    ] {
+        assert!(dot.contains(edge), "missing `{}` in:\n{}", edge, dot);
+    }
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,84 @@
+// Helpers shared by the integration tests: scratch dirs, assembling and
+// running programs with the voxvm binary, registers out of state dumps.
+// Every test file uses its own subset of them.
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+pub const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+/// Scratch dir of a test, `name` keeps tests running in parallel apart
+pub fn work_dir(name: &str) -> PathBuf {
+    let work: PathBuf = env::temp_dir().join(format!("voxvm-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&work).unwrap();
+    work
+}
+
+/// Assembles `vvs` into `vve` with extra assembler args, whether it worked or not
+pub fn try_assemble_file(vvs: &Path, vve: &Path, extra: &[&str]) -> Output {
+    Command::new(VOXVM)
+        .arg(format!("--vas={}", vvs.display()))
+        .arg(format!("--vas-out={}", vve.display()))
+        .args(extra)
+        .output()
+        .unwrap()
+}
+
+/// Assembles `vvs` into `vve`, panics with the assembler errors
+pub fn assemble_file(vvs: &Path, vve: &Path, extra: &[&str]) {
+    let asm: Output = try_assemble_file(vvs, vve, extra);
+    assert!(
+        asm.status.success(),
+        "{}: assembling failed:\n{}",
+        vvs.display(),
+        String::from_utf8_lossy(&asm.stderr)
+    );
+}
+
+/// Writes `src` to `work`/`name`.vvs and assembles it into `name`.vve,
+/// returns the vve and the assembler run
+pub fn try_assemble(work: &Path, name: &str, src: &str, extra: &[&str]) -> (PathBuf, Output) {
+    let (vvs, vve) = (work.join(format!("{}.vvs", name)), work.join(format!("{}.vve", name)));
+    fs::write(&vvs, src).unwrap();
+    let asm: Output = try_assemble_file(&vvs, &vve, extra);
+    (vve, asm)
+}
+
+/// `try_assemble` that panics with the assembler errors, returns the vve
+pub fn assemble(work: &Path, name: &str, src: &str, extra: &[&str]) -> PathBuf {
+    let (vve, asm) = try_assemble(work, name, src, extra);
+    assert!(asm.status.success(), "{}", String::from_utf8_lossy(&asm.stderr));
+    vve
+}
+
+/// Runs `vve` with extra args, returns the run and its state dump (empty
+/// if it wrote none). The dump goes next to the vve
+pub fn run(vve: &Path, extra: &[&str]) -> (Output, String) {
+    let state: PathBuf = vve.with_extension("state");
+    let _ = fs::remove_file(&state);
+    let out: Output = Command::new(VOXVM)
+        .arg(format!("--vve={}", vve.display()))
+        .arg(format!("--dump-state={}", state.display()))
+        .args(extra)
+        .output()
+        .unwrap();
+    (out, fs::read_to_string(&state).unwrap_or_default())
+}
+
+/// State dump of a run of `vve` that has to succeed
+pub fn run_state(vve: &Path, extra: &[&str]) -> String {
+    let (out, dump) = run(vve, extra);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    dump
+}
+
+/// Value of `reg` in a state dump, as long as it is a uint
+pub fn reg(dump: &str, reg: &str) -> u64 {
+    let prefix: String = format!("{}: uint(", reg);
+    let line: &str = dump.lines().find(|l| l.starts_with(&prefix)).unwrap_or_else(|| panic!("no uint {} in\n{}", reg, dump));
+    line[prefix.len()..line.len() - 1].parse().unwrap()
+}
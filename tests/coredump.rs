@@ -0,0 +1,84 @@
+// `--coredump_exit` keeps typed stacks, call frames, heap blocks and refs,
+// `--inspect-dump` reads them back.
+
+mod common;
+
+use std::{fs, path::PathBuf, process::Command};
+
+use common::VOXVM;
+
+const SRC: &str = "section text
+.start
+    iload r1 -7
+    push r1
+    fload r9 2.5
+    call @inner
+    halt
+
+func inner
+    alloc r2 16
+    alloc r3 8
+    uload r4 8
+    store r2 r3 r4
+    free r4
+    halt
+";
+
+fn work_dir(name: &str) -> PathBuf {
+    common::work_dir(&format!("coredump-{}", name))
+}
+
+fn inspect(work: &PathBuf, dump: &str) -> (bool, String, String) {
+    let out = Command::new(VOXVM)
+        .arg(format!("--inspect-dump={}", dump))
+        .current_dir(work)
+        .output()
+        .unwrap();
+    (
+        out.status.success(),
+        String::from_utf8_lossy(&out.stdout).to_string(),
+        String::from_utf8_lossy(&out.stderr).to_string(),
+    )
+}
+
+#[test]
+fn dump_roundtrips_machine_state() {
+    let work: PathBuf = work_dir("state");
+    fs::write(work.join("d.vvs"), SRC).unwrap();
+    let asm = Command::new(VOXVM).args(["--vas=d.vvs", "--vas-out=d.vve"]).current_dir(&work).output().unwrap();
+    assert!(asm.status.success(), "{}", String::from_utf8_lossy(&asm.stderr));
+    let run = Command::new(VOXVM).args(["--vve=d.vve", "--coredump_exit"]).current_dir(&work).output().unwrap();
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    assert!(fs::read(work.join("voxvm.dump")).unwrap().starts_with(b"VXDUMP"));
+
+    let (ok, out, err) = inspect(&work, "voxvm.dump");
+    let _ = fs::remove_dir_all(&work);
+    assert!(ok, "{}", err);
+    for line in [
+        "r1: int(-7)\n",
+        "r3: address(24)\n",
+        "r9: float(2.5)\n",
+        "exceptions: [HeapFreeFault]\n",
+        "stack slots: 1\n  0: int(-7)\n",
+        "call frames: 1\n  0: ret 0x1b, func 0\n",
+        "  0x0+16 #1: 00000000000000180000000000000000\n",
+        "gc objects: 2\n  0x0 #0\n  0x18 #1\n",
+        "heap refs: 1\n  0x0 -> 0x18\n",
+    ] {
+        assert!(out.contains(line), "missing {:?} in\n{}", line, out);
+    }
+}
+
+#[test]
+fn truncated_dump_is_rejected() {
+    let work: PathBuf = work_dir("trunc");
+    fs::write(work.join("bad.dump"), b"VXDUMP\x00\x02\x00\x00").unwrap();
+    fs::write(work.join("raw.dump"), [0u8; 32]).unwrap();
+    let (trunc_ok, _, trunc_err) = inspect(&work, "bad.dump");
+    let (raw_ok, _, raw_err) = inspect(&work, "raw.dump");
+    let _ = fs::remove_dir_all(&work);
+    assert!(!trunc_ok);
+    assert!(trunc_err.contains("dump is truncated at byte 0x8"), "{}", trunc_err);
+    assert!(!raw_ok);
+    assert!(raw_err.contains("not a voxvm core dump"), "{}", raw_err);
+}
@@ -0,0 +1,68 @@
+// `--coverage` records executed instructions, `--cov-report` maps them back
+// onto the source through the line table: hit counts for lines that ran,
+// ##### for code that didn't, - for lines without code.
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+const SRC: &str = "section text
+.start
+    uload r1 0
+    uload r2 3
+label loop
+    uinc r1
+    ucmp r1 r2
+    jl @loop
+    jg @never
+    halt
+label never
+    uload r3 1
+    halt
+";
+
+const REPORT: &str = "        -:     1: section text
+        -:     2: .start
+        1:     3:     uload r1 0
+        1:     4:     uload r2 3
+        -:     5: label loop
+        3:     6:     uinc r1
+        3:     7:     ucmp r1 r2
+        3:     8:     jl @loop
+        1:     9:     jg @never
+        1:    10:     halt
+        -:    11: label never
+    #####:    12:     uload r3 1
+    #####:    13:     halt
+Lines executed: 7/9
+";
+
+#[test]
+fn coverage_report_annotates_source() {
+    let work: PathBuf = env::temp_dir().join(format!("voxvm-coverage-{}", std::process::id()));
+    fs::create_dir_all(&work).unwrap();
+    let (vvs, vve, cov) = (work.join("c.vvs"), work.join("c.vve"), work.join("c.cov"));
+    fs::write(&vvs, SRC).unwrap();
+    let asm = Command::new(VOXVM)
+        .arg(format!("--vas={}", vvs.display()))
+        .arg(format!("--vas-out={}", vve.display()))
+        .output()
+        .unwrap();
+    assert!(asm.status.success(), "{}", String::from_utf8_lossy(&asm.stderr));
+    let run = Command::new(VOXVM)
+        .arg(format!("--vve={}", vve.display()))
+        .arg(format!("--coverage={}", cov.display()))
+        .output()
+        .unwrap();
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    let report = Command::new(VOXVM)
+        .arg(format!("--cov-report={}", cov.display()))
+        .arg(format!("--vve={}", vve.display()))
+        .arg(format!("--src={}", vvs.display()))
+        .output()
+        .unwrap();
+    let _ = fs::remove_dir_all(&work);
+
+    assert!(report.status.success(), "{}", String::from_utf8_lossy(&report.stderr));
+    assert_eq!(String::from_utf8_lossy(&report.stdout), REPORT);
+}
@@ -0,0 +1,93 @@
+// `voxvm diff-vve` compares two builds by functions and data variables,
+// code that only moved doesn't count as a change.
+
+mod common;
+
+use std::{fs, path::PathBuf, process::Command};
+
+use common::{VOXVM, assemble, work_dir};
+
+const OLD: &str = "section text
+.start
+    uload r1 3
+    call @twice
+    call @old
+    halt
+
+func twice
+    uadd r1 r1
+    ucmp r1 r2
+    jz @done
+    uinc r1
+label done
+    ret
+
+func old
+    uload r3 1
+    ret
+
+section data
+    count uint 7
+    msg const str \"hi\"
+    blob db 0xCA 0xFE
+";
+
+const NEW: &str = "section text
+.start
+    uload r1 3
+    call @helper
+    call @twice
+    excclear @zero_division
+    halt
+
+func helper
+    uload r9 9
+    ret
+
+func twice
+    uadd r1 r1
+    ucmp r1 r2
+    jz @done
+    udec r1
+label done
+    ret
+
+section data
+    count uint 8
+    msg const str \"hi\"
+    extra uint 1
+";
+
+#[test]
+fn diff_vve_reports_structural_changes() {
+    let work: PathBuf = work_dir("diffvve");
+    let old: PathBuf = assemble(&work, "old", OLD, &[]);
+    let new: PathBuf = assemble(&work, "new", NEW, &[]);
+
+    let same = Command::new(VOXVM).arg("diff-vve").arg(&old).arg(&old).output().unwrap();
+    let diff = Command::new(VOXVM).arg("diff-vve").arg(&old).arg(&new).output().unwrap();
+    let _ = fs::remove_dir_all(&work);
+    assert_eq!(same.status.code(), Some(0));
+    assert!(same.stdout.is_empty());
+    assert_eq!(diff.status.code(), Some(1), "{}", String::from_utf8_lossy(&diff.stderr));
+
+    let out: String = String::from_utf8_lossy(&diff.stdout).to_string();
+    for expected in [
+        "functions:\n-  old (2 instructions)\n+  helper (2 instructions)\n",
+        "+  call <helper>\n   call <twice>\n-  call <old>\n+  excclear 0x1\n   halt\n",
+        "   jz <twice+0x11>\n-  uinc r1\n+  udec r1\n",
+        "-  count: uint, 8 bytes: 00 00 00 00 00 00 00 07\n+  count: uint, 8 bytes: 00 00 00 00 00 00 00 08\n",
+        "-  blob: bytes, 2 bytes: ca fe\n",
+        "+  extra: uint, 8 bytes: 00 00 00 00 00 00 00 01\n",
+    ] {
+        assert!(out.contains(expected), "missing {:?} in\n{}", expected, out);
+    }
+    // twice moved but only its changed instruction shows up, msg didn't change
+    assert!(!out.contains("msg"), "{}", out);
+}
+
+#[test]
+fn diff_vve_fails_on_missing_file() {
+    let out = Command::new(VOXVM).arg("diff-vve").arg("no/such.vve").arg("no/other.vve").output().unwrap();
+    assert_eq!(out.status.code(), Some(2));
+}
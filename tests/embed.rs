@@ -0,0 +1,237 @@
+// voxvm as a library: a host assembles a program, exposes Rust closures as
+// ncalls, captures what the guest prints, pauses it, reloads its data and
+// loads native configs into it, all without the voxvm binary.
+
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use voxvm::{
+    assembly::VoxAssembly,
+    native::NcallSource,
+    output::{SharedBuffer, VmOutput},
+    registers::Register,
+    vm::{RegTypes, VM},
+};
+
+const MIN_VVE_VERSION: u16 = 3;
+
+// ncall 0x200 is left to the host
+const SRC: &str = "section text
+.start
+    uload r1 21
+    ncall 0x200 r0
+    uload r2 1
+    ncall @print r0
+    halt
+";
+
+fn work_dir(name: &str) -> PathBuf {
+    common::work_dir(&format!("embed-{}", name))
+}
+
+/// Assembles `src` into `work`, returns the .vve path
+fn assemble(work: &Path, name: &str, src: &str) -> String {
+    let (vvs, vve) = (work.join(format!("{}.vvs", name)), work.join(format!("{}.vve", name)));
+    fs::write(&vvs, src).unwrap();
+    let vve: String = vve.display().to_string();
+    VoxAssembly::new(vvs.display().to_string(), vve.clone()).assemble();
+    vve
+}
+
+fn new_vm(vve: &str, stdout: &SharedBuffer) -> VM {
+    let mut vm = VM::new(1 << 20, 64 << 10, 64 << 10, 64);
+    vm.load_vve(vve, MIN_VVE_VERSION);
+    vm.output.stdout = Box::new(stdout.clone());
+    vm
+}
+
+/// Assembles `src` and loads it into a fresh VM printing into `stdout`
+fn load(name: &str, src: &str, stdout: &SharedBuffer) -> VM {
+    let work: PathBuf = work_dir(name);
+    let vm = new_vm(&assemble(&work, "e", src), stdout);
+    let _ = fs::remove_dir_all(&work);
+    vm
+}
+
+fn double_r1(vm: &mut VM) {
+    vm.registers[1] = Register::uint(vm.registers[1].as_u64() * 2);
+    vm.reg_types[1] = RegTypes::uint64;
+}
+
+#[test]
+fn host_ncall_runs_in_the_guest() {
+    let stdout = SharedBuffer::new();
+    let mut vm = load("host", SRC, &stdout);
+    assert_eq!(vm.missing_ncalls(), vec![0x200]);
+    assert_eq!(vm.register_ncall(0x200, Box::new(double_r1)), None);
+    assert!(vm.missing_ncalls().is_empty());
+    vm.run();
+    assert_eq!(stdout.contents(), "42\n");
+}
+
+#[test]
+fn host_ncall_shadows_std_until_unregistered() {
+    let stdout = SharedBuffer::new();
+    let mut vm = load("shadow", SRC, &stdout);
+    vm.register_ncall(0x200, Box::new(double_r1));
+    // @print is std ncall 1, the host version prints nothing
+    assert_eq!(vm.register_ncall(0x1, Box::new(|_: &mut VM| {})), Some(NcallSource::Std));
+    assert!(vm.unregister_ncall(0x1));
+    assert!(!vm.unregister_ncall(0x1));
+    vm.run();
+    assert_eq!(stdout.contents(), "42\n");
+}
+
+const FUNCS: &str = "section text
+.start
+    halt
+
+func muladd
+    movr r0 r1
+    umul r0 r2
+    uadd r0 r3
+    ret
+";
+
+#[test]
+fn run_function_returns_r0() {
+    let mut vm = load("func", FUNCS, &SharedBuffer::new());
+    let args = [Register::uint(6), Register::uint(7), Register::uint(0)];
+    assert_eq!(vm.run_function("muladd", &args), Ok(Register::uint(42)));
+    // the VM can be called again
+    let args = [Register::uint(2), Register::uint(3), Register::uint(4)];
+    assert_eq!(vm.run_function("muladd", &args), Ok(Register::uint(10)));
+    assert!(vm.run_function("missing", &[]).unwrap_err().contains("No function named 'missing'"));
+}
+
+#[test]
+fn output_sinks_capture_both_streams() {
+    let (stdout, stderr) = (SharedBuffer::new(), SharedBuffer::new());
+    let src = "section text
+.start
+    uload r1 1
+    uload r2 1
+    ncall @print r0
+    uload r1 2
+    uload r2 2
+    ncall @print r0
+    halt
+";
+    let mut vm = load("output", src, &SharedBuffer::new());
+    vm.output = VmOutput { stdout: Box::new(stdout.clone()), stderr: Box::new(stderr.clone()) };
+    vm.run();
+    assert_eq!((stdout.contents().as_str(), stderr.contents().as_str()), ("1\n", "2\n"));
+}
+
+#[test]
+fn paused_run_yields_and_continues() {
+    let stdout = SharedBuffer::new();
+    let mut vm = load("pause", SRC, &stdout);
+    vm.register_ncall(0x200, Box::new(double_r1));
+    vm.yield_on_pause = true;
+    vm.quiet = true;
+    let pause = vm.pause_handle();
+    pause.pause();
+    vm.run();
+    assert!(vm.is_running());
+    assert_eq!(stdout.contents(), "");
+    pause.resume();
+    vm.run();
+    assert!(!vm.is_running());
+    assert_eq!(stdout.contents(), "42\n");
+}
+
+const DATA: &str = "section text
+.start
+    dsload r0 num 0
+    dsload r1 limit 0
+    halt
+section data
+    num uint NUM
+    limit const uint LIMIT
+";
+
+#[test]
+fn reload_data_updates_mutable_variables() {
+    let work: PathBuf = work_dir("reload");
+    let data = |num: u64, limit: u64| DATA.replace("NUM", &num.to_string()).replace("LIMIT", &limit.to_string());
+    let old: String = assemble(&work, "old", &data(5, 100));
+    let new: String = assemble(&work, "new", &data(9, 200));
+    let mut vm = new_vm(&old, &SharedBuffer::new());
+    let updated = vm.reload_data(&new);
+    let missing = vm.reload_data(&work.join("missing.vve").display().to_string());
+    let _ = fs::remove_dir_all(&work);
+
+    assert_eq!(updated, Ok(1));
+    assert!(missing.is_err());
+    vm.run();
+    assert_eq!((vm.registers[0], vm.registers[1]), (Register::uint(9), Register::uint(100)));
+}
+
+const NATIVE: &str = "section text
+.start
+    uload r1 2
+    uload r2 3
+    ncall 0x100 r0
+    halt
+";
+
+#[test]
+fn native_config_loads_into_the_vm() {
+    let work: PathBuf = work_dir("native");
+    let lib: PathBuf = work.join("libtestfr.so");
+    let c_src: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("nconfigs/libs/libtestfr.c");
+    let built = Command::new("cc").args(["-shared", "-fPIC", "-o"]).arg(&lib).arg(&c_src).output();
+    if !built.is_ok_and(|o| o.status.success()) {
+        eprintln!("no C compiler to build the plugin, skipping");
+        let _ = fs::remove_dir_all(&work);
+        return;
+    }
+    let full: String = fs::read_to_string(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("nconfigs/test.toml"))
+        .unwrap()
+        .replace("nconfigs/libs/libtestfr.so", &lib.display().to_string());
+    let cfg: PathBuf = work.join("add.toml");
+    fs::write(&cfg, full.split("[functions.unsigned_pow2]").next().unwrap()).unwrap();
+    let cfg: String = cfg.display().to_string();
+
+    let mut vm = new_vm(&assemble(&work, "native", NATIVE), &SharedBuffer::new());
+    assert_eq!(vm.missing_ncalls(), vec![0x100]);
+    let loaded = vm.load_native_config(&cfg).map_err(|e| format!("{:?}", e));
+    // 0x100 is taken now
+    let again: bool = vm.load_native_config(&cfg).is_err();
+    vm.run();
+    let _ = fs::remove_dir_all(&work);
+
+    assert_eq!(loaded, Ok(vec![0x100]));
+    assert!(again);
+    assert_eq!(vm.registers[0], Register::uint(5));
+}
+
+#[test]
+fn load_vvr_reports_unreadable_and_oversized_images() {
+    let work: PathBuf = work_dir("vvr");
+    let raw: PathBuf = work.join("raw.vvr");
+    fs::write(&raw, [0u8; 64]).unwrap();
+    let raw: String = raw.display().to_string();
+    let missing: String = work.join("missing.vvr").display().to_string();
+    let mut vm = VM::new(1 << 20, 64 << 10, 64 << 10, 64);
+    let results = [
+        vm.load_vvr(&missing, 0, 1024),
+        vm.load_vvr(&work.display().to_string(), 0, 1024),
+        vm.load_vvr(&raw, 0, 63),
+        vm.load_vvr(&raw, 64, 1024),
+        vm.load_vvr(&raw, 0, 64),
+    ];
+    let _ = fs::remove_dir_all(&work);
+
+    assert!(results[0].as_ref().is_err_and(|e| e.contains("No such file")), "{:?}", results[0]);
+    assert!(results[1].is_err(), "a directory loaded");
+    assert_eq!(results[2], Err("image is 64 bytes, limit is 63 bytes".to_string()));
+    assert!(results[3].as_ref().is_err_and(|e| e.contains("entry 0x40")), "{:?}", results[3]);
+    assert_eq!(results[4], Ok(()));
+}
@@ -0,0 +1,85 @@
+// Terminal input pumped into the event queue (ncalls 0x58..0x5B), with a
+// queue small enough to overflow.
+
+use std::{
+    env, fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+const SRC: &str = "section text
+.start
+    uload r1 2000
+    ncall @ev_term_pump r0
+    movr r10 r0
+    ncall @ev_count r0
+    movr r11 r0
+    ncall @ev_pop r0
+    movr r12 r0
+    movr r13 r1
+    movr r14 r2
+    movr r15 r3
+    ncall @ev_pop r0
+    movr r16 r1
+    ncall @ev_pop r0
+    movr r17 r1
+    movr r18 r3
+    ncall @ev_pop r0
+    movr r19 r0
+    ncall @ev_dropped r0
+    movr r20 r0
+    ncall @ev_dropped r0
+    movr r21 r0
+    halt
+";
+
+#[test]
+fn terminal_keys_become_events() {
+    let work: PathBuf = env::temp_dir().join(format!("voxvm-events-{}", std::process::id()));
+    fs::create_dir_all(&work).unwrap();
+    let (vvs, vve, state) = (work.join("e.vvs"), work.join("e.vve"), work.join("e.state"));
+    fs::write(&vvs, SRC).unwrap();
+    let asm = Command::new(VOXVM)
+        .arg(format!("--vas={}", vvs.display()))
+        .arg(format!("--vas-out={}", vve.display()))
+        .output()
+        .unwrap();
+    assert!(asm.status.success(), "{}", String::from_utf8_lossy(&asm.stderr));
+
+    let mut child = Command::new(VOXVM)
+        .arg(format!("--vve={}", vve.display()))
+        .arg(format!("--dump-state={}", state.display()))
+        .args(["--event-queue=3", "--event-overflow=drop-newest"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // 'a', arrow up, Ctrl-C, then 'Z' which doesn't fit anymore
+    child.stdin.take().unwrap().write_all(b"a\x1b[A\x03Z").unwrap();
+    let out = child.wait_with_output().unwrap();
+    let dump: String = fs::read_to_string(&state).unwrap_or_default();
+    let _ = fs::remove_dir_all(&work);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+
+    let expected: [(&str, u64); 12] = [
+        ("r10", 4),      // decoded
+        ("r11", 3),      // queued
+        ("r12", 1),      // EV_KEY
+        ("r13", 0x61),   // 'a'
+        ("r14", 0x61),
+        ("r15", 0),
+        ("r16", 0x1001), // KEY_UP
+        ("r17", 0x63),   // 'c'
+        ("r18", 2),      // MOD_CTRL
+        ("r19", 0),      // empty
+        ("r20", 1),      // 'Z' dropped
+        ("r21", 0),
+    ];
+    for (reg, val) in expected {
+        assert!(dump.contains(&format!("{}: uint({})\n", reg, val)), "{} != {}\n{}", reg, val, dump);
+    }
+}
@@ -0,0 +1,52 @@
+// `voxvm fmt` must not change what a file assembles to and must be idempotent.
+// Every fixture is formatted into a temp dir, then both versions are assembled
+// and the executables compared byte for byte.
+
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use common::{VOXVM, assemble_file, work_dir};
+
+fn assemble(src: &Path, out: &Path) -> Vec<u8> {
+    assemble_file(src, out, &[]);
+    fs::read(out).unwrap()
+}
+
+#[test]
+fn fmt_keeps_bytecode() {
+    let fixtures: PathBuf = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures");
+    let work: PathBuf = work_dir("fmt");
+
+    let mut sources: Vec<PathBuf> = fs::read_dir(&fixtures)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "vvs"))
+        .collect();
+    sources.sort();
+
+    for src in sources {
+        let name: &str = src.file_stem().unwrap().to_str().unwrap();
+        let formatted: PathBuf = work.join(format!("{}.vvs", name));
+        fs::copy(&src, &formatted).unwrap();
+
+        let fmt = Command::new(VOXVM).arg("fmt").arg(&formatted).output().unwrap();
+        assert!(fmt.status.success(), "{}: fmt failed", name);
+        let check = Command::new(VOXVM)
+            .args(["fmt", "--check"])
+            .arg(&formatted)
+            .output()
+            .unwrap();
+        assert!(check.status.success(), "{}: fmt is not idempotent", name);
+
+        let orig_bin: Vec<u8> = assemble(&src, &work.join(format!("{}.orig.vve", name)));
+        let fmt_bin: Vec<u8> = assemble(&formatted, &work.join(format!("{}.fmt.vve", name)));
+        assert!(orig_bin == fmt_bin, "{}: formatting changed the bytecode", name);
+    }
+
+    let _ = fs::remove_dir_all(&work);
+}
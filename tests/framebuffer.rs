@@ -0,0 +1,59 @@
+// `--fb-dump` presents framebuffers (ncall 0x68) as PPM files.
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+const SRC: &str = "section text
+.start
+    uload r1 2
+    uload r2 1
+    ncall @fb_create r0
+    movr r20 r0
+    movr r1 r20
+    uload r2 1
+    uload r3 0
+    uload r4 1
+    uload r5 1
+    uload r6 0x10203040
+    ncall @fb_fill r0
+    movr r1 r20
+    ncall 0x68 r0
+    ncall 0x68 r0
+    halt
+";
+
+#[test]
+fn presented_frames_become_ppm() {
+    let work: PathBuf = env::temp_dir().join(format!("voxvm-fb-{}", std::process::id()));
+    fs::create_dir_all(&work).unwrap();
+    let (vvs, vve) = (work.join("f.vvs"), work.join("f.vve"));
+    fs::write(&vvs, SRC).unwrap();
+    let asm = Command::new(VOXVM)
+        .arg(format!("--vas={}", vvs.display()))
+        .arg(format!("--vas-out={}", vve.display()))
+        .output()
+        .unwrap();
+    assert!(asm.status.success(), "{}", String::from_utf8_lossy(&asm.stderr));
+
+    let plain = Command::new(VOXVM).arg(format!("--vve={}", vve.display())).output().unwrap();
+    assert!(!plain.status.success());
+    assert!(String::from_utf8_lossy(&plain.stderr).contains("doesn't provide: 0x68"));
+
+    let run = Command::new(VOXVM)
+        .arg(format!("--vve={}", vve.display()))
+        .arg(format!("--fb-dump={}", work.display()))
+        .output()
+        .unwrap();
+    let frames: Vec<Option<Vec<u8>>> = ["frame_0000.ppm", "frame_0001.ppm", "frame_0002.ppm"]
+        .iter()
+        .map(|f| fs::read(work.join(f)).ok())
+        .collect();
+    let _ = fs::remove_dir_all(&work);
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    let mut expected: Vec<u8> = b"P6\n2 1\n255\n".to_vec();
+    expected.extend([0, 0, 0, 0x10, 0x20, 0x30]);
+    assert_eq!(frames[0].as_ref(), Some(&expected));
+    assert_eq!(frames[1].as_ref(), Some(&expected));
+    assert_eq!(frames[2], None);
+}
@@ -0,0 +1,130 @@
+// Golden-state conformance suite.
+// Every tests/fixtures/NAME.vvs is assembled and run by the voxvm binary,
+// its stdout and final VM state (--dump-state) are compared against
+// tests/fixtures/NAME.golden.
+// Run with VOXVM_UPDATE_GOLDEN=1 to (re)write the golden snapshots.
+// A first line like `# args: --flag` passes extra flags to the VM run.
+// Fixtures are also assembled as little-endian images, which should load
+// and run to the very same snapshot.
+
+mod common;
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use common::VOXVM;
+
+const UPDATE_ENV: &str = "VOXVM_UPDATE_GOLDEN";
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures")
+}
+
+/// Assembles the fixture in `byte_order` and runs it, returns its snapshot
+fn run_fixture(src: &Path, work: &Path, byte_order: &str) -> Result<String, String> {
+    let name: &str = src.file_stem().unwrap().to_str().unwrap();
+    let vve: PathBuf = work.join(format!("{}-{}.vve", name, byte_order));
+    let state: PathBuf = work.join(format!("{}.state", name));
+
+    let asm = Command::new(VOXVM)
+        .arg(format!("--vas={}", src.display()))
+        .arg(format!("--vas-out={}", vve.display()))
+        .arg(format!("--vas-byte-order={}", byte_order))
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !asm.status.success() {
+        return Err(format!("assembling failed:\n{}", String::from_utf8_lossy(&asm.stderr)));
+    }
+    let le_flag: bool = (fs::read(&vve).map_err(|e| e.to_string())?[0x2E] & 0x1) != 0;
+    if le_flag != (byte_order == "le") {
+        return Err(format!("{} image has the little-endian flag {}", byte_order, le_flag));
+    }
+
+    let source: String = fs::read_to_string(src).map_err(|e| e.to_string())?;
+    let extra_args: Vec<&str> = source
+        .lines()
+        .next()
+        .and_then(|l| l.strip_prefix("# args:"))
+        .map_or(Vec::new(), |a| a.split_whitespace().collect());
+
+    let run = Command::new(VOXVM)
+        .args(&extra_args)
+        .arg(format!("--vve={}", vve.display()))
+        .arg("--init-ram=1MB")
+        .arg("--init-stack-size=64KB")
+        .arg("--init-heap-size=64KB")
+        .arg(format!("--dump-state={}", state.display()))
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !run.status.success() {
+        return Err(format!("run failed:\n{}", String::from_utf8_lossy(&run.stderr)));
+    }
+
+    let state_text: String = fs::read_to_string(&state).map_err(|e| e.to_string())?;
+    Ok(format!(
+        "== stdout ==\n{}== state ==\n{}",
+        String::from_utf8_lossy(&run.stdout),
+        state_text
+    ))
+}
+
+#[test]
+fn golden_fixtures() {
+    let update: bool = env::var(UPDATE_ENV).is_ok();
+    let work: PathBuf = common::work_dir("golden");
+
+    let mut sources: Vec<PathBuf> = fs::read_dir(fixtures_dir())
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "vvs"))
+        .collect();
+    sources.sort();
+    assert!(!sources.is_empty(), "No fixtures found");
+
+    let mut failures: Vec<String> = Vec::new();
+    for src in &sources {
+        let golden: PathBuf = src.with_extension("golden");
+        let actual: String = match run_fixture(src, &work, "be") {
+            Ok(v) => v,
+            Err(e) => {
+                failures.push(format!("{}: {}", src.display(), e));
+                continue;
+            }
+        };
+        match run_fixture(src, &work, "le") {
+            Ok(le) if le == actual => {}
+            Ok(le) => failures.push(format!(
+                "{}: little-endian image differs\n--- big-endian\n{}--- little-endian\n{}",
+                src.display(),
+                actual,
+                le
+            )),
+            Err(e) => failures.push(format!("{} (le): {}", src.display(), e)),
+        }
+
+        if update {
+            fs::write(&golden, &actual).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&golden) {
+            Ok(expected) if expected == actual => {}
+            Ok(expected) => failures.push(format!(
+                "{}: snapshot mismatch\n--- expected\n{}--- actual\n{}",
+                src.display(),
+                expected,
+                actual
+            )),
+            Err(_) => failures.push(format!(
+                "{}: no golden snapshot, run with {}=1",
+                src.display(),
+                UPDATE_ENV
+            )),
+        }
+    }
+    let _ = fs::remove_dir_all(&work);
+
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}
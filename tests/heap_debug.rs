@@ -0,0 +1,59 @@
+// `--heap-debug` reports where the heap went when an allocation fails.
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+const SRC: &str = "section text
+.start
+    alloc r4 100
+    uload r1 20000
+    call @grow
+    call @grow
+    call @grow
+    call @grow
+    halt
+
+func grow
+    allocr r0 r1
+    ret
+";
+
+#[test]
+fn alloc_fault_lists_sites() {
+    let work: PathBuf = env::temp_dir().join(format!("voxvm-heapdbg-{}", std::process::id()));
+    fs::create_dir_all(&work).unwrap();
+    let (vvs, vve) = (work.join("h.vvs"), work.join("h.vve"));
+    fs::write(&vvs, SRC).unwrap();
+
+    let asm = Command::new(VOXVM)
+        .arg(format!("--vas={}", vvs.display()))
+        .arg(format!("--vas-out={}", vve.display()))
+        .output()
+        .unwrap();
+    assert!(asm.status.success(), "{}", String::from_utf8_lossy(&asm.stderr));
+    let run = |debug: bool| {
+        let mut cmd = Command::new(VOXVM);
+        if debug {
+            cmd.arg("--heap-debug");
+        }
+        let out = cmd
+            .arg(format!("--vve={}", vve.display()))
+            .arg("--init-ram=1MB")
+            .arg("--init-stack-size=64KB")
+            .arg("--init-heap-size=64KB")
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        String::from_utf8_lossy(&out.stderr).to_string()
+    };
+
+    let report: String = run(true);
+    assert!(report.contains("heap: can't allocate 20000 bytes at ip"), "{}", report);
+    assert!(report.contains("(line 12) in grow"), "{}", report);
+    assert!(report.contains("  60000 bytes in 3 blocks from ip"), "{}", report);
+    assert!(report.contains("  100 bytes in 1 blocks from ip 0x0 (line 3)"), "{}", report);
+    assert!(!run(false).contains("can't allocate"));
+
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,77 @@
+// `voxvm hexdump` decodes the header and annotates code and data of a vve,
+// the same way for both byte orders.
+// Little-endian images swap the fields of every section, data symbols too.
+
+mod common;
+
+use std::{fs, path::PathBuf, process::Command};
+
+use common::VOXVM;
+
+const SRC: &str = "section text
+.start
+    call @twice
+    halt
+
+func twice
+    uadd r1 r1
+    ret
+
+section data
+    count uint 7
+    msg const str \"hi\"
+    blob db 0xCA 0xFE
+";
+
+/// Assembles SRC in `byte_order`, returns the work dir and the vve in it
+fn assemble(byte_order: &str, tag: &str) -> (PathBuf, PathBuf) {
+    let work: PathBuf = common::work_dir(&format!("{}-{}", tag, byte_order));
+    let vve: PathBuf = common::assemble(&work, "h", SRC, &[&format!("--vas-byte-order={}", byte_order)]);
+    (work, vve)
+}
+
+fn hexdump(byte_order: &str) -> String {
+    let (work, vve) = assemble(byte_order, "hexdump");
+    let out = Command::new(VOXVM).arg("hexdump").arg(&vve).output().unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let _ = fs::remove_dir_all(&work);
+    String::from_utf8_lossy(&out.stdout).to_string()
+}
+
+#[test]
+fn hexdump_annotates_image() {
+    let dump: String = hexdump("be");
+    for expected in [
+        "magic: VVE\n",
+        "version: 4\n",
+        "byte order: big-endian\n",
+        "functions: 1\n",
+        "    0  0x0000000a  twice\n",
+        "  0x0006  relocs",
+        "<.start>:\n00000000  90 00 00 00 00 00 00 00 00 ff",
+        "<twice>:\n0000000a  11 01 01 91",
+        "uint, 8 bytes\n",
+        "str, 4 bytes [const rodata]\n",
+        "bytes, 2 bytes\n",
+    ] {
+        assert!(dump.contains(expected), "missing {:?} in\n{}", expected, dump);
+    }
+
+    let le: String = hexdump("le");
+    assert!(le.contains("byte order: little-endian\n"), "{}", le);
+    assert!(le.contains("  0x0007  words"), "{}", le);
+    let after_header = |d: &str| d[d.find("\ncode [").unwrap()..].to_string();
+    assert_eq!(after_header(&dump), after_header(&le));
+}
+
+#[test]
+fn data_symbols_follow_byte_order() {
+    // the name length of `count` in the data_syms section, then the name
+    for (byte_order, len) in [("be", [0u8, 5]), ("le", [5u8, 0])] {
+        let (work, vve) = assemble(byte_order, "datasyms");
+        let bytes: Vec<u8> = fs::read(&vve).unwrap();
+        let _ = fs::remove_dir_all(&work);
+        let entry: Vec<u8> = [&len[..], b"count"].concat();
+        assert!(bytes.windows(entry.len()).any(|w| w == entry), "{} image has no {:?}", byte_order, entry);
+    }
+}
@@ -0,0 +1,95 @@
+// `--hot-loops=N` runs hot loops from pre-decoded bodies and
+// `--decode-cache` runs instructions decoded once per ip, the results must
+// match the interpreter: branches inside and out of the body, calls, a
+// type error raised inside it and a nested loop.
+
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+const SRC: &str = "section text
+.start
+    uload r1 50
+    uload r2 1
+    uload r3 0
+    uload r6 0
+    iload r8 -3
+label outer
+    uload r4 4
+label inner
+    uadd r3 r2
+    udec r4
+    jnz @inner
+    uload r5 7
+    ucmp r1 r5
+    jz @skip
+    call @bump
+label skip
+    usub r1 r2
+    jnz @outer
+    movr r7 r3
+    uadd r7 r8
+    jexc @incorrectregtype @caught
+    halt
+label caught
+    uload r9 1
+    halt
+
+func bump
+    uinc r6
+    ret
+";
+
+/// State dump, instructions executed and stderr of a run
+fn run(vve: &Path, extra: &[&str]) -> (String, u64, String) {
+    let status: PathBuf = vve.with_extension("json");
+    let status_arg: String = format!("--json-status={}", status.display());
+    let (run, dump) = common::run(vve, &[&["--init-heap-size=64KB", &status_arg][..], extra].concat());
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(status).unwrap()).unwrap();
+    (
+        dump,
+        json["stats"]["instructions"].as_u64().unwrap(),
+        String::from_utf8_lossy(&run.stderr).to_string(),
+    )
+}
+
+fn assemble(name: &str) -> (PathBuf, PathBuf) {
+    let work: PathBuf = common::work_dir(name);
+    let vve: PathBuf = common::assemble(&work, "h", SRC, &[]);
+    (work, vve)
+}
+
+#[test]
+fn hot_loops_match_interpreter() {
+    let (work, vve) = assemble("hotloops");
+    let (plain, plain_count, _) = run(&vve, &[]);
+    let (threaded, threaded_count, info) = run(&vve, &["--hot-loops=2"]);
+    let _ = fs::remove_dir_all(&work);
+
+    assert!(plain.contains("r3: uint(200)"), "{}", plain);
+    assert!(plain.contains("r6: uint(49)"), "{}", plain);
+    assert!(plain.contains("r9: uint(1)"), "{}", plain);
+    assert_eq!(plain, threaded);
+    assert_eq!(plain_count, threaded_count);
+    assert!(info.contains("--hot-loops: 2 loop bodies threaded"), "{}", info);
+}
+
+#[test]
+fn decode_cache_matches_interpreter() {
+    let (work, vve) = assemble("decodecache");
+    let (plain, plain_count, _) = run(&vve, &[]);
+    let (cached, cached_count, info) = run(&vve, &["--decode-cache"]);
+    let (both, both_count, _) = run(&vve, &["--decode-cache", "--hot-loops=3"]);
+    let _ = fs::remove_dir_all(&work);
+
+    assert_eq!(plain, cached);
+    assert_eq!(plain, both);
+    assert_eq!(plain_count, cached_count);
+    assert_eq!(plain_count, both_count);
+    // every instruction that ran, all but the halt jexc jumps over
+    assert!(info.contains("--decode-cache: 22 instructions decoded"), "{}", info);
+}
@@ -0,0 +1,87 @@
+// `--watch-data` copies changed mutable data variables from a rebuilt vve
+// into the running VM. Const ones keep their old values, a str shortened
+// by dsstore gets the length of its new value back.
+
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    thread::sleep,
+    time::Duration,
+};
+
+use common::{VOXVM, reg};
+
+/// Waits (50ms steps, 20s at most) for `flag` to become nonzero, then
+/// loads the variables into r5..r8
+const SRC: &str = "section text
+.start
+    alloc r2 8
+    uload r3 2
+    store r2 r3 r3
+    dsstore r2 r3 word
+    uload r1 50
+    uload r9 0
+    uload r10 400
+    uload r11 0
+label wait
+    dsload r4 flag 0
+    ucmp r4 r9
+    jg @done
+    ncall @sleep r0
+    uinc r11
+    ucmp r11 r10
+    jl @wait
+    halt
+label done
+    dsload r5 num 0
+    dsload r6 limit 0
+    dsload r12 word 0
+    strlen r7 r12
+    strindex r8 r12 r9
+    halt
+section data
+    flag uint FLAG
+    num uint NUM
+    limit const uint LIMIT
+    word str \"WORD\"
+";
+
+fn assemble(work: &Path, name: &str, flag: u64, num: u64, limit: u64, word: &str) -> PathBuf {
+    let src: String = SRC
+        .replace("FLAG", &flag.to_string())
+        .replace("NUM", &num.to_string())
+        .replace("LIMIT", &limit.to_string())
+        .replace("WORD", word);
+    common::assemble(work, name, &src, &[])
+}
+
+#[test]
+fn reload_updates_mutable_variables_only() {
+    let work: PathBuf = common::work_dir("hotreload");
+    let vve: PathBuf = assemble(&work, "prog", 0, 5, 100, "ab");
+    let state: PathBuf = work.join("prog.state");
+    let vm = Command::new(VOXVM)
+        .arg(format!("--vve={}", vve.display()))
+        .arg(format!("--watch-data={}", vve.display()))
+        .arg("--init-heap-size=64KB")
+        .arg(format!("--dump-state={}", state.display()))
+        .spawn()
+        .unwrap();
+    sleep(Duration::from_millis(300));
+    let rebuilt: PathBuf = assemble(&work, "rebuilt", 1, 6, 200, "cd");
+    fs::rename(&rebuilt, &vve).unwrap();
+    let out = vm.wait_with_output().unwrap();
+    let dump: String = fs::read_to_string(&state).unwrap_or_default();
+    let _ = fs::remove_dir_all(&work);
+    assert!(out.status.success());
+
+    assert!(dump.contains("exceptions: []"), "{}", dump);
+    assert_eq!(reg(&dump, "r4"), 1, "the reload never came\n{}", dump);
+    assert_eq!(reg(&dump, "r5"), 6, "{}", dump);
+    assert_eq!(reg(&dump, "r6"), 100, "{}", dump);
+    assert_eq!(reg(&dump, "r7"), 2, "{}", dump);
+    assert_eq!(reg(&dump, "r8"), 'c' as u64, "{}", dump);
+}
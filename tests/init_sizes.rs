@@ -0,0 +1,57 @@
+// Init sizes take bare byte counts, KiB.. spellings and TB, totals past the
+// available memory are refused, stack + heap over RAM warns.
+
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use common::VOXVM;
+
+const SRC: &str = "section text
+.start
+    uload r1 1
+    halt
+";
+
+fn run(vve: &Path, sizes: [&str; 3]) -> Output {
+    Command::new(VOXVM)
+        .arg(format!("--vve={}", vve.display()))
+        .arg(format!("--init-ram={}", sizes[0]))
+        .arg(format!("--init-stack-size={}", sizes[1]))
+        .arg(format!("--init-heap-size={}", sizes[2]))
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn init_size_spellings_and_limits() {
+    let work: PathBuf = common::work_dir("sizes");
+    let vve: PathBuf = common::assemble(&work, "s", SRC, &[]);
+
+    let plain = run(&vve, ["1048576", "64KiB", "0.0625mib"]);
+    let huge = run(&vve, ["1024TB", "64KB", "64KB"]);
+    let lopsided = run(&vve, ["64KB", "64KB", "64KB"]);
+    let bad = run(&vve, ["12XB", "64KB", "64KB"]);
+    let _ = fs::remove_dir_all(&work);
+
+    let stdout: String = String::from_utf8_lossy(&plain.stdout).to_string();
+    assert!(plain.status.success(), "{}", String::from_utf8_lossy(&plain.stderr));
+    assert!(stdout.contains("init RAM size = 1.0MB"), "{}", stdout);
+    assert!(stdout.contains("init stack size = 64.0KB"), "{}", stdout);
+    assert!(stdout.contains("init heap size = 64.0KB"), "{}", stdout);
+    assert!(plain.stderr.is_empty(), "{}", String::from_utf8_lossy(&plain.stderr));
+
+    let stderr: String = String::from_utf8_lossy(&huge.stderr).to_string();
+    assert!(!huge.status.success());
+    assert!(stderr.contains("ERROR: RAM + stack + heap = 1024.0TB"), "{}", stderr);
+
+    let stderr: String = String::from_utf8_lossy(&lopsided.stderr).to_string();
+    assert!(lopsided.status.success(), "{}", stderr);
+    assert!(stderr.contains("WARNING: stack + heap (128.0KB) is more than init RAM (64.0KB)"), "{}", stderr);
+
+    assert!(String::from_utf8_lossy(&bad.stderr).contains("Init ram value is incorrect"));
+}
@@ -0,0 +1,117 @@
+// `voxvm lint` reports every kind of finding on a deliberately broken source
+// and stays quiet on a clean one.
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+const BROKEN: &str = "section text
+.start
+    uload r1 5
+    uadd r1 r2
+    fload r3 1.5
+    iadd r3 r1
+    push r1
+    ucmp r1 r1
+    jz @skip
+    push r1
+label skip
+    call @missing
+    jmp @end
+    uload r9 1
+label dead
+label end
+    pop r5
+    halt
+
+func f
+    push r1
+    ret
+";
+
+fn lint(name: &str, src: &str) -> (bool, String) {
+    let path: PathBuf = env::temp_dir().join(format!("voxvm-lint-{}-{}.vvs", std::process::id(), name));
+    fs::write(&path, src).unwrap();
+    let out = Command::new(VOXVM).arg("lint").arg(&path).output().unwrap();
+    let _ = fs::remove_file(&path);
+    (out.status.success(), String::from_utf8_lossy(&out.stdout).to_string())
+}
+
+#[test]
+fn lint_reports_findings() {
+    let (ok, out) = lint("broken", BROKEN);
+    assert!(!ok);
+    for expected in [
+        ":4: warning: r2 may be read before it is written",
+        ":6: warning: iadd expects int in r1, but it holds uint",
+        ":6: warning: iadd expects int in r3, but it holds float",
+        ":12: warning: data stack depth differs on paths joining here (1 vs 2)",
+        ":12: error: call to undefined function 'missing'",
+        ":14: warning: unreachable code",
+        ":15: warning: label 'dead' is never used",
+        ":22: warning: 1 value(s) pushed in this function are still on the data stack at ret",
+    ] {
+        assert!(out.contains(expected), "missing `{}` in:\n{}", expected, out);
+    }
+}
+
+#[test]
+fn lint_clean_source() {
+    let fixture: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/flow.vvs");
+    let (ok, out) = lint("flow", &fs::read_to_string(fixture).unwrap());
+    assert!(ok, "unexpected findings:\n{}", out);
+}
+
+#[test]
+fn lint_expands_invoke() {
+    let src: &str = "section text
+.start
+    uload r1 2
+    invoke @twice, r1 -> r3
+    invoke @twice, r1 -> x4
+    invoke @nowhere, 1
+    halt
+
+func twice
+    clobbers r0
+    movr r0 r1
+    uadd r0 r1
+    ret
+";
+    let (ok, out) = lint("invoke", src);
+    assert!(!ok);
+    assert!(out.contains(":5: error: invoke: result should go to a register, got 'x4'"), "{}", out);
+    assert!(out.contains(":6: error: call to undefined function 'nowhere'"), "{}", out);
+    assert!(!out.contains(":4:"), "{}", out);
+}
+
+#[test]
+fn lint_alias_conflicts() {
+    let src: &str = "section text
+alias count = r5
+alias count = r6
+alias tally = r5
+alias r3 = r4
+alias bad = x1
+.start
+    uload count 1
+    halt
+
+func f
+alias count = r7
+    movr r0 count
+    ret
+";
+    let (ok, out) = lint("alias", src);
+    assert!(!ok);
+    for expected in [
+        ":3: error: alias 'count' already names r5 (line 2)",
+        ":4: warning: 'count' and 'tally' both name r5",
+        ":5: error: alias 'r3' would hide a register or instruction",
+        ":6: error: alias 'bad' should name a register, got 'x1'",
+        ":12: warning: alias 'count' shadows the global one of line 2 (r5)",
+    ] {
+        assert!(out.contains(expected), "missing `{}` in:\n{}", expected, out);
+    }
+    assert!(!out.contains(":8:") && !out.contains(":13:"), "{}", out);
+}
@@ -0,0 +1,66 @@
+// `--mem-profile` counts accesses per data variable and heap block and
+// lists the hottest first, with names and allocation sites.
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+const SRC: &str = "section text
+.start
+    uload r2 8
+    allocr r1 r2
+    uload r9 0
+label loop
+    dsload r3 limit 0
+    store r1 r9 r2
+    uinc r9
+    ucmp r9 r3
+    jl @loop
+    dssave r9 total 0
+    load r2 r4 r1 r2
+    halt
+section data
+    limit const uint 5
+    total uint 0
+";
+
+#[test]
+fn mem_profile_hottest_first() {
+    let work: PathBuf = env::temp_dir().join(format!("voxvm-memprof-{}", std::process::id()));
+    fs::create_dir_all(&work).unwrap();
+    let (vvs, vve, out) = (work.join("m.vvs"), work.join("m.vve"), work.join("m.txt"));
+    fs::write(&vvs, SRC).unwrap();
+
+    let asm = Command::new(VOXVM)
+        .arg(format!("--vas={}", vvs.display()))
+        .arg(format!("--vas-out={}", vve.display()))
+        .output()
+        .unwrap();
+    assert!(asm.status.success(), "{}", String::from_utf8_lossy(&asm.stderr));
+    let run = Command::new(VOXVM)
+        .arg(format!("--vve={}", vve.display()))
+        .arg("--init-ram=1MB")
+        .arg("--init-stack-size=64KB")
+        .arg("--init-heap-size=64KB")
+        .arg(format!("--mem-profile={}", out.display()))
+        .output()
+        .unwrap();
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+
+    let report: String = fs::read_to_string(&out).unwrap();
+    let rows: Vec<Vec<&str>> = report
+        .lines()
+        .skip(2)
+        .map(|l| l.split_whitespace().collect())
+        .collect();
+    assert_eq!(rows.len(), 3, "{}", report);
+    assert_eq!(&rows[0][..2], ["1", "5"], "{}", report);
+    assert_eq!(rows[0][3..7].join(" "), "heap block #1, 8", "{}", report);
+    assert!(rows[0].join(" ").ends_with("allocated at ip 0x6 (line 4)"), "{}", report);
+    assert_eq!(&rows[1][..2], ["5", "0"], "{}", report);
+    assert_eq!(rows[1][3..].join(" "), "data limit (const)", "{}", report);
+    assert_eq!(&rows[2][..2], ["0", "1"], "{}", report);
+    assert_eq!(rows[2][3..].join(" "), "data total", "{}", report);
+
+    let _ = fs::remove_dir_all(&work);
+}
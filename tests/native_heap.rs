@@ -0,0 +1,115 @@
+// Heap access of native functions (`heap_api = true`): `reverse_buf` of
+// nconfigs/libs/libtestfr.c reverses a heap buffer through the HeapApi
+// callbacks, `attach_shared`/`shared_fill`/`shared_sum` keep a host pointer
+// to an `ncall @shm_create` region between calls. Skipped without a C
+// compiler, like native_hooks.
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+const SRC: &str = "section text
+.start
+    alloc r1 4
+    movr r5 r1
+    uload r2 0x0102030400000000
+    uload r3 4
+    store r1 r2 r3
+    ncall 0x102 r0
+    movr r10 r0
+    uload r4 1
+    load r4 r11 r5 r3
+    halt
+";
+
+const SHARED_SRC: &str = "section text
+.start
+    uload r1 32
+    ncall @shm_create r0
+    movr r5 r0
+    movr r1 r5
+    ncall 0x103 r0
+    movr r10 r0
+    uload r1 7
+    ncall 0x104 r0
+    uload r3 8
+    uload r4 1
+    loadi r4 r11 [r5+24] r3
+    uload r2 1
+    storei [r5] r2 r3
+    ncall 0x105 r0
+    movr r12 r0
+    movr r1 r5
+    ncall @shm_query r0
+    movr r13 r1
+    free r5
+    movr r1 r5
+    ncall @shm_release r0
+    movr r14 r0
+    halt
+";
+
+/// State dump of `src` run with the test plugin, None without a C compiler
+fn run_with_plugin(tag: &str, src: &str) -> Option<String> {
+    let work: PathBuf = env::temp_dir().join(format!("voxvm-nheap-{}-{}", tag, std::process::id()));
+    fs::create_dir_all(work.join("cfg")).unwrap();
+    let lib: PathBuf = work.join("libtestfr.so");
+    let c_src: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("nconfigs/libs/libtestfr.c");
+    let built = Command::new("cc").args(["-shared", "-fPIC", "-o"]).arg(&lib).arg(&c_src).output();
+    if !built.is_ok_and(|o| o.status.success()) {
+        eprintln!("no C compiler to build the plugin, skipping");
+        let _ = fs::remove_dir_all(&work);
+        return None;
+    }
+    let cfg: String = fs::read_to_string(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("nconfigs/test.toml"))
+        .unwrap()
+        .replace("nconfigs/libs/libtestfr.so", &lib.display().to_string());
+    fs::write(work.join("cfg").join("test.toml"), cfg).unwrap();
+    fs::write(work.join("h.vvs"), src).unwrap();
+
+    let asm = Command::new(VOXVM)
+        .arg(format!("--vas={}", work.join("h.vvs").display()))
+        .arg(format!("--vas-out={}", work.join("h.vve").display()))
+        .output()
+        .unwrap();
+    assert!(asm.status.success(), "{}", String::from_utf8_lossy(&asm.stderr));
+    let state: PathBuf = work.join("h.state");
+    let run = Command::new(VOXVM)
+        .arg(format!("--native-configs={}", work.join("cfg").display()))
+        .arg(format!("--vve={}", work.join("h.vve").display()))
+        .arg(format!("--dump-state={}", state.display()))
+        .output()
+        .unwrap();
+    let dump: String = fs::read_to_string(&state).unwrap_or_default();
+    let _ = fs::remove_dir_all(&work);
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    Some(dump)
+}
+
+#[test]
+fn native_function_reaches_heap_buffer() {
+    let Some(dump) = run_with_plugin("buf", SRC) else {
+        return;
+    };
+    for line in ["r10: uint(4)", "r11: uint(67305985)", "exceptions: []"] {
+        assert!(dump.lines().any(|l| l == line), "no '{}' in\n{}", line, dump);
+    }
+}
+
+#[test]
+fn native_function_keeps_shared_region() {
+    let Some(dump) = run_with_plugin("shm", SHARED_SRC) else {
+        return;
+    };
+    // 32 bytes of 7, then the first 8 set to 0,..,0,1 by the guest
+    for line in [
+        "r10: uint(32)",
+        "r11: uint(506381209866536711)",
+        "r12: uint(169)",
+        "r13: uint(32)",
+        "r14: uint(1)",
+        "exceptions: [HeapFreeFault]",
+    ] {
+        assert!(dump.lines().any(|l| l == line), "no '{}' in\n{}", line, dump);
+    }
+}
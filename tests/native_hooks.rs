@@ -0,0 +1,87 @@
+// Opcode hooks of native plugins: nconfigs/libs/libtestfr.c is built with the
+// system C compiler (the test is skipped without one), its `count_nops` post
+// hook counts nops in r20 and `safe_udiv` pre hook emulates udiv by zero.
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+const SRC: &str = "section text
+.start
+    nop
+    nop
+    uload r1 12
+    uload r2 0
+    udiv r3 r1 r2
+    uload r2 4
+    udiv r4 r1 r2
+    nop
+    halt
+";
+
+#[test]
+fn hooks_run_around_opcodes() {
+    let work: PathBuf = env::temp_dir().join(format!("voxvm-hooks-{}", std::process::id()));
+    fs::create_dir_all(work.join("cfg")).unwrap();
+    let lib: PathBuf = work.join("libtestfr.so");
+    let c_src: PathBuf =
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("nconfigs/libs/libtestfr.c");
+    let built = Command::new("cc")
+        .args(["-shared", "-fPIC", "-o"])
+        .arg(&lib)
+        .arg(&c_src)
+        .output();
+    if !built.is_ok_and(|o| o.status.success()) {
+        eprintln!("no C compiler to build the plugin, skipping");
+        return;
+    }
+    let cfg: String =
+        fs::read_to_string(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("nconfigs/test.toml"))
+            .unwrap()
+            .replace("nconfigs/libs/libtestfr.so", &lib.display().to_string());
+    fs::write(work.join("cfg").join("test.toml"), cfg).unwrap();
+    fs::write(work.join("hooks.vvs"), SRC).unwrap();
+
+    let asm = Command::new(VOXVM)
+        .arg(format!("--vas={}", work.join("hooks.vvs").display()))
+        .arg(format!("--vas-out={}", work.join("hooks.vve").display()))
+        .output()
+        .unwrap();
+    assert!(
+        asm.status.success(),
+        "{}",
+        String::from_utf8_lossy(&asm.stderr)
+    );
+    let state: PathBuf = work.join("hooks.state");
+    let run = Command::new(VOXVM)
+        .arg(format!("--native-configs={}", work.join("cfg").display()))
+        .arg(format!("--vve={}", work.join("hooks.vve").display()))
+        .arg("--init-ram=1MB")
+        .arg("--init-stack-size=64KB")
+        .arg("--init-heap-size=64KB")
+        .arg(format!("--dump-state={}", state.display()))
+        .output()
+        .unwrap();
+    assert!(
+        run.status.success(),
+        "{}",
+        String::from_utf8_lossy(&run.stderr)
+    );
+
+    let dump: String = fs::read_to_string(&state).unwrap();
+    for line in [
+        "r3: uint(0)",
+        "r4: uint(3)",
+        "r20: uint(3)",
+        "exceptions: []",
+    ] {
+        assert!(
+            dump.lines().any(|l| l == line),
+            "no '{}' in\n{}",
+            line,
+            dump
+        );
+    }
+
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,111 @@
+// `ncall @native_load` loads a native config while the program runs, with
+// --allow-native-load only. Codes already taken and configs with hooks are
+// refused. Uses the plugin of nconfigs/libs/libtestfr.c, skipped without a
+// C compiler like native_heap.
+
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Loads `cfg` twice around `ncall 0x100` (unsigned_add), then `hooked`.
+/// `{cfg}`, `{hooked}` and their utf16 sizes are filled in by `program`
+const SRC: &str = "section text
+.start
+    alloc r20 512
+    dslea r4 cfg 9
+    uload r3 {cfg_len}
+    storedat r20 r4 r3
+    alloc r21 512
+    dslea r4 hooked 9
+    uload r3 {hooked_len}
+    storedat r21 r4 r3
+    movr r1 r20
+    uload r2 {cfg_len}
+    ncall @native_load r0
+    movr r10 r0
+    uload r1 2
+    uload r2 3
+    ncall 0x100 r0
+    movr r11 r0
+    movr r1 r20
+    uload r2 {cfg_len}
+    ncall @native_load r0
+    jexc @nativefault @taken
+    halt
+label taken
+    movr r12 r0
+    ncall @lasterr r0
+    movr r13 r0
+    movr r1 r21
+    uload r2 {hooked_len}
+    ncall @native_load r0
+    jexc @nativefault @hooks
+    halt
+label hooks
+    uload r14 1
+    halt
+section data
+    cfg str \"{cfg}\"
+    hooked str \"{hooked}\"
+";
+
+const DENIED_SRC: &str = "section text
+.start
+    uload r1 0
+    uload r2 0
+    ncall @native_load r0
+    jexc @nativefault @denied
+    halt
+label denied
+    ncall @lasterr r0
+    movr r13 r0
+    halt
+";
+
+fn program(cfg: &Path, hooked: &Path) -> String {
+    let (cfg, hooked) = (cfg.display().to_string(), hooked.display().to_string());
+    SRC.replace("{cfg_len}", &(cfg.encode_utf16().count() * 2).to_string())
+        .replace("{hooked_len}", &(hooked.encode_utf16().count() * 2).to_string())
+        .replace("{cfg}", &cfg)
+        .replace("{hooked}", &hooked)
+}
+
+/// State dump after running `src`
+fn run(work: &Path, name: &str, src: &str, extra: &[&str]) -> String {
+    common::run_state(&common::assemble(work, name, src, &[]), extra)
+}
+
+#[test]
+fn native_config_loads_at_runtime() {
+    let work: PathBuf = common::work_dir("nload");
+    let lib: PathBuf = work.join("libtestfr.so");
+    let c_src: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("nconfigs/libs/libtestfr.c");
+    let built = Command::new("cc").args(["-shared", "-fPIC", "-o"]).arg(&lib).arg(&c_src).output();
+    if !built.is_ok_and(|o| o.status.success()) {
+        eprintln!("no C compiler to build the plugin, skipping");
+        let _ = fs::remove_dir_all(&work);
+        return;
+    }
+    let full: String = fs::read_to_string(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("nconfigs/test.toml"))
+        .unwrap()
+        .replace("nconfigs/libs/libtestfr.so", &lib.display().to_string());
+    let (cfg, hooked) = (work.join("add.toml"), work.join("hooked.toml"));
+    let functions_only: &str = full.split("[functions.unsigned_pow2]").next().unwrap();
+    fs::write(&cfg, functions_only).unwrap();
+    fs::write(&hooked, full.replace("ncall_code = 0x10", "ncall_code = 0x20")).unwrap();
+
+    let dump: String = run(&work, "load", &program(&cfg, &hooked), &["--allow-native-load"]);
+    let denied: String = run(&work, "denied", DENIED_SRC, &[]);
+    let _ = fs::remove_dir_all(&work);
+
+    // 0x100 added, 2 + 3, the second load collides (native subsystem, AlreadyExists)
+    for line in ["r10: uint(1)", "r11: uint(5)", "r12: uint(0)", "r13: uint(53251)", "r14: uint(1)", "exceptions: []"] {
+        assert!(dump.lines().any(|l| l == line), "no '{}' in\n{}", line, dump);
+    }
+    // PermissionDenied without --allow-native-load
+    assert!(denied.lines().any(|l| l == "r13: uint(53250)"), "{}", denied);
+}
@@ -0,0 +1,43 @@
+// Images list their ncall codes (SECT_NCALLS), the VM refuses to start one
+// that needs codes it doesn't provide instead of faulting mid-run.
+
+mod common;
+
+use std::{fs, path::PathBuf};
+
+const SRC: &str = "section text
+.start
+    uload r1 41
+    uload r2 1
+    ncall @print r0
+    ncall 0x100 r0
+    ncall 0x101 r0
+    halt
+";
+
+fn run(order: &str) -> (bool, String, String) {
+    let work: PathBuf = common::work_dir(&format!("ncaps-{}", order));
+    let vve: PathBuf = common::assemble(&work, "n", SRC, &[&format!("--vas-byte-order={}", order)]);
+    let (out, _) = common::run(&vve, &["--init-ram=1MB", "--init-stack-size=64KB", "--init-heap-size=64KB"]);
+    let _ = fs::remove_dir_all(&work);
+    (
+        out.status.success(),
+        String::from_utf8_lossy(&out.stdout).to_string(),
+        String::from_utf8_lossy(&out.stderr).to_string(),
+    )
+}
+
+#[test]
+fn missing_ncalls_are_reported_before_running() {
+    for order in ["be", "le"] {
+        let (ok, stdout, stderr) = run(order);
+        assert!(!ok);
+        assert!(
+            stderr.contains("uses ncalls this VM doesn't provide: 0x100, 0x101"),
+            "{}",
+            stderr
+        );
+        // nothing ran, not even the print before the first missing ncall
+        assert!(!stdout.contains("41") && !stderr.contains("41\n"), "{}{}", stdout, stderr);
+    }
+}
@@ -0,0 +1,52 @@
+// Bad numeric literals fail assembling with the line and the reason.
+
+mod common;
+
+use std::{fs, path::PathBuf};
+
+fn assemble(name: &str, src: &str) -> (bool, String) {
+    let work: PathBuf = common::work_dir(&format!("lit-{}", name));
+    let (_, out) = common::try_assemble(&work, "l", src, &[]);
+    let _ = fs::remove_dir_all(&work);
+    (out.status.success(), String::from_utf8_lossy(&out.stderr).to_string())
+}
+
+fn code(body: &str) -> String {
+    format!("section text\n.start\n{}\n    halt\n", body)
+}
+
+#[test]
+fn bad_digit_is_named() {
+    let (ok, err) = assemble("digit", &code("    uload r1 0x1G"));
+    assert!(!ok);
+    assert!(err.contains("2: Invalid number '0x1G': 'g' is not a hex digit"), "{}", err);
+}
+
+#[test]
+fn out_of_range_immediates() {
+    let (ok, err) = assemble("u32", &code("    uload32 r1 0x1_0000_0000"));
+    assert!(!ok);
+    assert!(err.contains("out of the 32-bit range"), "{}", err);
+
+    let (ok, err) = assemble("u64", &code("    uload r1 18446744073709551616"));
+    assert!(!ok);
+    assert!(err.contains("doesn't fit into 64 bits"), "{}", err);
+
+    let (ok, err) = assemble("i64", &code("    iload r1 9223372036854775808"));
+    assert!(!ok);
+    assert!(err.contains("out of the 64-bit signed range"), "{}", err);
+}
+
+#[test]
+fn misplaced_separator() {
+    let (ok, err) = assemble("sep", &code("    uload r1 1_000_"));
+    assert!(!ok);
+    assert!(err.contains("'_' only goes between digits"), "{}", err);
+}
+
+#[test]
+fn bad_data_initializer() {
+    let (ok, err) = assemble("data", "section text\n.start\n    halt\nsection data\n    a uint 0b102\n");
+    assert!(!ok);
+    assert!(err.contains("Invalid number '0b102': '2' is not a binary digit"), "{}", err);
+}
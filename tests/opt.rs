@@ -0,0 +1,99 @@
+// `--opt` shrinks the program without changing what it computes, and leaves
+// sources with numeric jump targets alone.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+const SRC: &str = "section data
+    used   uint 7
+    unused uint 9
+    !align=8
+    junk   const str \"never read\"
+section text
+.start
+    uload r1 2       # two
+    uload r2 3
+    uadd r1 r2
+    uload r3 10
+    umul r3 r1
+    iload r4 -4
+    iload r5 6
+    isub r4 r5
+    fload r6 1.5
+    fload r6 2.5
+    dsload r7 used 0
+    jmp @end
+    uload r9 1
+    uadd r9 r9
+label end
+    halt
+";
+
+/// Assembles `src`, returns the assembler stdout, the image size and the
+/// final state without the ip line
+fn build_and_run(work: &Path, name: &str, src: &str, opt: bool) -> (String, u64, String) {
+    let vvs: PathBuf = work.join(format!("{}.vvs", name));
+    let vve: PathBuf = work.join(format!("{}.vve", name));
+    let state: PathBuf = work.join(format!("{}.state", name));
+    fs::write(&vvs, src).unwrap();
+
+    let mut asm = Command::new(VOXVM);
+    asm.arg(format!("--vas={}", vvs.display())).arg(format!("--vas-out={}", vve.display()));
+    if opt {
+        asm.arg("--opt");
+    }
+    let asm = asm.output().unwrap();
+    assert!(asm.status.success(), "{}", String::from_utf8_lossy(&asm.stderr));
+
+    let run = Command::new(VOXVM)
+        .arg(format!("--vve={}", vve.display()))
+        .arg("--init-ram=1MB")
+        .arg("--init-stack-size=64KB")
+        .arg("--init-heap-size=64KB")
+        .arg(format!("--dump-state={}", state.display()))
+        .output()
+        .unwrap();
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+
+    let state_text: String = fs::read_to_string(&state)
+        .unwrap()
+        .lines()
+        .filter(|l| !l.starts_with("ip:"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    (
+        String::from_utf8_lossy(&asm.stdout).to_string(),
+        fs::metadata(&vve).unwrap().len(),
+        state_text,
+    )
+}
+
+#[test]
+fn opt_keeps_results() {
+    let work: PathBuf = env::temp_dir().join(format!("voxvm-opt-{}", std::process::id()));
+    fs::create_dir_all(&work).unwrap();
+
+    let (_, plain_size, plain_state) = build_and_run(&work, "plain", SRC, false);
+    let (report, opt_size, opt_state) = build_and_run(&work, "opt", SRC, true);
+    assert_eq!(plain_state, opt_state);
+    assert!(opt_size < plain_size, "{} >= {}", opt_size, plain_size);
+    assert!(
+        report.contains(
+            "opt: folded 3 constant op(s), removed 4 dead load(s) and 2 unreachable instruction(s), \
+             code 96 -> 68 bytes; dropped 2 unused data variable(s), 46 bytes"
+        ),
+        "{}",
+        report
+    );
+
+    let numeric: String = SRC.replace("jmp @end", "jmp @end\n    jz 0");
+    let (report, _, _) = build_and_run(&work, "numeric", &numeric, true);
+    assert!(report.contains("opt: skipped, 20: jump target '0' is not a label"), "{}", report);
+
+    let _ = fs::remove_dir_all(&work);
+}
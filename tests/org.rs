@@ -0,0 +1,52 @@
+// `.org` can only move forward, in code and in data.
+
+mod common;
+
+use std::{fs, path::PathBuf};
+
+fn assemble(name: &str, src: &str) -> (bool, String) {
+    let work: PathBuf = common::work_dir(&format!("org-{}", name));
+    let (_, out) = common::try_assemble(&work, "o", src, &[]);
+    let _ = fs::remove_dir_all(&work);
+    (out.status.success(), String::from_utf8_lossy(&out.stderr).to_string())
+}
+
+#[test]
+fn org_behind_code_fails() {
+    let (ok, err) = assemble(
+        "code",
+        "section text
+.start
+    uload r1 1
+    uload r2 2
+.org 0x8
+    halt
+",
+    );
+    assert!(!ok);
+    assert!(err.contains(".org 0x8 is behind the current address 0xc"), "{}", err);
+}
+
+#[test]
+fn org_behind_data_fails() {
+    let (ok, err) = assemble(
+        "data",
+        "section text
+.start
+    halt
+section data
+    a uint 1
+.org 0x4
+    b uint 2
+",
+    );
+    assert!(!ok);
+    assert!(err.contains(".org 0x4 is behind the current address 0x12"), "{}", err);
+}
+
+#[test]
+fn org_needs_an_address() {
+    let (ok, err) = assemble("arg", "section text\n.start\n.org far\n    halt\n");
+    assert!(!ok);
+    assert!(err.contains(".org should be used as .org ADDR"), "{}", err);
+}
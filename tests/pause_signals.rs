@@ -0,0 +1,80 @@
+// `--pause-signals`: Ctrl-Z pauses the VM between instructions and
+// SIGCONT resumes it, the host process keeps running.
+#![cfg(unix)]
+
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Lines},
+    path::PathBuf,
+    process::{ChildStderr, Command, Stdio},
+};
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+const SRC: &str = "section text
+.start
+    uload r1 7
+    uload r2 2
+    ncall 0x1 r0
+label spin
+    uinc r5
+    jmp @spin
+";
+
+fn signal(pid: u32, sig: &str) {
+    let status = Command::new("kill").arg(format!("-{}", sig)).arg(pid.to_string()).status().unwrap();
+    assert!(status.success());
+}
+
+fn wait_line(lines: &mut Lines<BufReader<ChildStderr>>, prefix: &str) -> String {
+    for line in lines.by_ref() {
+        let line: String = line.unwrap();
+        if line.starts_with(prefix) {
+            return line;
+        }
+    }
+    panic!("stderr closed before '{}'", prefix);
+}
+
+#[test]
+fn tstp_pauses_and_cont_resumes() {
+    let work: PathBuf = env::temp_dir().join(format!("voxvm-pause-{}", std::process::id()));
+    fs::create_dir_all(&work).unwrap();
+    let (vvs, vve) = (work.join("p.vvs"), work.join("p.vve"));
+    fs::write(&vvs, SRC).unwrap();
+
+    let asm = Command::new(VOXVM)
+        .arg(format!("--vas={}", vvs.display()))
+        .arg(format!("--vas-out={}", vve.display()))
+        .output()
+        .unwrap();
+    assert!(asm.status.success(), "{}", String::from_utf8_lossy(&asm.stderr));
+
+    let mut child = Command::new(VOXVM)
+        .arg("--pause-signals")
+        .arg(format!("--vve={}", vve.display()))
+        .arg("--init-ram=1MB")
+        .arg("--init-stack-size=64KB")
+        .arg("--init-heap-size=64KB")
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(child.stderr.take().unwrap()).lines();
+    // the guest printed, so the handler is installed
+    wait_line(&mut lines, "7");
+
+    signal(child.id(), "TSTP");
+    let paused: String = wait_line(&mut lines, "INFO: VM paused at ip");
+    assert!(paused.contains("instructions"), "{}", paused);
+    signal(child.id(), "CONT");
+    wait_line(&mut lines, "INFO: VM resumed");
+    signal(child.id(), "TSTP");
+    wait_line(&mut lines, "INFO: VM paused at ip");
+    signal(child.id(), "TSTP");
+    wait_line(&mut lines, "INFO: VM resumed");
+
+    child.kill().unwrap();
+    let _ = child.wait();
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,38 @@
+// `ncall @res_usage` reports the host process' memory and CPU time next to
+// the VM's own counters. Host figures vary from run to run, so this only
+// checks they are there; the VM ones are exact.
+
+mod common;
+
+use std::{fs, path::PathBuf};
+
+use common::reg;
+
+const SRC: &str = "section text
+.start
+    alloc r20 40
+    uload r10 0
+    uload r11 1000
+label spin
+    uinc r10
+    ucmp r10 r11
+    jl @spin
+    ncall @res_usage r0
+    halt
+";
+
+#[test]
+fn res_usage_reports_host_and_vm() {
+    let work: PathBuf = common::work_dir("resusage");
+    let vve: PathBuf = common::assemble(&work, "r", SRC, &[]);
+    let (run, dump) = common::run(&vve, &[]);
+    let _ = fs::remove_dir_all(&work);
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+
+    assert!(dump.contains("exceptions: []"), "{}", dump);
+    assert!(reg(&dump, "r0") > 0, "{}", dump);
+    assert!(reg(&dump, "r1") >= reg(&dump, "r0"), "{}", dump);
+    // alloc, two uloads, 1000 rounds of three, then the ncall itself
+    assert_eq!(reg(&dump, "r4"), 3003, "{}", dump);
+    assert_eq!(reg(&dump, "r5"), 40, "{}", dump);
+}
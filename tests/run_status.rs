@@ -0,0 +1,60 @@
+// `--quiet` leaves stdout to the guest, `--json-status` reports the run
+// into a file or onto a descriptor.
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+const SRC: &str = "section text
+.start
+    uload r1 42
+    uload r2 1
+    ncall @print r0
+    alloc r3 8
+    halt
+";
+
+#[test]
+fn quiet_run_reports_json_status() {
+    let work: PathBuf = env::temp_dir().join(format!("voxvm-status-{}", std::process::id()));
+    fs::create_dir_all(&work).unwrap();
+    let (vvs, vve, status) = (work.join("s.vvs"), work.join("s.vve"), work.join("status.json"));
+    fs::write(&vvs, SRC).unwrap();
+    let asm = Command::new(VOXVM)
+        .arg(format!("--vas={}", vvs.display()))
+        .arg(format!("--vas-out={}", vve.display()))
+        .output()
+        .unwrap();
+    assert!(asm.status.success(), "{}", String::from_utf8_lossy(&asm.stderr));
+
+    let run = Command::new(VOXVM)
+        .arg(format!("--vve={}", vve.display()))
+        .arg("--init-heap-size=64KB")
+        .arg("--quiet")
+        .arg(format!("--json-status={}", status.display()))
+        .output()
+        .unwrap();
+    let to_file: String = fs::read_to_string(&status).unwrap_or_default();
+    let to_fd = Command::new(VOXVM)
+        .arg(format!("--vve={}", vve.display()))
+        .args(["--quiet", "--json-status=2"])
+        .output()
+        .unwrap();
+    let _ = fs::remove_dir_all(&work);
+
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "42\n");
+    let json: serde_json::Value = serde_json::from_str(&to_file).unwrap();
+    assert_eq!(json["voxvm"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(json["format"], "vve");
+    assert_eq!(json["status"], "halted");
+    assert_eq!(json["sizes"]["heap"], 64 * 1024);
+    assert_eq!(json["exceptions"].as_array().unwrap().len(), 0);
+    assert_eq!(json["stats"]["instructions"], 5);
+    assert_eq!(json["stats"]["allocs"], 1);
+    assert_eq!(json["stats"]["heap_blocks"], 1);
+
+    assert_eq!(String::from_utf8_lossy(&to_fd.stdout), "42\n");
+    let json: serde_json::Value = serde_json::from_slice(&to_fd.stderr).unwrap();
+    assert_eq!(json["status"], "halted");
+}
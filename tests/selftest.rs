@@ -0,0 +1,50 @@
+// `voxvm selftest` has to pass on every build. Each family runs as its own
+// test, so a failure names the family and lists just the failing cases;
+// `selftest_passes` runs the whole battery and catches families missing here.
+
+use std::process::Command;
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+/// Runs `voxvm selftest [family]`, panics with the failing cases
+fn selftest(family: Option<&str>) {
+    let out = Command::new(VOXVM).arg("selftest").args(family).output().unwrap();
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let failed: Vec<&str> = stdout.lines().filter(|l| l.contains(" FAIL")).collect();
+    let summary: &str = stdout.lines().last().unwrap_or_default();
+    assert!(failed.is_empty(), "{}:\n{}", summary, failed.join("\n"));
+    assert!(out.status.success(), "selftest failed:\n{}", stdout);
+}
+
+#[test]
+fn selftest_passes() {
+    selftest(None);
+}
+
+macro_rules! families {
+    ($($test:ident => $family:literal),* $(,)?) => {
+        $(
+            #[test]
+            fn $test() {
+                selftest(Some($family));
+            }
+        )*
+    };
+}
+
+families! {
+    bitwise => "bitwise",
+    control => "control",
+    convert => "convert",
+    float => "float",
+    func => "func",
+    heap => "heap",
+    int => "int",
+    jump => "jump",
+    moves => "move",
+    native => "native",
+    set => "set",
+    stack => "stack",
+    string => "string",
+    uint => "uint",
+}
@@ -0,0 +1,68 @@
+// The assembler records the deepest data stack of every function body in
+// the vve and warns about paths that leave it imbalanced. With
+// --stack-precheck a call whose callee wouldn't fit under the stack limit
+// raises stack_overflow at the call instead of inside the callee.
+
+mod common;
+
+use std::{
+    fs,
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+use common::{VOXVM, run_state};
+
+const SRC: &str = "section text
+.start
+    push r1
+    call @deep
+    jexc @stack_overflow @caught
+    uload r10 1
+    halt
+label caught
+    uload r10 2
+    halt
+
+func deep
+    uinc r11
+    push r1
+    push r1
+    push r1
+    pop r1
+    pop r1
+    pop r1
+    ret
+
+func uneven
+    ucmp r1 r2
+    jz @skip
+    push r1
+label skip
+    ret
+";
+
+#[test]
+fn stack_depth_recorded_and_prechecked() {
+    let work: PathBuf = common::work_dir("stackdepth");
+    let (vve, asm) = common::try_assemble(&work, "s", SRC, &[]);
+    let hexdump: Output = Command::new(VOXVM).arg("hexdump").arg(&vve).output().unwrap();
+    let plain: String = run_state(&vve, &["--max-stack-slots=3"]);
+    let checked: String = run_state(&vve, &["--max-stack-slots=3", "--stack-precheck"]);
+    let roomy: String = run_state(&vve, &["--max-stack-slots=4", "--stack-precheck"]);
+    let _ = fs::remove_dir_all(&work);
+
+    let asm_err: String = String::from_utf8_lossy(&asm.stderr).to_string();
+    assert!(asm.status.success(), "{}", asm_err);
+    assert!(asm_err.contains("27: WARNING: data stack depth differs on paths joining here (0 vs 1)"), "{}", asm_err);
+    assert!(!asm_err.contains("deep"), "{}", asm_err);
+    assert!(String::from_utf8_lossy(&hexdump.stdout).contains("stack_depth"));
+
+    // without the precheck deep runs and its third push overflows
+    assert!(plain.lines().any(|l| l == "r11: uint(1)"), "{}", plain);
+    // deep needs 3 slots over the 1 pushed before the call
+    assert!(checked.lines().any(|l| l == "r10: uint(2)"), "{}", checked);
+    assert!(checked.lines().any(|l| l == "r11: uint(0)"), "{}", checked);
+    assert!(roomy.lines().any(|l| l == "r10: uint(1)"), "{}", roomy);
+    assert!(roomy.lines().any(|l| l == "r11: uint(1)"), "{}", roomy);
+}
@@ -0,0 +1,98 @@
+// `--stdlib`: a program linked by the assembler (calls by name) and one
+// linked by the VM at load time (calls by reserved index) behave the same.
+
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use common::{assemble_file, reg, work_dir};
+
+const STD: &str = "section text
+func square
+    umul r1 r1
+    ret
+
+func sq_plus_bias
+    call @square
+    dsload r2 bias 0
+    uadd r1 r2
+    ret
+
+func greet
+    dsload r1 greeting 0
+    uload r2 1
+    ncall 1 r0
+    uload r4 3
+    uload r5 1
+label again
+    usub r4 r5
+    jnz @again
+    lea r6 @bias
+    dslea r7 bias 0
+    ret
+section rodata
+    greeting const str \"hi from std\"
+section data
+    !align=16
+    bias uint 100
+";
+
+const PROG: &str = "section text
+.start
+    uload r1 7
+    call @sq_plus_bias
+    uload r2 1
+    ncall 1 r0
+    dsload r1 local 0
+    ncall 1 r0
+    dsload r1 title 0
+    ncall 1 r0
+    call @greet
+    halt
+section rodata
+    title const str \"prog\"
+section data
+    local uint 42
+";
+
+/// Runs `vve` (with extra args), returns stdout and the final registers
+fn run(vve: &Path, extra: &[&str]) -> (String, String) {
+    let sizes = ["--init-ram=1MB", "--init-stack-size=64KB", "--init-heap-size=64KB"];
+    let (out, dump) = common::run(vve, &[&sizes[..], extra].concat());
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let regs: Vec<&str> = dump.lines().filter(|l| l.starts_with('r')).collect();
+    (String::from_utf8_lossy(&out.stdout).to_string(), regs.join("\n"))
+}
+
+#[test]
+fn stdlib_linked_by_assembler_and_vm() {
+    let work: PathBuf = work_dir("stdlib");
+    let path = |name: &str| work.join(name);
+    fs::write(path("std.vvs"), STD).unwrap();
+    fs::write(path("prog.vvs"), PROG).unwrap();
+    // without the stdlib at hand, functions are called by their reserved indices
+    let by_index: String = PROG.replace("@sq_plus_bias", "257").replace("@greet", "258");
+    fs::write(path("prog2.vvs"), by_index).unwrap();
+
+    let asm = |src: &str, out: &str, extra: &[&str]| assemble_file(&path(src), &path(out), extra);
+    let stdlib_arg: String = format!("--stdlib={}", path("std.vve").display());
+    asm("std.vvs", "std.vve", &[]);
+    asm("prog.vvs", "prog.vve", &[&stdlib_arg]);
+    asm("prog2.vvs", "prog2.vve", &[]);
+
+    let (out1, regs1) = run(&path("prog.vve"), &[]);
+    let (out2, regs2) = run(&path("prog2.vve"), &[&stdlib_arg]);
+    assert!(out1.ends_with("149\n42\nprog\nhi from std\n"), "{}", out1);
+    assert_eq!(out1, out2);
+    assert_eq!(regs1, regs2);
+
+    // lea of a stdlib variable points where dslea does, and its alignment held
+    let (lea, dslea) = (reg(&regs1, "r6"), reg(&regs1, "r7"));
+    assert_eq!(lea, dslea);
+    assert_eq!((lea + 1 + 8) % 16, 0);
+
+    let _ = fs::remove_dir_all(&work);
+}
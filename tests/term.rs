@@ -0,0 +1,69 @@
+// Terminal ncalls degrade without a terminal: no escape sequences, raw mode
+// reports 0, keys come from piped stdin and the size from COLUMNS/LINES.
+
+use std::{env, fs, io::Write, path::PathBuf, process::{Command, Stdio}};
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+const SRC: &str = "section text
+.start
+    uload r1 0
+    ncall @term_clear r0
+    movr r10 r0
+    uload r1 1
+    ncall @term_raw r0
+    movr r11 r0
+    ncall @term_size r0
+    movr r12 r0
+    movr r13 r1
+    uload r1 100
+    ncall @term_key r0
+    movr r14 r0
+    ncall @term_key r0
+    movr r15 r0
+    ncall @term_key r0
+    movr r16 r0
+    halt
+";
+
+#[test]
+fn piped_run_degrades() {
+    let work: PathBuf = env::temp_dir().join(format!("voxvm-term-{}", std::process::id()));
+    fs::create_dir_all(&work).unwrap();
+    let (vvs, vve, state) = (work.join("t.vvs"), work.join("t.vve"), work.join("t.state"));
+    fs::write(&vvs, SRC).unwrap();
+    let asm = Command::new(VOXVM)
+        .arg(format!("--vas={}", vvs.display()))
+        .arg(format!("--vas-out={}", vve.display()))
+        .output()
+        .unwrap();
+    assert!(asm.status.success(), "{}", String::from_utf8_lossy(&asm.stderr));
+
+    let mut child = Command::new(VOXVM)
+        .arg(format!("--vve={}", vve.display()))
+        .arg(format!("--dump-state={}", state.display()))
+        .env("COLUMNS", "132")
+        .env("LINES", "43")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"q\x1b").unwrap();
+    let out = child.wait_with_output().unwrap();
+    let dump: String = fs::read_to_string(&state).unwrap();
+    let _ = fs::remove_dir_all(&work);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert!(!String::from_utf8_lossy(&out.stdout).contains('\x1b'));
+    for line in [
+        "r10: uint(0)\n",
+        "r11: uint(0)\n",
+        "r12: uint(132)\n",
+        "r13: uint(43)\n",
+        "r14: int(113)\n",
+        "r15: int(27)\n",
+        "r16: int(-1)\n",
+    ] {
+        assert!(dump.contains(line), "missing {:?} in\n{}", line, dump);
+    }
+}
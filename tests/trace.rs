@@ -0,0 +1,63 @@
+// `--trace` writes balanced, ordered begin/end events for nested calls.
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+use serde_json::Value;
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+const SRC: &str = "section text
+.start
+    uload r1 3
+    call @outer
+    call @leaf
+    halt
+
+func outer
+    call @leaf
+    call @leaf
+    ret
+
+func leaf
+    uadd r1 r1
+    ret
+";
+
+#[test]
+fn trace_nested_calls() {
+    let work: PathBuf = env::temp_dir().join(format!("voxvm-trace-{}", std::process::id()));
+    fs::create_dir_all(&work).unwrap();
+    let (vvs, vve, json) = (work.join("t.vvs"), work.join("t.vve"), work.join("t.json"));
+    fs::write(&vvs, SRC).unwrap();
+
+    let asm = Command::new(VOXVM)
+        .arg(format!("--vas={}", vvs.display()))
+        .arg(format!("--vas-out={}", vve.display()))
+        .output()
+        .unwrap();
+    assert!(asm.status.success(), "{}", String::from_utf8_lossy(&asm.stderr));
+    let run = Command::new(VOXVM)
+        .arg(format!("--vve={}", vve.display()))
+        .arg("--init-ram=1MB")
+        .arg("--init-stack-size=64KB")
+        .arg("--init-heap-size=64KB")
+        .arg(format!("--trace={}", json.display()))
+        .output()
+        .unwrap();
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+
+    let trace: Value = serde_json::from_str(&fs::read_to_string(&json).unwrap()).unwrap();
+    let events: &Vec<Value> = trace["traceEvents"].as_array().unwrap();
+    let seq: Vec<String> = events
+        .iter()
+        .map(|e| format!("{} {}", e["ph"].as_str().unwrap(), e["name"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        seq,
+        ["B outer", "B leaf", "E leaf", "B leaf", "E leaf", "E outer", "B leaf", "E leaf"]
+    );
+    let ts: Vec<f64> = events.iter().map(|e| e["ts"].as_f64().unwrap()).collect();
+    assert!(ts.windows(2).all(|w| w[0] <= w[1]), "{:?}", ts);
+
+    let _ = fs::remove_dir_all(&work);
+}
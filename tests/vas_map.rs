@@ -0,0 +1,54 @@
+// `--vas-map=out.map` writes the functions, labels and data variables of the
+// assembled program with their addresses and sizes.
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+const VOXVM: &str = env!("CARGO_BIN_EXE_voxvm");
+
+const SRC: &str = "section text
+.start
+    uload r1 3
+label again
+    call @twice
+    udec r1
+    jnz @again
+    halt
+
+func twice
+    uadd r2 r2
+    ret
+section rodata
+    greeting str \"hi\"
+section data
+    counter uint 7
+";
+
+#[test]
+fn symbol_map_lists_code_and_data() {
+    let work: PathBuf = env::temp_dir().join(format!("voxvm-vasmap-{}", std::process::id()));
+    fs::create_dir_all(&work).unwrap();
+    let (vvs, vve, map) = (work.join("m.vvs"), work.join("m.vve"), work.join("m.map"));
+    fs::write(&vvs, SRC).unwrap();
+    let asm = Command::new(VOXVM)
+        .arg(format!("--vas={}", vvs.display()))
+        .arg(format!("--vas-out={}", vve.display()))
+        .arg(format!("--vas-map={}", map.display()))
+        .output()
+        .unwrap();
+    let text: String = fs::read_to_string(&map).unwrap_or_default();
+    let _ = fs::remove_dir_all(&work);
+    assert!(asm.status.success(), "{}", String::from_utf8_lossy(&asm.stderr));
+
+    // uload (short form) 6, call 9, udec 2, jnz 9, halt 1; uadd 3, ret 1
+    for line in [
+        "code 0x0 size 0x1f (31 bytes)",
+        "data 0x1f size 0x1e (30 bytes, 13 read-only)",
+        "entry 0x0",
+        "0x0000000000000006 21         label again",
+        "0x000000000000001b 4          func  twice (#0)",
+        "0x000000000000001f 13         ro    greeting",
+        "0x000000000000002c 17         rw    counter",
+    ] {
+        assert!(text.lines().any(|l| l == line), "no '{}' in\n{}", line, text);
+    }
+}
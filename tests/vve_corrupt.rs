@@ -0,0 +1,101 @@
+// Truncated or corrupted .vve images are refused with an error when the
+// header is read, section lengths and entry counts included, instead of
+// panicking somewhere in the loader, hexdump or the linker.
+
+mod common;
+
+use std::{fs, path::PathBuf, process::Command};
+
+use common::VOXVM;
+use voxvm::fileformats::{SECT_DATA_SYMBOLS, SECT_RODATA, SECT_SYMBOLS, VoxExeHeader};
+
+const SRC: &str = "section text
+.start
+    call @twice
+    halt
+
+func twice
+    uadd r1 r1
+    ret
+
+section data
+    count uint 7
+    msg const str \"hi\"
+";
+
+/// Assembles SRC, returns the work dir and the vve bytes
+fn image(tag: &str) -> (PathBuf, Vec<u8>) {
+    let work: PathBuf = common::work_dir(&format!("corrupt-{}", tag));
+    let vve: PathBuf = common::assemble(&work, "c", SRC, &[]);
+    let bytes: Vec<u8> = fs::read(&vve).unwrap();
+    (work, bytes)
+}
+
+/// Offsets of (kind, len, data) of every section, the image is big-endian
+fn section_offsets(bytes: &[u8]) -> Vec<(u16, usize, usize)> {
+    let header: VoxExeHeader = VoxExeHeader::from_bytes(bytes, "c.vve", 4).unwrap();
+    let mut cur: usize = 0x30 + header.func_table_len as usize * 16 + 8;
+    let mut res = Vec::new();
+    for sect in &header.sections {
+        res.push((sect.kind, cur + 2, cur + 10));
+        cur += 10 + sect.data.len();
+    }
+    res
+}
+
+#[test]
+fn truncated_headers_are_refused() {
+    let (work, bytes) = image("truncated");
+    let _ = fs::remove_dir_all(&work);
+    let size: usize = VoxExeHeader::from_bytes(&bytes, "c.vve", 4).unwrap().size();
+    for len in 0..size {
+        assert!(VoxExeHeader::from_bytes(&bytes[..len], "c.vve", 4).is_err(), "{} of {} bytes loaded", len, size);
+    }
+}
+
+#[test]
+fn oversized_section_lengths_are_refused() {
+    let (work, bytes) = image("sect-len");
+    let _ = fs::remove_dir_all(&work);
+    for (kind, len_at, _) in section_offsets(&bytes) {
+        for len in [u64::MAX, u64::MAX - 9, bytes.len() as u64] {
+            let mut bad: Vec<u8> = bytes.clone();
+            bad[len_at..len_at + 8].copy_from_slice(&len.to_be_bytes());
+            let res = VoxExeHeader::from_bytes(&bad, "c.vve", 4);
+            assert!(res.is_err(), "section {:#x} with length {:#x} loaded", kind, len);
+        }
+    }
+}
+
+#[test]
+fn oversized_entry_counts_are_refused() {
+    let (work, bytes) = image("count");
+    let _ = fs::remove_dir_all(&work);
+    let sections = section_offsets(&bytes);
+    for kind in [SECT_SYMBOLS, SECT_DATA_SYMBOLS] {
+        assert!(sections.iter().any(|(k, _, _)| *k == kind), "no section {:#x}", kind);
+    }
+    // rodata is a size, not a count
+    for (kind, _, data_at) in sections.into_iter().filter(|(k, _, _)| *k != SECT_RODATA) {
+        let mut bad: Vec<u8> = bytes.clone();
+        bad[data_at..data_at + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+        let res = VoxExeHeader::from_bytes(&bad, "c.vve", 4);
+        assert!(res.unwrap_err().contains("truncated"), "section {:#x} with a huge count loaded", kind);
+    }
+}
+
+#[test]
+fn hexdump_reports_corrupted_images() {
+    let (work, mut bytes) = image("hexdump");
+    let (_, len_at, _) = section_offsets(&bytes)[0];
+    bytes[len_at..len_at + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+    let vve: PathBuf = work.join("bad.vve");
+    fs::write(&vve, &bytes).unwrap();
+    let out = Command::new(VOXVM).arg("hexdump").arg(&vve).output().unwrap();
+    let _ = fs::remove_dir_all(&work);
+
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(!out.status.success());
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    assert!(stderr.contains("sections run past the end of the file"), "{}", stderr);
+}
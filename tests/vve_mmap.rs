@@ -0,0 +1,43 @@
+// A vve past MMAP_VVE_MIN (256KB) is mapped instead of read when voxvm is
+// built with the `mmap-vve` feature. Either way the program has to run the
+// same (growing the memory with dlbc too), and its data segment stores
+// must not reach the file.
+
+mod common;
+
+use std::{fs, path::PathBuf};
+
+use common::reg;
+
+const SRC: &str = "section text
+.start
+    uload r3 77
+    dssave r3 big 319992
+    dsload r4 big 319992
+    dsload r5 num 0
+    alloc r20 8
+    uload r9 8
+    store r20 r3 r9
+    dlbc r8 r20 r9
+    halt
+section data
+    num uint 5
+    big uint[40000] !zeros=40000
+";
+
+#[test]
+fn big_vve_runs_and_stays_unchanged() {
+    let work: PathBuf = common::work_dir("vvemmap");
+    let vve: PathBuf = common::assemble(&work, "m", SRC, &[]);
+    let before: Vec<u8> = fs::read(&vve).unwrap();
+    let (run, dump) = common::run(&vve, &[]);
+    let after: Vec<u8> = fs::read(&vve).unwrap();
+    let _ = fs::remove_dir_all(&work);
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+
+    assert!(before.len() >= 256 * 1024, "{} bytes", before.len());
+    assert!(before == after, "running the program changed the vve");
+    assert!(dump.contains("exceptions: []"), "{}", dump);
+    assert_eq!(reg(&dump, "r4"), 77, "{}", dump);
+    assert_eq!(reg(&dump, "r5"), 5, "{}", dump);
+}